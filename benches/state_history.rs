@@ -0,0 +1,153 @@
+#![feature(test)]
+
+extern crate test;
+
+use croaring::Treemap as RoaringTreemap;
+use martinez::{
+    accessors::state::{account, storage},
+    kv::{
+        new_mem_database,
+        tables::{self, BitmapKey},
+        traits::*,
+        MdbxWithDirHandle,
+    },
+    models::*,
+    stagedsync::stages::{ACCOUNT_HISTORY_INDEX, EXECUTION, STORAGE_HISTORY_INDEX},
+    u256_to_h256,
+};
+use test::Bencher;
+
+const BLOCKS: u64 = 10_000;
+const READ_AT: BlockNumber = BlockNumber(100);
+
+fn address() -> Address {
+    Address::from_low_u64_be(0xb0)
+}
+
+fn location() -> U256 {
+    1_u64.as_u256()
+}
+
+/// A database where one account and one of its storage slots change in every block, with the
+/// history indexes built up to `indexed_to`.
+fn history_db(indexed_to: Option<u64>) -> MdbxWithDirHandle {
+    let db = new_mem_database().unwrap();
+    let txn = db.begin_mutable().unwrap();
+
+    let address = address();
+    let location = u256_to_h256(location());
+    let mut account_bitmap = RoaringTreemap::create();
+    let mut storage_bitmap = RoaringTreemap::create();
+    for block in 1..=BLOCKS {
+        txn.set(
+            tables::AccountChangeSet,
+            BlockNumber(block),
+            tables::AccountChange {
+                address,
+                account: Some(Account {
+                    nonce: block,
+                    ..Default::default()
+                }),
+            },
+        )
+        .unwrap();
+        txn.set(
+            tables::StorageChangeSet,
+            tables::StorageChangeKey {
+                block_number: BlockNumber(block),
+                address,
+            },
+            tables::StorageChange {
+                location,
+                value: block.as_u256(),
+            },
+        )
+        .unwrap();
+        if matches!(indexed_to, Some(indexed_to) if block <= indexed_to) {
+            account_bitmap.add(block);
+            storage_bitmap.add(block);
+        }
+    }
+    txn.set(
+        tables::Account,
+        address,
+        Account {
+            nonce: BLOCKS + 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    EXECUTION.save_progress(&txn, BlockNumber(BLOCKS)).unwrap();
+
+    if let Some(indexed_to) = indexed_to {
+        txn.set(
+            tables::AccountHistory,
+            BitmapKey {
+                inner: address,
+                block_number: BlockNumber(u64::MAX),
+            },
+            account_bitmap,
+        )
+        .unwrap();
+        txn.set(
+            tables::StorageHistory,
+            BitmapKey {
+                inner: (address, location),
+                block_number: BlockNumber(u64::MAX),
+            },
+            storage_bitmap,
+        )
+        .unwrap();
+        ACCOUNT_HISTORY_INDEX
+            .save_progress(&txn, BlockNumber(indexed_to))
+            .unwrap();
+        STORAGE_HISTORY_INDEX
+            .save_progress(&txn, BlockNumber(indexed_to))
+            .unwrap();
+    }
+    txn.commit().unwrap();
+
+    db
+}
+
+fn bench_account(b: &mut Bencher, indexed_to: Option<u64>) {
+    let db = history_db(indexed_to);
+    let txn = db.begin().unwrap();
+    b.iter(|| account::read(&txn, address(), Some(READ_AT)).unwrap());
+}
+
+fn bench_storage(b: &mut Bencher, indexed_to: Option<u64>) {
+    let db = history_db(indexed_to);
+    let txn = db.begin().unwrap();
+    b.iter(|| storage::read(&txn, address(), location(), Some(READ_AT)).unwrap());
+}
+
+#[bench]
+fn account_indexed(b: &mut Bencher) {
+    bench_account(b, Some(BLOCKS));
+}
+
+#[bench]
+fn account_index_behind(b: &mut Bencher) {
+    bench_account(b, Some(BLOCKS - 100));
+}
+
+#[bench]
+fn account_unindexed(b: &mut Bencher) {
+    bench_account(b, None);
+}
+
+#[bench]
+fn storage_indexed(b: &mut Bencher) {
+    bench_storage(b, Some(BLOCKS));
+}
+
+#[bench]
+fn storage_index_behind(b: &mut Bencher) {
+    bench_storage(b, Some(BLOCKS - 100));
+}
+
+#[bench]
+fn storage_unindexed(b: &mut Bencher) {
+    bench_storage(b, None);
+}
@@ -1,11 +1,51 @@
-use martinez::{binutil::MartinezDataDir, kv::mdbx::*, models::*, stagedsync::stages::*};
-use async_trait::async_trait;
+use anyhow::format_err;
 use clap::Parser;
-use ethnum::U256;
-use jsonrpsee::{core::RpcResult, http_server::HttpServerBuilder, proc_macros::rpc};
-use mdbx::EnvironmentKind;
-use std::{future::pending, net::SocketAddr, sync::Arc};
-use tracing_subscriber::{prelude::*, EnvFilter};
+use ethereum_interfaces::sentry::sentry_client::SentryClient;
+use jsonrpsee::{
+    http_client::{HttpClient, HttpClientBuilder},
+    http_server::{HttpServerBuilder, HttpServerHandle},
+    ws_server::{WsServerBuilder, WsServerHandle},
+};
+use martinez::{
+    binutil::MartinezDataDir,
+    downloader::top_block_estimate::PublishedTopBlockEstimate,
+    http_compression, ipc,
+    kv::{mdbx::*, replica::ReadReplica},
+    models::*,
+    observability::{Observability, ObservabilityOpts},
+    rpc::{
+        bor::{BorApiServer, BorApiServerImpl},
+        common::{LogLimits, ReceiptCache},
+        debug::{DebugApiServer, DebugApiServerImpl},
+        eth::{EthApiServer, EthApiServerImpl, PendingTransactionFilters},
+        martinez::{MartinezApiServer, MartinezApiServerImpl},
+        miner::{MinerApiServer, MinerApiServerImpl},
+        net::{SentryAdminApiServer, SentryApiServerImpl, SentryEthApiServer, SentryNetApiServer},
+        ots::{OtsApiServer, OtsApiServerImpl},
+        proxy::{proxy_to_upstream, ProxyCache},
+        pubsub::{announce_heads, ChainNotifications, EthPubSubApiServer, EthPubSubApiServerImpl},
+        trace::{TraceApiServer, TraceApiServerImpl},
+        txpool::{
+            rebroadcast_local_transactions, LocalTransactions, SubmissionLimits, TxPoolApiServer,
+            TxPoolApiServerImpl,
+        },
+        HeadSource, Namespace, RpcRegistry,
+    },
+    sentry::{peer_tracker::PeerTracker, sentry_address::SentryAddress},
+    stagedsync::freeze::DbFreeze,
+    txpool::PoolLimits,
+};
+use parking_lot::Mutex;
+use std::{
+    future::pending,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tonic::transport::Channel;
+use tracing::*;
 
 #[derive(Parser)]
 #[clap(name = "Martinez RPC", about = "RPC server for Martinez")]
@@ -15,70 +55,438 @@ pub struct Opt {
 
     #[clap(long)]
     pub listen_address: SocketAddr,
+
+    /// Namespaces served over HTTP, comma-separated. All of them if not given.
+    #[clap(long = "http.api", use_delimiter = true)]
+    pub http_api: Vec<Namespace>,
+
+    /// Also serve the API over WebSocket on this address, with `eth_subscribe` notifications of
+    /// new heads, logs and transactions submitted through this server.
+    #[clap(long = "ws.addr")]
+    pub ws_listen_address: Option<SocketAddr>,
+
+    /// Namespaces served over WebSocket, comma-separated. All of them if not given.
+    #[clap(long = "ws.api", use_delimiter = true)]
+    pub ws_api: Vec<Namespace>,
+
+    /// Also serve the API, subscriptions included, over a Unix socket created at this path.
+    #[clap(long = "ipc.path")]
+    pub ipc_path: Option<PathBuf>,
+
+    /// Namespaces served over IPC, comma-separated. All of them if not given.
+    #[clap(long = "ipc.api", use_delimiter = true)]
+    pub ipc_api: Vec<Namespace>,
+
+    /// Upstream JSON-RPC endpoint for methods and data not available locally.
+    #[clap(long)]
+    pub upstream_url: Option<String>,
+
+    /// Responses of the upstream kept to answer the same request again: for good when it asks for
+    /// a block by hash or a finalized one, until the head moves for other blocks given by number.
+    /// 0 to forward every request.
+    #[clap(long = "upstream.cacheentries", default_value = "4096")]
+    pub upstream_cache_entries: usize,
+
+    /// Additional chains to serve from this process, as `<datadir>@<listen_address>`.
+    #[clap(long = "extra-chain")]
+    pub extra_chains: Vec<ChainEndpoint>,
+
+    /// Chain head served as `latest`: `finish` for the Finish stage progress, `canonical`
+    /// for the highest canonical header, or a stage name for the canonical head capped
+    /// at that stage's progress.
+    #[clap(long, default_value = "finish")]
+    pub head: HeadSource,
+
+    /// Put a front end before the JSON-RPC server that speaks HTTP/2 (cleartext, prior
+    /// knowledge) as well as HTTP/1.1 and gzips large responses for clients that accept it.
+    #[clap(long)]
+    pub compression: bool,
+
+    /// Fee recipient of blocks built by this node, reported by `eth_coinbase`. Can be changed
+    /// at runtime through `miner_setEtherbase`.
+    #[clap(long)]
+    pub etherbase: Option<Address>,
+
+    /// Highest fee, in ether, a transaction submitted through this server may pay at its fee
+    /// cap. 0 for no cap.
+    #[clap(long = "rpc.txfeecap", default_value = "1")]
+    pub rpc_tx_fee_cap: f64,
+
+    /// Lowest priority fee per gas, in wei, of transactions submitted through this server.
+    #[clap(long = "rpc.mingasprice", default_value = "0")]
+    pub rpc_min_gas_price: u64,
+
+    /// Most transactions of one sender kept in the pool of transactions submitted through this
+    /// server.
+    #[clap(long = "txpool.accountslots", default_value = "16")]
+    pub txpool_account_slots: usize,
+
+    /// Most transactions kept in the pool.
+    #[clap(long = "txpool.globalslots", default_value = "4096")]
+    pub txpool_global_slots: usize,
+
+    /// Percentage by which a transaction must raise both fee caps of the pooled one with the
+    /// same sender and nonce to replace it.
+    #[clap(long = "txpool.pricebump", default_value = "10")]
+    pub txpool_price_bump: u64,
+
+    /// Most blocks one `eth_getLogs` request may scan. 0 for no limit.
+    #[clap(long = "rpc.logs.maxblocks", default_value = "10000")]
+    pub rpc_logs_max_blocks: u64,
+
+    /// Most logs one `eth_getLogs` request may return. 0 for no limit.
+    #[clap(long = "rpc.logs.maxresults", default_value = "10000")]
+    pub rpc_logs_max_results: usize,
+
+    /// Most addresses and topics, counting every alternative, in one `eth_getLogs` filter.
+    #[clap(long = "rpc.logs.maxtopics", default_value = "1000")]
+    pub rpc_logs_max_topics: usize,
+
+    /// Blocks whose receipts are kept after being replayed for `eth_getTransactionReceipt`, so
+    /// that fetching every receipt of a block replays it once. 0 to replay each time, only up to
+    /// the transaction asked for.
+    #[clap(long = "rpc.receipts.cacheblocks", default_value = "32")]
+    pub rpc_receipts_cache_blocks: usize,
+
+    /// gRPC API of the node's sentry, such as the one `martinez --sentry.embedded` runs, to serve
+    /// `net_peerCount`, `eth_protocolVersion`, `admin_nodeInfo`, `admin_peers` and peer events from
+    /// its live peer set.
+    #[clap(long = "sentry.api.addr")]
+    pub sentry_api_addr: Option<SentryAddress>,
+
+    #[clap(flatten)]
+    pub observability: ObservabilityOpts,
 }
 
-#[rpc(server, namespace = "eth")]
-pub trait EthApi {
-    #[method(name = "blockNumber")]
-    async fn block_number(&self) -> RpcResult<BlockNumber>;
-    #[method(name = "getBalance")]
-    async fn get_balance(&self, address: Address, block_number: BlockNumber) -> RpcResult<U256>;
+#[derive(Debug)]
+pub struct ChainEndpoint {
+    pub datadir: MartinezDataDir,
+    pub listen_address: SocketAddr,
 }
 
-pub struct EthApiServerImpl<E>
-where
-    E: EnvironmentKind,
-{
-    db: Arc<MdbxEnvironment<E>>,
+impl FromStr for ChainEndpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (datadir, listen_address) = s
+            .rsplit_once('@')
+            .ok_or_else(|| format_err!("expected <datadir>@<listen_address>, got {}", s))?;
+
+        Ok(Self {
+            datadir: datadir.parse()?,
+            listen_address: listen_address.parse()?,
+        })
+    }
 }
 
-#[async_trait]
-impl<E> EthApiServer for EthApiServerImpl<E>
-where
-    E: EnvironmentKind,
-{
-    async fn block_number(&self) -> RpcResult<BlockNumber> {
-        Ok(FINISH
-            .get_progress(&self.db.begin()?)?
-            .unwrap_or(BlockNumber(0)))
+/// Periodically check whether the writing node grew the database, reopening it if reads no
+/// longer follow, and re-read sync progress so that its commits show up in logs.
+async fn watch_head<E: EnvironmentKind>(
+    db: Arc<ReadReplica<E>>,
+    head_source: HeadSource,
+    name: String,
+) {
+    let mut head = None;
+    loop {
+        if let Err(e) = db.refresh().await {
+            warn!("{}: failed to refresh database: {}", name, e);
+        }
+
+        match async { head_source.resolve(&db.get().await?.begin()?) }.await {
+            Ok(progress) => {
+                if Some(progress) != head {
+                    debug!("{}: database head is now {}", name, progress);
+                    head = Some(progress);
+                }
+            }
+            Err(e) => warn!("{}: failed to read database head: {}", name, e),
+        }
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
     }
+}
 
-    async fn get_balance(&self, address: Address, block_number: BlockNumber) -> RpcResult<U256> {
-        Ok(
-            martinez::accessors::state::account::read(&self.db.begin()?, address, Some(block_number))?
-                .map(|acc| acc.balance)
-                .unwrap_or(U256::ZERO),
-        )
+/// Transports to serve a database on, each with the namespaces enabled on it, all of them if
+/// empty.
+pub struct Endpoints<'a> {
+    pub http: SocketAddr,
+    pub http_api: &'a [Namespace],
+    pub ws: Option<SocketAddr>,
+    pub ws_api: &'a [Namespace],
+    pub ipc: Option<&'a Path>,
+    pub ipc_api: &'a [Namespace],
+}
+
+/// Open the database in `datadir` and start serving it on `endpoints`, all of them sharing the
+/// same method implementations.
+#[allow(clippy::too_many_arguments)]
+async fn serve(
+    datadir: &MartinezDataDir,
+    endpoints: Endpoints<'_>,
+    head: HeadSource,
+    compression: bool,
+    etherbase: Option<Address>,
+    limits: SubmissionLimits,
+    log_limits: LogLimits,
+    receipt_cache_blocks: usize,
+    upstream: Option<Arc<HttpClient>>,
+    upstream_cache_entries: usize,
+    sentry: Option<SentryAddress>,
+    observability: Arc<Observability>,
+) -> anyhow::Result<(HttpServerHandle, Option<WsServerHandle>)> {
+    // Opened read-only alongside a running node: every request begins its own read
+    // transaction, so it always sees the latest commit. `watch_head` reopens the database if
+    // reads stop following the writer as it grows the map.
+    let db = Arc::new(ReadReplica::<mdbx::NoWriteMap>::open(
+        &datadir.chain_data_dir(),
+        martinez::kv::tables::CHAINDATA_TABLES.clone(),
+    )?);
+    tokio::spawn(watch_head(db.clone(), head, datadir.to_string()));
+
+    let peers = PeerTracker::new();
+    let sentry = match sentry {
+        Some(addr) => {
+            tokio::spawn(peers.clone().follow(addr.clone()));
+            Some(SentryClient::new(
+                Channel::builder(addr.addr).connect_lazy()?,
+            ))
+        }
+        None => None,
+    };
+    let local_transactions = Arc::new(LocalTransactions::new(limits.pool));
+    let etherbase = Arc::new(Mutex::new(etherbase));
+    let notifications = ChainNotifications::default();
+    let mut registry = RpcRegistry::default();
+    registry.register(
+        Namespace::Eth,
+        EthApiServerImpl {
+            db: db.clone(),
+            head,
+            upstream: upstream.clone(),
+            sentry: sentry.clone(),
+            local_transactions: local_transactions.clone(),
+            etherbase: etherbase.clone(),
+            limits,
+            log_limits,
+            receipt_cache: ReceiptCache::new(receipt_cache_blocks),
+            pending_filters: PendingTransactionFilters::default(),
+            notifications: notifications.clone(),
+            top_block_estimate: PublishedTopBlockEstimate::new(&datadir.0),
+        }
+        .into_rpc(),
+    );
+    registry.register_subscriptions(
+        Namespace::Eth,
+        EthPubSubApiServerImpl {
+            notifications: notifications.clone(),
+            log_limits,
+        }
+        .into_rpc(),
+    );
+    registry.register(
+        Namespace::Martinez,
+        MartinezApiServerImpl {
+            db: db.clone(),
+            head,
+            observability,
+            freeze: DbFreeze::new(&datadir.0),
+            top_block_estimate: PublishedTopBlockEstimate::new(&datadir.0),
+        }
+        .into_rpc(),
+    );
+    registry.register(
+        Namespace::Ots,
+        OtsApiServerImpl {
+            db: db.clone(),
+            head,
+        }
+        .into_rpc(),
+    );
+    registry.register(
+        Namespace::Debug,
+        DebugApiServerImpl {
+            db: db.clone(),
+            head,
+        }
+        .into_rpc(),
+    );
+    registry.register(
+        Namespace::Trace,
+        TraceApiServerImpl {
+            db: db.clone(),
+            head,
+            log_limits,
+        }
+        .into_rpc(),
+    );
+    registry.register(
+        Namespace::Bor,
+        BorApiServerImpl {
+            db: db.clone(),
+            head,
+        }
+        .into_rpc(),
+    );
+    registry.register(
+        Namespace::TxPool,
+        TxPoolApiServerImpl {
+            db: db.clone(),
+            head,
+            local_transactions: local_transactions.clone(),
+        }
+        .into_rpc(),
+    );
+    registry.register(
+        Namespace::Miner,
+        MinerApiServerImpl { etherbase }.into_rpc(),
+    );
+    if let Some(client) = sentry.clone() {
+        let sentry = SentryApiServerImpl { client, peers };
+        registry.register(Namespace::Net, SentryNetApiServer::into_rpc(sentry.clone()));
+        registry.register(Namespace::Eth, SentryEthApiServer::into_rpc(sentry.clone()));
+        registry.register(Namespace::Admin, SentryAdminApiServer::into_rpc(sentry));
+    }
+    if upstream.is_some() || sentry.is_some() {
+        tokio::spawn(rebroadcast_local_transactions(
+            db.clone(),
+            upstream.clone(),
+            sentry,
+            local_transactions,
+        ));
+    }
+    if let Some(upstream) = upstream {
+        let cache = Arc::new(ProxyCache::new(db.clone(), head, upstream_cache_entries));
+        proxy_to_upstream(&mut registry, upstream, cache)?;
+    }
+
+    if endpoints.ws.is_some() || endpoints.ipc.is_some() {
+        tokio::spawn(announce_heads(db, head, notifications));
+    }
+
+    let ws_handle = if let Some(ws_listen_address) = endpoints.ws {
+        let handle = WsServerBuilder::default()
+            .build(ws_listen_address)
+            .await?
+            .start(registry.module(endpoints.ws_api, true)?)?;
+        info!("Serving {} on ws://{}", datadir, ws_listen_address);
+        Some(handle)
+    } else {
+        None
+    };
+
+    if let Some(ipc_path) = endpoints.ipc {
+        tokio::spawn(ipc::serve(
+            ipc_path,
+            registry.module(endpoints.ipc_api, true)?.into(),
+        )?);
+        info!("Serving {} on {}", datadir, ipc_path.display());
     }
+
+    let listen_address = endpoints.http;
+    let module = registry.module(endpoints.http_api, false)?;
+    let handle = if compression {
+        let server = HttpServerBuilder::default().build(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+        let backend = server.local_addr()?;
+        let handle = server.start(module)?;
+        let front = http_compression::serve(listen_address, backend)?;
+        tokio::spawn(async move {
+            if let Err(e) = front.await {
+                error!("RPC front end on {} failed: {}", listen_address, e);
+            }
+        });
+        handle
+    } else {
+        HttpServerBuilder::default()
+            .build(listen_address)?
+            .start(module)?
+    };
+
+    info!("Serving {} on {}", datadir, listen_address);
+
+    Ok((handle, ws_handle))
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let opt = Opt::parse();
 
-    let env_filter = if std::env::var(EnvFilter::DEFAULT_ENV)
-        .unwrap_or_default()
-        .is_empty()
-    {
-        EnvFilter::new("martinez=info,rpc=info")
-    } else {
-        EnvFilter::from_default_env()
-    };
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().with_target(false))
-        .with(env_filter)
-        .init();
-
-    let db = Arc::new(
-        martinez::kv::mdbx::MdbxEnvironment::<mdbx::NoWriteMap>::open_ro(
-            mdbx::Environment::new(),
-            &opt.datadir,
-            martinez::kv::tables::CHAINDATA_TABLES.clone(),
-        )?,
+    let observability = Arc::new(martinez::observability::init(
+        "martinez=info,rpc=info",
+        true,
+        &opt.observability,
+    )?);
+
+    let upstream = opt
+        .upstream_url
+        .map(|url| HttpClientBuilder::default().build(url))
+        .transpose()?
+        .map(Arc::new);
+
+    let limits = SubmissionLimits::new(
+        opt.rpc_tx_fee_cap,
+        opt.rpc_min_gas_price,
+        PoolLimits {
+            max_per_account: opt.txpool_account_slots,
+            max_total: opt.txpool_global_slots,
+            price_bump: opt.txpool_price_bump,
+        },
+    )?;
+    let log_limits = LogLimits::new(
+        opt.rpc_logs_max_blocks,
+        opt.rpc_logs_max_results,
+        opt.rpc_logs_max_topics,
     );
 
-    let server = HttpServerBuilder::default().build(opt.listen_address)?;
-    let _server_handle = server.start(EthApiServerImpl { db }.into_rpc())?;
+    let mut server_handles = vec![
+        serve(
+            &opt.datadir,
+            Endpoints {
+                http: opt.listen_address,
+                http_api: &opt.http_api,
+                ws: opt.ws_listen_address,
+                ws_api: &opt.ws_api,
+                ipc: opt.ipc_path.as_deref(),
+                ipc_api: &opt.ipc_api,
+            },
+            opt.head,
+            opt.compression,
+            opt.etherbase,
+            limits,
+            log_limits,
+            opt.rpc_receipts_cache_blocks,
+            upstream,
+            opt.upstream_cache_entries,
+            opt.sentry_api_addr,
+            observability.clone(),
+        )
+        .await?,
+    ];
+    for chain in &opt.extra_chains {
+        server_handles.push(
+            serve(
+                &chain.datadir,
+                Endpoints {
+                    http: chain.listen_address,
+                    http_api: &opt.http_api,
+                    ws: None,
+                    ws_api: &[],
+                    ipc: None,
+                    ipc_api: &[],
+                },
+                opt.head,
+                opt.compression,
+                opt.etherbase,
+                limits,
+                log_limits,
+                opt.rpc_receipts_cache_blocks,
+                None,
+                opt.upstream_cache_entries,
+                None,
+                observability.clone(),
+            )
+            .await?,
+        );
+    }
 
     pending().await
 }
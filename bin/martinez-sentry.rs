@@ -0,0 +1,44 @@
+use clap::Parser;
+use martinez::{
+    observability::ObservabilityOpts,
+    sentry::{chain_config::ChainsConfig, server::SentryOpts},
+};
+use std::net::SocketAddr;
+
+#[derive(Parser)]
+#[clap(
+    name = "Martinez Sentry",
+    about = "P2P networking service for Martinez"
+)]
+pub struct Opt {
+    /// Name of the chain to find peers for.
+    #[clap(long = "chain", default_value = "mainnet")]
+    pub chain_name: String,
+
+    /// Address to serve the sentry gRPC API on.
+    #[clap(long = "sentry.api.addr", default_value = "127.0.0.1:8000")]
+    pub sentry_api_addr: SocketAddr,
+
+    #[clap(flatten)]
+    pub sentry: SentryOpts,
+
+    #[clap(flatten)]
+    pub observability: ObservabilityOpts,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let opt = Opt::parse();
+
+    let _observability =
+        martinez::observability::init("martinez=info,sentry=info", true, &opt.observability)?;
+
+    let chain_config = ChainsConfig::new()?.get(&opt.chain_name)?;
+
+    martinez::sentry::server::run(
+        opt.sentry,
+        opt.sentry_api_addr,
+        &chain_config.chain_spec().p2p,
+    )
+    .await
+}
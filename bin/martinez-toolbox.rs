@@ -1,19 +1,50 @@
 use martinez::{
+    accessors::chain,
     binutil::MartinezDataDir,
+    consensus::pre_validate_body,
+    crypto::{keccak, keccak256},
+    era1::{self, Era1Block, Era1Reader, Era1Writer, MAX_ERA1_SIZE},
+    execution::execute_block,
     hex_to_bytes,
     kv::{
-        tables::{self, CHAINDATA_TABLES},
+        mdbx::MdbxEnvironment,
+        tables::{self, split_chart, DatabaseChart, CHAINDATA_TABLES},
         traits::*,
     },
     models::*,
-    stagedsync,
+    sentry::{
+        sentry_client_connector::{
+            SentryClientConnector, SentryClientConnectorImpl, SentryClientConnectorTest,
+        },
+        sentry_client_recorder::SentryClientReplay,
+    },
+    stagedsync::{
+        self,
+        stage::{ExecOutput, Stage, StageInput, UnwindInput},
+        stages::*,
+    },
     stages::*,
+    trie::{self, TrieNode},
+    Buffer,
 };
 use anyhow::{bail, ensure, format_err, Context};
 use bytes::Bytes;
 use clap::Parser;
+use flate2::{write::GzEncoder, Compression};
 use itertools::Itertools;
-use std::{borrow::Cow, path::PathBuf, sync::Arc};
+use mdbx::EnvironmentKind;
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 use tokio::pin;
 use tracing::*;
 use tracing_subscriber::{prelude::*, EnvFilter};
@@ -55,6 +86,40 @@ pub enum OptCommand {
         max_entries: Option<usize>,
     },
 
+    /// Dump intermediate trie nodes, either along the path of an account or under a prefix
+    DbDumpTrie {
+        /// Account to print the trie path of, with its storage trie if `--storage` is set
+        #[clap(long)]
+        address: Option<Address>,
+        /// Nibble prefix in hex, e.g. `a3f`, of the account trie or, with `--storage`, of the storage trie
+        #[clap(long)]
+        prefix: Option<String>,
+        /// Dump the storage trie of `address` instead of the account trie path
+        #[clap(long, requires = "address")]
+        storage: bool,
+    },
+
+    /// Dump all accounts as of a past block, one line per account
+    DbDumpState {
+        /// Block to dump state after, defaults to the last executed block
+        #[clap(long)]
+        block: Option<BlockNumber>,
+        /// Either `json`, lines in the shape of `geth dump --iterative`, or `csv`
+        #[clap(long, default_value = "json")]
+        format: DumpFormat,
+        /// Include storage of every account
+        #[clap(long)]
+        storage: bool,
+    },
+
+    /// Copy-compact the database, and its history environment if kept separately, into new files
+    /// and swap them in place of the old ones
+    DbCompact {
+        /// Keep the original database next to the compacted one instead of deleting it
+        #[clap(long)]
+        keep_original: bool,
+    },
+
     /// Check table equality in two databases
     CheckEqual {
         #[clap(long, parse(from_os_str))]
@@ -65,6 +130,13 @@ pub enum OptCommand {
         table: String,
     },
 
+    /// Check that hashed state matches plain state
+    CheckHashedState,
+
+    /// Repair hashed state from plain state and rebuild the state trie, checking it against the
+    /// state root of the last executed block
+    HealState,
+
     /// Execute Block Hashes stage
     Blockhashes,
 
@@ -94,6 +166,74 @@ pub enum OptCommand {
     ReadStorageChanges {
         block: BlockNumber,
     },
+
+    /// Export executed blocks with their receipts into era1 archives
+    Era1Export {
+        /// First era to export, era N covers blocks N * 8192 to N * 8192 + 8191
+        #[clap(long)]
+        start_epoch: u64,
+        /// Number of eras to export
+        #[clap(long, default_value = "1")]
+        epochs: u64,
+        #[clap(long, parse(from_os_str))]
+        output_dir: PathBuf,
+    },
+
+    /// Import headers and bodies from era1 archives, on top of the current chain
+    Era1Import {
+        #[clap(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+
+    /// Export canonical blocks into a chain file of concatenated RLP blocks, as written by `geth
+    /// export` and read by `geth import` and `reth import`, gzipped if the name ends in `.gz`
+    ChainExport {
+        /// First block to export
+        #[clap(long, default_value = "0")]
+        from: BlockNumber,
+        /// Last block to export, the head of the canonical chain by default
+        #[clap(long)]
+        to: Option<BlockNumber>,
+        #[clap(parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Write the chain spec as a geth genesis file, for `geth init` and `reth --chain`
+    GenesisExport {
+        #[clap(parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Compare Keccak-256 throughput of one-by-one and batched hashing
+    BenchKeccak {
+        /// Number of inputs to hash
+        #[clap(long, default_value = "4000000")]
+        count: usize,
+        /// Size of each input in bytes, 20 for addresses, 32 for storage slots, ~540 for headers
+        #[clap(long, default_value = "32")]
+        size: usize,
+        /// Inputs per batch
+        #[clap(long, default_value = "65536")]
+        batch_size: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum DumpFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for DumpFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => bail!("expected json or csv, got {}", s),
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -112,6 +252,12 @@ pub struct HeaderDownloadOpts {
     )]
     pub sentry_api_addr: martinez::sentry::sentry_address::SentryAddress,
 
+    #[clap(
+        long = "replay",
+        help = "Replay a session recorded with --downloader.record instead of connecting to a sentry, on the chain it was recorded on"
+    )]
+    pub replay: Option<PathBuf>,
+
     #[clap(flatten)]
     pub downloader_opts: martinez::downloader::opts::Opts,
 }
@@ -139,19 +285,147 @@ async fn blockhashes(data_dir: MartinezDataDir) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn bench_keccak(count: usize, size: usize, batch_size: usize) -> anyhow::Result<()> {
+    ensure!(batch_size > 0, "batch size must be positive");
+
+    let inputs = (0..count as u64)
+        .map(|i| {
+            i.to_be_bytes()
+                .iter()
+                .copied()
+                .cycle()
+                .take(size)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let started_at = Instant::now();
+    let sequential = inputs.iter().map(keccak256).collect::<Vec<_>>();
+    let sequential_elapsed = started_at.elapsed();
+
+    let started_at = Instant::now();
+    let mut batched = Vec::with_capacity(count);
+    for chunk in inputs.chunks(batch_size) {
+        batched.extend(keccak::hash_batch(chunk));
+    }
+    let batched_elapsed = started_at.elapsed();
+
+    ensure!(sequential == batched, "batched hashes differ");
+
+    for (name, elapsed) in [
+        ("one-by-one", sequential_elapsed),
+        ("batched", batched_elapsed),
+    ] {
+        println!(
+            "{:>10}: {:?}, {:.0} hashes/s, {:.1} MB/s",
+            name,
+            elapsed,
+            count as f64 / elapsed.as_secs_f64(),
+            (count * size) as f64 / elapsed.as_secs_f64() / 1_000_000.0
+        );
+    }
+
+    Ok(())
+}
+
 #[allow(unreachable_code)]
+fn check_hashed_state_cmd(data_dir: MartinezDataDir) -> anyhow::Result<()> {
+    let etl_temp_path = data_dir.etl_temp_dir();
+    std::fs::create_dir_all(&etl_temp_path)?;
+    let etl_temp_dir =
+        tempfile::tempdir_in(&etl_temp_path).context("failed to create ETL temp dir")?;
+
+    let env = open_db(data_dir)?;
+
+    let tx = env.begin()?;
+
+    let mismatches = check_hashed_state(&tx, &etl_temp_dir)?;
+    for mismatch in &mismatches {
+        println!("{:?}", mismatch);
+    }
+
+    ensure!(
+        mismatches.is_empty(),
+        "{} mismatches between hashed and plain state",
+        mismatches.len()
+    );
+
+    info!("Hashed state matches plain state");
+
+    Ok(())
+}
+
+fn heal_state(data_dir: MartinezDataDir) -> anyhow::Result<()> {
+    let etl_temp_path = data_dir.etl_temp_dir();
+    std::fs::create_dir_all(&etl_temp_path)?;
+    let etl_temp_dir =
+        tempfile::tempdir_in(&etl_temp_path).context("failed to create ETL temp dir")?;
+
+    let env = martinez::kv::mdbx::MdbxEnvironment::<mdbx::NoWriteMap>::open_rw(
+        mdbx::Environment::new(),
+        &data_dir.chain_data_dir(),
+        CHAINDATA_TABLES.clone(),
+    )?;
+    let tx = env.begin_mutable()?;
+
+    let executed = EXECUTION.get_progress(&tx)?.unwrap_or(BlockNumber(0));
+    let hash = chain::canonical_hash::read(&tx, executed)?
+        .ok_or_else(|| format_err!("No canonical hash for block {}", executed))?;
+    let state_root = chain::header::read(&tx, BlockKey::new(executed, hash))?
+        .ok_or_else(|| format_err!("No header for block {}", executed))?
+        .state_root;
+
+    let mismatches = check_hashed_state(&tx, &etl_temp_dir)?;
+    info!(
+        "Healing {} mismatches between hashed and plain state",
+        mismatches.len()
+    );
+    heal_hashed_state(&tx, &mismatches)?;
+
+    info!("Rebuilding state trie");
+    let root = trie::regenerate_intermediate_hashes(&tx, &etl_temp_dir, Some(state_root))
+        .with_context(|| {
+            format!(
+                "plain state does not match block {}, re-execute it from an earlier block",
+                executed
+            )
+        })?;
+
+    HASH_STATE.save_progress(&tx, executed)?;
+    INTERMEDIATE_HASHES.save_progress(&tx, executed)?;
+    tx.commit()?;
+
+    info!("Block #{} state root OK: {:?}", executed, root);
+
+    Ok(())
+}
+
 async fn header_download(data_dir: MartinezDataDir, opts: HeaderDownloadOpts) -> anyhow::Result<()> {
     let chains_config = martinez::sentry::chain_config::ChainsConfig::new()?;
-    let chain_config = chains_config.get(&opts.chain_name)?;
 
-    let sentry_api_addr = opts.sentry_api_addr.clone();
-    let sentry_connector =
-        martinez::sentry::sentry_client_connector::SentryClientConnectorImpl::new(sentry_api_addr);
+    let (chain_config, sentry_connector, played_out) = match &opts.replay {
+        Some(path) => {
+            let replay = SentryClientReplay::open(path).await?;
+            let chain_config = chains_config.get(replay.chain_name())?;
+            let played_out = replay.played_out();
+            let connector: Box<dyn SentryClientConnector> =
+                Box::new(SentryClientConnectorTest::new(Box::new(replay)));
+            (chain_config, connector, Some(played_out))
+        }
+        None => {
+            let chain_config = chains_config.get(&opts.chain_name)?;
+            let connector = opts.downloader_opts.sentry_connector(
+                Box::new(SentryClientConnectorImpl::new(opts.sentry_api_addr.clone())),
+                &chain_config.chain_name(),
+            )?;
+            (chain_config, connector, None)
+        }
+    };
 
     let sentry_status_provider =
         martinez::downloader::sentry_status_provider::SentryStatusProvider::new(chain_config.clone());
     let mut sentry_reactor = martinez::sentry::sentry_client_reactor::SentryClientReactor::new(
-        Box::new(sentry_connector),
+        sentry_connector,
         sentry_status_provider.current_status_stream(),
     );
     sentry_reactor.start()?;
@@ -163,20 +437,92 @@ async fn header_download(data_dir: MartinezDataDir, opts: HeaderDownloadOpts) ->
         opts.downloader_opts.headers_batch_size,
         sentry.clone(),
         sentry_status_provider,
+        opts.downloader_opts.ui_mode(),
     )?;
 
     std::fs::create_dir_all(&data_dir.0)?;
     let db = martinez::kv::new_database(&data_dir.chain_data_dir())?;
 
-    let mut staged_sync = stagedsync::StagedSync::new();
-    staged_sync.push(stage);
-    staged_sync.run(&db).await?;
+    if let Some(played_out) = played_out {
+        replay_header_download(&db, stage, &played_out).await?;
+    } else {
+        let mut staged_sync = stagedsync::StagedSync::new();
+        staged_sync.push(stage);
+        staged_sync.run(&db).await?;
+    }
 
     let _ = sentry.write().await.stop().await;
 
     Ok(())
 }
 
+/// Run `stage` until the recording it downloads from is played out, committing after every run
+/// as the sync loop does.
+async fn replay_header_download<E: EnvironmentKind>(
+    db: &MdbxEnvironment<E>,
+    mut stage: HeaderDownload,
+    played_out: &AtomicBool,
+) -> anyhow::Result<()> {
+    let start_time = Instant::now();
+    let mut tx = db.begin_mutable()?;
+    let start_progress = HEADERS.get_progress(&tx)?;
+
+    let mut progress = start_progress;
+    let mut restarted = false;
+    loop {
+        let done = match stage
+            .execute(
+                &mut tx,
+                StageInput {
+                    restarted,
+                    first_started_at: (start_time, start_progress),
+                    previous_stage: None,
+                    stage_progress: progress,
+                },
+            )
+            .await?
+        {
+            ExecOutput::Progress {
+                stage_progress,
+                done,
+            } => {
+                HEADERS.save_progress(&tx, stage_progress)?;
+                progress = Some(stage_progress);
+                done
+            }
+            ExecOutput::Unwind { unwind_to } => {
+                let output = stage
+                    .unwind(
+                        &mut tx,
+                        UnwindInput {
+                            stage_progress: progress.unwrap_or_default(),
+                            unwind_to,
+                        },
+                    )
+                    .await?;
+                HEADERS.save_progress(&tx, output.stage_progress)?;
+                progress = Some(output.stage_progress);
+                false
+            }
+        };
+        tx.commit()?;
+
+        if done || played_out.load(Ordering::SeqCst) {
+            break;
+        }
+        tx = db.begin_mutable()?;
+        restarted = true;
+    }
+
+    info!(
+        "Replay reached header {} in {}",
+        progress.unwrap_or_default(),
+        stagedsync::format_duration(Instant::now() - start_time, true)
+    );
+
+    Ok(())
+}
+
 fn open_db(
     data_dir: MartinezDataDir,
 ) -> anyhow::Result<martinez::kv::mdbx::MdbxEnvironment<mdbx::NoWriteMap>> {
@@ -215,6 +561,125 @@ fn table_sizes(data_dir: MartinezDataDir, csv: bool) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn dir_size(path: &Path) -> anyhow::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += entry?.metadata()?.len();
+    }
+    Ok(size)
+}
+
+/// `path` with `suffix` appended to its last component.
+fn sibling_path(path: &Path, suffix: &str) -> anyhow::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("no directory name in {}", path.display()))?;
+    Ok(path.with_file_name(format!("{}-{}", name.to_string_lossy(), suffix)))
+}
+
+/// Copy the tables of `chart` from `src` into a new environment at `dst_path`.
+fn compact_env(
+    src: &mdbx::Environment<mdbx::NoWriteMap>,
+    chart: &DatabaseChart,
+    dst_path: &Path,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dst_path)?;
+    let dst = martinez::kv::mdbx::MdbxEnvironment::<mdbx::NoWriteMap>::open_rw(
+        mdbx::Environment::new(),
+        dst_path,
+        chart.clone(),
+    )?;
+
+    let src_txn = src.begin_ro_txn()?;
+    for (table, info) in chart.iter().sorted_by_key(|(table, _)| *table) {
+        let src_db = src_txn
+            .open_db(Some(table))
+            .with_context(|| format!("failed to open table: {}", table))?;
+        let mut src_cur = src_txn.cursor(&src_db)?;
+
+        let dst_txn = dst.begin_rw_txn()?;
+        let dst_db = dst_txn.open_db(Some(table))?;
+        let mut dst_cur = dst_txn.cursor(&dst_db)?;
+
+        let flags = if info.dup_sort {
+            mdbx::WriteFlags::APPEND_DUP
+        } else {
+            mdbx::WriteFlags::APPEND
+        };
+
+        let mut entries = 0_u64;
+        for res in src_cur.iter_start::<Cow<[u8]>, Cow<[u8]>>() {
+            let (k, v) = res?;
+            dst_cur.put(&k, &v, flags)?;
+            entries += 1;
+        }
+        drop(dst_cur);
+        dst_txn.commit()?;
+
+        info!("Copied {}: {} entries", table, entries);
+    }
+
+    Ok(())
+}
+
+fn db_compact(data_dir: MartinezDataDir, keep_original: bool) -> anyhow::Result<()> {
+    let chain_data_dir = data_dir.chain_data_dir();
+    let src = open_db(data_dir)?;
+
+    // Environments to compact, with the tables each one holds. History kept on its own
+    // environment is compacted there, so that the main one does not get its tables.
+    let mut envs = Vec::new();
+    match (src.cold(), src.cold_path()?) {
+        (Some(cold), Some(cold_path)) => {
+            let (hot_chart, cold_chart) = split_chart(&CHAINDATA_TABLES);
+            envs.push((chain_data_dir, &*src, hot_chart));
+            envs.push((cold_path, cold, cold_chart));
+        }
+        _ => envs.push((chain_data_dir, &*src, CHAINDATA_TABLES.clone())),
+    }
+
+    for (src_path, _, _) in &envs {
+        ensure!(
+            !sibling_path(src_path, "compact")?.exists()
+                && !sibling_path(src_path, "old")?.exists(),
+            "leftovers from a previous compaction found next to {}, remove them first",
+            src_path.display()
+        );
+    }
+    for (src_path, src_env, chart) in &envs {
+        compact_env(src_env, chart, &sibling_path(src_path, "compact")?)?;
+    }
+    let src_paths = envs
+        .into_iter()
+        .map(|(src_path, _, _)| src_path)
+        .collect::<Vec<_>>();
+    drop(src);
+
+    let mut before = 0;
+    let mut after = 0;
+    for src_path in src_paths {
+        let dst_path = sibling_path(&src_path, "compact")?;
+        let old_path = sibling_path(&src_path, "old")?;
+        before += dir_size(&src_path)?;
+        after += dir_size(&dst_path)?;
+
+        std::fs::rename(&src_path, &old_path)?;
+        std::fs::rename(&dst_path, &src_path)?;
+        if !keep_original {
+            std::fs::remove_dir_all(&old_path)?;
+        }
+    }
+
+    info!(
+        "Compaction complete: {} -> {}, reclaimed {}",
+        bytesize::ByteSize::b(before),
+        bytesize::ByteSize::b(after),
+        bytesize::ByteSize::b(before.saturating_sub(after))
+    );
+
+    Ok(())
+}
+
 fn db_query(data_dir: MartinezDataDir, table: String, key: Bytes) -> anyhow::Result<()> {
     let env = open_db(data_dir)?;
 
@@ -340,6 +805,260 @@ fn check_table_eq(db1_path: PathBuf, db2_path: PathBuf, table: String) -> anyhow
     Ok(())
 }
 
+fn era1_export(
+    data_dir: MartinezDataDir,
+    start_epoch: u64,
+    epochs: u64,
+    output_dir: PathBuf,
+) -> anyhow::Result<()> {
+    let env = open_db(data_dir)?;
+    let tx = env.begin()?;
+
+    let chain_spec =
+        chain::chain_config::read(&tx)?.ok_or_else(|| format_err!("No chain config"))?;
+    let executed = EXECUTION.get_progress(&tx)?.unwrap_or(BlockNumber(0));
+
+    std::fs::create_dir_all(&output_dir)?;
+
+    for epoch in start_epoch..start_epoch + epochs {
+        let start = BlockNumber(epoch * MAX_ERA1_SIZE as u64);
+        let end = start + (MAX_ERA1_SIZE as u64 - 1);
+
+        // Receipts are regenerated by execution, and pre-Byzantium receipts carry
+        // intermediate state roots that execution does not keep.
+        ensure!(
+            chain_spec.collect_block_spec(start).revision >= Revision::Byzantium,
+            "era {} has pre-Byzantium blocks, their receipts cannot be regenerated",
+            epoch
+        );
+        ensure!(
+            end <= executed,
+            "era {} ends at block {}, but execution has only reached {}",
+            epoch,
+            end,
+            executed
+        );
+
+        let tmp_path = output_dir.join(format!("{}.era1.tmp", epoch));
+        let mut writer = Era1Writer::new(BufWriter::new(File::create(&tmp_path)?))?;
+        let mut buffer = Buffer::new(&tx, BlockNumber(0), Some(BlockNumber(start.0 - 1)));
+
+        for block_number in start..=end {
+            let hash = chain::canonical_hash::read(&tx, block_number)?
+                .ok_or_else(|| format_err!("no canonical block {}", block_number))?;
+            let key = BlockKey::new(block_number, hash);
+            let header = chain::header::read(&tx, key)?
+                .ok_or_else(|| format_err!("header {} not found", block_number))?;
+            ensure!(
+                header.difficulty != 0,
+                "block {} is past the merge, era1 only covers proof-of-work blocks",
+                block_number
+            );
+            let body = chain::block_body::read_without_senders(&tx, key)?
+                .ok_or_else(|| format_err!("block body {} not found", block_number))?;
+            let senders = chain::tx_sender::read(&tx, key)?;
+            ensure!(
+                senders.len() == body.transactions.len(),
+                "senders of block {} not recovered",
+                block_number
+            );
+            let total_difficulty = chain::td::read(&tx, key)?.ok_or_else(|| {
+                format_err!("total difficulty of block {} not found", block_number)
+            })?;
+
+            let receipts = execute_block(
+                &mut buffer,
+                &chain_spec,
+                &header.clone().into(),
+                &BlockBodyWithSenders {
+                    transactions: body
+                        .transactions
+                        .iter()
+                        .zip(senders)
+                        .map(|(transaction, sender)| MessageWithSender {
+                            message: transaction.message.clone(),
+                            sender,
+                        })
+                        .collect(),
+                    ommers: body.ommers.clone(),
+                    rollup_cost_data: body
+                        .transactions
+                        .iter()
+                        .map(MessageWithSignature::rollup_cost_data)
+                        .collect(),
+                },
+            )?;
+
+            writer.push(&Era1Block {
+                header,
+                body,
+                receipts: era1::encode_receipts(&receipts),
+                total_difficulty,
+            })?;
+        }
+
+        let root = writer.finish()?;
+        let path = output_dir.join(era1::file_name(&chain_spec.name, epoch, root));
+        std::fs::rename(&tmp_path, &path)?;
+
+        info!("Exported era {} to {}", epoch, path.display());
+    }
+
+    Ok(())
+}
+
+fn write_chain<W: Write>(
+    tx: &martinez::kv::mdbx::MdbxTransaction<'_, mdbx::RO, mdbx::NoWriteMap>,
+    from: BlockNumber,
+    to: BlockNumber,
+    w: &mut W,
+) -> anyhow::Result<()> {
+    for block_number in from..=to {
+        let hash = chain::canonical_hash::read(tx, block_number)?
+            .ok_or_else(|| format_err!("no canonical block {}", block_number))?;
+        let header = chain::header::read(tx, BlockKey::new(block_number, hash))?
+            .ok_or_else(|| format_err!("header {} not found", block_number))?;
+        let body = chain::block_body::read_without_senders(tx, BlockKey::new(block_number, hash))?
+            .ok_or_else(|| format_err!("block body {} not found", block_number))?;
+
+        w.write_all(&rlp::encode(&Block {
+            header,
+            transactions: body.transactions,
+            ommers: body.ommers,
+        }))?;
+
+        if block_number.0 % 100_000 == 0 {
+            info!("Exported block {}", block_number);
+        }
+    }
+
+    Ok(())
+}
+
+fn chain_export(
+    data_dir: MartinezDataDir,
+    from: BlockNumber,
+    to: Option<BlockNumber>,
+    output: PathBuf,
+) -> anyhow::Result<()> {
+    let env = open_db(data_dir)?;
+    let tx = env.begin()?;
+
+    let to = match to {
+        Some(to) => to,
+        None => chain::canonical_hash::last(&tx)?
+            .map(|(number, _)| number)
+            .ok_or_else(|| format_err!("no canonical chain"))?,
+    };
+    ensure!(from <= to, "empty block range {}..={}", from, to);
+
+    let file = BufWriter::new(File::create(&output)?);
+    if output.extension() == Some("gz".as_ref()) {
+        let mut w = GzEncoder::new(file, Compression::default());
+        write_chain(&tx, from, to, &mut w)?;
+        w.finish()?.flush()?;
+    } else {
+        let mut w = file;
+        write_chain(&tx, from, to, &mut w)?;
+        w.flush()?;
+    }
+
+    info!("Exported blocks {} to {} to {}", from, to, output.display());
+
+    Ok(())
+}
+
+fn genesis_export(data_dir: MartinezDataDir, output: PathBuf) -> anyhow::Result<()> {
+    let env = open_db(data_dir)?;
+    let tx = env.begin()?;
+
+    let chain_spec =
+        chain::chain_config::read(&tx)?.ok_or_else(|| format_err!("No chain config"))?;
+
+    let mut w = BufWriter::new(File::create(&output)?);
+    serde_json::to_writer_pretty(&mut w, &geth_genesis_json(&chain_spec)?)?;
+    w.flush()?;
+
+    Ok(())
+}
+
+fn era1_import(data_dir: MartinezDataDir, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    let env = martinez::kv::mdbx::MdbxEnvironment::<mdbx::NoWriteMap>::open_rw(
+        mdbx::Environment::new(),
+        &data_dir.chain_data_dir(),
+        CHAINDATA_TABLES.clone(),
+    )?;
+
+    for path in files {
+        let tx = env.begin_mutable()?;
+
+        let head = HEADERS.get_progress(&tx)?.unwrap_or(BlockNumber(0));
+        ensure!(
+            BODIES.get_progress(&tx)?.unwrap_or(BlockNumber(0)) == head,
+            "bodies are behind headers, cannot import on top of them"
+        );
+        let mut parent_hash = chain::canonical_hash::read(&tx, head)?
+            .ok_or_else(|| format_err!("no canonical block {}", head))?;
+
+        let mut reader = Era1Reader::new(BufReader::new(File::open(&path)?))?;
+        let mut next = head + 1;
+        while let Some(Era1Block {
+            header,
+            body,
+            total_difficulty,
+            ..
+        }) = reader.next_block()?
+        {
+            let number = header.number;
+            let hash = header.hash();
+            ensure!(
+                number == next,
+                "block {} in {} does not follow block {}",
+                number,
+                path.display(),
+                next.0 - 1
+            );
+            ensure!(
+                header.parent_hash == parent_hash,
+                "block {} does not extend the canonical chain",
+                number
+            );
+
+            pre_validate_body(&header, &body.transactions, &body.ommers)
+                .with_context(|| format!("body of block {} does not match its header", number))?;
+
+            tx.set(tables::Header, (number, hash), header)?;
+            tx.set(tables::CanonicalHeader, number, hash)?;
+            tx.set(tables::HeaderNumber, hash, number)?;
+            tx.set(
+                tables::HeadersTotalDifficulty,
+                (number, hash),
+                total_difficulty,
+            )?;
+
+            chain::block_body::write(&tx, BlockKey::new(number, hash), &body)?;
+
+            parent_hash = hash;
+            next = number + 1;
+        }
+
+        ensure!(next > head + 1, "{} has no blocks", path.display());
+        let last = BlockNumber(next.0 - 1);
+        HEADERS.save_progress(&tx, last)?;
+        BODIES.save_progress(&tx, last)?;
+        tx.commit()?;
+
+        info!(
+            "Imported blocks {} to {} from {}",
+            head + 1,
+            last,
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
 fn read_block(data_dir: MartinezDataDir, block_num: BlockNumber) -> anyhow::Result<()> {
     let env = open_db(data_dir)?;
 
@@ -351,9 +1070,11 @@ fn read_block(data_dir: MartinezDataDir, block_num: BlockNumber) -> anyhow::Resu
     let header = tx
         .get(tables::Header, (block_num, canonical_hash))?
         .ok_or_else(|| format_err!("header not found"))?;
-    let body =
-        martinez::accessors::chain::block_body::read_without_senders(&tx, canonical_hash, block_num)?
-            .ok_or_else(|| format_err!("block body not found"))?;
+    let body = martinez::accessors::chain::block_body::read_without_senders(
+        &tx,
+        BlockKey::new(block_num, canonical_hash),
+    )?
+    .ok_or_else(|| format_err!("block body not found"))?;
 
     let partial_header = PartialHeader::from(header.clone());
 
@@ -372,6 +1093,26 @@ fn read_block(data_dir: MartinezDataDir, block_num: BlockNumber) -> anyhow::Resu
         block.header.ommers_hash
     );
 
+    // Receipts are not stored, but the logs they carry are, which is enough for the bloom.
+    if EXECUTION.get_progress(&tx)?.unwrap_or(BlockNumber(0)) >= block_num {
+        let mut logs = vec![];
+        for res in tx.cursor(tables::Log)?.walk(Some((block_num, TxIndex(0)))) {
+            let ((block_number, _), tx_logs) = res?;
+            if block_number != block_num {
+                break;
+            }
+            logs.extend(tx_logs);
+        }
+
+        let logs_bloom = logs_bloom(&logs);
+        ensure!(
+            logs_bloom == header.logs_bloom,
+            "bloom mismatch: expected in header {:?}, computed {:?}",
+            header.logs_bloom,
+            logs_bloom
+        );
+    }
+
     println!("{:?}", partial_header);
     println!("OMMERS:");
     for (i, v) in block.ommers.into_iter().enumerate() {
@@ -386,6 +1127,206 @@ fn read_block(data_dir: MartinezDataDir, block_num: BlockNumber) -> anyhow::Resu
     Ok(())
 }
 
+fn parse_nibbles(s: &str) -> anyhow::Result<Vec<u8>> {
+    s.chars()
+        .map(|c| {
+            c.to_digit(16)
+                .map(|n| n as u8)
+                .ok_or_else(|| format_err!("invalid nibble {:?}", c))
+        })
+        .collect()
+}
+
+fn format_nibbles(nibbles: &[u8]) -> String {
+    if nibbles.is_empty() {
+        "(root)".to_string()
+    } else {
+        nibbles.iter().map(|n| format!("{:x}", n)).collect()
+    }
+}
+
+fn print_trie_node(node: &TrieNode) {
+    let indent = "  ".repeat(node.path.len());
+    println!(
+        "{}{} state={:016b} tree={:016b} hash={:016b}",
+        indent,
+        format_nibbles(&node.path),
+        node.state_mask,
+        node.tree_mask,
+        node.hash_mask
+    );
+    if let Some(root_hash) = node.root_hash {
+        println!("{}  root {:?}", indent, root_hash);
+    }
+    for child in node.children() {
+        println!(
+            "{}  {:x}: {}{}",
+            indent,
+            child.nibble,
+            if child.in_tree { "branch" } else { "-" },
+            child
+                .hash
+                .map(|hash| format!(" {:?}", hash))
+                .unwrap_or_default()
+        );
+    }
+}
+
+fn db_dump_trie(
+    data_dir: MartinezDataDir,
+    address: Option<Address>,
+    prefix: Option<String>,
+    storage: bool,
+) -> anyhow::Result<()> {
+    let env = open_db(data_dir)?;
+
+    let tx = env.begin()?;
+
+    let prefix = parse_nibbles(prefix.as_deref().unwrap_or_default())?;
+
+    match address {
+        Some(address) if storage => {
+            let hashed_address = keccak256(address);
+            println!("Storage trie of {:?} ({:?})", address, hashed_address);
+            for node in trie::walk_storage_trie(&tx, hashed_address, prefix)? {
+                print_trie_node(&node?);
+            }
+        }
+        Some(address) => {
+            let hashed_address = keccak256(address);
+            println!("Account trie path of {:?} ({:?})", address, hashed_address);
+            let path = trie::account_trie_path(&tx, address)?;
+            for node in &path {
+                print_trie_node(node);
+            }
+            // Whatever sits below the deepest node on the path belongs to neighbouring accounts.
+            if let Some(last) = path.last() {
+                println!("Subtrie under {}", format_nibbles(&last.path));
+                for node in trie::walk_account_trie(&tx, last.path.clone())?.skip(1) {
+                    print_trie_node(&node?);
+                }
+            }
+        }
+        None => {
+            for node in trie::walk_account_trie(&tx, prefix)? {
+                print_trie_node(&node?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn db_dump_state(
+    data_dir: MartinezDataDir,
+    block: Option<BlockNumber>,
+    format: DumpFormat,
+    storage: bool,
+) -> anyhow::Result<()> {
+    let env = open_db(data_dir)?;
+
+    let tx = env.begin()?;
+
+    let executed = EXECUTION.get_progress(&tx)?.unwrap_or(BlockNumber(0));
+    let block = block.unwrap_or(executed);
+    ensure!(
+        block <= executed,
+        "block {} is past the last executed block {}",
+        block,
+        executed
+    );
+    // State after `block` is rebuilt from the changesets of later blocks.
+    martinez::accessors::prune::ensure_available(
+        &tx,
+        martinez::accessors::prune::PruneTarget::History,
+        block + 1,
+    )?;
+
+    let canonical_hash = tx
+        .get(tables::CanonicalHeader, block)?
+        .ok_or_else(|| format_err!("no such canonical block"))?;
+    let header = tx
+        .get(tables::Header, (block, canonical_hash))?
+        .ok_or_else(|| format_err!("header not found"))?;
+
+    let changed_storage = if storage {
+        martinez::accessors::state::dump::changed_storage(&tx, block)?
+    } else {
+        Default::default()
+    };
+
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    match format {
+        DumpFormat::Json => writeln!(out, "{}", serde_json::json!({ "root": header.state_root }))?,
+        DumpFormat::Csv => writeln!(out, "address,balance,nonce,code_hash,location,value")?,
+    }
+
+    martinez::accessors::state::dump::walk_accounts(&tx, block, |address, account| {
+        let mut slots = vec![];
+        if storage {
+            martinez::accessors::state::dump::walk_storage(
+                &tx,
+                address,
+                block,
+                changed_storage.get(&address).unwrap_or(&Default::default()),
+                |location, value| {
+                    slots.push((location, value));
+                    Ok(())
+                },
+            )?;
+        }
+
+        match format {
+            DumpFormat::Json => {
+                let mut entry = serde_json::json!({
+                    "balance": account.balance.to_string(),
+                    "nonce": account.nonce,
+                    "codeHash": account.code_hash,
+                    "address": address,
+                });
+                if account.code_hash != EMPTY_HASH {
+                    let code = tx
+                        .get(tables::Code, account.code_hash)?
+                        .ok_or_else(|| format_err!("code {:?} not found", account.code_hash))?;
+                    entry["code"] = format!("0x{}", hex::encode(&code)).into();
+                }
+                if storage {
+                    entry["storage"] = slots
+                        .into_iter()
+                        .map(|(location, value)| {
+                            (
+                                format!("{:?}", location),
+                                serde_json::Value::from(format!(
+                                    "{:?}",
+                                    martinez::u256_to_h256(value)
+                                )),
+                            )
+                        })
+                        .collect::<serde_json::Map<String, serde_json::Value>>()
+                        .into();
+                }
+                writeln!(out, "{}", entry)?;
+            }
+            DumpFormat::Csv => {
+                writeln!(
+                    out,
+                    "{:?},{},{},{:?},,",
+                    address, account.balance, account.nonce, account.code_hash
+                )?;
+                for (location, value) in slots {
+                    writeln!(out, "{:?},,,,{:?},{}", address, location, value)?;
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    out.flush()?;
+
+    Ok(())
+}
+
 fn read_account(data_dir: MartinezDataDir, address: Address) -> anyhow::Result<()> {
     let env = open_db(data_dir)?;
 
@@ -491,13 +1432,41 @@ async fn main() -> anyhow::Result<()> {
             starting_key,
             max_entries,
         } => db_walk(opt.data_dir, table, starting_key, max_entries)?,
+        OptCommand::DbDumpTrie {
+            address,
+            prefix,
+            storage,
+        } => db_dump_trie(opt.data_dir, address, prefix, storage)?,
+        OptCommand::DbDumpState {
+            block,
+            format,
+            storage,
+        } => db_dump_state(opt.data_dir, block, format, storage)?,
+        OptCommand::DbCompact { keep_original } => db_compact(opt.data_dir, keep_original)?,
         OptCommand::CheckEqual { db1, db2, table } => check_table_eq(db1, db2, table)?,
+        OptCommand::CheckHashedState => check_hashed_state_cmd(opt.data_dir)?,
+        OptCommand::HealState => heal_state(opt.data_dir)?,
         OptCommand::HeaderDownload { opts } => header_download(opt.data_dir, opts).await?,
         OptCommand::ReadBlock { block_number } => read_block(opt.data_dir, block_number)?,
         OptCommand::ReadAccount { address } => read_account(opt.data_dir, address)?,
         OptCommand::ReadAccountChanges { block } => read_account_changes(opt.data_dir, block)?,
         OptCommand::ReadStorage { address } => read_storage(opt.data_dir, address)?,
         OptCommand::ReadStorageChanges { block } => read_storage_changes(opt.data_dir, block)?,
+        OptCommand::Era1Export {
+            start_epoch,
+            epochs,
+            output_dir,
+        } => era1_export(opt.data_dir, start_epoch, epochs, output_dir)?,
+        OptCommand::Era1Import { files } => era1_import(opt.data_dir, files)?,
+        OptCommand::ChainExport { from, to, output } => {
+            chain_export(opt.data_dir, from, to, output)?
+        }
+        OptCommand::GenesisExport { output } => genesis_export(opt.data_dir, output)?,
+        OptCommand::BenchKeccak {
+            count,
+            size,
+            batch_size,
+        } => bench_keccak(count, size, batch_size)?,
     }
 
     Ok(())
@@ -1,6 +1,17 @@
+use anyhow::{bail, format_err, Context};
+use async_trait::async_trait;
+use clap::Parser;
 use martinez::{
+    accessors::chain::{last_forkchoice, tx_sequence},
     binutil::MartinezDataDir,
-    downloader::sentry_status_provider::SentryStatusProvider,
+    consensus::pre_validate_body,
+    downloader::{
+        beacon_checkpoint::{apply_checkpoint, fetch_finalized_checkpoint},
+        heimdall::HeimdallClient,
+        sentry_status_provider::SentryStatusProvider,
+        top_block_estimate::PublishedTopBlockEstimate,
+    },
+    export::{self, ExportFormat, ExportSchema, Table},
     kv::{
         mdbx::*,
         tables::{self, ErasedTable},
@@ -11,16 +22,21 @@ use martinez::{
         sentry_client_connector::SentryClientConnectorImpl,
         sentry_client_reactor::SentryClientReactor,
     },
-    stagedsync::{self, stage::*, stages::*},
+    sql_mirror::SqlMirror,
+    stagedsync::{
+        self,
+        block_stream::{self, BlockStream},
+        header_repair::HeaderRepair,
+        stage::*,
+        stages::*,
+    },
     stages::*,
     version_string, StageId,
 };
-use anyhow::{bail, format_err, Context};
-use async_trait::async_trait;
-use clap::Parser;
 use mdbx::EnvironmentKind;
 use rayon::prelude::*;
 use std::{
+    net::{Ipv4Addr, SocketAddr},
     panic,
     path::PathBuf,
     sync::Arc,
@@ -28,7 +44,6 @@ use std::{
 };
 use tokio::pin;
 use tracing::*;
-use tracing_subscriber::{prelude::*, EnvFilter};
 
 #[derive(Parser)]
 #[clap(name = "Martinez", about = "Next-generation Ethereum implementation.")]
@@ -41,6 +56,12 @@ pub struct Opt {
     #[clap(long = "datadir", help = "Database directory path", default_value_t)]
     pub data_dir: MartinezDataDir,
 
+    /// Keep block history (changesets, history indices, logs and call traces) in a separate
+    /// database at this path, e.g. on a slower disk. Only for new databases; once set, it has
+    /// to be passed on every start.
+    #[clap(long, parse(from_os_str))]
+    pub history_dir: Option<PathBuf>,
+
     /// Name of the testnet to join
     #[clap(
         long = "chain",
@@ -49,6 +70,11 @@ pub struct Opt {
     )]
     pub chain_name: String,
 
+    /// Chain spec file to use instead of `--chain`, either a Martinez RON chain spec or a geth
+    /// genesis.json.
+    #[clap(long = "chain-spec-file", parse(from_os_str))]
+    pub chain_spec_file: Option<PathBuf>,
+
     /// Sentry GRPC service URL
     #[clap(
         long = "sentry.api.addr",
@@ -57,10 +83,29 @@ pub struct Opt {
     )]
     pub sentry_api_addr: martinez::sentry::sentry_address::SentryAddress,
 
+    /// Run the sentry in-process, serving its API on the port of `--sentry.api.addr`.
+    #[clap(long = "sentry.embedded")]
+    pub sentry_embedded: bool,
+
+    /// Embedded sentry options.
+    #[clap(flatten)]
+    pub sentry_opts: martinez::sentry::server::SentryOpts,
+
     /// Last block where to sync to.
     #[clap(long)]
     pub max_block: Option<BlockNumber>,
 
+    /// Stop with an error instead of unwinding more than this many blocks. Unwinds below the
+    /// pruned or finalized horizon are always refused.
+    #[clap(long)]
+    pub max_reorg_depth: Option<u64>,
+
+    /// Canonical blocks checked for a missing header or total difficulty per sync cycle, in the
+    /// background. Missing total difficulties are recomputed, and missing headers downloaded
+    /// again. 0 to disable.
+    #[clap(long = "header-repair.batch", default_value = "100000")]
+    pub header_repair_batch: u64,
+
     /// Use incremental staged sync.
     #[clap(long)]
     pub increment: Option<u64>,
@@ -85,6 +130,18 @@ pub struct Opt {
     #[clap(long)]
     pub execution_exit_after_batch: bool,
 
+    /// Throttle execution to this many Mgas per second, to leave resources to other services.
+    #[clap(long, conflicts_with = "execution_max_cpu_percent")]
+    pub execution_max_mgas_per_sec: Option<u64>,
+
+    /// Throttle execution to spend at most this share of time, in percent, executing blocks.
+    #[clap(long)]
+    pub execution_max_cpu_percent: Option<u8>,
+
+    /// Index value transfers made inside contract execution, for `martinez_getInternalTransfers`.
+    #[clap(long)]
+    pub index_internal_transfers: bool,
+
     /// Skip commitment (state root) verification.
     #[clap(long)]
     pub skip_commitment: bool,
@@ -96,6 +153,282 @@ pub struct Opt {
     /// Delay applied at the terminating stage.
     #[clap(long, default_value = "2000")]
     pub delay_after_sync: u64,
+
+    /// Collect per-table database operation metrics, served along with the others.
+    #[clap(long = "db.stats")]
+    pub db_stats: bool,
+
+    /// Log database operations slower than this many milliseconds. Implies `--db.stats`.
+    #[clap(long = "db.slow-op-threshold")]
+    pub db_slow_op_threshold: Option<u64>,
+
+    /// Start with the database frozen: keep it as is until released with
+    /// `martinez_unfreezeDatabase`, serving RPC meanwhile.
+    #[clap(long = "db.freeze")]
+    pub db_freeze: bool,
+
+    /// Beacon node REST API URL to take the initial finalized checkpoint from.
+    #[clap(long = "beacon.api.addr")]
+    pub beacon_api_addr: Option<String>,
+
+    /// Heimdall REST API URL to fetch Bor spans and state-sync events from, on Polygon PoS.
+    #[clap(long = "bor.heimdall-url", default_value = "http://localhost:1317")]
+    pub bor_heimdall_url: String,
+
+    /// Import blocks pushed to this address over WebSocket JSON-RPC (`feed_submitBlock`), e.g. by
+    /// an L2 sequencer or a relay, instead of downloading them from peers.
+    #[clap(
+        long = "feed.listen-address",
+        requires = "feed_signer",
+        conflicts_with = "erigon_data_dir"
+    )]
+    pub feed_listen_address: Option<SocketAddr>,
+
+    /// Address that must have signed the hash of every fed block.
+    #[clap(long = "feed.signer")]
+    pub feed_signer: Option<Address>,
+
+    /// Stream every executed canonical block with its receipts and state changes to indexers
+    /// connecting to this address, see `/schema` there for the format.
+    #[clap(long = "block-stream.listen-address")]
+    pub block_stream_listen_address: Option<SocketAddr>,
+
+    /// Blocks a stream consumer may fall behind before it is disconnected.
+    #[clap(long = "block-stream.capacity", default_value = "1024")]
+    pub block_stream_capacity: usize,
+
+    /// Mirror executed canonical blocks into this SQL database for ad-hoc querying, e.g.
+    /// `sqlite://mirror.db?mode=rwc` or `postgres://user@localhost/chain`.
+    #[clap(long = "sql-mirror.url")]
+    pub sql_mirror_url: Option<String>,
+
+    /// Table to mirror, one of `blocks`, `transactions`, `receipts` and `logs`. Can be repeated,
+    /// all of them by default.
+    #[clap(long = "sql-mirror.table")]
+    pub sql_mirror_tables: Vec<Table>,
+
+    /// Column to mirror, as `<table>.<column>`, e.g. `logs.address`. Can be repeated; tables
+    /// without any keep all of their columns. The block number column has to be kept.
+    #[clap(long = "sql-mirror.column")]
+    pub sql_mirror_columns: Vec<String>,
+
+    #[clap(flatten)]
+    pub observability: martinez::observability::ObservabilityOpts,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Parser)]
+pub enum Command {
+    /// Inspect the configuration without starting the node.
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    /// Run a single stage outside of the sync loop.
+    #[clap(subcommand)]
+    Stage(StageCommand),
+    /// Export blocks, transactions, receipts and logs over a block range to CSV or Parquet
+    /// files, one per table.
+    Export {
+        /// First block to export.
+        #[clap(long)]
+        from: BlockNumber,
+        /// Last block to export.
+        #[clap(long)]
+        to: BlockNumber,
+        /// Either `csv` or `parquet`.
+        #[clap(long, default_value = "csv")]
+        format: ExportFormat,
+        #[clap(long, parse(from_os_str))]
+        output_dir: PathBuf,
+        /// Table to export, one of `blocks`, `transactions`, `receipts` and `logs`. Can be
+        /// repeated, all of them by default.
+        #[clap(long = "table")]
+        tables: Vec<Table>,
+        /// Column to export, as `<table>.<column>`, e.g. `transactions.hash`. Can be repeated;
+        /// tables without any keep all of their columns.
+        #[clap(long = "column")]
+        columns: Vec<String>,
+    },
+}
+
+#[derive(Parser)]
+pub enum ConfigCommand {
+    /// Print the chain spec selected by `--chain` or `--chain-spec-file` as JSON: chain id,
+    /// genesis hash, consensus engine and fork blocks.
+    ShowChain,
+}
+
+#[derive(Parser)]
+pub enum StageCommand {
+    /// Run a stage that only processes data already in the database, e.g. `Execution`, up to a
+    /// block.
+    Run {
+        /// Name of the stage, e.g. `Execution` or `HashState`.
+        stage: String,
+        /// First block to process, defaults to the one after the recorded progress of the stage.
+        #[clap(long)]
+        from: Option<BlockNumber>,
+        /// Last block to process.
+        #[clap(long)]
+        to: BlockNumber,
+        /// Run the stage as usual but discard all of its writes, leaving the database untouched.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Unwind a stage down to a block, leaving the other stages as they are.
+    Unwind {
+        /// Name of the stage, e.g. `Execution` or `HashState`.
+        stage: String,
+        /// Block to unwind to.
+        #[clap(long)]
+        to: BlockNumber,
+        /// Unwind the stage as usual but discard all of its writes.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Overwrite the recorded progress of a stage without running it, so that the next run
+    /// starts after `progress`.
+    SetProgress {
+        /// Name of the stage, e.g. `Execution` or `HashState`.
+        stage: String,
+        progress: BlockNumber,
+    },
+}
+
+/// Stages that only process data already in the database, which can run on their own.
+fn offline_stages<'db, E>(
+    opt: &Opt,
+    etl_temp_dir: &Arc<tempfile::TempDir>,
+    execution_throttle: Option<ExecutionThrottle>,
+) -> Vec<Box<dyn Stage<'db, E>>>
+where
+    E: EnvironmentKind,
+{
+    vec![
+        Box::new(TotalGasIndex),
+        Box::new(BlockHashes {
+            temp_dir: etl_temp_dir.clone(),
+        }),
+        Box::new(TotalTxIndex),
+        Box::new(SenderRecovery {
+            batch_size: opt.sender_recovery_batch_size.try_into().unwrap(),
+        }),
+        Box::new(Execution {
+            batch_size: opt.execution_batch_size.saturating_mul(1_000_000_000_u64),
+            history_batch_size: opt
+                .execution_history_batch_size
+                .saturating_mul(1_000_000_000_u64),
+            exit_after_batch: false,
+            batch_until: None,
+            commit_every: None,
+            prune_from: BlockNumber(0),
+            index_internal_transfers: opt.index_internal_transfers,
+            throttle: execution_throttle,
+        }),
+        Box::new(HashState::new(etl_temp_dir.clone(), None)),
+        Box::new(Interhashes::new(etl_temp_dir.clone(), None)),
+        Box::new(CallTraceIndex {
+            temp_dir: etl_temp_dir.clone(),
+            flush_interval: 50_000,
+        }),
+        Box::new(ContractCreatorIndex),
+        Box::new(AddressAppearanceIndex {
+            temp_dir: etl_temp_dir.clone(),
+            flush_interval: 50_000,
+        }),
+    ]
+}
+
+async fn run_stage_command(
+    opt: &Opt,
+    command: &StageCommand,
+    execution_throttle: Option<ExecutionThrottle>,
+) -> anyhow::Result<()> {
+    let etl_temp_path = opt.data_dir.etl_temp_dir();
+    let _ = std::fs::remove_dir_all(&etl_temp_path);
+    std::fs::create_dir_all(&etl_temp_path)?;
+    let etl_temp_dir =
+        Arc::new(tempfile::tempdir_in(&etl_temp_path).context("failed to create ETL temp dir")?);
+    let db = martinez::kv::new_database_with_cold(
+        &opt.data_dir.chain_data_dir(),
+        opt.history_dir.as_deref(),
+    )?;
+
+    let stages = offline_stages::<mdbx::WriteMap>(opt, &etl_temp_dir, execution_throttle);
+    let find_stage = |name: &str| {
+        stages
+            .into_iter()
+            .find(|s| s.id().0.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format_err!("{} is not a stage that can run on its own", name))
+    };
+
+    match command {
+        StageCommand::Run {
+            stage,
+            from,
+            to,
+            dry_run,
+        } => {
+            let mut stage = find_stage(stage)?;
+            stagedsync::single_stage::run_stage(&db, &mut *stage, *from, *to, *dry_run).await?;
+        }
+        StageCommand::Unwind { stage, to, dry_run } => {
+            let mut stage = find_stage(stage)?;
+            stagedsync::single_stage::unwind_stage(&db, &mut *stage, *to, *dry_run).await?;
+        }
+        StageCommand::SetProgress { stage, progress } => {
+            let stage_id = find_stage(stage)?.id();
+            let tx = db.begin_mutable()?;
+            let previous = stage_id.get_progress(&tx)?;
+            stage_id.save_progress(&tx, *progress)?;
+            tx.commit()?;
+            info!(
+                "{} progress set to {}, was {}",
+                stage_id,
+                progress,
+                previous
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| "none".to_string())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_export_command(
+    opt: &Opt,
+    from: BlockNumber,
+    to: BlockNumber,
+    format: ExportFormat,
+    output_dir: &std::path::Path,
+    tables: &[Table],
+    columns: &[String],
+) -> anyhow::Result<()> {
+    let schema = ExportSchema::new(tables, columns)?;
+    // Read-only, so that a running node can keep syncing.
+    let db = MdbxEnvironment::<mdbx::NoWriteMap>::open_ro(
+        mdbx::Environment::new(),
+        &opt.data_dir.chain_data_dir(),
+        martinez::kv::tables::CHAINDATA_TABLES.clone(),
+    )?;
+
+    let paths = export::export(&db.begin()?, from, to, &schema, format, output_dir)?;
+    for path in paths {
+        info!("Exported {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn chain_config(opt: &Opt) -> anyhow::Result<martinez::sentry::chain_config::ChainConfig> {
+    if let Some(chain_spec_file) = &opt.chain_spec_file {
+        martinez::sentry::chain_config::ChainConfig::from_file(chain_spec_file)
+    } else {
+        martinez::sentry::chain_config::ChainsConfig::new()?.get(&opt.chain_name)
+    }
 }
 
 #[derive(Debug)]
@@ -290,17 +623,8 @@ where
 
         let mut tx_cur = tx.cursor(tables::BlockTransaction.erased())?;
 
-        let prev_body = tx
-            .get(
-                tables::BlockBody,
-                (
-                    highest_block,
-                    tx.get(tables::CanonicalHeader, highest_block)?.unwrap(),
-                ),
-            )?
-            .unwrap();
-
-        let mut starting_index = prev_body.base_tx_id + prev_body.tx_amount as u64;
+        let first_index = tx_sequence::read(tx)?;
+        let mut starting_index = first_index;
         let canonical_header_walker = canonical_header_cur.walk(Some(highest_block + 1));
         pin!(canonical_header_walker);
         let erigon_body_walker =
@@ -353,8 +677,14 @@ where
                             );
                         }
 
+                        let header = tx
+                            .get(tables::Header, (block_num, block_hash))?
+                            .ok_or_else(|| {
+                                format_err!("No header for block #{}/{}", block_num, block_hash)
+                            })?;
+
                         accum_txs += tx_amount;
-                        batch.push((block_num, block_hash, body, txs));
+                        batch.push((block_num, block_hash, header, body, txs));
 
                         break;
                     } else {
@@ -380,18 +710,25 @@ where
             converted.reserve(batch.len());
             batch
                 .par_drain(..)
-                .map(move |(block_number, block_hash, body, txs)| {
+                .map(move |(block_number, block_hash, header, body, txs)| {
+                    let txs = txs
+                        .into_iter()
+                        .map(|v| rlp::decode::<martinez::models::MessageWithSignature>(&v))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    pre_validate_body(&header, &txs, &body.uncles).with_context(|| {
+                        format!(
+                            "Body of block #{}/{} in Erigon does not match its header",
+                            block_number, block_hash
+                        )
+                    })?;
+
                     Ok::<_, anyhow::Error>((
                         block_number,
                         block_hash,
                         body.uncles,
                         txs.into_iter()
-                            .map(|v| {
-                                Ok(rlp::decode::<martinez::models::MessageWithSignature>(&v)?
-                                    .encode()
-                                    .to_vec())
-                            })
-                            .collect::<anyhow::Result<Vec<_>>>()?,
+                            .map(|tx| tx.encode().to_vec())
+                            .collect::<Vec<_>>(),
                     ))
                 })
                 .collect_into_vec(&mut converted);
@@ -443,6 +780,8 @@ where
             }
         };
 
+        tx_sequence::allocate(tx, starting_index.0 - first_index.0)?;
+
         Ok(ExecOutput::Progress {
             stage_progress: highest_block,
             done,
@@ -468,7 +807,8 @@ where
             let mut deleted = 0;
             while deleted < body.tx_amount {
                 let to_delete = body.base_tx_id + deleted;
-                if block_tx_cur.seek(to_delete)?.is_some() {
+                // Siblings may share transactions, the range can be gone already.
+                if block_tx_cur.seek_exact(to_delete)?.is_some() {
                     block_tx_cur.delete_current()?;
                 }
 
@@ -529,28 +869,40 @@ where
 fn main() -> anyhow::Result<()> {
     let opt: Opt = Opt::parse();
 
+    if let Some(Command::Config(ConfigCommand::ShowChain)) = &opt.command {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&chain_config(&opt)?.summary())?
+        );
+        return Ok(());
+    }
+
+    let execution_throttle = match (
+        opt.execution_max_mgas_per_sec,
+        opt.execution_max_cpu_percent,
+    ) {
+        (Some(0), _) => bail!("Execution throttle must be above 0 Mgas/s"),
+        (Some(mgas), _) => Some(ExecutionThrottle::GasPerSecond(
+            mgas.saturating_mul(1_000_000),
+        )),
+        (None, Some(percent)) if !(1..=100).contains(&percent) => {
+            bail!(
+                "Execution CPU share must be between 1 and 100%, got {}",
+                percent
+            )
+        }
+        (None, Some(percent)) => Some(ExecutionThrottle::CpuPercent(percent)),
+        (None, None) => None,
+    };
+
+    if opt.db_stats || opt.db_slow_op_threshold.is_some() {
+        martinez::kv::stats::enable(opt.db_slow_op_threshold.map(Duration::from_millis));
+    }
+
     let nocolor = std::env::var("RUST_LOG_STYLE")
         .map(|val| val == "never")
         .unwrap_or(false);
 
-    // tracing setup
-    let env_filter = if std::env::var(EnvFilter::DEFAULT_ENV)
-        .unwrap_or_default()
-        .is_empty()
-    {
-        EnvFilter::new("martinez=info")
-    } else {
-        EnvFilter::from_default_env()
-    };
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::layer()
-                .with_target(false)
-                .with_ansi(!nocolor),
-        )
-        .with(env_filter)
-        .init();
-
     std::thread::Builder::new()
         .stack_size(64 * 1024 * 1024)
         .spawn(move || {
@@ -559,26 +911,58 @@ fn main() -> anyhow::Result<()> {
                 .thread_stack_size(64 * 1024 * 1024)
                 .build()?;
 
+            // tracing setup, inside the runtime for the OTLP exporter
+            let _observability = {
+                let _guard = rt.enter();
+                martinez::observability::init("martinez=info", !nocolor, &opt.observability)?
+            };
+
             rt.block_on(async move {
                 info!("Starting Martinez ({})", version_string());
 
-                let chains_config = martinez::sentry::chain_config::ChainsConfig::new()?;
-                let chain_config = chains_config.get(&opt.chain_name)?;
+                if let Some(Command::Stage(command)) = &opt.command {
+                    return run_stage_command(&opt, command, execution_throttle).await;
+                }
+                if let Some(Command::Export {
+                    from,
+                    to,
+                    format,
+                    output_dir,
+                    tables,
+                    columns,
+                }) = &opt.command
+                {
+                    return run_export_command(
+                        &opt, *from, *to, *format, output_dir, tables, columns,
+                    );
+                }
+
+                let chain_config = chain_config(&opt)?;
+                let bor = matches!(
+                    chain_config.chain_spec().consensus.seal_verification,
+                    SealVerificationParams::Bor { .. }
+                );
 
                 // database setup
                 let erigon_db = if let Some(erigon_data_dir) = opt.erigon_data_dir {
                     let erigon_chain_data_dir = erigon_data_dir.join("chaindata");
-                    let erigon_db = martinez::kv::mdbx::MdbxEnvironment::<mdbx::NoWriteMap>::open_ro(
-                        mdbx::Environment::new(),
-                        &erigon_chain_data_dir,
-                        martinez::kv::tables::CHAINDATA_TABLES.clone(),
-                    )?;
+                    let erigon_db =
+                        martinez::kv::mdbx::MdbxEnvironment::<mdbx::NoWriteMap>::open_ro(
+                            mdbx::Environment::new(),
+                            &erigon_chain_data_dir,
+                            martinez::kv::tables::CHAINDATA_TABLES.clone(),
+                        )?;
                     Some(Arc::new(erigon_db))
                 } else {
                     None
                 };
 
                 std::fs::create_dir_all(&opt.data_dir.0)?;
+                let freeze = stagedsync::freeze::DbFreeze::new(&opt.data_dir.0);
+                freeze.clear_stale_ack()?;
+                if opt.db_freeze {
+                    freeze.request()?;
+                }
                 let martinez_chain_data_dir = opt.data_dir.chain_data_dir();
                 let etl_temp_path = opt.data_dir.etl_temp_dir();
                 let _ = std::fs::remove_dir_all(&etl_temp_path);
@@ -587,7 +971,10 @@ fn main() -> anyhow::Result<()> {
                     tempfile::tempdir_in(&etl_temp_path)
                         .context("failed to create ETL temp dir")?,
                 );
-                let db = martinez::kv::new_database(&martinez_chain_data_dir)?;
+                let db = martinez::kv::new_database_with_cold(
+                    &martinez_chain_data_dir,
+                    opt.history_dir.as_deref(),
+                )?;
                 {
                     let span = span!(Level::INFO, "", " Genesis initialization ");
                     let _g = span.enter();
@@ -600,35 +987,129 @@ fn main() -> anyhow::Result<()> {
                         txn.commit()?;
                     }
                 }
+                {
+                    let txn = db.begin_mutable()?;
+                    if recover_interrupted_execution(&txn)?.is_some() {
+                        txn.commit()?;
+                    }
+                }
+
+                if let Some(beacon_api_addr) = &opt.beacon_api_addr {
+                    let finalized =
+                        last_forkchoice::read(&db.begin()?, last_forkchoice::FINALIZED_BLOCK_HASH)?;
+                    if finalized.is_none() {
+                        let checkpoint = fetch_finalized_checkpoint(beacon_api_addr).await?;
+                        let txn = db.begin_mutable()?;
+                        if apply_checkpoint(&txn, checkpoint)? {
+                            txn.commit()?;
+                            info!(
+                                "Starting from finalized checkpoint {}/{:?}",
+                                checkpoint.block_number, checkpoint.block_hash
+                            );
+                        }
+                    }
+                }
 
                 let sentry_status_provider = SentryStatusProvider::new(chain_config.clone());
                 // staged sync setup
                 let mut staged_sync = stagedsync::StagedSync::new();
                 staged_sync.set_min_progress_to_commit_after_stage(1024);
                 staged_sync.set_max_block(opt.max_block);
+                staged_sync.set_max_unwind_depth(opt.max_reorg_depth);
                 staged_sync.set_exit_after_sync(opt.exit_after_sync);
                 staged_sync.set_delay_after_sync(Some(Duration::from_millis(opt.delay_after_sync)));
+                staged_sync.set_freeze(freeze);
+                if opt.header_repair_batch > 0 {
+                    staged_sync.set_header_repair(HeaderRepair::new(opt.header_repair_batch));
+                }
+                if opt.block_stream_listen_address.is_some() || opt.sql_mirror_url.is_some() {
+                    let block_stream = BlockStream::new(opt.block_stream_capacity);
+                    staged_sync.set_block_stream(block_stream.clone());
+                    if let Some(url) = &opt.sql_mirror_url {
+                        let schema =
+                            ExportSchema::new(&opt.sql_mirror_tables, &opt.sql_mirror_columns)?;
+                        let mirror = SqlMirror::connect(url, schema).await?;
+                        let receiver = block_stream.subscribe();
+                        tokio::spawn(async move {
+                            if let Err(e) = mirror.run(receiver).await {
+                                error!("SQL mirror failed: {:?}", e);
+                            }
+                        });
+                    }
+                    if let Some(listen_address) = opt.block_stream_listen_address {
+                        tokio::spawn(async move {
+                            if let Err(e) = block_stream::serve(listen_address, block_stream).await
+                            {
+                                error!("Block stream failed: {:?}", e);
+                            }
+                        });
+                    }
+                }
+                let mut _feed_server = None;
+                let mut body_sentry = None;
                 if let Some(erigon_db) = erigon_db.clone() {
                     staged_sync.push(ConvertHeaders {
                         db: erigon_db,
                         max_block: opt.max_block,
                         exit_after_progress: opt.increment,
                     });
+                } else if let Some(feed_listen_address) = opt.feed_listen_address {
+                    let (server, feed_headers) =
+                        start_block_feed(feed_listen_address, opt.feed_signer.unwrap()).await?;
+                    _feed_server = Some(server);
+                    staged_sync.push(feed_headers);
                 } else {
                     // sentry setup
-                    let mut sentry_reactor = SentryClientReactor::new(
+                    if opt.sentry_embedded {
+                        let sentry_api_addr = SocketAddr::from((
+                            Ipv4Addr::LOCALHOST,
+                            opt.sentry_api_addr.addr.port_u16().unwrap_or(8000),
+                        ));
+                        let sentry_opts = opt.sentry_opts;
+                        let p2p = chain_config.chain_spec().p2p.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) =
+                                martinez::sentry::server::run(sentry_opts, sentry_api_addr, &p2p)
+                                    .await
+                            {
+                                error!("Embedded sentry failed: {:?}", e);
+                            }
+                        });
+                    }
+                    let sentry_connector = opt.downloader_opts.sentry_connector(
                         Box::new(SentryClientConnectorImpl::new(opt.sentry_api_addr.clone())),
+                        &chain_config.chain_name(),
+                    )?;
+                    let mut sentry_reactor = SentryClientReactor::new(
+                        sentry_connector,
                         sentry_status_provider.current_status_stream(),
                     );
                     sentry_reactor.start()?;
-
-                    staged_sync.push(HeaderDownload::new(
+                    tokio::spawn(martinez::sentry::metrics::record_peer_events(
+                        sentry_reactor.peer_events(),
+                        sentry_reactor.connected_peers(),
+                    ));
+                    let sentry_reactor = sentry_reactor.into_shared();
+                    body_sentry = Some(sentry_reactor.clone());
+
+                    let header_download = HeaderDownload::new(
                         chain_config,
                         opt.downloader_opts.headers_mem_limit(),
                         opt.downloader_opts.headers_batch_size,
-                        sentry_reactor.into_shared(),
+                        sentry_reactor,
                         sentry_status_provider,
-                    )?);
+                        opt.downloader_opts.ui_mode(),
+                    )?;
+                    tokio::spawn({
+                        let published = PublishedTopBlockEstimate::new(&opt.data_dir.0);
+                        let estimates = header_download.watch_top_block_estimate();
+                        async move {
+                            if let Err(e) = published.publish(estimates).await {
+                                warn!("Stopped publishing the top block estimate: {}", e);
+                            }
+                        }
+                    });
+                    staged_sync.push(header_download);
                 }
                 staged_sync.push(TotalGasIndex);
                 staged_sync.push(BlockHashes {
@@ -639,13 +1120,24 @@ fn main() -> anyhow::Result<()> {
                         db: erigon_db,
                         commit_after: Duration::from_secs(120),
                     });
-                } else {
-                    // also add body download stage here
+                } else if opt.feed_listen_address.is_some() {
+                    staged_sync.push(FeedBodies);
+                } else if let Some(sentry) = body_sentry {
+                    staged_sync.push(BodyDownload::new(
+                        sentry,
+                        opt.downloader_opts.bodies_batch_size,
+                        opt.downloader_opts.bodies_era1_dir.clone(),
+                    ));
                 }
                 staged_sync.push(TotalTxIndex);
                 staged_sync.push(SenderRecovery {
                     batch_size: opt.sender_recovery_batch_size.try_into().unwrap(),
                 });
+                if bor {
+                    staged_sync.push(BorHeimdall {
+                        client: HeimdallClient::new(&opt.bor_heimdall_url),
+                    });
+                }
                 staged_sync.push(Execution {
                     batch_size: opt.execution_batch_size.saturating_mul(1_000_000_000_u64),
                     history_batch_size: opt
@@ -655,6 +1147,8 @@ fn main() -> anyhow::Result<()> {
                     batch_until: None,
                     commit_every: None,
                     prune_from: BlockNumber(0),
+                    index_internal_transfers: opt.index_internal_transfers,
+                    throttle: execution_throttle,
                 });
                 if !opt.skip_commitment {
                     staged_sync.push(HashState::new(etl_temp_dir.clone(), None));
@@ -664,6 +1158,11 @@ fn main() -> anyhow::Result<()> {
                     temp_dir: etl_temp_dir.clone(),
                     flush_interval: 50_000,
                 });
+                staged_sync.push(ContractCreatorIndex);
+                staged_sync.push(AddressAppearanceIndex {
+                    temp_dir: etl_temp_dir.clone(),
+                    flush_interval: 50_000,
+                });
                 staged_sync.push(FinishStage);
 
                 info!("Running staged sync");
@@ -2,9 +2,146 @@ use crate::{
     kv::{mdbx::MdbxTransaction, tables},
     models::*,
 };
+use anyhow::format_err;
 use mdbx::{EnvironmentKind, TransactionKind, RW};
 use tracing::*;
 
+pub mod canonical_hash {
+    use super::*;
+
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        block_number: impl Into<BlockNumber>,
+    ) -> anyhow::Result<Option<H256>> {
+        let block_number = block_number.into();
+        trace!("Reading canonical hash for block number {}", block_number);
+
+        tx.get(tables::CanonicalHeader, block_number)
+    }
+
+    /// Highest canonical block, regardless of how far the stages have processed it.
+    pub fn last<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+    ) -> anyhow::Result<Option<(BlockNumber, H256)>> {
+        tx.cursor(tables::CanonicalHeader)?.last()
+    }
+}
+
+pub mod header_number {
+    use super::*;
+
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        hash: H256,
+    ) -> anyhow::Result<Option<BlockNumber>> {
+        trace!("Reading header number for hash {:?}", hash);
+
+        tx.get(tables::HeaderNumber, hash)
+    }
+}
+
+pub mod header {
+    use super::*;
+
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        key: BlockKey,
+    ) -> anyhow::Result<Option<BlockHeader>> {
+        trace!("Reading header for block {}", key);
+
+        tx.get(tables::Header, (key.number, key.hash))
+    }
+}
+
+/// Lookup of a block by either its number or its hash.
+pub mod block_id {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum BlockId {
+        Number(BlockNumber),
+        Hash(H256),
+    }
+
+    impl From<BlockNumber> for BlockId {
+        fn from(number: BlockNumber) -> Self {
+            Self::Number(number)
+        }
+    }
+
+    impl From<H256> for BlockId {
+        fn from(hash: H256) -> Self {
+            Self::Hash(hash)
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ResolvedBlock {
+        pub number: BlockNumber,
+        pub hash: H256,
+        pub canonical: bool,
+    }
+
+    impl ResolvedBlock {
+        pub fn key(&self) -> BlockKey {
+            BlockKey::new(self.number, self.hash)
+        }
+    }
+
+    /// Numbers resolve through the canonical chain only. Hashes resolve to any stored header.
+    pub fn resolve<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        id: impl Into<BlockId>,
+    ) -> anyhow::Result<Option<ResolvedBlock>> {
+        let id = id.into();
+        trace!("Resolving block {:?}", id);
+
+        Ok(match id {
+            BlockId::Number(number) => {
+                super::canonical_hash::read(tx, number)?.map(|hash| ResolvedBlock {
+                    number,
+                    hash,
+                    canonical: true,
+                })
+            }
+            BlockId::Hash(hash) => match super::header_number::read(tx, hash)? {
+                Some(number) => Some(ResolvedBlock {
+                    number,
+                    hash,
+                    canonical: super::canonical_hash::read(tx, number)? == Some(hash),
+                }),
+                None => None,
+            },
+        })
+    }
+}
+
+pub mod top_block_estimate {
+    use super::*;
+    use crate::kv::traits::{TableDecode, TableEncode};
+
+    const KEY: &[u8] = b"TopBlockEstimate";
+
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+    ) -> anyhow::Result<Option<BlockNumber>> {
+        trace!("Reading top block estimate");
+
+        tx.get(tables::DbInfo, KEY.to_vec())?
+            .map(|v| BlockNumber::decode(&v))
+            .transpose()
+    }
+
+    pub fn write<E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, RW, E>,
+        block_number: BlockNumber,
+    ) -> anyhow::Result<()> {
+        trace!("Writing top block estimate {}", block_number);
+
+        tx.set(tables::DbInfo, KEY.to_vec(), block_number.encode().to_vec())
+    }
+}
+
 pub mod tx {
     use super::*;
 
@@ -56,41 +193,75 @@ pub mod tx {
 pub mod tx_sender {
     use super::*;
 
+    /// Senders of the transactions of block `key`, empty until the senders stage has
+    /// recovered them.
     pub fn read<K: TransactionKind, E: EnvironmentKind>(
         tx: &MdbxTransaction<'_, K, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
+        key: BlockKey,
     ) -> anyhow::Result<Vec<Address>> {
-        let number = number.into();
-
-        trace!(
-            "Reading transaction senders for block {}/{:?}",
-            number,
-            hash
-        );
+        trace!("Reading transaction senders for block {}", key);
 
         Ok(tx
-            .get(tables::TxSender, (number, hash))?
+            .get(tables::TxSender, (key.number, key.hash))?
             .unwrap_or_default())
     }
 
     pub fn write<E: EnvironmentKind>(
         tx: &MdbxTransaction<'_, RW, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
+        key: BlockKey,
         senders: Vec<Address>,
     ) -> anyhow::Result<()> {
-        let number = number.into();
         trace!(
-            "Writing {} transaction senders for block {}/{:?}",
+            "Writing {} transaction senders for block {}",
             senders.len(),
-            number,
-            hash
+            key
         );
 
-        tx.set(tables::TxSender, (number, hash), senders).unwrap();
+        tx.set(tables::TxSender, (key.number, key.hash), senders)
+    }
+}
 
-        Ok(())
+/// Allocator of ids in the transaction table.
+pub mod tx_sequence {
+    use super::*;
+
+    fn key() -> Vec<u8> {
+        tables::BlockTransaction::const_db_name()
+            .as_bytes()
+            .to_vec()
+    }
+
+    /// Id the next allocated transaction will get.
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+    ) -> anyhow::Result<TxIndex> {
+        if let Some(value) = tx.get(tables::Sequence, key())? {
+            return Ok(TxIndex(u64::from_be_bytes(value.as_slice().try_into()?)));
+        }
+
+        // Databases written before the sequence existed: continue after the last transaction.
+        Ok(tx
+            .cursor(tables::BlockTransaction)?
+            .last()?
+            .map(|(id, _)| id + 1)
+            .unwrap_or(TxIndex(0)))
+    }
+
+    /// Reserves `amount` consecutive ids and returns the first one.
+    pub fn allocate<E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, RW, E>,
+        amount: u64,
+    ) -> anyhow::Result<TxIndex> {
+        let base_tx_id = read(tx)?;
+        trace!("Allocating {} transaction ids from {}", amount, base_tx_id);
+
+        tx.set(
+            tables::Sequence,
+            key(),
+            (base_tx_id + amount).0.to_be_bytes().to_vec(),
+        )?;
+
+        Ok(base_tx_id)
     }
 }
 
@@ -99,44 +270,36 @@ pub mod storage_body {
 
     pub fn read<K, E>(
         tx: &MdbxTransaction<'_, K, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
+        key: BlockKey,
     ) -> anyhow::Result<Option<BodyForStorage>>
     where
         K: TransactionKind,
         E: EnvironmentKind,
     {
-        let number = number.into();
-        trace!("Reading storage body for block {}/{:?}", number, hash);
+        trace!("Reading storage body for block {}", key);
 
-        tx.get(tables::BlockBody, (number, hash))
+        tx.get(tables::BlockBody, (key.number, key.hash))
     }
 
-    pub fn has<K, E>(
-        tx: &MdbxTransaction<'_, K, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
-    ) -> anyhow::Result<bool>
+    pub fn has<K, E>(tx: &MdbxTransaction<'_, K, E>, key: BlockKey) -> anyhow::Result<bool>
     where
         K: TransactionKind,
         E: EnvironmentKind,
     {
-        Ok(read(tx, hash, number)?.is_some())
+        Ok(read(tx, key)?.is_some())
     }
 
     pub fn write<E>(
         tx: &MdbxTransaction<'_, RW, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
+        key: BlockKey,
         body: &BodyForStorage,
     ) -> anyhow::Result<()>
     where
         E: EnvironmentKind,
     {
-        let number = number.into();
-        trace!("Writing storage body for block {}/{:?}", number, hash);
+        trace!("Writing storage body for block {}", key);
 
-        tx.set(tables::BlockBody, (number, hash), body.clone())?;
+        tx.set(tables::BlockBody, (key.number, key.hash), body.clone())?;
 
         Ok(())
     }
@@ -147,14 +310,13 @@ pub mod block_body {
 
     fn read_base<K, E>(
         tx: &MdbxTransaction<'_, K, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
+        key: BlockKey,
     ) -> anyhow::Result<Option<(BlockBody, TxIndex)>>
     where
         K: TransactionKind,
         E: EnvironmentKind,
     {
-        if let Some(body) = super::storage_body::read(tx, hash, number)? {
+        if let Some(body) = super::storage_body::read(tx, key)? {
             let transactions = super::tx::read(tx, body.base_tx_id, body.tx_amount.try_into()?)?;
 
             return Ok(Some((
@@ -171,22 +333,84 @@ pub mod block_body {
 
     pub fn read_without_senders<K: TransactionKind, E: EnvironmentKind>(
         tx: &MdbxTransaction<'_, K, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
+        key: BlockKey,
     ) -> anyhow::Result<Option<BlockBody>> {
-        Ok(read_base(tx, hash, number)?.map(|(v, _)| v))
+        Ok(read_base(tx, key)?.map(|(v, _)| v))
+    }
+
+    /// Stores the body of a block, canonical or not, and returns its storage form.
+    ///
+    /// Transactions get fresh ids from the sequence, unless another block at the same height
+    /// has exactly the same transactions: then its ids are shared, so re-mined reorged blocks
+    /// do not store their transactions twice.
+    pub fn write<E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, RW, E>,
+        key: BlockKey,
+        body: &BlockBody,
+    ) -> anyhow::Result<BodyForStorage> {
+        let BlockKey { number, hash } = key;
+        let tx_amount = body.transactions.len() as u64;
+
+        let mut base_tx_id = None;
+        if tx_amount > 0 {
+            let mut cursor = tx.cursor(tables::BlockBody)?;
+            let mut entry = cursor.seek(number)?;
+            while let Some(((sibling_number, sibling_hash), sibling)) = entry {
+                if sibling_number != number {
+                    break;
+                }
+
+                if sibling_hash != hash
+                    && sibling.tx_amount == tx_amount
+                    && super::tx::read(tx, sibling.base_tx_id, tx_amount as usize)?
+                        == body.transactions
+                {
+                    trace!(
+                        "Block {}/{:?} shares transactions with {:?}",
+                        number,
+                        hash,
+                        sibling_hash
+                    );
+                    base_tx_id = Some(sibling.base_tx_id);
+                    break;
+                }
+
+                entry = cursor.next()?;
+            }
+        }
+
+        let base_tx_id = match base_tx_id {
+            Some(base_tx_id) => base_tx_id,
+            None => {
+                let base_tx_id = super::tx_sequence::allocate(tx, tx_amount)?;
+                super::tx::write(tx, base_tx_id, &body.transactions)?;
+                base_tx_id
+            }
+        };
+
+        let storage_body = BodyForStorage {
+            base_tx_id,
+            tx_amount,
+            uncles: body.ommers.clone(),
+        };
+        super::storage_body::write(tx, key, &storage_body)?;
+
+        Ok(storage_body)
     }
 
     pub fn read_with_senders<K: TransactionKind, E: EnvironmentKind>(
         tx: &MdbxTransaction<'_, K, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
+        key: BlockKey,
     ) -> anyhow::Result<Option<BlockBodyWithSenders>> {
-        let number = number.into();
-        if let Some((body, _)) = read_base(tx, hash, number)? {
-            let senders = super::tx_sender::read(tx, hash, number)?;
+        if let Some((body, _)) = read_base(tx, key)? {
+            let senders = super::tx_sender::read(tx, key)?;
 
             return Ok(Some(BlockBodyWithSenders {
+                rollup_cost_data: body
+                    .transactions
+                    .iter()
+                    .map(MessageWithSignature::rollup_cost_data)
+                    .collect(),
                 transactions: body
                     .transactions
                     .into_iter()
@@ -209,13 +433,140 @@ pub mod td {
 
     pub fn read<K: TransactionKind, E: EnvironmentKind>(
         tx: &MdbxTransaction<'_, K, E>,
-        hash: H256,
-        number: impl Into<BlockNumber>,
+        key: BlockKey,
     ) -> anyhow::Result<Option<U256>> {
-        let number = number.into();
-        trace!("Reading total difficulty at block {}/{:?}", number, hash);
+        trace!("Reading total difficulty at block {}", key);
+
+        tx.get(tables::HeadersTotalDifficulty, (key.number, key.hash))
+    }
+
+    pub fn write<E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, RW, E>,
+        key: BlockKey,
+        total_difficulty: U256,
+    ) -> anyhow::Result<()> {
+        trace!(
+            "Writing total difficulty {} at block {}",
+            total_difficulty,
+            key
+        );
+
+        tx.set(
+            tables::HeadersTotalDifficulty,
+            (key.number, key.hash),
+            total_difficulty,
+        )
+    }
+}
+
+/// Bor spans, keyed by their first block.
+pub mod bor_span {
+    use super::*;
+
+    /// Span containing `block_number`, if it has been fetched from Heimdall.
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        block_number: BlockNumber,
+    ) -> anyhow::Result<Option<BorSpan>> {
+        let mut cursor = tx.cursor(tables::BorSpan)?;
+        let span = match cursor.seek(block_number)? {
+            Some((start_block, span)) if start_block == block_number => Some(span),
+            Some(_) => cursor.prev()?.map(|(_, span)| span),
+            None => cursor.last()?.map(|(_, span)| span),
+        };
+
+        Ok(span.filter(|span| span.contains(block_number)))
+    }
+
+    /// Span fetched last.
+    pub fn last<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+    ) -> anyhow::Result<Option<BorSpan>> {
+        Ok(tx.cursor(tables::BorSpan)?.last()?.map(|(_, span)| span))
+    }
+}
+
+pub mod last_forkchoice {
+    use super::*;
+
+    pub const HEAD_BLOCK_HASH: &[u8] = b"headBlockHash";
+    pub const SAFE_BLOCK_HASH: &[u8] = b"safeBlockHash";
+    pub const FINALIZED_BLOCK_HASH: &[u8] = b"finalizedBlockHash";
+    pub const FINALIZED_STATE_ROOT: &[u8] = b"finalizedStateRoot";
+
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        key: &[u8],
+    ) -> anyhow::Result<Option<H256>> {
+        trace!("Reading forkchoice marker {}", String::from_utf8_lossy(key));
 
-        tx.get(tables::HeadersTotalDifficulty, (number, hash))
+        tx.get(tables::LastForkchoice, key.to_vec())
+    }
+
+    pub fn write<E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, RW, E>,
+        key: &[u8],
+        value: H256,
+    ) -> anyhow::Result<()> {
+        trace!(
+            "Writing forkchoice marker {}: {:?}",
+            String::from_utf8_lossy(key),
+            value
+        );
+
+        tx.set(tables::LastForkchoice, key.to_vec(), value)
+    }
+
+    /// Number of the block marked by `key`, `None` if there is no marker or the block is not on
+    /// the local canonical chain.
+    pub fn read_canonical_number<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        key: &[u8],
+    ) -> anyhow::Result<Option<BlockNumber>> {
+        if let Some(hash) = read(tx, key)? {
+            if let Some(number) = super::header_number::read(tx, hash)? {
+                if super::canonical_hash::read(tx, number)? == Some(hash) {
+                    return Ok(Some(number));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Record the forkchoice state of an `engine_forkchoiceUpdated` call. Zero safe and
+    /// finalized hashes, sent until there are such blocks, leave their markers alone.
+    pub fn update<E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, RW, E>,
+        head: H256,
+        safe: H256,
+        finalized: H256,
+    ) -> anyhow::Result<()> {
+        if !finalized.is_zero() {
+            if let (Some(previous), Some(number)) = (
+                read_canonical_number(tx, FINALIZED_BLOCK_HASH)?,
+                super::header_number::read(tx, finalized)?,
+            ) {
+                if number < previous {
+                    return Err(format_err!(
+                        "Finalized block cannot move back from {} to {}/{:?}",
+                        previous,
+                        number,
+                        finalized
+                    ));
+                }
+            }
+        }
+
+        write(tx, HEAD_BLOCK_HASH, head)?;
+        if !safe.is_zero() {
+            write(tx, SAFE_BLOCK_HASH, safe)?;
+        }
+        if !finalized.is_zero() {
+            write(tx, FINALIZED_BLOCK_HASH, finalized)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -250,10 +601,129 @@ pub mod tl {
     }
 }
 
+/// Chain spec the database was initialized with, stored under the genesis hash.
+pub mod chain_config {
+    use super::*;
+
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+    ) -> anyhow::Result<Option<ChainSpec>> {
+        trace!("Reading chain config");
+
+        match super::canonical_hash::read(tx, BlockNumber(0))? {
+            Some(genesis_hash) => tx.get(tables::Config, genesis_hash),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Hash of the highest header downloaded, the head the canonical chain is built towards.
+pub mod last_header {
+    use super::*;
+
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+    ) -> anyhow::Result<Option<H256>> {
+        trace!("Reading last header");
+
+        tx.get(tables::LastHeader, Default::default())
+    }
+
+    pub fn write<E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, RW, E>,
+        hash: H256,
+    ) -> anyhow::Result<()> {
+        trace!("Writing last header {:?}", hash);
+
+        tx.set(tables::LastHeader, Default::default(), hash)
+    }
+}
+
+/// Receipts are not stored, so they are regenerated by replaying blocks on top of the state of
+/// their parent.
+pub mod receipts {
+    use super::*;
+    use crate::{
+        consensus::{engine_factory, Consensus},
+        execution::{analysis_cache::AnalysisCache, processor::ExecutionProcessor},
+        stagedsync::stages::EXECUTION,
+        Buffer,
+    };
+
+    /// Replays blocks of one database, reusing the consensus engine and code analysis between
+    /// them.
+    pub struct Replayer {
+        chain_spec: ChainSpec,
+        engine: Box<dyn Consensus>,
+        analysis_cache: AnalysisCache,
+    }
+
+    impl Replayer {
+        pub fn new<K: TransactionKind, E: EnvironmentKind>(
+            tx: &MdbxTransaction<'_, K, E>,
+        ) -> anyhow::Result<Self> {
+            let chain_spec =
+                super::chain_config::read(tx)?.ok_or_else(|| format_err!("No chain config"))?;
+            Ok(Self {
+                engine: engine_factory(chain_spec.clone())?,
+                chain_spec,
+                analysis_cache: AnalysisCache::default(),
+            })
+        }
+
+        /// Receipts of block `key`, `None` unless it is canonical and executed.
+        pub fn read<K: TransactionKind, E: EnvironmentKind>(
+            &mut self,
+            tx: &MdbxTransaction<'_, K, E>,
+            key: BlockKey,
+        ) -> anyhow::Result<Option<Vec<Receipt>>> {
+            trace!("Replaying block {} for its receipts", key);
+
+            if super::canonical_hash::read(tx, key.number)? != Some(key.hash)
+                || EXECUTION.get_progress(tx)?.unwrap_or(BlockNumber(0)) < key.number
+            {
+                return Ok(None);
+            }
+            if key.number.0 == 0 {
+                return Ok(Some(vec![]));
+            }
+
+            let (header, body) = match (
+                super::header::read(tx, key)?,
+                super::block_body::read_with_senders(tx, key)?,
+            ) {
+                (Some(header), Some(body)) => (header, body),
+                _ => return Ok(None),
+            };
+
+            let mut buffer = Buffer::new(tx, BlockNumber(0), Some(BlockNumber(key.number.0 - 1)));
+            ExecutionProcessor::new(
+                &mut buffer,
+                None,
+                &mut self.analysis_cache,
+                &mut *self.engine,
+                &PartialHeader::from(header),
+                &body,
+                &self.chain_spec.collect_block_spec(key.number),
+            )
+            .execute_block_no_post_validation()
+            .map(Some)
+        }
+    }
+
+    /// Receipts of block `key`, `None` unless it is canonical and executed.
+    pub fn read<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        key: BlockKey,
+    ) -> anyhow::Result<Option<Vec<Receipt>>> {
+        Replayer::new(tx)?.read(tx, key)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::kv::new_mem_database;
+    use crate::{crypto::TrieEncode, kv::new_mem_database};
     use bytes::Bytes;
 
     #[test]
@@ -290,7 +760,7 @@ mod tests {
         let sender2 = Address::random();
         let senders = [sender1, sender2];
 
-        let block1_hash = H256::random();
+        let block1 = BlockKey::new(1, H256::random());
         let body = BodyForStorage {
             base_tx_id: 1.into(),
             tx_amount: 2,
@@ -301,13 +771,13 @@ mod tests {
         let rwtx = db.begin_mutable().unwrap();
         let rwtx = &rwtx;
 
-        storage_body::write(rwtx, block1_hash, 1, &body).unwrap();
-        rwtx.set(tables::CanonicalHeader, 1.into(), block1_hash)
+        storage_body::write(rwtx, block1, &body).unwrap();
+        rwtx.set(tables::CanonicalHeader, 1.into(), block1.hash)
             .unwrap();
         tx::write(rwtx, 1, &txs).unwrap();
-        tx_sender::write(rwtx, block1_hash, 1, senders.to_vec()).unwrap();
+        tx_sender::write(rwtx, block1, senders.to_vec()).unwrap();
 
-        let recovered_body = storage_body::read(rwtx, block1_hash, 1)
+        let recovered_body = storage_body::read(rwtx, block1)
             .unwrap()
             .expect("Could not recover storage body.");
         let recovered_hash = rwtx
@@ -315,11 +785,252 @@ mod tests {
             .unwrap()
             .expect("Could not recover block hash");
         let recovered_txs = tx::read(rwtx, 1, 2).unwrap();
-        let recovered_senders = tx_sender::read(rwtx, block1_hash, 1).unwrap();
+        let recovered_senders = tx_sender::read(rwtx, block1).unwrap();
 
         assert_eq!(body, recovered_body);
-        assert_eq!(block1_hash, recovered_hash);
+        assert_eq!(block1.hash, recovered_hash);
         assert_eq!(txs, *recovered_txs);
         assert_eq!(senders, *recovered_senders);
     }
+
+    fn transaction(nonce: u64) -> MessageWithSignature {
+        MessageWithSignature {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce,
+                gas_price: 20_000.as_u256(),
+                gas_limit: 21_000,
+                action: TransactionAction::Call(Address::repeat_byte(1)),
+                value: 1.as_u256(),
+                input: Bytes::new(),
+            },
+            signature: MessageSignature::new(false, H256::repeat_byte(2), H256::repeat_byte(3))
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn body_tx_ids() {
+        let db = new_mem_database().unwrap();
+        let rwtx = db.begin_mutable().unwrap();
+        let rwtx = &rwtx;
+
+        // Pre-existing transactions written without the sequence.
+        tx::write(rwtx, 0, &[transaction(0), transaction(1)]).unwrap();
+        assert_eq!(tx_sequence::read(rwtx).unwrap(), TxIndex(2));
+
+        let body = BlockBody {
+            transactions: vec![transaction(2), transaction(3)],
+            ommers: vec![],
+        };
+        let canonical =
+            block_body::write(rwtx, BlockKey::new(1, H256::repeat_byte(0xaa)), &body).unwrap();
+        assert_eq!(canonical.base_tx_id, TxIndex(2));
+        assert_eq!(tx_sequence::read(rwtx).unwrap(), TxIndex(4));
+
+        // A sibling with the same transactions shares them.
+        let sibling =
+            block_body::write(rwtx, BlockKey::new(1, H256::repeat_byte(0xbb)), &body).unwrap();
+        assert_eq!(sibling.base_tx_id, canonical.base_tx_id);
+        assert_eq!(tx_sequence::read(rwtx).unwrap(), TxIndex(4));
+
+        // A sibling with different transactions gets its own.
+        let other = BlockBody {
+            transactions: vec![transaction(2)],
+            ommers: vec![],
+        };
+        let fork =
+            block_body::write(rwtx, BlockKey::new(1, H256::repeat_byte(0xcc)), &other).unwrap();
+        assert_eq!(fork.base_tx_id, TxIndex(4));
+        assert_eq!(tx_sequence::read(rwtx).unwrap(), TxIndex(5));
+
+        assert_eq!(
+            block_body::read_without_senders(rwtx, BlockKey::new(1, H256::repeat_byte(0xbb)))
+                .unwrap()
+                .unwrap(),
+            body
+        );
+        assert_eq!(
+            block_body::read_without_senders(rwtx, BlockKey::new(1, H256::repeat_byte(0xcc)))
+                .unwrap()
+                .unwrap(),
+            other
+        );
+    }
+
+    #[test]
+    fn typed_transactions() {
+        let db = new_mem_database().unwrap();
+        let rwtx = db.begin_mutable().unwrap();
+        let rwtx = &rwtx;
+
+        let signature =
+            MessageSignature::new(true, H256::repeat_byte(2), H256::repeat_byte(3)).unwrap();
+        let access_list = vec![AccessListItem {
+            address: Address::repeat_byte(4),
+            slots: vec![H256::repeat_byte(5)],
+        }];
+        let body = BlockBody {
+            transactions: vec![
+                transaction(0),
+                MessageWithSignature {
+                    message: Message::EIP2930 {
+                        chain_id: ChainId(1),
+                        nonce: 1,
+                        gas_price: 20_000.as_u256(),
+                        gas_limit: 30_000,
+                        action: TransactionAction::Call(Address::repeat_byte(1)),
+                        value: 1.as_u256(),
+                        input: Bytes::from_static(&[0xde, 0xad]),
+                        access_list: access_list.clone(),
+                    },
+                    signature: signature.clone(),
+                },
+                MessageWithSignature {
+                    message: Message::EIP1559 {
+                        chain_id: ChainId(1),
+                        nonce: 2,
+                        max_priority_fee_per_gas: 1_000.as_u256(),
+                        max_fee_per_gas: 30_000.as_u256(),
+                        gas_limit: 100_000,
+                        action: TransactionAction::Create,
+                        value: 0.as_u256(),
+                        input: Bytes::from_static(&[0x60, 0x00]),
+                        access_list,
+                    },
+                    signature,
+                },
+            ],
+            ommers: vec![],
+        };
+        let key = BlockKey::new(1, H256::repeat_byte(0xaa));
+        block_body::write(rwtx, key, &body).unwrap();
+
+        let read = block_body::read_without_senders(rwtx, key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(read, body);
+        for (tx, tx_type) in
+            read.transactions
+                .iter()
+                .zip([TxType::Legacy, TxType::EIP2930, TxType::EIP1559])
+        {
+            assert_eq!(tx.tx_type(), tx_type);
+            // Raw encodings served over RPC carry the EIP-2718 type byte.
+            let raw = tx.trie_encode();
+            if tx_type != TxType::Legacy {
+                assert_eq!(raw[0], tx_type as u8);
+            }
+            assert_eq!(&MessageWithSignature::trie_decode(&raw).unwrap(), tx);
+        }
+    }
+
+    #[test]
+    fn resolve_block_id() {
+        let db = new_mem_database().unwrap();
+        let rwtx = db.begin_mutable().unwrap();
+        let rwtx = &rwtx;
+
+        let canonical = H256::repeat_byte(0xaa);
+        let fork = H256::repeat_byte(0xbb);
+        rwtx.set(tables::CanonicalHeader, 1.into(), canonical)
+            .unwrap();
+        rwtx.set(tables::HeaderNumber, canonical, 1.into()).unwrap();
+        rwtx.set(tables::HeaderNumber, fork, 1.into()).unwrap();
+
+        assert_eq!(
+            canonical_hash::last(rwtx).unwrap(),
+            Some((1.into(), canonical))
+        );
+
+        let expected = block_id::ResolvedBlock {
+            number: 1.into(),
+            hash: canonical,
+            canonical: true,
+        };
+        assert_eq!(
+            block_id::resolve(rwtx, BlockNumber(1)).unwrap(),
+            Some(expected)
+        );
+        assert_eq!(block_id::resolve(rwtx, canonical).unwrap(), Some(expected));
+        assert_eq!(
+            block_id::resolve(rwtx, fork).unwrap(),
+            Some(block_id::ResolvedBlock {
+                number: 1.into(),
+                hash: fork,
+                canonical: false,
+            })
+        );
+        assert_eq!(block_id::resolve(rwtx, BlockNumber(2)).unwrap(), None);
+        assert_eq!(
+            block_id::resolve(rwtx, H256::repeat_byte(0xcc)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn forkchoice_markers() {
+        use last_forkchoice::*;
+
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let hash = |n: u8| H256::repeat_byte(n);
+        for n in 1..=3 {
+            tx.set(tables::CanonicalHeader, BlockNumber(n.into()), hash(n))
+                .unwrap();
+            tx.set(tables::HeaderNumber, hash(n), BlockNumber(n.into()))
+                .unwrap();
+        }
+        // Known, but not canonical.
+        tx.set(tables::HeaderNumber, hash(0xaa), BlockNumber(2))
+            .unwrap();
+
+        assert_eq!(
+            read_canonical_number(&tx, FINALIZED_BLOCK_HASH).unwrap(),
+            None
+        );
+
+        update(&tx, hash(3), H256::zero(), H256::zero()).unwrap();
+        assert_eq!(read(&tx, HEAD_BLOCK_HASH).unwrap(), Some(hash(3)));
+        assert_eq!(read(&tx, SAFE_BLOCK_HASH).unwrap(), None);
+        assert_eq!(read(&tx, FINALIZED_BLOCK_HASH).unwrap(), None);
+
+        update(&tx, hash(3), hash(2), hash(2)).unwrap();
+        assert_eq!(
+            read_canonical_number(&tx, SAFE_BLOCK_HASH).unwrap(),
+            Some(BlockNumber(2))
+        );
+        assert_eq!(
+            read_canonical_number(&tx, FINALIZED_BLOCK_HASH).unwrap(),
+            Some(BlockNumber(2))
+        );
+
+        update(&tx, hash(3), hash(0xaa), hash(2)).unwrap();
+        assert_eq!(read_canonical_number(&tx, SAFE_BLOCK_HASH).unwrap(), None);
+
+        update(&tx, hash(3), hash(2), hash(1)).unwrap_err();
+        assert_eq!(read(&tx, FINALIZED_BLOCK_HASH).unwrap(), Some(hash(2)));
+    }
+
+    #[test]
+    fn genesis_accessors() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let chain_spec = crate::res::chainspec::RINKEBY.clone();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        crate::state::genesis::initialize_genesis(&tx, &temp_dir, chain_spec.clone()).unwrap();
+
+        let genesis = BlockKey::new(0, canonical_hash::read(&tx, 0).unwrap().unwrap());
+        assert_eq!(chain_config::read(&tx).unwrap(), Some(chain_spec));
+        assert_eq!(last_header::read(&tx).unwrap(), Some(genesis.hash));
+        assert_eq!(receipts::read(&tx, genesis).unwrap(), Some(vec![]));
+
+        // Not canonical, nor executed.
+        let sibling = BlockKey::new(0, H256::repeat_byte(0xaa));
+        assert_eq!(receipts::read(&tx, sibling).unwrap(), None);
+        let child = BlockKey::new(1, H256::repeat_byte(0xaa));
+        assert_eq!(receipts::read(&tx, child).unwrap(), None);
+    }
 }
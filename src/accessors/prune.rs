@@ -0,0 +1,185 @@
+use crate::{
+    kv::{mdbx::MdbxTransaction, tables, traits::*},
+    models::*,
+};
+use mdbx::{EnvironmentKind, TransactionKind, RW};
+use std::fmt::Display;
+use tracing::*;
+
+/// Kind of historical data that may be discarded by pruning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::Display)]
+pub enum PruneTarget {
+    /// Account and storage changesets.
+    History,
+    /// Call trace sets and call from/to indices.
+    CallTraces,
+    /// Block bodies and their transactions. Headers are always kept.
+    Blocks,
+}
+
+/// Requested data existed but is gone, as opposed to never having existed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataPruned {
+    pub target: PruneTarget,
+    pub horizon: BlockNumber,
+    pub block_number: BlockNumber,
+    /// Where the pruned data can still be found, if it was archived.
+    pub archive: Option<String>,
+}
+
+impl Display for DataPruned {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} data pruned below block {} (requested block {})",
+            self.target, self.horizon, self.block_number
+        )?;
+        if let Some(archive) = &self.archive {
+            write!(f, ", available from {}", archive)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DataPruned {}
+
+fn key(target: PruneTarget) -> Vec<u8> {
+    format!("PruneHorizon{}", target).into_bytes()
+}
+
+fn archive_key(target: PruneTarget) -> Vec<u8> {
+    format!("PruneArchive{}", target).into_bytes()
+}
+
+/// Lowest block for which `target` data is retained.
+pub fn read<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    target: PruneTarget,
+) -> anyhow::Result<Option<BlockNumber>> {
+    trace!("Reading prune horizon for {}", target);
+
+    tx.get(tables::DbInfo, key(target))?
+        .map(|v| BlockNumber::decode(&v))
+        .transpose()
+}
+
+pub fn write<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RW, E>,
+    target: PruneTarget,
+    horizon: BlockNumber,
+) -> anyhow::Result<()> {
+    trace!("Writing prune horizon for {}: {}", target, horizon);
+
+    tx.set(tables::DbInfo, key(target), horizon.encode().to_vec())
+}
+
+/// Where `target` data below the horizon was archived to, e.g. a directory of era1 files.
+pub fn read_archive<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    target: PruneTarget,
+) -> anyhow::Result<Option<String>> {
+    tx.get(tables::DbInfo, archive_key(target))?
+        .map(String::from_utf8)
+        .transpose()
+        .map_err(From::from)
+}
+
+pub fn write_archive<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RW, E>,
+    target: PruneTarget,
+    archive: &str,
+) -> anyhow::Result<()> {
+    trace!("Writing prune archive for {}: {}", target, archive);
+
+    tx.set(
+        tables::DbInfo,
+        archive_key(target),
+        archive.as_bytes().to_vec(),
+    )
+}
+
+/// Why `target` data for `block_number` is missing, if it has been discarded.
+pub fn pruned<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    target: PruneTarget,
+    block_number: BlockNumber,
+) -> anyhow::Result<Option<DataPruned>> {
+    if let Some(horizon) = read(tx, target)? {
+        if block_number < horizon {
+            return Ok(Some(DataPruned {
+                target,
+                horizon,
+                block_number,
+                archive: read_archive(tx, target)?,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Fails with [`DataPruned`] if `target` data for `block_number` has been discarded.
+pub fn ensure_available<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    target: PruneTarget,
+    block_number: BlockNumber,
+) -> anyhow::Result<()> {
+    if let Some(e) = pruned(tx, target, block_number)? {
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::new_mem_database;
+
+    #[test]
+    fn prune_horizon() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        assert_eq!(read(&tx, PruneTarget::History).unwrap(), None);
+        ensure_available(&tx, PruneTarget::History, BlockNumber(0)).unwrap();
+
+        write(&tx, PruneTarget::History, BlockNumber(100)).unwrap();
+
+        assert_eq!(
+            read(&tx, PruneTarget::History).unwrap(),
+            Some(BlockNumber(100))
+        );
+        assert_eq!(read(&tx, PruneTarget::CallTraces).unwrap(), None);
+        ensure_available(&tx, PruneTarget::History, BlockNumber(100)).unwrap();
+        assert_eq!(
+            ensure_available(&tx, PruneTarget::History, BlockNumber(99))
+                .unwrap_err()
+                .downcast::<DataPruned>()
+                .unwrap(),
+            DataPruned {
+                target: PruneTarget::History,
+                horizon: BlockNumber(100),
+                block_number: BlockNumber(99),
+                archive: None,
+            }
+        );
+
+        write(&tx, PruneTarget::Blocks, BlockNumber(8192)).unwrap();
+        write_archive(&tx, PruneTarget::Blocks, "/data/era1").unwrap();
+
+        assert_eq!(
+            pruned(&tx, PruneTarget::Blocks, BlockNumber(8192)).unwrap(),
+            None
+        );
+        let e = pruned(&tx, PruneTarget::Blocks, BlockNumber(0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(e.archive.as_deref(), Some("/data/era1"));
+        assert_eq!(
+            e.to_string(),
+            "Blocks data pruned below block 8192 (requested block 0), available from /data/era1"
+        );
+        assert_eq!(read_archive(&tx, PruneTarget::History).unwrap(), None);
+    }
+}
@@ -1,11 +1,29 @@
 use crate::{
     kv::{mdbx::MdbxTransaction, tables, traits::*},
     models::*,
+    stagedsync::stages::{ACCOUNT_HISTORY_INDEX, STORAGE_HISTORY_INDEX},
 };
 use mdbx::{EnvironmentKind, TransactionKind};
 
 pub mod account {
     use super::*;
+    use crate::kv::mdbx::MdbxCursor;
+
+    fn read_change<K: TransactionKind>(
+        cursor: &mut MdbxCursor<'_, K, tables::AccountChangeSet>,
+        address_to_find: Address,
+        change_block: BlockNumber,
+    ) -> anyhow::Result<Option<Option<Account>>> {
+        if let Some(tables::AccountChange { address, account }) =
+            cursor.seek_both_range(change_block, address_to_find)?
+        {
+            if address == address_to_find {
+                return Ok(Some(account));
+            }
+        }
+
+        Ok(None)
+    }
 
     pub fn read<K: TransactionKind, E: EnvironmentKind>(
         tx: &MdbxTransaction<'_, K, E>,
@@ -13,20 +31,21 @@ pub mod account {
         block_number: Option<BlockNumber>,
     ) -> anyhow::Result<Option<Account>> {
         if let Some(block_number) = block_number {
-            if let Some(block_number) = super::history_index::find_next_block(
+            let last_change_block = tx
+                .cursor(tables::AccountChangeSet)?
+                .last()?
+                .map(|(block_number, _)| block_number);
+            let mut cursor = tx.cursor(tables::AccountChangeSet)?;
+            if let Some(account) = super::history_index::find_change(
                 tx,
+                ACCOUNT_HISTORY_INDEX,
                 tables::AccountHistory,
                 address_to_find,
                 block_number,
+                last_change_block,
+                |change_block| read_change(&mut cursor, address_to_find, change_block),
             )? {
-                if let Some(tables::AccountChange { address, account }) = tx
-                    .cursor(tables::AccountChangeSet)?
-                    .seek_both_range(block_number, address_to_find)?
-                {
-                    if address == address_to_find {
-                        return Ok(account);
-                    }
-                }
+                return Ok(account);
             }
         }
 
@@ -36,7 +55,29 @@ pub mod account {
 
 pub mod storage {
     use super::*;
-    use crate::u256_to_h256;
+    use crate::{kv::mdbx::MdbxCursor, u256_to_h256};
+
+    /// Value of `location_to_find` before block `change_block`, if the block changed it.
+    pub(crate) fn read_change<K: TransactionKind>(
+        cursor: &mut MdbxCursor<'_, K, tables::StorageChangeSet>,
+        address: Address,
+        location_to_find: H256,
+        change_block: BlockNumber,
+    ) -> anyhow::Result<Option<U256>> {
+        if let Some(tables::StorageChange { location, value }) = cursor.seek_both_range(
+            tables::StorageChangeKey {
+                block_number: change_block,
+                address,
+            },
+            location_to_find,
+        )? {
+            if location == location_to_find {
+                return Ok(Some(value));
+            }
+        }
+
+        Ok(None)
+    }
 
     pub fn read<K: TransactionKind, E: EnvironmentKind>(
         tx: &MdbxTransaction<'_, K, E>,
@@ -46,25 +87,21 @@ pub mod storage {
     ) -> anyhow::Result<U256> {
         let location_to_find = u256_to_h256(location_to_find);
         if let Some(block_number) = block_number {
-            if let Some(block_number) = super::history_index::find_next_block(
+            let last_change_block = tx
+                .cursor(tables::StorageChangeSet)?
+                .last()?
+                .map(|(tables::StorageChangeKey { block_number, .. }, _)| block_number);
+            let mut cursor = tx.cursor(tables::StorageChangeSet)?;
+            if let Some(value) = super::history_index::find_change(
                 tx,
+                STORAGE_HISTORY_INDEX,
                 tables::StorageHistory,
                 (address, location_to_find),
                 block_number,
+                last_change_block,
+                |change_block| read_change(&mut cursor, address, location_to_find, change_block),
             )? {
-                if let Some(tables::StorageChange { location, value }) =
-                    tx.cursor(tables::StorageChangeSet)?.seek_both_range(
-                        tables::StorageChangeKey {
-                            block_number,
-                            address,
-                        },
-                        location_to_find,
-                    )?
-                {
-                    if location == location_to_find {
-                        return Ok(value);
-                    }
-                }
+                return Ok(value);
             }
         }
 
@@ -79,9 +116,56 @@ pub mod storage {
 
 pub mod history_index {
     use super::*;
-    use crate::kv::{mdbx::MdbxTransaction, tables::BitmapKey};
+    use crate::{
+        kv::{mdbx::MdbxTransaction, tables::BitmapKey},
+        StageId,
+    };
     use croaring::Treemap as RoaringTreemap;
 
+    /// Find the first change to `needle` after `block_number` and read it with `read_change`.
+    ///
+    /// The history index built by `stage` is looked up as far as it goes, and only the changesets
+    /// of blocks past its progress, up to `last_change_block`, are probed one by one.
+    pub fn find_change<'db: 'tx, 'tx, K, TK, E, H, T>(
+        tx: &'tx MdbxTransaction<'db, TK, E>,
+        stage: StageId,
+        table: H,
+        needle: K,
+        block_number: BlockNumber,
+        last_change_block: Option<BlockNumber>,
+        mut read_change: impl FnMut(BlockNumber) -> anyhow::Result<Option<T>>,
+    ) -> anyhow::Result<Option<T>>
+    where
+        H: Table<Key = BitmapKey<K>, Value = RoaringTreemap, SeekKey = BitmapKey<K>>,
+        BitmapKey<K>: TableObject,
+        K: Copy + PartialEq,
+        TK: TransactionKind,
+        E: EnvironmentKind,
+    {
+        let mut scan_from = block_number + 1;
+        if let Some(indexed_to) = stage.get_progress(tx)? {
+            if indexed_to > block_number {
+                if let Some(change_block) = find_next_block(tx, table, needle, block_number)? {
+                    if change_block <= indexed_to {
+                        return read_change(change_block);
+                    }
+                }
+                scan_from = indexed_to + 1;
+            }
+        }
+
+        if let Some(last_change_block) = last_change_block {
+            for change_block in scan_from..=last_change_block {
+                if let Some(change) = read_change(change_block)? {
+                    return Ok(Some(change));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Find the first block after `block_number` in which `needle` was changed.
     pub fn find_next_block<'db: 'tx, 'tx, K, TK, E, H>(
         tx: &'tx MdbxTransaction<'db, TK, E>,
         table: H,
@@ -96,31 +180,313 @@ pub mod history_index {
         E: EnvironmentKind,
     {
         let mut ch = tx.cursor(table)?;
-        if let Some((index_key, change_blocks)) = ch.seek(BitmapKey {
+        let mut entry = ch.seek(BitmapKey {
             inner: needle,
             block_number,
-        })? {
-            if index_key.inner == needle {
-                return Ok(change_blocks
-                    .iter()
-                    .find(|&change_block| *block_number < change_block)
-                    .map(BlockNumber));
+        })?;
+        // Chunks are keyed by their highest block, so the answer may live in a subsequent chunk.
+        while let Some((index_key, change_blocks)) = entry {
+            if index_key.inner != needle {
+                break;
+            }
+
+            if let Some(change_block) = change_blocks
+                .iter()
+                .find(|&change_block| *block_number < change_block)
+            {
+                return Ok(Some(BlockNumber(change_block)));
             }
+
+            entry = ch.next()?;
         }
 
         Ok(None)
     }
 }
 
+/// Iteration over the whole state as of a past block.
+pub mod dump {
+    use super::*;
+    use crate::h256_to_u256;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    /// Call `f` with every account existing after execution of `block_number`, in address order.
+    ///
+    /// Plain state is merged with accounts changed after the block, which are gathered up front,
+    /// so memory use grows with the number of blocks between `block_number` and the head.
+    pub fn walk_accounts<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        block_number: BlockNumber,
+        mut f: impl FnMut(Address, Account) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut changed = BTreeSet::new();
+        for entry in tx
+            .cursor(tables::AccountChangeSet)?
+            .walk(Some(block_number + 1))
+        {
+            let (_, tables::AccountChange { address, .. }) = entry?;
+            changed.insert(address);
+        }
+        let mut changed = changed.into_iter().peekable();
+
+        let mut visit = |address| {
+            if let Some(account) = super::account::read(tx, address, Some(block_number))? {
+                f(address, account)?;
+            }
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        for entry in tx.cursor(tables::Account)?.walk(None) {
+            let (address, _) = entry?;
+            while let Some(changed_address) = changed.next_if(|&a| a < address) {
+                visit(changed_address)?;
+            }
+            changed.next_if_eq(&address);
+            visit(address)?;
+        }
+        for address in changed {
+            visit(address)?;
+        }
+
+        Ok(())
+    }
+
+    /// Storage locations changed after `block_number`, by account.
+    pub fn changed_storage<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        block_number: BlockNumber,
+    ) -> anyhow::Result<BTreeMap<Address, BTreeSet<H256>>> {
+        let mut out = BTreeMap::<_, BTreeSet<_>>::new();
+        for entry in tx
+            .cursor(tables::StorageChangeSet)?
+            .walk(Some(block_number + 1))
+        {
+            let (tables::StorageChangeKey { address, .. }, tables::StorageChange { location, .. }) =
+                entry?;
+            out.entry(address).or_default().insert(location);
+        }
+
+        Ok(out)
+    }
+
+    /// Call `f` with every nonzero storage slot of `address` after execution of `block_number`,
+    /// in location order. `changed` are the locations of the account changed after the block,
+    /// see [`changed_storage`].
+    pub fn walk_storage<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        address: Address,
+        block_number: BlockNumber,
+        changed: &BTreeSet<H256>,
+        mut f: impl FnMut(H256, U256) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut changed = changed.iter().copied().peekable();
+
+        let mut visit = |location: H256| {
+            let value =
+                super::storage::read(tx, address, h256_to_u256(location), Some(block_number))?;
+            if value != U256::ZERO {
+                f(location, value)?;
+            }
+
+            Ok::<_, anyhow::Error>(())
+        };
+
+        for entry in tx.cursor(tables::Storage)?.walk_dup(address) {
+            let (location, _) = entry?;
+            while let Some(changed_location) = changed.next_if(|&l| l < location) {
+                visit(changed_location)?;
+            }
+            changed.next_if_eq(&location);
+            visit(location)?;
+        }
+        for location in changed {
+            visit(location)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
     use crate::{
         h256_to_u256,
-        kv::{new_mem_database, tables},
+        kv::{
+            new_mem_database,
+            tables::{self, BitmapKey},
+        },
+        stagedsync::stages::EXECUTION,
     };
+    use croaring::Treemap as RoaringTreemap;
     use hex_literal::hex;
 
+    #[test]
+    fn read_account_history() {
+        let db = new_mem_database().unwrap();
+        let txn = db.begin_mutable().unwrap();
+
+        let address = hex!("b000000000000000000000000000000000000008").into();
+        let account = |balance: u64| Account {
+            balance: balance.as_u256(),
+            ..Default::default()
+        };
+
+        txn.set(
+            tables::AccountChangeSet,
+            BlockNumber(3),
+            tables::AccountChange {
+                address,
+                account: None,
+            },
+        )
+        .unwrap();
+        txn.set(
+            tables::AccountChangeSet,
+            BlockNumber(7),
+            tables::AccountChange {
+                address,
+                account: Some(account(1)),
+            },
+        )
+        .unwrap();
+        txn.set(tables::Account, address, account(2)).unwrap();
+        EXECUTION.save_progress(&txn, BlockNumber(10)).unwrap();
+
+        let check = || {
+            assert_eq!(
+                super::account::read(&txn, address, Some(BlockNumber(1))).unwrap(),
+                None
+            );
+            assert_eq!(
+                super::account::read(&txn, address, Some(BlockNumber(5))).unwrap(),
+                Some(account(1))
+            );
+            assert_eq!(
+                super::account::read(&txn, address, Some(BlockNumber(8))).unwrap(),
+                Some(account(2))
+            );
+            assert_eq!(
+                super::account::read(&txn, address, None).unwrap(),
+                Some(account(2))
+            );
+        };
+
+        let index = |blocks: &[u64], progress| {
+            let mut bitmap = RoaringTreemap::create();
+            for &block in blocks {
+                bitmap.add(block);
+            }
+            txn.set(
+                tables::AccountHistory,
+                BitmapKey {
+                    inner: address,
+                    block_number: BlockNumber(u64::MAX),
+                },
+                bitmap,
+            )
+            .unwrap();
+            ACCOUNT_HISTORY_INDEX
+                .save_progress(&txn, BlockNumber(progress))
+                .unwrap();
+        };
+
+        // No history index yet, changesets are probed directly.
+        check();
+
+        // Index behind execution, changesets are probed past its progress only.
+        index(&[3], 5);
+        check();
+
+        index(&[3, 7], 10);
+        check();
+    }
+
+    #[test]
+    fn dump_state_at_block() {
+        let db = new_mem_database().unwrap();
+        let txn = db.begin_mutable().unwrap();
+
+        let a1 = hex!("a000000000000000000000000000000000000001").into();
+        let a2 = hex!("a000000000000000000000000000000000000002").into();
+        let a3 = hex!("a000000000000000000000000000000000000003").into();
+        let account = |balance: u64| Account {
+            balance: balance.as_u256(),
+            ..Default::default()
+        };
+        let loc1 = H256::from_low_u64_be(1);
+        let loc2 = H256::from_low_u64_be(2);
+
+        // a1 is untouched, a2 is deleted and a3 created in block 5.
+        txn.set(tables::Account, a1, account(1)).unwrap();
+        txn.set(tables::Account, a3, account(3)).unwrap();
+        txn.set(
+            tables::AccountChangeSet,
+            BlockNumber(5),
+            tables::AccountChange {
+                address: a2,
+                account: Some(account(2)),
+            },
+        )
+        .unwrap();
+        txn.set(
+            tables::AccountChangeSet,
+            BlockNumber(5),
+            tables::AccountChange {
+                address: a3,
+                account: None,
+            },
+        )
+        .unwrap();
+
+        // loc1 of a1 is cleared and loc2 set in block 5.
+        txn.set(tables::Storage, a1, (loc2, 7.as_u256())).unwrap();
+        for (location, value) in [(loc1, 6.as_u256()), (loc2, U256::ZERO)] {
+            txn.set(
+                tables::StorageChangeSet,
+                tables::StorageChangeKey {
+                    block_number: BlockNumber(5),
+                    address: a1,
+                },
+                tables::StorageChange { location, value },
+            )
+            .unwrap();
+        }
+        EXECUTION.save_progress(&txn, BlockNumber(5)).unwrap();
+
+        let accounts = |block_number| {
+            let mut out = vec![];
+            super::dump::walk_accounts(&txn, BlockNumber(block_number), |address, account| {
+                out.push((address, account));
+                Ok(())
+            })
+            .unwrap();
+            out
+        };
+        assert_eq!(accounts(4), vec![(a1, account(1)), (a2, account(2))]);
+        assert_eq!(accounts(5), vec![(a1, account(1)), (a3, account(3))]);
+
+        let storage = |block_number| {
+            let changed = super::dump::changed_storage(&txn, BlockNumber(block_number)).unwrap();
+            let mut out = vec![];
+            super::dump::walk_storage(
+                &txn,
+                a1,
+                BlockNumber(block_number),
+                changed.get(&a1).unwrap_or(&Default::default()),
+                |location, value| {
+                    out.push((location, value));
+                    Ok(())
+                },
+            )
+            .unwrap();
+            out
+        };
+        assert_eq!(storage(4), vec![(loc1, 6.as_u256())]);
+        assert_eq!(storage(5), vec![(loc2, 7.as_u256())]);
+    }
+
     #[test]
     fn read_storage() {
         let db = new_mem_database().unwrap();
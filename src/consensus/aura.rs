@@ -0,0 +1,653 @@
+//! Aura (Authority Round), the proof-of-authority engine of Gnosis Chain before its merge.
+//!
+//! Time is divided into steps of `step_duration` seconds, and only the validator at `step % n`
+//! of the current set may propose a block in a step. Validator sets change at fixed blocks or,
+//! for a contract set, when a change signalled by the contract becomes final: once more than
+//! half of the validators have signed blocks from the signalling one on, the next block calls
+//! `finalizeChange()` on the contract.
+//!
+//! Signers of the blocks since the last change are tracked in memory, from the first block
+//! executed by the engine.
+use super::{base::ConsensusEngineBase, *};
+use crate::crypto::pubkey_to_address;
+use anyhow::{ensure, format_err};
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    Message, SECP256K1,
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::SystemTime,
+};
+
+/// `getValidators()`
+const GET_VALIDATORS: [u8; 4] = hex!("b7ab4db5");
+/// `finalizeChange()`
+const FINALIZE_CHANGE: [u8; 4] = hex!("75286211");
+/// `reward(address[],uint16[])`
+const REWARD: [u8; 4] = hex!("f91c2898");
+/// `InitiateChange(bytes32 indexed parentHash, address[] newSet)`
+const INITIATE_CHANGE: H256 = H256(hex!(
+    "55252fa6eee4741b4e24a74a70e9c11fd2c2281df8d6ea13126ff845f7825c89"
+));
+
+/// Difficulty of an Aura block, which makes chains that skip fewer steps heavier.
+pub fn aura_difficulty(parent_step: u64, step: u64) -> U256 {
+    U256::from(u128::MAX) + U256::from(parent_step) - U256::from(step)
+}
+
+fn abi_word(data: &[u8], index: usize) -> anyhow::Result<&[u8]> {
+    data.get(index * 32..(index + 1) * 32)
+        .ok_or_else(|| format_err!("ABI data too short"))
+}
+
+fn abi_usize(word: &[u8]) -> anyhow::Result<usize> {
+    ensure!(
+        word[..24].iter().all(|&b| b == 0),
+        "ABI offset or length out of range"
+    );
+    Ok(u64::from_be_bytes(word[24..].try_into().unwrap()) as usize)
+}
+
+/// Elements of the dynamic array referenced by the `index`th word of `data`.
+fn abi_array(data: &[u8], index: usize) -> anyhow::Result<Vec<&[u8]>> {
+    let offset = abi_usize(abi_word(data, index)?)?;
+    let array = data
+        .get(offset..)
+        .ok_or_else(|| format_err!("ABI offset out of range"))?;
+    let len = abi_usize(abi_word(array, 0)?)?;
+    (1..=len).map(|i| abi_word(array, i)).collect()
+}
+
+fn abi_addresses(data: &[u8], index: usize) -> anyhow::Result<Vec<Address>> {
+    Ok(abi_array(data, index)?
+        .into_iter()
+        .map(|word| Address::from_slice(&word[12..]))
+        .collect())
+}
+
+/// `reward([author], [0])`, where kind 0 is the reward of the block author.
+fn reward_call(author: Address) -> Bytes {
+    let mut words = [[0; 32]; 6];
+    words[0][31] = 0x40;
+    words[1][31] = 0x80;
+    words[2][31] = 1;
+    words[3][12..].copy_from_slice(author.as_bytes());
+    words[4][31] = 1;
+
+    REWARD
+        .into_iter()
+        .chain(words.into_iter().flatten())
+        .collect::<Vec<u8>>()
+        .into()
+}
+
+fn recover_signer(header: &BlockHeader, signature: &H520) -> anyhow::Result<Address> {
+    let signature = signature.as_bytes();
+    let public = SECP256K1.recover_ecdsa(
+        &Message::from_slice(header.truncated_hash().as_bytes())?,
+        &RecoverableSignature::from_compact(
+            &signature[..64],
+            RecoveryId::from_i32(signature[64].into())?,
+        )?,
+    )?;
+
+    Ok(pubkey_to_address(&public))
+}
+
+/// Checks that the block was proposed by the validator of its step.
+fn check_proposer(header: &PartialHeader, validators: &[Address]) -> anyhow::Result<()> {
+    let step = header.aura_seal.ok_or(ValidationError::InvalidSeal)?.step;
+    ensure!(!validators.is_empty(), "Empty validator set");
+
+    let expected = validators[(step % validators.len() as u64) as usize];
+    if header.beneficiary != expected {
+        return Err(ValidationError::WrongProposer {
+            expected,
+            got: header.beneficiary,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Blocks that are not final yet, with their signers.
+#[derive(Debug, Default)]
+struct RollingFinality {
+    blocks: VecDeque<(BlockNumber, Address)>,
+    sign_count: HashMap<Address, usize>,
+}
+
+impl RollingFinality {
+    /// Adds a block and returns the latest block that became final, if any: a block is final
+    /// once more than half of the `validators` signed it or a block after it.
+    fn push(
+        &mut self,
+        number: BlockNumber,
+        signer: Address,
+        validators: usize,
+    ) -> Option<BlockNumber> {
+        self.blocks.push_back((number, signer));
+        *self.sign_count.entry(signer).or_default() += 1;
+
+        let mut finalized = None;
+        while self.sign_count.len() * 2 > validators {
+            let (number, signer) = self.blocks.pop_front().unwrap();
+            let count = self.sign_count.get_mut(&signer).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.sign_count.remove(&signer);
+            }
+            finalized = Some(number);
+        }
+
+        finalized
+    }
+}
+
+#[derive(Debug)]
+pub struct Aura {
+    base: ConsensusEngineBase,
+    step_duration: u64,
+    validators: BTreeMap<BlockNumber, AuraValidatorSet>,
+    block_reward: BTreeMap<BlockNumber, U256>,
+    block_reward_contract: BTreeMap<BlockNumber, Address>,
+    /// Validators of the contract set in effect, as last returned by `getValidators()`.
+    contract_validators: Option<(Address, Vec<Address>)>,
+    finality: RollingFinality,
+    /// Block that signalled a change of the contract set, and whether it is final.
+    pending_change: Option<(BlockNumber, bool)>,
+}
+
+impl Aura {
+    pub fn new(
+        chain_id: ChainId,
+        eip1559_block: Option<BlockNumber>,
+        step_duration: u64,
+        validators: BTreeMap<BlockNumber, AuraValidatorSet>,
+        block_reward: BTreeMap<BlockNumber, U256>,
+        block_reward_contract: BTreeMap<BlockNumber, Address>,
+    ) -> Self {
+        Self {
+            base: ConsensusEngineBase::new(chain_id, eip1559_block),
+            step_duration,
+            validators,
+            block_reward,
+            block_reward_contract,
+            contract_validators: None,
+            finality: Default::default(),
+            pending_change: None,
+        }
+    }
+
+    fn validator_set(&self, block_number: BlockNumber) -> anyhow::Result<&AuraValidatorSet> {
+        self.validators
+            .range(..=block_number)
+            .next_back()
+            .map(|(_, set)| set)
+            .ok_or_else(|| format_err!("No validator set for block {}", block_number))
+    }
+}
+
+impl Consensus for Aura {
+    fn pre_validate_block(&self, block: &Block, state: &mut dyn State) -> anyhow::Result<()> {
+        self.base.pre_validate_block(block, state)?;
+        self.validate_ommers(&block.header, &block.ommers, state)
+    }
+
+    fn validate_block_header(
+        &self,
+        header: &BlockHeader,
+        state: &mut dyn State,
+        with_future_timestamp_check: bool,
+    ) -> anyhow::Result<()> {
+        let parent = self
+            .base
+            .get_parent_header(state, header)?
+            .ok_or(ValidationError::UnknownParent)?;
+
+        self.base
+            .validate_block_header(header, &parent, with_future_timestamp_check)?;
+
+        let step = header.aura_seal.ok_or(ValidationError::InvalidSeal)?.step;
+        let parent_step = parent.aura_seal.ok_or(ValidationError::InvalidSeal)?.step;
+        if step <= parent_step {
+            return Err(ValidationError::InvalidSeal.into());
+        }
+
+        if with_future_timestamp_check {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs();
+            if step > now / self.step_duration + 1 {
+                return Err(ValidationError::FutureBlock {
+                    now,
+                    got: header.timestamp,
+                }
+                .into());
+            }
+        }
+
+        if header.difficulty != aura_difficulty(parent_step, step) {
+            return Err(ValidationError::WrongDifficulty.into());
+        }
+
+        self.validate_seal(header)
+    }
+
+    fn validate_ommers(
+        &self,
+        _: &BlockHeader,
+        ommers: &[BlockHeader],
+        _: &mut dyn State,
+    ) -> anyhow::Result<()> {
+        if !ommers.is_empty() {
+            return Err(ValidationError::TooManyOmmers.into());
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the block is signed by its author. Whether the author may propose in the
+    /// block's step is checked on execution, when the validator set is known.
+    fn validate_seal(&self, header: &BlockHeader) -> anyhow::Result<()> {
+        let seal = header.aura_seal.ok_or(ValidationError::InvalidSeal)?;
+        if recover_signer(header, &seal.signature)? != header.beneficiary {
+            return Err(ValidationError::InvalidSeal.into());
+        }
+
+        Ok(())
+    }
+
+    fn pre_execution(&self, header: &PartialHeader) -> anyhow::Result<Vec<FinalizationChange>> {
+        let contract = match self.validator_set(header.number)? {
+            AuraValidatorSet::List(validators) => {
+                check_proposer(header, validators)?;
+                return Ok(Vec::new());
+            }
+            AuraValidatorSet::Contract(contract) => *contract,
+        };
+
+        let mut changes = Vec::new();
+        let finalizing = matches!(self.pending_change, Some((_, true)));
+        if finalizing {
+            changes.push(FinalizationChange::SystemCall {
+                contract,
+                data: FINALIZE_CHANGE.to_vec().into(),
+            });
+        }
+
+        match &self.contract_validators {
+            Some((address, validators)) if *address == contract && !finalizing => {
+                check_proposer(header, validators)?;
+            }
+            _ => changes.push(FinalizationChange::SystemCall {
+                contract,
+                data: GET_VALIDATORS.to_vec().into(),
+            }),
+        }
+
+        Ok(changes)
+    }
+
+    fn post_execution(
+        &mut self,
+        header: &PartialHeader,
+        receipts: &[Receipt],
+    ) -> anyhow::Result<()> {
+        let contract = match self.validator_set(header.number)? {
+            AuraValidatorSet::Contract(contract) => *contract,
+            AuraValidatorSet::List(_) => return Ok(()),
+        };
+
+        if receipts
+            .iter()
+            .flat_map(|receipt| &receipt.logs)
+            .any(|log| {
+                log.address == contract
+                    && log.topics.first() == Some(&INITIATE_CHANGE)
+                    && log.topics.get(1) == Some(&header.parent_hash)
+            })
+        {
+            self.pending_change = Some((header.number, false));
+        }
+
+        let validators = self
+            .contract_validators
+            .as_ref()
+            .map(|(_, validators)| validators.len())
+            .unwrap_or_default();
+        if let Some(finalized) = self
+            .finality
+            .push(header.number, header.beneficiary, validators)
+        {
+            if let Some((signalled_at, final_)) = &mut self.pending_change {
+                if *signalled_at <= finalized {
+                    *final_ = true;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_system_call(
+        &mut self,
+        header: &PartialHeader,
+        contract: Address,
+        data: &[u8],
+        output: Bytes,
+    ) -> anyhow::Result<Vec<FinalizationChange>> {
+        match data.get(..4) {
+            Some(selector) if selector == GET_VALIDATORS => {
+                let validators = abi_addresses(&output, 0)?;
+                check_proposer(header, &validators)?;
+
+                if self
+                    .contract_validators
+                    .as_ref()
+                    .map(|(address, _)| *address)
+                    != Some(contract)
+                {
+                    // Nothing is pending for a contract that just took over.
+                    self.pending_change = None;
+                    self.finality = Default::default();
+                }
+                self.contract_validators = Some((contract, validators));
+            }
+            Some(selector) if selector == FINALIZE_CHANGE => {
+                self.pending_change = None;
+                self.finality = Default::default();
+            }
+            Some(selector) if selector == REWARD => {
+                let receivers = abi_addresses(&output, 0)?;
+                let amounts = abi_array(&output, 1)?;
+                ensure!(
+                    receivers.len() == amounts.len(),
+                    "Reward contract returned {} receivers and {} amounts",
+                    receivers.len(),
+                    amounts.len()
+                );
+
+                return Ok(receivers
+                    .into_iter()
+                    .zip(amounts)
+                    .map(|(address, amount)| FinalizationChange::Reward {
+                        address,
+                        amount: U256::from_be_bytes(amount.try_into().unwrap()),
+                        kind: RewardKind::Block,
+                    })
+                    .collect());
+            }
+            _ => {}
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn finalize(
+        &self,
+        header: &PartialHeader,
+        _: &[BlockHeader],
+        _: Revision,
+        _: &mut dyn State,
+    ) -> anyhow::Result<Vec<FinalizationChange>> {
+        if let Some((_, &contract)) = self
+            .block_reward_contract
+            .range(..=header.number)
+            .next_back()
+        {
+            return Ok(vec![FinalizationChange::SystemCall {
+                contract,
+                data: reward_call(header.beneficiary),
+            }]);
+        }
+
+        let block_reward = self
+            .block_reward
+            .range(..=header.number)
+            .next_back()
+            .map(|(_, &reward)| reward)
+            .unwrap_or(U256::ZERO);
+        if block_reward == U256::ZERO {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![FinalizationChange::Reward {
+            address: header.beneficiary,
+            amount: block_reward,
+            kind: RewardKind::Block,
+        }])
+    }
+
+    fn get_beneficiary(&self, header: &BlockHeader) -> anyhow::Result<Address> {
+        Ok(header.beneficiary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::to_pubkey, InMemoryState};
+    use secp256k1::SecretKey;
+
+    const CONTRACT: Address = H160([0xcc; 20]);
+
+    fn key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn address(byte: u8) -> Address {
+        pubkey_to_address(&to_pubkey(&key(byte)))
+    }
+
+    fn aura(validators: AuraValidatorSet) -> Aura {
+        Aura::new(
+            ChainId(100),
+            None,
+            5,
+            [(BlockNumber(0), validators)].into_iter().collect(),
+            Default::default(),
+            Default::default(),
+        )
+    }
+
+    fn header(number: u64, step: u64, beneficiary: Address) -> PartialHeader {
+        PartialHeader {
+            number: BlockNumber(number),
+            beneficiary,
+            aura_seal: Some(AuraSeal {
+                step,
+                signature: H520::zero(),
+            }),
+            ..PartialHeader::empty()
+        }
+    }
+
+    fn abi_addresses_output(addresses: &[Address]) -> Bytes {
+        let mut output = vec![0; 64];
+        output[31] = 0x20;
+        output[63] = addresses.len() as u8;
+        for address in addresses {
+            output.extend_from_slice(&[0; 12]);
+            output.extend_from_slice(address.as_bytes());
+        }
+        output.into()
+    }
+
+    #[test]
+    fn seal() {
+        let mut header = BlockHeader {
+            number: BlockNumber(1),
+            beneficiary: address(1),
+            ..BlockHeader::empty()
+        };
+        let (recovery_id, signature) = SECP256K1
+            .sign_ecdsa_recoverable(
+                &Message::from_slice(header.truncated_hash().as_bytes()).unwrap(),
+                &key(1),
+            )
+            .serialize_compact();
+        let mut seal = signature.to_vec();
+        seal.push(recovery_id.to_i32() as u8);
+        header.aura_seal = Some(AuraSeal {
+            step: 7,
+            signature: H520::from_slice(&seal),
+        });
+
+        let engine = aura(AuraValidatorSet::List(vec![address(1)]));
+        engine.validate_seal(&header).unwrap();
+
+        header.beneficiary = address(2);
+        assert!(engine.validate_seal(&header).is_err());
+    }
+
+    #[test]
+    fn proposer_rotation() {
+        let engine = aura(AuraValidatorSet::List(vec![
+            address(1),
+            address(2),
+            address(3),
+        ]));
+
+        engine.pre_execution(&header(1, 4, address(2))).unwrap();
+        assert_eq!(
+            engine
+                .pre_execution(&header(1, 5, address(2)))
+                .unwrap_err()
+                .downcast::<ValidationError>()
+                .unwrap(),
+            ValidationError::WrongProposer {
+                expected: address(3),
+                got: address(2)
+            }
+        );
+    }
+
+    #[test]
+    fn contract_transition() {
+        let mut engine = aura(AuraValidatorSet::Contract(CONTRACT));
+        let system_calls = |changes: Vec<FinalizationChange>| {
+            changes
+                .into_iter()
+                .map(|change| match change {
+                    FinalizationChange::SystemCall { contract, data } => {
+                        assert_eq!(contract, CONTRACT);
+                        data[..4].to_vec()
+                    }
+                    other => panic!("unexpected change {:?}", other),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The set is read from the contract first.
+        let first = header(1, 10, address(1));
+        assert_eq!(
+            system_calls(engine.pre_execution(&first).unwrap()),
+            vec![GET_VALIDATORS.to_vec()]
+        );
+        engine
+            .on_system_call(
+                &first,
+                CONTRACT,
+                &GET_VALIDATORS,
+                abi_addresses_output(&[address(1)]),
+            )
+            .unwrap();
+
+        // Signals a change, final right away with a single validator.
+        engine
+            .post_execution(
+                &first,
+                &[Receipt::new(
+                    TxType::Legacy,
+                    true,
+                    21_000,
+                    vec![Log {
+                        address: CONTRACT,
+                        topics: vec![INITIATE_CHANGE, first.parent_hash],
+                        data: Bytes::new(),
+                    }],
+                )],
+            )
+            .unwrap();
+
+        let second = header(2, 11, address(2));
+        assert_eq!(
+            system_calls(engine.pre_execution(&second).unwrap()),
+            vec![FINALIZE_CHANGE.to_vec(), GET_VALIDATORS.to_vec()]
+        );
+        engine
+            .on_system_call(&second, CONTRACT, &FINALIZE_CHANGE, Bytes::new())
+            .unwrap();
+        engine
+            .on_system_call(
+                &second,
+                CONTRACT,
+                &GET_VALIDATORS,
+                abi_addresses_output(&[address(1), address(2)]),
+            )
+            .unwrap();
+        engine.post_execution(&second, &[]).unwrap();
+
+        // The new set is cached until the next change.
+        assert_eq!(
+            system_calls(engine.pre_execution(&header(3, 12, address(1))).unwrap()),
+            Vec::<Vec<u8>>::new()
+        );
+        assert!(engine.pre_execution(&header(3, 12, address(2))).is_err());
+    }
+
+    #[test]
+    fn reward_contract() {
+        let mut engine = aura(AuraValidatorSet::List(vec![address(1)]));
+        engine.block_reward_contract = [(BlockNumber(0), CONTRACT)].into_iter().collect();
+        let header = header(1, 1, address(1));
+
+        let changes = engine
+            .finalize(
+                &header,
+                &[],
+                Revision::London,
+                &mut InMemoryState::default(),
+            )
+            .unwrap();
+        let data = match &changes[..] {
+            [FinalizationChange::SystemCall { contract, data }] if *contract == CONTRACT => {
+                data.clone()
+            }
+            other => panic!("unexpected changes {:?}", other),
+        };
+        assert_eq!(abi_addresses(&data[4..], 0).unwrap(), vec![address(1)]);
+
+        // (address[] receivers, uint256[] rewards)
+        let mut output = vec![0; 32 * 8];
+        output[31] = 0x40;
+        output[63] = 0xa0;
+        output[95] = 2;
+        output[108..128].copy_from_slice(address(1).as_bytes());
+        output[140..160].copy_from_slice(address(2).as_bytes());
+        output[191] = 2;
+        output[223] = 10;
+        output[255] = 20;
+
+        assert_eq!(
+            engine
+                .on_system_call(&header, CONTRACT, &data, output.into())
+                .unwrap()
+                .into_iter()
+                .map(|change| match change {
+                    FinalizationChange::Reward {
+                        address, amount, ..
+                    } => (address, amount),
+                    other => panic!("unexpected change {:?}", other),
+                })
+                .collect::<Vec<_>>(),
+            vec![
+                (address(1), U256::from(10_u8)),
+                (address(2), U256::from(20_u8))
+            ]
+        );
+    }
+}
@@ -3,10 +3,55 @@ use crate::{chain::protocol_param::param, models::*, state::*};
 use anyhow::Context;
 use std::time::SystemTime;
 
+// https://eips.ethereum.org/EIPS/eip-1559
+pub fn expected_base_fee_per_gas(
+    eip1559_block: Option<BlockNumber>,
+    number: BlockNumber,
+    parent: &BlockHeader,
+) -> Option<U256> {
+    if let Some(fork_block) = eip1559_block {
+        if number >= fork_block {
+            if number == fork_block {
+                return Some(param::INITIAL_BASE_FEE.into());
+            }
+
+            let parent_gas_target = parent.gas_limit / param::ELASTICITY_MULTIPLIER;
+
+            // A parent without base fee past the fork is invalid itself, report the mismatch.
+            let parent_base_fee_per_gas = parent.base_fee_per_gas?;
+
+            if parent.gas_used == parent_gas_target {
+                return Some(parent_base_fee_per_gas);
+            }
+
+            if parent.gas_used > parent_gas_target {
+                let gas_used_delta = parent.gas_used - parent_gas_target;
+                let base_fee_per_gas_delta = std::cmp::max(
+                    U256::ONE,
+                    parent_base_fee_per_gas * U256::from(gas_used_delta)
+                        / U256::from(parent_gas_target)
+                        / U256::from(param::BASE_FEE_MAX_CHANGE_DENOMINATOR),
+                );
+                return Some(parent_base_fee_per_gas + base_fee_per_gas_delta);
+            } else {
+                let gas_used_delta = parent_gas_target - parent.gas_used;
+                let base_fee_per_gas_delta = parent_base_fee_per_gas * U256::from(gas_used_delta)
+                    / U256::from(parent_gas_target)
+                    / U256::from(param::BASE_FEE_MAX_CHANGE_DENOMINATOR);
+
+                return Some(parent_base_fee_per_gas.saturating_sub(base_fee_per_gas_delta));
+            }
+        }
+    }
+
+    None
+}
+
 #[derive(Debug)]
 pub struct ConsensusEngineBase {
     chain_id: ChainId,
     eip1559_block: Option<BlockNumber>,
+    sealed_extra_data: bool,
 }
 
 impl ConsensusEngineBase {
@@ -14,9 +59,17 @@ impl ConsensusEngineBase {
         Self {
             chain_id,
             eip1559_block,
+            sealed_extra_data: false,
         }
     }
 
+    /// For engines that keep the seal in the extra data, past the 32 bytes of vanity, and check
+    /// its layout themselves.
+    pub fn with_sealed_extra_data(mut self) -> Self {
+        self.sealed_extra_data = true;
+        self
+    }
+
     pub fn validate_block_header(
         &self,
         header: &BlockHeader,
@@ -54,7 +107,7 @@ impl ConsensusEngineBase {
             return Err(ValidationError::InvalidGasLimit.into());
         }
 
-        if header.extra_data.len() > 32 {
+        if !self.sealed_extra_data && header.extra_data.len() > 32 {
             return Err(ValidationError::ExtraDataTooLong.into());
         }
 
@@ -82,7 +135,8 @@ impl ConsensusEngineBase {
             return Err(ValidationError::InvalidGasLimit.into());
         }
 
-        let expected_base_fee_per_gas = self.expected_base_fee_per_gas(header, parent);
+        let expected_base_fee_per_gas =
+            expected_base_fee_per_gas(self.eip1559_block, header.number, parent);
         if header.base_fee_per_gas != expected_base_fee_per_gas {
             return Err(ValidationError::WrongBaseFee {
                 expected: expected_base_fee_per_gas,
@@ -149,82 +203,42 @@ impl ConsensusEngineBase {
         header.beneficiary
     }
 
-    // https://eips.ethereum.org/EIPS/eip-1559
-    fn expected_base_fee_per_gas(
-        &self,
-        header: &BlockHeader,
-        parent: &BlockHeader,
-    ) -> Option<U256> {
-        if let Some(fork_block) = self.eip1559_block {
-            if header.number >= fork_block {
-                if header.number == fork_block {
-                    return Some(param::INITIAL_BASE_FEE.into());
-                }
-
-                let parent_gas_target = parent.gas_limit / param::ELASTICITY_MULTIPLIER;
-
-                let parent_base_fee_per_gas = parent.base_fee_per_gas.unwrap();
-
-                if parent.gas_used == parent_gas_target {
-                    return Some(parent_base_fee_per_gas);
-                }
+    pub fn pre_validate_block(&self, block: &Block, state: &mut dyn State) -> anyhow::Result<()> {
+        pre_validate_body(&block.header, &block.transactions, &block.ommers)?;
 
-                if parent.gas_used > parent_gas_target {
-                    let gas_used_delta = parent.gas_used - parent_gas_target;
-                    let base_fee_per_gas_delta = std::cmp::max(
-                        U256::ONE,
-                        parent_base_fee_per_gas * U256::from(gas_used_delta)
-                            / U256::from(parent_gas_target)
-                            / U256::from(param::BASE_FEE_MAX_CHANGE_DENOMINATOR),
-                    );
-                    return Some(parent_base_fee_per_gas + base_fee_per_gas_delta);
-                } else {
-                    let gas_used_delta = parent_gas_target - parent.gas_used;
-                    let base_fee_per_gas_delta = parent_base_fee_per_gas
-                        * U256::from(gas_used_delta)
-                        / U256::from(parent_gas_target)
-                        / U256::from(param::BASE_FEE_MAX_CHANGE_DENOMINATOR);
+        self.validate_ommers(&block.header, &block.ommers, state)?;
 
-                    return Some(parent_base_fee_per_gas.saturating_sub(base_fee_per_gas_delta));
-                }
-            }
+        for txn in &block.transactions {
+            pre_validate_transaction(txn, self.chain_id, block.header.base_fee_per_gas)?;
         }
 
-        None
+        Ok(())
     }
 
-    pub fn pre_validate_block(&self, block: &Block, state: &mut dyn State) -> anyhow::Result<()> {
-        let expected_ommers_hash = Block::ommers_hash(&block.ommers);
-        if block.header.ommers_hash != expected_ommers_hash {
-            return Err(ValidationError::WrongOmmersHash {
-                expected: expected_ommers_hash,
-                got: block.header.ommers_hash,
-            }
-            .into());
-        }
-
-        let expected_transactions_root = Block::transactions_root(&block.transactions);
-        if block.header.transactions_root != expected_transactions_root {
-            return Err(ValidationError::WrongTransactionsRoot {
-                expected: expected_transactions_root,
-                got: block.header.transactions_root,
-            }
-            .into());
-        }
-
-        if block.ommers.len() > 2 {
+    /// See [YP] Section 11.1 "Ommer Validation".
+    pub fn validate_ommers(
+        &self,
+        header: &BlockHeader,
+        ommers: &[BlockHeader],
+        state: &mut dyn State,
+    ) -> anyhow::Result<()> {
+        if ommers.len() > 2 {
             return Err(ValidationError::TooManyOmmers.into());
         }
 
-        if block.ommers.len() == 2 && block.ommers[0] == block.ommers[1] {
+        if ommers.len() == 2 && ommers[0] == ommers[1] {
             return Err(ValidationError::DuplicateOmmer.into());
         }
 
+        if ommers.is_empty() {
+            return Ok(());
+        }
+
         let parent = self
-            .get_parent_header(state, &block.header)?
+            .get_parent_header(state, header)?
             .ok_or(ValidationError::UnknownParent)?;
 
-        for ommer in &block.ommers {
+        for ommer in ommers {
             let ommer_parent = self
                 .get_parent_header(state, ommer)?
                 .ok_or(ValidationError::UnknownParent)?;
@@ -235,7 +249,7 @@ impl ConsensusEngineBase {
             if !self.is_kin(
                 ommer,
                 &parent,
-                block.header.parent_hash,
+                header.parent_hash,
                 6,
                 state,
                 &mut old_ommers,
@@ -249,10 +263,6 @@ impl ConsensusEngineBase {
             }
         }
 
-        for txn in &block.transactions {
-            pre_validate_transaction(txn, self.chain_id, block.header.base_fee_per_gas)?;
-        }
-
         Ok(())
     }
 }
@@ -262,6 +272,26 @@ mod tests {
     use super::*;
     use crate::res::chainspec::MAINNET;
 
+    #[test]
+    fn body_must_match_header() {
+        let ommer = BlockHeader {
+            number: BlockNumber(1),
+            ..BlockHeader::empty()
+        };
+        let header = BlockHeader {
+            number: BlockNumber(2),
+            ommers_hash: Block::ommers_hash(&[ommer.clone()]),
+            transactions_root: EMPTY_ROOT,
+            ..BlockHeader::empty()
+        };
+
+        pre_validate_body(&header, &[], &[ommer]).unwrap();
+        assert!(matches!(
+            pre_validate_body(&header, &[], &[]),
+            Err(ValidationError::WrongOmmersHash { .. })
+        ));
+    }
+
     #[test]
     fn validate_max_fee_per_gas() {
         let base_fee_per_gas = 1_000_000_000_u64;
@@ -317,4 +347,76 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn validate_header_fields_against_parent() {
+        let engine =
+            ConsensusEngineBase::new(MAINNET.params.chain_id, MAINNET.consensus.eip1559_block);
+
+        let parent = BlockHeader::new(
+            PartialHeader {
+                number: BlockNumber(1_000_000),
+                gas_limit: 8_000_000,
+                timestamp: 1_000,
+                ..PartialHeader::empty()
+            },
+            EMPTY_LIST_HASH,
+            EMPTY_ROOT,
+        );
+        let child = BlockHeader::new(
+            PartialHeader {
+                number: BlockNumber(1_000_001),
+                gas_limit: 8_000_000 + 8_000_000 / 1024 - 1,
+                timestamp: 1_013,
+                ..PartialHeader::empty()
+            },
+            EMPTY_LIST_HASH,
+            EMPTY_ROOT,
+        );
+        engine
+            .validate_block_header(&child, &parent, false)
+            .unwrap();
+
+        let mut bad = child.clone();
+        bad.gas_limit = 8_000_000 + 8_000_000 / 1024;
+        assert_eq!(
+            engine
+                .validate_block_header(&bad, &parent, false)
+                .unwrap_err()
+                .downcast::<ValidationError>()
+                .unwrap(),
+            ValidationError::InvalidGasLimit
+        );
+
+        let mut bad = child.clone();
+        bad.extra_data = vec![0; 33].into();
+        assert_eq!(
+            engine
+                .validate_block_header(&bad, &parent, false)
+                .unwrap_err()
+                .downcast::<ValidationError>()
+                .unwrap(),
+            ValidationError::ExtraDataTooLong
+        );
+
+        let mut bad = child.clone();
+        bad.timestamp = parent.timestamp;
+        assert!(engine.validate_block_header(&bad, &parent, false).is_err());
+
+        // Post-London child of a parent that has no base fee.
+        let london = MAINNET.consensus.eip1559_block.unwrap();
+        let mut parent = parent;
+        parent.number = london;
+        let mut bad = child;
+        bad.number = london + 1;
+        bad.base_fee_per_gas = Some(U256::from(1_000_000_000_u64));
+        assert!(matches!(
+            engine
+                .validate_block_header(&bad, &parent, false)
+                .unwrap_err()
+                .downcast::<ValidationError>()
+                .unwrap(),
+            ValidationError::WrongBaseFee { expected: None, .. }
+        ));
+    }
 }
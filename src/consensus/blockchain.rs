@@ -130,6 +130,7 @@ impl<'state> Blockchain<'state> {
         let body = BlockBodyWithSenders {
             transactions: block.transactions.clone(),
             ommers: block.ommers.clone(),
+            rollup_cost_data: vec![],
         };
 
         let block_spec = self.config.collect_block_spec(block.header.number);
@@ -0,0 +1,495 @@
+//! Bor, the proof-of-stake engine of Polygon PoS.
+//!
+//! Validators are staked on Ethereum, and Heimdall elects the producers of each span of blocks
+//! among them. Producers take turns to sign sprints of blocks, and the header at the end of a
+//! sprint lists the producers of the next one in its extra data. At the start of a sprint, the
+//! block commits the next span to the validator contract when the current one is about to end,
+//! and the state-sync events relayed by Heimdall to the state receiver contract, as system calls
+//! whose failures are ignored.
+//!
+//! Spans and events are fetched from Heimdall by the `BorHeimdall` stage ahead of execution,
+//! and read back through [`State::read_bor_span`] and [`State::read_bor_events`].
+//!
+//! Proposer priorities within a span are not tracked, so only the bounds of the difficulty are
+//! checked rather than the turn of the signer. Polygon-specific execution rules, such as fees
+//! paid to the signer rather than the coinbase, fee transfer logs, the burnt base fee contract
+//! and the base fee change denominator of Delhi, are not applied.
+use super::{base::ConsensusEngineBase, *};
+use crate::crypto::{keccak256, pubkey_to_address};
+use anyhow::{ensure, format_err};
+use rlp::RlpStream;
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    Message, SECP256K1,
+};
+use std::collections::BTreeMap;
+
+/// `commitSpan(uint256,uint256,uint256,bytes,bytes)`
+const COMMIT_SPAN: [u8; 4] = hex!("23c2a2b4");
+/// `commitState(uint256,bytes)`
+const COMMIT_STATE: [u8; 4] = hex!("19494a17");
+
+const EXTRA_VANITY_LENGTH: usize = 32;
+const EXTRA_SEAL_LENGTH: usize = 65;
+/// Address and 20 byte big-endian voting power of each producer listed at the end of a sprint.
+const VALIDATOR_HEADER_LENGTH: usize = 40;
+
+/// Length of the sprint that `block_number` belongs to.
+pub fn bor_sprint_length(
+    sprint: &BTreeMap<BlockNumber, u64>,
+    block_number: BlockNumber,
+) -> anyhow::Result<u64> {
+    sprint
+        .range(..=block_number)
+        .next_back()
+        .map(|(_, &length)| length)
+        .filter(|&length| length > 0)
+        .ok_or_else(|| format_err!("No sprint length for block {}", block_number))
+}
+
+/// Hash signed by the producer: the header without the seal at the end of its extra data.
+fn seal_hash(header: &BlockHeader) -> H256 {
+    let extra_data = &header.extra_data[..header.extra_data.len() - EXTRA_SEAL_LENGTH];
+
+    let mut s = RlpStream::new();
+    s.begin_list(if header.base_fee_per_gas.is_some() {
+        16
+    } else {
+        15
+    });
+    s.append(&header.parent_hash);
+    s.append(&header.ommers_hash);
+    s.append(&header.beneficiary);
+    s.append(&header.state_root);
+    s.append(&header.transactions_root);
+    s.append(&header.receipts_root);
+    s.append(&header.logs_bloom);
+    s.append(&header.difficulty);
+    s.append(&header.number);
+    s.append(&header.gas_limit);
+    s.append(&header.gas_used);
+    s.append(&header.timestamp);
+    s.append(&extra_data);
+    s.append(&header.mix_hash);
+    s.append(&header.nonce);
+    if let Some(base_fee_per_gas) = header.base_fee_per_gas {
+        s.append(&base_fee_per_gas);
+    }
+
+    keccak256(s.out())
+}
+
+/// Producer that signed the block.
+pub fn bor_signer(header: &BlockHeader) -> anyhow::Result<Address> {
+    if header.extra_data.len() < EXTRA_VANITY_LENGTH + EXTRA_SEAL_LENGTH {
+        return Err(ValidationError::InvalidSeal.into());
+    }
+
+    let signature = &header.extra_data[header.extra_data.len() - EXTRA_SEAL_LENGTH..];
+    let public = SECP256K1.recover_ecdsa(
+        &Message::from_slice(seal_hash(header).as_bytes())?,
+        &RecoverableSignature::from_compact(
+            &signature[..64],
+            RecoveryId::from_i32(signature[64].into())?,
+        )?,
+    )?;
+
+    Ok(pubkey_to_address(&public))
+}
+
+/// Producers as listed in the header at the end of a sprint, sorted by address.
+fn validator_header_bytes(producers: &[BorValidator]) -> Vec<u8> {
+    let mut producers = producers.iter().collect::<Vec<_>>();
+    producers.sort_by_key(|producer| producer.address);
+
+    producers
+        .into_iter()
+        .flat_map(|producer| {
+            let mut entry = [0; VALIDATOR_HEADER_LENGTH];
+            entry[..20].copy_from_slice(producer.address.as_bytes());
+            entry[32..].copy_from_slice(&producer.voting_power.to_be_bytes());
+            entry
+        })
+        .collect()
+}
+
+/// Call data with `uint256` arguments followed by `bytes` arguments.
+fn abi_call(selector: [u8; 4], words: &[u64], bytes: &[&[u8]]) -> Bytes {
+    let word = |v: u64| {
+        let mut word = [0; 32];
+        word[24..].copy_from_slice(&v.to_be_bytes());
+        word
+    };
+
+    let mut head = selector.to_vec();
+    let mut tail = Vec::new();
+    for &v in words {
+        head.extend_from_slice(&word(v));
+    }
+    for data in bytes {
+        head.extend_from_slice(&word(
+            (32 * (words.len() + bytes.len()) + tail.len()) as u64,
+        ));
+        tail.extend_from_slice(&word(data.len() as u64));
+        tail.extend_from_slice(data);
+        tail.resize((tail.len() + 31) / 32 * 32, 0);
+    }
+
+    head.into_iter().chain(tail).collect::<Vec<u8>>().into()
+}
+
+fn commit_span_call(span: &BorSpan) -> Bytes {
+    abi_call(
+        COMMIT_SPAN,
+        &[span.id, span.start_block.0, span.end_block.0],
+        &[
+            &rlp::encode_list(&span.validators)[..],
+            &rlp::encode_list(&span.selected_producers)[..],
+        ],
+    )
+}
+
+fn commit_state_call(event: &StateSyncEvent) -> Bytes {
+    abi_call(COMMIT_STATE, &[event.time], &[&rlp::encode(event)[..]])
+}
+
+#[derive(Debug)]
+pub struct Bor {
+    base: ConsensusEngineBase,
+    sprint: BTreeMap<BlockNumber, u64>,
+    validator_contract: Address,
+    state_receiver_contract: Address,
+}
+
+impl Bor {
+    pub fn new(
+        chain_id: ChainId,
+        eip1559_block: Option<BlockNumber>,
+        sprint: BTreeMap<BlockNumber, u64>,
+        validator_contract: Address,
+        state_receiver_contract: Address,
+    ) -> Self {
+        Self {
+            base: ConsensusEngineBase::new(chain_id, eip1559_block).with_sealed_extra_data(),
+            sprint,
+            validator_contract,
+            state_receiver_contract,
+        }
+    }
+
+    fn span(state: &dyn State, block_number: BlockNumber) -> anyhow::Result<BorSpan> {
+        state.read_bor_span(block_number)?.ok_or_else(|| {
+            format_err!(
+                "Span of block {} not fetched from Heimdall yet",
+                block_number
+            )
+        })
+    }
+}
+
+impl Consensus for Bor {
+    fn pre_validate_block(&self, block: &Block, state: &mut dyn State) -> anyhow::Result<()> {
+        self.base.pre_validate_block(block, state)?;
+        self.validate_ommers(&block.header, &block.ommers, state)
+    }
+
+    fn validate_block_header(
+        &self,
+        header: &BlockHeader,
+        state: &mut dyn State,
+        with_future_timestamp_check: bool,
+    ) -> anyhow::Result<()> {
+        let parent = self
+            .base
+            .get_parent_header(state, header)?
+            .ok_or(ValidationError::UnknownParent)?;
+
+        self.base
+            .validate_block_header(header, &parent, with_future_timestamp_check)?;
+
+        if header.mix_hash != H256::zero() || header.ommers_hash != EMPTY_LIST_HASH {
+            return Err(ValidationError::InvalidSeal.into());
+        }
+
+        let signer = bor_signer(header)?;
+        let span = Self::span(state, header.number)?;
+        if !span
+            .selected_producers
+            .iter()
+            .any(|producer| producer.address == signer)
+        {
+            return Err(ValidationError::UnauthorizedSigner { signer }.into());
+        }
+
+        // The signer in turn signs with the number of producers as difficulty, and the others
+        // with less, the further they are from their turn.
+        if header.difficulty == U256::ZERO
+            || header.difficulty > U256::from(span.selected_producers.len() as u64)
+        {
+            return Err(ValidationError::WrongDifficulty.into());
+        }
+
+        let validators =
+            &header.extra_data[EXTRA_VANITY_LENGTH..header.extra_data.len() - EXTRA_SEAL_LENGTH];
+        let sprint = bor_sprint_length(&self.sprint, header.number)?;
+        if (header.number.0 + 1) % sprint == 0 {
+            let next_span = Self::span(state, header.number + 1)?;
+            if validators != validator_header_bytes(&next_span.selected_producers) {
+                return Err(ValidationError::InvalidSeal.into());
+            }
+        } else if !validators.is_empty() {
+            return Err(ValidationError::InvalidSeal.into());
+        }
+
+        Ok(())
+    }
+
+    fn validate_ommers(
+        &self,
+        _: &BlockHeader,
+        ommers: &[BlockHeader],
+        _: &mut dyn State,
+    ) -> anyhow::Result<()> {
+        if !ommers.is_empty() {
+            return Err(ValidationError::TooManyOmmers.into());
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the seal is well-formed. Whether the signer may produce the block is checked
+    /// along with the header, against the span.
+    fn validate_seal(&self, header: &BlockHeader) -> anyhow::Result<()> {
+        let validators = header
+            .extra_data
+            .len()
+            .checked_sub(EXTRA_VANITY_LENGTH + EXTRA_SEAL_LENGTH)
+            .ok_or(ValidationError::InvalidSeal)?;
+        ensure!(
+            validators % VALIDATOR_HEADER_LENGTH == 0,
+            ValidationError::InvalidSeal
+        );
+        bor_signer(header)?;
+
+        Ok(())
+    }
+
+    /// At the start of each sprint, commits the next span if the current one ends within the
+    /// sprint, then the state-sync events of the sprint.
+    fn finalize(
+        &self,
+        header: &PartialHeader,
+        _: &[BlockHeader],
+        _: Revision,
+        state: &mut dyn State,
+    ) -> anyhow::Result<Vec<FinalizationChange>> {
+        let sprint = bor_sprint_length(&self.sprint, header.number)?;
+        if header.number.0 == 0 || header.number.0 % sprint != 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut changes = Vec::new();
+
+        let span = Self::span(state, header.number)?;
+        if span.end_block.0 > sprint && span.end_block.0 - sprint + 1 == header.number.0 {
+            let next_span = Self::span(state, span.end_block + 1)?;
+            changes.push(FinalizationChange::SystemCall {
+                contract: self.validator_contract,
+                data: commit_span_call(&next_span),
+            });
+        }
+
+        for event in state.read_bor_events(header.number)? {
+            changes.push(FinalizationChange::SystemCall {
+                contract: self.state_receiver_contract,
+                data: commit_state_call(&event),
+            });
+        }
+
+        Ok(changes)
+    }
+
+    fn ignores_failed_system_calls(&self) -> bool {
+        true
+    }
+
+    fn get_beneficiary(&self, header: &BlockHeader) -> anyhow::Result<Address> {
+        bor_signer(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::to_pubkey,
+        kv::{new_mem_database, tables},
+        state::Buffer,
+    };
+    use secp256k1::SecretKey;
+
+    const VALIDATOR_CONTRACT: Address = H160(hex!("0000000000000000000000000000000000001000"));
+    const STATE_RECEIVER: Address = H160(hex!("0000000000000000000000000000000000001001"));
+
+    fn key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn address(byte: u8) -> Address {
+        pubkey_to_address(&to_pubkey(&key(byte)))
+    }
+
+    fn bor() -> Bor {
+        Bor::new(
+            ChainId(137),
+            None,
+            [(BlockNumber(0), 4)].into_iter().collect(),
+            VALIDATOR_CONTRACT,
+            STATE_RECEIVER,
+        )
+    }
+
+    fn span(id: u64, start_block: u64, end_block: u64, producers: &[u8]) -> BorSpan {
+        let validators = producers
+            .iter()
+            .map(|&byte| BorValidator {
+                id: byte.into(),
+                address: address(byte),
+                voting_power: 10,
+            })
+            .collect::<Vec<_>>();
+
+        BorSpan {
+            id,
+            start_block: BlockNumber(start_block),
+            end_block: BlockNumber(end_block),
+            validators: validators.clone(),
+            selected_producers: validators,
+        }
+    }
+
+    fn sign(header: &mut BlockHeader, key: &SecretKey) {
+        let hash = seal_hash(header);
+        let (recovery_id, signature) = SECP256K1
+            .sign_ecdsa_recoverable(&Message::from_slice(hash.as_bytes()).unwrap(), key)
+            .serialize_compact();
+
+        let mut extra_data = header.extra_data.to_vec();
+        let seal_start = extra_data.len() - EXTRA_SEAL_LENGTH;
+        extra_data[seal_start..seal_start + 64].copy_from_slice(&signature);
+        extra_data[seal_start + 64] = recovery_id.to_i32() as u8;
+        header.extra_data = extra_data.into();
+    }
+
+    #[test]
+    fn seal() {
+        let mut header = BlockHeader {
+            number: BlockNumber(5),
+            extra_data: vec![0; EXTRA_VANITY_LENGTH + EXTRA_SEAL_LENGTH].into(),
+            ..BlockHeader::empty()
+        };
+        sign(&mut header, &key(1));
+
+        let engine = bor();
+        engine.validate_seal(&header).unwrap();
+        assert_eq!(bor_signer(&header).unwrap(), address(1));
+        assert_eq!(engine.get_beneficiary(&header).unwrap(), address(1));
+
+        header.gas_used = 1;
+        assert_ne!(bor_signer(&header).unwrap(), address(1));
+
+        header.extra_data = vec![0; EXTRA_VANITY_LENGTH + EXTRA_SEAL_LENGTH + 20].into();
+        assert!(engine.validate_seal(&header).is_err());
+    }
+
+    #[test]
+    fn validator_bytes() {
+        let span = span(1, 0, 15, &[2, 1]);
+        let bytes = validator_header_bytes(&span.selected_producers);
+
+        let mut addresses = vec![address(1), address(2)];
+        addresses.sort();
+        assert_eq!(bytes.len(), 2 * VALIDATOR_HEADER_LENGTH);
+        for (entry, address) in bytes.chunks(VALIDATOR_HEADER_LENGTH).zip(addresses) {
+            assert_eq!(&entry[..20], address.as_bytes());
+            assert_eq!(entry[39], 10);
+        }
+    }
+
+    #[test]
+    fn sprint_commits() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        tx.set(tables::BorSpan, BlockNumber(0), span(0, 0, 11, &[1]))
+            .unwrap();
+        tx.set(tables::BorSpan, BlockNumber(12), span(1, 12, 23, &[1, 2]))
+            .unwrap();
+        let event = StateSyncEvent {
+            id: 1,
+            contract: Address::repeat_byte(0xcc),
+            data: vec![0xab; 40].into(),
+            tx_hash: H256::repeat_byte(0xdd),
+            log_index: 3,
+            chain_id: "137".into(),
+            time: 1_600_000_000,
+        };
+        tx.set(
+            tables::BorStateSyncEvents,
+            BlockNumber(8),
+            vec![event.clone()],
+        )
+        .unwrap();
+        let mut state = Buffer::new(&tx, BlockNumber(0), None);
+
+        let engine = bor();
+        let calls = |number: u64, state: &mut dyn State| {
+            engine
+                .finalize(
+                    &PartialHeader {
+                        number: BlockNumber(number),
+                        ..PartialHeader::empty()
+                    },
+                    &[],
+                    Revision::London,
+                    state,
+                )
+                .unwrap()
+                .into_iter()
+                .map(|change| match change {
+                    FinalizationChange::SystemCall { contract, data } => (contract, data),
+                    other => panic!("unexpected change {:?}", other),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Not the start of a sprint.
+        assert!(calls(5, &mut state).is_empty());
+        // Start of a sprint, with nothing to commit.
+        assert!(calls(4, &mut state).is_empty());
+
+        // The last sprint of span 0 commits span 1, then the events.
+        let calls = calls(8, &mut state);
+        assert_eq!(calls.len(), 2);
+
+        let (contract, data) = &calls[0];
+        assert_eq!(*contract, VALIDATOR_CONTRACT);
+        assert_eq!(data[..4], COMMIT_SPAN);
+        assert_eq!(data[4 + 31], 1);
+        assert_eq!(data[4 + 63], 12);
+        assert_eq!(data[4 + 95], 23);
+
+        let (contract, data) = &calls[1];
+        assert_eq!(*contract, STATE_RECEIVER);
+        let record = rlp::encode(&event);
+        let mut expected = COMMIT_STATE.to_vec();
+        expected.extend_from_slice(&[0; 24]);
+        expected.extend_from_slice(&event.time.to_be_bytes());
+        expected.extend_from_slice(&[0; 31]);
+        expected.push(0x40);
+        expected.extend_from_slice(&[0; 24]);
+        expected.extend_from_slice(&(record.len() as u64).to_be_bytes());
+        expected.extend_from_slice(&record);
+        expected.resize(4 + 32 * 3 + (record.len() + 31) / 32 * 32, 0);
+        assert_eq!(data[..], expected[..]);
+    }
+}
@@ -2,10 +2,41 @@ use self::difficulty::BlockDifficultyBombData;
 use super::{base::ConsensusEngineBase, *};
 use crate::{chain::protocol_param::param, h256_to_u256};
 use ::ethash::LightDAG;
-use std::collections::BTreeMap;
+use anyhow::Context;
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
 
 pub mod difficulty;
 
+const EPOCH_LENGTH: u64 = 30_000;
+
+/// Light DAG of the most recently verified epoch, so that consecutive seal checks don't rebuild it.
+#[derive(Default)]
+struct LightDagCache(Mutex<Option<(u64, Arc<LightDAG>)>>);
+
+impl LightDagCache {
+    fn get(&self, block_number: BlockNumber) -> Arc<LightDAG> {
+        let epoch = block_number.0 / EPOCH_LENGTH;
+        let mut cache = self.0.lock();
+        match &*cache {
+            Some((cached_epoch, dag)) if *cached_epoch == epoch => dag.clone(),
+            _ => {
+                let dag = Arc::new(LightDAG::new(block_number.0.into()));
+                *cache = Some((epoch, dag.clone()));
+                dag
+            }
+        }
+    }
+}
+
+impl Debug for LightDagCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("LightDagCache")
+            .field(&self.0.lock().as_ref().map(|(epoch, _)| *epoch))
+            .finish()
+    }
+}
+
 #[derive(Debug)]
 pub struct Ethash {
     base: ConsensusEngineBase,
@@ -14,7 +45,9 @@ pub struct Ethash {
     homestead_formula: Option<BlockNumber>,
     byzantium_formula: Option<BlockNumber>,
     difficulty_bomb: Option<DifficultyBomb>,
+    ecip1017_era_rounds: Option<u64>,
     skip_pow_verification: bool,
+    light_dag_cache: LightDagCache,
 }
 
 impl Ethash {
@@ -27,6 +60,7 @@ impl Ethash {
         homestead_formula: Option<BlockNumber>,
         byzantium_formula: Option<BlockNumber>,
         difficulty_bomb: Option<DifficultyBomb>,
+        ecip1017_era_rounds: Option<u64>,
         skip_pow_verification: bool,
     ) -> Self {
         Self {
@@ -36,14 +70,101 @@ impl Ethash {
             homestead_formula,
             byzantium_formula,
             difficulty_bomb,
+            ecip1017_era_rounds,
             skip_pow_verification,
+            light_dag_cache: Default::default(),
+        }
+    }
+
+    /// Difficulty and seal checks of the ommers, on top of the engine-agnostic ones.
+    fn validate_ommer_seals(
+        &self,
+        ommers: &[BlockHeader],
+        state: &mut dyn State,
+    ) -> anyhow::Result<()> {
+        for ommer in ommers {
+            self.validate_block_header(ommer, state, false)
+                .context(ValidationError::InvalidOmmerHeader)?;
+            self.validate_seal(ommer)
+                .context(ValidationError::InvalidOmmerHeader)?;
+        }
+
+        Ok(())
+    }
+
+    /// ECIP-1017 era of the block, zero-based.
+    fn era(&self, block_number: BlockNumber) -> u64 {
+        self.ecip1017_era_rounds
+            .map(|rounds| block_number.0.saturating_sub(1) / rounds)
+            .unwrap_or(0)
+    }
+
+    fn block_reward(&self, block_number: BlockNumber, revision: Revision) -> U256 {
+        let block_reward = self
+            .block_reward
+            .range(..=block_number)
+            .next_back()
+            .map(|(_, &reward)| reward)
+            .unwrap_or_else(|| {
+                U256::from(if revision >= Revision::Constantinople {
+                    param::BLOCK_REWARD_CONSTANTINOPLE
+                } else if revision >= Revision::Byzantium {
+                    param::BLOCK_REWARD_BYZANTIUM
+                } else {
+                    param::BLOCK_REWARD_FRONTIER
+                })
+            });
+
+        ecip1017_reward(block_reward, self.era(block_number))
+    }
+}
+
+/// ECIP-1017: `reward` reduced by 20% for each era.
+fn ecip1017_reward(reward: U256, era: u64) -> U256 {
+    // Multiplied by 4^era and divided by 5^era, rounding down once like other clients, for as
+    // many eras as fit in 512 bits, which is every era before any reward up to 2^70 wei reaches
+    // zero. Past that each era takes 4/5 of the rounded down reward of the one before.
+    let mut numerator = U512::from(ethereum_types::U256::from(reward.to_be_bytes()));
+    let mut denominator = U512::one();
+    for _ in 0..era {
+        if numerator < denominator {
+            return U256::ZERO;
+        }
+
+        match (
+            numerator.checked_mul(U512::from(4)),
+            denominator.checked_mul(U512::from(5)),
+        ) {
+            (Some(n), Some(d)) => {
+                numerator = n;
+                denominator = d;
+            }
+            _ => {
+                numerator = numerator / denominator * 4 / 5;
+                denominator = U512::one();
+            }
         }
     }
+
+    let mut out = [0; 64];
+    (numerator / denominator).to_big_endian(&mut out);
+    U256::from_be_bytes(out[32..].try_into().unwrap())
 }
 
 impl Consensus for Ethash {
     fn pre_validate_block(&self, block: &Block, state: &mut dyn State) -> anyhow::Result<()> {
-        self.base.pre_validate_block(block, state)
+        self.base.pre_validate_block(block, state)?;
+        self.validate_ommer_seals(&block.ommers, state)
+    }
+
+    fn validate_ommers(
+        &self,
+        header: &BlockHeader,
+        ommers: &[BlockHeader],
+        state: &mut dyn State,
+    ) -> anyhow::Result<()> {
+        self.base.validate_ommers(header, ommers, state)?;
+        self.validate_ommer_seals(ommers, state)
     }
 
     fn validate_block_header(
@@ -83,8 +204,7 @@ impl Consensus for Ethash {
     }
     fn validate_seal(&self, header: &BlockHeader) -> anyhow::Result<()> {
         if !self.skip_pow_verification {
-            type Dag = LightDAG;
-            let light_dag = Dag::new(header.number.0.into());
+            let light_dag = self.light_dag_cache.get(header.number);
             let (mixh, final_hash) = light_dag.hashimoto(header.truncated_hash(), header.nonce);
 
             if mixh != header.mix_hash {
@@ -102,33 +222,33 @@ impl Consensus for Ethash {
         header: &PartialHeader,
         ommers: &[BlockHeader],
         revision: Revision,
+        _: &mut dyn State,
     ) -> anyhow::Result<Vec<FinalizationChange>> {
         let mut changes = Vec::with_capacity(1 + ommers.len());
-        let block_reward = {
-            if revision >= Revision::Constantinople {
-                param::BLOCK_REWARD_CONSTANTINOPLE
-            } else if revision >= Revision::Byzantium {
-                param::BLOCK_REWARD_BYZANTIUM
-            } else {
-                param::BLOCK_REWARD_FRONTIER
-            }
-        };
-
         let block_number = header.number;
+        let block_reward = self.block_reward(block_number, revision);
+        let era = self.era(block_number);
+
         let mut miner_reward = block_reward;
         for ommer in ommers {
-            let ommer_reward =
-                (U256::from(8 + ommer.number.0 - block_number.0) * block_reward) >> 3;
+            let ommer_reward = if era == 0 {
+                (U256::from(8 + ommer.number.0 - block_number.0) * block_reward) >> 3
+            } else {
+                // ECIP-1017: flat ommer reward after the first era
+                block_reward >> 5
+            };
             changes.push(FinalizationChange::Reward {
                 address: ommer.beneficiary,
                 amount: ommer_reward,
+                kind: RewardKind::Uncle,
             });
             miner_reward += block_reward / 32;
         }
 
         changes.push(FinalizationChange::Reward {
             address: header.beneficiary,
-            amount: miner_reward.into(),
+            amount: miner_reward,
+            kind: RewardKind::Block,
         });
 
         Ok(changes)
@@ -138,3 +258,135 @@ impl Consensus for Ethash {
         Ok(header.beneficiary)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryState;
+    use hex_literal::hex;
+
+    fn ethash(ecip1017_era_rounds: Option<u64>) -> Ethash {
+        Ethash::new(
+            ChainId(61),
+            None,
+            13,
+            [(BlockNumber(0), U256::from(param::BLOCK_REWARD_FRONTIER))]
+                .into_iter()
+                .collect(),
+            None,
+            None,
+            None,
+            ecip1017_era_rounds,
+            true,
+        )
+    }
+
+    fn rewards(engine: &Ethash, number: u64, ommer_number: u64) -> Vec<U256> {
+        let mut header = PartialHeader::empty();
+        header.number = BlockNumber(number);
+        header.beneficiary = hex!("0000000000000000000000000000000000000001").into();
+        let mut ommer = BlockHeader::empty();
+        ommer.number = BlockNumber(ommer_number);
+        ommer.beneficiary = hex!("0000000000000000000000000000000000000002").into();
+
+        engine
+            .finalize(
+                &header,
+                &[ommer],
+                Revision::Frontier,
+                &mut InMemoryState::default(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|change| match change {
+                FinalizationChange::Reward { amount, .. } => amount,
+                other => panic!("unexpected change {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ecip1017_rewards() {
+        let eth = U256::from(ETHER);
+
+        // no eras: regular Ethash rewards
+        assert_eq!(
+            rewards(&ethash(None), 5_000_001, 5_000_000),
+            vec![eth * 35 / 8, eth * 5 + eth * 5 / 32]
+        );
+
+        // first era is not reduced
+        assert_eq!(
+            rewards(&ethash(Some(5_000_000)), 5_000_000, 4_999_999),
+            vec![eth * 35 / 8, eth * 5 + eth * 5 / 32]
+        );
+
+        // second era: 4 ETH block reward, flat ommer reward
+        assert_eq!(
+            rewards(&ethash(Some(5_000_000)), 5_000_001, 5_000_000),
+            vec![eth * 4 / 32, eth * 4 + eth * 4 / 32]
+        );
+
+        // eras far beyond what 4^era and 5^era fit in
+        assert_eq!(
+            rewards(&ethash(Some(1)), 1_000_000, 999_999),
+            vec![U256::ZERO, U256::ZERO]
+        );
+    }
+
+    #[test]
+    fn ecip1017_reward_rounding() {
+        let eth = U256::from(ETHER);
+
+        // rounded down once, not every era
+        assert_eq!(ecip1017_reward(U256::from(2_u64), 2), U256::from(1_u64));
+        assert_eq!(ecip1017_reward(eth * 5, 5), eth * 5 * 1024 / 3125);
+        assert_eq!(ecip1017_reward(U256::MAX, 0), U256::MAX);
+        assert_eq!(
+            ecip1017_reward(U256::MAX, 1),
+            U256::MAX / 5 * 4 + U256::MAX % 5 * 4 / 5
+        );
+        assert_eq!(ecip1017_reward(U256::MAX, 1_000), U256::ZERO);
+    }
+
+    #[test]
+    fn reward_kinds() {
+        let mut header = PartialHeader::empty();
+        header.number = BlockNumber(10);
+        header.beneficiary = hex!("0000000000000000000000000000000000000001").into();
+        let mut ommer = BlockHeader::empty();
+        ommer.number = BlockNumber(9);
+        ommer.beneficiary = hex!("0000000000000000000000000000000000000002").into();
+
+        assert_eq!(
+            ethash(None)
+                .finalize(
+                    &header,
+                    &[ommer.clone(), ommer],
+                    Revision::Frontier,
+                    &mut InMemoryState::default(),
+                )
+                .unwrap()
+                .into_iter()
+                .map(|change| match change {
+                    FinalizationChange::Reward { address, kind, .. } => (address, kind),
+                    other => panic!("unexpected change {:?}", other),
+                })
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    hex!("0000000000000000000000000000000000000002").into(),
+                    RewardKind::Uncle
+                ),
+                (
+                    hex!("0000000000000000000000000000000000000002").into(),
+                    RewardKind::Uncle
+                ),
+                (
+                    hex!("0000000000000000000000000000000000000001").into(),
+                    RewardKind::Block
+                ),
+            ]
+        );
+    }
+}
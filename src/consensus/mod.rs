@@ -1,15 +1,39 @@
+mod aura;
 mod base;
 mod blockchain;
+mod bor;
 mod ethash;
 
-pub use self::{blockchain::*, ethash::*};
+pub use self::{aura::*, base::expected_base_fee_per_gas, blockchain::*, bor::*, ethash::*};
 use crate::{models::*, State};
 use anyhow::bail;
+use bytes::Bytes;
+use hex_literal::hex;
 use std::fmt::{Debug, Display};
 
+/// Sender of [`FinalizationChange::SystemCall`]s.
+pub const SYSTEM_ADDRESS: Address = H160(hex!("fffffffffffffffffffffffffffffffffffffffe"));
+
+/// What a reward is paid for, so that traces can report it the way other clients do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RewardKind {
+    /// Reward of the block's beneficiary, including its share for included ommers.
+    Block,
+    /// Reward of the beneficiary of an included ommer.
+    Uncle,
+}
+
 #[derive(Debug)]
 pub enum FinalizationChange {
-    Reward { address: Address, amount: U256 },
+    Reward {
+        address: Address,
+        amount: U256,
+        kind: RewardKind,
+    },
+    /// Call of a system contract by [`SYSTEM_ADDRESS`], which pays no gas and keeps no nonce.
+    /// Its output is handed back to the engine with [`Consensus::on_system_call`]. A failed call
+    /// invalidates the block, unless [`Consensus::ignores_failed_system_calls`].
+    SystemCall { contract: Address, data: Bytes },
 }
 
 pub trait Consensus: Debug + Send + Sync + 'static {
@@ -29,9 +53,23 @@ pub trait Consensus: Debug + Send + Sync + 'static {
         with_future_timestamp_check: bool,
     ) -> anyhow::Result<()>;
 
+    /// Validates the ommers of a block, including their seals.
+    /// See [YP] Section 11.1 "Ommer Validation".
+    fn validate_ommers(
+        &self,
+        header: &BlockHeader,
+        ommers: &[BlockHeader],
+        state: &mut dyn State,
+    ) -> anyhow::Result<()>;
+
     /// Validates the seal of the header
     fn validate_seal(&self, header: &BlockHeader) -> anyhow::Result<()>;
 
+    /// Changes to apply before executing the block's transactions, e.g. on consensus epoch transitions.
+    fn pre_execution(&self, _header: &PartialHeader) -> anyhow::Result<Vec<FinalizationChange>> {
+        Ok(Vec::new())
+    }
+
     /// Finalizes block execution by applying changes in the state of accounts or of the consensus itself
     ///
     /// NOTE: For Ethash See [YP] Section 11.3 "Reward Application".
@@ -40,8 +78,36 @@ pub trait Consensus: Debug + Send + Sync + 'static {
         block: &PartialHeader,
         ommers: &[BlockHeader],
         revision: Revision,
+        state: &mut dyn State,
     ) -> anyhow::Result<Vec<FinalizationChange>>;
 
+    /// Receives the receipts of the block's transactions, before it is finalized.
+    fn post_execution(
+        &mut self,
+        _header: &PartialHeader,
+        _receipts: &[Receipt],
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Receives the output of a [`FinalizationChange::SystemCall`] made for the engine, and
+    /// returns the changes to apply in turn.
+    fn on_system_call(
+        &mut self,
+        _header: &PartialHeader,
+        _contract: Address,
+        _data: &[u8],
+        _output: Bytes,
+    ) -> anyhow::Result<Vec<FinalizationChange>> {
+        Ok(Vec::new())
+    }
+
+    /// Whether a failed [`FinalizationChange::SystemCall`] is reverted and skipped, rather than
+    /// invalidating the block.
+    fn ignores_failed_system_calls(&self) -> bool {
+        false
+    }
+
     /// See [YP] Section 11.3 "Reward Application".
     fn get_beneficiary(&self, header: &BlockHeader) -> anyhow::Result<Address>;
 }
@@ -95,6 +161,13 @@ pub enum ValidationError {
         got: Option<U256>,
     }, // see EIP-1559
     InvalidSeal,     // Nonce or mix_hash
+    WrongProposer {
+        expected: Address,
+        got: Address,
+    }, // Aura: block not authored by the validator of its step
+    UnauthorizedSigner {
+        signer: Address,
+    }, // Bor: block not signed by a producer of its span
 
     // See [YP] Section 6.2 "Execution", Eq (58)
     MissingSender, // S(T) = ∅
@@ -152,6 +225,11 @@ pub fn pre_validate_transaction(
     canonical_chain_id: ChainId,
     base_fee_per_gas: Option<U256>,
 ) -> Result<(), ValidationError> {
+    // Deposits carry no fees, whether the chain takes them is checked on execution.
+    if txn.is_deposit() {
+        return Ok(());
+    }
+
     if let Some(chain_id) = txn.chain_id() {
         if chain_id != canonical_chain_id {
             return Err(ValidationError::WrongChainId);
@@ -172,6 +250,79 @@ pub fn pre_validate_transaction(
     Ok(())
 }
 
+/// Checks that a body belongs to the header: its transactions and ommers
+/// must hash to the header's transactions root and ommers hash.
+pub fn pre_validate_body(
+    header: &BlockHeader,
+    transactions: &[MessageWithSignature],
+    ommers: &[BlockHeader],
+) -> Result<(), ValidationError> {
+    let expected_ommers_hash = Block::ommers_hash(ommers);
+    if header.ommers_hash != expected_ommers_hash {
+        return Err(ValidationError::WrongOmmersHash {
+            expected: expected_ommers_hash,
+            got: header.ommers_hash,
+        });
+    }
+
+    let expected_transactions_root = Block::transactions_root(transactions);
+    if header.transactions_root != expected_transactions_root {
+        return Err(ValidationError::WrongTransactionsRoot {
+            expected: expected_transactions_root,
+            got: header.transactions_root,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that the receipts of an executed block match its header:
+/// the logs bloom and, from Byzantium on, the receipts root.
+pub fn post_validate_receipts(
+    header: &PartialHeader,
+    revision: Revision,
+    receipts: &[Receipt],
+) -> Result<(), ValidationError> {
+    if revision >= Revision::Byzantium {
+        let expected = receipts_root(receipts);
+        if expected != header.receipts_root {
+            return Err(ValidationError::WrongReceiptsRoot {
+                expected,
+                got: header.receipts_root,
+            });
+        }
+    }
+
+    let expected = receipts_bloom(receipts);
+    if expected != header.logs_bloom {
+        return Err(ValidationError::WrongLogsBloom {
+            expected,
+            got: header.logs_bloom,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks the header fields that only depend on the parent:
+/// gas limit bounds and delta, extra data size, timestamp ordering and EIP-1559 base fee.
+/// See [YP] Section 4.3.4 "Block Header Validity".
+pub fn validate_header_fields(
+    chain_config: &ChainSpec,
+    header: &BlockHeader,
+    parent: &BlockHeader,
+) -> anyhow::Result<()> {
+    let mut base = base::ConsensusEngineBase::new(
+        chain_config.params.chain_id,
+        chain_config.consensus.eip1559_block,
+    );
+    if let SealVerificationParams::Bor { .. } = chain_config.consensus.seal_verification {
+        base = base.with_sealed_extra_data();
+    }
+
+    base.validate_block_header(header, parent, false)
+}
+
 pub fn engine_factory(chain_config: ChainSpec) -> anyhow::Result<Box<dyn Consensus>> {
     Ok(match chain_config.consensus.seal_verification {
         SealVerificationParams::Ethash {
@@ -180,6 +331,7 @@ pub fn engine_factory(chain_config: ChainSpec) -> anyhow::Result<Box<dyn Consens
             homestead_formula,
             byzantium_formula,
             difficulty_bomb,
+            ecip1017_era_rounds,
             skip_pow_verification,
         } => Box::new(Ethash::new(
             chain_config.params.chain_id,
@@ -189,8 +341,34 @@ pub fn engine_factory(chain_config: ChainSpec) -> anyhow::Result<Box<dyn Consens
             homestead_formula,
             byzantium_formula,
             difficulty_bomb,
+            ecip1017_era_rounds,
             skip_pow_verification,
         )),
+        SealVerificationParams::Aura {
+            step_duration,
+            validators,
+            block_reward,
+            block_reward_contract,
+        } => Box::new(Aura::new(
+            chain_config.params.chain_id,
+            chain_config.consensus.eip1559_block,
+            step_duration,
+            validators,
+            block_reward,
+            block_reward_contract,
+        )),
+        SealVerificationParams::Bor {
+            sprint,
+            validator_contract,
+            state_receiver_contract,
+            ..
+        } => Box::new(Bor::new(
+            chain_config.params.chain_id,
+            chain_config.consensus.eip1559_block,
+            sprint,
+            validator_contract,
+            state_receiver_contract,
+        )),
         _ => bail!("unsupported consensus engine"),
     })
 }
@@ -0,0 +1,81 @@
+//! Batched Keccak-256 hashing on a thread pool shared by the hashing stages and header
+//! verification, keeping the bulk of hashing off the async runtime.
+use super::keccak256;
+use ethereum_types::H256;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, IntCounter};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+
+static POOL: Lazy<ThreadPool> = Lazy::new(|| {
+    ThreadPoolBuilder::new()
+        .thread_name(|i| format!("keccak-{}", i))
+        .build()
+        .unwrap()
+});
+
+static BATCHED_HASHES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "keccak_batched_hashes_total",
+        "Keccak-256 hashes computed through the batch API"
+    )
+    .unwrap()
+});
+
+/// Batches smaller than this are hashed on the calling thread.
+pub const MIN_PARALLEL_BATCH: usize = 512;
+
+/// Minimum number of inputs each pool task hashes.
+const TASK_LEN: usize = 256;
+
+fn run<T: Sync>(items: &[T], hash: impl Fn(&T) -> H256 + Sync + Send) -> Vec<H256> {
+    BATCHED_HASHES.inc_by(items.len() as u64);
+
+    if items.len() < MIN_PARALLEL_BATCH {
+        return items.iter().map(hash).collect();
+    }
+
+    POOL.install(|| items.par_iter().with_min_len(TASK_LEN).map(hash).collect())
+}
+
+/// Hashes of `inputs`, in order.
+pub fn hash_batch<T: AsRef<[u8]> + Sync>(inputs: &[T]) -> Vec<H256> {
+    run(inputs, |input| keccak256(input))
+}
+
+/// Hashes of `key(item)` for every item, in order.
+pub fn hash_batch_by<T: Sync, D: AsRef<[u8]>>(
+    items: &[T],
+    key: impl Fn(&T) -> D + Sync + Send,
+) -> Vec<H256> {
+    run(items, |item| keccak256(key(item)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::Address;
+
+    #[test]
+    fn batch_matches_sequential() {
+        for len in [0, 1, MIN_PARALLEL_BATCH - 1, MIN_PARALLEL_BATCH, 5000] {
+            let inputs = (0..len as u64)
+                .map(|i| i.to_be_bytes().repeat(i as usize % 20))
+                .collect::<Vec<_>>();
+            let expected = inputs.iter().map(keccak256).collect::<Vec<_>>();
+
+            assert_eq!(hash_batch(&inputs), expected);
+
+            let entries = inputs.into_iter().enumerate().collect::<Vec<_>>();
+            assert_eq!(
+                hash_batch_by(&entries, |(_, input)| input.clone()),
+                expected
+            );
+        }
+
+        let addresses = [Address::repeat_byte(1), Address::repeat_byte(2)];
+        assert_eq!(
+            hash_batch(&addresses),
+            vec![keccak256(addresses[0]), keccak256(addresses[1])]
+        );
+    }
+}
@@ -7,6 +7,7 @@ use secp256k1::{PublicKey, SECP256K1};
 use sha3::{Digest, Keccak256};
 
 pub mod blake2;
+pub mod keccak;
 
 /// Concrete `Hasher` impl for the Keccak-256 hash
 #[derive(Default, Debug, Clone, PartialEq)]
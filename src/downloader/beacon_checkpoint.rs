@@ -0,0 +1,192 @@
+use crate::{accessors::chain::last_forkchoice, kv::mdbx::MdbxTransaction, models::*};
+use anyhow::{format_err, Context};
+use hyper::{header, Body, Client, Request, StatusCode};
+use mdbx::{EnvironmentKind, RW};
+use serde::Deserialize;
+use tracing::*;
+
+/// Execution block of the latest finalized beacon block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FinalizedCheckpoint {
+    pub block_number: BlockNumber,
+    pub block_hash: H256,
+    pub state_root: H256,
+}
+
+#[derive(Deserialize)]
+struct BeaconBlockResponse {
+    data: SignedBeaconBlock,
+}
+
+#[derive(Deserialize)]
+struct SignedBeaconBlock {
+    message: BeaconBlock,
+}
+
+#[derive(Deserialize)]
+struct BeaconBlock {
+    body: BeaconBlockBody,
+}
+
+#[derive(Deserialize)]
+struct BeaconBlockBody {
+    execution_payload: Option<ExecutionPayload>,
+}
+
+#[derive(Deserialize)]
+struct ExecutionPayload {
+    block_number: String,
+    block_hash: H256,
+    state_root: H256,
+}
+
+fn parse_finalized_block(body: &[u8]) -> anyhow::Result<FinalizedCheckpoint> {
+    let response = serde_json::from_slice::<BeaconBlockResponse>(body)
+        .context("malformed beacon block response")?;
+
+    let payload = response
+        .data
+        .message
+        .body
+        .execution_payload
+        .filter(|payload| !payload.block_hash.is_zero())
+        .ok_or_else(|| format_err!("finalized beacon block has no execution payload yet"))?;
+
+    Ok(FinalizedCheckpoint {
+        block_number: BlockNumber(
+            payload
+                .block_number
+                .parse()
+                .context("invalid execution block number")?,
+        ),
+        block_hash: payload.block_hash,
+        state_root: payload.state_root,
+    })
+}
+
+/// Fetches the finalized checkpoint from the REST API of a beacon node, e.g. `http://localhost:5052`.
+pub async fn fetch_finalized_checkpoint(
+    beacon_api_url: &str,
+) -> anyhow::Result<FinalizedCheckpoint> {
+    let request = Request::get(format!(
+        "{}/eth/v2/beacon/blocks/finalized",
+        beacon_api_url.trim_end_matches('/')
+    ))
+    .header(header::ACCEPT, "application/json")
+    .body(Body::empty())?;
+
+    let response = Client::new()
+        .request(request)
+        .await
+        .with_context(|| format!("failed to reach beacon node at {}", beacon_api_url))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    if status != StatusCode::OK {
+        return Err(format_err!(
+            "beacon node returned {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        ));
+    }
+
+    parse_finalized_block(&body)
+}
+
+/// Sets the initial forkchoice of a node that has none yet to the checkpoint.
+/// Returns `false` and leaves the markers alone if a finalized block is already known.
+pub fn apply_checkpoint<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RW, E>,
+    checkpoint: FinalizedCheckpoint,
+) -> anyhow::Result<bool> {
+    if let Some(finalized) = last_forkchoice::read(tx, last_forkchoice::FINALIZED_BLOCK_HASH)? {
+        debug!(
+            "Finalized block {:?} already known, ignoring checkpoint",
+            finalized
+        );
+        return Ok(false);
+    }
+
+    for key in [
+        last_forkchoice::HEAD_BLOCK_HASH,
+        last_forkchoice::SAFE_BLOCK_HASH,
+        last_forkchoice::FINALIZED_BLOCK_HASH,
+    ] {
+        last_forkchoice::write(tx, key, checkpoint.block_hash)?;
+    }
+    last_forkchoice::write(
+        tx,
+        last_forkchoice::FINALIZED_STATE_ROOT,
+        checkpoint.state_root,
+    )?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::new_mem_database;
+    use hex_literal::hex;
+
+    #[test]
+    fn parse_and_apply_checkpoint() {
+        let checkpoint = parse_finalized_block(
+            br#"{
+                "version": "bellatrix",
+                "execution_optimistic": false,
+                "data": {
+                    "message": {
+                        "slot": "4700013",
+                        "body": {
+                            "execution_payload": {
+                                "block_number": "15537394",
+                                "block_hash": "0x56a9bb0302da44b8c0b3df540781424684c3af04d0b7a38d72842b762076a664",
+                                "state_root": "0x40c07091e16263270f3579385090fea02dd5f061ba6750228fcc082ff762fda7"
+                            }
+                        }
+                    },
+                    "signature": "0x"
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            checkpoint,
+            FinalizedCheckpoint {
+                block_number: BlockNumber(15537394),
+                block_hash: hex!(
+                    "56a9bb0302da44b8c0b3df540781424684c3af04d0b7a38d72842b762076a664"
+                )
+                .into(),
+                state_root: hex!(
+                    "40c07091e16263270f3579385090fea02dd5f061ba6750228fcc082ff762fda7"
+                )
+                .into(),
+            }
+        );
+
+        parse_finalized_block(br#"{"data": {"message": {"body": {}}}}"#).unwrap_err();
+
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        assert!(apply_checkpoint(&tx, checkpoint).unwrap());
+        assert_eq!(
+            last_forkchoice::read(&tx, last_forkchoice::HEAD_BLOCK_HASH).unwrap(),
+            Some(checkpoint.block_hash)
+        );
+        assert_eq!(
+            last_forkchoice::read(&tx, last_forkchoice::FINALIZED_STATE_ROOT).unwrap(),
+            Some(checkpoint.state_root)
+        );
+
+        let later = FinalizedCheckpoint {
+            block_hash: H256::repeat_byte(1),
+            ..checkpoint
+        };
+        assert!(!apply_checkpoint(&tx, later).unwrap());
+        assert_eq!(
+            last_forkchoice::read(&tx, last_forkchoice::FINALIZED_BLOCK_HASH).unwrap(),
+            Some(checkpoint.block_hash)
+        );
+    }
+}
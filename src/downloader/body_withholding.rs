@@ -0,0 +1,186 @@
+//! Detection of peers that serve headers but withhold the bodies of a range.
+//!
+//! The body download stage asks the peers found here for each range, rotating away from those
+//! that answer with no bodies instead of asking them forever, and retrieves a range some other
+//! way once enough of them withheld it.
+use crate::{models::BlockNumber, sentry::sentry_client::PeerId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Peers that returned no bodies for a range, counted until some peer delivers.
+#[derive(Debug, Default)]
+struct RangeState {
+    withheld_by: HashSet<PeerId>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RangeStatus {
+    /// Ask another peer.
+    Retry,
+    /// Too many peers withheld it: retrieve it some other way, e.g. from a snapshot.
+    NeedsAlternative,
+}
+
+/// Tracks body requests by the first block of their range.
+#[derive(Debug)]
+pub struct BodyWithholdingDetector {
+    ranges: BTreeMap<BlockNumber, RangeState>,
+    /// Ranges each peer currently withholds.
+    peer_counts: HashMap<PeerId, usize>,
+    max_withholding_peers: usize,
+}
+
+impl BodyWithholdingDetector {
+    /// A range needs another source once `max_withholding_peers` distinct peers withheld it.
+    pub fn new(max_withholding_peers: usize) -> Self {
+        Self {
+            ranges: BTreeMap::new(),
+            peer_counts: HashMap::new(),
+            max_withholding_peers,
+        }
+    }
+
+    /// Records the answer of `peer` to a request for bodies starting at `start`.
+    pub fn record_response(
+        &mut self,
+        start: BlockNumber,
+        peer: PeerId,
+        delivered: usize,
+    ) -> RangeStatus {
+        if delivered > 0 {
+            // The peer set is not withholding this range, forgive those who did.
+            if let Some(state) = self.ranges.remove(&start) {
+                for peer in state.withheld_by {
+                    self.forget(peer);
+                }
+            }
+            return RangeStatus::Retry;
+        }
+
+        let state = self.ranges.entry(start).or_default();
+        if state.withheld_by.insert(peer) {
+            *self.peer_counts.entry(peer).or_default() += 1;
+        }
+
+        if state.withheld_by.len() >= self.max_withholding_peers {
+            RangeStatus::NeedsAlternative
+        } else {
+            RangeStatus::Retry
+        }
+    }
+
+    fn forget(&mut self, peer: PeerId) {
+        if let Some(count) = self.peer_counts.get_mut(&peer) {
+            *count -= 1;
+            if *count == 0 {
+                self.peer_counts.remove(&peer);
+            }
+        }
+    }
+
+    /// Peer among `candidates` to ask next for the range at `start`: one that has not withheld
+    /// it, preferring those that withhold the fewest ranges.
+    pub fn next_peer(
+        &self,
+        start: BlockNumber,
+        candidates: impl IntoIterator<Item = PeerId>,
+    ) -> Option<PeerId> {
+        let withheld_by = self.ranges.get(&start).map(|state| &state.withheld_by);
+        candidates
+            .into_iter()
+            .filter(|peer| !withheld_by.map_or(false, |w| w.contains(peer)))
+            .min_by_key(|peer| self.withheld_count(*peer))
+    }
+
+    /// Number of ranges `peer` withholds, e.g. to penalize it.
+    pub fn withheld_count(&self, peer: PeerId) -> usize {
+        self.peer_counts.get(&peer).copied().unwrap_or(0)
+    }
+
+    /// Forgets the range at `start`, e.g. once it was retrieved some other way, and returns the
+    /// peers that withheld it.
+    pub fn give_up(&mut self, start: BlockNumber) -> Vec<PeerId> {
+        let peers = self
+            .ranges
+            .remove(&start)
+            .map(|state| state.withheld_by.into_iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for &peer in &peers {
+            self.forget(peer);
+        }
+
+        peers
+    }
+
+    /// Drops a disconnected peer, so that it does not count towards giving up on a range.
+    pub fn remove_peer(&mut self, peer: PeerId) {
+        if self.peer_counts.remove(&peer).is_some() {
+            for state in self.ranges.values_mut() {
+                state.withheld_by.remove(&peer);
+            }
+        }
+    }
+
+    /// Drops ranges from `block` on, e.g. after an unwind.
+    pub fn reset_from(&mut self, block: BlockNumber) {
+        for (_, state) in self.ranges.split_off(&block) {
+            for peer in state.withheld_by {
+                self.forget(peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn withholding() {
+        let mut detector = BodyWithholdingDetector::new(3);
+        let peers = (1..=4).map(PeerId::repeat_byte).collect::<Vec<_>>();
+        let range = BlockNumber(100);
+
+        assert_eq!(
+            detector.record_response(range, peers[0], 0),
+            RangeStatus::Retry
+        );
+        // Asked again, still one peer.
+        assert_eq!(
+            detector.record_response(range, peers[0], 0),
+            RangeStatus::Retry
+        );
+        assert_eq!(detector.withheld_count(peers[0]), 1);
+        assert_eq!(detector.next_peer(range, peers.clone()), Some(peers[1]));
+
+        // A peer that withholds elsewhere is asked last.
+        detector.record_response(BlockNumber(200), peers[1], 0);
+        assert_eq!(detector.next_peer(range, peers.clone()), Some(peers[2]));
+
+        detector.record_response(range, peers[1], 0);
+        assert_eq!(
+            detector.record_response(range, peers[2], 0),
+            RangeStatus::NeedsAlternative
+        );
+        assert_eq!(detector.next_peer(range, peers.clone()), Some(peers[3]));
+
+        // Given up ranges start over.
+        assert_eq!(detector.give_up(BlockNumber(200)), vec![peers[1]]);
+        assert_eq!(detector.withheld_count(peers[1]), 1);
+        detector.record_response(BlockNumber(200), peers[1], 0);
+
+        // Leaving peers no longer count.
+        detector.remove_peer(peers[2]);
+        assert_eq!(
+            detector.record_response(range, peers[0], 0),
+            RangeStatus::Retry
+        );
+
+        // Delivery clears the range.
+        detector.record_response(range, peers[3], 10);
+        assert_eq!(detector.withheld_count(peers[0]), 0);
+        assert_eq!(detector.withheld_count(peers[1]), 1);
+
+        detector.reset_from(BlockNumber(150));
+        assert_eq!(detector.withheld_count(peers[1]), 0);
+    }
+}
@@ -1,7 +1,9 @@
 use super::{
     downloader_forky, downloader_linear, downloader_preverified,
-    headers::header_slices::HeaderSlices, stages::fork_switch_command::ForkSwitchCommand,
-    ui::ui_system::UISystemShared, verification::header_slice_verifier::HeaderSliceVerifier,
+    headers::header_slices::{HeaderSlices, HeaderSlicesProgress, HeaderSlicesProgressChannel},
+    stages::{fork_switch_command::ForkSwitchCommand, TopBlockEstimateChannel},
+    ui::ui_system::UISystemShared,
+    verification::header_slice_verifier::HeaderSliceVerifier,
 };
 use crate::{
     kv::mdbx::MdbxTransaction,
@@ -14,6 +16,7 @@ use std::{
     fmt::{Debug, Formatter},
     sync::Arc,
 };
+use tokio::sync::watch;
 
 #[derive(Debug)]
 pub struct Downloader {
@@ -21,6 +24,8 @@ pub struct Downloader {
     downloader_linear: downloader_linear::DownloaderLinear,
     downloader_forky: downloader_forky::DownloaderForky,
     genesis_block_hash: H256,
+    progress: HeaderSlicesProgressChannel,
+    top_block_estimate: TopBlockEstimateChannel,
 }
 
 pub struct DownloaderReport {
@@ -73,11 +78,15 @@ impl Downloader {
         sentry: SentryClientReactorShared,
     ) -> anyhow::Result<Self> {
         let verifier = Arc::new(verifier);
+        let progress = HeaderSlicesProgressChannel::new();
+        let top_block_estimate = TopBlockEstimateChannel::new();
 
         let downloader_preverified = downloader_preverified::DownloaderPreverified::new(
             verifier.preverified_hashes_config(&chain_config.chain_name())?,
             mem_limit,
             sentry.clone(),
+            progress.clone(),
+            top_block_estimate.clone(),
         );
 
         let downloader_linear = downloader_linear::DownloaderLinear::new(
@@ -85,20 +94,38 @@ impl Downloader {
             verifier.clone(),
             mem_limit,
             sentry.clone(),
+            progress.clone(),
+            top_block_estimate.clone(),
         );
 
-        let downloader_forky =
-            downloader_forky::DownloaderForky::new(chain_config.clone(), verifier, sentry);
+        let downloader_forky = downloader_forky::DownloaderForky::new(
+            chain_config.clone(),
+            verifier,
+            sentry,
+            progress.clone(),
+        );
 
         let instance = Self {
             downloader_preverified,
             downloader_linear,
             downloader_forky,
             genesis_block_hash: chain_config.genesis_block_hash(),
+            progress,
+            top_block_estimate,
         };
         Ok(instance)
     }
 
+    /// Progress of the header slices of the current and later runs, updated while downloading.
+    pub fn watch_progress(&self) -> watch::Receiver<HeaderSlicesProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Top block estimated from peer announcements, updated as soon as it changes.
+    pub fn watch_top_block_estimate(&self) -> watch::Receiver<Option<BlockNumber>> {
+        self.top_block_estimate.subscribe()
+    }
+
     pub async fn run<'downloader, 'db: 'downloader, E: EnvironmentKind>(
         &'downloader self,
         db_transaction: &'downloader MdbxTransaction<'db, RW, E>,
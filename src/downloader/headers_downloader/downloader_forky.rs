@@ -7,6 +7,7 @@ use super::{
         header_slices,
         header_slices::{
             is_block_num_aligned_to_slice_start, HeaderSlice, HeaderSliceStatus, HeaderSlices,
+            HeaderSlicesProgressChannel,
         },
     },
     headers_ui::HeaderSlicesView,
@@ -27,6 +28,7 @@ pub struct DownloaderForky {
     chain_config: ChainConfig,
     verifier: Arc<Box<dyn HeaderSliceVerifier>>,
     sentry: SentryClientReactorShared,
+    progress: HeaderSlicesProgressChannel,
 }
 
 pub struct DownloaderForkyReport {
@@ -42,11 +44,13 @@ impl DownloaderForky {
         chain_config: ChainConfig,
         verifier: Arc<Box<dyn HeaderSliceVerifier>>,
         sentry: SentryClientReactorShared,
+        progress: HeaderSlicesProgressChannel,
     ) -> Self {
         Self {
             chain_config,
             verifier,
             sentry,
+            progress,
         }
     }
 
@@ -232,13 +236,17 @@ impl DownloaderForky {
         } else {
             let loaded_header_slices =
                 Self::load_header_slices(db_transaction, start_block_num, forky_max_blocks_count)?;
-            Arc::new(loaded_header_slices)
+            Arc::new(loaded_header_slices.with_progress_channel(self.progress.clone()))
         };
 
         let fork_header_slices = previous_run_fork_header_slices
             .unwrap_or_else(|| Arc::new(HeaderSlices::empty(header_slices.max_slices())));
 
-        let header_slices_view = HeaderSlicesView::new(header_slices.clone(), "DownloaderForky");
+        let header_slices_view = HeaderSlicesView::new(
+            header_slices.clone(),
+            "DownloaderForky",
+            self.sentry.read().await.connected_peers(),
+        );
         let _header_slices_view_scope =
             UISystemViewScope::new(&ui_system, Box::new(header_slices_view));
 
@@ -4,7 +4,7 @@ use super::{
         header_slices,
         header_slices::{
             align_block_num_to_slice_start, is_block_num_aligned_to_slice_start, HeaderSliceStatus,
-            HeaderSlices,
+            HeaderSlices, HeaderSlicesProgressChannel,
         },
     },
     headers_ui::HeaderSlicesView,
@@ -27,6 +27,8 @@ pub struct DownloaderLinear {
     verifier: Arc<Box<dyn HeaderSliceVerifier>>,
     mem_limit: usize,
     sentry: SentryClientReactorShared,
+    progress: HeaderSlicesProgressChannel,
+    top_block_estimate: TopBlockEstimateChannel,
 }
 
 pub struct DownloaderLinearReport {
@@ -42,12 +44,16 @@ impl DownloaderLinear {
         verifier: Arc<Box<dyn HeaderSliceVerifier>>,
         mem_limit: usize,
         sentry: SentryClientReactorShared,
+        progress: HeaderSlicesProgressChannel,
+        top_block_estimate: TopBlockEstimateChannel,
     ) -> Self {
         Self {
             chain_config,
             verifier,
             mem_limit,
             sentry,
+            progress,
+            top_block_estimate,
         }
     }
 
@@ -56,7 +62,8 @@ impl DownloaderLinear {
         start_block_num: BlockNumber,
     ) -> anyhow::Result<BlockNumber> {
         info!("DownloaderLinear: waiting to estimate a top block number...");
-        let stage = TopBlockEstimateStage::new(self.sentry.clone());
+        let stage =
+            TopBlockEstimateStage::new(self.sentry.clone(), self.top_block_estimate.clone());
         while !stage.is_over() && stage.estimated_top_block_num().is_none() {
             stage.execute().await?;
         }
@@ -126,14 +133,17 @@ impl DownloaderLinear {
             anyhow::bail!("expected a saved parent header of {}", start_block_num.0);
         }
 
-        let header_slices = Arc::new(HeaderSlices::new(
-            self.mem_limit,
-            start_block_num,
-            final_block_num,
-        ));
+        let header_slices = Arc::new(
+            HeaderSlices::new(self.mem_limit, start_block_num, final_block_num)
+                .with_progress_channel(self.progress.clone()),
+        );
         let sentry = self.sentry.clone();
 
-        let header_slices_view = HeaderSlicesView::new(header_slices.clone(), "DownloaderLinear");
+        let header_slices_view = HeaderSlicesView::new(
+            header_slices.clone(),
+            "DownloaderLinear",
+            self.sentry.read().await.connected_peers(),
+        );
         let _header_slices_view_scope =
             UISystemViewScope::new(&ui_system, Box::new(header_slices_view));
 
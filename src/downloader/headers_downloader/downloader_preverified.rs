@@ -4,7 +4,9 @@ use super::{
     downloader_stage_loop::DownloaderStageLoop,
     headers::{
         header_slices,
-        header_slices::{align_block_num_to_slice_start, HeaderSlices},
+        header_slices::{
+            align_block_num_to_slice_start, HeaderSlices, HeaderSlicesProgressChannel,
+        },
     },
     headers_ui::HeaderSlicesView,
     stages::*,
@@ -19,6 +21,8 @@ pub struct DownloaderPreverified {
     preverified_hashes_config: PreverifiedHashesConfig,
     mem_limit: usize,
     sentry: SentryClientReactorShared,
+    progress: HeaderSlicesProgressChannel,
+    top_block_estimate: TopBlockEstimateChannel,
 }
 
 pub struct DownloaderPreverifiedReport {
@@ -33,11 +37,15 @@ impl DownloaderPreverified {
         preverified_hashes_config: PreverifiedHashesConfig,
         mem_limit: usize,
         sentry: SentryClientReactorShared,
+        progress: HeaderSlicesProgressChannel,
+        top_block_estimate: TopBlockEstimateChannel,
     ) -> Self {
         Self {
             preverified_hashes_config,
             mem_limit,
             sentry,
+            progress,
+            top_block_estimate,
         }
     }
 
@@ -75,15 +83,17 @@ impl DownloaderPreverified {
             });
         }
 
-        let header_slices = Arc::new(HeaderSlices::new(
-            self.mem_limit,
-            start_block_num,
-            final_block_num,
-        ));
+        let header_slices = Arc::new(
+            HeaderSlices::new(self.mem_limit, start_block_num, final_block_num)
+                .with_progress_channel(self.progress.clone()),
+        );
         let sentry = self.sentry.clone();
 
-        let header_slices_view =
-            HeaderSlicesView::new(header_slices.clone(), "DownloaderPreverified");
+        let header_slices_view = HeaderSlicesView::new(
+            header_slices.clone(),
+            "DownloaderPreverified",
+            self.sentry.read().await.connected_peers(),
+        );
         let _header_slices_view_scope =
             UISystemViewScope::new(&ui_system, Box::new(header_slices_view));
 
@@ -106,7 +116,8 @@ impl DownloaderPreverified {
             true,
         );
         let refill_stage = RefillStage::new(header_slices.clone());
-        let top_block_estimate_stage = TopBlockEstimateStage::new(sentry.clone());
+        let top_block_estimate_stage =
+            TopBlockEstimateStage::new(sentry.clone(), self.top_block_estimate.clone());
 
         let refill_stage_is_over = refill_stage.is_over_check();
 
@@ -1,4 +1,5 @@
 use super::{headers::header_slices::HeaderSlices, stages::stage::Stage as DownloaderStage};
+use crate::task_metrics::TaskMetrics;
 use futures_core::Stream;
 use std::{any::type_name, collections::HashMap, pin::Pin, sync::Arc};
 use tokio_stream::{StreamExt, StreamMap};
@@ -10,6 +11,7 @@ fn make_stage_stream<'a, Stage: DownloaderStage + 'a>(
     mut stage: Stage,
     stage_name: String,
 ) -> StageStream<'a> {
+    let metrics = TaskMetrics::new("downloader_stage", &stage_name);
     let stream = async_stream::stream! {
         loop {
             debug!("{}: start", stage_name);
@@ -18,7 +20,8 @@ fn make_stage_stream<'a, Stage: DownloaderStage + 'a>(
             yield result;
         }
     };
-    Box::pin(stream)
+    // A stage polled for long holds up all the others in the loop.
+    Box::pin(metrics.instrument(stream))
 }
 
 fn short_stage_name<Stage: DownloaderStage>() -> &'static str {
@@ -6,7 +6,7 @@ use super::{
         header_slices,
         header_slices::{HeaderSlice, HeaderSliceStatus, HeaderSlices},
     },
-    ui::ui_system::UISystem,
+    ui::ui_system::{UIMode, UISystem},
     verification::header_slice_verifier_mock::HeaderSliceVerifierMock,
 };
 use crate::{
@@ -53,7 +53,7 @@ async fn run_downloader(
     let db = kv::new_mem_database()?;
     let db_transaction = db.begin_mutable()?;
 
-    let ui_system = Arc::new(AsyncMutex::new(UISystem::new()));
+    let ui_system = Arc::new(AsyncMutex::new(UISystem::new(UIMode::Off)));
 
     let mut report = downloader
         .run(
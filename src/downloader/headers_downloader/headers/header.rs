@@ -1,5 +1,5 @@
 use crate::{
-    crypto::keccak256,
+    crypto::{keccak, keccak256},
     models::{BlockHeader as BaseBlockHeader, *},
 };
 use bytes::Bytes;
@@ -69,6 +69,19 @@ impl BlockHeader {
             .unwrap_or_else(|| Self::hash_compute(&self.rlp_repr()))
     }
 
+    /// Same as calling `hash_prepare` on every header, hashing them as one batch.
+    pub fn hash_prepare_batch(headers: &mut [BlockHeader]) {
+        for header in headers.iter_mut() {
+            if header.rlp_repr_cached.is_none() {
+                header.rlp_repr_prepare();
+            }
+        }
+        let hashes = keccak::hash_batch_by(headers, |header| header.rlp_repr());
+        for (header, hash) in headers.iter_mut().zip(hashes) {
+            header.hash_cached = Some(hash);
+        }
+    }
+
     #[cfg(test)]
     pub fn set_hash_cached(&mut self, value: Option<H256>) {
         self.hash_cached = value;
@@ -1,6 +1,6 @@
 use super::header::BlockHeader;
 use crate::{models::BlockNumber, sentry::sentry_client::PeerId};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::{
     collections::{HashMap, VecDeque},
     sync::{
@@ -11,6 +11,7 @@ use std::{
 };
 use strum::IntoEnumIterator;
 use tokio::sync::watch;
+use tracing::*;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, strum::EnumIter, strum::Display)]
 pub enum HeaderSliceStatus {
@@ -37,12 +38,71 @@ pub struct HeaderSlice {
     pub start_block_num: BlockNumber,
     pub status: HeaderSliceStatus,
     pub headers: Option<Vec<BlockHeader>>,
+    /// Peer that supplied `headers`.
     pub from_peer_id: Option<PeerId>,
     pub request_time: Option<time::Instant>,
     pub request_attempt: u16,
     pub refetch_attempt: u16,
 }
 
+/// Download progress of a HeaderSlices, published to the receivers of
+/// [`HeaderSlicesProgressChannel::subscribe`] whenever the status watchers are notified.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeaderSlicesProgress {
+    /// Number of slices in each status, in the order of [`HeaderSliceStatus`] variants.
+    pub status_counts: Vec<(HeaderSliceStatus, usize)>,
+    /// Blocks covered by the current slices.
+    pub span: std::ops::Range<BlockNumber>,
+    pub final_block_num: BlockNumber,
+    /// Headers received since the slices were created.
+    pub downloaded_count: u64,
+    /// Headers received per second, averaged over at least `DOWNLOAD_RATE_INTERVAL`.
+    pub download_rate: f64,
+}
+
+impl HeaderSlicesProgress {
+    pub fn count(&self, status: HeaderSliceStatus) -> usize {
+        self.status_counts
+            .iter()
+            .find_map(|(s, count)| (*s == status).then_some(*count))
+            .unwrap_or(0)
+    }
+}
+
+/// Channel of [`HeaderSlicesProgress`] that can be shared by consecutive HeaderSlices,
+/// so that observers keep a single subscription across downloader runs.
+#[derive(Clone, Debug)]
+pub struct HeaderSlicesProgressChannel {
+    sender: Arc<watch::Sender<HeaderSlicesProgress>>,
+    receiver: watch::Receiver<HeaderSlicesProgress>,
+}
+
+impl HeaderSlicesProgressChannel {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(HeaderSlicesProgress::default());
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<HeaderSlicesProgress> {
+        self.receiver.clone()
+    }
+}
+
+impl Default for HeaderSlicesProgressChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct DownloadRateSample {
+    time: time::Instant,
+    downloaded_count: u64,
+    rate: f64,
+}
+
 struct HeaderSliceStatusWatch {
     pub sender: watch::Sender<usize>,
     pub receiver: watch::Receiver<usize>,
@@ -56,16 +116,30 @@ struct HeaderSliceStatusWatch {
 /// HeaderSlice 0: headers 0-192
 /// HeaderSlice 1: headers 192-384
 /// HeaderSlice 2: headers 384-576
+///
+/// When created with `new`, max_slices starts small and is tuned on every refill
+/// between MIN_ADAPTIVE_SLICES and the hard cap derived from the memory limit:
+/// it grows while downloaded slices are verified as fast as they arrive,
+/// and shrinks when they pile up waiting for verification.
 pub struct HeaderSlices {
     slices: RwLock<VecDeque<Arc<RwLock<HeaderSlice>>>>,
-    max_slices: usize,
+    max_slices: AtomicUsize,
+    min_slices: usize,
+    max_slices_cap: usize,
     max_block_num: AtomicU64,
     final_block_num: BlockNumber,
     state_watches: HashMap<HeaderSliceStatus, HeaderSliceStatusWatch>,
+    downloaded_count: AtomicU64,
+    download_rate_sample: Mutex<DownloadRateSample>,
+    progress: HeaderSlicesProgressChannel,
 }
 
 pub const HEADER_SLICE_SIZE: usize = 192;
 
+const MIN_ADAPTIVE_SLICES: usize = 16;
+
+const DOWNLOAD_RATE_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
 const ATOMIC_ORDERING: Ordering = Ordering::SeqCst;
 
 impl HeaderSlices {
@@ -75,17 +149,21 @@ impl HeaderSlices {
         final_block_num: BlockNumber,
     ) -> Self {
         let total_block_num = (final_block_num.0 - start_block_num.0) as usize;
-        let max_slices = std::cmp::min(
+        let max_slices_cap = std::cmp::min(
             estimate_max_slices_for_mem_limit(mem_limit),
             (total_block_num + HEADER_SLICE_SIZE - 1) / HEADER_SLICE_SIZE,
         );
+        let min_slices = std::cmp::min(MIN_ADAPTIVE_SLICES, max_slices_cap);
 
-        Self::from_slices_vec(
+        let mut header_slices = Self::from_slices_vec(
             Vec::new(),
             Some(start_block_num),
-            Some(max_slices),
+            Some(min_slices),
             Some(final_block_num),
-        )
+        );
+        header_slices.min_slices = min_slices;
+        header_slices.max_slices_cap = max_slices_cap;
+        header_slices
     }
 
     #[allow(clippy::needless_range_loop)]
@@ -129,23 +207,39 @@ impl HeaderSlices {
 
         Self {
             slices: RwLock::new(slice_locks),
-            max_slices,
+            max_slices: AtomicUsize::new(max_slices),
+            min_slices: max_slices,
+            max_slices_cap: max_slices,
             max_block_num: AtomicU64::new(max_block_num),
             final_block_num,
             state_watches,
+            downloaded_count: AtomicU64::new(0),
+            download_rate_sample: Mutex::new(DownloadRateSample::new()),
+            progress: HeaderSlicesProgressChannel::new(),
         }
     }
 
     pub fn empty(max_slices: usize) -> Self {
         Self {
             slices: RwLock::new(VecDeque::new()),
-            max_slices,
+            max_slices: AtomicUsize::new(max_slices),
+            min_slices: max_slices,
+            max_slices_cap: max_slices,
             max_block_num: AtomicU64::new(0),
             final_block_num: BlockNumber(0),
             state_watches: Self::make_state_watches_from_slices(&[]),
+            downloaded_count: AtomicU64::new(0),
+            download_rate_sample: Mutex::new(DownloadRateSample::new()),
+            progress: HeaderSlicesProgressChannel::new(),
         }
     }
 
+    /// Publish progress to `channel` instead of a channel of its own.
+    pub fn with_progress_channel(mut self, channel: HeaderSlicesProgressChannel) -> Self {
+        self.progress = channel;
+        self
+    }
+
     #[cfg(test)]
     pub fn clone_slices_vec(&self) -> Vec<HeaderSlice> {
         self.slices
@@ -286,12 +380,42 @@ impl HeaderSlices {
         status_watch.count.fetch_sub(count, ATOMIC_ORDERING);
     }
 
+    /// Tune max_slices to the verification throughput: a backlog of downloaded slices
+    /// means the window is larger than verification can keep up with.
+    fn adapt_max_slices(&self, slices_len: usize) {
+        if self.min_slices == self.max_slices_cap {
+            return;
+        }
+
+        let max_slices = self.max_slices();
+        let backlog = self.count_slices_in_status(HeaderSliceStatus::Downloaded)
+            + self.count_slices_in_status(HeaderSliceStatus::VerifiedInternally);
+
+        let new_max_slices = if backlog > max_slices / 2 {
+            std::cmp::max(max_slices * 3 / 4, self.min_slices)
+        } else if (backlog < max_slices / 4) && (slices_len >= max_slices) {
+            std::cmp::min(max_slices + max_slices / 4 + 1, self.max_slices_cap)
+        } else {
+            max_slices
+        };
+
+        if new_max_slices != max_slices {
+            debug!(
+                "HeaderSlices: max_slices {} -> {} (backlog {})",
+                max_slices, new_max_slices, backlog
+            );
+            self.max_slices.store(new_max_slices, ATOMIC_ORDERING);
+        }
+    }
+
     pub fn refill(&self) {
         let mut slices = self.slices.write();
         let initial_len = slices.len();
         let mut count = 0;
 
-        for _ in initial_len..self.max_slices {
+        self.adapt_max_slices(initial_len);
+
+        for _ in initial_len..self.max_slices() {
             let max_block_num = self.max_block_num();
             if max_block_num >= self.final_block_num {
                 break;
@@ -344,7 +468,7 @@ impl HeaderSlices {
     pub fn prepend_slice(&self) -> anyhow::Result<()> {
         let mut slices = self.slices.write();
 
-        if slices.len() >= self.max_slices {
+        if slices.len() >= self.max_slices() {
             return Err(anyhow::format_err!(
                 "can't prepend: max_slices limit reached"
             ));
@@ -391,7 +515,7 @@ impl HeaderSlices {
 
     pub fn trim_start_to_fit_max_slices(&self) {
         let mut slices = self.slices.write();
-        while slices.len() > self.max_slices {
+        while slices.len() > self.max_slices() {
             let removed_slice_lock = slices.pop_front().unwrap();
             let removed_status = removed_slice_lock.read().status;
             let status_watch = &self.state_watches[&removed_status];
@@ -422,6 +546,11 @@ impl HeaderSlices {
 
         old_status_watch.count.fetch_sub(1, ATOMIC_ORDERING);
         new_status_watch.count.fetch_add(1, ATOMIC_ORDERING);
+
+        if status == HeaderSliceStatus::Downloaded {
+            self.downloaded_count
+                .fetch_add(slice.len() as u64, ATOMIC_ORDERING);
+        }
     }
 
     pub fn watch_status_changes(&self, status: HeaderSliceStatus) -> watch::Receiver<usize> {
@@ -434,6 +563,25 @@ impl HeaderSlices {
             let count = watch.count.load(ATOMIC_ORDERING);
             let _ = watch.sender.send(count);
         }
+        let _ = self.progress.sender.send(self.progress());
+    }
+
+    pub fn progress(&self) -> HeaderSlicesProgress {
+        let downloaded_count = self.downloaded_count.load(ATOMIC_ORDERING);
+        let download_rate = self
+            .download_rate_sample
+            .lock()
+            .update(time::Instant::now(), downloaded_count);
+
+        HeaderSlicesProgress {
+            status_counts: HeaderSliceStatus::iter()
+                .map(|status| (status, self.count_slices_in_status(status)))
+                .collect(),
+            span: self.min_block_num()..self.max_block_num(),
+            final_block_num: self.final_block_num,
+            downloaded_count,
+            download_rate,
+        }
     }
 
     pub fn count_slices_in_status(&self, status: HeaderSliceStatus) -> usize {
@@ -451,7 +599,7 @@ impl HeaderSlices {
     }
 
     pub fn max_slices(&self) -> usize {
-        self.max_slices
+        self.max_slices.load(ATOMIC_ORDERING)
     }
 
     pub fn min_block_num(&self) -> BlockNumber {
@@ -482,6 +630,27 @@ impl HeaderSlices {
     }
 }
 
+impl DownloadRateSample {
+    fn new() -> Self {
+        Self {
+            time: time::Instant::now(),
+            downloaded_count: 0,
+            rate: 0.0,
+        }
+    }
+
+    /// Rate since the previous sample, which is replaced once it is old enough to give a stable rate.
+    fn update(&mut self, now: time::Instant, downloaded_count: u64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.time);
+        if elapsed >= DOWNLOAD_RATE_INTERVAL {
+            self.rate = (downloaded_count - self.downloaded_count) as f64 / elapsed.as_secs_f64();
+            self.time = now;
+            self.downloaded_count = downloaded_count;
+        }
+        self.rate
+    }
+}
+
 pub fn align_block_num_to_slice_start(num: BlockNumber) -> BlockNumber {
     let slice_size = HEADER_SLICE_SIZE as u64;
     BlockNumber(num.0 / slice_size * slice_size)
@@ -504,6 +673,13 @@ impl HeaderSlice {
         let end = BlockNumber(self.start_block_num.0 + self.len() as u64);
         self.start_block_num..end
     }
+
+    /// Drops the headers along with the peer they came from, so that whoever supplies the
+    /// slice next is the one held responsible for it.
+    pub fn clear_headers(&mut self) {
+        self.headers = None;
+        self.from_peer_id = None;
+    }
 }
 
 impl Default for HeaderSliceStatus {
@@ -547,3 +723,76 @@ impl TryFrom<char> for HeaderSliceStatus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adaptive_max_slices() {
+        let final_block_num = BlockNumber((1000 * HEADER_SLICE_SIZE) as u64);
+        let slices = HeaderSlices::new(usize::MAX, BlockNumber(0), final_block_num);
+        assert_eq!(slices.max_slices(), MIN_ADAPTIVE_SLICES);
+
+        // nothing waits for verification: the window grows
+        slices.refill();
+        assert_eq!(slices.max_slices(), 21);
+        assert_eq!(slices.clone_statuses().len(), 21);
+
+        // everything waits for verification: the window shrinks, but not below the minimum
+        slices.for_each(|slice_lock| {
+            let mut slice = slice_lock.write();
+            slices.set_slice_status(&mut slice, HeaderSliceStatus::Downloaded);
+        });
+        slices.refill();
+        assert_eq!(slices.max_slices(), MIN_ADAPTIVE_SLICES);
+    }
+
+    #[test]
+    fn progress_watch() {
+        let final_block_num = BlockNumber((10 * HEADER_SLICE_SIZE) as u64);
+        let channel = HeaderSlicesProgressChannel::new();
+        let progress = channel.subscribe();
+        let slices =
+            HeaderSlices::from_slices_vec(Vec::new(), None, Some(4), Some(final_block_num))
+                .with_progress_channel(channel);
+
+        let slice_lock = slices.find_by_status(HeaderSliceStatus::Empty).unwrap();
+        let mut slice = slice_lock.write();
+        let header = BlockHeader::from(crate::models::BlockHeader::empty());
+        slice.headers = Some(vec![header; HEADER_SLICE_SIZE]);
+        slices.set_slice_status(&mut slice, HeaderSliceStatus::Downloaded);
+        drop(slice);
+
+        // published only on notification
+        assert_eq!(*progress.borrow(), HeaderSlicesProgress::default());
+        slices.notify_status_watchers();
+
+        let latest = progress.borrow().clone();
+        assert_eq!(latest.count(HeaderSliceStatus::Empty), 3);
+        assert_eq!(latest.count(HeaderSliceStatus::Downloaded), 1);
+        assert_eq!(latest.count(HeaderSliceStatus::Saved), 0);
+        assert_eq!(
+            latest.span,
+            BlockNumber(0)..BlockNumber((4 * HEADER_SLICE_SIZE) as u64)
+        );
+        assert_eq!(latest.final_block_num, final_block_num);
+        assert_eq!(latest.downloaded_count, HEADER_SLICE_SIZE as u64);
+    }
+
+    #[test]
+    fn download_rate() {
+        let mut sample = DownloadRateSample::new();
+        let start = sample.time;
+        assert_eq!(sample.update(start + DOWNLOAD_RATE_INTERVAL / 2, 100), 0.0);
+        assert_eq!(
+            sample.update(start + DOWNLOAD_RATE_INTERVAL * 2, 400),
+            200.0
+        );
+        // kept until the next interval is over
+        assert_eq!(
+            sample.update(start + DOWNLOAD_RATE_INTERVAL * 2, 1000),
+            200.0
+        );
+    }
+}
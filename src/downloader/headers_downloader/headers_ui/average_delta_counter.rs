@@ -1,39 +1,39 @@
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
 
+/// Average rate of change of a value, over the samples taken within a time window.
 pub struct AverageDeltaCounter {
-    window: VecDeque<u64>,
-    current_value: Option<u64>,
-    sum: u64,
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
 }
 
 impl AverageDeltaCounter {
-    pub fn new(window_size: usize) -> Self {
+    pub fn new(window: Duration) -> Self {
         Self {
-            window: VecDeque::<u64>::with_capacity(window_size),
-            current_value: None,
-            sum: 0,
+            window,
+            samples: VecDeque::new(),
         }
     }
 
     pub fn update(&mut self, value: u64) {
-        let previous_value = self.current_value.unwrap_or(value);
-        self.current_value = Some(value);
+        let now = Instant::now();
+        self.samples.push_back((now, value));
 
-        if self.window.len() == self.window.capacity() {
-            self.sum -= self.window.pop_front().unwrap();
+        // The oldest sample still in the window is kept, to measure from.
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= self.window {
+            self.samples.pop_front();
         }
-
-        let delta = value - previous_value;
-        self.window.push_back(delta);
-        self.sum += delta;
     }
 
+    /// Change per second.
     pub fn average(&self) -> u64 {
-        let len = self.window.len() as u64;
-        if len == 0 {
-            0
-        } else {
-            self.sum / len
+        match (self.samples.front(), self.samples.back()) {
+            (Some(&(start, first)), Some(&(end, last))) if end > start => {
+                (last.saturating_sub(first) as f64 / (end - start).as_secs_f64()) as u64
+            }
+            _ => 0,
         }
     }
 }
@@ -6,32 +6,32 @@ use super::{
     },
     ui_view::UIView,
 };
-use crate::models::BlockNumber;
-use crossterm::{cursor, style, terminal, QueueableCommand};
-use std::{
-    cell::RefCell,
-    io::{stdout, Write},
-    sync::Arc,
-};
+use crate::{models::BlockNumber, sentry::peer_tracker::ConnectedPeers};
+use std::{cell::RefCell, sync::Arc, time::Duration};
+use tracing::*;
 
 pub struct HeaderSlicesView {
     header_slices: Arc<HeaderSlices>,
     phase_name: String,
     speed_counter: RefCell<AverageDeltaCounter>,
+    connected_peers: ConnectedPeers,
 }
 
 impl HeaderSlicesView {
-    pub fn new(header_slices: Arc<HeaderSlices>, phase_name: &str) -> Self {
+    pub fn new(
+        header_slices: Arc<HeaderSlices>,
+        phase_name: &str,
+        connected_peers: ConnectedPeers,
+    ) -> Self {
         Self {
             header_slices,
             phase_name: String::from(phase_name),
-            speed_counter: RefCell::new(AverageDeltaCounter::new(60)),
+            speed_counter: RefCell::new(AverageDeltaCounter::new(Duration::from_secs(60))),
+            connected_peers,
         }
     }
-}
 
-impl UIView for HeaderSlicesView {
-    fn draw(&self) -> anyhow::Result<()> {
+    fn progress_desc(&self) -> String {
         let phase_name = &self.phase_name;
         let min_block_num = self.header_slices.min_block_num();
         let saved_blocks_count = self
@@ -41,13 +41,48 @@ impl UIView for HeaderSlicesView {
         let current_block_num = BlockNumber(min_block_num.0 + saved_blocks_count as u64);
         let max_block_num = self.header_slices.max_block_num();
         let final_block_num = self.header_slices.final_block_num();
-        let counters = self.header_slices.status_counters();
-        let statuses = self.header_slices.clone_statuses();
 
         // speed
         let mut speed_counter = self.speed_counter.borrow_mut();
         speed_counter.update(current_block_num.0);
         let speed = speed_counter.average();
+        let peers_count = self.connected_peers.read().len();
+
+        std::format!(
+            "{} headers {} - {} of {} at {} blk/sec, {} peers ...",
+            phase_name,
+            current_block_num.0,
+            max_block_num.0,
+            final_block_num.0,
+            speed,
+            peers_count,
+        )
+    }
+}
+
+impl UIView for HeaderSlicesView {
+    fn log(&self) -> anyhow::Result<()> {
+        // overall progress
+        info!("{}", self.progress_desc());
+
+        // counters
+        let mut counters_str = String::new();
+        for (status, count) in self.header_slices.status_counters() {
+            counters_str.push_str(std::format!("{}: {}; ", status, count).as_str());
+        }
+        debug!("{}", counters_str);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "crossterm")]
+    fn draw(&self) -> anyhow::Result<()> {
+        use crossterm::{cursor, style, terminal, QueueableCommand};
+        use std::io::{stdout, Write};
+
+        let progress_desc = self.progress_desc();
+        let counters = self.header_slices.status_counters();
+        let statuses = self.header_slices.clone_statuses();
 
         let mut stdout = stdout();
 
@@ -59,14 +94,6 @@ impl UIView for HeaderSlicesView {
         stdout.queue(terminal::EnableLineWrap {})?;
 
         // overall progress
-        let progress_desc = std::format!(
-            "{} headers {} - {} of {} at {} blk/sec ...",
-            phase_name,
-            current_block_num.0,
-            max_block_num.0,
-            final_block_num.0,
-            speed,
-        );
         stdout.queue(style::Print(progress_desc))?;
         stdout.queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
         stdout.queue(cursor::MoveToNextLine(1))?;
@@ -80,7 +107,11 @@ impl UIView for HeaderSlicesView {
         stdout.queue(cursor::MoveToNextLine(1))?;
 
         // counters
-        stdout.queue(style::Print(format_counters(counters)))?;
+        let mut counters_str = String::new();
+        for (status, count) in counters {
+            counters_str.push_str(std::format!("({}): {}; ", char::from(status), count).as_str());
+        }
+        stdout.queue(style::Print(counters_str))?;
         stdout.queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
         stdout.queue(cursor::MoveToNextLine(1))?;
 
@@ -94,11 +125,3 @@ impl UIView for HeaderSlicesView {
         Ok(())
     }
 }
-
-fn format_counters(counters: Vec<(HeaderSliceStatus, usize)>) -> String {
-    let mut line = String::new();
-    for (status, count) in counters {
-        line.push_str(std::format!("({}): {}; ", char::from(status), count).as_str());
-    }
-    line
-}
@@ -1,13 +1,6 @@
 mod average_delta_counter;
+mod header_slices_view;
 
 use super::{super::ui::ui_view, headers};
 
-#[cfg(feature = "crossterm")]
-mod ui_crossterm;
-#[cfg(feature = "crossterm")]
-pub use ui_crossterm::HeaderSlicesView;
-
-#[cfg(not(feature = "crossterm"))]
-mod ui_tracing;
-#[cfg(not(feature = "crossterm"))]
-pub use ui_tracing::HeaderSlicesView;
+pub use header_slices_view::HeaderSlicesView;
@@ -14,3 +14,5 @@ mod downloader_stage_loop;
 
 #[cfg(test)]
 mod downloader_tests;
+
+pub use headers::header_slices::{HeaderSliceStatus, HeaderSlicesProgress};
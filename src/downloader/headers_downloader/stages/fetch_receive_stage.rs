@@ -2,10 +2,15 @@ use super::headers::{
     header::BlockHeader,
     header_slices::{HeaderSlice, HeaderSliceStatus, HeaderSlices},
 };
-use crate::sentry::{
-    messages::{BlockHeadersMessage, EthMessageId, Message},
-    sentry_client::PeerId,
-    sentry_client_reactor::*,
+use crate::{
+    crypto::keccak256,
+    models::BlockHeader as HeaderType,
+    sentry::{
+        dedup::PeerDedupCache,
+        messages::{BlockHeadersMessage, EthMessageId, Message},
+        sentry_client::PeerId,
+        sentry_client_reactor::*,
+    },
 };
 use futures_core::Stream;
 use std::{
@@ -24,12 +29,18 @@ struct BlockHeadersMessageFromPeer {
 
 type BlockHeadersMessageStream = Pin<Box<dyn Stream<Item = BlockHeadersMessageFromPeer> + Send>>;
 
+const DEDUP_MAX_PEERS: usize = 128;
+const DEDUP_SLICES_PER_PEER: usize = 1024;
+
 /// Receives the slices, and sets Downloaded status.
 pub struct FetchReceiveStage {
     header_slices: Arc<HeaderSlices>,
     sentry: SentryClientReactorShared,
     is_over: Arc<AtomicBool>,
     message_stream: Mutex<Option<BlockHeadersMessageStream>>,
+    /// Slices accepted from each peer, so that a peer re-sending one doesn't get it verified
+    /// again.
+    accepted: PeerDedupCache,
 }
 
 impl FetchReceiveStage {
@@ -39,6 +50,11 @@ impl FetchReceiveStage {
             sentry,
             is_over: Arc::new(false.into()),
             message_stream: Mutex::new(None),
+            accepted: PeerDedupCache::new(
+                EthMessageId::BlockHeaders,
+                DEDUP_MAX_PEERS,
+                DEDUP_SLICES_PER_PEER,
+            ),
         }
     }
 
@@ -72,6 +88,18 @@ impl FetchReceiveStage {
             return;
         }
 
+        let from_peer_id = message_from_peer.from_peer_id;
+        let slice_hash = keccak256(rlp::encode_list::<HeaderType, _>(&headers[..]));
+        if let Some(peer_id) = from_peer_id {
+            if self.accepted.contains(peer_id, slice_hash) {
+                debug!(
+                    "FetchReceiveStage ignores a headers slice already received from peer {:?}",
+                    peer_id
+                );
+                return;
+            }
+        }
+
         let start_block_num = headers[0].number;
         let slice_lock_opt = self.header_slices.find_by_start_block_num(start_block_num);
 
@@ -80,10 +108,12 @@ impl FetchReceiveStage {
                 let mut slice = slice_lock.write();
                 let slice_status = slice.status;
                 if slice_status == HeaderSliceStatus::Waiting {
-                    let from_peer_id = message_from_peer.from_peer_id;
                     let headers: Vec<BlockHeader> =
                         headers.into_iter().map(BlockHeader::from).collect();
                     self.update_slice(slice.deref_mut(), headers, from_peer_id);
+                    if let Some(peer_id) = from_peer_id {
+                        self.accepted.insert(peer_id, slice_hash);
+                    }
                 } else {
                     debug!("FetchReceiveStage ignores a headers slice that we didn't request starting at: {:?}; status = {:?}", start_block_num, slice_status);
                 }
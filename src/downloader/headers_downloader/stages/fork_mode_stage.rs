@@ -221,14 +221,14 @@ impl ForkModeStage {
     fn refetch_canonical_slice(&self, slice: &mut HeaderSlice) {
         self.header_slices
             .set_slice_status(slice, HeaderSliceStatus::Empty);
-        slice.headers = None;
+        slice.clear_headers();
         slice.refetch_attempt += 1;
     }
 
     fn refetch_fork_slice(&self, slice: &mut HeaderSlice) {
         self.fork_header_slices
             .set_slice_status(slice, HeaderSliceStatus::Empty);
-        slice.headers = None;
+        slice.clear_headers();
         slice.refetch_attempt += 1;
     }
 
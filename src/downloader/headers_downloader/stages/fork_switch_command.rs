@@ -99,7 +99,7 @@ impl ForkSwitchCommand {
             // reset the status
             self.header_slices
                 .set_slice_status(slice, HeaderSliceStatus::Empty);
-            slice.headers = None;
+            slice.clear_headers();
             slice.refetch_attempt = 0;
 
             num = BlockNumber(num.0 + len as u64);
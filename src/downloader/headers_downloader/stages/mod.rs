@@ -27,7 +27,7 @@ pub use refill_stage::RefillStage;
 pub use retry_stage::RetryStage;
 pub use save_stage::SaveStage;
 pub use timeout_stage::TimeoutStage;
-pub use top_block_estimate_stage::TopBlockEstimateStage;
+pub use top_block_estimate_stage::{TopBlockEstimateChannel, TopBlockEstimateStage};
 pub use verify_link_forky_stage::VerifyLinkForkyStage;
 pub use verify_link_linear_stage::VerifyLinkLinearStage;
 pub use verify_preverified_stage::VerifyPreverifiedStage;
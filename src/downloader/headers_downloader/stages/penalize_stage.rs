@@ -2,11 +2,22 @@ use super::headers::{
     header_slice_status_watch::HeaderSliceStatusWatch,
     header_slices::{HeaderSliceStatus, HeaderSlices},
 };
-use crate::sentry::{sentry_client::PeerId, sentry_client_reactor::*};
+use crate::sentry::{metrics::peer_label, sentry_client::PeerId, sentry_client_reactor::*};
+use once_cell::sync::Lazy;
 use parking_lot::RwLockUpgradableReadGuard;
+use prometheus::{register_int_counter_vec, IntCounterVec};
 use std::{collections::HashSet, ops::DerefMut, sync::Arc};
 use tracing::*;
 
+static BAD_SLICES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "downloader_bad_header_slices_total",
+        "Header slices that failed verification, by the peer that supplied them",
+        &["peer"]
+    )
+    .unwrap()
+});
+
 /// Penalize peers for sending us headers that failed to verify, and mark the related slices as Empty for retry.
 pub struct PenalizeStage {
     header_slices: Arc<HeaderSlices>,
@@ -53,8 +64,14 @@ impl PenalizeStage {
             let slice = slice_lock.read();
             if slice.status == HeaderSliceStatus::Invalid {
                 match slice.from_peer_id {
-                    Some(from_peer_id) => { peers.insert(from_peer_id); }
-                    None => warn!("PenalizeStage: got an invalid headers slice from an unknown peer starting at: {:?}", slice.start_block_num),
+                    Some(from_peer_id) => {
+                        BAD_SLICES.with_label_values(&[&peer_label(from_peer_id)]).inc();
+                        peers.insert(from_peer_id);
+                    }
+                    None => {
+                        BAD_SLICES.with_label_values(&["unknown"]).inc();
+                        warn!("PenalizeStage: got an invalid headers slice from an unknown peer starting at: {:?}", slice.start_block_num);
+                    }
                 }
             }
         });
@@ -68,7 +85,7 @@ impl PenalizeStage {
                 let mut slice = RwLockUpgradableReadGuard::upgrade(slice);
                 self.header_slices
                     .set_slice_status(slice.deref_mut(), HeaderSliceStatus::Empty);
-                slice.headers = None;
+                slice.clear_headers();
             }
         });
     }
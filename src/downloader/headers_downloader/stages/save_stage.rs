@@ -4,6 +4,7 @@ use super::headers::{
     header_slices::{HeaderSlice, HeaderSliceStatus, HeaderSlices},
 };
 use crate::{
+    accessors::chain,
     kv::{
         mdbx::MdbxTransaction,
         tables::{self, HeaderKey},
@@ -203,8 +204,9 @@ where
         header: &BlockHeader,
         tx: &'tx MdbxTransaction<'db, RW, E>,
     ) -> anyhow::Result<Option<U256>> {
-        let Some(parent_total_difficulty) = Self::read_parent_header_total_difficulty(header, tx)? else {
-            return Ok(None)
+        let Some(parent_total_difficulty) = Self::read_parent_header_total_difficulty(header, tx)?
+        else {
+            return Ok(None);
         };
         let total_difficulty = parent_total_difficulty + header.difficulty();
         Ok(Some(total_difficulty))
@@ -250,7 +252,7 @@ where
         let header_key: HeaderKey = (block_num, header_hash);
 
         tx.set(tables::CanonicalHeader, block_num, header_hash)?;
-        tx.set(tables::LastHeader, Default::default(), header_hash)?;
+        chain::last_header::write(tx, header_hash)?;
 
         let total_difficulty_opt = Self::header_total_difficulty(header, tx)?;
         if let Some(total_difficulty) = total_difficulty_opt {
@@ -276,7 +278,7 @@ where
         // update LastHeader to point to unwind_to_block_num
         let last_header_hash_opt = tx.get(tables::CanonicalHeader, unwind_to_block_num)?;
         if let Some(hash) = last_header_hash_opt {
-            tx.set(tables::LastHeader, Default::default(), hash)?;
+            chain::last_header::write(tx, hash)?;
         } else {
             anyhow::bail!(
                 "unwind: not found header hash of the top block after unwind {}",
@@ -1,6 +1,7 @@
 use crate::{
     models::BlockNumber,
     sentry::{
+        dedup::PeerDedupCache,
         messages::{EthMessageId, Message, NewBlockHashesMessage},
         sentry_client::PeerId,
         sentry_client_reactor::*,
@@ -14,7 +15,7 @@ use std::{
     pin::Pin,
     sync::{atomic::*, Arc},
 };
-use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::{watch, Mutex as AsyncMutex};
 use tokio_stream::StreamExt;
 use tracing::*;
 
@@ -26,21 +27,73 @@ struct NewBlockHashesMessageFromPeer {
 type NewBlockHashesMessageStream =
     Pin<Box<dyn Stream<Item = NewBlockHashesMessageFromPeer> + Send>>;
 
+/// How many peers need to announce their heads before the estimate is trusted.
+const MIN_PEERS_QUORUM: usize = 3;
+
+const DEDUP_MAX_PEERS: usize = 128;
+const DEDUP_HASHES_PER_PEER: usize = 256;
+
+/// Channel of the top block estimate that can be shared by consecutive stages, so that
+/// observers keep a single subscription across downloader runs.
+#[derive(Clone, Debug)]
+pub struct TopBlockEstimateChannel {
+    sender: Arc<watch::Sender<Option<BlockNumber>>>,
+    receiver: watch::Receiver<Option<BlockNumber>>,
+}
+
+impl TopBlockEstimateChannel {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(None);
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<BlockNumber>> {
+        self.receiver.clone()
+    }
+
+    fn publish(&self, estimate: BlockNumber) {
+        if *self.receiver.borrow() != Some(estimate) {
+            // Never fails, as the channel keeps a receiver of its own.
+            let _ = self.sender.send(Some(estimate));
+        }
+    }
+}
+
+impl Default for TopBlockEstimateChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Listen to new block hashes announces to estimate the current top block number.
+/// The estimate is the median of the heads announced by at least MIN_PEERS_QUORUM peers,
+/// so that a single peer on a fork or lying about its head can't skew it.
+/// Every time the median moves, it is published to the subscribers of the estimate channel.
 pub struct TopBlockEstimateStage {
     sentry: SentryClientReactorShared,
+    estimate: TopBlockEstimateChannel,
     is_over: Arc<AtomicBool>,
     message_stream: AsyncMutex<Option<NewBlockHashesMessageStream>>,
     peer_top_blocks: Arc<Mutex<HashMap<PeerId, BlockNumber>>>,
+    announced: PeerDedupCache,
 }
 
 impl TopBlockEstimateStage {
-    pub fn new(sentry: SentryClientReactorShared) -> Self {
+    pub fn new(sentry: SentryClientReactorShared, estimate: TopBlockEstimateChannel) -> Self {
         Self {
             sentry,
+            estimate,
             is_over: Arc::new(false.into()),
             message_stream: AsyncMutex::new(None),
             peer_top_blocks: Arc::new(Mutex::new(HashMap::<PeerId, BlockNumber>::new())),
+            announced: PeerDedupCache::new(
+                EthMessageId::NewBlockHashes,
+                DEDUP_MAX_PEERS,
+                DEDUP_HASHES_PER_PEER,
+            ),
         }
     }
 
@@ -79,8 +132,12 @@ impl TopBlockEstimateStage {
             .message
             .ids
             .iter()
+            .filter(|id| self.announced.insert(from_peer_id, id.hash))
             .map(|id| id.number)
             .collect::<Vec<BlockNumber>>();
+        if block_nums.is_empty() {
+            return;
+        }
         let mut peer_top_blocks = self.peer_top_blocks.lock();
         if let Some(current_peer_top_block) = peer_top_blocks.get(&from_peer_id) {
             block_nums.push(*current_peer_top_block);
@@ -88,6 +145,10 @@ impl TopBlockEstimateStage {
 
         let peer_top_block = block_nums.iter().max_by_key(|num| num.0).unwrap();
         peer_top_blocks.insert(from_peer_id, *peer_top_block);
+
+        if let Some(estimate) = median_peer_block_num(&peer_top_blocks, MIN_PEERS_QUORUM) {
+            self.estimate.publish(estimate);
+        }
     }
 
     fn receive_messages(
@@ -114,7 +175,7 @@ impl TopBlockEstimateStage {
     pub fn estimated_top_block_num_provider(&self) -> impl Fn() -> Option<BlockNumber> {
         let peer_top_blocks = self.peer_top_blocks.clone();
         move || -> Option<BlockNumber> {
-            Self::average_peer_block_num(peer_top_blocks.lock().deref())
+            median_peer_block_num(peer_top_blocks.lock().deref(), MIN_PEERS_QUORUM)
         }
     }
 
@@ -122,22 +183,25 @@ impl TopBlockEstimateStage {
         self.estimated_top_block_num_provider()()
     }
 
-    fn average_peer_block_num(peer_blocks: &HashMap<PeerId, BlockNumber>) -> Option<BlockNumber> {
-        if peer_blocks.is_empty() {
-            return None;
-        }
-
-        let block_nums = peer_blocks.values().map(|num| num.0);
-        let average_block_num = BlockNumber(block_nums.sum::<u64>() / (peer_blocks.len() as u64));
-        Some(average_block_num)
-    }
-
     pub fn can_proceed_check(&self) -> impl Fn() -> bool {
         let is_over = self.is_over.clone();
         move || -> bool { !is_over.load(Ordering::SeqCst) }
     }
 }
 
+fn median_peer_block_num(
+    peer_blocks: &HashMap<PeerId, BlockNumber>,
+    quorum: usize,
+) -> Option<BlockNumber> {
+    if peer_blocks.is_empty() || peer_blocks.len() < quorum {
+        return None;
+    }
+
+    let mut block_nums = peer_blocks.values().copied().collect::<Vec<_>>();
+    block_nums.sort_unstable();
+    Some(block_nums[block_nums.len() / 2])
+}
+
 #[async_trait::async_trait]
 impl super::stage::Stage for TopBlockEstimateStage {
     async fn execute(&mut self) -> anyhow::Result<()> {
@@ -147,3 +211,37 @@ impl super::stage::Stage for TopBlockEstimateStage {
         Box::new(Self::can_proceed_check(self))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_peer_block_num() {
+        let mut peer_blocks = HashMap::new();
+        peer_blocks.insert(PeerId::repeat_byte(1), BlockNumber(100));
+        peer_blocks.insert(PeerId::repeat_byte(2), BlockNumber(1_000_000));
+        assert_eq!(median_peer_block_num(&peer_blocks, 3), None);
+
+        peer_blocks.insert(PeerId::repeat_byte(3), BlockNumber(102));
+        assert_eq!(
+            median_peer_block_num(&peer_blocks, 3),
+            Some(BlockNumber(102))
+        );
+    }
+
+    #[test]
+    fn estimate_channel() {
+        let channel = TopBlockEstimateChannel::new();
+        let receiver = channel.subscribe();
+        assert_eq!(*receiver.borrow(), None);
+
+        channel.publish(BlockNumber(102));
+        assert_eq!(*receiver.borrow(), Some(BlockNumber(102)));
+        // Subscribers of later runs see the latest estimate.
+        assert_eq!(
+            *channel.clone().subscribe().borrow(),
+            Some(BlockNumber(102))
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use super::{
     headers::{
+        header,
         header_slice_status_watch::HeaderSliceStatusWatch,
         header_slices,
         header_slices::{HeaderSlice, HeaderSliceStatus, HeaderSlices},
@@ -85,9 +86,7 @@ impl VerifyPreverifiedStage {
 
     fn prepare_slice_hashes(slice: &mut HeaderSlice) {
         if let Some(headers) = slice.headers.as_mut() {
-            for header in headers {
-                header.hash_prepare();
-            }
+            header::BlockHeader::hash_prepare_batch(headers);
         }
     }
 
@@ -1,5 +1,6 @@
 use super::{
     headers::{
+        header::BlockHeader,
         header_slice_status_watch::HeaderSliceStatusWatch,
         header_slices::{HeaderSlice, HeaderSliceStatus, HeaderSlices},
     },
@@ -84,9 +85,7 @@ impl VerifySlicesStage {
 
     fn prepare_slice_hashes(slice: &mut HeaderSlice) {
         if let Some(headers) = slice.headers.as_mut() {
-            for header in headers {
-                header.hash_prepare();
-            }
+            BlockHeader::hash_prepare_batch(headers);
         }
     }
 
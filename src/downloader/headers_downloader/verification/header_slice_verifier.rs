@@ -2,7 +2,11 @@ use super::{
     super::headers::header::BlockHeader, preverified_hashes_config::PreverifiedHashesConfig,
 };
 use crate::{
-    consensus::difficulty::{canonical_difficulty, BlockDifficultyBombData},
+    consensus::{
+        aura_difficulty,
+        difficulty::{canonical_difficulty, BlockDifficultyBombData},
+        validate_header_fields,
+    },
     models::{switch_is_active, BlockNumber, ChainSpec, SealVerificationParams, EMPTY_LIST_HASH},
 };
 use std::fmt::Debug;
@@ -46,6 +50,7 @@ impl HeaderSliceVerifier for EthashHeaderSliceVerifier {
         verify_link_by_parent_hash(child, parent)
             && verify_link_block_nums(child, parent)
             && verify_link_timestamps(child, parent)
+            && verify_link_header_fields(child, parent, chain_spec)
             && verify_link_difficulties(child, parent, chain_spec)
             && verify_link_pow(child, parent)
     }
@@ -60,6 +65,7 @@ impl HeaderSliceVerifier for EthashHeaderSliceVerifier {
         verify_slice_is_linked_by_parent_hash(headers)
             && verify_slice_block_nums(headers, start_block_num)
             && verify_slice_timestamps(headers, max_timestamp)
+            && verify_slice_header_fields(headers, chain_spec)
             && verify_slice_difficulties(headers, chain_spec)
             && verify_slice_pow(headers)
     }
@@ -90,6 +96,14 @@ fn verify_link_timestamps(child: &BlockHeader, parent: &BlockHeader) -> bool {
     parent_timestamp < child_timestamp
 }
 
+fn verify_link_header_fields(
+    child: &BlockHeader,
+    parent: &BlockHeader,
+    chain_spec: &ChainSpec,
+) -> bool {
+    validate_header_fields(chain_spec, &child.header, &parent.header).is_ok()
+}
+
 fn verify_link_difficulties(
     child: &BlockHeader,
     parent: &BlockHeader,
@@ -103,6 +117,16 @@ fn verify_link_difficulties(
                 difficulty_bomb,
                 ..
             } => (byzantium_formula, homestead_formula, difficulty_bomb),
+            SealVerificationParams::Aura { .. } => {
+                return match (child.header.aura_seal, parent.header.aura_seal) {
+                    (Some(seal), Some(parent_seal)) => {
+                        child.difficulty() == aura_difficulty(parent_seal.step, seal.step)
+                    }
+                    _ => false,
+                };
+            }
+            // Depends on the producers of the span, which headers are downloaded ahead of.
+            SealVerificationParams::Bor { .. } => return true,
             _ => {
                 panic!("unsupported consensus engine");
             }
@@ -181,6 +205,12 @@ fn verify_slice_timestamps(headers: &[BlockHeader], max_timestamp: u64) -> bool
     last_timestamp < max_timestamp
 }
 
+/// Verify gas limit, extra data and base fee against the consensus rules.
+fn verify_slice_header_fields(headers: &[BlockHeader], chain_spec: &ChainSpec) -> bool {
+    enumerate_sequential_pairs(headers)
+        .all(|(parent, child)| verify_link_header_fields(child, parent, chain_spec))
+}
+
 /// Verify that difficulty field is calculated properly.
 fn verify_slice_difficulties(headers: &[BlockHeader], chain_spec: &ChainSpec) -> bool {
     enumerate_sequential_pairs(headers)
@@ -0,0 +1,306 @@
+//! Client of the REST API of Heimdall, which elects the producers of Bor spans and relays
+//! state-sync events from Ethereum.
+use crate::{models::*, util::hexbytes};
+use anyhow::{ensure, format_err, Context};
+use bytes::Bytes;
+use hyper::{client::HttpConnector, header, Body, Client, Request, StatusCode};
+use serde::Deserialize;
+
+/// Events fetched per request, the most Heimdall returns.
+const EVENTS_PAGE_SIZE: usize = 50;
+
+#[derive(Deserialize)]
+struct HeimdallResponse<T> {
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct HeimdallSpan {
+    span_id: u64,
+    start_block: u64,
+    end_block: u64,
+    validator_set: HeimdallValidatorSet,
+    selected_producers: Vec<HeimdallValidator>,
+}
+
+#[derive(Deserialize)]
+struct HeimdallValidatorSet {
+    validators: Vec<HeimdallValidator>,
+}
+
+#[derive(Deserialize)]
+struct HeimdallValidator {
+    #[serde(rename = "ID")]
+    id: u64,
+    power: u64,
+    signer: Address,
+}
+
+impl From<HeimdallValidator> for BorValidator {
+    fn from(validator: HeimdallValidator) -> Self {
+        Self {
+            id: validator.id,
+            address: validator.signer,
+            voting_power: validator.power,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HeimdallEventRecord {
+    id: u64,
+    contract: Address,
+    #[serde(with = "hexbytes")]
+    data: Bytes,
+    tx_hash: H256,
+    log_index: u64,
+    bor_chain_id: String,
+    record_time: String,
+}
+
+/// Seconds since the epoch of an RFC 3339 UTC time such as `2020-05-30T16:31:12.390218776Z`,
+/// rounded down.
+fn parse_record_time(s: &str) -> anyhow::Result<u64> {
+    let parse = || -> Option<u64> {
+        let (date, time) = s.strip_suffix('Z')?.split_once('T')?;
+        let mut date = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+        let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+        let time = time.split('.').next()?;
+        let mut time = time.splitn(3, ':').map(|v| v.parse::<i64>().ok());
+        let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+
+        // Days from the epoch of the proleptic Gregorian date, see
+        // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        (days * 86_400 + hour * 3_600 + minute * 60 + second)
+            .try_into()
+            .ok()
+    };
+
+    parse().ok_or_else(|| format_err!("invalid record time {}", s))
+}
+
+fn parse_span(body: &[u8]) -> anyhow::Result<BorSpan> {
+    let span = serde_json::from_slice::<HeimdallResponse<HeimdallSpan>>(body)
+        .context("malformed span response")?
+        .result
+        .ok_or_else(|| format_err!("span response without result"))?;
+
+    Ok(BorSpan {
+        id: span.span_id,
+        start_block: BlockNumber(span.start_block),
+        end_block: BlockNumber(span.end_block),
+        validators: span
+            .validator_set
+            .validators
+            .into_iter()
+            .map(From::from)
+            .collect(),
+        selected_producers: span
+            .selected_producers
+            .into_iter()
+            .map(From::from)
+            .collect(),
+    })
+}
+
+fn parse_events(body: &[u8]) -> anyhow::Result<Vec<StateSyncEvent>> {
+    serde_json::from_slice::<HeimdallResponse<Vec<HeimdallEventRecord>>>(body)
+        .context("malformed event record response")?
+        .result
+        .unwrap_or_default()
+        .into_iter()
+        .map(|record| {
+            Ok(StateSyncEvent {
+                id: record.id,
+                contract: record.contract,
+                data: record.data,
+                tx_hash: record.tx_hash,
+                log_index: record.log_index,
+                chain_id: record.bor_chain_id,
+                time: parse_record_time(&record.record_time)?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct HeimdallClient {
+    url: String,
+    client: Client<HttpConnector>,
+}
+
+impl HeimdallClient {
+    /// Client of the Heimdall REST API at `url`, e.g. `http://localhost:1317`.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            client: Client::new(),
+        }
+    }
+
+    async fn get(&self, path: String) -> anyhow::Result<hyper::body::Bytes> {
+        let request = Request::get(format!("{}{}", self.url, path))
+            .header(header::ACCEPT, "application/json")
+            .body(Body::empty())?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .with_context(|| format!("failed to reach Heimdall at {}", self.url))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        ensure!(
+            status == StatusCode::OK,
+            "Heimdall returned {} for {}: {}",
+            status,
+            path,
+            String::from_utf8_lossy(&body)
+        );
+
+        Ok(body)
+    }
+
+    pub async fn fetch_span(&self, id: u64) -> anyhow::Result<BorSpan> {
+        parse_span(&self.get(format!("/bor/span/{}", id)).await?)
+    }
+
+    /// State-sync events from `from_id` on, recorded before `to_time`, in order.
+    pub async fn fetch_events(
+        &self,
+        from_id: u64,
+        to_time: u64,
+    ) -> anyhow::Result<Vec<StateSyncEvent>> {
+        let mut events = Vec::new();
+        loop {
+            let page = parse_events(
+                &self
+                    .get(format!(
+                        "/clerk/event-record/list?from-id={}&to-time={}&limit={}",
+                        from_id + events.len() as u64,
+                        to_time,
+                        EVENTS_PAGE_SIZE
+                    ))
+                    .await?,
+            )?;
+            let full = page.len() == EVENTS_PAGE_SIZE;
+            events.extend(page);
+            if !full {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn record_time() {
+        assert_eq!(
+            parse_record_time("2020-05-30T16:31:12.390218776Z").unwrap(),
+            1_590_856_272
+        );
+        assert_eq!(
+            parse_record_time("2024-02-29T00:00:00Z").unwrap(),
+            1_709_164_800
+        );
+        assert_eq!(parse_record_time("1970-01-01T00:00:00Z").unwrap(), 0);
+        parse_record_time("2020-05-30T16:31:12+02:00").unwrap_err();
+        parse_record_time("2020-13-30T16:31:12Z").unwrap_err();
+    }
+
+    #[test]
+    fn span_and_events() {
+        let validator = r#"{
+            "ID": 3,
+            "startEpoch": 0,
+            "endEpoch": 0,
+            "nonce": 1,
+            "power": 10000,
+            "pubKey": "0x04",
+            "signer": "0x1efecb61a2f80aa34d3b9f3bf3b0fdb9b9f9ab34",
+            "last_updated": "0",
+            "jailed": false,
+            "accum": -40000
+        }"#;
+        let span = parse_span(
+            format!(
+                r#"{{
+                    "height": "100",
+                    "result": {{
+                        "span_id": 1,
+                        "start_block": 256,
+                        "end_block": 6655,
+                        "validator_set": {{"validators": [{v}], "proposer": {v}}},
+                        "selected_producers": [{v}],
+                        "bor_chain_id": "137"
+                    }}
+                }}"#,
+                v = validator
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let validator = BorValidator {
+            id: 3,
+            address: hex!("1efecb61a2f80aa34d3b9f3bf3b0fdb9b9f9ab34").into(),
+            voting_power: 10000,
+        };
+        assert_eq!(
+            span,
+            BorSpan {
+                id: 1,
+                start_block: BlockNumber(256),
+                end_block: BlockNumber(6655),
+                validators: vec![validator.clone()],
+                selected_producers: vec![validator],
+            }
+        );
+
+        let events = parse_events(
+            br#"{
+                "height": "100",
+                "result": [{
+                    "id": 1,
+                    "contract": "0x8397259c983751daf40400790063935a11afa28a",
+                    "data": "0x01ff",
+                    "tx_hash": "0xa2d3bbd3e2a53e0e5f0b9d6e36e9b2d41d2b8f2e1c7e1a8b0a0e8e8b6e2d0a1f",
+                    "log_index": 2,
+                    "bor_chain_id": "137",
+                    "record_time": "2020-05-30T16:31:12.390218776Z"
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            vec![StateSyncEvent {
+                id: 1,
+                contract: hex!("8397259c983751daf40400790063935a11afa28a").into(),
+                data: vec![0x01, 0xff].into(),
+                tx_hash: hex!("a2d3bbd3e2a53e0e5f0b9d6e36e9b2d41d2b8f2e1c7e1a8b0a0e8e8b6e2d0a1f")
+                    .into(),
+                log_index: 2,
+                chain_id: "137".into(),
+                time: 1_590_856_272,
+            }]
+        );
+
+        assert!(parse_events(br#"{"height": "100", "result": null}"#)
+            .unwrap()
+            .is_empty());
+    }
+}
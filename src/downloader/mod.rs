@@ -1,5 +1,9 @@
+pub mod beacon_checkpoint;
+pub mod body_withholding;
+pub mod heimdall;
 pub mod opts;
 pub mod sentry_status_provider;
+pub mod top_block_estimate;
 pub mod ui;
 
 mod headers_downloader;
@@ -11,4 +15,5 @@ pub use headers_downloader::{
         DownloaderUnwindRequest as HeadersDownloaderUnwindRequest,
     },
     verification::header_slice_verifier,
+    HeaderSliceStatus, HeaderSlicesProgress,
 };
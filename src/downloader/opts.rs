@@ -1,10 +1,16 @@
+use super::ui::ui_system::UIMode;
+use crate::sentry::{
+    sentry_client_connector::SentryClientConnector,
+    sentry_client_recorder::{Recording, SentryClientConnectorRecorder},
+};
 use clap::Parser;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 pub struct Opts {
     #[clap(
         long = "downloader.headers-mem-limit",
-        help = "How much memory in Mb to allocate for the active parallel download window.",
+        help = "Upper limit in Mb of memory for the active parallel download window, which is sized adaptively below it.",
         default_value = "50"
     )]
     pub headers_mem_limit_mb: u32,
@@ -14,6 +20,27 @@ pub struct Opts {
         default_value = "100000"
     )]
     pub headers_batch_size: usize,
+    #[clap(
+        long = "downloader.bodies-batch-size",
+        help = "How many block bodies to ask a peer for in one request.",
+        default_value = "128"
+    )]
+    pub bodies_batch_size: usize,
+    #[clap(
+        long = "downloader.bodies-era1-dir",
+        help = "Directory of era1 archives to read the bodies that peers withhold from."
+    )]
+    pub bodies_era1_dir: Option<PathBuf>,
+    #[clap(
+        long = "ui",
+        help = "How download progress is shown: off, log for a summary logged periodically, or tty for a view redrawn at the top of the terminal. tty if stdout is a terminal, log otherwise, by default."
+    )]
+    pub ui: Option<UIMode>,
+    #[clap(
+        long = "downloader.record",
+        help = "Record the messages exchanged with the sentry to this file, to replay the header download offline with `martinez-toolbox download-headers --replay`."
+    )]
+    pub record: Option<PathBuf>,
 }
 
 impl Opts {
@@ -22,4 +49,23 @@ impl Opts {
             .try_into()
             .unwrap_or(usize::MAX)
     }
+
+    pub fn ui_mode(&self) -> UIMode {
+        self.ui.unwrap_or_else(UIMode::detect)
+    }
+
+    /// `connector`, recording the session if asked to.
+    pub fn sentry_connector(
+        &self,
+        connector: Box<dyn SentryClientConnector>,
+        chain_name: &str,
+    ) -> anyhow::Result<Box<dyn SentryClientConnector>> {
+        Ok(match &self.record {
+            Some(path) => Box::new(SentryClientConnectorRecorder::new(
+                connector,
+                Recording::create(path, chain_name)?,
+            )),
+            None => connector,
+        })
+    }
 }
@@ -1,4 +1,5 @@
 use crate::{
+    accessors::chain,
     kv::{
         mdbx::MdbxTransaction,
         tables::{self, HeaderKey},
@@ -51,9 +52,8 @@ impl SentryStatusProvider {
         &self,
         tx: &MdbxTransaction<'db, RW, E>,
     ) -> anyhow::Result<Status> {
-        let header_hash = tx
-            .get(tables::LastHeader, Default::default())?
-            .ok_or(SentryStatusProviderError::StatusDataNotFound)?;
+        let header_hash =
+            chain::last_header::read(tx)?.ok_or(SentryStatusProviderError::StatusDataNotFound)?;
 
         let block_num = tx
             .get(tables::HeaderNumber, header_hash)?
@@ -0,0 +1,100 @@
+//! Top block estimate of the header downloader, published to a file in the data directory as
+//! soon as it changes, so that readers in other processes such as the RPC daemon see it while
+//! the headers stage still holds its write transaction.
+use crate::models::BlockNumber;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use tokio::sync::watch;
+use tracing::*;
+
+const FILE: &str = "TOP_BLOCK_ESTIMATE";
+
+#[derive(Clone, Debug)]
+pub struct PublishedTopBlockEstimate {
+    dir: PathBuf,
+}
+
+impl PublishedTopBlockEstimate {
+    /// Estimate published in data directory `dir`.
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(FILE)
+    }
+
+    /// Latest published estimate, if any.
+    pub fn read(&self) -> anyhow::Result<Option<BlockNumber>> {
+        match std::fs::read_to_string(self.path()) {
+            Ok(s) => Ok(Some(BlockNumber(s.trim().parse().with_context(|| {
+                format!("invalid top block estimate in {}", self.dir.display())
+            })?))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| {
+                format!(
+                    "failed to read top block estimate in {}",
+                    self.dir.display()
+                )
+            }),
+        }
+    }
+
+    fn write(&self, estimate: BlockNumber) -> anyhow::Result<()> {
+        // Moved into place, so that readers never see a partial write.
+        let tmp_path = self.dir.join(format!("{}.tmp", FILE));
+        std::fs::write(&tmp_path, estimate.0.to_string())
+            .and_then(|_| std::fs::rename(&tmp_path, self.path()))
+            .with_context(|| {
+                format!(
+                    "failed to publish top block estimate in {}",
+                    self.dir.display()
+                )
+            })
+    }
+
+    /// Publish every estimate received from `estimates`, until its sender is dropped.
+    pub async fn publish(
+        self,
+        mut estimates: watch::Receiver<Option<BlockNumber>>,
+    ) -> anyhow::Result<()> {
+        loop {
+            let estimate = *estimates.borrow();
+            if let Some(estimate) = estimate {
+                self.write(estimate)?;
+                debug!("Published top block estimate {}", estimate);
+            }
+
+            if estimates.changed().await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn publish() {
+        let dir = tempfile::tempdir().unwrap();
+        let published = PublishedTopBlockEstimate::new(dir.path());
+        assert_eq!(published.read().unwrap(), None);
+
+        let (sender, receiver) = watch::channel(None);
+        let publisher = tokio::spawn(published.clone().publish(receiver));
+
+        sender.send(Some(BlockNumber(15_000_000))).unwrap();
+        while published.read().unwrap().is_none() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(published.read().unwrap(), Some(BlockNumber(15_000_000)));
+
+        drop(sender);
+        publisher.await.unwrap().unwrap();
+        assert_eq!(published.read().unwrap(), Some(BlockNumber(15_000_000)));
+    }
+}
@@ -1,13 +1,68 @@
 use super::ui_view::UIView;
 use parking_lot::Mutex;
-use std::{sync::Arc, time::Duration};
+use std::{str::FromStr, sync::Arc, time::Duration};
 use tokio::{
     sync::{mpsc, Mutex as AsyncMutex},
     task::JoinHandle,
 };
 use tracing::*;
 
+/// How often [`UIMode::Log`] logs a summary of the view.
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How the downloader shows its progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UIMode {
+    /// Not at all.
+    Off,
+    /// Summaries logged periodically, for output that is not a terminal, such as under systemd or
+    /// docker.
+    Log,
+    /// View redrawn every second at the top of the terminal.
+    #[cfg(feature = "crossterm")]
+    Tty,
+}
+
+impl UIMode {
+    /// [`Self::Tty`] if stdout is a terminal it can be drawn on, [`Self::Log`] otherwise.
+    pub fn detect() -> Self {
+        #[cfg(feature = "crossterm")]
+        if atty::is(atty::Stream::Stdout) {
+            return Self::Tty;
+        }
+
+        Self::Log
+    }
+
+    fn interval(self) -> Duration {
+        match self {
+            Self::Off | Self::Log => LOG_INTERVAL,
+            #[cfg(feature = "crossterm")]
+            Self::Tty => Duration::from_secs(1),
+        }
+    }
+}
+
+impl FromStr for UIMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "log" => Ok(Self::Log),
+            #[cfg(feature = "crossterm")]
+            "tty" => Ok(Self::Tty),
+            #[cfg(not(feature = "crossterm"))]
+            "tty" => Err(anyhow::format_err!(
+                "tty UI not available, built without the crossterm feature"
+            )),
+            _ => Err(anyhow::format_err!("expected off, log or tty, got {}", s)),
+        }
+    }
+}
+
 pub struct UISystem {
+    mode: UIMode,
     view_cell: Arc<Mutex<Option<Box<dyn UIView>>>>,
     event_loop: Option<UISystemEventLoop>,
     event_loop_handle: Option<JoinHandle<()>>,
@@ -21,23 +76,25 @@ pub struct UISystemViewScope {
 }
 
 struct UISystemEventLoop {
+    mode: UIMode,
     view_cell: Arc<Mutex<Option<Box<dyn UIView>>>>,
     stop_signal_receiver: mpsc::Receiver<()>,
 }
 
 impl UISystem {
-    #[allow(clippy::new_without_default)]
-    pub fn new() -> Self {
+    pub fn new(mode: UIMode) -> Self {
         let view_cell = Arc::new(Mutex::new(None));
 
         let (stop_signal_sender, stop_signal_receiver) = mpsc::channel::<()>(1);
 
         let event_loop = UISystemEventLoop {
+            mode,
             view_cell: Arc::clone(&view_cell),
             stop_signal_receiver,
         };
 
         Self {
+            mode,
             view_cell: Arc::clone(&view_cell),
             event_loop: Some(event_loop),
             event_loop_handle: None,
@@ -50,6 +107,10 @@ impl UISystem {
             .event_loop
             .take()
             .ok_or_else(|| anyhow::format_err!("already started once"))?;
+        if self.mode == UIMode::Off {
+            return Ok(());
+        }
+
         let handle = tokio::spawn(async move {
             let result = event_loop.run().await;
             if let Err(error) = result {
@@ -107,10 +168,15 @@ impl UISystemEventLoop {
 
         loop {
             tokio::select! {
-                _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                _ = tokio::time::sleep(self.mode.interval()) => {
                     let view_cell = self.view_cell.lock();
                     if let Some(view) = &*view_cell {
-                        _ = view.draw()?;
+                        match self.mode {
+                            UIMode::Off => {}
+                            UIMode::Log => view.log()?,
+                            #[cfg(feature = "crossterm")]
+                            UIMode::Tty => view.draw()?,
+                        }
                     }
                 }
                 Some(_) = stop_signal_receiver.recv() => {
@@ -1,3 +1,7 @@
 pub trait UIView: Send {
+    /// Log a one-line summary.
+    fn log(&self) -> anyhow::Result<()>;
+    /// Redraw the view at the top of the terminal.
+    #[cfg(feature = "crossterm")]
     fn draw(&self) -> anyhow::Result<()>;
 }
@@ -0,0 +1,434 @@
+//! Era1 archives of pre-merge history, as produced and consumed by other clients.
+//!
+//! An era1 file is an e2store stream: a version entry, then a compressed header, body,
+//! receipts list and total difficulty for each of up to [`MAX_ERA1_SIZE`] blocks,
+//! an accumulator over the header records and finally a block index.
+use crate::models::*;
+use anyhow::{bail, ensure, format_err};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+
+/// Number of blocks in a full era1 file.
+pub const MAX_ERA1_SIZE: usize = 8192;
+
+const HEADER_LENGTH: u64 = 8;
+
+pub mod entry_type {
+    pub const VERSION: u16 = 0x3265;
+    pub const COMPRESSED_HEADER: u16 = 0x03;
+    pub const COMPRESSED_BODY: u16 = 0x04;
+    pub const COMPRESSED_RECEIPTS: u16 = 0x05;
+    pub const TOTAL_DIFFICULTY: u16 = 0x06;
+    pub const ACCUMULATOR: u16 = 0x07;
+    pub const BLOCK_INDEX: u16 = 0x3266;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Era1Block {
+    pub header: BlockHeader,
+    pub body: BlockBody,
+    /// RLP list of the block's receipts in network encoding.
+    pub receipts: Bytes,
+    pub total_difficulty: U256,
+}
+
+pub fn encode_receipts(receipts: &[Receipt]) -> Bytes {
+    rlp::encode_list(receipts).freeze()
+}
+
+/// Canonical file name, e.g. `mainnet-00000-5ec1ffb8.era1`.
+pub fn file_name(network: &str, epoch: u64, accumulator_root: H256) -> String {
+    format!(
+        "{}-{:05}-{}.era1",
+        network.to_lowercase(),
+        epoch,
+        hex::encode(&accumulator_root[..4])
+    )
+}
+
+fn write_entry<W: Write>(w: &mut W, ty: u16, data: &[u8]) -> anyhow::Result<u64> {
+    let mut header = [0; HEADER_LENGTH as usize];
+    header[..2].copy_from_slice(&ty.to_le_bytes());
+    header[2..6].copy_from_slice(&u32::try_from(data.len())?.to_le_bytes());
+    w.write_all(&header)?;
+    w.write_all(data)?;
+
+    Ok(HEADER_LENGTH + data.len() as u64)
+}
+
+fn read_entry<R: Read>(r: &mut R) -> anyhow::Result<Option<(u16, Vec<u8>)>> {
+    let mut header = [0; HEADER_LENGTH as usize];
+    let mut read = 0;
+    while read < header.len() {
+        let n = r.read(&mut header[read..])?;
+        if n == 0 {
+            ensure!(read == 0, "truncated entry header");
+            return Ok(None);
+        }
+        read += n;
+    }
+
+    let ty = u16::from_le_bytes([header[0], header[1]]);
+    let len = u32::from_le_bytes([header[2], header[3], header[4], header[5]]);
+    ensure!(
+        header[6..] == [0, 0],
+        "reserved bytes of entry {:#x} are not zero",
+        ty
+    );
+
+    let mut data = vec![0; len as usize];
+    r.read_exact(&mut data)?;
+
+    Ok(Some((ty, data)))
+}
+
+fn compress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = snap::write::FrameEncoder::new(Vec::new());
+    encoder.write_all(data)?;
+    Ok(encoder.into_inner()?)
+}
+
+fn decompress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    snap::read::FrameDecoder::new(data).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+fn sha256(a: &[u8], b: &[u8]) -> H256 {
+    H256::from_slice(&Sha256::new().chain_update(a).chain_update(b).finalize())
+}
+
+/// SSZ `hash_tree_root` of `List[HeaderRecord, MAX_ERA1_SIZE]`,
+/// where `HeaderRecord` is `(block_hash: Bytes32, total_difficulty: uint256)`.
+pub fn accumulator_root(records: &[(H256, U256)]) -> H256 {
+    assert!(records.len() <= MAX_ERA1_SIZE);
+
+    let mut layer = records
+        .iter()
+        .map(|(hash, td)| sha256(hash.as_bytes(), &td.to_le_bytes()))
+        .collect::<Vec<_>>();
+
+    let mut zero_hash = H256::zero();
+    for _ in 0..MAX_ERA1_SIZE.trailing_zeros() {
+        if layer.len() % 2 == 1 {
+            layer.push(zero_hash);
+        }
+        layer = layer
+            .chunks(2)
+            .map(|pair| sha256(pair[0].as_bytes(), pair[1].as_bytes()))
+            .collect();
+        zero_hash = sha256(zero_hash.as_bytes(), zero_hash.as_bytes());
+    }
+    let root = layer.pop().unwrap_or(zero_hash);
+
+    let mut length = [0; 32];
+    length[..8].copy_from_slice(&(records.len() as u64).to_le_bytes());
+    sha256(root.as_bytes(), &length)
+}
+
+/// Writes blocks of a single era into an era1 stream.
+#[derive(Debug)]
+pub struct Era1Writer<W: Write> {
+    w: W,
+    written: u64,
+    starting_number: Option<BlockNumber>,
+    offsets: Vec<u64>,
+    records: Vec<(H256, U256)>,
+}
+
+impl<W: Write> Era1Writer<W> {
+    pub fn new(mut w: W) -> anyhow::Result<Self> {
+        let written = write_entry(&mut w, entry_type::VERSION, &[])?;
+        Ok(Self {
+            w,
+            written,
+            starting_number: None,
+            offsets: Vec::new(),
+            records: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, block: &Era1Block) -> anyhow::Result<()> {
+        ensure!(self.records.len() < MAX_ERA1_SIZE, "era is full");
+        let starting_number = *self.starting_number.get_or_insert(block.header.number);
+        ensure!(
+            block.header.number == starting_number + self.records.len() as u64,
+            "block {} is not contiguous with the era starting at {}",
+            block.header.number,
+            starting_number
+        );
+
+        self.offsets.push(self.written);
+        for (ty, data) in [
+            (
+                entry_type::COMPRESSED_HEADER,
+                compress(&rlp::encode(&block.header))?,
+            ),
+            (
+                entry_type::COMPRESSED_BODY,
+                compress(&rlp::encode(&block.body))?,
+            ),
+            (entry_type::COMPRESSED_RECEIPTS, compress(&block.receipts)?),
+            (
+                entry_type::TOTAL_DIFFICULTY,
+                block.total_difficulty.to_le_bytes().to_vec(),
+            ),
+        ] {
+            self.written += write_entry(&mut self.w, ty, &data)?;
+        }
+        self.records
+            .push((block.header.hash(), block.total_difficulty));
+
+        Ok(())
+    }
+
+    /// Writes the accumulator and block index, returning the accumulator root.
+    pub fn finish(mut self) -> anyhow::Result<H256> {
+        let starting_number = self
+            .starting_number
+            .ok_or_else(|| format_err!("era is empty"))?;
+
+        let root = accumulator_root(&self.records);
+        self.written += write_entry(&mut self.w, entry_type::ACCUMULATOR, root.as_bytes())?;
+
+        // Offsets are relative to the start of the index entry.
+        let mut index = Vec::with_capacity(16 + self.offsets.len() * 8);
+        index.extend_from_slice(&starting_number.0.to_le_bytes());
+        for offset in &self.offsets {
+            index.extend_from_slice(&(*offset as i64 - self.written as i64).to_le_bytes());
+        }
+        index.extend_from_slice(&(self.offsets.len() as u64).to_le_bytes());
+        write_entry(&mut self.w, entry_type::BLOCK_INDEX, &index)?;
+
+        self.w.flush()?;
+
+        Ok(root)
+    }
+}
+
+/// Reads blocks from an era1 stream, verifying the accumulator and block index once the last block is read.
+#[derive(Debug)]
+pub struct Era1Reader<R: Read> {
+    r: R,
+    read: u64,
+    starting_number: Option<BlockNumber>,
+    offsets: Vec<u64>,
+    records: Vec<(H256, U256)>,
+    accumulator_root: Option<H256>,
+}
+
+impl<R: Read> Era1Reader<R> {
+    pub fn new(mut r: R) -> anyhow::Result<Self> {
+        let (ty, data) = read_entry(&mut r)?.ok_or_else(|| format_err!("empty era1 file"))?;
+        ensure!(
+            ty == entry_type::VERSION && data.is_empty(),
+            "not an era1 file"
+        );
+
+        Ok(Self {
+            r,
+            read: HEADER_LENGTH,
+            starting_number: None,
+            offsets: Vec::new(),
+            records: Vec::new(),
+            accumulator_root: None,
+        })
+    }
+
+    fn next_entry(&mut self, expected: u16) -> anyhow::Result<Vec<u8>> {
+        let (ty, data) =
+            read_entry(&mut self.r)?.ok_or_else(|| format_err!("unexpected end of era1 file"))?;
+        ensure!(
+            ty == expected,
+            "unexpected entry {:#x}, expected {:#x}",
+            ty,
+            expected
+        );
+        self.read += HEADER_LENGTH + data.len() as u64;
+
+        Ok(data)
+    }
+
+    /// Next block of the era, `None` after the last one.
+    pub fn next_block(&mut self) -> anyhow::Result<Option<Era1Block>> {
+        if self.accumulator_root.is_some() {
+            return Ok(None);
+        }
+
+        let offset = self.read;
+        let (ty, data) =
+            read_entry(&mut self.r)?.ok_or_else(|| format_err!("unexpected end of era1 file"))?;
+        self.read += HEADER_LENGTH + data.len() as u64;
+
+        match ty {
+            entry_type::COMPRESSED_HEADER => {}
+            entry_type::ACCUMULATOR => {
+                self.finish(data)?;
+                return Ok(None);
+            }
+            other => bail!("unexpected entry {:#x}, expected header", other),
+        }
+
+        let header = rlp::decode::<BlockHeader>(&decompress(&data)?)?;
+        let body =
+            rlp::decode::<BlockBody>(&decompress(&self.next_entry(entry_type::COMPRESSED_BODY)?)?)?;
+        let receipts = decompress(&self.next_entry(entry_type::COMPRESSED_RECEIPTS)?)?.into();
+        let total_difficulty = self.next_entry(entry_type::TOTAL_DIFFICULTY)?;
+        let total_difficulty = U256::from_le_bytes(
+            total_difficulty
+                .try_into()
+                .map_err(|_| format_err!("invalid total difficulty entry"))?,
+        );
+
+        let starting_number = *self.starting_number.get_or_insert(header.number);
+        ensure!(
+            header.number == starting_number + self.records.len() as u64,
+            "block {} is not contiguous with the era starting at {}",
+            header.number,
+            starting_number
+        );
+
+        self.offsets.push(offset);
+        self.records.push((header.hash(), total_difficulty));
+
+        Ok(Some(Era1Block {
+            header,
+            body,
+            receipts,
+            total_difficulty,
+        }))
+    }
+
+    fn finish(&mut self, accumulator: Vec<u8>) -> anyhow::Result<()> {
+        ensure!(accumulator.len() == 32, "invalid accumulator entry");
+        let root = H256::from_slice(&accumulator);
+        let computed = accumulator_root(&self.records);
+        ensure!(
+            root == computed,
+            "accumulator mismatch: file has {:?}, computed {:?}",
+            root,
+            computed
+        );
+
+        let index_offset = self.read;
+        let index = self.next_entry(entry_type::BLOCK_INDEX)?;
+        ensure!(
+            index.len() == 16 + self.offsets.len() * 8,
+            "block index size does not match {} blocks",
+            self.offsets.len()
+        );
+        let starting_number = u64::from_le_bytes(index[..8].try_into().unwrap());
+        ensure!(
+            self.starting_number.map(|n| n.0) == Some(starting_number),
+            "block index starts at {}, file at {:?}",
+            starting_number,
+            self.starting_number
+        );
+        let count = u64::from_le_bytes(index[index.len() - 8..].try_into().unwrap());
+        ensure!(
+            count == self.offsets.len() as u64,
+            "block index count {} does not match {} blocks",
+            count,
+            self.offsets.len()
+        );
+        for (i, offset) in self.offsets.iter().enumerate() {
+            let relative = i64::from_le_bytes(index[8 + i * 8..16 + i * 8].try_into().unwrap());
+            ensure!(
+                index_offset as i64 + relative == *offset as i64,
+                "block index offset mismatch for block #{}",
+                i
+            );
+        }
+
+        self.accumulator_root = Some(root);
+
+        Ok(())
+    }
+
+    /// Accumulator root, available once all blocks have been read.
+    pub fn accumulator_root(&self) -> Option<H256> {
+        self.accumulator_root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64, total_difficulty: u64) -> Era1Block {
+        let receipts = vec![Receipt::new(TxType::Legacy, true, 21_000, vec![])];
+        Era1Block {
+            header: BlockHeader {
+                number: BlockNumber(number),
+                difficulty: 0x20000.as_u256(),
+                gas_used: 21_000,
+                ..BlockHeader::empty()
+            },
+            body: BlockBody {
+                transactions: vec![],
+                ommers: vec![],
+            },
+            receipts: encode_receipts(&receipts),
+            total_difficulty: total_difficulty.as_u256(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let blocks = (0..3)
+            .map(|i| block(8192 + i, 0x20000 * (i + 1)))
+            .collect::<Vec<_>>();
+
+        let mut writer = Era1Writer::new(Vec::new()).unwrap();
+        for block in &blocks {
+            writer.push(block).unwrap();
+        }
+        writer.push(&block(9000, 1)).unwrap_err();
+
+        let mut out = Vec::new();
+        let mut writer = Era1Writer::new(&mut out).unwrap();
+        for block in &blocks {
+            writer.push(block).unwrap();
+        }
+        let root = writer.finish().unwrap();
+        assert_eq!(
+            root,
+            accumulator_root(
+                &blocks
+                    .iter()
+                    .map(|b| (b.header.hash(), b.total_difficulty))
+                    .collect::<Vec<_>>()
+            )
+        );
+        assert_ne!(root, accumulator_root(&[]));
+
+        let mut reader = Era1Reader::new(out.as_slice()).unwrap();
+        let mut read = Vec::new();
+        while let Some(block) = reader.next_block().unwrap() {
+            read.push(block);
+        }
+        assert_eq!(read, blocks);
+        assert_eq!(reader.accumulator_root(), Some(root));
+        assert_eq!(
+            file_name("Mainnet", 1, root),
+            format!("mainnet-00001-{}.era1", hex::encode(&root[..4]))
+        );
+
+        // Tampering with a total difficulty breaks the accumulator.
+        let td_offset = out
+            .windows(8)
+            .position(|w| w == [0x06, 0, 32, 0, 0, 0, 0, 0])
+            .unwrap();
+        out[td_offset + 8] ^= 1;
+        let mut reader = Era1Reader::new(out.as_slice()).unwrap();
+        let res = (|| {
+            while reader.next_block()?.is_some() {}
+            Ok::<_, anyhow::Error>(())
+        })();
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("accumulator mismatch"));
+    }
+}
@@ -1,10 +1,39 @@
 use super::evm::AnalyzedCode;
+use bytes::Bytes;
 use ethereum_types::H256;
 use lru::LruCache;
 
+/// Format version of analyses persisted in [`crate::kv::tables::CodeAnalysis`], bump on any
+/// change to the encoding or to what analysis computes. Entries of other versions are ignored
+/// and overwritten with fresh ones.
+pub const ANALYSIS_FORMAT_VERSION: u8 = 1;
+
+/// Code below this size is analyzed faster than its analysis is read back from the database.
+pub const MIN_PERSISTED_CODE_SIZE: usize = 4096;
+
+/// Encode `analysis` for [`crate::kv::tables::CodeAnalysis`].
+pub fn encode_analysis(analysis: &AnalyzedCode) -> Bytes {
+    let mut out = vec![ANALYSIS_FORMAT_VERSION];
+    out.extend_from_slice(&analysis.jumpdest_bitmap());
+    out.into()
+}
+
+/// Restore analysis of `code` from [`encode_analysis`] output, `None` if it is of another format
+/// version or does not match the code.
+pub fn decode_analysis(code: &[u8], encoded: &[u8]) -> Option<AnalyzedCode> {
+    match encoded.split_first() {
+        Some((&ANALYSIS_FORMAT_VERSION, bitmap)) => {
+            AnalyzedCode::from_jumpdest_bitmap(code, bitmap)
+        }
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 pub struct AnalysisCache {
     inner: LruCache<H256, AnalyzedCode>,
+    /// Encoded analyses of large code made since the last [`Self::take_unpersisted`].
+    unpersisted: Vec<(H256, Bytes)>,
 }
 
 impl Default for AnalysisCache {
@@ -17,6 +46,7 @@ impl AnalysisCache {
     pub fn new(cap: usize) -> Self {
         Self {
             inner: LruCache::new(cap),
+            unpersisted: Vec::new(),
         }
     }
 
@@ -27,4 +57,58 @@ impl AnalysisCache {
     pub fn put(&mut self, code_hash: H256, code: AnalyzedCode) {
         self.inner.put(code_hash, code);
     }
+
+    /// Like [`Self::put`], for analysis that was just made rather than loaded from the database.
+    pub fn put_new(&mut self, code_hash: H256, code: AnalyzedCode) {
+        if code.code().len() >= MIN_PERSISTED_CODE_SIZE {
+            self.unpersisted.push((code_hash, encode_analysis(&code)));
+        }
+        self.put(code_hash, code);
+    }
+
+    /// Analyses to write into [`crate::kv::tables::CodeAnalysis`].
+    pub fn take_unpersisted(&mut self) -> Vec<(H256, Bytes)> {
+        std::mem::take(&mut self.unpersisted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::evm::OpCode;
+
+    #[test]
+    fn analysis_roundtrip() {
+        // JUMPDEST, PUSH2 with a JUMPDEST byte in its immediate, a run of JUMPDESTs, then a
+        // PUSH32 running past the end.
+        let mut code = vec![
+            OpCode::JUMPDEST.to_u8(),
+            OpCode::PUSH2.to_u8(),
+            OpCode::JUMPDEST.to_u8(),
+            0x00,
+        ];
+        code.extend(std::iter::repeat(OpCode::JUMPDEST.to_u8()).take(MIN_PERSISTED_CODE_SIZE));
+        code.push(OpCode::PUSH32.to_u8());
+        code.push(0x01);
+
+        let analysis = AnalyzedCode::analyze(&code);
+        let encoded = encode_analysis(&analysis);
+        let decoded = decode_analysis(&code, &encoded).unwrap();
+        assert_eq!(decoded.jumpdest_bitmap(), analysis.jumpdest_bitmap());
+        assert_eq!(decoded.jumpdest_bitmap()[0], 0b1111_0001);
+        assert_eq!(decoded.code(), analysis.code());
+
+        assert!(decode_analysis(&code[..code.len() - 8], &encoded).is_none());
+        let mut outdated = encoded.to_vec();
+        outdated[0] = ANALYSIS_FORMAT_VERSION.wrapping_add(1);
+        assert!(decode_analysis(&code, &outdated).is_none());
+
+        let code_hash = H256::repeat_byte(1);
+        let mut cache = AnalysisCache::default();
+        cache.put(code_hash, decoded);
+        assert!(cache.take_unpersisted().is_empty());
+        cache.put_new(code_hash, analysis);
+        assert_eq!(cache.take_unpersisted(), vec![(code_hash, encoded)]);
+        assert!(cache.take_unpersisted().is_empty());
+    }
 }
@@ -118,6 +118,51 @@ impl AnalyzedCode {
         }
     }
 
+    /// The code, without padding.
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Positions of valid jump destinations as a bitmap over the code, least significant bit
+    /// first.
+    pub fn jumpdest_bitmap(&self) -> Vec<u8> {
+        let mut bitmap = vec![0; (self.jumpdest_map.0.len() + 7) / 8];
+        for (i, &jumpdest) in self.jumpdest_map.0.iter().enumerate() {
+            if jumpdest {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        bitmap
+    }
+
+    /// Restore analysis of `code` from its [`Self::jumpdest_bitmap`], `None` if the bitmap is
+    /// not sized for the code.
+    pub fn from_jumpdest_bitmap(code: &[u8], bitmap: &[u8]) -> Option<Self> {
+        if bitmap.len() != (code.len() + 7) / 8 {
+            return None;
+        }
+
+        let jumpdest_map = JumpdestMap(
+            (0..code.len())
+                .map(|i| bitmap[i / 8] & (1 << (i % 8)) != 0)
+                .collect(),
+        );
+
+        // Room for the immediate of a PUSH32 at the very end, plus the final STOP.
+        let code_len = code.len();
+        let mut padded_code = code.to_vec();
+        padded_code.resize(code_len + 33, OpCode::STOP.to_u8());
+        let padded_code = Bytes::from(padded_code);
+        let mut code = padded_code.clone();
+        code.truncate(code_len);
+
+        Some(Self {
+            jumpdest_map,
+            code,
+            padded_code,
+        })
+    }
+
     /// Execute analyzed EVM bytecode using provided `Host` context.
     pub fn execute<H, T>(
         self,
@@ -504,7 +549,13 @@ where
                 return Err(StatusCode::InvalidInstruction);
             }
             OpCode::SELFDESTRUCT => {
+                // The host has no access to the tracer, so the destruction is reported here,
+                // with the balance about to be sent.
+                let address = state.message.recipient;
+                let beneficiary = u256_to_address(*state.stack.get(0));
+                let balance = host.get_balance(address);
                 selfdestruct!(state, host, REVISION);
+                tracer.capture_self_destruct(address, beneficiary, balance);
                 break;
             }
             other => {
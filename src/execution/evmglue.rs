@@ -1,6 +1,6 @@
 use super::{
     address::*,
-    analysis_cache::AnalysisCache,
+    analysis_cache::{decode_analysis, AnalysisCache, MIN_PERSISTED_CODE_SIZE},
     precompiled,
     tracer::{CodeKind, MessageKind, Tracer},
 };
@@ -146,7 +146,7 @@ where
             // https://github.com/ethereum/EIPs/issues/684
             res.status_code = StatusCode::InvalidInstruction;
             res.gas_left = 0;
-            return Ok(res);
+            return Ok(self.exit(message.depth, res));
         }
 
         let snapshot = self.state.take_snapshot();
@@ -159,6 +159,14 @@ where
 
         self.state.subtract_from_balance(message.sender, value)?;
         self.state.add_to_balance(contract_addr, value)?;
+        if message.depth > 0 && value != 0 {
+            self.state.add_internal_transfer(InternalTransfer {
+                kind: TransferKind::Create,
+                from: message.sender,
+                to: contract_addr,
+                value,
+            });
+        }
 
         let deploy_message = InterpreterMessage {
             kind: CallKind::Call,
@@ -200,6 +208,10 @@ where
 
         if res.status_code == StatusCode::Success {
             res.create_address = Some(contract_addr);
+            self.state.add_created_contract(ContractCreation {
+                creator: message.sender,
+                address: contract_addr,
+            });
         } else {
             self.state.revert_to_snapshot(snapshot);
             if res.status_code != StatusCode::Revert {
@@ -207,10 +219,11 @@ where
             }
         }
 
-        Ok(res)
+        Ok(self.exit(message.depth, res))
     }
 
     fn call(&mut self, message: InterpreterMessage) -> anyhow::Result<Output> {
+        let depth = message.depth;
         let mut res = Output {
             status_code: StatusCode::Success,
             gas_left: message.gas,
@@ -267,7 +280,7 @@ where
             && !precompiled
             && !self.state.exists(message.code_address)?
         {
-            return Ok(res);
+            return Ok(self.exit(depth, res));
         }
 
         let snapshot = self.state.take_snapshot();
@@ -280,6 +293,14 @@ where
             } else {
                 self.state.subtract_from_balance(message.sender, value)?;
                 self.state.add_to_balance(message.recipient, value)?;
+                if message.depth > 0 && value != 0 {
+                    self.state.add_internal_transfer(InternalTransfer {
+                        kind: TransferKind::Call,
+                        from: message.sender,
+                        to: message.recipient,
+                        value,
+                    });
+                }
             }
         }
 
@@ -305,7 +326,7 @@ where
         } else {
             let code = code.unwrap_or_default();
             if code.is_empty() {
-                return Ok(res);
+                return Ok(self.exit(depth, res));
             }
 
             let code_hash = self.state.get_code_hash(message.code_address)?;
@@ -320,7 +341,16 @@ where
             }
         }
 
-        Ok(res)
+        Ok(self.exit(depth, res))
+    }
+
+    /// Reports the result of a message whose start was traced.
+    fn exit(&mut self, depth: i32, res: Output) -> Output {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.capture_exit(depth.try_into().unwrap(), &res);
+        }
+
+        res
     }
 
     fn execute(
@@ -334,8 +364,20 @@ where
             if let Some(cache) = self.analysis_cache.get(code_hash) {
                 cache
             } else {
-                let analysis = AnalyzedCode::analyze(&code);
-                self.analysis_cache.put(code_hash, analysis);
+                let persisted = if code.len() >= MIN_PERSISTED_CODE_SIZE {
+                    self.state
+                        .db()
+                        .read_code_analysis(code_hash)?
+                        .and_then(|encoded| decode_analysis(&code, &encoded))
+                } else {
+                    None
+                };
+                match persisted {
+                    Some(analysis) => self.analysis_cache.put(code_hash, analysis),
+                    None => self
+                        .analysis_cache
+                        .put_new(code_hash, AnalyzedCode::analyze(&code)),
+                }
                 self.analysis_cache.get(code_hash).unwrap()
             }
         } else {
@@ -549,6 +591,14 @@ impl<'r, 'state, 'tracer, 'analysis, 'h, 'c, 't, 'a, B: State> Host
             .add_to_balance(beneficiary, balance)
             .unwrap();
         self.inner.state.set_balance(address, 0).unwrap();
+        if balance != 0 {
+            self.inner.state.add_internal_transfer(InternalTransfer {
+                kind: TransferKind::SelfDestruct,
+                from: address,
+                to: beneficiary,
+                value: balance,
+            });
+        }
 
         // if let Some(tracer) = &mut self.tracer {
         //     tracer.capture_self_destruct(address, beneficiary);
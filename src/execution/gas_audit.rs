@@ -0,0 +1,229 @@
+//! Audit of a block whose gas used does not match its header: it is executed again with
+//! instruction tracing, and the gas of each transaction is reported with the opcodes that spent
+//! it, to show where execution diverges from the rest of the network.
+use super::{
+    analysis_cache::AnalysisCache,
+    evm::{ExecutionState, OpCode},
+    processor::ExecutionProcessor,
+    tracer::Tracer,
+};
+use crate::{consensus::Consensus, models::*, State};
+use serde::Serialize;
+use std::{collections::HashMap, fmt::Display};
+
+/// Opcodes listed for each transaction.
+const TOP_OPS: usize = 10;
+
+/// Instruction about to be executed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpContext {
+    pub op: &'static str,
+    pub pc: usize,
+    pub depth: u16,
+    pub gas_left: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxGasReport {
+    pub index: usize,
+    pub gas_used: u64,
+    pub cumulative_gas_used: u64,
+    pub success: bool,
+    /// Gas spent by opcode, calls and creations including what their callees spent, most
+    /// expensive first.
+    pub top_ops: Vec<(&'static str, u64)>,
+    /// Last instruction the transaction executed.
+    pub last_op: Option<OpContext>,
+}
+
+/// Report of a block whose gas used differs from its header.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GasDivergence {
+    pub block_number: BlockNumber,
+    pub block_hash: H256,
+    pub expected: u64,
+    pub got: u64,
+    pub transactions: Vec<TxGasReport>,
+}
+
+impl Display for GasDivergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "block #{} ({:?}) used {} gas, header says {}: {}",
+            self.block_number,
+            self.block_hash,
+            self.got,
+            self.expected,
+            serde_json::to_string(&self.transactions).map_err(|_| std::fmt::Error)?
+        )
+    }
+}
+
+impl std::error::Error for GasDivergence {}
+
+/// Profile of one transaction, see [`TxGasReport`].
+#[derive(Debug, Default)]
+struct TxProfile {
+    op_gas: HashMap<u8, u64>,
+    last_op: Option<OpContext>,
+}
+
+#[derive(Debug, Default)]
+struct GasAuditTracer {
+    /// Last instruction of each frame being executed, not accounted for yet.
+    pending: Vec<(u16, OpCode, i64)>,
+    current: TxProfile,
+    done: Vec<TxProfile>,
+}
+
+impl Tracer for GasAuditTracer {
+    fn trace_instructions(&self) -> bool {
+        true
+    }
+
+    fn capture_state(&mut self, env: &ExecutionState, pc: usize, op: OpCode, _: u64, depth: u16) {
+        let gas_left = *env.gas_left();
+
+        // Frames above this one are over. Their last instructions are left out: the gas left
+        // after them is not traced.
+        while matches!(self.pending.last(), Some((d, ..)) if *d > depth) {
+            self.pending.pop();
+        }
+        if matches!(self.pending.last(), Some((d, ..)) if *d == depth) {
+            let (_, prev_op, prev_gas_left) = self.pending.pop().unwrap();
+            *self.current.op_gas.entry(prev_op.0).or_default() +=
+                prev_gas_left.saturating_sub(gas_left).max(0) as u64;
+        }
+        self.pending.push((depth, op, gas_left));
+
+        self.current.last_op = Some(OpContext {
+            op: op.name(),
+            pc,
+            depth,
+            gas_left,
+        });
+    }
+
+    // Called once at the end of every transaction.
+    fn capture_contract_creations(&mut self, _: &[ContractCreation]) {
+        self.pending.clear();
+        self.done.push(std::mem::take(&mut self.current));
+    }
+}
+
+/// Executes `block` again on top of `state`, which must not include it, without writing
+/// anything, and reports the gas of every transaction.
+#[allow(clippy::too_many_arguments)]
+pub fn audit_block_gas<S: State>(
+    state: &mut S,
+    analysis_cache: &mut AnalysisCache,
+    engine: &mut dyn Consensus,
+    header: &PartialHeader,
+    block_hash: H256,
+    block: &BlockBodyWithSenders,
+    block_spec: &BlockExecutionSpec,
+) -> anyhow::Result<GasDivergence> {
+    let mut tracer = GasAuditTracer::default();
+    let receipts = ExecutionProcessor::new(
+        state,
+        Some(&mut tracer),
+        analysis_cache,
+        engine,
+        header,
+        block,
+        block_spec,
+    )
+    .execute_block_no_post_validation()?;
+
+    let mut previous_cumulative = 0;
+    let transactions = receipts
+        .iter()
+        .zip(tracer.done)
+        .enumerate()
+        .map(|(index, (receipt, profile))| {
+            let mut top_ops = profile
+                .op_gas
+                .into_iter()
+                .map(|(op, gas)| (OpCode(op).name(), gas))
+                .collect::<Vec<_>>();
+            top_ops.sort_by(|(a_op, a_gas), (b_op, b_gas)| b_gas.cmp(a_gas).then(a_op.cmp(b_op)));
+            top_ops.truncate(TOP_OPS);
+
+            let gas_used = receipt.cumulative_gas_used - previous_cumulative;
+            previous_cumulative = receipt.cumulative_gas_used;
+
+            TxGasReport {
+                index,
+                gas_used,
+                cumulative_gas_used: receipt.cumulative_gas_used,
+                success: receipt.success,
+                top_ops,
+                last_op: profile.last_op,
+            }
+        })
+        .collect();
+
+    Ok(GasDivergence {
+        block_number: header.number,
+        block_hash,
+        expected: header.gas_used,
+        got: previous_cumulative,
+        transactions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::evm::{CallKind, InterpreterMessage};
+
+    fn state(gas_left: i64) -> ExecutionState {
+        ExecutionState::new(InterpreterMessage {
+            kind: CallKind::Call,
+            is_static: false,
+            depth: 0,
+            gas: gas_left,
+            recipient: Address::zero(),
+            sender: Address::zero(),
+            input_data: Default::default(),
+            value: U256::ZERO,
+            code_address: Address::zero(),
+        })
+    }
+
+    #[test]
+    fn op_gas() {
+        let mut tracer = GasAuditTracer::default();
+
+        tracer.capture_state(&state(1000), 0, OpCode::PUSH1, 3, 0);
+        tracer.capture_state(&state(997), 2, OpCode::CALL, 700, 0);
+        tracer.capture_state(&state(600), 0, OpCode::SSTORE, 0, 1);
+        tracer.capture_state(&state(100), 1, OpCode::STOP, 0, 1);
+        tracer.capture_state(&state(497), 3, OpCode::STOP, 0, 0);
+        tracer.capture_contract_creations(&[]);
+
+        let profile = &tracer.done[0];
+        assert_eq!(profile.op_gas[&OpCode::PUSH1.0], 3);
+        // Including the callee.
+        assert_eq!(profile.op_gas[&OpCode::CALL.0], 500);
+        assert_eq!(profile.op_gas[&OpCode::SSTORE.0], 500);
+        assert!(!profile.op_gas.contains_key(&OpCode::STOP.0));
+        assert_eq!(
+            profile.last_op,
+            Some(OpContext {
+                op: "STOP",
+                pc: 3,
+                depth: 0,
+                gas_left: 497
+            })
+        );
+
+        // Next transaction starts afresh.
+        tracer.capture_contract_creations(&[]);
+        assert_eq!(tracer.done[1].last_op, None);
+    }
+}
@@ -1,12 +1,19 @@
 use self::{analysis_cache::AnalysisCache, processor::ExecutionProcessor};
-use crate::{consensus, crypto::*, models::*, State};
+use crate::{
+    consensus, crypto::*, kv::mdbx::*, models::*, trie, Buffer, State, Witness, WitnessRecorder,
+    WitnessState,
+};
 
 pub mod address;
 pub mod analysis_cache;
 pub mod evm;
 pub mod evmglue;
+pub mod gas_audit;
+pub mod optimism;
+pub mod outcome;
 pub mod precompiled;
 pub mod processor;
+pub mod simulation;
 pub mod tracer;
 
 pub fn execute_block<S: State>(
@@ -30,14 +37,69 @@ pub fn execute_block<S: State>(
     .execute_and_write_block()
 }
 
+/// Executes a block, collecting the witness needed to execute it again without `state`.
+pub fn execute_block_with_witness<S: State>(
+    state: &mut S,
+    config: &ChainSpec,
+    header: &PartialHeader,
+    block: &BlockBodyWithSenders,
+) -> anyhow::Result<(Vec<Receipt>, Witness)> {
+    let mut recorder = WitnessRecorder::new(state);
+    let receipts = execute_block(&mut recorder, config, header, block)?;
+    Ok((receipts, recorder.into_witness()))
+}
+
+/// Executes a block on top of the current state of the database, collecting a witness that
+/// proves what the block read against the state root of its parent. The hashed state and the
+/// intermediate hashes have to be at the parent block. Nothing is written to the database.
+pub fn execute_block_with_proven_witness<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    config: &ChainSpec,
+    header: &PartialHeader,
+    block: &BlockBodyWithSenders,
+) -> anyhow::Result<(Vec<Receipt>, Witness)>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut state = Buffer::new(txn, BlockNumber(0), None);
+    let (receipts, mut witness) = execute_block_with_witness(&mut state, config, header, block)?;
+    witness.proof = trie::prove(
+        txn,
+        witness.accounts.keys().copied(),
+        witness.storage.iter().flat_map(|(&address, storage)| {
+            storage.keys().map(move |&location| (address, location))
+        }),
+    )?;
+    Ok((receipts, witness))
+}
+
+/// Executes a block against a witness only, without access to the database.
+pub fn execute_block_statelessly(
+    witness: Witness,
+    config: &ChainSpec,
+    header: &PartialHeader,
+    block: &BlockBodyWithSenders,
+) -> anyhow::Result<(Vec<Receipt>, WitnessState)> {
+    let mut state = WitnessState::new(witness);
+    let receipts = execute_block(&mut state, config, header, block)?;
+    Ok((receipts, state))
+}
+
 #[cfg(test)]
 mod tests {
     use super::{address::create_address, *};
     use crate::{
-        chain::protocol_param::param, crypto::root_hash, res::chainspec::MAINNET, InMemoryState,
+        chain::protocol_param::param,
+        crypto::root_hash,
+        genesis::initialize_genesis,
+        kv::{new_mem_database, tables},
+        res::chainspec::MAINNET,
+        InMemoryState,
     };
     use hex_literal::hex;
     use sha3::{Digest, Keccak256};
+    use tempfile::TempDir;
 
     #[test]
     fn compute_receipt_root() {
@@ -81,6 +143,7 @@ mod tests {
             cumulative_gas_used: gas_used,
             bloom: Bloom::zero(),
             logs: vec![],
+            deposit_nonce: None,
         }];
 
         let header = PartialHeader {
@@ -141,6 +204,7 @@ mod tests {
             &BlockBodyWithSenders {
                 transactions: vec![tx],
                 ommers: vec![],
+                rollup_cost_data: vec![],
             },
         )
         .unwrap();
@@ -192,6 +256,7 @@ mod tests {
             &BlockBodyWithSenders {
                 transactions: vec![tx],
                 ommers: vec![],
+                rollup_cost_data: vec![],
             },
         )
         .unwrap();
@@ -203,4 +268,152 @@ mod tests {
         assert!(miner_account.balance > 2 * param::BLOCK_REWARD_CONSTANTINOPLE);
         assert!(miner_account.balance < 3 * param::BLOCK_REWARD_CONSTANTINOPLE);
     }
+
+    #[test]
+    fn execute_from_witness() {
+        let miner = hex!("5a0b54d5dc17e0aadc383d2db43b0a0d3e029c4c").into();
+        let sender = hex!("b685342b8c54347aad148e1f22eff3eb3eb29391").into();
+        let recipient = hex!("71562b71999873db5b286df957af199ec94617f7").into();
+
+        let gas_used = 21_000;
+        let receipts = vec![Receipt {
+            tx_type: TxType::Legacy,
+            success: true,
+            cumulative_gas_used: gas_used,
+            bloom: Bloom::zero(),
+            logs: vec![],
+            deposit_nonce: None,
+        }];
+        let header = PartialHeader {
+            number: 13_500_001.into(),
+            beneficiary: miner,
+            gas_limit: 100_000,
+            gas_used,
+            receipts_root: root_hash(&receipts),
+            ..PartialHeader::empty()
+        };
+        let block = BlockBodyWithSenders {
+            transactions: vec![MessageWithSender {
+                message: Message::Legacy {
+                    chain_id: Some(ChainId(1)),
+                    nonce: 0,
+                    gas_price: U256::ZERO,
+                    gas_limit: gas_used,
+                    action: TransactionAction::Call(recipient),
+                    value: GIGA.as_u256(),
+                    input: Default::default(),
+                },
+                sender,
+            }],
+            ommers: vec![],
+            rollup_cost_data: vec![],
+        };
+
+        let mut state = InMemoryState::default();
+        state.update_account(
+            sender,
+            None,
+            Some(Account {
+                balance: ETHER.into(),
+                ..Default::default()
+            }),
+        );
+
+        let (receipts, witness) =
+            execute_block_with_witness(&mut state, &MAINNET, &header, &block).unwrap();
+        assert!(witness.accounts.contains_key(&sender));
+        assert!(witness.accounts.contains_key(&recipient));
+
+        // Without the recipient the block cannot be executed.
+        let mut incomplete = witness.clone();
+        incomplete.accounts.remove(&recipient);
+        execute_block_statelessly(incomplete, &MAINNET, &header, &block).unwrap_err();
+
+        let (stateless_receipts, stateless_state) =
+            execute_block_statelessly(witness, &MAINNET, &header, &block).unwrap();
+        assert_eq!(stateless_receipts, receipts);
+        for address in [sender, recipient, miner] {
+            assert_eq!(
+                stateless_state.read_account(address).unwrap(),
+                state.read_account(address).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn prove_witness_against_parent() {
+        let db = new_mem_database().unwrap();
+        let txn = db.begin_mutable().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        initialize_genesis(&txn, &temp_dir, MAINNET.clone()).unwrap();
+        let genesis_hash = txn
+            .get(tables::CanonicalHeader, BlockNumber(0))
+            .unwrap()
+            .unwrap();
+        let state_root = txn
+            .get(tables::Header, (BlockNumber(0), genesis_hash))
+            .unwrap()
+            .unwrap()
+            .state_root;
+
+        let miner = hex!("5a0b54d5dc17e0aadc383d2db43b0a0d3e029c4c").into();
+        let sender = *MAINNET.balances[&BlockNumber(0)].keys().next().unwrap();
+        let recipient = hex!("71562b71999873db5b286df957af199ec94617f7").into();
+
+        let gas_used = 21_000;
+        let receipts = vec![Receipt {
+            tx_type: TxType::Legacy,
+            success: true,
+            cumulative_gas_used: gas_used,
+            bloom: Bloom::zero(),
+            logs: vec![],
+            deposit_nonce: None,
+        }];
+        let header = PartialHeader {
+            number: 1.into(),
+            beneficiary: miner,
+            gas_limit: 100_000,
+            gas_used,
+            receipts_root: root_hash(&receipts),
+            ..PartialHeader::empty()
+        };
+        let block = BlockBodyWithSenders {
+            transactions: vec![MessageWithSender {
+                message: Message::Legacy {
+                    chain_id: None,
+                    nonce: 0,
+                    gas_price: U256::ZERO,
+                    gas_limit: gas_used,
+                    action: TransactionAction::Call(recipient),
+                    value: GIGA.as_u256(),
+                    input: Default::default(),
+                },
+                sender,
+            }],
+            ommers: vec![],
+            rollup_cost_data: vec![],
+        };
+
+        let (receipts, witness) =
+            execute_block_with_proven_witness(&txn, &MAINNET, &header, &block).unwrap();
+        witness.verify(state_root).unwrap();
+        assert!(witness.proof.account(sender).unwrap().is_some());
+        assert_eq!(witness.proof.account(recipient).unwrap(), None);
+
+        // Proven against another state, or with a value the proof does not back, it is rejected.
+        witness.verify(EMPTY_ROOT).unwrap_err();
+        let mut forged = witness.clone();
+        forged.accounts.insert(
+            recipient,
+            Some(Account {
+                balance: ETHER.into(),
+                ..Default::default()
+            }),
+        );
+        forged.verify(state_root).unwrap_err();
+
+        let (stateless_receipts, _) =
+            execute_block_statelessly(witness, &MAINNET, &header, &block).unwrap();
+        assert_eq!(stateless_receipts, receipts);
+    }
 }
@@ -0,0 +1,113 @@
+//! Fees of OP-stack rollups on top of the L2 execution: the cost of posting each transaction to
+//! L1, and the base fee, which goes to a vault instead of being burnt.
+use crate::{chain::protocol_param::fee, models::*, state::IntraBlockState, State};
+use hex_literal::hex;
+
+/// Predeploy holding the attributes of the latest L1 block, set by the first deposit of every
+/// block.
+pub const L1_BLOCK_ADDRESS: Address = H160(hex!("4200000000000000000000000000000000000015"));
+pub const BASE_FEE_VAULT: Address = H160(hex!("4200000000000000000000000000000000000019"));
+pub const L1_FEE_VAULT: Address = H160(hex!("420000000000000000000000000000000000001a"));
+
+const L1_BASE_FEE_SLOT: u64 = 1;
+const L1_FEE_OVERHEAD_SLOT: u64 = 5;
+const L1_FEE_SCALAR_SLOT: u64 = 6;
+
+/// Scalars are in millionths.
+const L1_FEE_SCALAR_DECIMALS: u64 = 1_000_000;
+
+/// Before Regolith, the signature was assumed missing from the cost data and charged on top.
+const SIGNATURE_DATA_GAS: u64 = 68 * fee::G_TX_DATA_NON_ZERO_ISTANBUL;
+
+/// L1 fee parameters, as of the L1 block the current L2 block derives from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct L1BlockInfo {
+    pub base_fee: U256,
+    pub fee_overhead: U256,
+    pub fee_scalar: U256,
+}
+
+/// What a transaction was charged for being posted to L1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct L1Fee {
+    pub gas_used: U256,
+    pub gas_price: U256,
+    /// In millionths.
+    pub scalar: U256,
+    pub fee: U256,
+}
+
+impl L1Fee {
+    /// Scalar as a decimal number, the way it is reported over RPC.
+    pub fn scalar_decimal(&self) -> String {
+        let decimals = L1_FEE_SCALAR_DECIMALS.as_u256();
+        let fraction = format!("{:06}", (self.scalar % decimals).as_u64());
+        match fraction.trim_end_matches('0') {
+            "" => (self.scalar / decimals).to_string(),
+            fraction => format!("{}.{}", self.scalar / decimals, fraction),
+        }
+    }
+}
+
+impl L1BlockInfo {
+    pub fn read<S: State>(state: &mut IntraBlockState<'_, S>) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_fee: state.get_current_storage(L1_BLOCK_ADDRESS, L1_BASE_FEE_SLOT.as_u256())?,
+            fee_overhead: state
+                .get_current_storage(L1_BLOCK_ADDRESS, L1_FEE_OVERHEAD_SLOT.as_u256())?,
+            fee_scalar: state
+                .get_current_storage(L1_BLOCK_ADDRESS, L1_FEE_SCALAR_SLOT.as_u256())?,
+        })
+    }
+
+    pub fn l1_fee(&self, data: RollupCostData, spec: OptimismSpec) -> L1Fee {
+        let mut data_gas =
+            data.zeroes * fee::G_TX_DATA_ZERO + data.ones * fee::G_TX_DATA_NON_ZERO_ISTANBUL;
+        if !spec.regolith {
+            data_gas += SIGNATURE_DATA_GAS;
+        }
+
+        let gas_used = data_gas.as_u256() + self.fee_overhead;
+        L1Fee {
+            gas_used,
+            gas_price: self.base_fee,
+            scalar: self.fee_scalar,
+            fee: gas_used * self.base_fee * self.fee_scalar / L1_FEE_SCALAR_DECIMALS.as_u256(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn l1_fee() {
+        let info = L1BlockInfo {
+            base_fee: 20_000_000_000_u64.as_u256(),
+            fee_overhead: 188.as_u256(),
+            fee_scalar: 684_000.as_u256(),
+        };
+        let data = RollupCostData {
+            zeroes: 10,
+            ones: 100,
+        };
+
+        let fee = info.l1_fee(data, OptimismSpec { regolith: true });
+        assert_eq!(fee.gas_used, (10 * 4 + 100 * 16 + 188).as_u256());
+        assert_eq!(fee.fee, (1828 * 20_000_000_000_u64 / 1_000 * 684).as_u256());
+        assert_eq!(fee.scalar_decimal(), "0.684");
+
+        let fee = info.l1_fee(data, OptimismSpec { regolith: false });
+        assert_eq!(fee.gas_used, (1828 + 68 * 16).as_u256());
+
+        assert_eq!(
+            L1Fee {
+                scalar: 1_000_000.as_u256(),
+                ..fee
+            }
+            .scalar_decimal(),
+            "1"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use super::evm::StatusCode;
+use crate::consensus::ValidationError;
+use bytes::Bytes;
+use std::fmt::Display;
+
+/// JSON-RPC error codes reported for failed executions.
+pub mod error_code {
+    /// Same code as geth uses for `execution reverted`.
+    pub const REVERTED: i32 = 3;
+    pub const OUT_OF_GAS: i32 = -32010;
+    pub const INVALID_OPCODE: i32 = -32011;
+    pub const STACK_ERROR: i32 = -32012;
+    pub const EXECUTION_FAILED: i32 = -32015;
+    /// EIP-1474 "Transaction rejected".
+    pub const INVALID_TRANSACTION: i32 = -32003;
+}
+
+/// How a transaction or call ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExecutionOutcome {
+    Success {
+        output: Bytes,
+        gas_used: u64,
+    },
+    /// Terminated with REVERT, `data` is the revert reason as returned by the contract.
+    Revert {
+        data: Bytes,
+        gas_used: u64,
+    },
+    OutOfGas {
+        gas_used: u64,
+    },
+    /// Hit the designated INVALID instruction or an undefined opcode.
+    InvalidOpcode(StatusCode),
+    StackError(StatusCode),
+    /// Any other exceptional halt.
+    Failure(StatusCode),
+    /// Transaction was rejected before execution, e.g. wrong nonce or insufficient balance.
+    InvalidTransaction(ValidationError),
+}
+
+impl ExecutionOutcome {
+    pub fn new(status_code: StatusCode, output_data: Bytes, gas_used: u64) -> Self {
+        match status_code {
+            StatusCode::Success => Self::Success {
+                output: output_data,
+                gas_used,
+            },
+            StatusCode::Revert => Self::Revert {
+                data: output_data,
+                gas_used,
+            },
+            StatusCode::OutOfGas => Self::OutOfGas { gas_used },
+            StatusCode::InvalidInstruction | StatusCode::UndefinedInstruction => {
+                Self::InvalidOpcode(status_code)
+            }
+            StatusCode::StackOverflow | StatusCode::StackUnderflow => Self::StackError(status_code),
+            other => Self::Failure(other),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Success { .. })
+    }
+
+    /// Output of a successful execution, or revert data.
+    pub fn output(&self) -> Option<&Bytes> {
+        match self {
+            Self::Success { output, .. } => Some(output),
+            Self::Revert { data, .. } => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn gas_used(&self) -> Option<u64> {
+        match self {
+            Self::Success { gas_used, .. }
+            | Self::Revert { gas_used, .. }
+            | Self::OutOfGas { gas_used } => Some(*gas_used),
+            _ => None,
+        }
+    }
+
+    /// JSON-RPC error code of a failed execution, `None` on success.
+    pub fn error_code(&self) -> Option<i32> {
+        Some(match self {
+            Self::Success { .. } => return None,
+            Self::Revert { .. } => error_code::REVERTED,
+            Self::OutOfGas { .. } => error_code::OUT_OF_GAS,
+            Self::InvalidOpcode(_) => error_code::INVALID_OPCODE,
+            Self::StackError(_) => error_code::STACK_ERROR,
+            Self::Failure(_) => error_code::EXECUTION_FAILED,
+            Self::InvalidTransaction(_) => error_code::INVALID_TRANSACTION,
+        })
+    }
+}
+
+impl Display for ExecutionOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Success { .. } => write!(f, "success"),
+            Self::Revert { .. } => write!(f, "execution reverted"),
+            Self::OutOfGas { .. } => write!(f, "out of gas"),
+            Self::InvalidOpcode(status_code)
+            | Self::StackError(status_code)
+            | Self::Failure(status_code) => write!(f, "{}", status_code),
+            Self::InvalidTransaction(e) => write!(f, "invalid transaction: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_status_codes() {
+        let outcome = ExecutionOutcome::new(StatusCode::Revert, Bytes::from_static(b"nope"), 100);
+        assert_eq!(outcome.error_code(), Some(error_code::REVERTED));
+        assert_eq!(outcome.output(), Some(&Bytes::from_static(b"nope")));
+        assert_eq!(outcome.gas_used(), Some(100));
+
+        assert_eq!(
+            ExecutionOutcome::new(StatusCode::UndefinedInstruction, Bytes::new(), 0),
+            ExecutionOutcome::InvalidOpcode(StatusCode::UndefinedInstruction)
+        );
+        assert_eq!(
+            ExecutionOutcome::new(StatusCode::StackUnderflow, Bytes::new(), 0).error_code(),
+            Some(error_code::STACK_ERROR)
+        );
+        assert_eq!(
+            ExecutionOutcome::new(StatusCode::BadJumpDestination, Bytes::new(), 0).to_string(),
+            "bad jump destination"
+        );
+        assert!(ExecutionOutcome::new(StatusCode::Success, Bytes::new(), 21_000).is_success());
+    }
+}
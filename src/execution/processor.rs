@@ -1,4 +1,10 @@
-use super::{analysis_cache::AnalysisCache, root_hash, tracer::Tracer};
+use super::{
+    analysis_cache::AnalysisCache,
+    evmglue::CallResult,
+    optimism::{L1BlockInfo, L1Fee, BASE_FEE_VAULT, L1_FEE_VAULT},
+    outcome::ExecutionOutcome,
+    tracer::Tracer,
+};
 use crate::{
     chain::{
         intrinsic_gas::*,
@@ -11,10 +17,15 @@ use crate::{
     state::IntraBlockState,
     State,
 };
-use anyhow::Context;
+use anyhow::{bail, ensure, format_err, Context};
+use bytes::Bytes;
 use std::cmp::min;
+use tracing::*;
 use TransactionAction;
 
+/// Gas available to a [`FinalizationChange::SystemCall`].
+const SYSTEM_CALL_GAS: u64 = 50_000_000;
+
 pub struct ExecutionProcessor<'r, 'tracer, 'analysis, 'e, 'h, 'b, 'c, S>
 where
     S: State,
@@ -27,6 +38,7 @@ where
     block: &'b BlockBodyWithSenders,
     block_spec: &'c BlockExecutionSpec,
     cumulative_gas_used: u64,
+    l1_fees: Vec<Option<L1Fee>>,
 }
 
 impl<'r, 'tracer, 'analysis, 'e, 'h, 'b, 'c, S>
@@ -52,6 +64,7 @@ where
             block,
             block_spec,
             cumulative_gas_used: 0,
+            l1_fees: Vec::new(),
         }
     }
 
@@ -59,14 +72,25 @@ where
         self.header.gas_limit - self.cumulative_gas_used
     }
 
-    pub(crate) fn state(&mut self) -> &mut IntraBlockState<'r, S> {
+    pub fn state(&mut self) -> &mut IntraBlockState<'r, S> {
         &mut self.state
     }
 
-    pub(crate) fn into_state(self) -> IntraBlockState<'r, S> {
+    pub fn into_state(self) -> IntraBlockState<'r, S> {
         self.state
     }
 
+    /// Tracer of the transactions executed from now on.
+    pub fn set_tracer(&mut self, tracer: Option<&'tracer mut dyn Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// L1 fees charged to the transactions executed so far on an OP-stack chain, `None` for
+    /// deposits and transactions without rollup cost data.
+    pub fn l1_fees(&self) -> &[Option<L1Fee>] {
+        &self.l1_fees
+    }
+
     pub fn validate_transaction(&mut self, tx: &MessageWithSender) -> anyhow::Result<()> {
         pre_validate_transaction(
             tx,
@@ -75,6 +99,20 @@ where
         )
         .expect("Tx must have been prevalidated");
 
+        // Deposits are validated on L1, and included even if they fail.
+        if let Message::Deposit { is_system_tx, .. } = tx.message {
+            if self.block_spec.optimism.is_none() {
+                return Err(ValidationError::UnsupportedTransactionType.into());
+            }
+
+            // System deposits do not take gas from the block.
+            if is_system_tx {
+                return Ok(());
+            }
+
+            return self.validate_block_gas(tx);
+        }
+
         if self.state.get_code_hash(tx.sender)? != EMPTY_HASH {
             return Err(ValidationError::SenderNoEOA { sender: tx.sender }.into());
         }
@@ -107,6 +145,10 @@ where
             .into());
         }
 
+        self.validate_block_gas(tx)
+    }
+
+    fn validate_block_gas(&self, tx: &MessageWithSender) -> anyhow::Result<()> {
         let available_gas = self.available_gas();
         if available_gas < tx.gas_limit() {
             // Corresponds to the final condition of Eq (58) in Yellow Paper Section 6.2 "Execution".
@@ -123,17 +165,88 @@ where
     }
 
     fn execute_transaction(&mut self, txn: &MessageWithSender) -> anyhow::Result<Receipt> {
+        self.execute_transaction_with_outcome(txn)
+            .map(|(receipt, _)| receipt)
+    }
+
+    /// Validates and executes a single transaction on top of the current state,
+    /// reporting validation failures as [`ExecutionOutcome::InvalidTransaction`] rather than errors.
+    ///
+    /// Its changes stay in the processor's state like those of any other transaction, so that a
+    /// second call of the same message fails on its nonce. Nothing is written to the underlying
+    /// state unless the processor's state is, so `eth_call` runs each call in a processor of its
+    /// own and drops it afterwards.
+    pub fn call(&mut self, txn: &MessageWithSender) -> anyhow::Result<ExecutionOutcome> {
+        let res = pre_validate_transaction(
+            txn,
+            self.block_spec.params.chain_id,
+            self.header.base_fee_per_gas,
+        )
+        .map_err(anyhow::Error::from)
+        .and_then(|_| self.validate_transaction(txn))
+        .and_then(|_| self.execute_transaction_with_outcome(txn));
+
+        match res {
+            Ok((_, outcome)) => Ok(outcome),
+            Err(e) => match e.downcast::<ValidationError>() {
+                Ok(e) => Ok(ExecutionOutcome::InvalidTransaction(e)),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    /// Executes a transaction without charging it for being posted to L1, as done for calls.
+    pub fn execute_transaction_with_outcome(
+        &mut self,
+        txn: &MessageWithSender,
+    ) -> anyhow::Result<(Receipt, ExecutionOutcome)> {
+        self.execute_transaction_with_rollup_cost(txn, None)
+    }
+
+    fn execute_transaction_with_rollup_cost(
+        &mut self,
+        txn: &MessageWithSender,
+        rollup_cost_data: Option<RollupCostData>,
+    ) -> anyhow::Result<(Receipt, ExecutionOutcome)> {
+        if let Some(optimism) = self.block_spec.optimism {
+            if txn.is_deposit() {
+                return self.execute_deposit(txn, optimism);
+            }
+        }
+
         let rev = self.block_spec.revision;
 
         self.state.clear_journal_and_substate();
 
         self.state.access_account(txn.sender);
 
+        let l1_fee = match (self.block_spec.optimism, rollup_cost_data) {
+            (Some(optimism), Some(data)) => {
+                Some(L1BlockInfo::read(&mut self.state)?.l1_fee(data, optimism))
+            }
+            _ => None,
+        };
+        let l1_cost = l1_fee.map(|l1_fee| l1_fee.fee).unwrap_or(U256::ZERO);
+        if l1_cost > 0 {
+            // The rest of the balance was checked on validation.
+            let balance = self.state.get_balance(txn.sender)?;
+            let max_cost = U256::from(txn.gas_limit()) * txn.max_fee_per_gas() + txn.value();
+            if balance - max_cost < l1_cost {
+                return Err(ValidationError::InsufficientFunds {
+                    account: txn.sender,
+                    available: ethereum_types::U256::from(balance.to_be_bytes()).into(),
+                    required: U512::from(ethereum_types::U256::from(max_cost.to_be_bytes()))
+                        + U512::from(ethereum_types::U256::from(l1_cost.to_be_bytes())),
+                }
+                .into());
+            }
+        }
+
         let base_fee_per_gas = self.header.base_fee_per_gas.unwrap_or(U256::ZERO);
         let effective_gas_price = txn.effective_gas_price(base_fee_per_gas);
         self.state.subtract_from_balance(
             txn.sender,
-            U256::from(txn.gas_limit()) * effective_gas_price,
+            U256::from(txn.gas_limit()) * effective_gas_price + l1_cost,
         )?;
 
         if let TransactionAction::Call(to) = txn.action() {
@@ -177,49 +290,270 @@ where
             U256::from(gas_used) * priority_fee_per_gas,
         )?;
 
+        if self.block_spec.optimism.is_some() {
+            self.state
+                .add_to_balance(BASE_FEE_VAULT, U256::from(gas_used) * base_fee_per_gas)?;
+            if l1_fee.is_some() {
+                self.state.add_to_balance(L1_FEE_VAULT, l1_cost)?;
+            }
+        }
+        self.l1_fees.push(l1_fee);
+
+        self.finish_transaction(txn, vm_res, gas_used, None)
+    }
+
+    /// Deposits are paid for on L1: they mint their value whatever the outcome, and one that
+    /// cannot be executed is still included, only incrementing the nonce of its sender.
+    fn execute_deposit(
+        &mut self,
+        txn: &MessageWithSender,
+        optimism: OptimismSpec,
+    ) -> anyhow::Result<(Receipt, ExecutionOutcome)> {
+        let Message::Deposit {
+            mint, is_system_tx, ..
+        } = txn.message
+        else {
+            unreachable!()
+        };
+        let rev = self.block_spec.revision;
+
+        self.state.clear_journal_and_substate();
+
+        self.state.add_to_balance(txn.sender, mint)?;
+        let nonce = self.state.get_nonce(txn.sender)?;
+
+        let g0 = intrinsic_gas(txn, rev >= Revision::Homestead, rev >= Revision::Istanbul);
+        let gas = u128::from(txn.gas_limit()).checked_sub(g0);
+        let executable = !(is_system_tx && optimism.regolith)
+            && self.state.get_balance(txn.sender)? >= txn.value();
+
+        let (vm_res, gas_used) = match gas {
+            Some(gas) if executable => {
+                self.state.access_account(txn.sender);
+                if let TransactionAction::Call(to) = txn.action() {
+                    self.state.access_account(to);
+                    // EVM itself increments the nonce for contract creation
+                    self.state.set_nonce(txn.sender, nonce + 1)?;
+                }
+
+                let vm_res = evmglue::execute(
+                    &mut self.state,
+                    // https://github.com/rust-lang/rust-clippy/issues/7846
+                    #[allow(clippy::needless_option_as_deref)]
+                    self.tracer.as_deref_mut(),
+                    self.analysis_cache,
+                    self.header,
+                    self.block_spec,
+                    txn,
+                    gas.try_into().unwrap(),
+                )?;
+
+                // Nothing is refunded, the gas price being zero.
+                let gas_used = if optimism.regolith {
+                    txn.gas_limit() - self.refund_gas(txn, vm_res.gas_left as u64)?
+                } else if is_system_tx {
+                    0
+                } else {
+                    txn.gas_limit()
+                };
+
+                (vm_res, gas_used)
+            }
+            _ => {
+                self.state.set_nonce(txn.sender, nonce + 1)?;
+
+                let vm_res = CallResult {
+                    status_code: StatusCode::Failure,
+                    gas_left: 0,
+                    output_data: Bytes::new(),
+                };
+                let gas_used = if is_system_tx && !optimism.regolith {
+                    0
+                } else {
+                    txn.gas_limit()
+                };
+
+                (vm_res, gas_used)
+            }
+        };
+        self.l1_fees.push(None);
+
+        self.finish_transaction(txn, vm_res, gas_used, optimism.regolith.then_some(nonce))
+    }
+
+    fn finish_transaction(
+        &mut self,
+        txn: &MessageWithSender,
+        vm_res: CallResult,
+        gas_used: u64,
+        deposit_nonce: Option<u64>,
+    ) -> anyhow::Result<(Receipt, ExecutionOutcome)> {
         self.state.destruct_selfdestructs()?;
-        if rev >= Revision::Spurious {
+        if self.block_spec.revision >= Revision::Spurious {
             self.state.destruct_touched_dead()?;
         }
 
         self.state.finalize_transaction();
 
+        if let Some(tracer) = self.tracer.as_deref_mut() {
+            tracer.capture_internal_transfers(self.state.internal_transfers());
+            tracer.capture_contract_creations(self.state.created_contracts());
+        }
+
         self.cumulative_gas_used += gas_used;
 
-        Ok(Receipt {
+        let receipt = Receipt {
             tx_type: txn.tx_type(),
             success: vm_res.status_code == StatusCode::Success,
             cumulative_gas_used: self.cumulative_gas_used,
             bloom: logs_bloom(self.state.logs()),
             logs: self.state.logs().to_vec(),
-        })
+            deposit_nonce,
+        };
+
+        Ok((
+            receipt,
+            ExecutionOutcome::new(vm_res.status_code, vm_res.output_data, gas_used),
+        ))
     }
 
-    pub fn execute_block_no_post_validation(&mut self) -> anyhow::Result<Vec<Receipt>> {
-        let mut receipts = Vec::with_capacity(self.block.transactions.len());
+    /// Validates and executes transaction `index` of the block, on top of the state left by
+    /// the ones before it.
+    pub fn execute_block_transaction(
+        &mut self,
+        index: usize,
+    ) -> anyhow::Result<(Receipt, ExecutionOutcome)> {
+        let block = self.block;
+        let txn = block
+            .transactions
+            .get(index)
+            .ok_or_else(|| format_err!("No tx #{} in block", index))?;
+        self.validate_transaction(txn)
+            .with_context(|| format!("Failed to validate tx #{}", index))?;
+        self.execute_transaction_with_rollup_cost(txn, block.rollup_cost_data.get(index).copied())
+    }
+
+    /// Executes the block up to, and excluding, transaction `index`, leaving the state that
+    /// transaction starts from. Returns the receipts of the transactions before it.
+    ///
+    /// Must be called on a fresh processor, as it applies the changes made at the start of the
+    /// block. Those made at the end are not.
+    pub fn execute_block_up_to(&mut self, index: usize) -> anyhow::Result<Vec<Receipt>> {
+        ensure!(
+            index <= self.block.transactions.len(),
+            "Cannot execute up to tx #{} of a block with {} transactions",
+            index,
+            self.block.transactions.len()
+        );
 
         for (&address, &balance) in &self.block_spec.balance_changes {
             self.state.set_balance(address, balance)?;
         }
 
-        for (i, txn) in self.block.transactions.iter().enumerate() {
-            self.validate_transaction(txn)
-                .with_context(|| format!("Failed to validate tx #{}", i))?;
-            receipts.push(self.execute_transaction(txn)?);
+        let changes = self.engine.pre_execution(self.header)?;
+        self.apply_changes(changes)?;
+
+        let mut receipts = Vec::with_capacity(index);
+        for i in 0..index {
+            receipts.push(self.execute_block_transaction(i)?.0);
         }
 
-        for change in
-            self.engine
-                .finalize(self.header, &self.block.ommers, self.block_spec.revision)?
-        {
+        Ok(receipts)
+    }
+
+    pub fn execute_block_no_post_validation(&mut self) -> anyhow::Result<Vec<Receipt>> {
+        let receipts = self.execute_block_up_to(self.block.transactions.len())?;
+
+        self.engine.post_execution(self.header, &receipts)?;
+
+        let changes = self.engine.finalize(
+            self.header,
+            &self.block.ommers,
+            self.block_spec.revision,
+            self.state.db(),
+        )?;
+        self.apply_changes(changes)?;
+
+        Ok(receipts)
+    }
+
+    fn apply_changes(&mut self, changes: Vec<FinalizationChange>) -> anyhow::Result<()> {
+        for change in changes {
             match change {
-                FinalizationChange::Reward { address, amount } => {
+                FinalizationChange::Reward {
+                    address,
+                    amount,
+                    kind,
+                } => {
+                    if let Some(tracer) = self.tracer.as_deref_mut() {
+                        tracer.capture_reward(address, kind, amount);
+                    }
                     self.state.add_to_balance(address, amount)?;
                 }
+                FinalizationChange::SystemCall { contract, data } => {
+                    if let Some(output) = self.system_call(contract, data.clone())? {
+                        let changes =
+                            self.engine
+                                .on_system_call(self.header, contract, &data, output)?;
+                        self.apply_changes(changes)?;
+                    }
+                }
             }
         }
 
-        Ok(receipts)
+        Ok(())
+    }
+
+    /// Returns the output of the call, or `None` if it failed and the engine ignores that.
+    fn system_call(&mut self, contract: Address, data: Bytes) -> anyhow::Result<Option<Bytes>> {
+        let txn = MessageWithSender {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce: 0,
+                gas_price: U256::ZERO,
+                gas_limit: SYSTEM_CALL_GAS,
+                action: TransactionAction::Call(contract),
+                value: U256::ZERO,
+                input: data,
+            },
+            sender: SYSTEM_ADDRESS,
+        };
+
+        self.state.clear_journal_and_substate();
+
+        let vm_res = evmglue::execute(
+            &mut self.state,
+            None,
+            self.analysis_cache,
+            self.header,
+            self.block_spec,
+            &txn,
+            SYSTEM_CALL_GAS,
+        )?;
+        let success = vm_res.status_code == StatusCode::Success;
+        if !success {
+            if !self.engine.ignores_failed_system_calls() {
+                bail!(
+                    "System call to {:?} failed: {:?}",
+                    contract,
+                    vm_res.status_code
+                );
+            }
+            debug!(
+                "System call to {:?} failed: {:?}",
+                contract, vm_res.status_code
+            );
+        }
+
+        self.state.destruct_selfdestructs()?;
+        if self.block_spec.revision >= Revision::Spurious {
+            // Drops the system address, touched by the call.
+            self.state.destruct_touched_dead()?;
+        }
+
+        self.state.finalize_transaction();
+
+        Ok(success.then_some(vm_res.output_data))
     }
 
     pub fn execute_and_write_block(mut self) -> anyhow::Result<Vec<Receipt>> {
@@ -248,32 +582,9 @@ where
             .into());
         }
 
-        let block_num = self.header.number;
-        let rev = self.block_spec.revision;
-
-        if rev >= Revision::Byzantium {
-            let expected = root_hash(&receipts);
-            if expected != self.header.receipts_root {
-                return Err(ValidationError::WrongReceiptsRoot {
-                    expected,
-                    got: self.header.receipts_root,
-                }
-                .into());
-            }
-        }
-
-        let expected_logs_bloom = receipts
-            .iter()
-            .fold(Bloom::zero(), |bloom, r| bloom | r.bloom);
-        if expected_logs_bloom != self.header.logs_bloom {
-            return Err(ValidationError::WrongLogsBloom {
-                expected: expected_logs_bloom,
-                got: self.header.logs_bloom,
-            }
-            .into());
-        }
+        post_validate_receipts(self.header, self.block_spec.revision, &receipts)?;
 
-        self.state.write_to_db(block_num)?;
+        self.state.write_to_db(self.header.number)?;
 
         Ok(receipts)
     }
@@ -304,7 +615,14 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{execution::address::create_address, res::chainspec::MAINNET, InMemoryState};
+    use crate::{
+        execution::{
+            address::create_address,
+            tracer::{CallFrameTracer, CallTreeTracer, SelfDestruct, StructLogger, TransferTracer},
+        },
+        res::chainspec::MAINNET,
+        InMemoryState,
+    };
     use bytes::Bytes;
     use bytes_literal::bytes;
     use hex_literal::hex;
@@ -353,6 +671,59 @@ mod tests {
         assert!(receipt.success);
     }
 
+    #[test]
+    fn call_outcome() {
+        let header = PartialHeader {
+            number: 5_000_000.into(),
+            gas_limit: 8_000_000,
+            ..PartialHeader::empty()
+        };
+        let block = Default::default();
+
+        let sender = hex!("004512399a230565b99be5c3b0030a56f3ace68c").into();
+
+        // PUSH1 0 PUSH1 0 REVERT
+        let txn = |nonce| MessageWithSender {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce,
+                gas_price: U256::ZERO,
+                gas_limit: 100_000,
+                action: TransactionAction::Create,
+                value: U256::ZERO,
+                input: hex!("60006000fd").to_vec().into(),
+            },
+            sender,
+        };
+
+        let mut state = InMemoryState::default();
+        let mut analysis_cache = AnalysisCache::default();
+        let mut engine = engine_factory(MAINNET.clone()).unwrap();
+        let block_spec = MAINNET.collect_block_spec(header.number);
+        let mut processor = ExecutionProcessor::new(
+            &mut state,
+            None,
+            &mut analysis_cache,
+            &mut *engine,
+            &header,
+            &block,
+            &block_spec,
+        );
+
+        let outcome = processor.call(&txn(0)).unwrap();
+        assert!(matches!(outcome, ExecutionOutcome::Revert { .. }));
+        assert_eq!(outcome.output(), Some(&Bytes::new()));
+
+        assert_eq!(
+            processor.call(&txn(0)).unwrap(),
+            ExecutionOutcome::InvalidTransaction(ValidationError::WrongNonce {
+                account: sender,
+                expected: 1,
+                got: 0
+            })
+        );
+    }
+
     #[test]
     fn eip3607_reject_transactions_from_senders_with_deployed_code() {
         let header = PartialHeader {
@@ -626,6 +997,348 @@ mod tests {
         );
     }
 
+    #[test]
+    fn internal_transfers() {
+        let header = PartialHeader {
+            number: 5_000_000.into(),
+            gas_limit: 8_000_000,
+            ..PartialHeader::empty()
+        };
+        let block = Default::default();
+
+        let sender = hex!("004512399a230565b99be5c3b0030a56f3ace68c").into();
+        let caller = Address::repeat_byte(0xaa);
+        let suicidal = Address::repeat_byte(0xbb);
+        let beneficiary = Address::repeat_byte(0xcc);
+        let reverting = Address::repeat_byte(0xdd);
+
+        // CALL suicidal with 5 wei, then CALL reverting with 7 wei
+        let caller_code = hex!("6000808080600573bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb5af1506000808080600773dddddddddddddddddddddddddddddddddddddddd5af15000");
+        // PUSH20 beneficiary SELFDESTRUCT
+        let suicidal_code = hex!("73ccccccccccccccccccccccccccccccccccccccccff");
+        // PUSH1 0 DUP1 REVERT
+        let reverting_code = hex!("600080fd");
+
+        let txn = MessageWithSender {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce: 0,
+                gas_price: U256::ZERO,
+                gas_limit: 200_000,
+                action: TransactionAction::Call(caller),
+                value: U256::ZERO,
+                input: Bytes::new(),
+            },
+            sender,
+        };
+
+        let mut state = InMemoryState::default();
+        let mut analysis_cache = AnalysisCache::default();
+        let mut engine = engine_factory(MAINNET.clone()).unwrap();
+        let block_spec = MAINNET.collect_block_spec(header.number);
+        let mut tracer = TransferTracer::default();
+        {
+            let mut processor = ExecutionProcessor::new(
+                &mut state,
+                Some(&mut tracer),
+                &mut analysis_cache,
+                &mut *engine,
+                &header,
+                &block,
+                &block_spec,
+            );
+
+            processor
+                .state()
+                .add_to_balance(caller, 100.as_u256())
+                .unwrap();
+            for (address, code) in [
+                (caller, &caller_code[..]),
+                (suicidal, &suicidal_code[..]),
+                (reverting, &reverting_code[..]),
+            ] {
+                processor
+                    .state()
+                    .set_code(address, code.to_vec().into())
+                    .unwrap();
+            }
+
+            let receipt = processor.execute_transaction(&txn).unwrap();
+            assert!(receipt.success);
+            assert_eq!(
+                processor.state().get_balance(beneficiary).unwrap(),
+                5.as_u256()
+            );
+            assert_eq!(processor.state().get_balance(caller).unwrap(), 95.as_u256());
+        }
+
+        assert_eq!(
+            tracer.into_transfers().collect::<Vec<_>>(),
+            vec![(
+                TxIndex(0),
+                vec![
+                    InternalTransfer {
+                        kind: TransferKind::Call,
+                        from: caller,
+                        to: suicidal,
+                        value: 5.as_u256(),
+                    },
+                    InternalTransfer {
+                        kind: TransferKind::SelfDestruct,
+                        from: suicidal,
+                        to: beneficiary,
+                        value: 5.as_u256(),
+                    },
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn call_frames() {
+        let header = PartialHeader {
+            number: 5_000_000.into(),
+            gas_limit: 8_000_000,
+            ..PartialHeader::empty()
+        };
+        let block = Default::default();
+
+        let sender = hex!("004512399a230565b99be5c3b0030a56f3ace68c").into();
+        let caller = Address::repeat_byte(0xaa);
+        let callee = Address::repeat_byte(0xbb);
+        let recipient = Address::repeat_byte(0xcc);
+
+        // CALL callee with 5 wei
+        let caller_code = hex!("6000808080600573bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb5af15000");
+
+        let txn = |nonce, to| MessageWithSender {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce,
+                gas_price: U256::ZERO,
+                gas_limit: 100_000,
+                action: TransactionAction::Call(to),
+                value: U256::ZERO,
+                input: Bytes::new(),
+            },
+            sender,
+        };
+
+        let mut state = InMemoryState::default();
+        let mut analysis_cache = AnalysisCache::default();
+        let mut engine = engine_factory(MAINNET.clone()).unwrap();
+        let block_spec = MAINNET.collect_block_spec(header.number);
+        let mut tracer = CallFrameTracer::default();
+        {
+            let mut processor = ExecutionProcessor::new(
+                &mut state,
+                Some(&mut tracer),
+                &mut analysis_cache,
+                &mut *engine,
+                &header,
+                &block,
+                &block_spec,
+            );
+
+            processor
+                .state()
+                .add_to_balance(caller, 100.as_u256())
+                .unwrap();
+            processor
+                .state()
+                .set_code(caller, caller_code.to_vec().into())
+                .unwrap();
+
+            assert!(
+                processor
+                    .execute_transaction(&txn(0, caller))
+                    .unwrap()
+                    .success
+            );
+            assert!(
+                processor
+                    .execute_transaction(&txn(1, recipient))
+                    .unwrap()
+                    .success
+            );
+        }
+
+        assert_eq!(
+            tracer
+                .into_frames()
+                .into_iter()
+                .map(|frames| frames
+                    .into_iter()
+                    .map(|frame| (frame.depth, frame.from, frame.to, frame.value))
+                    .collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            vec![
+                vec![
+                    (0, sender, caller, U256::ZERO),
+                    (1, caller, callee, 5.as_u256()),
+                ],
+                vec![(0, sender, recipient, U256::ZERO)],
+            ]
+        );
+    }
+
+    #[test]
+    fn call_tree_and_struct_logs() {
+        let header = PartialHeader {
+            number: 5_000_000.into(),
+            gas_limit: 8_000_000,
+            ..PartialHeader::empty()
+        };
+        let block = Default::default();
+
+        let sender = hex!("004512399a230565b99be5c3b0030a56f3ace68c").into();
+        let caller = Address::repeat_byte(0xaa);
+        let callee = Address::repeat_byte(0xbb);
+
+        // CALL callee with 5 wei
+        let caller_code = hex!("6000808080600573bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb5af15000");
+
+        let txn = MessageWithSender {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce: 0,
+                gas_price: U256::ZERO,
+                gas_limit: 100_000,
+                action: TransactionAction::Call(caller),
+                value: U256::ZERO,
+                input: Bytes::new(),
+            },
+            sender,
+        };
+
+        let mut state = InMemoryState::default();
+        let mut analysis_cache = AnalysisCache::default();
+        let mut engine = engine_factory(MAINNET.clone()).unwrap();
+        let block_spec = MAINNET.collect_block_spec(header.number);
+        let mut tracer = (
+            CallTreeTracer::default(),
+            StructLogger::new(Default::default()),
+        );
+        {
+            let mut processor = ExecutionProcessor::new(
+                &mut state,
+                Some(&mut tracer),
+                &mut analysis_cache,
+                &mut *engine,
+                &header,
+                &block,
+                &block_spec,
+            );
+
+            processor
+                .state()
+                .add_to_balance(caller, 100.as_u256())
+                .unwrap();
+            processor
+                .state()
+                .set_code(caller, caller_code.to_vec().into())
+                .unwrap();
+
+            assert!(processor.execute_transaction(&txn).unwrap().success);
+        }
+        let (call_tracer, struct_logger) = tracer;
+
+        let tree = call_tracer.into_trees().pop().flatten().unwrap();
+        assert_eq!((tree.frame.from, tree.frame.to), (sender, caller));
+        assert_eq!(tree.status_code, StatusCode::Success);
+        assert_eq!(
+            tree.calls
+                .iter()
+                .map(|call| (
+                    call.frame.depth,
+                    call.frame.to,
+                    call.frame.value,
+                    call.gas_used
+                ))
+                .collect::<Vec<_>>(),
+            vec![(1, callee, 5.as_u256(), 0)]
+        );
+
+        let logs = struct_logger.into_logs();
+        assert_eq!(
+            logs.iter().map(|log| log.op.name()).collect::<Vec<_>>(),
+            vec!["PUSH1", "DUP1", "DUP1", "DUP1", "PUSH1", "PUSH20", "GAS", "CALL", "POP", "STOP"]
+        );
+        assert_eq!(logs[0].gas_cost, 3);
+        assert_eq!(logs[0].gas - logs[1].gas, 3);
+        assert_eq!(logs[7].stack.as_ref().unwrap().len(), 7);
+        assert_eq!(logs[8].stack.as_ref().unwrap(), &vec![U256::ONE]);
+        assert_eq!(tree.gas_used, logs[0].gas - logs[9].gas);
+    }
+
+    #[test]
+    fn self_destruct_in_call_tree() {
+        let header = PartialHeader {
+            number: 5_000_000.into(),
+            gas_limit: 8_000_000,
+            ..PartialHeader::empty()
+        };
+        let block = Default::default();
+
+        let sender = hex!("004512399a230565b99be5c3b0030a56f3ace68c").into();
+        let contract = Address::repeat_byte(0xaa);
+        let beneficiary = Address::repeat_byte(0xbb);
+
+        // SELFDESTRUCT to beneficiary
+        let code = hex!("73bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbff");
+
+        let txn = MessageWithSender {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce: 0,
+                gas_price: U256::ZERO,
+                gas_limit: 100_000,
+                action: TransactionAction::Call(contract),
+                value: U256::ZERO,
+                input: Bytes::new(),
+            },
+            sender,
+        };
+
+        let mut state = InMemoryState::default();
+        let mut analysis_cache = AnalysisCache::default();
+        let mut engine = engine_factory(MAINNET.clone()).unwrap();
+        let block_spec = MAINNET.collect_block_spec(header.number);
+        let mut tracer = CallTreeTracer::default();
+        {
+            let mut processor = ExecutionProcessor::new(
+                &mut state,
+                Some(&mut tracer),
+                &mut analysis_cache,
+                &mut *engine,
+                &header,
+                &block,
+                &block_spec,
+            );
+
+            processor
+                .state()
+                .add_to_balance(contract, 7.as_u256())
+                .unwrap();
+            processor
+                .state()
+                .set_code(contract, code.to_vec().into())
+                .unwrap();
+
+            assert!(processor.execute_transaction(&txn).unwrap().success);
+        }
+
+        let tree = tracer.into_trees().pop().flatten().unwrap();
+        assert!(tree.calls.is_empty());
+        assert_eq!(
+            tree.self_destruct,
+            Some(SelfDestruct {
+                beneficiary,
+                balance: 7.as_u256()
+            })
+        );
+    }
+
     #[test]
     fn out_of_gas_during_account_recreation() {
         let block_number = 2_081_788.into();
@@ -746,4 +1459,185 @@ mod tests {
         // suicide_beneficiary should've been touched and deleted
         assert_eq!(state.read_account(suicide_beneficiary).unwrap(), None);
     }
+
+    #[test]
+    fn execute_block_up_to() {
+        let header = PartialHeader {
+            number: 13_500_001.into(),
+            gas_limit: 100_000,
+            base_fee_per_gas: Some(7.as_u256()),
+            ..PartialHeader::empty()
+        };
+        let sender = hex!("004512399a230565b99be5c3b0030a56f3ace68c").into();
+        let recipient = hex!("71562b71999873db5b286df957af199ec94617f7").into();
+
+        let t = |nonce| MessageWithSender {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce,
+                gas_price: 7.as_u256(),
+                gas_limit: 21_000,
+                action: TransactionAction::Call(recipient),
+                value: GIGA.as_u256(),
+                input: Bytes::new(),
+            },
+            sender,
+        };
+        let block = BlockBodyWithSenders {
+            transactions: vec![t(0), t(1)],
+            ommers: vec![],
+            rollup_cost_data: vec![],
+        };
+
+        let mut state = InMemoryState::default();
+        state.update_account(
+            sender,
+            None,
+            Some(Account {
+                balance: ETHER.into(),
+                ..Default::default()
+            }),
+        );
+        let mut analysis_cache = AnalysisCache::default();
+        let mut engine = engine_factory(MAINNET.clone()).unwrap();
+        let block_spec = MAINNET.collect_block_spec(header.number);
+        let mut processor = ExecutionProcessor::new(
+            &mut state,
+            None,
+            &mut analysis_cache,
+            &mut *engine,
+            &header,
+            &block,
+            &block_spec,
+        );
+
+        let receipts = processor.execute_block_up_to(1).unwrap();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(processor.state().get_nonce(sender).unwrap(), 1);
+        assert_eq!(
+            processor.state().get_balance(recipient).unwrap(),
+            GIGA.as_u256()
+        );
+
+        let (receipt, _) = processor.execute_block_transaction(1).unwrap();
+        assert_eq!(receipt.cumulative_gas_used, 42_000);
+        assert_eq!(
+            processor.state().get_balance(recipient).unwrap(),
+            (2 * GIGA).as_u256()
+        );
+
+        processor.execute_block_transaction(2).unwrap_err();
+    }
+
+    #[test]
+    fn optimism_deposits_and_l1_fee() {
+        let mut chain_spec = MAINNET.clone();
+        chain_spec.optimism = Some(OptimismParams {
+            regolith: Some(BlockNumber(0)),
+        });
+
+        let header = PartialHeader {
+            number: 13_500_001.into(),
+            gas_limit: 30_000_000,
+            base_fee_per_gas: Some(7.as_u256()),
+            ..PartialHeader::empty()
+        };
+        let block = Default::default();
+        let sender = hex!("004512399a230565b99be5c3b0030a56f3ace68c").into();
+        let recipient = hex!("71562b71999873db5b286df957af199ec94617f7").into();
+
+        let deposit = |gas_limit| MessageWithSender {
+            message: Message::Deposit {
+                source_hash: H256::zero(),
+                from: sender,
+                action: TransactionAction::Call(recipient),
+                mint: ETHER.as_u256(),
+                value: GIGA.as_u256(),
+                gas_limit,
+                is_system_tx: false,
+                input: Bytes::new(),
+            },
+            sender,
+        };
+
+        let mut state = InMemoryState::default();
+        let mut analysis_cache = AnalysisCache::default();
+        let mut engine = engine_factory(chain_spec.clone()).unwrap();
+        let block_spec = chain_spec.collect_block_spec(header.number);
+        let mut processor = ExecutionProcessor::new(
+            &mut state,
+            None,
+            &mut analysis_cache,
+            &mut *engine,
+            &header,
+            &block,
+            &block_spec,
+        );
+
+        processor.validate_transaction(&deposit(21_000)).unwrap();
+        let receipt = processor.execute_transaction(&deposit(21_000)).unwrap();
+        assert!(receipt.success);
+        assert_eq!(receipt.cumulative_gas_used, 21_000);
+        assert_eq!(receipt.deposit_nonce, Some(0));
+
+        // Below the intrinsic gas, only the mint and the nonce increment remain.
+        let receipt = processor.execute_transaction(&deposit(20_000)).unwrap();
+        assert!(!receipt.success);
+        assert_eq!(receipt.cumulative_gas_used, 41_000);
+        assert_eq!(receipt.deposit_nonce, Some(1));
+        assert_eq!(processor.state().get_nonce(sender).unwrap(), 2);
+        assert_eq!(
+            processor.state().get_balance(recipient).unwrap(),
+            GIGA.as_u256()
+        );
+        assert_eq!(
+            processor.state().get_balance(sender).unwrap(),
+            (2 * ETHER - u128::from(GIGA)).as_u256()
+        );
+
+        for (slot, value) in [(1, 10), (5, 188), (6, 1_000_000)] {
+            processor
+                .state()
+                .set_storage(
+                    crate::execution::optimism::L1_BLOCK_ADDRESS,
+                    slot.as_u256(),
+                    value.as_u256(),
+                )
+                .unwrap();
+        }
+
+        let txn = MessageWithSender {
+            message: Message::Legacy {
+                chain_id: None,
+                nonce: 2,
+                gas_price: 7.as_u256(),
+                gas_limit: 21_000,
+                action: TransactionAction::Call(recipient),
+                value: U256::ZERO,
+                input: Bytes::new(),
+            },
+            sender,
+        };
+        processor.validate_transaction(&txn).unwrap();
+        let (receipt, _) = processor
+            .execute_transaction_with_rollup_cost(
+                &txn,
+                Some(RollupCostData {
+                    zeroes: 3,
+                    ones: 97,
+                }),
+            )
+            .unwrap();
+        assert!(receipt.success);
+        assert_eq!(receipt.deposit_nonce, None);
+
+        let l1_fee = ((3 * 4 + 97 * 16 + 188) * 10).as_u256();
+        assert_eq!(processor.l1_fees()[..2], [None, None]);
+        assert_eq!(processor.l1_fees()[2].unwrap().fee, l1_fee);
+        assert_eq!(processor.state().get_balance(L1_FEE_VAULT).unwrap(), l1_fee);
+        assert_eq!(
+            processor.state().get_balance(BASE_FEE_VAULT).unwrap(),
+            (21_000 * 7).as_u256()
+        );
+    }
 }
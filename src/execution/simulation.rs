@@ -0,0 +1,569 @@
+//! Execution of call sequences in blocks built on top of the chain, as done by `eth_simulateV1`.
+//!
+//! Changes of each simulated block are written to the state and seen by the blocks after it,
+//! so the state should be an overlay that is thrown away, like a [`Buffer`](crate::Buffer)
+//! that is never flushed.
+use super::{
+    analysis_cache::AnalysisCache, outcome::ExecutionOutcome, processor::ExecutionProcessor,
+};
+use crate::{
+    consensus::{self, pre_validate_transaction, ValidationError},
+    models::*,
+    State,
+};
+use anyhow::ensure;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// Changes to an account applied before the calls of a block.
+#[derive(Clone, Debug, Default)]
+pub struct AccountOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<u64>,
+    pub code: Option<Bytes>,
+    /// Replaces the whole storage of the account.
+    pub state: Option<HashMap<U256, U256>>,
+    /// Replaces individual slots, keeping the rest of the storage.
+    pub state_diff: HashMap<U256, U256>,
+}
+
+/// Header fields of a simulated block. Unset fields are derived from the parent.
+#[derive(Clone, Debug, Default)]
+pub struct BlockOverrides {
+    pub number: Option<BlockNumber>,
+    pub timestamp: Option<u64>,
+    pub gas_limit: Option<u64>,
+    pub beneficiary: Option<Address>,
+    pub mix_hash: Option<H256>,
+    pub base_fee_per_gas: Option<U256>,
+}
+
+/// Unsigned transaction to simulate. The nonce and gas limit default to the sender's
+/// next nonce and the gas left in the block.
+#[derive(Clone, Debug, Default)]
+pub struct SimulatedCall {
+    pub sender: Address,
+    pub nonce: Option<u64>,
+    pub gas_limit: Option<u64>,
+    pub gas_price: Option<U256>,
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    pub action: Option<Address>,
+    pub value: U256,
+    pub input: Bytes,
+    pub access_list: Vec<AccessListItem>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BlockStateCalls {
+    pub block_overrides: BlockOverrides,
+    pub state_overrides: HashMap<Address, AccountOverride>,
+    pub calls: Vec<SimulatedCall>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatedCallResult {
+    pub outcome: ExecutionOutcome,
+    pub logs: Vec<Log>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulatedBlock {
+    pub hash: H256,
+    pub header: BlockHeader,
+    pub calls: Vec<SimulatedCallResult>,
+}
+
+/// Serves the headers of simulated blocks, so that `BLOCKHASH` can reach them.
+#[derive(Debug)]
+struct SimulationState<'s, S: State> {
+    inner: &'s mut S,
+    headers: HashMap<(BlockNumber, H256), BlockHeader>,
+}
+
+impl<'s, S: State> State for SimulationState<'s, S> {
+    fn read_account(&self, address: Address) -> anyhow::Result<Option<Account>> {
+        self.inner.read_account(address)
+    }
+
+    fn read_code(&self, code_hash: H256) -> anyhow::Result<Bytes> {
+        self.inner.read_code(code_hash)
+    }
+
+    fn read_code_analysis(&self, code_hash: H256) -> anyhow::Result<Option<Bytes>> {
+        self.inner.read_code_analysis(code_hash)
+    }
+
+    fn read_storage(&self, address: Address, location: U256) -> anyhow::Result<U256> {
+        self.inner.read_storage(address, location)
+    }
+
+    fn erase_storage(&mut self, address: Address) -> anyhow::Result<()> {
+        self.inner.erase_storage(address)
+    }
+
+    fn read_header(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<BlockHeader>> {
+        if let Some(header) = self.headers.get(&(block_number, block_hash)) {
+            return Ok(Some(header.clone()));
+        }
+
+        self.inner.read_header(block_number, block_hash)
+    }
+
+    fn read_body(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<BlockBody>> {
+        self.inner.read_body(block_number, block_hash)
+    }
+
+    fn total_difficulty(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<U256>> {
+        self.inner.total_difficulty(block_number, block_hash)
+    }
+
+    fn read_bor_span(&self, block_number: BlockNumber) -> anyhow::Result<Option<BorSpan>> {
+        self.inner.read_bor_span(block_number)
+    }
+
+    fn read_bor_events(&self, block_number: BlockNumber) -> anyhow::Result<Vec<StateSyncEvent>> {
+        self.inner.read_bor_events(block_number)
+    }
+
+    fn begin_block(&mut self, block_number: BlockNumber) {
+        self.inner.begin_block(block_number)
+    }
+
+    fn update_account(
+        &mut self,
+        address: Address,
+        initial: Option<Account>,
+        current: Option<Account>,
+    ) {
+        self.inner.update_account(address, initial, current)
+    }
+
+    fn update_code(&mut self, code_hash: H256, code: Bytes) -> anyhow::Result<()> {
+        self.inner.update_code(code_hash, code)
+    }
+
+    fn update_storage(
+        &mut self,
+        address: Address,
+        location: U256,
+        initial: U256,
+        current: U256,
+    ) -> anyhow::Result<()> {
+        self.inner.update_storage(address, location, initial, current)
+    }
+}
+
+fn simulated_header(
+    chain_spec: &ChainSpec,
+    parent: &BlockHeader,
+    overrides: &BlockOverrides,
+    validation: bool,
+) -> anyhow::Result<PartialHeader> {
+    let number = overrides.number.unwrap_or(parent.number + 1);
+    ensure!(
+        number == parent.number + 1,
+        "block {} does not follow block {}",
+        number,
+        parent.number
+    );
+    let timestamp = overrides.timestamp.unwrap_or(parent.timestamp + 12);
+    ensure!(
+        timestamp > parent.timestamp,
+        "timestamp {} of block {} is not after its parent",
+        timestamp,
+        number
+    );
+
+    let expected_base_fee_per_gas =
+        consensus::expected_base_fee_per_gas(chain_spec.consensus.eip1559_block, number, parent);
+    let base_fee_per_gas = match overrides.base_fee_per_gas {
+        Some(base_fee_per_gas) => {
+            ensure!(
+                expected_base_fee_per_gas.is_some(),
+                "block {} is before London, it has no base fee",
+                number
+            );
+            Some(base_fee_per_gas)
+        }
+        // Without validation calls without fees must go through.
+        None if !validation => expected_base_fee_per_gas.map(|_| U256::ZERO),
+        None => expected_base_fee_per_gas,
+    };
+
+    Ok(PartialHeader {
+        parent_hash: parent.hash(),
+        beneficiary: overrides.beneficiary.unwrap_or_else(Address::zero),
+        number,
+        gas_limit: overrides.gas_limit.unwrap_or(parent.gas_limit),
+        timestamp,
+        difficulty: parent.difficulty,
+        mix_hash: overrides.mix_hash.unwrap_or(parent.mix_hash),
+        base_fee_per_gas,
+        ..PartialHeader::empty()
+    })
+}
+
+fn apply_override<S: State>(
+    processor: &mut ExecutionProcessor<'_, '_, '_, '_, '_, '_, '_, S>,
+    address: Address,
+    account: &AccountOverride,
+) -> anyhow::Result<()> {
+    let state = processor.state();
+
+    if let Some(balance) = account.balance {
+        state.set_balance(address, balance)?;
+    }
+    if let Some(nonce) = account.nonce {
+        state.set_nonce(address, nonce)?;
+    }
+    if let Some(code) = &account.code {
+        state.set_code(address, code.clone())?;
+    }
+
+    if let Some(storage) = &account.state {
+        // Wiping the storage resets nonce and code too, put them back.
+        let nonce = state.get_nonce(address)?;
+        let code = state.get_code(address)?;
+        state.create_contract(address)?;
+        state.set_nonce(address, nonce)?;
+        if let Some(code) = code {
+            state.set_code(address, code)?;
+        }
+
+        for (&location, &value) in storage {
+            state.set_storage(address, location, value)?;
+        }
+    }
+    for (&location, &value) in &account.state_diff {
+        state.set_storage(address, location, value)?;
+    }
+
+    Ok(())
+}
+
+fn message(chain_id: ChainId, call: &SimulatedCall, nonce: u64, gas_limit: u64) -> Message {
+    let action = call
+        .action
+        .map(TransactionAction::Call)
+        .unwrap_or(TransactionAction::Create);
+
+    if call.max_fee_per_gas.is_some() || call.max_priority_fee_per_gas.is_some() {
+        Message::EIP1559 {
+            chain_id,
+            nonce,
+            max_priority_fee_per_gas: call.max_priority_fee_per_gas.unwrap_or(U256::ZERO),
+            max_fee_per_gas: call.max_fee_per_gas.unwrap_or(U256::ZERO),
+            gas_limit,
+            action,
+            value: call.value,
+            input: call.input.clone(),
+            access_list: call.access_list.clone(),
+        }
+    } else if !call.access_list.is_empty() {
+        Message::EIP2930 {
+            chain_id,
+            nonce,
+            gas_price: call.gas_price.unwrap_or(U256::ZERO),
+            gas_limit,
+            action,
+            value: call.value,
+            input: call.input.clone(),
+            access_list: call.access_list.clone(),
+        }
+    } else {
+        Message::Legacy {
+            chain_id: None,
+            nonce,
+            gas_price: call.gas_price.unwrap_or(U256::ZERO),
+            gas_limit,
+            action,
+            value: call.value,
+            input: call.input.clone(),
+        }
+    }
+}
+
+/// Without validation only what execution cannot do without is checked: the sender pays
+/// for gas and value, and the block has the gas.
+fn check_funds<S: State>(
+    processor: &mut ExecutionProcessor<'_, '_, '_, '_, '_, '_, '_, S>,
+    txn: &MessageWithSender,
+    base_fee_per_gas: U256,
+    gas_left: u64,
+) -> anyhow::Result<()> {
+    if txn.gas_limit() > gas_left {
+        return Err(ValidationError::BlockGasLimitExceeded {
+            available: gas_left,
+            required: txn.gas_limit(),
+        }
+        .into());
+    }
+
+    let required = U512::from(ethereum_types::U256::from(
+        (U256::from(txn.gas_limit()) * txn.effective_gas_price(base_fee_per_gas)).to_be_bytes(),
+    )) + U512::from(ethereum_types::U256::from(txn.value().to_be_bytes()));
+    let available =
+        ethereum_types::U256::from(processor.state().get_balance(txn.sender)?.to_be_bytes()).into();
+    if available < required {
+        return Err(ValidationError::InsufficientFunds {
+            account: txn.sender,
+            available,
+            required,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Simulates `blocks` one after another on top of `parent`, with `state` at the end of it.
+///
+/// With `validation`, calls are checked like transactions of a real block and the first
+/// invalid one fails the simulation. Without it, fees default to zero, nonces are not checked,
+/// and invalid calls are reported in their results.
+pub fn simulate<S: State>(
+    state: &mut S,
+    chain_spec: &ChainSpec,
+    parent: BlockHeader,
+    blocks: Vec<BlockStateCalls>,
+    validation: bool,
+) -> anyhow::Result<Vec<SimulatedBlock>> {
+    let mut state = SimulationState {
+        inner: state,
+        headers: HashMap::new(),
+    };
+    let mut analysis_cache = AnalysisCache::default();
+    let mut engine = consensus::engine_factory(chain_spec.clone())?;
+
+    let mut parent = parent;
+    let mut simulated = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let mut header = simulated_header(chain_spec, &parent, &block.block_overrides, validation)?;
+        let block_spec = chain_spec.collect_block_spec(header.number);
+        let base_fee_per_gas = header.base_fee_per_gas.unwrap_or(U256::ZERO);
+
+        let body = BlockBodyWithSenders::default();
+        let mut processor = ExecutionProcessor::new(
+            &mut state,
+            None,
+            &mut analysis_cache,
+            &mut *engine,
+            &header,
+            &body,
+            &block_spec,
+        );
+
+        for (&address, account) in &block.state_overrides {
+            apply_override(&mut processor, address, account)?;
+        }
+        processor.state().finalize_transaction();
+
+        let mut gas_used = 0;
+        let mut receipts = Vec::with_capacity(block.calls.len());
+        let mut results = Vec::with_capacity(block.calls.len());
+        for (i, call) in block.calls.iter().enumerate() {
+            let nonce = match call.nonce {
+                Some(nonce) => nonce,
+                None => processor.state().get_nonce(call.sender)?,
+            };
+            let gas_left = header.gas_limit - gas_used;
+            let txn = MessageWithSender {
+                message: message(
+                    chain_spec.params.chain_id,
+                    call,
+                    nonce,
+                    call.gas_limit.unwrap_or(gas_left),
+                ),
+                sender: call.sender,
+            };
+
+            let res = if validation {
+                pre_validate_transaction(&txn, chain_spec.params.chain_id, header.base_fee_per_gas)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|_| processor.validate_transaction(&txn))
+            } else {
+                check_funds(&mut processor, &txn, base_fee_per_gas, gas_left)
+            }
+            .and_then(|_| processor.execute_transaction_with_outcome(&txn));
+
+            match res {
+                Ok((receipt, outcome)) => {
+                    gas_used = receipt.cumulative_gas_used;
+                    results.push(SimulatedCallResult {
+                        outcome,
+                        logs: receipt.logs.clone(),
+                    });
+                    receipts.push(receipt);
+                }
+                Err(e) => match e.downcast::<ValidationError>() {
+                    Ok(e) if validation => {
+                        return Err(anyhow::Error::from(e)
+                            .context(format!("call #{} in block {} is invalid", i, header.number)))
+                    }
+                    Ok(e) => results.push(SimulatedCallResult {
+                        outcome: ExecutionOutcome::InvalidTransaction(e),
+                        logs: vec![],
+                    }),
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+
+        processor.into_state().write_to_db(header.number)?;
+
+        header.gas_used = gas_used;
+        header.receipts_root = receipts_root(&receipts);
+        header.logs_bloom = receipts_bloom(&receipts);
+        // Calls are not signed, so there are no transactions to commit to.
+        let header = BlockHeader::new(header, EMPTY_LIST_HASH, EMPTY_ROOT);
+        let hash = header.hash();
+        state.headers.insert((header.number, hash), header.clone());
+
+        simulated.push(SimulatedBlock {
+            hash,
+            header: header.clone(),
+            calls: results,
+        });
+        parent = header;
+    }
+
+    Ok(simulated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{res::chainspec::MAINNET, InMemoryState};
+    use hex_literal::hex;
+
+    fn parent() -> BlockHeader {
+        BlockHeader {
+            number: 15_000_000.into(),
+            gas_limit: 30_000_000,
+            gas_used: 15_000_000,
+            timestamp: 1_655_000_000,
+            base_fee_per_gas: Some(10_000_000_000_u64.into()),
+            ..BlockHeader::empty()
+        }
+    }
+
+    #[test]
+    fn state_carries_over_between_blocks() {
+        let sender = Address::repeat_byte(1);
+        let contract = Address::repeat_byte(2);
+        let recipient = Address::repeat_byte(3);
+
+        // Copies storage slot 0 into the log data: PUSH1 0 SLOAD PUSH1 0 MSTORE PUSH1 32 PUSH1 0 LOG0
+        let code = Bytes::from(hex!("60005460005260206000a0").to_vec());
+
+        let mut state = InMemoryState::default();
+        let blocks = vec![
+            BlockStateCalls {
+                state_overrides: [
+                    (
+                        sender,
+                        AccountOverride {
+                            balance: Some(U256::from(1_000_000_u64)),
+                            ..Default::default()
+                        },
+                    ),
+                    (
+                        contract,
+                        AccountOverride {
+                            code: Some(code),
+                            state_diff: [(U256::ZERO, U256::from(42_u64))].into_iter().collect(),
+                            ..Default::default()
+                        },
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+                calls: vec![SimulatedCall {
+                    sender,
+                    action: Some(recipient),
+                    value: U256::from(1000_u64),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            BlockStateCalls {
+                calls: vec![
+                    SimulatedCall {
+                        sender,
+                        action: Some(contract),
+                        ..Default::default()
+                    },
+                    SimulatedCall {
+                        sender,
+                        action: Some(recipient),
+                        value: U256::from(10_000_000_u64),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            },
+        ];
+
+        let simulated = simulate(&mut state, &MAINNET, parent(), blocks, false).unwrap();
+        assert_eq!(simulated.len(), 2);
+        assert_eq!(simulated[0].header.number, BlockNumber(15_000_001));
+        assert_eq!(simulated[1].header.parent_hash, simulated[0].hash);
+        assert_eq!(simulated[0].header.base_fee_per_gas, Some(U256::ZERO));
+
+        assert!(simulated[0].calls[0].outcome.is_success());
+        assert_eq!(simulated[0].calls[0].outcome.gas_used(), Some(21_000));
+
+        // Overrides of the first block are still there in the second one.
+        let call = &simulated[1].calls[0];
+        assert!(call.outcome.is_success());
+        assert_eq!(call.logs.len(), 1);
+        assert_eq!(call.logs[0].data[31], 42);
+
+        // The sender spent most of its balance in the first block.
+        assert!(matches!(
+            simulated[1].calls[1].outcome,
+            ExecutionOutcome::InvalidTransaction(ValidationError::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn validation_rejects_invalid_calls() {
+        let sender = Address::repeat_byte(1);
+        let block = BlockStateCalls {
+            calls: vec![SimulatedCall {
+                sender,
+                action: Some(Address::repeat_byte(2)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut state = InMemoryState::default();
+        let parent = parent();
+        // Zero gas price is below the base fee.
+        simulate(
+            &mut state,
+            &MAINNET,
+            parent.clone(),
+            vec![block.clone()],
+            true,
+        )
+        .unwrap_err();
+
+        let simulated = simulate(&mut state, &MAINNET, parent, vec![block], false).unwrap();
+        assert!(simulated[0].calls[0].outcome.is_success());
+    }
+}
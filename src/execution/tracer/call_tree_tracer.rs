@@ -0,0 +1,95 @@
+use super::*;
+use crate::execution::evm::{Output, StatusCode};
+
+/// SELFDESTRUCT ending a message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfDestruct {
+    pub beneficiary: Address,
+    pub balance: U256,
+}
+
+/// Message sent during a transaction with its result, and the messages it sent in turn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallTree {
+    pub frame: CallFrame,
+    pub gas_used: u64,
+    pub status_code: StatusCode,
+    pub output: Bytes,
+    pub calls: Vec<CallTree>,
+    /// Made after all of `calls`.
+    pub self_destruct: Option<SelfDestruct>,
+}
+
+/// Collects the messages of every transaction it traces, nested by caller.
+#[derive(Debug, Default)]
+pub struct CallTreeTracer {
+    /// Messages being executed, outermost first.
+    stack: Vec<CallTree>,
+    root: Option<CallTree>,
+    trees: Vec<Option<CallTree>>,
+}
+
+impl CallTreeTracer {
+    /// Transaction itself, by transaction index. `None` if it did not get to execution.
+    pub fn into_trees(self) -> Vec<Option<CallTree>> {
+        self.trees
+    }
+}
+
+impl Tracer for CallTreeTracer {
+    fn capture_start(
+        &mut self,
+        depth: u16,
+        from: Address,
+        to: Address,
+        kind: MessageKind,
+        input: Bytes,
+        gas: u64,
+        value: U256,
+    ) {
+        self.stack.push(CallTree {
+            frame: CallFrame {
+                depth,
+                kind,
+                from,
+                to,
+                input,
+                gas,
+                value,
+            },
+            gas_used: 0,
+            status_code: StatusCode::Success,
+            output: Bytes::new(),
+            calls: vec![],
+            self_destruct: None,
+        });
+    }
+
+    fn capture_exit(&mut self, _: u16, output: &Output) {
+        if let Some(mut call) = self.stack.pop() {
+            call.gas_used = call.frame.gas.saturating_sub(output.gas_left.max(0) as u64);
+            call.status_code = output.status_code.clone();
+            call.output = output.output_data.clone();
+
+            match self.stack.last_mut() {
+                Some(caller) => caller.calls.push(call),
+                None => self.root = Some(call),
+            }
+        }
+    }
+
+    fn capture_self_destruct(&mut self, _: Address, beneficiary: Address, balance: U256) {
+        if let Some(call) = self.stack.last_mut() {
+            call.self_destruct = Some(SelfDestruct {
+                beneficiary,
+                balance,
+            });
+        }
+    }
+
+    // Reported once at the end of every transaction.
+    fn capture_internal_transfers(&mut self, _: &[InternalTransfer]) {
+        self.stack.clear();
+        self.trees.push(self.root.take());
+    }
+}
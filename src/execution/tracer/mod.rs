@@ -1,9 +1,16 @@
+pub mod call_tree_tracer;
 pub mod eip3155_tracer;
+pub mod prestate_tracer;
+pub mod struct_logger;
 
 use auto_impl::auto_impl;
+pub use call_tree_tracer::{CallTree, CallTreeTracer, SelfDestruct};
 pub use eip3155_tracer::StdoutTracer;
+pub use prestate_tracer::{AccountPrestate, PrestateTracer};
+pub use struct_logger::{StructLog, StructLogger, StructLoggerConfig};
 
 use crate::{
+    consensus::RewardKind,
     execution::evm::{ExecutionState, OpCode},
     models::*,
 };
@@ -62,9 +69,21 @@ pub trait Tracer: Send {
     ) {
     }
     fn capture_end(&mut self, output: &Output) {}
-    fn capture_self_destruct(&mut self, caller: Address, beneficiary: Address) {}
+    /// Final result of a message reported to [`Self::capture_start`], including precompiles
+    /// and accounts without code.
+    fn capture_exit(&mut self, depth: u16, output: &Output) {}
+    /// SELFDESTRUCT of `caller`, sending its `balance` to `beneficiary`.
+    fn capture_self_destruct(&mut self, caller: Address, beneficiary: Address, balance: U256) {}
     fn capture_account_read(&mut self, account: Address) {}
     fn capture_account_write(&mut self, account: Address) {}
+    /// Block or ommer reward paid to `author` after the block's transactions.
+    fn capture_reward(&mut self, author: Address, kind: RewardKind, value: U256) {}
+    /// Value transfers made inside contract execution that were not reverted.
+    /// Called once for every executed transaction, even if there were none.
+    fn capture_internal_transfers(&mut self, transfers: &[InternalTransfer]) {}
+    /// Contracts created by a transaction that were not reverted.
+    /// Called once for every executed transaction, even if there were none.
+    fn capture_contract_creations(&mut self, creations: &[ContractCreation]) {}
 }
 
 /// Tracer which does nothing.
@@ -98,10 +117,14 @@ impl Tracer for CallTracer {
         self.addresses.entry(to).or_default().to = true;
     }
 
-    fn capture_self_destruct(&mut self, caller: Address, beneficiary: Address) {
+    fn capture_self_destruct(&mut self, caller: Address, beneficiary: Address, _: U256) {
         self.addresses.entry(caller).or_default().from = true;
         self.addresses.entry(beneficiary).or_default().to = true;
     }
+
+    fn capture_reward(&mut self, author: Address, _: RewardKind, _: U256) {
+        self.addresses.entry(author).or_default().to = true;
+    }
 }
 
 impl CallTracer {
@@ -113,3 +136,268 @@ impl CallTracer {
             .into_iter()
     }
 }
+
+/// Collects internal transfers of a block, grouped by transaction.
+#[derive(Debug, Default)]
+pub struct TransferTracer {
+    transfers: Vec<Vec<InternalTransfer>>,
+}
+
+impl Tracer for TransferTracer {
+    fn capture_internal_transfers(&mut self, transfers: &[InternalTransfer]) {
+        self.transfers.push(transfers.to_vec());
+    }
+}
+
+impl TransferTracer {
+    /// Internal transfers of transactions that made any, keyed by transaction index.
+    pub fn into_transfers(self) -> impl Iterator<Item = (TxIndex, Vec<InternalTransfer>)> {
+        self.transfers
+            .into_iter()
+            .enumerate()
+            .filter(|(_, transfers)| !transfers.is_empty())
+            .map(|(index, transfers)| (TxIndex(index as u64), transfers))
+    }
+}
+
+/// Collects contract creations of a block, grouped by transaction.
+#[derive(Debug, Default)]
+pub struct CreationTracer {
+    creations: Vec<Vec<ContractCreation>>,
+}
+
+impl Tracer for CreationTracer {
+    fn capture_contract_creations(&mut self, creations: &[ContractCreation]) {
+        self.creations.push(creations.to_vec());
+    }
+}
+
+impl CreationTracer {
+    /// Contract creations of transactions that made any, keyed by transaction index.
+    pub fn into_creations(self) -> impl Iterator<Item = (TxIndex, Vec<ContractCreation>)> {
+        self.creations
+            .into_iter()
+            .enumerate()
+            .filter(|(_, creations)| !creations.is_empty())
+            .map(|(index, creations)| (TxIndex(index as u64), creations))
+    }
+}
+
+/// Collects the block and ommer rewards of a block.
+#[derive(Debug, Default)]
+pub struct RewardTracer {
+    rewards: Vec<(Address, RewardKind, U256)>,
+}
+
+impl Tracer for RewardTracer {
+    fn capture_reward(&mut self, author: Address, kind: RewardKind, value: U256) {
+        self.rewards.push((author, kind, value));
+    }
+}
+
+impl RewardTracer {
+    /// Rewards in the order they were paid.
+    pub fn into_rewards(self) -> Vec<(Address, RewardKind, U256)> {
+        self.rewards
+    }
+}
+
+/// Message sent during a transaction: the transaction itself at depth 0, or a call or
+/// creation made by a contract.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallFrame {
+    pub depth: u16,
+    pub kind: MessageKind,
+    pub from: Address,
+    pub to: Address,
+    pub input: Bytes,
+    pub gas: u64,
+    pub value: U256,
+}
+
+/// Collects the messages of a block, grouped by transaction, in the order they were sent.
+#[derive(Debug, Default)]
+pub struct CallFrameTracer {
+    current: Vec<CallFrame>,
+    frames: Vec<Vec<CallFrame>>,
+}
+
+impl Tracer for CallFrameTracer {
+    fn capture_start(
+        &mut self,
+        depth: u16,
+        from: Address,
+        to: Address,
+        kind: MessageKind,
+        input: Bytes,
+        gas: u64,
+        value: U256,
+    ) {
+        self.current.push(CallFrame {
+            depth,
+            kind,
+            from,
+            to,
+            input,
+            gas,
+            value,
+        });
+    }
+
+    // Reported once at the end of every transaction.
+    fn capture_internal_transfers(&mut self, _: &[InternalTransfer]) {
+        self.frames.push(std::mem::take(&mut self.current));
+    }
+}
+
+impl CallFrameTracer {
+    /// Messages of every transaction, by transaction index.
+    pub fn into_frames(self) -> Vec<Vec<CallFrame>> {
+        self.frames
+    }
+}
+
+/// Feeds every event to both tracers.
+impl<A: Tracer, B: Tracer> Tracer for (A, B) {
+    fn trace_instructions(&self) -> bool {
+        self.0.trace_instructions() || self.1.trace_instructions()
+    }
+    fn capture_start(
+        &mut self,
+        depth: u16,
+        from: Address,
+        to: Address,
+        call_type: MessageKind,
+        input: Bytes,
+        gas: u64,
+        value: U256,
+    ) {
+        self.0.capture_start(
+            depth,
+            from,
+            to,
+            call_type.clone(),
+            input.clone(),
+            gas,
+            value,
+        );
+        self.1
+            .capture_start(depth, from, to, call_type, input, gas, value);
+    }
+    fn capture_state(
+        &mut self,
+        env: &ExecutionState,
+        pc: usize,
+        op: OpCode,
+        cost: u64,
+        depth: u16,
+    ) {
+        self.0.capture_state(env, pc, op, cost, depth);
+        self.1.capture_state(env, pc, op, cost, depth);
+    }
+    fn capture_end(&mut self, output: &Output) {
+        self.0.capture_end(output);
+        self.1.capture_end(output);
+    }
+    fn capture_exit(&mut self, depth: u16, output: &Output) {
+        self.0.capture_exit(depth, output);
+        self.1.capture_exit(depth, output);
+    }
+    fn capture_self_destruct(&mut self, caller: Address, beneficiary: Address, balance: U256) {
+        self.0.capture_self_destruct(caller, beneficiary, balance);
+        self.1.capture_self_destruct(caller, beneficiary, balance);
+    }
+    fn capture_account_read(&mut self, account: Address) {
+        self.0.capture_account_read(account);
+        self.1.capture_account_read(account);
+    }
+    fn capture_account_write(&mut self, account: Address) {
+        self.0.capture_account_write(account);
+        self.1.capture_account_write(account);
+    }
+    fn capture_reward(&mut self, author: Address, kind: RewardKind, value: U256) {
+        self.0.capture_reward(author, kind, value);
+        self.1.capture_reward(author, kind, value);
+    }
+    fn capture_internal_transfers(&mut self, transfers: &[InternalTransfer]) {
+        self.0.capture_internal_transfers(transfers);
+        self.1.capture_internal_transfers(transfers);
+    }
+    fn capture_contract_creations(&mut self, creations: &[ContractCreation]) {
+        self.0.capture_contract_creations(creations);
+        self.1.capture_contract_creations(creations);
+    }
+}
+
+/// Tracer that may be switched off.
+impl<T: Tracer> Tracer for Option<T> {
+    fn trace_instructions(&self) -> bool {
+        matches!(self, Some(tracer) if tracer.trace_instructions())
+    }
+    fn capture_start(
+        &mut self,
+        depth: u16,
+        from: Address,
+        to: Address,
+        call_type: MessageKind,
+        input: Bytes,
+        gas: u64,
+        value: U256,
+    ) {
+        if let Some(tracer) = self {
+            tracer.capture_start(depth, from, to, call_type, input, gas, value);
+        }
+    }
+    fn capture_state(
+        &mut self,
+        env: &ExecutionState,
+        pc: usize,
+        op: OpCode,
+        cost: u64,
+        depth: u16,
+    ) {
+        if let Some(tracer) = self {
+            tracer.capture_state(env, pc, op, cost, depth);
+        }
+    }
+    fn capture_end(&mut self, output: &Output) {
+        if let Some(tracer) = self {
+            tracer.capture_end(output);
+        }
+    }
+    fn capture_exit(&mut self, depth: u16, output: &Output) {
+        if let Some(tracer) = self {
+            tracer.capture_exit(depth, output);
+        }
+    }
+    fn capture_self_destruct(&mut self, caller: Address, beneficiary: Address, balance: U256) {
+        if let Some(tracer) = self {
+            tracer.capture_self_destruct(caller, beneficiary, balance);
+        }
+    }
+    fn capture_account_read(&mut self, account: Address) {
+        if let Some(tracer) = self {
+            tracer.capture_account_read(account);
+        }
+    }
+    fn capture_account_write(&mut self, account: Address) {
+        if let Some(tracer) = self {
+            tracer.capture_account_write(account);
+        }
+    }
+    fn capture_reward(&mut self, author: Address, kind: RewardKind, value: U256) {
+        if let Some(tracer) = self {
+            tracer.capture_reward(author, kind, value);
+        }
+    }
+    fn capture_internal_transfers(&mut self, transfers: &[InternalTransfer]) {
+        if let Some(tracer) = self {
+            tracer.capture_internal_transfers(transfers);
+        }
+    }
+    fn capture_contract_creations(&mut self, creations: &[ContractCreation]) {
+        if let Some(tracer) = self {
+            tracer.capture_contract_creations(creations);
+        }
+    }
+}
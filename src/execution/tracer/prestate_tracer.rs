@@ -0,0 +1,111 @@
+use super::*;
+use crate::{u256_to_h256, IntraBlockState, State};
+use std::collections::BTreeSet;
+
+/// Account as it was before a transaction, with the storage slots the transaction accessed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccountPrestate {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Bytes,
+    pub storage: BTreeMap<U256, U256>,
+}
+
+/// Collects the accounts and storage slots accessed by the transaction it traces, to read their
+/// state from before it with [`PrestateTracer::read_prestate`].
+#[derive(Debug, Default)]
+pub struct PrestateTracer {
+    accounts: BTreeMap<Address, BTreeSet<U256>>,
+}
+
+impl PrestateTracer {
+    fn touch(&mut self, address: Address) -> &mut BTreeSet<U256> {
+        self.accounts.entry(address).or_default()
+    }
+
+    /// Accessed accounts that exist in `state`, which must be the one the transaction started
+    /// from.
+    pub fn read_prestate<S: State>(
+        &self,
+        state: &mut IntraBlockState<'_, S>,
+    ) -> anyhow::Result<BTreeMap<Address, AccountPrestate>> {
+        let mut out = BTreeMap::new();
+        for (&address, slots) in &self.accounts {
+            if !state.exists(address)? {
+                continue;
+            }
+
+            let mut storage = BTreeMap::new();
+            for &slot in slots {
+                storage.insert(slot, state.get_current_storage(address, slot)?);
+            }
+            out.insert(
+                address,
+                AccountPrestate {
+                    balance: state.get_balance(address)?,
+                    nonce: state.get_nonce(address)?,
+                    code: state.get_code(address)?.unwrap_or_default(),
+                    storage,
+                },
+            );
+        }
+
+        Ok(out)
+    }
+}
+
+impl Tracer for PrestateTracer {
+    fn trace_instructions(&self) -> bool {
+        true
+    }
+
+    fn capture_start(
+        &mut self,
+        _: u16,
+        from: Address,
+        to: Address,
+        _: MessageKind,
+        _: Bytes,
+        _: u64,
+        _: U256,
+    ) {
+        self.touch(from);
+        self.touch(to);
+    }
+
+    fn capture_state(&mut self, env: &ExecutionState, _: usize, op: OpCode, _: u64, _: u16) {
+        // Stack underflows are only checked after tracing.
+        let stack = env.stack();
+        if stack.is_empty() {
+            return;
+        }
+
+        let top = *stack.get(0);
+        match op {
+            OpCode::SLOAD | OpCode::SSTORE => {
+                self.touch(env.message.recipient).insert(top);
+            }
+            OpCode::BALANCE
+            | OpCode::EXTCODESIZE
+            | OpCode::EXTCODECOPY
+            | OpCode::EXTCODEHASH
+            | OpCode::SELFDESTRUCT => {
+                self.touch(Address::from(u256_to_h256(top)));
+            }
+            _ => {}
+        }
+    }
+
+    fn capture_self_destruct(&mut self, caller: Address, beneficiary: Address, _: U256) {
+        self.touch(caller);
+        self.touch(beneficiary);
+    }
+
+    fn capture_account_read(&mut self, account: Address) {
+        self.touch(account);
+    }
+
+    fn capture_account_write(&mut self, account: Address) {
+        self.touch(account);
+    }
+}
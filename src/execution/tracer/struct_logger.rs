@@ -0,0 +1,129 @@
+use super::*;
+use crate::execution::evm::Output;
+
+/// Instruction of a transaction, with the state it was executed in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StructLog {
+    pub pc: usize,
+    pub op: OpCode,
+    /// Gas left before the instruction.
+    pub gas: u64,
+    /// Gas spent by the instruction, including by the calls and creations it made. The static
+    /// cost for the last instruction of a frame.
+    pub gas_cost: u64,
+    pub depth: u16,
+    /// Bottom first.
+    pub stack: Option<Vec<U256>>,
+    pub memory: Option<Bytes>,
+    /// Storage of the executing contract read or written by the transaction so far, only for
+    /// SLOAD and SSTORE.
+    pub storage: Option<BTreeMap<U256, U256>>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StructLoggerConfig {
+    pub disable_stack: bool,
+    pub disable_storage: bool,
+    pub enable_memory: bool,
+}
+
+/// Logs every instruction of the transactions it traces.
+#[derive(Debug, Default)]
+pub struct StructLogger {
+    config: StructLoggerConfig,
+    logs: Vec<StructLog>,
+    /// Last instruction of each frame being executed, by index in `logs`.
+    pending: Vec<usize>,
+    /// SLOAD of a slot whose value is on the stack at the next instruction of the frame.
+    pending_load: Option<(usize, Address, U256)>,
+    storage: HashMap<Address, BTreeMap<U256, U256>>,
+}
+
+impl StructLogger {
+    pub fn new(config: StructLoggerConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn into_logs(self) -> Vec<StructLog> {
+        self.logs
+    }
+}
+
+impl Tracer for StructLogger {
+    fn trace_instructions(&self) -> bool {
+        true
+    }
+
+    fn capture_state(
+        &mut self,
+        env: &ExecutionState,
+        pc: usize,
+        op: OpCode,
+        cost: u64,
+        depth: u16,
+    ) {
+        let gas = (*env.gas_left()).max(0) as u64;
+
+        // Frames above this one are over.
+        while matches!(self.pending.last(), Some(&i) if self.logs[i].depth > depth) {
+            self.pending.pop();
+        }
+        if matches!(self.pending.last(), Some(&i) if self.logs[i].depth == depth) {
+            let prev = &mut self.logs[self.pending.pop().unwrap()];
+            prev.gas_cost = prev.gas.saturating_sub(gas);
+        }
+
+        if let Some((i, address, key)) = self.pending_load.take() {
+            if self.logs[i].depth == depth && !env.stack().is_empty() {
+                let value = *env.stack().get(0);
+                self.storage.entry(address).or_default().insert(key, value);
+                if let Some(storage) = &mut self.logs[i].storage {
+                    storage.insert(key, value);
+                }
+            }
+        }
+
+        // Stack underflows are only checked after tracing.
+        let address = env.message.recipient;
+        let stack = env.stack();
+        let storage = match op {
+            OpCode::SLOAD if !stack.is_empty() => {
+                self.pending_load = Some((self.logs.len(), address, *stack.get(0)));
+                true
+            }
+            OpCode::SSTORE if stack.len() >= 2 => {
+                let (key, value) = (*stack.get(0), *stack.get(1));
+                self.storage.entry(address).or_default().insert(key, value);
+                true
+            }
+            _ => false,
+        };
+
+        self.pending.push(self.logs.len());
+        self.logs.push(StructLog {
+            pc,
+            op,
+            gas,
+            gas_cost: cost,
+            depth,
+            stack: (!self.config.disable_stack).then(|| stack.0.to_vec()),
+            memory: self
+                .config
+                .enable_memory
+                .then(|| Bytes::copy_from_slice(env.memory())),
+            storage: (storage && !self.config.disable_storage)
+                .then(|| self.storage.get(&address).cloned().unwrap_or_default()),
+        });
+    }
+
+    fn capture_exit(&mut self, depth: u16, _: &Output) {
+        // Instructions that ended the transaction's frames keep their static cost.
+        if depth == 0 {
+            self.pending.clear();
+            self.pending_load = None;
+        }
+    }
+}
@@ -0,0 +1,842 @@
+//! Columnar export of blocks, transactions, receipts and logs over a block range, to CSV or
+//! Parquet files that data warehouses can bulk-load.
+//!
+//! Each table goes to its own file, `<table>_<from>_<to>.<format>`, with the columns selected in
+//! an [`ExportSchema`]. Receipts are not stored, so blocks are replayed for the receipts and logs
+//! tables, which requires them to be executed.
+use crate::{
+    accessors::chain, execution::address::create_address, kv::mdbx::*, models::*,
+    stagedsync::stages::EXECUTION,
+};
+use anyhow::{bail, ensure, format_err};
+use parquet::{
+    basic::Compression,
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    file::{
+        properties::WriterProperties,
+        writer::{FileWriter, RowGroupWriter, SerializedFileWriter},
+    },
+    schema::parser::parse_message_type,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::*;
+
+/// Rows buffered per Parquet row group.
+const ROW_GROUP_SIZE: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "parquet" => Ok(Self::Parquet),
+            _ => bail!("expected csv or parquet, got {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Table {
+    Blocks,
+    Transactions,
+    Receipts,
+    Logs,
+}
+
+impl Table {
+    pub const ALL: [Self; 4] = [Self::Blocks, Self::Transactions, Self::Receipts, Self::Logs];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Blocks => "blocks",
+            Self::Transactions => "transactions",
+            Self::Receipts => "receipts",
+            Self::Logs => "logs",
+        }
+    }
+
+    /// Every column of the table, in default order.
+    pub fn columns(self) -> &'static [Column] {
+        match self {
+            Self::Blocks => BLOCK_COLUMNS,
+            Self::Transactions => TRANSACTION_COLUMNS,
+            Self::Receipts => RECEIPT_COLUMNS,
+            Self::Logs => LOG_COLUMNS,
+        }
+    }
+
+    fn needs_receipts(self) -> bool {
+        matches!(self, Self::Receipts | Self::Logs)
+    }
+}
+
+impl FromStr for Table {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|table| table.name() == s)
+            .ok_or_else(|| {
+                format_err!("expected blocks, transactions, receipts or logs, got {}", s)
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    /// INT64 in Parquet.
+    U64,
+    /// Hashes, addresses and data: `0x`-prefixed hex in CSV, BINARY in Parquet.
+    Bytes,
+    /// 256-bit quantities, which overflow the integer types of warehouses: decimal strings.
+    U256,
+    Bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Column {
+    pub name: &'static str,
+    pub ty: ColumnType,
+    pub nullable: bool,
+}
+
+const fn column(name: &'static str, ty: ColumnType) -> Column {
+    Column {
+        name,
+        ty,
+        nullable: false,
+    }
+}
+
+const fn nullable(name: &'static str, ty: ColumnType) -> Column {
+    Column {
+        name,
+        ty,
+        nullable: true,
+    }
+}
+
+const BLOCK_COLUMNS: &[Column] = &[
+    column("number", ColumnType::U64),
+    column("hash", ColumnType::Bytes),
+    column("parent_hash", ColumnType::Bytes),
+    column("timestamp", ColumnType::U64),
+    column("miner", ColumnType::Bytes),
+    column("state_root", ColumnType::Bytes),
+    column("gas_limit", ColumnType::U64),
+    column("gas_used", ColumnType::U64),
+    nullable("base_fee_per_gas", ColumnType::U256),
+    column("difficulty", ColumnType::U256),
+    column("transaction_count", ColumnType::U64),
+    column("extra_data", ColumnType::Bytes),
+];
+
+const TRANSACTION_COLUMNS: &[Column] = &[
+    column("block_number", ColumnType::U64),
+    column("transaction_index", ColumnType::U64),
+    column("hash", ColumnType::Bytes),
+    column("type", ColumnType::U64),
+    column("from", ColumnType::Bytes),
+    nullable("to", ColumnType::Bytes),
+    column("nonce", ColumnType::U64),
+    column("value", ColumnType::U256),
+    column("gas_limit", ColumnType::U64),
+    column("max_fee_per_gas", ColumnType::U256),
+    column("max_priority_fee_per_gas", ColumnType::U256),
+    column("input", ColumnType::Bytes),
+];
+
+const RECEIPT_COLUMNS: &[Column] = &[
+    column("block_number", ColumnType::U64),
+    column("transaction_index", ColumnType::U64),
+    column("transaction_hash", ColumnType::Bytes),
+    column("status", ColumnType::Bool),
+    column("cumulative_gas_used", ColumnType::U64),
+    column("gas_used", ColumnType::U64),
+    column("effective_gas_price", ColumnType::U256),
+    nullable("contract_address", ColumnType::Bytes),
+    column("log_count", ColumnType::U64),
+];
+
+const LOG_COLUMNS: &[Column] = &[
+    column("block_number", ColumnType::U64),
+    column("transaction_index", ColumnType::U64),
+    column("log_index", ColumnType::U64),
+    column("transaction_hash", ColumnType::Bytes),
+    column("address", ColumnType::Bytes),
+    nullable("topic0", ColumnType::Bytes),
+    nullable("topic1", ColumnType::Bytes),
+    nullable("topic2", ColumnType::Bytes),
+    nullable("topic3", ColumnType::Bytes),
+    column("data", ColumnType::Bytes),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Value {
+    U64(u64),
+    Bytes(Vec<u8>),
+    U256(U256),
+    Bool(bool),
+    Null,
+}
+
+impl From<u64> for Value {
+    fn from(v: u64) -> Self {
+        Self::U64(v)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(v: usize) -> Self {
+        Self::U64(v as u64)
+    }
+}
+
+impl From<U256> for Value {
+    fn from(v: U256) -> Self {
+        Self::U256(v)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Self::Bool(v)
+    }
+}
+
+impl From<H256> for Value {
+    fn from(v: H256) -> Self {
+        Self::Bytes(v.as_bytes().to_vec())
+    }
+}
+
+impl From<Address> for Value {
+    fn from(v: Address) -> Self {
+        Self::Bytes(v.as_bytes().to_vec())
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(v: &[u8]) -> Self {
+        Self::Bytes(v.to_vec())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map(Into::into).unwrap_or(Self::Null)
+    }
+}
+
+pub(crate) type Row = Vec<Value>;
+
+/// Columns to export, by table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportSchema {
+    /// Indices into [`Table::columns`].
+    tables: BTreeMap<Table, Vec<usize>>,
+}
+
+impl ExportSchema {
+    /// `tables`, or all of them if empty, with every column unless some of a table are given in
+    /// `columns` as `<table>.<column>`, in which case only those, in that order.
+    pub fn new(tables: &[Table], columns: &[String]) -> anyhow::Result<Self> {
+        let tables = if tables.is_empty() {
+            &Table::ALL[..]
+        } else {
+            tables
+        };
+
+        let mut selected = BTreeMap::<Table, Vec<usize>>::new();
+        for column in columns {
+            let (table, name) = column
+                .split_once('.')
+                .ok_or_else(|| format_err!("expected <table>.<column>, got {}", column))?;
+            let table = table.parse::<Table>()?;
+            ensure!(
+                tables.contains(&table),
+                "column {} of table {}, which is not exported",
+                name,
+                table.name()
+            );
+            let index = table
+                .columns()
+                .iter()
+                .position(|c| c.name == name)
+                .ok_or_else(|| format_err!("no column {} in table {}", name, table.name()))?;
+            selected.entry(table).or_default().push(index);
+        }
+
+        Ok(Self {
+            tables: tables
+                .iter()
+                .map(|&table| {
+                    let columns = selected
+                        .remove(&table)
+                        .unwrap_or_else(|| (0..table.columns().len()).collect());
+                    (table, columns)
+                })
+                .collect(),
+        })
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = Table> + '_ {
+        self.tables.keys().copied()
+    }
+
+    /// Selected columns of `table`, empty if it is not exported.
+    pub fn columns(&self, table: Table) -> Vec<Column> {
+        self.tables
+            .get(&table)
+            .map(|indices| indices.iter().map(|&i| table.columns()[i]).collect())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn project(&self, table: Table, row: &[Value]) -> Row {
+        self.tables[&table]
+            .iter()
+            .map(|&i| row[i].clone())
+            .collect()
+    }
+}
+
+trait TableWriter {
+    fn write(&mut self, rows: Vec<Row>) -> anyhow::Result<()>;
+    fn finish(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+struct CsvWriter(BufWriter<File>);
+
+impl CsvWriter {
+    fn new(path: &Path, columns: &[Column]) -> anyhow::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(
+            out,
+            "{}",
+            columns.iter().map(|c| c.name).collect::<Vec<_>>().join(",")
+        )?;
+        Ok(Self(out))
+    }
+}
+
+/// Text of a value, hex or decimal, so never needs quoting in CSV.
+pub(crate) fn text_field(value: &Value) -> String {
+    match value {
+        Value::U64(v) => v.to_string(),
+        Value::Bytes(v) => format!("0x{}", hex::encode(v)),
+        Value::U256(v) => v.to_string(),
+        Value::Bool(v) => v.to_string(),
+        Value::Null => String::new(),
+    }
+}
+
+impl TableWriter for CsvWriter {
+    fn write(&mut self, rows: Vec<Row>) -> anyhow::Result<()> {
+        let mut line = String::new();
+        for row in rows {
+            line.clear();
+            for (i, value) in row.iter().enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                line.push_str(&text_field(value));
+            }
+            writeln!(self.0, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        self.0.flush()?;
+        Ok(())
+    }
+}
+
+/// Parquet message type of `columns`.
+fn parquet_schema(table: Table, columns: &[Column]) -> String {
+    let mut schema = format!("message {} {{\n", table.name());
+    for column in columns {
+        let (ty, annotation) = match column.ty {
+            ColumnType::U64 => ("INT64", " (UINT_64)"),
+            ColumnType::Bytes => ("BINARY", ""),
+            ColumnType::U256 => ("BINARY", " (UTF8)"),
+            ColumnType::Bool => ("BOOLEAN", ""),
+        };
+        let repetition = if column.nullable {
+            "OPTIONAL"
+        } else {
+            "REQUIRED"
+        };
+        let _ = writeln!(
+            schema,
+            "  {} {} {}{};",
+            repetition, ty, column.name, annotation
+        );
+    }
+    schema.push('}');
+    schema
+}
+
+struct ParquetWriter {
+    writer: SerializedFileWriter<File>,
+    columns: Vec<Column>,
+    rows: Vec<Row>,
+}
+
+impl ParquetWriter {
+    fn new(path: &Path, table: Table, columns: Vec<Column>) -> anyhow::Result<Self> {
+        let schema = Arc::new(parse_message_type(&parquet_schema(table, &columns))?);
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+
+        Ok(Self {
+            writer: SerializedFileWriter::new(File::create(path)?, schema, properties)?,
+            columns,
+            rows: Vec::new(),
+        })
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut row_group = self.writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column_writer) = row_group.next_column()? {
+            let column = self.columns[index];
+            let values = self.rows.iter().map(|row| &row[index]);
+            let def_levels = values
+                .clone()
+                .map(|value| i16::from(*value != Value::Null))
+                .collect::<Vec<_>>();
+            let def_levels = column.nullable.then(|| &def_levels[..]);
+            let values = values.filter(|value| **value != Value::Null);
+
+            match &mut column_writer {
+                ColumnWriter::Int64ColumnWriter(w) => {
+                    let values = values
+                        .map(|value| match value {
+                            Value::U64(v) => *v as i64,
+                            _ => unreachable!(),
+                        })
+                        .collect::<Vec<_>>();
+                    w.write_batch(&values, def_levels, None)?;
+                }
+                ColumnWriter::BoolColumnWriter(w) => {
+                    let values = values
+                        .map(|value| match value {
+                            Value::Bool(v) => *v,
+                            _ => unreachable!(),
+                        })
+                        .collect::<Vec<_>>();
+                    w.write_batch(&values, def_levels, None)?;
+                }
+                ColumnWriter::ByteArrayColumnWriter(w) => {
+                    let values = values
+                        .map(|value| match value {
+                            Value::Bytes(v) => ByteArray::from(v.clone()),
+                            Value::U256(v) => ByteArray::from(v.to_string().into_bytes()),
+                            _ => unreachable!(),
+                        })
+                        .collect::<Vec<_>>();
+                    w.write_batch(&values, def_levels, None)?;
+                }
+                _ => unreachable!(),
+            }
+
+            row_group.close_column(column_writer)?;
+            index += 1;
+        }
+        self.writer.close_row_group(row_group)?;
+        self.rows.clear();
+
+        Ok(())
+    }
+}
+
+impl TableWriter for ParquetWriter {
+    fn write(&mut self, rows: Vec<Row>) -> anyhow::Result<()> {
+        self.rows.extend(rows);
+        if self.rows.len() >= ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+/// Rows of every table for one block, in [`Table::columns`] order.
+#[derive(Default)]
+pub(crate) struct BlockRows {
+    blocks: Vec<Row>,
+    transactions: Vec<Row>,
+    receipts: Vec<Row>,
+    logs: Vec<Row>,
+}
+
+impl BlockRows {
+    pub(crate) fn take(&mut self, table: Table) -> Vec<Row> {
+        std::mem::take(match table {
+            Table::Blocks => &mut self.blocks,
+            Table::Transactions => &mut self.transactions,
+            Table::Receipts => &mut self.receipts,
+            Table::Logs => &mut self.logs,
+        })
+    }
+}
+
+pub(crate) fn block_rows(
+    header: &BlockHeader,
+    hash: H256,
+    transactions: &[MessageWithSignature],
+    body: &BlockBodyWithSenders,
+    receipts: Option<&[Receipt]>,
+) -> BlockRows {
+    let number = header.number.0;
+    let mut rows = BlockRows {
+        blocks: vec![vec![
+            number.into(),
+            hash.into(),
+            header.parent_hash.into(),
+            header.timestamp.into(),
+            header.beneficiary.into(),
+            header.state_root.into(),
+            header.gas_limit.into(),
+            header.gas_used.into(),
+            header.base_fee_per_gas.into(),
+            header.difficulty.into(),
+            transactions.len().into(),
+            header.extra_data[..].into(),
+        ]],
+        ..Default::default()
+    };
+
+    let hashes = transactions
+        .iter()
+        .map(|msg| msg.hash())
+        .collect::<Vec<_>>();
+    for (index, (txn, &tx_hash)) in body.transactions.iter().zip(&hashes).enumerate() {
+        rows.transactions.push(vec![
+            number.into(),
+            index.into(),
+            tx_hash.into(),
+            (txn.tx_type() as u64).into(),
+            txn.sender.into(),
+            match txn.action() {
+                TransactionAction::Call(to) => Some(to),
+                TransactionAction::Create => None,
+            }
+            .into(),
+            txn.nonce().into(),
+            txn.value().into(),
+            txn.gas_limit().into(),
+            txn.max_fee_per_gas().into(),
+            txn.max_priority_fee_per_gas().into(),
+            txn.input()[..].into(),
+        ]);
+    }
+
+    if let Some(receipts) = receipts {
+        let base_fee_per_gas = header.base_fee_per_gas.unwrap_or(U256::ZERO);
+        let mut prev_cumulative_gas_used = 0;
+        let mut log_index = 0_u64;
+        for (index, ((txn, &tx_hash), receipt)) in body
+            .transactions
+            .iter()
+            .zip(&hashes)
+            .zip(receipts)
+            .enumerate()
+        {
+            rows.receipts.push(vec![
+                number.into(),
+                index.into(),
+                tx_hash.into(),
+                receipt.success.into(),
+                receipt.cumulative_gas_used.into(),
+                (receipt.cumulative_gas_used - prev_cumulative_gas_used).into(),
+                txn.effective_gas_price(base_fee_per_gas).into(),
+                match txn.action() {
+                    TransactionAction::Call(_) => None,
+                    TransactionAction::Create => Some(create_address(
+                        txn.sender,
+                        receipt.deposit_nonce.unwrap_or_else(|| txn.nonce()),
+                    )),
+                }
+                .into(),
+                receipt.logs.len().into(),
+            ]);
+            prev_cumulative_gas_used = receipt.cumulative_gas_used;
+
+            for log in &receipt.logs {
+                let topic = |i: usize| Value::from(log.topics.get(i).copied());
+                rows.logs.push(vec![
+                    number.into(),
+                    index.into(),
+                    log_index.into(),
+                    tx_hash.into(),
+                    log.address.into(),
+                    topic(0),
+                    topic(1),
+                    topic(2),
+                    topic(3),
+                    log.data[..].into(),
+                ]);
+                log_index += 1;
+            }
+        }
+    }
+
+    rows
+}
+
+/// Export canonical blocks `from..=to` into `output_dir`, one file per table of `schema`.
+/// Returns the paths of the files written.
+pub fn export<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    from: BlockNumber,
+    to: BlockNumber,
+    schema: &ExportSchema,
+    format: ExportFormat,
+    output_dir: &Path,
+) -> anyhow::Result<Vec<PathBuf>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    ensure!(from <= to, "empty block range {}..={}", from, to);
+
+    let with_receipts = schema.tables().any(Table::needs_receipts);
+    if with_receipts {
+        let executed = EXECUTION.get_progress(tx)?.unwrap_or(BlockNumber(0));
+        ensure!(
+            to <= executed,
+            "receipts are replayed up to block {}, but execution has only reached {}",
+            to,
+            executed
+        );
+    }
+
+    let mut replayer = chain::receipts::Replayer::new(tx)?;
+
+    std::fs::create_dir_all(output_dir)?;
+    let mut paths = Vec::new();
+    let mut writers = Vec::<(Table, Box<dyn TableWriter>)>::new();
+    for table in schema.tables() {
+        let path = output_dir.join(format!(
+            "{}_{}_{}.{}",
+            table.name(),
+            from,
+            to,
+            format.extension()
+        ));
+        let columns = schema.columns(table);
+        let writer: Box<dyn TableWriter> = match format {
+            ExportFormat::Csv => Box::new(CsvWriter::new(&path, &columns)?),
+            ExportFormat::Parquet => Box::new(ParquetWriter::new(&path, table, columns)?),
+        };
+        writers.push((table, writer));
+        paths.push(path);
+    }
+
+    for number in from.0..=to.0 {
+        let number = BlockNumber(number);
+        let hash = chain::canonical_hash::read(tx, number)?
+            .ok_or_else(|| format_err!("no canonical hash for block {}", number))?;
+        let key = BlockKey::new(number, hash);
+        let header = chain::header::read(tx, key)?
+            .ok_or_else(|| format_err!("no header for block {}", key))?;
+        let body = chain::block_body::read_with_senders(tx, key)?
+            .ok_or_else(|| format_err!("no body for block {}", key))?;
+        let transactions = chain::block_body::read_without_senders(tx, key)?
+            .ok_or_else(|| format_err!("no body for block {}", key))?
+            .transactions;
+
+        let receipts = if with_receipts {
+            Some(
+                replayer
+                    .read(tx, key)?
+                    .ok_or_else(|| format_err!("no receipts for block {}", key))?,
+            )
+        } else {
+            None
+        };
+
+        let mut rows = block_rows(&header, hash, &transactions, &body, receipts.as_deref());
+        for (table, writer) in &mut writers {
+            writer.write(
+                rows.take(*table)
+                    .into_iter()
+                    .map(|row| schema.project(*table, &row))
+                    .collect(),
+            )?;
+        }
+
+        if number.0 % 10_000 == 0 {
+            info!("Exported block {}", number);
+        }
+    }
+
+    for (_, writer) in writers {
+        writer.finish()?;
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn schema() {
+        let schema = ExportSchema::new(&[], &[]).unwrap();
+        assert_eq!(schema.tables().collect::<Vec<_>>(), Table::ALL);
+        assert_eq!(schema.columns(Table::Logs), LOG_COLUMNS);
+
+        let schema = ExportSchema::new(
+            &[Table::Transactions, Table::Blocks],
+            &["transactions.value".into(), "transactions.hash".into()],
+        )
+        .unwrap();
+        assert_eq!(
+            schema.tables().collect::<Vec<_>>(),
+            [Table::Blocks, Table::Transactions]
+        );
+        assert_eq!(schema.columns(Table::Blocks), BLOCK_COLUMNS);
+        assert_eq!(
+            schema
+                .columns(Table::Transactions)
+                .iter()
+                .map(|c| c.name)
+                .collect::<Vec<_>>(),
+            ["value", "hash"]
+        );
+        assert!(schema.columns(Table::Receipts).is_empty());
+
+        ExportSchema::new(&[], &["transactions.gas_price".into()]).unwrap_err();
+        ExportSchema::new(&[Table::Blocks], &["logs.data".into()]).unwrap_err();
+        ExportSchema::new(&[], &["hash".into()]).unwrap_err();
+    }
+
+    #[test]
+    fn rows() {
+        let sender = hex!("004512399a230565b99be5c3b0030a56f3ace68c").into();
+        let header = BlockHeader {
+            number: BlockNumber(5),
+            base_fee_per_gas: Some(7.as_u256()),
+            ..BlockHeader::empty()
+        };
+        let message = Message::Legacy {
+            chain_id: None,
+            nonce: 3,
+            gas_price: 10.as_u256(),
+            gas_limit: 60_000,
+            action: TransactionAction::Create,
+            value: 1.as_u256(),
+            input: vec![0x60].into(),
+        };
+        let transactions = vec![MessageWithSignature {
+            message: message.clone(),
+            signature: MessageSignature::new(false, H256::repeat_byte(1), H256::repeat_byte(2))
+                .unwrap(),
+        }];
+        let body = BlockBodyWithSenders {
+            transactions: vec![MessageWithSender { message, sender }],
+            ommers: vec![],
+            rollup_cost_data: vec![],
+        };
+        let receipts = vec![Receipt {
+            tx_type: TxType::Legacy,
+            success: true,
+            cumulative_gas_used: 50_000,
+            bloom: Bloom::zero(),
+            logs: vec![Log {
+                address: sender,
+                topics: vec![H256::repeat_byte(3)],
+                data: vec![0xff].into(),
+            }],
+            deposit_nonce: None,
+        }];
+
+        let rows = block_rows(
+            &header,
+            header.hash(),
+            &transactions,
+            &body,
+            Some(&receipts),
+        );
+        assert_eq!(rows.blocks[0][10], Value::U64(1));
+        assert_eq!(rows.transactions[0][5], Value::Null);
+        assert_eq!(rows.receipts[0][7], Value::from(create_address(sender, 3)));
+        assert_eq!(rows.receipts[0][6], Value::U256(10.as_u256()));
+        assert_eq!(rows.logs[0][5], Value::from(H256::repeat_byte(3)));
+        assert_eq!(rows.logs[0][6], Value::Null);
+
+        let schema = ExportSchema::new(
+            &[Table::Logs],
+            &[
+                "logs.log_index".into(),
+                "logs.topic1".into(),
+                "logs.data".into(),
+            ],
+        )
+        .unwrap();
+        let row = schema.project(Table::Logs, &rows.logs[0]);
+        assert_eq!(
+            row.iter().map(text_field).collect::<Vec<_>>(),
+            ["0", "", "0xff"]
+        );
+    }
+
+    #[test]
+    fn parquet_message_type() {
+        let columns = [
+            column("number", ColumnType::U64),
+            nullable("to", ColumnType::Bytes),
+            column("value", ColumnType::U256),
+        ];
+        let schema = parquet_schema(Table::Transactions, &columns);
+        assert_eq!(
+            schema,
+            "message transactions {\n  REQUIRED INT64 number (UINT_64);\n  OPTIONAL BINARY to;\n  REQUIRED BINARY value (UTF8);\n}"
+        );
+    }
+}
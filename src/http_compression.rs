@@ -0,0 +1,173 @@
+//! HTTP front end for the JSON-RPC server: HTTP/2 and gzip response compression, which the
+//! JSON-RPC server does not offer by itself.
+use flate2::{write::GzEncoder, Compression};
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY},
+    http::HeaderMap,
+    service::{make_service_fn, service_fn},
+    Body, Client, Request, Response, Server, StatusCode, Version,
+};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+use std::{convert::Infallible, future::Future, io::Write, net::SocketAddr};
+
+/// Responses smaller than this are sent as is, compressing them saves next to nothing.
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+static RAW_BYTES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "rpc_response_raw_bytes_total",
+        "Size of RPC responses before compression"
+    )
+    .unwrap()
+});
+
+static SENT_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "rpc_response_bytes_total",
+        "Size of RPC responses as sent, by content encoding",
+        &["encoding"]
+    )
+    .unwrap()
+});
+
+/// Whether an `Accept-Encoding` header allows gzip.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            (name.eq_ignore_ascii_case("gzip") || name == "*")
+                && params.all(|param| match param.strip_prefix("q=") {
+                    Some(q) => q.parse::<f32>().map(|q| q > 0.0).unwrap_or(false),
+                    None => true,
+                })
+        })
+}
+
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 4), Compression::fast());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+async fn forward(
+    client: Client<HttpConnector>,
+    backend: SocketAddr,
+    req: Request<Body>,
+) -> anyhow::Result<Response<Body>> {
+    let compress = accepts_gzip(req.headers());
+
+    let (mut parts, body) = req.into_parts();
+    parts.uri = format!(
+        "http://{}{}",
+        backend,
+        parts
+            .uri
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/")
+    )
+    .parse()?;
+    // The backend only speaks HTTP/1.1, and compression is done here.
+    parts.version = Version::HTTP_11;
+    parts.headers.remove(ACCEPT_ENCODING);
+
+    let (mut parts, body) = client
+        .request(Request::from_parts(parts, body))
+        .await?
+        .into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    RAW_BYTES.inc_by(body.len() as u64);
+
+    parts
+        .headers
+        .insert(VARY, HeaderValue::from_static("accept-encoding"));
+    let body = if compress && body.len() >= MIN_COMPRESSED_SIZE {
+        let body = tokio::task::spawn_blocking(move || gzip(&body)).await??;
+        SENT_BYTES
+            .with_label_values(&["gzip"])
+            .inc_by(body.len() as u64);
+        parts
+            .headers
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        parts.headers.insert(CONTENT_LENGTH, body.len().into());
+        body.into()
+    } else {
+        SENT_BYTES
+            .with_label_values(&["identity"])
+            .inc_by(body.len() as u64);
+        body
+    };
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Bind `listen_address` and return the future serving it, which forwards every request to
+/// the JSON-RPC server on `backend`.
+pub fn serve(
+    listen_address: SocketAddr,
+    backend: SocketAddr,
+) -> anyhow::Result<impl Future<Output = hyper::Result<()>>> {
+    let client = Client::new();
+    let make_service = make_service_fn(move |_| {
+        let client = client.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let client = client.clone();
+                async move {
+                    Ok::<_, Infallible>(forward(client, backend, req).await.unwrap_or_else(|e| {
+                        Response::builder()
+                            .status(StatusCode::BAD_GATEWAY)
+                            .body(Body::from(e.to_string()))
+                            .unwrap()
+                    }))
+                }
+            }))
+        }
+    });
+
+    Ok(Server::try_bind(&listen_address)?.serve(make_service))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn accept_encoding() {
+        let accepts = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(value).unwrap());
+            accepts_gzip(&headers)
+        };
+
+        assert!(!accepts_gzip(&HeaderMap::new()));
+        assert!(accepts("gzip"));
+        assert!(accepts("deflate, GZIP;q=0.5"));
+        assert!(accepts("br;q=1.0, *"));
+        assert!(!accepts("identity"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts("gzip; q=0.000, deflate"));
+    }
+
+    #[test]
+    fn gzip_roundtrip() {
+        let data = br#"{"jsonrpc":"2.0","id":1,"result":[]}"#.repeat(100);
+        let compressed = gzip(&data).unwrap();
+        assert!(compressed.len() < data.len());
+
+        let mut decompressed = vec![];
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
@@ -0,0 +1,141 @@
+//! IPC transport for the JSON-RPC server, which does not offer one by itself: a Unix socket over
+//! which clients exchange a stream of JSON values with the server, requests one way and responses
+//! and subscription notifications the other, as with geth's IPC endpoint.
+use futures_util::{stream::select_all, StreamExt};
+use jsonrpsee::core::server::rpc_module::Methods;
+use serde_json::{json, Deserializer, Value};
+use std::{future::Future, io::ErrorKind, os::unix::fs::FileTypeExt, path::Path};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::mpsc,
+};
+use tracing::*;
+
+/// JSON-RPC error codes of requests that could not be read.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+
+fn error_response(code: i64, message: &str) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": { "code": code, "message": message },
+    })
+    .to_string()
+}
+
+/// Bind the socket at `path` and return the future serving `methods` to its clients. A socket
+/// left at `path` by a previous run is replaced, any other file is an error.
+pub fn serve(path: &Path, methods: Methods) -> anyhow::Result<impl Future<Output = ()>> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Ok(_) => anyhow::bail!("{} exists and is not a socket", path.display()),
+        Err(e) => return Err(e.into()),
+    }
+    let listener = UnixListener::bind(path)?;
+
+    Ok(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_connection(stream, methods.clone()));
+                }
+                Err(e) => warn!("Failed to accept IPC connection: {}", e),
+            }
+        }
+    })
+}
+
+async fn serve_connection(stream: UnixStream, methods: Methods) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (out, mut outgoing) = mpsc::unbounded_channel::<String>();
+
+    // Outlives reading for the responses still being made. Stops at the first write after the
+    // client is gone, which in turn ends the subscriptions forwarding to it.
+    tokio::spawn(async move {
+        while let Some(mut message) = outgoing.recv().await {
+            message.push('\n');
+            if writer.write_all(message.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut buf = Vec::new();
+    'read: loop {
+        match reader.read_buf(&mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                debug!("IPC connection failed: {}", e);
+                break;
+            }
+        }
+
+        // Requests are not delimited, only complete values are taken out of the buffer.
+        let mut values = Deserializer::from_slice(&buf).into_iter::<Value>();
+        let mut read = 0;
+        loop {
+            match values.next() {
+                Some(Ok(request)) => {
+                    read = values.byte_offset();
+                    tokio::spawn(handle(methods.clone(), request, out.clone()));
+                }
+                Some(Err(e)) if e.is_eof() => break,
+                Some(Err(_)) => {
+                    // The rest of the stream cannot be told apart from the bad value.
+                    let _ = out.send(error_response(PARSE_ERROR, "Parse error"));
+                    break 'read;
+                }
+                None => break,
+            }
+        }
+        buf.drain(..read);
+    }
+}
+
+/// Answer a request or a batch of them, then forward the notifications of the subscriptions they
+/// made until the connection goes away.
+async fn handle(methods: Methods, request: Value, out: mpsc::UnboundedSender<String>) {
+    let mut subscriptions = Vec::new();
+    let call = |request: Value| {
+        let methods = &methods;
+        async move {
+            match methods.raw_json_request(&request.to_string()).await {
+                Ok((response, notifications)) => (response, Some(notifications)),
+                Err(_) => (error_response(INVALID_REQUEST, "Invalid request"), None),
+            }
+        }
+    };
+
+    let response = match request {
+        Value::Array(batch) if !batch.is_empty() => {
+            let mut responses = Vec::with_capacity(batch.len());
+            for request in batch {
+                let (response, notifications) = call(request).await;
+                responses.push(response);
+                subscriptions.extend(notifications);
+            }
+            format!("[{}]", responses.join(","))
+        }
+        Value::Array(_) => error_response(INVALID_REQUEST, "Invalid request"),
+        request => {
+            let (response, notifications) = call(request).await;
+            subscriptions.extend(notifications);
+            response
+        }
+    };
+    if out.send(response).is_err() {
+        return;
+    }
+
+    // Ends at once for requests that made no subscription.
+    let mut notifications = select_all(subscriptions);
+    while let Some(notification) = notifications.next().await {
+        if out.send(notification).is_err() {
+            break;
+        }
+    }
+}
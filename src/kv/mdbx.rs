@@ -1,12 +1,25 @@
-use crate::kv::{traits::*, *};
+use crate::kv::{
+    stats::{Op, OpTimer},
+    traits::*,
+    *,
+};
 use ::mdbx::{DatabaseFlags, WriteFlags};
 pub use ::mdbx::{EnvironmentKind, TransactionKind, RO, RW};
-use anyhow::Context;
-use std::{collections::HashMap, marker::PhantomData, ops::Deref, path::Path};
+use anyhow::{bail, Context};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 use tables::*;
 
+/// [`DbInfo`] key under which the main environment records where the [`COLD_TABLES`] are.
+const COLD_PATH_KEY: &[u8] = b"ColdTablesPath";
+
+/// Decoded object along with its encoded size.
 #[derive(Clone, Debug)]
-struct TableObjectWrapper<T>(T);
+struct TableObjectWrapper<T>(T, usize);
 
 impl<'tx, T> ::mdbx::TableObject<'tx> for TableObjectWrapper<T>
 where
@@ -18,13 +31,15 @@ where
     {
         T::decode(data_val)
             .map_err(|e| ::mdbx::Error::DecodeError(e.into()))
-            .map(Self)
+            .map(|v| Self(v, data_val.len()))
     }
 }
 
 #[derive(Debug)]
 pub struct MdbxEnvironment<E: EnvironmentKind> {
     inner: ::mdbx::Environment<E>,
+    /// Environment of the [`COLD_TABLES`], if they are not kept in `inner`.
+    cold: Option<::mdbx::Environment<E>>,
 }
 
 impl<E: EnvironmentKind> MdbxEnvironment<E> {
@@ -53,15 +68,26 @@ impl<E: EnvironmentKind> MdbxEnvironment<E> {
             inner: b
                 .open(path)
                 .with_context(|| format!("failed to open database at {}", path.display()))?,
+            cold: None,
         })
     }
 
+    /// Opens the database read-only, along with the environment of its [`COLD_TABLES`] if they
+    /// are kept separately.
     pub fn open_ro(
         b: ::mdbx::EnvironmentBuilder<E>,
         path: &Path,
         chart: DatabaseChart,
     ) -> anyhow::Result<Self> {
-        Self::open(b, path, chart, true)
+        let mut s = Self::open(b, path, chart.clone(), true)?;
+
+        if let Some(cold_path) = s.cold_path()? {
+            let (_, cold_chart) = split_chart(&chart);
+            s.cold =
+                Some(Self::open(::mdbx::Environment::new(), &cold_path, cold_chart, true)?.inner);
+        }
+
+        Ok(s)
     }
 
     pub fn open_rw(
@@ -71,21 +97,117 @@ impl<E: EnvironmentKind> MdbxEnvironment<E> {
     ) -> anyhow::Result<Self> {
         let s = Self::open(b, path, chart.clone(), false)?;
 
-        let tx = s.inner.begin_rw_txn()?;
-        for (table, info) in &*chart {
-            tx.create_db(
-                Some(table),
-                if info.dup_sort {
-                    DatabaseFlags::DUP_SORT
-                } else {
-                    DatabaseFlags::default()
-                },
-            )?;
+        if let Some(cold_path) = s.cold_path()? {
+            bail!(
+                "history tables of {} are kept at {}, open it along with them",
+                path.display(),
+                cold_path.display()
+            );
+        }
+
+        create_tables(&s.inner, &chart)?;
+
+        Ok(s)
+    }
+
+    /// Like [`Self::open_rw`], but keeps the [`COLD_TABLES`] in a separate environment at
+    /// `cold_path`, opened with `cold_b`, e.g. on a slower disk.
+    ///
+    /// Existing history is not moved: a database that already has history in the main
+    /// environment cannot be split.
+    pub fn open_rw_with_cold(
+        b: ::mdbx::EnvironmentBuilder<E>,
+        path: &Path,
+        cold_b: ::mdbx::EnvironmentBuilder<E>,
+        cold_path: &Path,
+        chart: DatabaseChart,
+    ) -> anyhow::Result<Self> {
+        let (hot_chart, cold_chart) = split_chart(&chart);
+
+        let mut s = Self::open(b, path, chart, false)?;
+        create_tables(&s.inner, &hot_chart)?;
+
+        match s.cold_path()? {
+            Some(recorded) if recorded != cold_path => bail!(
+                "history tables of {} are kept at {}, not {}",
+                path.display(),
+                recorded.display(),
+                cold_path.display()
+            ),
+            Some(_) => {}
+            None => {
+                let tx = s.inner.begin_rw_txn()?;
+                for &table in COLD_TABLES {
+                    if let Ok(db) = tx.open_db(Some(table)) {
+                        if tx.db_stat(&db)?.entries() > 0 {
+                            bail!(
+                                "{} already has history in table {}, it cannot be moved to {}",
+                                path.display(),
+                                table,
+                                cold_path.display()
+                            );
+                        }
+                    }
+                }
+                tx.put(
+                    &tx.open_db(Some(DbInfo::const_db_name()))?,
+                    COLD_PATH_KEY,
+                    cold_path
+                        .to_str()
+                        .with_context(|| format!("non-UTF-8 path {}", cold_path.display()))?,
+                    WriteFlags::UPSERT,
+                )?;
+                tx.commit()?;
+            }
         }
-        tx.commit()?;
+
+        let cold = Self::open(cold_b, cold_path, cold_chart.clone(), false)?;
+        create_tables(&cold.inner, &cold_chart)?;
+        s.cold = Some(cold.inner);
 
         Ok(s)
     }
+
+    /// Environment of the [`COLD_TABLES`], if they are kept separately.
+    pub fn cold(&self) -> Option<&::mdbx::Environment<E>> {
+        self.cold.as_ref()
+    }
+
+    /// Where the [`COLD_TABLES`] are kept, if not in this environment.
+    pub fn cold_path(&self) -> anyhow::Result<Option<PathBuf>> {
+        let tx = self.inner.begin_ro_txn()?;
+        // Absent from databases that were never opened read-write.
+        let db = match tx.open_db(Some(DbInfo::const_db_name())) {
+            Ok(db) => db,
+            Err(_) => return Ok(None),
+        };
+
+        Ok(tx
+            .get::<Vec<u8>>(&db, COLD_PATH_KEY)?
+            .map(String::from_utf8)
+            .transpose()?
+            .map(PathBuf::from))
+    }
+}
+
+fn create_tables<E: EnvironmentKind>(
+    env: &::mdbx::Environment<E>,
+    chart: &DatabaseChart,
+) -> anyhow::Result<()> {
+    let tx = env.begin_rw_txn()?;
+    for (table, info) in &**chart {
+        tx.create_db(
+            Some(table),
+            if info.dup_sort {
+                DatabaseFlags::DUP_SORT
+            } else {
+                DatabaseFlags::default()
+            },
+        )?;
+    }
+    tx.commit()?;
+
+    Ok(())
 }
 
 impl<E: EnvironmentKind> Deref for MdbxEnvironment<E> {
@@ -100,12 +222,22 @@ impl<E: EnvironmentKind> MdbxEnvironment<E> {
     pub fn begin(&self) -> anyhow::Result<MdbxTransaction<'_, RO, E>> {
         Ok(MdbxTransaction {
             inner: self.inner.begin_ro_txn()?,
+            cold: self
+                .cold
+                .as_ref()
+                .map(|cold| cold.begin_ro_txn())
+                .transpose()?,
         })
     }
 
     pub fn begin_mutable(&self) -> anyhow::Result<MdbxTransaction<'_, RW, E>> {
         Ok(MdbxTransaction {
             inner: self.inner.begin_rw_txn()?,
+            cold: self
+                .cold
+                .as_ref()
+                .map(|cold| cold.begin_rw_txn())
+                .transpose()?,
         })
     }
 }
@@ -117,6 +249,7 @@ where
     E: EnvironmentKind,
 {
     inner: ::mdbx::Transaction<'env, K, E>,
+    cold: Option<::mdbx::Transaction<'env, K, E>>,
 }
 
 impl<'env, E> MdbxTransaction<'env, RO, E>
@@ -125,27 +258,25 @@ where
 {
     pub fn table_sizes(&self) -> anyhow::Result<HashMap<String, u64>> {
         let mut out = HashMap::new();
-        let main_db = self.inner.open_db(None)?;
-        let mut cursor = self.inner.cursor(&main_db)?;
-        while let Some((table, _)) = cursor.next_nodup::<Vec<u8>, ()>()? {
-            let table = String::from_utf8(table)?;
-            let db = self
-                .inner
-                .open_db(Some(&table))
-                .with_context(|| format!("failed to open table: {}", table))?;
-            let st = self
-                .inner
-                .db_stat(&db)
-                .with_context(|| format!("failed to get stats for table: {}", table))?;
-
-            out.insert(
-                table,
-                ((st.leaf_pages() + st.branch_pages() + st.overflow_pages())
-                    * st.page_size() as usize) as u64,
-            );
-
-            unsafe {
-                self.inner.close_db(db)?;
+        for txn in std::iter::once(&self.inner).chain(&self.cold) {
+            let main_db = txn.open_db(None)?;
+            let mut cursor = txn.cursor(&main_db)?;
+            while let Some((table, _)) = cursor.next_nodup::<Vec<u8>, ()>()? {
+                let table = String::from_utf8(table)?;
+                let db = txn
+                    .open_db(Some(&table))
+                    .with_context(|| format!("failed to open table: {}", table))?;
+                let st = txn
+                    .db_stat(&db)
+                    .with_context(|| format!("failed to get stats for table: {}", table))?;
+
+                *out.entry(table).or_default() +=
+                    ((st.leaf_pages() + st.branch_pages() + st.overflow_pages())
+                        * st.page_size() as usize) as u64;
+
+                unsafe {
+                    txn.close_db(db)?;
+                }
             }
         }
 
@@ -162,29 +293,40 @@ where
         self.inner.id()
     }
 
+    /// Transaction of the environment `table` is kept in.
+    fn txn_for(&self, table: &str) -> &::mdbx::Transaction<'env, K, E> {
+        match &self.cold {
+            Some(cold) if COLD_TABLES.contains(&table) => cold,
+            _ => &self.inner,
+        }
+    }
+
     pub fn cursor<'tx, T>(&'tx self, table: T) -> anyhow::Result<MdbxCursor<'tx, K, T>>
     where
         'env: 'tx,
         T: Table,
     {
         let table_name = table.db_name();
+        let txn = self.txn_for(table_name.as_ref());
         Ok(MdbxCursor {
-            inner: self
-                .inner
-                .cursor(&self.inner.open_db(Some(table_name.as_ref()))?)?,
+            inner: txn.cursor(&txn.open_db(Some(table_name.as_ref()))?)?,
             t: table.db_name(),
             _marker: PhantomData,
         })
     }
 
     pub fn get<T: Table>(&self, table: T, key: T::Key) -> anyhow::Result<Option<T::Value>> {
-        Ok(self
-            .inner
-            .get::<TableObjectWrapper<_>>(
-                &self.inner.open_db(Some(table.db_name().as_ref()))?,
-                key.encode().as_ref(),
-            )?
-            .map(|v| v.0))
+        let table_name = table.db_name();
+        let txn = self.txn_for(table_name.as_ref());
+        let db = txn.open_db(Some(table_name.as_ref()))?;
+        let timer = OpTimer::start();
+        let v = txn.get::<TableObjectWrapper<_>>(&db, key.encode().as_ref())?;
+        timer.finish(
+            table_name.as_ref(),
+            Op::Get,
+            v.as_ref().map(|v| v.1).unwrap_or(0),
+        );
+        Ok(v.map(|v| v.0))
     }
 }
 
@@ -193,12 +335,18 @@ impl<'env, E: EnvironmentKind> MdbxTransaction<'env, RW, E> {
     where
         T: Table,
     {
-        Ok(self.inner.put(
-            &self.inner.open_db(Some(table.db_name().as_ref()))?,
-            &k.encode(),
-            &v.encode(),
-            WriteFlags::UPSERT,
-        )?)
+        let table_name = table.db_name();
+        let txn = self.txn_for(table_name.as_ref());
+        let db = txn.open_db(Some(table_name.as_ref()))?;
+        let (k, v) = (k.encode(), v.encode());
+        let timer = OpTimer::start();
+        txn.put(&db, &k, &v, WriteFlags::UPSERT)?;
+        timer.finish(
+            table_name.as_ref(),
+            Op::Put,
+            k.as_ref().len() + v.as_ref().len(),
+        );
+        Ok(())
     }
 
     pub fn del<T>(&self, table: T, key: T::Key, value: Option<T::Value>) -> anyhow::Result<bool>
@@ -211,24 +359,34 @@ impl<'env, E: EnvironmentKind> MdbxTransaction<'env, RW, E> {
         if let Some(v) = &value {
             vref = Some(v.as_ref());
         };
-        Ok(self.inner.del(
-            &self.inner.open_db(Some(table.db_name().as_ref()))?,
-            key.encode(),
-            vref,
-        )?)
+        let table_name = table.db_name();
+        let txn = self.txn_for(table_name.as_ref());
+        let db = txn.open_db(Some(table_name.as_ref()))?;
+        let timer = OpTimer::start();
+        let deleted = txn.del(&db, key.encode(), vref)?;
+        timer.finish(table_name.as_ref(), Op::Delete, 0);
+        Ok(deleted)
     }
 
     pub fn clear_table<T>(&self, table: T) -> anyhow::Result<()>
     where
         T: Table,
     {
-        self.inner
-            .clear_db(&self.inner.open_db(Some(table.db_name().as_ref()))?)?;
+        let table_name = table.db_name();
+        let txn = self.txn_for(table_name.as_ref());
+        txn.clear_db(&txn.open_db(Some(table_name.as_ref()))?)?;
 
         Ok(())
     }
 
+    /// The two environments are not committed atomically. History goes first, so that dying in
+    /// between leaves it ahead of stage progress, as
+    /// [`crate::stages::recover_interrupted_execution`] already expects after a crash, rather
+    /// than missing.
     pub fn commit(self) -> anyhow::Result<()> {
+        if let Some(cold) = self.cold {
+            cold.commit()?;
+        }
         self.inner.commit()?;
 
         Ok(())
@@ -246,45 +404,55 @@ where
     _marker: PhantomData<T>,
 }
 
-fn map_res_inner<T, E>(
-    v: Result<Option<(TableObjectWrapper<T::Key>, TableObjectWrapper<T::Value>)>, E>,
-) -> anyhow::Result<Option<(T::Key, T::Value)>>
-where
-    T: Table,
-    <T as Table>::Key: TableDecode,
-    E: std::error::Error + Send + Sync + 'static,
-{
-    if let Some((k, v)) = v? {
-        return Ok(Some((k.0, v.0)));
-    }
-
-    Ok(None)
-}
+type RawEntry<T> = (
+    TableObjectWrapper<<T as Table>::Key>,
+    TableObjectWrapper<<T as Table>::Value>,
+);
 
 impl<'txn, K, T> MdbxCursor<'txn, K, T>
 where
     K: TransactionKind,
     T: Table,
 {
+    /// Runs a cursor read, recording it as `op` on the table.
+    fn read(
+        &mut self,
+        op: Op,
+        f: impl FnOnce(&mut ::mdbx::Cursor<'txn, K>) -> Result<Option<RawEntry<T>>, ::mdbx::Error>,
+    ) -> anyhow::Result<Option<(T::Key, T::Value)>>
+    where
+        T::Key: TableDecode,
+    {
+        let timer = OpTimer::start();
+        let v = (f)(&mut self.inner)?;
+        timer.finish(
+            self.t.as_ref(),
+            op,
+            v.as_ref().map(|(k, v)| k.1 + v.1).unwrap_or(0),
+        );
+
+        Ok(v.map(|(k, v)| (k.0, v.0)))
+    }
+
     pub fn first(&mut self) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.first())
+        self.read(Op::Seek, |c| c.first())
     }
 
     pub fn seek(&mut self, key: T::SeekKey) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.set_range(key.encode().as_ref()))
+        self.read(Op::Seek, |c| c.set_range(key.encode().as_ref()))
     }
 
     pub fn seek_exact(&mut self, key: T::Key) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.set_key(key.encode().as_ref()))
+        self.read(Op::Seek, |c| c.set_key(key.encode().as_ref()))
     }
 
     #[allow(clippy::should_implement_trait)]
@@ -292,28 +460,28 @@ where
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.next())
+        self.read(Op::Next, |c| c.next())
     }
 
     pub fn prev(&mut self) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.prev())
+        self.read(Op::Next, |c| c.prev())
     }
 
     pub fn last(&mut self) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.last())
+        self.read(Op::Seek, |c| c.last())
     }
 
     pub fn current(&mut self) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.get_current())
+        self.read(Op::Seek, |c| c.get_current())
     }
 
     pub fn walk(
@@ -392,47 +560,54 @@ where
     where
         T::Key: Clone,
     {
+        let timer = OpTimer::start();
         let res = self.inner.get_both_range::<TableObjectWrapper<T::Value>>(
             key.encode().as_ref(),
             value.encode().as_ref(),
         )?;
+        timer.finish(
+            self.t.as_ref(),
+            Op::Seek,
+            res.as_ref().map(|v| v.1).unwrap_or(0),
+        );
 
-        if let Some(v) = res {
-            return Ok(Some(v.0));
-        }
-
-        Ok(None)
+        Ok(res.map(|v| v.0))
     }
 
     pub fn last_dup(&mut self) -> anyhow::Result<Option<T::Value>>
     where
         T::Key: TableDecode,
     {
-        Ok(self
-            .inner
-            .last_dup::<TableObjectWrapper<T::Value>>()?
-            .map(|v| v.0))
+        let timer = OpTimer::start();
+        let res = self.inner.last_dup::<TableObjectWrapper<T::Value>>()?;
+        timer.finish(
+            self.t.as_ref(),
+            Op::Seek,
+            res.as_ref().map(|v| v.1).unwrap_or(0),
+        );
+
+        Ok(res.map(|v| v.0))
     }
 
     pub fn next_dup(&mut self) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.next_dup())
+        self.read(Op::Next, |c| c.next_dup())
     }
 
     pub fn next_no_dup(&mut self) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.next_nodup())
+        self.read(Op::Next, |c| c.next_nodup())
     }
 
     pub fn prev_dup(&mut self) -> anyhow::Result<Option<(T::Key, T::Value)>>
     where
         T::Key: TableDecode,
     {
-        map_res_inner::<T, _>(self.inner.prev_dup())
+        self.read(Op::Next, |c| c.prev_dup())
     }
 
     /// Walk over duplicates for some specific key.
@@ -492,34 +667,41 @@ impl<'txn, T> MdbxCursor<'txn, RW, T>
 where
     T: Table,
 {
+    fn write(&mut self, key: T::Key, value: T::Value, flags: WriteFlags) -> anyhow::Result<()> {
+        let (key, value) = (key.encode(), value.encode());
+        let timer = OpTimer::start();
+        self.inner.put(key.as_ref(), value.as_ref(), flags)?;
+        timer.finish(
+            self.t.as_ref(),
+            Op::Put,
+            key.as_ref().len() + value.as_ref().len(),
+        );
+
+        Ok(())
+    }
+
+    fn delete(&mut self, flags: WriteFlags) -> anyhow::Result<()> {
+        let timer = OpTimer::start();
+        self.inner.del(flags)?;
+        timer.finish(self.t.as_ref(), Op::Delete, 0);
+
+        Ok(())
+    }
+
     pub fn put(&mut self, key: T::Key, value: T::Value) -> anyhow::Result<()> {
-        Ok(self.inner.put(
-            key.encode().as_ref(),
-            value.encode().as_ref(),
-            WriteFlags::default(),
-        )?)
+        self.write(key, value, WriteFlags::default())
     }
 
     pub fn upsert(&mut self, key: T::Key, value: T::Value) -> anyhow::Result<()> {
-        Ok(self.inner.put(
-            key.encode().as_ref(),
-            value.encode().as_ref(),
-            WriteFlags::UPSERT,
-        )?)
+        self.write(key, value, WriteFlags::UPSERT)
     }
 
     pub fn append(&mut self, key: T::Key, value: T::Value) -> anyhow::Result<()> {
-        Ok(self.inner.put(
-            key.encode().as_ref(),
-            value.encode().as_ref(),
-            WriteFlags::APPEND,
-        )?)
+        self.write(key, value, WriteFlags::APPEND)
     }
 
     pub fn delete_current(&mut self) -> anyhow::Result<()> {
-        self.inner.del(WriteFlags::CURRENT)?;
-
-        Ok(())
+        self.delete(WriteFlags::CURRENT)
     }
 }
 
@@ -528,13 +710,9 @@ where
     T: DupSort,
 {
     pub fn delete_current_duplicates(&mut self) -> anyhow::Result<()> {
-        Ok(self.inner.del(WriteFlags::NO_DUP_DATA)?)
+        self.delete(WriteFlags::NO_DUP_DATA)
     }
     pub fn append_dup(&mut self, key: T::Key, value: T::Value) -> anyhow::Result<()> {
-        Ok(self.inner.put(
-            key.encode().as_ref(),
-            value.encode().as_ref(),
-            WriteFlags::APPEND_DUP,
-        )?)
+        self.write(key, value, WriteFlags::APPEND_DUP)
     }
 }
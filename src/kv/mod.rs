@@ -1,6 +1,10 @@
 pub mod mdbx;
+pub mod object;
+pub mod replica;
+pub mod stats;
 pub mod tables;
 pub mod traits;
+pub mod write_buffer;
 
 use self::traits::*;
 use crate::kv::tables::CHAINDATA_TABLES;
@@ -43,31 +47,132 @@ pub struct MdbxWithDirHandle {
 pub fn new_mem_database() -> anyhow::Result<MdbxWithDirHandle> {
     let tmpdir = tempfile::tempdir()?;
     Ok(MdbxWithDirHandle {
-        inner: new_environment(tmpdir.path(), n_mib_bytes!(64), None)?,
+        inner: new_environment(tmpdir.path(), None, n_mib_bytes!(64), None)?,
         _tmpdir: Some(tmpdir),
     })
 }
 
 pub fn new_database(path: &std::path::Path) -> anyhow::Result<MdbxWithDirHandle> {
+    new_database_with_cold(path, None)
+}
+
+/// Like [`new_database`], with the [`tables::COLD_TABLES`] kept at `cold_path` if it is set.
+pub fn new_database_with_cold(
+    path: &std::path::Path,
+    cold_path: Option<&std::path::Path>,
+) -> anyhow::Result<MdbxWithDirHandle> {
     Ok(MdbxWithDirHandle {
-        inner: new_environment(path, n_tib_bytes!(4), Some(n_gib_bytes!(4) as usize))?,
+        inner: new_environment(
+            path,
+            cold_path,
+            n_tib_bytes!(4),
+            Some(n_gib_bytes!(4) as usize),
+        )?,
         _tmpdir: None,
     })
 }
 
 fn new_environment(
     path: &std::path::Path,
+    cold_path: Option<&std::path::Path>,
     size_upper_limit: u128,
     growth_step: Option<usize>,
 ) -> anyhow::Result<mdbx::MdbxEnvironment<WriteMap>> {
-    let mut builder = ::mdbx::Environment::<WriteMap>::new();
-    builder.set_max_dbs(CHAINDATA_TABLES.len());
-    builder.set_geometry(Geometry {
-        size: Some(0..size_upper_limit.try_into().unwrap_or(usize::MAX)),
-        growth_step: growth_step.map(|s| s.try_into().unwrap_or(isize::MAX)),
-        shrink_threshold: None,
-        page_size: None,
-    });
-    builder.set_rp_augment_limit(16 * 256 * 1024);
-    mdbx::MdbxEnvironment::open_rw(builder, path, CHAINDATA_TABLES.deref().clone())
+    let builder = || {
+        let mut builder = ::mdbx::Environment::<WriteMap>::new();
+        builder.set_max_dbs(CHAINDATA_TABLES.len());
+        builder.set_geometry(Geometry {
+            size: Some(0..size_upper_limit.try_into().unwrap_or(usize::MAX)),
+            growth_step: growth_step.map(|s| s.try_into().unwrap_or(isize::MAX)),
+            shrink_threshold: None,
+            page_size: None,
+        });
+        builder.set_rp_augment_limit(16 * 256 * 1024);
+        builder
+    };
+    match cold_path {
+        Some(cold_path) => mdbx::MdbxEnvironment::open_rw_with_cold(
+            builder(),
+            path,
+            builder(),
+            cold_path,
+            CHAINDATA_TABLES.deref().clone(),
+        ),
+        None => mdbx::MdbxEnvironment::open_rw(builder(), path, CHAINDATA_TABLES.deref().clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kv::tables, models::*};
+
+    #[test]
+    fn cold_tables() {
+        let hot_dir = tempfile::tempdir().unwrap();
+        let cold_dir = tempfile::tempdir().unwrap();
+
+        let change = tables::AccountChange {
+            address: Address::repeat_byte(1),
+            account: None,
+        };
+        {
+            let db = new_environment(
+                hot_dir.path(),
+                Some(cold_dir.path()),
+                n_mib_bytes!(64),
+                None,
+            )
+            .unwrap();
+            let tx = db.begin_mutable().unwrap();
+            tx.set(tables::AccountChangeSet, BlockNumber(1), change.clone())
+                .unwrap();
+            tx.set(
+                tables::CanonicalHeader,
+                BlockNumber(1),
+                H256::repeat_byte(1),
+            )
+            .unwrap();
+            tx.commit().unwrap();
+        }
+
+        // Each table only went into its own environment.
+        for (path, account_changes, canonical_headers) in
+            [(hot_dir.path(), 0, 1), (cold_dir.path(), 1, 0)]
+        {
+            let env = ::mdbx::Environment::<WriteMap>::new()
+                .set_max_dbs(CHAINDATA_TABLES.len())
+                .open(path)
+                .unwrap();
+            let tx = env.begin_ro_txn().unwrap();
+            for (table, entries) in [
+                (tables::AccountChangeSet::const_db_name(), account_changes),
+                (tables::CanonicalHeader::const_db_name(), canonical_headers),
+            ] {
+                assert_eq!(
+                    tx.open_db(Some(table))
+                        .map(|db| tx.db_stat(&db).unwrap().entries())
+                        .unwrap_or(0),
+                    entries
+                );
+            }
+        }
+
+        // Readers find the history by themselves, writers have to name it.
+        let db = mdbx::MdbxEnvironment::<::mdbx::NoWriteMap>::open_ro(
+            ::mdbx::Environment::new(),
+            hot_dir.path(),
+            CHAINDATA_TABLES.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            db.begin()
+                .unwrap()
+                .get(tables::AccountChangeSet, BlockNumber(1))
+                .unwrap(),
+            Some(change)
+        );
+        drop(db);
+        new_environment(hot_dir.path(), None, n_mib_bytes!(64), None).unwrap_err();
+    }
 }
@@ -0,0 +1,256 @@
+//! Object-safe facade over the database, for code that wants to hold a
+//! `dyn` handle instead of being generic over the environment kind.
+//!
+//! It covers point reads and writes and plain cursor walks. Cursors cannot
+//! move within the duplicates of a key, so readers of dup-sorted tables,
+//! such as plain state and change sets, still go through `MdbxTransaction`.
+//! Only the RPC head resolution is written against it so far.
+
+use super::{
+    mdbx::{MdbxCursor, MdbxEnvironment, MdbxTransaction},
+    traits::*,
+    CustomTable, MdbxWithDirHandle,
+};
+use ::mdbx::{EnvironmentKind, TransactionKind, RW};
+use std::fmt::Debug;
+
+pub type RawEntry = (Vec<u8>, Vec<u8>);
+
+pub trait DynDatabase: Debug + Send + Sync {
+    fn begin_dyn(&self) -> anyhow::Result<Box<dyn DynTransaction + '_>>;
+    fn begin_mutable_dyn(&self) -> anyhow::Result<Box<dyn DynMutableTransaction + '_>>;
+}
+
+pub trait DynTransaction {
+    fn get_raw(&self, table: &str, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>>;
+    fn cursor_dyn<'tx>(&'tx self, table: &str) -> anyhow::Result<Box<dyn DynCursor + 'tx>>;
+}
+
+pub trait DynMutableTransaction: DynTransaction {
+    fn put_raw(&self, table: &str, key: &[u8], value: &[u8]) -> anyhow::Result<()>;
+    /// Delete `key`, or only its `value` in a dup-sorted table. Returns whether anything was
+    /// deleted.
+    fn delete_raw(&self, table: &str, key: &[u8], value: Option<&[u8]>) -> anyhow::Result<bool>;
+    fn clear_table_raw(&self, table: &str) -> anyhow::Result<()>;
+    fn commit_dyn(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+pub trait DynCursor {
+    fn first(&mut self) -> anyhow::Result<Option<RawEntry>>;
+    fn last(&mut self) -> anyhow::Result<Option<RawEntry>>;
+    fn seek(&mut self, key: &[u8]) -> anyhow::Result<Option<RawEntry>>;
+    fn seek_exact(&mut self, key: &[u8]) -> anyhow::Result<Option<RawEntry>>;
+    fn next(&mut self) -> anyhow::Result<Option<RawEntry>>;
+    fn prev(&mut self) -> anyhow::Result<Option<RawEntry>>;
+    fn current(&mut self) -> anyhow::Result<Option<RawEntry>>;
+}
+
+fn decode_entry<T: Table>((k, v): RawEntry) -> anyhow::Result<(T::Key, T::Value)>
+where
+    T::Key: TableDecode,
+{
+    Ok((T::Key::decode(&k)?, T::Value::decode(&v)?))
+}
+
+/// Typed access to tables through a [`DynTransaction`].
+pub trait DynTransactionExt: DynTransaction {
+    fn get<T: Table>(&self, table: T, key: T::Key) -> anyhow::Result<Option<T::Value>> {
+        self.get_raw(table.db_name().as_ref(), key.encode().as_ref())?
+            .map(|v| T::Value::decode(&v))
+            .transpose()
+    }
+
+    fn last<T: Table>(&self, table: T) -> anyhow::Result<Option<(T::Key, T::Value)>>
+    where
+        T::Key: TableDecode,
+    {
+        self.cursor_dyn(table.db_name().as_ref())?
+            .last()?
+            .map(decode_entry::<T>)
+            .transpose()
+    }
+}
+
+impl<Tx: DynTransaction + ?Sized> DynTransactionExt for Tx {}
+
+/// Typed writes to tables through a [`DynMutableTransaction`].
+pub trait DynMutableTransactionExt: DynMutableTransaction {
+    fn set<T: Table>(&self, table: T, key: T::Key, value: T::Value) -> anyhow::Result<()> {
+        self.put_raw(
+            table.db_name().as_ref(),
+            key.encode().as_ref(),
+            value.encode().as_ref(),
+        )
+    }
+
+    fn del<T: Table>(
+        &self,
+        table: T,
+        key: T::Key,
+        value: Option<T::Value>,
+    ) -> anyhow::Result<bool> {
+        let value = value.map(TableEncode::encode);
+        self.delete_raw(
+            table.db_name().as_ref(),
+            key.encode().as_ref(),
+            value.as_ref().map(AsRef::as_ref),
+        )
+    }
+}
+
+impl<Tx: DynMutableTransaction + ?Sized> DynMutableTransactionExt for Tx {}
+
+impl<E: EnvironmentKind> DynDatabase for MdbxEnvironment<E> {
+    fn begin_dyn(&self) -> anyhow::Result<Box<dyn DynTransaction + '_>> {
+        Ok(Box::new(self.begin()?))
+    }
+
+    fn begin_mutable_dyn(&self) -> anyhow::Result<Box<dyn DynMutableTransaction + '_>> {
+        Ok(Box::new(self.begin_mutable()?))
+    }
+}
+
+impl DynDatabase for MdbxWithDirHandle {
+    fn begin_dyn(&self) -> anyhow::Result<Box<dyn DynTransaction + '_>> {
+        Ok(Box::new(self.begin()?))
+    }
+
+    fn begin_mutable_dyn(&self) -> anyhow::Result<Box<dyn DynMutableTransaction + '_>> {
+        Ok(Box::new(self.begin_mutable()?))
+    }
+}
+
+impl<'env, K, E> DynTransaction for MdbxTransaction<'env, K, E>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    fn get_raw(&self, table: &str, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        self.get(CustomTable::from(table.to_string()), key.to_vec())
+    }
+
+    fn cursor_dyn<'tx>(&'tx self, table: &str) -> anyhow::Result<Box<dyn DynCursor + 'tx>> {
+        Ok(Box::new(self.cursor(CustomTable::from(table.to_string()))?))
+    }
+}
+
+impl<'env, E> DynMutableTransaction for MdbxTransaction<'env, RW, E>
+where
+    E: EnvironmentKind,
+{
+    fn put_raw(&self, table: &str, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        self.set(
+            CustomTable::from(table.to_string()),
+            key.to_vec(),
+            value.to_vec(),
+        )
+    }
+
+    fn delete_raw(&self, table: &str, key: &[u8], value: Option<&[u8]>) -> anyhow::Result<bool> {
+        self.del(
+            CustomTable::from(table.to_string()),
+            key.to_vec(),
+            value.map(<[u8]>::to_vec),
+        )
+    }
+
+    fn clear_table_raw(&self, table: &str) -> anyhow::Result<()> {
+        self.clear_table(CustomTable::from(table.to_string()))
+    }
+
+    fn commit_dyn(self: Box<Self>) -> anyhow::Result<()> {
+        (*self).commit()
+    }
+}
+
+impl<'txn, K> DynCursor for MdbxCursor<'txn, K, CustomTable>
+where
+    K: TransactionKind,
+{
+    fn first(&mut self) -> anyhow::Result<Option<RawEntry>> {
+        MdbxCursor::first(self)
+    }
+
+    fn last(&mut self) -> anyhow::Result<Option<RawEntry>> {
+        MdbxCursor::last(self)
+    }
+
+    fn seek(&mut self, key: &[u8]) -> anyhow::Result<Option<RawEntry>> {
+        MdbxCursor::seek(self, key.to_vec())
+    }
+
+    fn seek_exact(&mut self, key: &[u8]) -> anyhow::Result<Option<RawEntry>> {
+        MdbxCursor::seek_exact(self, key.to_vec())
+    }
+
+    fn next(&mut self) -> anyhow::Result<Option<RawEntry>> {
+        MdbxCursor::next(self)
+    }
+
+    fn prev(&mut self) -> anyhow::Result<Option<RawEntry>> {
+        MdbxCursor::prev(self)
+    }
+
+    fn current(&mut self) -> anyhow::Result<Option<RawEntry>> {
+        MdbxCursor::current(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kv::{new_mem_database, tables},
+        models::*,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn dyn_database() {
+        let db: Arc<dyn DynDatabase> = Arc::new(new_mem_database().unwrap());
+        {
+            let tx = db.begin_mutable_dyn().unwrap();
+            for n in 1..=3 {
+                tx.set(
+                    tables::CanonicalHeader,
+                    BlockNumber(n),
+                    H256::repeat_byte(n as u8),
+                )
+                .unwrap();
+            }
+            assert!(tx
+                .del(tables::CanonicalHeader, BlockNumber(3), None)
+                .unwrap());
+            tx.commit_dyn().unwrap();
+        }
+
+        let tx = db.begin_dyn().unwrap();
+
+        assert_eq!(
+            tx.get(tables::CanonicalHeader, BlockNumber(2)).unwrap(),
+            Some(H256::repeat_byte(2))
+        );
+        assert_eq!(
+            tx.get(tables::CanonicalHeader, BlockNumber(3)).unwrap(),
+            None
+        );
+
+        let mut cursor = tx.cursor_dyn("CanonicalHeader").unwrap();
+        assert_eq!(
+            cursor.first().unwrap(),
+            Some((
+                BlockNumber(1).encode().to_vec(),
+                H256::repeat_byte(1).as_bytes().to_vec()
+            ))
+        );
+        assert_eq!(
+            cursor.next().unwrap().map(|(k, _)| k),
+            Some(BlockNumber(2).encode().to_vec())
+        );
+        assert_eq!(cursor.next().unwrap(), None);
+        assert_eq!(
+            tx.last(tables::CanonicalHeader).unwrap(),
+            Some((BlockNumber(2), H256::repeat_byte(2)))
+        );
+    }
+}
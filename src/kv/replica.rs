@@ -0,0 +1,218 @@
+//! Read-only access to a database that a node in another process keeps writing to.
+//!
+//! Every read transaction sees the latest commit, and MDBX follows the writer as it grows the
+//! map within the bounds the database was opened with. Past them, read transactions may fail
+//! until the environment is reopened, which [`ReadReplica::refresh`] does once no reads are in
+//! flight.
+
+use super::{mdbx::MdbxEnvironment, tables::DatabaseChart};
+use ::mdbx::EnvironmentKind;
+use anyhow::format_err;
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::sync::{RwLock, RwLockReadGuard};
+use tracing::*;
+
+fn data_file_size(path: &Path) -> anyhow::Result<u64> {
+    Ok(std::fs::metadata(path.join("mdbx.dat"))?.len())
+}
+
+#[derive(Debug)]
+pub struct ReadReplica<E: EnvironmentKind> {
+    path: PathBuf,
+    chart: DatabaseChart,
+    // Empty only while reopening, or after reopening failed.
+    env: RwLock<Option<MdbxEnvironment<E>>>,
+    file_size: AtomicU64,
+    // Size of the map of the environment when it was opened.
+    map_size: AtomicU64,
+}
+
+impl<E: EnvironmentKind> ReadReplica<E> {
+    pub fn open(path: &Path, chart: DatabaseChart) -> anyhow::Result<Self> {
+        let env = Self::open_env(path, &chart)?;
+        let map_size = env.info()?.map_size() as u64;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            chart,
+            env: RwLock::new(Some(env)),
+            file_size: AtomicU64::new(data_file_size(path)?),
+            map_size: AtomicU64::new(map_size),
+        })
+    }
+
+    fn open_env(path: &Path, chart: &DatabaseChart) -> anyhow::Result<MdbxEnvironment<E>> {
+        MdbxEnvironment::open_ro(::mdbx::Environment::new(), path, chart.clone())
+    }
+
+    /// Environment to begin read transactions on. It is not reopened until the guard is dropped.
+    pub async fn get(&self) -> anyhow::Result<RwLockReadGuard<'_, MdbxEnvironment<E>>> {
+        RwLockReadGuard::try_map(self.env.read().await, Option::as_ref)
+            .map_err(|_| format_err!("database at {} is not open", self.path.display()))
+    }
+
+    /// Check whether the writer grew the map since the last call, and reopen the environment if
+    /// the data file outgrew the map it was opened with, or read transactions can no longer begin
+    /// on it. Returns whether it was reopened.
+    pub async fn refresh(&self) -> anyhow::Result<bool> {
+        let size = data_file_size(&self.path)?;
+        let previous_size = self.file_size.swap(size, Ordering::Relaxed);
+        if size > previous_size {
+            debug!(
+                "Database at {} grew from {} to {} bytes",
+                self.path.display(),
+                previous_size,
+                size
+            );
+        }
+
+        let map_size = self.map_size.load(Ordering::Relaxed);
+        if size > map_size {
+            // MDBX may manage to extend the mapping in place, or may not; not left to chance.
+            info!(
+                "Database at {} outgrew the map of {} bytes it was opened with, reopening it",
+                self.path.display(),
+                map_size
+            );
+        } else if let Some(env) = &*self.env.read().await {
+            match env.begin() {
+                Ok(_) => return Ok(false),
+                Err(e) => warn!(
+                    "Failed to read database at {}, reopening it: {}",
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+
+        let mut env = self.env.write().await;
+        // Closed first, as MDBX does not open a database twice in one process.
+        *env = None;
+        let reopened = Self::open_env(&self.path, &self.chart)?;
+        self.map_size
+            .store(reopened.info()?.map_size() as u64, Ordering::Relaxed);
+        *env = Some(reopened);
+        info!("Reopened database at {}", self.path.display());
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::new_environment, *};
+    use crate::kv::tables::{self, CHAINDATA_TABLES};
+    use byte_unit::*;
+    use std::process::Command;
+
+    const WRITER_DIR: &str = "MARTINEZ_TEST_REPLICA_WRITER_DIR";
+    const VALUE_SIZE: usize = 4096;
+    const MAP_SIZE: u128 = n_mib_bytes!(64);
+
+    // Run by the tests below in a child process, as MDBX does not open a database twice in one.
+    fn grow_map(path: &Path, map_size: u128, writes: u64) {
+        let db = new_environment(path, None, map_size, Some(n_mib_bytes!(1) as usize)).unwrap();
+        let tx = db.begin_mutable().unwrap();
+        for i in 0..writes {
+            tx.set(
+                tables::DbInfo,
+                i.to_be_bytes().to_vec(),
+                vec![0xab; VALUE_SIZE],
+            )
+            .unwrap();
+        }
+        tx.commit().unwrap();
+    }
+
+    fn run_writer(test: &str, path: &Path) {
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", test, "--nocapture"])
+            .env(WRITER_DIR, path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    fn open_replica(path: &Path) -> ReadReplica<::mdbx::NoWriteMap> {
+        drop(new_environment(path, None, MAP_SIZE, Some(n_mib_bytes!(1) as usize)).unwrap());
+
+        let replica = ReadReplica::open(path, CHAINDATA_TABLES.clone()).unwrap();
+        assert_eq!(replica.map_size.load(Ordering::Relaxed), MAP_SIZE as u64);
+        replica
+    }
+
+    async fn assert_written(replica: &ReadReplica<::mdbx::NoWriteMap>, writes: u64) {
+        let db = replica.get().await.unwrap();
+        let tx = db.begin().unwrap();
+        for i in [0, writes - 1] {
+            assert_eq!(
+                tx.get(tables::DbInfo, i.to_be_bytes().to_vec()).unwrap(),
+                Some(vec![0xab; VALUE_SIZE])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_after_writer_grows_map() {
+        const WRITES: u64 = 4096;
+
+        if let Some(path) = std::env::var_os(WRITER_DIR) {
+            grow_map(Path::new(&path), MAP_SIZE, WRITES);
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let replica = open_replica(dir.path());
+        let size_before = data_file_size(dir.path()).unwrap();
+        assert_eq!(
+            replica
+                .get()
+                .await
+                .unwrap()
+                .begin()
+                .unwrap()
+                .get(tables::DbInfo, 0_u64.to_be_bytes().to_vec())
+                .unwrap(),
+            None
+        );
+
+        run_writer(
+            "kv::replica::tests::reads_after_writer_grows_map",
+            dir.path(),
+        );
+        assert!(data_file_size(dir.path()).unwrap() > size_before);
+
+        assert!(!replica.refresh().await.unwrap());
+        assert_written(&replica, WRITES).await;
+    }
+
+    #[tokio::test]
+    async fn reopens_after_writer_grows_map_past_mapping() {
+        // Past the map of the replica, in values that take two pages each.
+        const WRITES: u64 = 12288;
+
+        if let Some(path) = std::env::var_os(WRITER_DIR) {
+            grow_map(Path::new(&path), MAP_SIZE * 4, WRITES);
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let replica = open_replica(dir.path());
+
+        run_writer(
+            "kv::replica::tests::reopens_after_writer_grows_map_past_mapping",
+            dir.path(),
+        );
+        assert!(data_file_size(dir.path()).unwrap() > MAP_SIZE as u64);
+
+        assert!(replica.refresh().await.unwrap());
+        assert!(replica.map_size.load(Ordering::Relaxed) > MAP_SIZE as u64);
+        assert_written(&replica, WRITES).await;
+
+        // Nothing left to follow.
+        assert!(!replica.refresh().await.unwrap());
+    }
+}
@@ -0,0 +1,155 @@
+//! Per-table statistics of database operations, off unless [`enable`]d since they cost a
+//! clock read and a labelled metric update on every call.
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter_vec, HistogramVec,
+    IntCounterVec,
+};
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Point lookup through the transaction.
+    Get,
+    /// Cursor positioning: seeks, first, last and current.
+    Seek,
+    /// Cursor step to a neighbouring entry or duplicate.
+    Next,
+    Put,
+    Delete,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Get => "get",
+            Self::Seek => "seek",
+            Self::Next => "next",
+            Self::Put => "put",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Slow operation threshold in microseconds, `u64::MAX` for none.
+static SLOW_THRESHOLD: AtomicU64 = AtomicU64::new(u64::MAX);
+
+static OPERATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "kv_operations_total",
+        "Database operations, by table and operation",
+        &["table", "op"]
+    )
+    .unwrap()
+});
+
+static BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "kv_bytes_total",
+        "Encoded bytes read or written, by table and operation",
+        &["table", "op"]
+    )
+    .unwrap()
+});
+
+static DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "kv_operation_duration_seconds",
+        "Latency of database operations, by table and operation",
+        &["table", "op"],
+        exponential_buckets(1e-6, 4.0, 10).unwrap()
+    )
+    .unwrap()
+});
+
+static SLOW_OPERATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "kv_slow_operations_total",
+        "Database operations over the slow operation threshold, by table and operation",
+        &["table", "op"]
+    )
+    .unwrap()
+});
+
+/// Starts collecting statistics, logging a warning for every operation that takes at least
+/// `slow_threshold` if it is set.
+pub fn enable(slow_threshold: Option<Duration>) {
+    SLOW_THRESHOLD.store(
+        slow_threshold
+            .map(|threshold| threshold.as_micros().try_into().unwrap_or(u64::MAX))
+            .unwrap_or(u64::MAX),
+        Ordering::Relaxed,
+    );
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Times one operation, if statistics are enabled.
+#[must_use]
+pub(crate) struct OpTimer(Option<Instant>);
+
+impl OpTimer {
+    pub fn start() -> Self {
+        Self(ENABLED.load(Ordering::Relaxed).then(Instant::now))
+    }
+
+    /// Records the operation against `table`, `bytes` being the encoded size of what was read or
+    /// written.
+    pub fn finish(self, table: &str, op: Op, bytes: usize) {
+        if let Some(started) = self.0 {
+            record(table, op, bytes, started.elapsed());
+        }
+    }
+}
+
+fn record(table: &str, op: Op, bytes: usize, elapsed: Duration) {
+    let labels = [table, op.as_str()];
+    OPERATIONS.with_label_values(&labels).inc();
+    BYTES.with_label_values(&labels).inc_by(bytes as u64);
+    DURATION
+        .with_label_values(&labels)
+        .observe(elapsed.as_secs_f64());
+
+    if elapsed.as_micros() >= u128::from(SLOW_THRESHOLD.load(Ordering::Relaxed)) {
+        SLOW_OPERATIONS.with_label_values(&labels).inc();
+        warn!(
+            table,
+            op = op.as_str(),
+            bytes,
+            elapsed = ?elapsed,
+            "Slow database operation"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_operations() {
+        let table = "StatsTestTable";
+        record(table, Op::Get, 10, Duration::from_micros(5));
+        record(table, Op::Get, 20, Duration::from_millis(5));
+
+        let labels = [table, "get"];
+        assert_eq!(OPERATIONS.with_label_values(&labels).get(), 2);
+        assert_eq!(BYTES.with_label_values(&labels).get(), 30);
+        assert_eq!(DURATION.with_label_values(&labels).get_sample_count(), 2);
+        assert_eq!(SLOW_OPERATIONS.with_label_values(&labels).get(), 0);
+
+        enable(Some(Duration::from_millis(1)));
+        record(table, Op::Get, 10, Duration::from_micros(5));
+        record(table, Op::Get, 20, Duration::from_millis(5));
+        disable();
+        assert_eq!(SLOW_OPERATIONS.with_label_values(&labels).get(), 1);
+    }
+}
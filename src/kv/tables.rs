@@ -308,6 +308,10 @@ scale_table_object!(BodyForStorage);
 scale_table_object!(BlockHeader);
 scale_table_object!(MessageWithSignature);
 scale_table_object!(Vec<crate::models::Log>);
+scale_table_object!(Vec<crate::models::InternalTransfer>);
+scale_table_object!(Vec<crate::models::ContractCreation>);
+scale_table_object!(crate::models::BorSpan);
+scale_table_object!(Vec<crate::models::StateSyncEvent>);
 
 macro_rules! ron_table_object {
     ($ty:ident) => {
@@ -753,6 +757,7 @@ decl_table!(HashedStorage => H256 => (H256, U256));
 decl_table!(AccountHistory => BitmapKey<Address> => RoaringTreemap);
 decl_table!(StorageHistory => BitmapKey<(Address, H256)> => RoaringTreemap);
 decl_table!(Code => H256 => Bytes);
+decl_table!(CodeAnalysis => H256 => Bytes);
 decl_table!(TrieAccount => Vec<u8> => Vec<u8>);
 decl_table!(TrieStorage => Vec<u8> => Vec<u8>);
 decl_table!(DbInfo => Vec<u8> => Vec<u8>);
@@ -772,6 +777,10 @@ decl_table!(LogAddressIndex => Vec<u8> => RoaringTreemap);
 decl_table!(CallTraceSet => BlockNumber => CallTraceSetEntry);
 decl_table!(CallFromIndex => BitmapKey<Address> => RoaringTreemap);
 decl_table!(CallToIndex => BitmapKey<Address> => RoaringTreemap);
+decl_table!(AddressAppearanceIndex => BitmapKey<Address> => RoaringTreemap);
+decl_table!(InternalTransfer => (BlockNumber, TxIndex) => Vec<crate::models::InternalTransfer>);
+decl_table!(ContractCreation => (BlockNumber, TxIndex) => Vec<crate::models::ContractCreation>);
+decl_table!(ContractCreator => Address => (BlockNumber, TxIndex));
 decl_table!(BlockTransactionLookup => H256 => TruncateStart<BlockNumber>);
 decl_table!(Config => H256 => ChainSpec);
 decl_table!(SyncStage => StageId => BlockNumber);
@@ -780,10 +789,41 @@ decl_table!(LastBlock => Vec<u8> => Vec<u8>);
 decl_table!(Migration => Vec<u8> => Vec<u8>);
 decl_table!(Sequence => Vec<u8> => Vec<u8>);
 decl_table!(LastHeader => VariableVec<0> => H256);
+decl_table!(LastForkchoice => Vec<u8> => H256);
 decl_table!(Issuance => Vec<u8> => Vec<u8>);
+decl_table!(BorSpan => BlockNumber => crate::models::BorSpan);
+decl_table!(BorStateSyncEvents => BlockNumber => Vec<crate::models::StateSyncEvent>);
 
 pub type DatabaseChart = Arc<HashMap<&'static str, TableInfo>>;
 
+/// Block history tables, which may be kept in a separate environment on cheaper storage, see
+/// [`crate::kv::mdbx::MdbxEnvironment::open_rw_with_cold`].
+pub const COLD_TABLES: &[&str] = &[
+    AccountChangeSet::const_db_name(),
+    StorageChangeSet::const_db_name(),
+    AccountHistory::const_db_name(),
+    StorageHistory::const_db_name(),
+    Log::const_db_name(),
+    LogTopicIndex::const_db_name(),
+    LogAddressIndex::const_db_name(),
+    CallTraceSet::const_db_name(),
+    CallFromIndex::const_db_name(),
+    CallToIndex::const_db_name(),
+    AddressAppearanceIndex::const_db_name(),
+    InternalTransfer::const_db_name(),
+    ContractCreation::const_db_name(),
+];
+
+/// Split `chart` into the tables kept in the main environment and the [`COLD_TABLES`].
+pub fn split_chart(chart: &DatabaseChart) -> (DatabaseChart, DatabaseChart) {
+    let (cold, hot) = chart
+        .iter()
+        .map(|(&name, info)| (name, info.clone()))
+        .partition::<HashMap<_, _>, _>(|(name, _)| COLD_TABLES.contains(name));
+
+    (Arc::new(hot), Arc::new(cold))
+}
+
 pub static CHAINDATA_TABLES: Lazy<Arc<HashMap<&'static str, TableInfo>>> = Lazy::new(|| {
     Arc::new(hashmap! {
         Account::const_db_name() => TableInfo::default(),
@@ -803,6 +843,7 @@ pub static CHAINDATA_TABLES: Lazy<Arc<HashMap<&'static str, TableInfo>>> = Lazy:
         AccountHistory::const_db_name() => TableInfo::default(),
         StorageHistory::const_db_name() => TableInfo::default(),
         Code::const_db_name() => TableInfo::default(),
+        CodeAnalysis::const_db_name() => TableInfo::default(),
         TrieAccount::const_db_name() => TableInfo::default(),
         TrieStorage::const_db_name() => TableInfo::default(),
         DbInfo::const_db_name() => TableInfo::default(),
@@ -824,6 +865,10 @@ pub static CHAINDATA_TABLES: Lazy<Arc<HashMap<&'static str, TableInfo>>> = Lazy:
         },
         CallFromIndex::const_db_name() => TableInfo::default(),
         CallToIndex::const_db_name() => TableInfo::default(),
+        AddressAppearanceIndex::const_db_name() => TableInfo::default(),
+        InternalTransfer::const_db_name() => TableInfo::default(),
+        ContractCreation::const_db_name() => TableInfo::default(),
+        ContractCreator::const_db_name() => TableInfo::default(),
         BlockTransactionLookup::const_db_name() => TableInfo::default(),
         Config::const_db_name() => TableInfo::default(),
         SyncStage::const_db_name() => TableInfo::default(),
@@ -832,7 +877,10 @@ pub static CHAINDATA_TABLES: Lazy<Arc<HashMap<&'static str, TableInfo>>> = Lazy:
         Migration::const_db_name() => TableInfo::default(),
         Sequence::const_db_name() => TableInfo::default(),
         LastHeader::const_db_name() => TableInfo::default(),
+        LastForkchoice::const_db_name() => TableInfo::default(),
         Issuance::const_db_name() => TableInfo::default(),
+        BorSpan::const_db_name() => TableInfo::default(),
+        BorStateSyncEvents::const_db_name() => TableInfo::default(),
     })
 });
 
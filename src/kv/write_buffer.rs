@@ -0,0 +1,155 @@
+use crate::kv::{mdbx::MdbxCursor, tables::ErasedTable, traits::*};
+use mdbx::RW;
+use std::collections::BTreeMap;
+
+/// Size of the buffered writes past which they are flushed.
+pub const DEFAULT_WRITE_BUFFER_CAPACITY: usize = 256 * 1024 * 1024;
+
+/// Write-back buffer in front of a table, for stages that write the same keys repeatedly or in
+/// random order.
+///
+/// Writes are kept in memory, a later one to a key replacing the earlier, and applied in key
+/// order once they outgrow the capacity, so that MDBX sees each key once per flush and touches
+/// its pages in order. Only for tables without duplicates.
+///
+/// Nothing reaches the transaction until a flush: stages must [`Self::flush`] before returning
+/// their progress, so that it is committed along with everything it covers, and a crash loses
+/// both.
+pub struct WriteBuffer<'tx, T>
+where
+    T: Table,
+{
+    cursor: MdbxCursor<'tx, RW, ErasedTable<T>>,
+    /// Encoded keys to their encoded values, `None` for deletions.
+    entries: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    size: usize,
+    capacity: usize,
+}
+
+impl<'tx, T> WriteBuffer<'tx, T>
+where
+    T: Table,
+{
+    pub fn new(cursor: MdbxCursor<'tx, RW, ErasedTable<T>>, capacity: usize) -> Self {
+        Self {
+            cursor,
+            entries: BTreeMap::new(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    fn insert(&mut self, key: T::Key, value: Option<Vec<u8>>) -> anyhow::Result<()> {
+        let key = key.encode().as_ref().to_vec();
+        let key_len = key.len();
+        self.size += key_len + value.as_ref().map(Vec::len).unwrap_or(0);
+        if let Some(replaced) = self.entries.insert(key, value) {
+            self.size -= key_len + replaced.map(|v| v.len()).unwrap_or(0);
+        }
+
+        if self.size > self.capacity {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn upsert(&mut self, key: T::Key, value: T::Value) -> anyhow::Result<()> {
+        self.insert(key, Some(value.encode().as_ref().to_vec()))
+    }
+
+    pub fn delete(&mut self, key: T::Key) -> anyhow::Result<()> {
+        self.insert(key, None)
+    }
+
+    /// Value of `key`, taking buffered writes into account.
+    pub fn get(&mut self, key: T::Key) -> anyhow::Result<Option<T::Value>> {
+        let key = key.encode();
+        match self.entries.get(key.as_ref()) {
+            Some(value) => value.as_deref().map(T::Value::decode).transpose(),
+            None => self
+                .cursor
+                .seek_exact(key.as_ref().to_vec())?
+                .map(|(_, v)| T::Value::decode(&v))
+                .transpose(),
+        }
+    }
+
+    /// Number of buffered writes.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Applies the buffered writes to the transaction, in key order.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        for (key, value) in std::mem::take(&mut self.entries) {
+            match value {
+                Some(value) => self.cursor.upsert(key, value)?,
+                None => {
+                    if self.cursor.seek_exact(key)?.is_some() {
+                        self.cursor.delete_current()?;
+                    }
+                }
+            }
+        }
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kv::{new_mem_database, tables},
+        models::*,
+    };
+
+    #[test]
+    fn write_buffer() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let number = |n| (H256::from_low_u64_be(n), BlockNumber(n));
+        let (kept, _) = number(1);
+        tx.set(tables::HeaderNumber, kept, BlockNumber(1)).unwrap();
+        let (deleted, _) = number(2);
+        tx.set(tables::HeaderNumber, deleted, BlockNumber(2))
+            .unwrap();
+
+        // Large enough for a few entries only.
+        let mut buffer = WriteBuffer::new(tx.cursor(tables::HeaderNumber.erased()).unwrap(), 200);
+        buffer.delete(deleted).unwrap();
+        assert_eq!(buffer.get(deleted).unwrap(), None);
+        assert_eq!(buffer.get(kept).unwrap(), Some(BlockNumber(1)));
+        assert_eq!(
+            tx.get(tables::HeaderNumber, deleted).unwrap(),
+            Some(BlockNumber(2))
+        );
+
+        for n in (3..20).rev() {
+            let (hash, block_number) = number(n);
+            buffer.upsert(hash, BlockNumber(0)).unwrap();
+            buffer.upsert(hash, block_number).unwrap();
+        }
+        // Flushed on overflow.
+        assert!(buffer.len() < 17);
+        assert_eq!(tx.get(tables::HeaderNumber, deleted).unwrap(), None);
+
+        buffer.flush().unwrap();
+        assert!(buffer.is_empty());
+        for n in (1..20).filter(|&n| n != 2) {
+            let (hash, block_number) = number(n);
+            assert_eq!(
+                tx.get(tables::HeaderNumber, hash).unwrap(),
+                Some(block_number)
+            );
+        }
+        assert_eq!(tx.get(tables::HeaderNumber, deleted).unwrap(), None);
+    }
+}
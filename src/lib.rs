@@ -26,16 +26,25 @@ pub mod chain;
 pub mod consensus;
 pub mod crypto;
 pub mod downloader;
+pub mod era1;
 pub mod etl;
 pub mod execution;
+pub mod export;
+pub mod http_compression;
+pub mod ipc;
 pub mod kv;
 pub mod models;
+pub mod observability;
 pub mod res;
+pub mod rpc;
 pub mod sentry;
+pub mod sql_mirror;
 pub mod stagedsync;
 pub mod stages;
 mod state;
+pub mod task_metrics;
 pub mod trie;
+pub mod txpool;
 pub(crate) mod util;
 
 pub use stagedsync::stages::StageId;
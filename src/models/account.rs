@@ -15,7 +15,7 @@ pub struct Account {
     pub code_hash: H256, // hash of the bytecode
 }
 
-#[derive(Debug, RlpEncodable, RlpDecodable)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
 pub struct RlpAccount {
     pub nonce: u64,
     pub balance: U256,
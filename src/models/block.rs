@@ -6,6 +6,29 @@ use rlp_derive::*;
 use sha3::*;
 use std::borrow::Borrow;
 
+/// Number and hash of a block, which key its header, body, senders and total difficulty in the
+/// database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockKey {
+    pub number: BlockNumber,
+    pub hash: H256,
+}
+
+impl BlockKey {
+    pub fn new(number: impl Into<BlockNumber>, hash: H256) -> Self {
+        Self {
+            number: number.into(),
+            hash,
+        }
+    }
+}
+
+impl std::fmt::Display for BlockKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{:?}", self.number, self.hash)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
 pub struct Block {
     pub header: BlockHeader,
@@ -90,6 +113,9 @@ impl From<Block> for BlockBody {
 pub struct BlockBodyWithSenders {
     pub transactions: Vec<MessageWithSender>,
     pub ommers: Vec<BlockHeader>,
+    /// Of each signed transaction, for OP-stack chains to charge it the cost of posting it to
+    /// L1. Transactions without it are not charged.
+    pub rollup_cost_data: Vec<RollupCostData>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encode, Decode, RlpDecodable)]
@@ -109,6 +135,8 @@ pub struct WithHash<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::strategies::{block_headers, transactions};
+    use proptest::{collection::vec, prelude::*};
 
     const CHAIN_ID: ChainId = ChainId(1);
 
@@ -134,6 +162,7 @@ mod tests {
             mix_hash: hex!("b26583e11ffc5d412b46d1ddb74e78c775fb54b049dc0cf0689e8430a45d9186").into(),
             nonce: hex!("596b98b5d0f8cc56").into(),
             base_fee_per_gas: Some(0x18aac2ec3d_u64.into()),
+            aura_seal: None,
         };
 
         let ommers = vec![];
@@ -324,6 +353,7 @@ mod tests {
                     .into(),
                 nonce: hex!("68b769c5451a7aea").into(),
                 base_fee_per_gas: None,
+                aura_seal: None,
             }]
         );
 
@@ -399,6 +429,7 @@ mod tests {
                     .into(),
                 nonce: hex!("0000000000000023").into(),
                 base_fee_per_gas: None,
+                aura_seal: None,
             }],
         };
 
@@ -465,4 +496,16 @@ mod tests {
 
         assert_eq!(rlp::decode::<BlockHeader>(&rlp::encode(&h)).unwrap(), h);
     }
+
+    proptest! {
+        #[test]
+        fn block_roundtrip(
+            header in block_headers(),
+            transactions in vec(transactions(), 0..4),
+            ommers in vec(block_headers(), 0..2),
+        ) {
+            let block = Block { header, transactions, ommers };
+            prop_assert_eq!(rlp::decode::<Block>(&rlp::encode(&block)).unwrap(), block);
+        }
+    }
 }
@@ -0,0 +1,67 @@
+use super::*;
+use bytes::Bytes;
+use parity_scale_codec::*;
+use rlp::*;
+use serde::*;
+
+/// Validator of a Bor span, as elected on Heimdall.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct BorValidator {
+    pub id: u64,
+    pub address: Address,
+    pub voting_power: u64,
+}
+
+/// Encoded as the minimal validator committed to the validator contract.
+impl Encodable for BorValidator {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
+        s.append(&self.id);
+        s.append(&self.voting_power);
+        s.append(&self.address);
+    }
+}
+
+/// Range of blocks produced by the same validator set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct BorSpan {
+    pub id: u64,
+    pub start_block: BlockNumber,
+    pub end_block: BlockNumber,
+    pub validators: Vec<BorValidator>,
+    /// Validators taking turns to produce the sprints of the span.
+    pub selected_producers: Vec<BorValidator>,
+}
+
+impl BorSpan {
+    pub fn contains(&self, block_number: BlockNumber) -> bool {
+        (self.start_block..=self.end_block).contains(&block_number)
+    }
+}
+
+/// Event of the Ethereum state sender contract, relayed by Heimdall to be committed on Bor.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct StateSyncEvent {
+    pub id: u64,
+    /// Receiver of the event on Bor.
+    pub contract: Address,
+    pub data: Bytes,
+    pub tx_hash: H256,
+    pub log_index: u64,
+    pub chain_id: String,
+    /// Unix time at which Heimdall recorded the event.
+    pub time: u64,
+}
+
+/// Encoded as the record passed to `commitState`, which leaves out the time.
+impl Encodable for StateSyncEvent {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(6);
+        s.append(&self.id);
+        s.append(&self.contract);
+        s.append(&self.data.as_ref());
+        s.append(&self.tx_hash);
+        s.append(&self.log_index);
+        s.append(&self.chain_id);
+    }
+}
@@ -3,6 +3,7 @@ use bytes::Bytes;
 use serde::*;
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
     time::Duration,
 };
 
@@ -15,6 +16,7 @@ pub struct BlockExecutionSpec {
     pub params: Params,
     pub system_contract_changes: HashMap<Address, Contract>,
     pub balance_changes: HashMap<Address, U256>,
+    pub optimism: Option<OptimismSpec>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -29,6 +31,10 @@ pub struct ChainSpec {
     pub contracts: BTreeMap<BlockNumber, HashMap<Address, Contract>>,
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub balances: BTreeMap<BlockNumber, HashMap<Address, U256>>,
+    /// Set for OP-stack rollups, whose blocks start with deposits and whose transactions pay
+    /// for being posted to L1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optimism: Option<OptimismParams>,
     pub p2p: P2PParams,
 }
 
@@ -83,6 +89,9 @@ impl ChainSpec {
                 .get(&block_number)
                 .cloned()
                 .unwrap_or_default(),
+            optimism: self.optimism.as_ref().map(|optimism| OptimismSpec {
+                regolith: switch_is_active(optimism.regolith, block_number),
+            }),
         }
     }
 
@@ -111,6 +120,111 @@ impl ChainSpec {
 
         forks
     }
+
+    /// What tooling needs to check that a node runs the expected chain, leaving out genesis
+    /// allocations and other bulk.
+    pub fn summary(&self, genesis_hash: H256) -> ChainSpecSummary {
+        ChainSpecSummary {
+            name: self.name.clone(),
+            chain_id: self.params.chain_id,
+            network_id: self.params.network_id,
+            genesis_hash,
+            consensus: match self.consensus.seal_verification {
+                SealVerificationParams::Clique { .. } => "clique",
+                SealVerificationParams::Ethash { .. } => "ethash",
+                SealVerificationParams::Aura { .. } => "aura",
+                SealVerificationParams::Bor { .. } => "bor",
+            },
+            upgrades: [
+                ("homestead", self.upgrades.homestead),
+                ("tangerine", self.upgrades.tangerine),
+                ("spurious", self.upgrades.spurious),
+                ("byzantium", self.upgrades.byzantium),
+                ("constantinople", self.upgrades.constantinople),
+                ("petersburg", self.upgrades.petersburg),
+                ("istanbul", self.upgrades.istanbul),
+                ("berlin", self.upgrades.berlin),
+                ("london", self.upgrades.london),
+                ("eip1559", self.consensus.eip1559_block),
+            ]
+            .into_iter()
+            .filter_map(|(name, block)| {
+                Some(UpgradeActivation {
+                    name,
+                    block: block?,
+                })
+            })
+            .collect(),
+            fork_blocks: self.gather_forks().into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct UpgradeActivation {
+    pub name: &'static str,
+    pub block: BlockNumber,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainSpecSummary {
+    pub name: String,
+    pub chain_id: ChainId,
+    pub network_id: NetworkId,
+    pub genesis_hash: H256,
+    pub consensus: &'static str,
+    /// Scheduled upgrades, in activation order.
+    pub upgrades: Vec<UpgradeActivation>,
+    /// Every block where the rules change, upgrades as well as reward and difficulty bomb
+    /// changes and irregular state changes.
+    pub fork_blocks: Vec<BlockNumber>,
+}
+
+/// Lazily built lookup of [`BlockExecutionSpec`]s for a chain.
+///
+/// A block spec only differs from the one of its parent at fork blocks, so it is collected
+/// once for every fork block and once for every range of blocks in between.
+#[derive(Debug)]
+pub struct BlockSpecCache {
+    chain_spec: ChainSpec,
+    forks: BTreeSet<BlockNumber>,
+    specs: HashMap<BlockNumber, Arc<BlockExecutionSpec>>,
+}
+
+impl BlockSpecCache {
+    pub fn new(chain_spec: ChainSpec) -> Self {
+        let mut forks = chain_spec.gather_forks();
+        // Genesis may carry transitions, contracts and balances of its own.
+        forks.insert(BlockNumber(0));
+
+        Self {
+            chain_spec,
+            forks,
+            specs: HashMap::new(),
+        }
+    }
+
+    pub fn chain_spec(&self) -> &ChainSpec {
+        &self.chain_spec
+    }
+
+    pub fn get(&mut self, block_number: impl Into<BlockNumber>) -> Arc<BlockExecutionSpec> {
+        let block_number = block_number.into();
+        // Fork blocks are keyed by themselves, other blocks by the first block after the last fork.
+        let key = if self.forks.contains(&block_number) {
+            block_number
+        } else {
+            // Genesis is always in the set, so there is a fork below any other block.
+            *self.forks.range(..block_number).next_back().unwrap() + 1
+        };
+
+        let chain_spec = &self.chain_spec;
+        self.specs
+            .entry(key)
+            .or_insert_with(|| Arc::new(chain_spec.collect_block_spec(block_number)))
+            .clone()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -177,9 +291,52 @@ pub enum SealVerificationParams {
             with = "::serde_with::rust::unwrap_or_skip"
         )]
         difficulty_bomb: Option<DifficultyBomb>,
+        /// Length of an ECIP-1017 era, after which block rewards are reduced by 20%.
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "::serde_with::rust::unwrap_or_skip"
+        )]
+        ecip1017_era_rounds: Option<u64>,
         #[serde(default)]
         skip_pow_verification: bool,
     },
+    Aura {
+        /// Length of a step in seconds. Each step has a single proposer.
+        step_duration: u64,
+        /// Validator sets, by the block from which they are in effect.
+        validators: BTreeMap<BlockNumber, AuraValidatorSet>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        block_reward: BTreeMap<BlockNumber, U256>,
+        /// Contracts computing the block rewards, by the block from which they replace
+        /// `block_reward`.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        block_reward_contract: BTreeMap<BlockNumber, Address>,
+    },
+    /// Polygon PoS. Producers are elected for spans of blocks on Heimdall, and take turns
+    /// producing sprints of blocks within a span.
+    Bor {
+        /// Sprint lengths, by the block from which they are in effect.
+        sprint: BTreeMap<BlockNumber, u64>,
+        /// Contract committing each span to the chain, `0x…1000` on Polygon.
+        validator_contract: Address,
+        /// Contract receiving the state-sync events from Ethereum, `0x…1001` on Polygon.
+        state_receiver_contract: Address,
+        /// Age in seconds of the events committed at the start of a sprint, by the block from
+        /// which it is in effect (Indore). Before it, events up to the start of the previous
+        /// sprint are committed.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        state_sync_delay: BTreeMap<BlockNumber, u64>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum AuraValidatorSet {
+    List(Vec<Address>),
+    /// Contract returning the validators from `getValidators()`. It signals changes with an
+    /// `InitiateChange` event, which the engine applies by calling `finalizeChange()` once the
+    /// signalling block is final.
+    Contract(Address),
 }
 
 impl SealVerificationParams {
@@ -267,6 +424,24 @@ pub struct Upgrades {
     pub london: Option<BlockNumber>,
 }
 
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct OptimismParams {
+    /// Deposits use the gas they consume rather than their gas limit, and their receipts record
+    /// the nonce of their sender.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    pub regolith: Option<BlockNumber>,
+}
+
+/// OP-stack rules in effect at a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OptimismSpec {
+    pub regolith: bool,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Params {
     pub chain_id: ChainId,
@@ -294,6 +469,13 @@ pub enum Seal {
         score: BlockScore,
         signers: Vec<Address>,
     },
+    Aura {
+        #[serde(with = "hexbytes")]
+        vanity: Bytes,
+        difficulty: U256,
+        step: u64,
+        signature: H520,
+    },
 }
 
 impl Seal {
@@ -301,12 +483,13 @@ impl Seal {
         match self {
             Seal::Ethash { difficulty, .. } => *difficulty,
             Seal::Clique { score, .. } => (*score as u8).into(),
+            Seal::Aura { difficulty, .. } => *difficulty,
         }
     }
 
     pub fn extra_data(&self) -> Bytes {
         match self {
-            Seal::Ethash { vanity, .. } => vanity.clone(),
+            Seal::Ethash { vanity, .. } | Seal::Aura { vanity, .. } => vanity.clone(),
             Seal::Clique {
                 vanity, signers, ..
             } => {
@@ -334,6 +517,18 @@ impl Seal {
             _ => H64::zero(),
         }
     }
+
+    pub fn aura_seal(&self) -> Option<AuraSeal> {
+        match self {
+            Seal::Aura {
+                step, signature, ..
+            } => Some(AuraSeal {
+                step: *step,
+                signature: *signature,
+            }),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -343,6 +538,25 @@ pub struct Genesis {
     pub gas_limit: u64,
     pub timestamp: u64,
     pub seal: Seal,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    pub base_fee_per_gas: Option<U256>,
+    /// Allocations besides balances, which are kept in [`ChainSpec::balances`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub accounts: BTreeMap<Address, GenesisAccount>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct GenesisAccount {
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default, with = "hexbytes", skip_serializing_if = "Bytes::is_empty")]
+    pub code: Bytes,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, U256>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -377,6 +591,9 @@ pub enum Precompile {
 pub struct P2PParams {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub bootnodes: Vec<NodeUrl>,
+    /// EIP-1459 node lists as `enrtree://<key>@<domain>` URLs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_networks: Vec<NodeUrl>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub preverified_hashes: Vec<H256>,
 }
@@ -419,6 +636,27 @@ mod tests {
     use hex_literal::hex;
     use maplit::*;
 
+    #[test]
+    fn summary() {
+        let summary = MAINNET.summary(H256::zero());
+        assert_eq!(summary.chain_id, ChainId(1));
+        assert_eq!(summary.consensus, "ethash");
+        assert_eq!(
+            summary.upgrades.first(),
+            Some(&UpgradeActivation {
+                name: "homestead",
+                block: BlockNumber(1_150_000)
+            })
+        );
+        assert!(summary
+            .upgrades
+            .windows(2)
+            .all(|w| w[0].block <= w[1].block));
+        assert!(summary.fork_blocks.contains(&BlockNumber(12_965_000)));
+
+        assert_eq!(RINKEBY.summary(H256::zero()).consensus, "clique");
+    }
+
     #[test]
     fn load_chainspec() {
         assert_eq!(
@@ -464,6 +702,8 @@ mod tests {
                             hex!("b279182d99e65703f0076e4812653aab85fca0f0").into(),
                         ],
                     },
+                    base_fee_per_gas: None,
+                    accounts: Default::default(),
                 },
                 contracts: Default::default(),
                 balances: btreemap! {
@@ -476,12 +716,16 @@ mod tests {
                         )),
                     )].into_iter()).collect::<HashMap<Address, U256>>(),
                 },
+                optimism: None,
                 p2p: P2PParams {
                     bootnodes: vec![
                         "enode://a24ac7c5484ef4ed0c5eb2d36620ba4e4aa13b8c84684e1b4aab0cebea2ae45cb4d375b77eab56516d34bfbd3c1a833fc51296ff084b770b94fb9028c4d25ccf@52.169.42.101:30303",
                         "enode://343149e4feefa15d882d9fe4ac7d88f885bd05ebb735e547f12e12080a9fa07c8014ca6fd7f373123488102fe5e34111f8509cf0b7de3f5b44339c9f25e87cb8@52.3.158.184:30303",
                         "enode://b6b28890b006743680c52e64e0d16db57f28124885595fa03a562be1d2bf0f3a1da297d56b13da25fb992888fd556d4c1a27b1f39d531bde7de1921c90061cc6@159.89.28.211:30303",
                     ].into_iter().map(ToString::to_string).collect(),
+                    dns_networks: vec![
+                        "enrtree://AKA3AM6LPBYEUDMVNU3BSVQJ5AD45Y7YPOHJLEF6W26QOE4VTUDPE@all.rinkeby.ethdisco.net".to_string(),
+                    ],
                     preverified_hashes: vec![],
                 }
             },
@@ -502,4 +746,40 @@ mod tests {
             .collect()
         );
     }
+
+    #[test]
+    fn cached_block_specs() {
+        for chain_spec in [&*MAINNET, &*ROPSTEN, &*RINKEBY] {
+            let mut cache = BlockSpecCache::new(chain_spec.clone());
+            for fork in chain_spec
+                .gather_forks()
+                .into_iter()
+                .chain([BlockNumber(0)])
+            {
+                for block_number in [fork.0.saturating_sub(1), fork.0, fork.0 + 1, fork.0 + 2] {
+                    assert_eq!(
+                        *cache.get(block_number),
+                        chain_spec.collect_block_spec(block_number),
+                        "{} block {}",
+                        chain_spec.name,
+                        block_number
+                    );
+                }
+            }
+        }
+
+        let mut cache = BlockSpecCache::new(MAINNET.clone());
+        assert!(Arc::ptr_eq(
+            &cache.get(BlockNumber(1_150_001)),
+            &cache.get(BlockNumber(1_919_999))
+        ));
+        assert!(!Arc::ptr_eq(
+            &cache.get(BlockNumber(1_919_999)),
+            &cache.get(BlockNumber(1_920_000))
+        ));
+        assert!(!Arc::ptr_eq(
+            &cache.get(BlockNumber(1_920_000)),
+            &cache.get(BlockNumber(1_920_001))
+        ));
+    }
 }
@@ -0,0 +1,11 @@
+use super::*;
+use parity_scale_codec::*;
+use serde::*;
+
+/// Contract deployed by a transaction, directly or through CREATE/CREATE2 of another contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct ContractCreation {
+    /// Account that executed the creation: the transaction sender or a factory contract.
+    pub creator: Address,
+    pub address: Address,
+}
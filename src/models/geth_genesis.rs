@@ -0,0 +1,671 @@
+//! Genesis files in the format taken by `geth init`.
+use super::*;
+use crate::util::*;
+use anyhow::{bail, ensure, format_err};
+use bytes::Bytes;
+use serde::{de, Deserialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+/// Base fee of the genesis block if London is active from the start, as in EIP-1559.
+const INITIAL_BASE_FEE: u64 = 1_000_000_000;
+
+const CLIQUE_VANITY_LENGTH: usize = 32;
+const CLIQUE_SEAL_LENGTH: usize = 65;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GethGenesis {
+    pub config: GethChainConfig,
+    #[serde(default, deserialize_with = "deserialize_quantity_u64")]
+    pub nonce: u64,
+    #[serde(default, deserialize_with = "deserialize_quantity_u64")]
+    pub timestamp: u64,
+    #[serde(default, with = "hexbytes")]
+    pub extra_data: Bytes,
+    #[serde(deserialize_with = "deserialize_quantity_u64")]
+    pub gas_limit: u64,
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub difficulty: U256,
+    #[serde(default)]
+    pub mix_hash: H256,
+    #[serde(default)]
+    pub coinbase: Address,
+    #[serde(default, deserialize_with = "deserialize_quantity_u64")]
+    pub number: u64,
+    #[serde(default, deserialize_with = "deserialize_optional_quantity")]
+    pub base_fee_per_gas: Option<U256>,
+    #[serde(default)]
+    pub alloc: BTreeMap<String, GethGenesisAccount>,
+}
+
+/// Fork schedule of a geth genesis file, block-based forks only.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GethChainConfig {
+    pub chain_id: u64,
+    pub homestead_block: Option<u64>,
+    pub eip150_block: Option<u64>,
+    pub eip155_block: Option<u64>,
+    pub eip158_block: Option<u64>,
+    pub byzantium_block: Option<u64>,
+    pub constantinople_block: Option<u64>,
+    pub petersburg_block: Option<u64>,
+    pub istanbul_block: Option<u64>,
+    pub muir_glacier_block: Option<u64>,
+    pub berlin_block: Option<u64>,
+    pub london_block: Option<u64>,
+    pub arrow_glacier_block: Option<u64>,
+    pub gray_glacier_block: Option<u64>,
+    pub clique: Option<GethCliqueConfig>,
+    /// Set for OP-stack chains. Regolith is only supported from genesis on.
+    pub optimism: Option<GethOptimismConfig>,
+    pub regolith_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct GethCliqueConfig {
+    /// Seconds between blocks.
+    pub period: u64,
+    pub epoch: u64,
+}
+
+/// The EIP-1559 parameters it holds are not supported.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct GethOptimismConfig {}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct GethGenesisAccount {
+    #[serde(deserialize_with = "deserialize_quantity")]
+    pub balance: U256,
+    #[serde(default, deserialize_with = "deserialize_quantity_u64")]
+    pub nonce: u64,
+    #[serde(default, with = "hexbytes")]
+    pub code: Bytes,
+    #[serde(default)]
+    pub storage: HashMap<String, String>,
+}
+
+/// Numbers in geth genesis files are either JSON numbers, or hex or decimal strings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+    Number(u64),
+    String(String),
+}
+
+fn parse_quantity(s: &str) -> anyhow::Result<U256> {
+    Ok(
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some("") => U256::ZERO,
+            Some(hex) => U256::from_str_radix(hex, 16)?,
+            None => U256::from_str_radix(s, 10)?,
+        },
+    )
+}
+
+fn deserialize_quantity<'de, D>(deserializer: D) -> Result<U256, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    match Quantity::deserialize(deserializer)? {
+        Quantity::Number(v) => Ok(v.as_u256()),
+        Quantity::String(s) => parse_quantity(&s).map_err(de::Error::custom),
+    }
+}
+
+fn deserialize_optional_quantity<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Wrapper(#[serde(deserialize_with = "deserialize_quantity")] U256);
+
+    Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(v)| v))
+}
+
+fn deserialize_quantity_u64<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let v = deserialize_quantity(deserializer)?;
+    if v > u64::MAX.as_u256() {
+        return Err(de::Error::custom(format!("{} does not fit into u64", v)));
+    }
+
+    Ok(v.as_u64())
+}
+
+/// Left-pad hex `s` into a 32 byte word, geth accepts storage keys and values shorter than that.
+fn parse_word(s: &str) -> anyhow::Result<H256> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    ensure!(s.len() <= 64, "storage word {} longer than 32 bytes", s);
+    let bytes = hex::decode(format!("{:0>64}", s))?;
+    Ok(H256::from_slice(&bytes))
+}
+
+impl GethGenesis {
+    /// Convert into a chain spec named `name`.
+    pub fn into_chain_spec(self, name: String) -> anyhow::Result<ChainSpec> {
+        let config = self.config;
+        let fork = |block: Option<u64>| block.map(BlockNumber);
+
+        let upgrades = Upgrades {
+            homestead: fork(config.homestead_block),
+            tangerine: fork(config.eip150_block),
+            spurious: fork(config.eip158_block.or(config.eip155_block)),
+            byzantium: fork(config.byzantium_block),
+            constantinople: fork(config.constantinople_block),
+            // Geth activates Petersburg along with Constantinople unless told otherwise.
+            petersburg: fork(config.petersburg_block.or(config.constantinople_block)),
+            istanbul: fork(config.istanbul_block),
+            berlin: fork(config.berlin_block),
+            london: fork(config.london_block),
+        };
+
+        let optimism = match (&config.optimism, config.regolith_time) {
+            (None, _) => None,
+            (Some(_), None) => Some(OptimismParams { regolith: None }),
+            (Some(_), Some(regolith_time)) => {
+                ensure!(
+                    regolith_time <= self.timestamp,
+                    "Regolith must be active from genesis"
+                );
+                Some(OptimismParams {
+                    regolith: Some(BlockNumber(self.number)),
+                })
+            }
+        };
+
+        let (seal_verification, seal) = if let Some(clique) = config.clique {
+            let extra_data = &self.extra_data;
+            ensure!(
+                extra_data.len() >= CLIQUE_VANITY_LENGTH + CLIQUE_SEAL_LENGTH,
+                "clique extra data too short"
+            );
+            let signers = &extra_data[CLIQUE_VANITY_LENGTH..extra_data.len() - CLIQUE_SEAL_LENGTH];
+            ensure!(
+                signers.len() % Address::len_bytes() == 0,
+                "clique extra data does not hold a whole number of signers"
+            );
+            ensure!(
+                extra_data[extra_data.len() - CLIQUE_SEAL_LENGTH..]
+                    .iter()
+                    .all(|&b| b == 0),
+                "clique genesis seal must be empty"
+            );
+            let score = if self.difficulty == 1.as_u256() {
+                BlockScore::NoTurn
+            } else if self.difficulty == 2.as_u256() {
+                BlockScore::InTurn
+            } else {
+                bail!(
+                    "clique genesis difficulty must be 1 or 2, got {}",
+                    self.difficulty
+                );
+            };
+
+            (
+                SealVerificationParams::Clique {
+                    period: Duration::from_secs(clique.period),
+                    epoch: clique.epoch,
+                },
+                Seal::Clique {
+                    vanity: H256::from_slice(&extra_data[..CLIQUE_VANITY_LENGTH]),
+                    score,
+                    signers: signers
+                        .chunks(Address::len_bytes())
+                        .map(Address::from_slice)
+                        .collect(),
+                },
+            )
+        } else {
+            let eth = |v: u64| v.as_u256() * 1_000_000_000_000_000_000_u128.as_u256();
+
+            let mut block_reward = BTreeMap::new();
+            for (block, reward) in [
+                (Some(0), eth(5)),
+                (config.byzantium_block, eth(3)),
+                (config.constantinople_block, eth(2)),
+            ] {
+                if let Some(block) = block {
+                    block_reward.insert(BlockNumber(block), reward);
+                }
+            }
+
+            let mut delays = BTreeMap::new();
+            for (block, delay) in [
+                (config.byzantium_block, 3_000_000),
+                (config.constantinople_block, 5_000_000),
+                (config.muir_glacier_block, 9_000_000),
+                (config.london_block, 9_700_000),
+                (config.arrow_glacier_block, 10_700_000),
+                (config.gray_glacier_block, 11_400_000),
+            ] {
+                if let Some(block) = block {
+                    delays.insert(BlockNumber(block), BlockNumber(delay));
+                }
+            }
+
+            (
+                SealVerificationParams::Ethash {
+                    duration_limit: 13,
+                    block_reward,
+                    homestead_formula: fork(config.homestead_block),
+                    byzantium_formula: fork(config.byzantium_block),
+                    difficulty_bomb: (!delays.is_empty()).then(|| DifficultyBomb { delays }),
+                    ecip1017_era_rounds: None,
+                    skip_pow_verification: false,
+                },
+                Seal::Ethash {
+                    vanity: self.extra_data,
+                    difficulty: self.difficulty,
+                    nonce: H64::from_low_u64_be(self.nonce),
+                    mix_hash: self.mix_hash,
+                },
+            )
+        };
+
+        let number = BlockNumber(self.number);
+        let base_fee_per_gas = self.base_fee_per_gas.or_else(|| {
+            (config.london_block == Some(self.number)).then(|| INITIAL_BASE_FEE.as_u256())
+        });
+
+        let mut balances = HashMap::new();
+        let mut accounts = BTreeMap::new();
+        for (address, account) in self.alloc {
+            let address = parse_word(&address)
+                .ok()
+                .and_then(|word| {
+                    word.as_bytes()[..12]
+                        .iter()
+                        .all(|&b| b == 0)
+                        .then(|| Address::from_slice(&word.as_bytes()[12..]))
+                })
+                .ok_or_else(|| format_err!("invalid alloc address {}", address))?;
+
+            balances.insert(address, account.balance);
+
+            let storage = account
+                .storage
+                .iter()
+                .map(|(location, value)| {
+                    Ok((parse_word(location)?, h256_to_u256(parse_word(value)?)))
+                })
+                .filter(|res| !matches!(res, Ok((_, value)) if *value == U256::ZERO))
+                .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+            if account.nonce != 0 || !account.code.is_empty() || !storage.is_empty() {
+                accounts.insert(
+                    address,
+                    GenesisAccount {
+                        nonce: account.nonce,
+                        code: account.code,
+                        storage,
+                    },
+                );
+            }
+        }
+
+        Ok(ChainSpec {
+            name,
+            consensus: ConsensusParams {
+                seal_verification,
+                eip1559_block: upgrades.london,
+            },
+            upgrades,
+            params: Params {
+                chain_id: ChainId(config.chain_id),
+                network_id: NetworkId(config.chain_id),
+                min_gas_limit: 5000,
+            },
+            genesis: Genesis {
+                number,
+                author: self.coinbase,
+                gas_limit: self.gas_limit,
+                timestamp: self.timestamp,
+                seal,
+                base_fee_per_gas,
+                accounts,
+            },
+            contracts: BTreeMap::new(),
+            balances: [(number, balances)].into_iter().collect(),
+            optimism,
+            p2p: P2PParams {
+                bootnodes: vec![],
+                dns_networks: vec![],
+                preverified_hashes: vec![],
+            },
+        })
+    }
+}
+
+fn hex_quantity(v: impl Into<U256>) -> String {
+    format!("0x{:x}", v.into())
+}
+
+/// Geth genesis file of `chain_spec`, for other clients to start the same chain. Fails for
+/// parameters the format cannot express, such as those of networks built into geth.
+pub fn geth_genesis_json(chain_spec: &ChainSpec) -> anyhow::Result<serde_json::Value> {
+    let genesis = &chain_spec.genesis;
+    let upgrades = &chain_spec.upgrades;
+    let fork = |block: Option<BlockNumber>| block.map(|block| block.0);
+
+    let mut config = json!({
+        "chainId": chain_spec.params.chain_id.0,
+        "homesteadBlock": fork(upgrades.homestead),
+        "eip150Block": fork(upgrades.tangerine),
+        "eip155Block": fork(upgrades.spurious),
+        "eip158Block": fork(upgrades.spurious),
+        "byzantiumBlock": fork(upgrades.byzantium),
+        "constantinopleBlock": fork(upgrades.constantinople),
+        "petersburgBlock": fork(upgrades.petersburg),
+        "istanbulBlock": fork(upgrades.istanbul),
+        "berlinBlock": fork(upgrades.berlin),
+        "londonBlock": fork(upgrades.london),
+    });
+
+    let (extra_data, nonce, mix_hash) =
+        match (&chain_spec.consensus.seal_verification, &genesis.seal) {
+            (
+                SealVerificationParams::Clique { period, epoch },
+                Seal::Clique {
+                    vanity, signers, ..
+                },
+            ) => {
+                config["clique"] = json!({ "period": period.as_secs(), "epoch": epoch });
+                let mut extra_data = vanity.as_bytes().to_vec();
+                for signer in signers {
+                    extra_data.extend_from_slice(signer.as_bytes());
+                }
+                extra_data.resize(extra_data.len() + CLIQUE_SEAL_LENGTH, 0);
+                (extra_data, 0, H256::zero())
+            }
+            (
+                SealVerificationParams::Ethash {
+                    difficulty_bomb, ..
+                },
+                Seal::Ethash {
+                    vanity,
+                    nonce,
+                    mix_hash,
+                    ..
+                },
+            ) => {
+                config["ethash"] = json!({});
+                // The other delays come with Byzantium, Constantinople and London.
+                for (block, delay) in difficulty_bomb.iter().flat_map(|bomb| &bomb.delays) {
+                    let field = match delay.0 {
+                        9_000_000 => "muirGlacierBlock",
+                        10_700_000 => "arrowGlacierBlock",
+                        11_400_000 => "grayGlacierBlock",
+                        _ => continue,
+                    };
+                    config[field] = json!(block.0);
+                }
+                (vanity.to_vec(), nonce.to_low_u64_be(), *mix_hash)
+            }
+            _ => bail!("only Ethash and Clique chains have geth genesis files"),
+        };
+
+    if let Some(optimism) = &chain_spec.optimism {
+        config["optimism"] = json!({});
+        if optimism.regolith.is_some() {
+            config["regolithTime"] = json!(genesis.timestamp);
+        }
+    }
+
+    let mut alloc = BTreeMap::<Address, serde_json::Value>::new();
+    if let Some(balances) = chain_spec.balances.get(&genesis.number) {
+        for (address, &balance) in balances {
+            alloc.insert(*address, json!({ "balance": hex_quantity(balance) }));
+        }
+    }
+    for (address, account) in &genesis.accounts {
+        let entry = alloc
+            .entry(*address)
+            .or_insert_with(|| json!({ "balance": "0x0" }));
+        entry["nonce"] = json!(hex_quantity(account.nonce));
+        entry["code"] = json!(format!("0x{}", hex::encode(&account.code)));
+        entry["storage"] = account
+            .storage
+            .iter()
+            .map(|(location, &value)| (format!("{:?}", location), json!(hex_quantity(value))))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+    }
+
+    let geth_genesis = json!({
+        "config": config,
+        "nonce": hex_quantity(nonce),
+        "timestamp": hex_quantity(genesis.timestamp),
+        "extraData": format!("0x{}", hex::encode(extra_data)),
+        "gasLimit": hex_quantity(genesis.gas_limit),
+        "difficulty": hex_quantity(genesis.seal.difficulty()),
+        "mixHash": mix_hash,
+        "coinbase": genesis.author,
+        "number": hex_quantity(genesis.number.0),
+        "baseFeePerGas": genesis.base_fee_per_gas.map(hex_quantity),
+        "alloc": alloc
+            .into_iter()
+            .map(|(address, account)| (format!("{:?}", address), account))
+            .collect::<serde_json::Map<_, _>>(),
+    });
+
+    // Anything lost on the way, e.g. custom block rewards, would make it a different chain.
+    let mut converted = serde_json::from_value::<GethGenesis>(geth_genesis.clone())?
+        .into_chain_spec(chain_spec.name.clone())?;
+    converted.p2p = chain_spec.p2p.clone();
+    let mut expected = chain_spec.clone();
+    for spec in [&mut converted, &mut expected] {
+        spec.balances.retain(|_, balances| !balances.is_empty());
+    }
+    ensure!(
+        converted == expected,
+        "chain spec {} has parameters that geth genesis files cannot express",
+        chain_spec.name
+    );
+
+    Ok(geth_genesis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn clique_genesis() {
+        let genesis = serde_json::from_str::<GethGenesis>(
+            r#"{
+                "config": {
+                    "chainId": 1337,
+                    "homesteadBlock": 0,
+                    "eip150Block": 0,
+                    "eip155Block": 0,
+                    "eip158Block": 0,
+                    "byzantiumBlock": 0,
+                    "constantinopleBlock": 0,
+                    "petersburgBlock": 0,
+                    "istanbulBlock": 0,
+                    "berlinBlock": 0,
+                    "londonBlock": 0,
+                    "clique": { "period": 5, "epoch": 30000 }
+                },
+                "difficulty": "1",
+                "gasLimit": "0x1c9c380",
+                "timestamp": 1650000000,
+                "extraData": "0x00000000000000000000000000000000000000000000000000000000000000007df9a875a174b3bc565e6424a0050ebc1b2d1d820000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+                "alloc": {
+                    "7df9a875a174b3bc565e6424a0050ebc1b2d1d82": { "balance": "0xde0b6b3a7640000" },
+                    "0x0000000000000000000000000000000000000100": {
+                        "balance": "0",
+                        "nonce": "0x1",
+                        "code": "0x6001600055",
+                        "storage": {
+                            "0x00": "0x2a",
+                            "0x01": "0x0000000000000000000000000000000000000000000000000000000000000000"
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap()
+        .into_chain_spec("Devnet".into())
+        .unwrap();
+
+        let signer = Address::from(hex!("7df9a875a174b3bc565e6424a0050ebc1b2d1d82"));
+        let contract = Address::from_low_u64_be(0x100);
+
+        assert_eq!(genesis.params.chain_id, ChainId(1337));
+        assert_eq!(genesis.upgrades.london, Some(BlockNumber(0)));
+        assert_eq!(genesis.consensus.eip1559_block, Some(BlockNumber(0)));
+        assert_eq!(
+            genesis.consensus.seal_verification,
+            SealVerificationParams::Clique {
+                period: Duration::from_secs(5),
+                epoch: 30000,
+            }
+        );
+        assert_eq!(
+            genesis.genesis.seal,
+            Seal::Clique {
+                vanity: H256::zero(),
+                score: BlockScore::NoTurn,
+                signers: vec![signer],
+            }
+        );
+        assert_eq!(genesis.genesis.gas_limit, 30_000_000);
+        assert_eq!(genesis.genesis.timestamp, 1650000000);
+        assert_eq!(
+            genesis.genesis.base_fee_per_gas,
+            Some(INITIAL_BASE_FEE.as_u256())
+        );
+        assert_eq!(
+            genesis.balances[&BlockNumber(0)][&signer],
+            1_000_000_000_000_000_000_u128.as_u256()
+        );
+        assert_eq!(genesis.genesis.accounts.len(), 1);
+        assert_eq!(
+            genesis.genesis.accounts[&contract],
+            GenesisAccount {
+                nonce: 1,
+                code: hex!("6001600055").to_vec().into(),
+                storage: [(H256::zero(), 0x2a.as_u256())].into_iter().collect(),
+            }
+        );
+    }
+
+    #[test]
+    fn geth_genesis_round_trip() {
+        let chain_spec = serde_json::from_str::<GethGenesis>(
+            r#"{
+                "config": {
+                    "chainId": 1337,
+                    "homesteadBlock": 0,
+                    "eip150Block": 0,
+                    "eip155Block": 0,
+                    "eip158Block": 0,
+                    "byzantiumBlock": 0,
+                    "constantinopleBlock": 0,
+                    "petersburgBlock": 0,
+                    "istanbulBlock": 0,
+                    "berlinBlock": 0,
+                    "londonBlock": 5,
+                    "clique": { "period": 5, "epoch": 30000 }
+                },
+                "difficulty": "2",
+                "gasLimit": "0x1c9c380",
+                "extraData": "0x00000000000000000000000000000000000000000000000000000000000000017df9a875a174b3bc565e6424a0050ebc1b2d1d820000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+                "alloc": {
+                    "7df9a875a174b3bc565e6424a0050ebc1b2d1d82": { "balance": "0xde0b6b3a7640000" },
+                    "0x0000000000000000000000000000000000000100": {
+                        "balance": "0",
+                        "code": "0x6001600055",
+                        "storage": { "0x00": "0x2a" }
+                    }
+                }
+            }"#,
+        )
+        .unwrap()
+        .into_chain_spec("Devnet".into())
+        .unwrap();
+
+        let exported = geth_genesis_json(&chain_spec).unwrap();
+        assert_eq!(exported["config"]["clique"]["period"], 5);
+        assert_eq!(exported["difficulty"], "0x2");
+        assert_eq!(
+            serde_json::from_value::<GethGenesis>(exported)
+                .unwrap()
+                .into_chain_spec("Devnet".into())
+                .unwrap(),
+            chain_spec
+        );
+
+        // The DAO fork moves balances, which a genesis file cannot do.
+        geth_genesis_json(&crate::res::chainspec::MAINNET).unwrap_err();
+    }
+
+    #[test]
+    fn ethash_genesis() {
+        let genesis = serde_json::from_str::<GethGenesis>(
+            r#"{
+                "config": {
+                    "chainId": 12345,
+                    "homesteadBlock": 0,
+                    "eip150Block": 0,
+                    "eip155Block": 0,
+                    "eip158Block": 0,
+                    "byzantiumBlock": 0,
+                    "constantinopleBlock": 10,
+                    "ethash": {}
+                },
+                "nonce": "0x42",
+                "difficulty": "0x20000",
+                "gasLimit": "0x7a1200",
+                "extraData": "0x1234",
+                "alloc": {}
+            }"#,
+        )
+        .unwrap()
+        .into_chain_spec("Devnet".into())
+        .unwrap();
+
+        assert_eq!(genesis.upgrades.petersburg, Some(BlockNumber(10)));
+        assert_eq!(genesis.upgrades.london, None);
+        assert_eq!(genesis.genesis.base_fee_per_gas, None);
+        assert_eq!(
+            genesis.genesis.seal,
+            Seal::Ethash {
+                vanity: hex!("1234").to_vec().into(),
+                difficulty: 0x20000.as_u256(),
+                nonce: H64::from_low_u64_be(0x42),
+                mix_hash: H256::zero(),
+            }
+        );
+        match genesis.consensus.seal_verification {
+            SealVerificationParams::Ethash {
+                block_reward,
+                difficulty_bomb,
+                ..
+            } => {
+                assert_eq!(
+                    block_reward,
+                    [
+                        (BlockNumber(0), 3_000_000_000_000_000_000_u128.as_u256()),
+                        (BlockNumber(10), 2_000_000_000_000_000_000_u128.as_u256()),
+                    ]
+                    .into_iter()
+                    .collect()
+                );
+                assert_eq!(
+                    difficulty_bomb.unwrap().get_delay_to(BlockNumber(10)),
+                    BlockNumber(5_000_000)
+                );
+            }
+            other => panic!("unexpected seal verification {:?}", other),
+        }
+    }
+}
@@ -5,6 +5,14 @@ use parity_scale_codec::*;
 use rlp::*;
 use serde::*;
 
+/// Seal of an Aura block, which takes the place of the mix hash and nonce in its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub struct AuraSeal {
+    pub step: u64,
+    /// Signature of the header's [`BlockHeader::truncated_hash`] by the step's proposer.
+    pub signature: H520,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
 /// Ethereum block header definition.
 pub struct BlockHeader {
@@ -24,6 +32,9 @@ pub struct BlockHeader {
     pub mix_hash: H256,
     pub nonce: H64,
     pub base_fee_per_gas: Option<U256>,
+    /// Set on Aura blocks, whose mix hash and nonce are then left zero.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aura_seal: Option<AuraSeal>,
 }
 
 impl Encodable for BlockHeader {
@@ -48,8 +59,13 @@ impl Encodable for BlockHeader {
         s.append(&self.gas_used);
         s.append(&self.timestamp);
         s.append(&self.extra_data.as_ref());
-        s.append(&self.mix_hash);
-        s.append(&self.nonce);
+        if let Some(seal) = &self.aura_seal {
+            s.append(&seal.step);
+            s.append(&seal.signature);
+        } else {
+            s.append(&self.mix_hash);
+            s.append(&self.nonce);
+        }
         if let Some(base_fee_per_gas) = self.base_fee_per_gas {
             s.append(&base_fee_per_gas);
         }
@@ -76,9 +92,30 @@ impl Decodable for BlockHeader {
             .ok_or(DecoderError::RlpInvalidLength)?
             .as_val::<Vec<u8>>()?
             .into();
-        let mix_hash = rlp.next().ok_or(DecoderError::RlpInvalidLength)?.as_val()?;
-        let nonce = rlp.next().ok_or(DecoderError::RlpInvalidLength)?.as_val()?;
+        // An Aura seal is a step and a signature, told apart from a mix hash by its size.
+        let seal = rlp.next().ok_or(DecoderError::RlpInvalidLength)?;
+        let (mix_hash, nonce, aura_seal) = if seal.size() == H256::len_bytes() {
+            (
+                seal.as_val()?,
+                rlp.next().ok_or(DecoderError::RlpInvalidLength)?.as_val()?,
+                None,
+            )
+        } else {
+            (
+                H256::zero(),
+                H64::zero(),
+                Some(AuraSeal {
+                    step: seal.as_val()?,
+                    signature: rlp.next().ok_or(DecoderError::RlpInvalidLength)?.as_val()?,
+                }),
+            )
+        };
         let base_fee_per_gas = rlp.next().map(|rlp| rlp.as_val()).transpose()?;
+        // Fields of later forks (withdrawals root and on) are not supported, and dropping them
+        // would silently change the hash.
+        if rlp.next().is_some() {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
 
         Ok(Self {
             parent_hash,
@@ -97,6 +134,7 @@ impl Decodable for BlockHeader {
             mix_hash,
             nonce,
             base_fee_per_gas,
+            aura_seal,
         })
     }
 }
@@ -121,6 +159,7 @@ impl BlockHeader {
             mix_hash: partial_header.mix_hash,
             nonce: partial_header.nonce,
             base_fee_per_gas: partial_header.base_fee_per_gas,
+            aura_seal: partial_header.aura_seal,
         }
     }
 
@@ -143,6 +182,7 @@ impl BlockHeader {
             mix_hash: H256::zero(),
             nonce: H64::zero(),
             base_fee_per_gas: None,
+            aura_seal: None,
         }
     }
 
@@ -236,6 +276,7 @@ pub struct PartialHeader {
     pub mix_hash: H256,
     pub nonce: H64,
     pub base_fee_per_gas: Option<U256>,
+    pub aura_seal: Option<AuraSeal>,
 }
 
 impl From<BlockHeader> for PartialHeader {
@@ -255,6 +296,7 @@ impl From<BlockHeader> for PartialHeader {
             mix_hash: header.mix_hash,
             nonce: header.nonce,
             base_fee_per_gas: header.base_fee_per_gas,
+            aura_seal: header.aura_seal,
         }
     }
 }
@@ -277,6 +319,41 @@ impl PartialHeader {
             mix_hash: H256::zero(),
             nonce: H64::zero(),
             base_fee_per_gas: None,
+            aura_seal: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::strategies::block_headers;
+    use proptest::prelude::*;
+
+    #[test]
+    fn unknown_fork_fields_rejected() {
+        let mut header = BlockHeader::empty();
+        header.base_fee_per_gas = Some(7.as_u256());
+
+        let mut s = RlpStream::new_list(17);
+        for item in Rlp::new(&rlp::encode(&header)).iter() {
+            s.append_raw(item.as_raw(), 1);
+        }
+        s.append(&H256::repeat_byte(0xaa));
+
+        assert_eq!(
+            rlp::decode::<BlockHeader>(&s.out()),
+            Err(DecoderError::RlpIncorrectListLen)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn header_roundtrip(header in block_headers()) {
+            let encoded = rlp::encode(&header);
+            let decoded = rlp::decode::<BlockHeader>(&encoded).unwrap();
+            prop_assert_eq!(decoded.hash(), header.hash());
+            prop_assert_eq!(decoded, header);
         }
     }
 }
@@ -1,16 +1,23 @@
 mod account;
 mod block;
 mod bloom;
+mod bor;
 mod chainspec;
+mod creation;
+mod geth_genesis;
 mod header;
 mod log;
 mod receipt;
 mod revision;
+#[cfg(test)]
+mod strategies;
 mod transaction;
+mod transfer;
+mod withdrawal;
 
 pub use self::{
-    account::*, block::*, bloom::*, chainspec::*, header::*, log::*, receipt::*, revision::*,
-    transaction::*,
+    account::*, block::*, bloom::*, bor::*, chainspec::*, creation::*, geth_genesis::*, header::*, log::*,
+    receipt::*, revision::*, transaction::*, transfer::*, withdrawal::*,
 };
 
 use derive_more::*;
@@ -19,7 +26,7 @@ use rlp::{Decodable, Encodable};
 use serde::{Deserialize, Serialize};
 use std::{iter::Step, mem::size_of, ops::Add};
 
-pub use ethereum_types::{Address, Bloom, H128, H160, H256, H512, H64, U512, U64};
+pub use ethereum_types::{Address, Bloom, H128, H160, H256, H512, H520, H64, U512, U64};
 pub use ethnum::*;
 
 pub const KECCAK_LENGTH: usize = H256::len_bytes();
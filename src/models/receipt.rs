@@ -1,8 +1,7 @@
 use super::*;
 use crate::crypto::*;
 use bytes::{BufMut, Bytes, BytesMut};
-use rlp::{DecoderError, Encodable, RlpStream};
-use rlp_derive::RlpDecodable;
+use rlp::{DecoderError, Encodable, Rlp, RlpStream};
 use serde::*;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -12,6 +11,9 @@ pub struct Receipt {
     pub cumulative_gas_used: u64,
     pub bloom: Bloom,
     pub logs: Vec<Log>,
+    /// Nonce of the sender of an OP-stack deposit, recorded from Regolith on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deposit_nonce: Option<u64>,
 }
 
 impl Receipt {
@@ -23,6 +25,7 @@ impl Receipt {
             cumulative_gas_used,
             bloom,
             logs,
+            deposit_nonce: None,
         }
     }
 
@@ -35,14 +38,20 @@ impl Receipt {
                 l.append(&self.bloom);
                 l.append_list(&self.logs);
             }
-            TxType::EIP2930 | TxType::EIP1559 => {
+            TxType::EIP2930 | TxType::EIP1559 | TxType::Deposit => {
                 let mut b = BytesMut::with_capacity(1);
                 b.put_u8(self.tx_type as u8);
-                let mut l = RlpStream::new_list_with_buffer(b, 4);
+                let mut l = RlpStream::new_list_with_buffer(
+                    b,
+                    4 + self.deposit_nonce.is_some() as usize,
+                );
                 l.append(&self.success);
                 l.append(&self.cumulative_gas_used);
                 l.append(&self.bloom);
                 l.append_list(&self.logs);
+                if let Some(deposit_nonce) = self.deposit_nonce {
+                    l.append(&deposit_nonce);
+                }
                 if standalone {
                     s.append_raw(&*l.out().freeze(), 1);
                 } else {
@@ -52,18 +61,21 @@ impl Receipt {
         }
     }
 
-    fn decode_inner(rlp: &rlp::Rlp, is_legacy: bool) -> Result<Self, DecoderError> {
-        Ok(match is_legacy {
-            true => {
-                let inner = UntypedReceipt::decode(rlp)?;
-                inner.into_receipt(TxType::Legacy)
-            }
-            false => {
-                let tx_type = u8::decode(rlp)?;
-                let inner = UntypedReceipt::decode(rlp)?;
-                inner.into_receipt(tx_type.try_into()?)
-            }
-        })
+    /// Decodes the receipt from its trie encoding: the RLP list for legacy receipts, the type
+    /// byte followed by the RLP list otherwise.
+    pub fn trie_decode(slice: &[u8]) -> Result<Self, DecoderError> {
+        let first = *slice.first().ok_or(DecoderError::Custom("empty slice"))?;
+
+        if Rlp::new(slice).is_list() {
+            return UntypedReceipt::decode(&Rlp::new(slice))?.into_receipt(TxType::Legacy);
+        }
+
+        let tx_type = TxType::try_from(first)?;
+        if tx_type == TxType::Legacy {
+            return Err(DecoderError::Custom("invalid receipt type"));
+        }
+
+        UntypedReceipt::decode(&Rlp::new(&slice[1..]))?.into_receipt(tx_type)
     }
 }
 
@@ -83,28 +95,99 @@ impl Encodable for Receipt {
 
 impl Decodable for Receipt {
     fn decode(rlp: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
-        // FIXME: This check may be incorrect
-        let is_legacy = !rlp.is_data();
-        Self::decode_inner(rlp, is_legacy)
+        if rlp.is_list() {
+            return UntypedReceipt::decode(rlp)?.into_receipt(TxType::Legacy);
+        }
+
+        // Typed receipts are wrapped in a byte string.
+        Self::trie_decode(rlp.data()?)
     }
 }
 
-#[derive(RlpDecodable)]
+/// Logs bloom of a block, the union of its receipts' blooms.
+pub fn receipts_bloom<'a, It>(receipts: It) -> Bloom
+where
+    It: IntoIterator<Item = &'a Receipt>,
+{
+    receipts
+        .into_iter()
+        .fold(Bloom::zero(), |bloom, receipt| bloom | receipt.bloom)
+}
+
+/// Receipts root of a block. Only matches the header from Byzantium on: earlier receipts
+/// carry intermediate state roots instead of the status, and those are not kept.
+pub fn receipts_root(receipts: &[Receipt]) -> H256 {
+    root_hash(receipts)
+}
+
 struct UntypedReceipt {
     pub success: bool,
     pub cumulative_gas_used: u64,
     pub bloom: Bloom,
     pub logs: Vec<Log>,
+    pub deposit_nonce: Option<u64>,
 }
 
 impl UntypedReceipt {
-    fn into_receipt(self, tx_type: TxType) -> Receipt {
-        Receipt {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let deposit_nonce = match rlp.item_count()? {
+            4 => None,
+            5 => Some(rlp.val_at(4)?),
+            _ => return Err(DecoderError::RlpIncorrectListLen),
+        };
+
+        Ok(Self {
+            success: rlp.val_at(0)?,
+            cumulative_gas_used: rlp.val_at(1)?,
+            bloom: rlp.val_at(2)?,
+            logs: rlp.list_at(3)?,
+            deposit_nonce,
+        })
+    }
+
+    fn into_receipt(self, tx_type: TxType) -> Result<Receipt, DecoderError> {
+        if self.deposit_nonce.is_some() && tx_type != TxType::Deposit {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+
+        Ok(Receipt {
             tx_type,
             success: self.success,
             cumulative_gas_used: self.cumulative_gas_used,
             bloom: self.bloom,
             logs: self.logs,
+            deposit_nonce: self.deposit_nonce,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::strategies::receipts;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn receipt_roundtrip(receipt in receipts()) {
+            prop_assert_eq!(&rlp::decode::<Receipt>(&rlp::encode(&receipt)).unwrap(), &receipt);
+            prop_assert_eq!(&Receipt::trie_decode(&receipt.trie_encode()).unwrap(), &receipt);
         }
     }
+
+    #[test]
+    fn deposit_receipt() {
+        let receipt = Receipt {
+            deposit_nonce: Some(7),
+            ..Receipt::new(TxType::Deposit, true, 21_000, vec![])
+        };
+
+        let encoded = receipt.trie_encode();
+        assert_eq!(encoded[0], 0x7E);
+        assert_eq!(Receipt::trie_decode(&encoded).unwrap(), receipt);
+        assert_eq!(
+            rlp::decode::<Receipt>(&rlp::encode(&receipt)).unwrap(),
+            receipt
+        );
+    }
 }
@@ -0,0 +1,238 @@
+//! Proptest strategies for consensus objects, for round-trip tests of their encodings.
+use super::*;
+use bytes::Bytes;
+use proptest::{collection::vec, option, prelude::*};
+
+pub fn addresses() -> impl Strategy<Value = Address> {
+    any::<[u8; 20]>().prop_map(Address::from)
+}
+
+pub fn h256s() -> impl Strategy<Value = H256> {
+    any::<[u8; 32]>().prop_map(H256::from)
+}
+
+pub fn u256s() -> impl Strategy<Value = U256> {
+    prop_oneof![
+        any::<u64>().prop_map(U256::from),
+        any::<[u8; 32]>().prop_map(U256::from_be_bytes),
+    ]
+}
+
+pub fn blooms() -> impl Strategy<Value = Bloom> {
+    vec(any::<u8>(), 256).prop_map(|v| Bloom::from_slice(&v))
+}
+
+pub fn bytes(max_len: usize) -> impl Strategy<Value = Bytes> {
+    vec(any::<u8>(), 0..max_len).prop_map(Bytes::from)
+}
+
+fn aura_seals() -> impl Strategy<Value = AuraSeal> {
+    (any::<u64>(), vec(any::<u8>(), 65)).prop_map(|(step, signature)| AuraSeal {
+        step,
+        signature: H520::from_slice(&signature),
+    })
+}
+
+pub fn block_headers() -> impl Strategy<Value = BlockHeader> {
+    (
+        (h256s(), h256s(), addresses(), h256s(), h256s(), h256s()),
+        (blooms(), u256s(), any::<u64>(), any::<u64>(), any::<u64>()),
+        (
+            any::<u64>(),
+            bytes(32),
+            h256s(),
+            any::<[u8; 8]>(),
+            option::of(u256s()),
+            option::of(aura_seals()),
+        ),
+    )
+        .prop_map(
+            |(
+                (
+                    parent_hash,
+                    ommers_hash,
+                    beneficiary,
+                    state_root,
+                    transactions_root,
+                    receipts_root,
+                ),
+                (logs_bloom, difficulty, number, gas_limit, gas_used),
+                (timestamp, extra_data, mix_hash, nonce, base_fee_per_gas, aura_seal),
+            )| BlockHeader {
+                parent_hash,
+                ommers_hash,
+                beneficiary,
+                state_root,
+                transactions_root,
+                receipts_root,
+                logs_bloom,
+                difficulty,
+                number: BlockNumber(number),
+                gas_limit,
+                gas_used,
+                timestamp,
+                extra_data,
+                // An Aura seal replaces both in the encoding.
+                mix_hash: if aura_seal.is_some() {
+                    H256::zero()
+                } else {
+                    mix_hash
+                },
+                nonce: if aura_seal.is_some() {
+                    H64::zero()
+                } else {
+                    H64::from(nonce)
+                },
+                base_fee_per_gas,
+                aura_seal,
+            },
+        )
+}
+
+fn actions() -> impl Strategy<Value = TransactionAction> {
+    prop_oneof![
+        Just(TransactionAction::Create),
+        addresses().prop_map(TransactionAction::Call),
+    ]
+}
+
+fn access_lists() -> impl Strategy<Value = AccessList> {
+    vec(
+        (addresses(), vec(h256s(), 0..4))
+            .prop_map(|(address, slots)| AccessListItem { address, slots }),
+        0..4,
+    )
+}
+
+/// Signature values accepted by [`MessageSignature::new`]: non-zero and below the curve order.
+fn signature_values() -> impl Strategy<Value = H256> {
+    any::<[u8; 32]>()
+        .prop_map(|mut v| {
+            v[0] &= 0x7f;
+            H256(v)
+        })
+        .prop_filter("zero signature value", |v| !v.is_zero())
+}
+
+fn signatures() -> impl Strategy<Value = MessageSignature> {
+    (any::<bool>(), signature_values(), signature_values())
+        .prop_map(|(odd_y_parity, r, s)| MessageSignature::new(odd_y_parity, r, s).unwrap())
+}
+
+fn messages() -> impl Strategy<Value = Message> {
+    // Legacy chain IDs are bounded so that `v` fits in 64 bits.
+    let legacy = (
+        option::of(any::<u32>()),
+        any::<u64>(),
+        u256s(),
+        any::<u64>(),
+        actions(),
+        u256s(),
+        bytes(64),
+    )
+        .prop_map(
+            |(chain_id, nonce, gas_price, gas_limit, action, value, input)| Message::Legacy {
+                chain_id: chain_id.map(|id| ChainId(id.into())),
+                nonce,
+                gas_price,
+                gas_limit,
+                action,
+                value,
+                input,
+            },
+        );
+    let eip2930 = (
+        any::<u64>(),
+        any::<u64>(),
+        u256s(),
+        any::<u64>(),
+        actions(),
+        u256s(),
+        bytes(64),
+        access_lists(),
+    )
+        .prop_map(
+            |(chain_id, nonce, gas_price, gas_limit, action, value, input, access_list)| {
+                Message::EIP2930 {
+                    chain_id: ChainId(chain_id),
+                    nonce,
+                    gas_price,
+                    gas_limit,
+                    action,
+                    value,
+                    input,
+                    access_list,
+                }
+            },
+        );
+    let eip1559 = (
+        (any::<u64>(), any::<u64>(), u256s(), u256s(), any::<u64>()),
+        (actions(), u256s(), bytes(64), access_lists()),
+    )
+        .prop_map(
+            |(
+                (chain_id, nonce, max_priority_fee_per_gas, max_fee_per_gas, gas_limit),
+                (action, value, input, access_list),
+            )| Message::EIP1559 {
+                chain_id: ChainId(chain_id),
+                nonce,
+                max_priority_fee_per_gas,
+                max_fee_per_gas,
+                gas_limit,
+                action,
+                value,
+                input,
+                access_list,
+            },
+        );
+
+    prop_oneof![legacy, eip2930, eip1559]
+}
+
+pub fn transactions() -> impl Strategy<Value = MessageWithSignature> {
+    (messages(), signatures())
+        .prop_map(|(message, signature)| MessageWithSignature { message, signature })
+}
+
+fn logs() -> impl Strategy<Value = Log> {
+    (addresses(), vec(h256s(), 0..4), bytes(64)).prop_map(|(address, topics, data)| Log {
+        address,
+        topics,
+        data,
+    })
+}
+
+pub fn receipts() -> impl Strategy<Value = Receipt> {
+    (
+        prop_oneof![
+            Just(TxType::Legacy),
+            Just(TxType::EIP2930),
+            Just(TxType::EIP1559)
+        ],
+        any::<bool>(),
+        any::<u64>(),
+        blooms(),
+        vec(logs(), 0..4),
+    )
+        .prop_map(
+            |(tx_type, success, cumulative_gas_used, bloom, logs)| Receipt {
+                tx_type,
+                success,
+                cumulative_gas_used,
+                bloom,
+                logs,
+                deposit_nonce: None,
+            },
+        )
+}
+
+pub fn withdrawals() -> impl Strategy<Value = Withdrawal> {
+    (any::<u64>(), any::<u64>(), addresses(), any::<u64>()).prop_map(
+        |(index, validator_index, address, amount)| Withdrawal {
+            index,
+            validator_index,
+            address,
+            amount,
+        },
+    )
+}
@@ -22,6 +22,8 @@ pub enum TxType {
     Legacy = 0,
     EIP2930 = 1,
     EIP1559 = 2,
+    /// OP-stack deposit transaction.
+    Deposit = 0x7E,
 }
 
 impl TryFrom<u8> for TxType {
@@ -31,6 +33,7 @@ impl TryFrom<u8> for TxType {
             0 => Ok(TxType::Legacy),
             1 => Ok(TxType::EIP2930),
             2 => Ok(TxType::EIP1559),
+            0x7E => Ok(TxType::Deposit),
             _ => Err(DecoderError::Custom("Invalid tx type")),
         }
     }
@@ -124,6 +127,16 @@ impl MessageSignature {
         }
     }
 
+    /// Placeholder signature of deposit transactions, which are not signed.
+    #[must_use]
+    pub const fn empty() -> Self {
+        Self {
+            odd_y_parity: false,
+            r: H256([0; 32]),
+            s: H256([0; 32]),
+        }
+    }
+
     #[must_use]
     pub fn malleable(&self) -> bool {
         const HALF_N: H256 = H256(hex!(
@@ -281,6 +294,23 @@ pub enum Message {
         input: Bytes,
         access_list: Vec<AccessListItem>,
     },
+    /// OP-stack deposit, derived from L1 rather than signed by its sender.
+    Deposit {
+        /// Uniquely identifies the origin of the deposit on L1.
+        source_hash: H256,
+        from: Address,
+        action: TransactionAction,
+        /// Minted to `from` on L2 before execution.
+        #[codec(compact)]
+        mint: U256,
+        #[codec(compact)]
+        value: U256,
+        #[codec(compact)]
+        gas_limit: u64,
+        is_system_tx: bool,
+        #[educe(Debug(method = "write_hex_string"))]
+        input: Bytes,
+    },
 }
 
 impl Message {
@@ -368,12 +398,71 @@ impl Message {
                 s.append_list(access_list);
                 s.out()
             }
+            // Not signed, this is the hash of the transaction itself.
+            Message::Deposit { .. } => encode_deposit(self),
         };
 
         H256::from_slice(Keccak256::digest(&msg.freeze()).as_slice())
     }
 }
 
+/// Type byte followed by the RLP list of the fields, deposits being unsigned.
+fn encode_deposit(message: &Message) -> BytesMut {
+    let mut b = BytesMut::with_capacity(1);
+    b.put_u8(TxType::Deposit as u8);
+    let mut s = RlpStream::new_with_buffer(b);
+    if let Message::Deposit {
+        source_hash,
+        from,
+        action,
+        mint,
+        value,
+        gas_limit,
+        is_system_tx,
+        input,
+    } = message
+    {
+        s.begin_list(8);
+        s.append(source_hash);
+        s.append(from);
+        s.append(action);
+        s.append(mint);
+        s.append(value);
+        s.append(gas_limit);
+        s.append(is_system_tx);
+        s.append(&input.as_ref());
+    }
+    s.out()
+}
+
+fn decode_deposit(rlp: &Rlp) -> Result<MessageWithSignature, DecoderError> {
+    if rlp.item_count()? != 8 {
+        return Err(DecoderError::RlpIncorrectListLen);
+    }
+
+    Ok(MessageWithSignature {
+        message: Message::Deposit {
+            source_hash: rlp.val_at(0)?,
+            from: rlp.val_at(1)?,
+            action: rlp.val_at(2)?,
+            mint: rlp.val_at(3)?,
+            value: rlp.val_at(4)?,
+            gas_limit: rlp.val_at(5)?,
+            is_system_tx: rlp.val_at(6)?,
+            input: rlp.val_at::<Vec<u8>>(7)?.into(),
+        },
+        signature: MessageSignature::empty(),
+    })
+}
+
+/// Zero and non-zero bytes of a signed transaction, for which OP-stack chains charge the cost
+/// of posting it to L1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RollupCostData {
+    pub zeroes: u64,
+    pub ones: u64,
+}
+
 #[derive(Clone, Debug, Deref, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
 pub struct MessageWithSignature {
     #[deref]
@@ -479,6 +568,14 @@ impl MessageWithSignature {
                     s.append(&s1.out());
                 }
             }
+            Message::Deposit { .. } => {
+                let out = encode_deposit(&self.message);
+                if standalone {
+                    s.append_raw(&*out.freeze(), 1);
+                } else {
+                    s.append(&out);
+                }
+            }
         }
     }
 }
@@ -550,6 +647,11 @@ impl MessageWithSignature {
             });
         }
 
+        if first == TxType::Deposit as u8 {
+            let s = slice.get(1..).ok_or(DecoderError::Custom("no tx body"))?;
+            return decode_deposit(&Rlp::new(s));
+        }
+
         let rlp = Rlp::new(slice);
         if rlp.is_list() {
             if rlp.item_count()? != 9 {
@@ -680,6 +782,10 @@ impl Decodable for MessageWithSignature {
             });
         }
 
+        if first == TxType::Deposit as u8 {
+            return decode_deposit(&Rlp::new(s));
+        }
+
         Err(DecoderError::Custom("invalid tx type"))
     }
 }
@@ -690,22 +796,30 @@ impl Message {
             Self::Legacy { .. } => TxType::Legacy,
             Self::EIP2930 { .. } => TxType::EIP2930,
             Self::EIP1559 { .. } => TxType::EIP1559,
+            Self::Deposit { .. } => TxType::Deposit,
         }
     }
 
+    pub const fn is_deposit(&self) -> bool {
+        matches!(self, Self::Deposit { .. })
+    }
+
     pub fn chain_id(&self) -> Option<ChainId> {
         match *self {
             Self::Legacy { chain_id, .. } => chain_id,
             Self::EIP2930 { chain_id, .. } => Some(chain_id),
             Self::EIP1559 { chain_id, .. } => Some(chain_id),
+            Self::Deposit { .. } => None,
         }
     }
 
+    /// Deposits have no nonce, the one of their sender is used on execution.
     pub const fn nonce(&self) -> u64 {
         match *self {
             Self::Legacy { nonce, .. }
             | Self::EIP2930 { nonce, .. }
             | Self::EIP1559 { nonce, .. } => nonce,
+            Self::Deposit { .. } => 0,
         }
     }
 
@@ -716,6 +830,7 @@ impl Message {
                 max_priority_fee_per_gas,
                 ..
             } => max_priority_fee_per_gas,
+            Self::Deposit { .. } => U256::ZERO,
         }
     }
 
@@ -725,6 +840,7 @@ impl Message {
             Self::EIP1559 {
                 max_fee_per_gas, ..
             } => max_fee_per_gas,
+            Self::Deposit { .. } => U256::ZERO,
         }
     }
 
@@ -732,7 +848,8 @@ impl Message {
         match *self {
             Self::Legacy { gas_limit, .. }
             | Self::EIP2930 { gas_limit, .. }
-            | Self::EIP1559 { gas_limit, .. } => gas_limit,
+            | Self::EIP1559 { gas_limit, .. }
+            | Self::Deposit { gas_limit, .. } => gas_limit,
         }
     }
 
@@ -740,7 +857,8 @@ impl Message {
         match *self {
             Self::Legacy { action, .. }
             | Self::EIP2930 { action, .. }
-            | Self::EIP1559 { action, .. } => action,
+            | Self::EIP1559 { action, .. }
+            | Self::Deposit { action, .. } => action,
         }
     }
 
@@ -748,7 +866,8 @@ impl Message {
         match *self {
             Self::Legacy { value, .. }
             | Self::EIP2930 { value, .. }
-            | Self::EIP1559 { value, .. } => value,
+            | Self::EIP1559 { value, .. }
+            | Self::Deposit { value, .. } => value,
         }
     }
 
@@ -756,20 +875,26 @@ impl Message {
         match self {
             Self::Legacy { input, .. }
             | Self::EIP2930 { input, .. }
-            | Self::EIP1559 { input, .. } => input,
+            | Self::EIP1559 { input, .. }
+            | Self::Deposit { input, .. } => input,
         }
     }
 
     pub const fn access_list(&self) -> Cow<'_, AccessList> {
         match self {
-            Self::Legacy { .. } => Cow::Owned(AccessList::new()),
+            Self::Legacy { .. } | Self::Deposit { .. } => Cow::Owned(AccessList::new()),
             Self::EIP2930 { access_list, .. } | Self::EIP1559 { access_list, .. } => {
                 Cow::Borrowed(access_list)
             }
         }
     }
 
+    /// Deposits pay for their gas on L1, so they are free on L2.
     pub(crate) fn priority_fee_per_gas(&self, base_fee_per_gas: U256) -> U256 {
+        if self.is_deposit() {
+            return U256::ZERO;
+        }
+
         assert!(self.max_fee_per_gas() >= base_fee_per_gas);
         min(
             self.max_priority_fee_per_gas(),
@@ -777,7 +902,11 @@ impl Message {
         )
     }
 
-    pub(crate) fn effective_gas_price(&self, base_fee_per_gas: U256) -> U256 {
+    pub fn effective_gas_price(&self, base_fee_per_gas: U256) -> U256 {
+        if self.is_deposit() {
+            return U256::ZERO;
+        }
+
         self.priority_fee_per_gas(base_fee_per_gas) + base_fee_per_gas
     }
 }
@@ -800,6 +929,10 @@ impl MessageWithSignature {
     }
 
     pub fn recover_sender(&self) -> anyhow::Result<Address> {
+        if let Message::Deposit { from, .. } = self.message {
+            return Ok(from);
+        }
+
         let mut sig = [0u8; 64];
 
         sig[..32].copy_from_slice(self.r().as_bytes());
@@ -815,12 +948,28 @@ impl MessageWithSignature {
         let address_slice = &Keccak256::digest(&public.serialize_uncompressed()[1..])[12..];
         Ok(Address::from_slice(address_slice))
     }
+
+    /// Deposits are not posted to L1 by the sequencer, so they cost nothing.
+    pub fn rollup_cost_data(&self) -> RollupCostData {
+        if self.is_deposit() {
+            return RollupCostData::default();
+        }
+
+        let encoded = self.trie_encode();
+        let zeroes = encoded.iter().filter(|&&b| b == 0).count() as u64;
+        RollupCostData {
+            zeroes,
+            ones: encoded.len() as u64 - zeroes,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::strategies::transactions;
     use hex_literal::hex;
+    use proptest::prelude::*;
 
     #[test]
     fn can_decode_raw_transaction() {
@@ -953,6 +1102,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn transaction_deposit() {
+        let from = hex!("deaddeaddeaddeaddeaddeaddeaddeaddead0001").into();
+        let tx = MessageWithSignature {
+            message: Message::Deposit {
+                source_hash: hex!(
+                    "a2d3bbd3e2a53e0e5f0b9d6e36e9b2d41d2b8f2e1c7e1a8b0a0e8e8b6e2d0a1f"
+                )
+                .into(),
+                from,
+                action: TransactionAction::Call(
+                    hex!("4200000000000000000000000000000000000015").into(),
+                ),
+                mint: U256::ZERO,
+                value: U256::ZERO,
+                gas_limit: 1_000_000,
+                is_system_tx: true,
+                input: hex!("015d8eb9").to_vec().into(),
+            },
+            signature: MessageSignature::empty(),
+        };
+
+        let encoded = tx.trie_encode();
+        assert_eq!(encoded[0], 0x7E);
+        assert_eq!(MessageWithSignature::trie_decode(&encoded).unwrap(), tx);
+        assert_eq!(
+            rlp::decode::<MessageWithSignature>(&rlp::encode(&tx)).unwrap(),
+            tx
+        );
+        assert_eq!(tx.hash(), tx.message.hash());
+        assert_eq!(tx.recover_sender().unwrap(), from);
+        assert_eq!(tx.rollup_cost_data(), RollupCostData::default());
+    }
+
     #[test]
     fn y_parity_and_chain_id() {
         for range in [0..27, 29..35] {
@@ -1037,4 +1220,12 @@ mod tests {
             38
         );
     }
+
+    proptest! {
+        #[test]
+        fn transaction_roundtrip(tx in transactions()) {
+            prop_assert_eq!(&rlp::decode::<MessageWithSignature>(&rlp::encode(&tx)).unwrap(), &tx);
+            prop_assert_eq!(&MessageWithSignature::trie_decode(&tx.trie_encode()).unwrap(), &tx);
+        }
+    }
 }
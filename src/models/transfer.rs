@@ -0,0 +1,24 @@
+use super::*;
+use parity_scale_codec::*;
+use serde::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "camelCase")]
+pub enum TransferKind {
+    /// Value sent along with a message call.
+    Call,
+    /// Endowment of a contract created by another contract.
+    Create,
+    /// Balance swept to the beneficiary of a self-destruct.
+    SelfDestruct,
+}
+
+/// Ether moved inside contract execution, i.e. not by the transaction itself.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "camelCase")]
+pub struct InternalTransfer {
+    pub kind: TransferKind,
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+}
@@ -0,0 +1,46 @@
+use super::*;
+use rlp_derive::*;
+use serde::*;
+
+/// Withdrawal of validator balance from the consensus layer, EIP-4895.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, RlpEncodable, RlpDecodable)]
+#[serde(rename_all = "camelCase")]
+pub struct Withdrawal {
+    pub index: u64,
+    pub validator_index: u64,
+    pub address: Address,
+    /// Amount in Gwei.
+    pub amount: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::strategies::withdrawals;
+    use hex_literal::hex;
+    use proptest::prelude::*;
+
+    #[test]
+    fn withdrawal_rlp() {
+        let withdrawal = Withdrawal {
+            index: 1,
+            validator_index: 2,
+            address: hex!("00000000000000000000000000000000000000ff").into(),
+            amount: 0x0400,
+        };
+        let encoded = rlp::encode(&withdrawal);
+
+        assert_eq!(
+            &encoded[..],
+            &hex!("da01029400000000000000000000000000000000000000ff820400")[..]
+        );
+        assert_eq!(rlp::decode::<Withdrawal>(&encoded).unwrap(), withdrawal);
+    }
+
+    proptest! {
+        #[test]
+        fn withdrawal_roundtrip(withdrawal in withdrawals()) {
+            prop_assert_eq!(rlp::decode::<Withdrawal>(&rlp::encode(&withdrawal)).unwrap(), withdrawal);
+        }
+    }
+}
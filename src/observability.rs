@@ -0,0 +1,198 @@
+//! Logging and tracing setup shared by the binaries.
+use crate::task_metrics::TaskMetricsLayer;
+use anyhow::Context;
+use clap::Parser;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use prometheus::{Encoder, TextEncoder};
+use std::{convert::Infallible, net::SocketAddr};
+use tracing::*;
+use tracing_subscriber::{
+    filter::filter_fn, fmt::format::debug_fn, prelude::*, reload, EnvFilter, Registry,
+};
+
+#[derive(Debug, Parser)]
+pub struct ObservabilityOpts {
+    /// Print logs as JSON objects, one per line.
+    #[clap(long = "log.json")]
+    pub log_json: bool,
+
+    /// Export spans to this OTLP/gRPC collector, e.g. `http://localhost:4317`.
+    #[clap(long = "otlp.endpoint")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Serve Prometheus metrics on this address, including the number of live spans and the
+    /// time spent in each poll of the ones the log filter lets through.
+    #[clap(long = "metrics.addr")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Serve tokio-console on this address. Needs a build with the `console` feature and
+    /// `RUSTFLAGS="--cfg tokio_unstable"`.
+    #[clap(long = "console.addr")]
+    pub console_addr: Option<SocketAddr>,
+}
+
+/// Handle to the installed subscriber. Dropping it flushes pending spans to the collector.
+pub struct Observability {
+    filter: reload::Handle<EnvFilter, Registry>,
+    otlp: bool,
+    console: bool,
+}
+
+impl Observability {
+    /// Replace the log filter, e.g. `martinez=info,martinez::stagedsync=debug`.
+    pub fn set_filter(&self, directives: &str) -> anyhow::Result<()> {
+        let filter = with_runtime_directives(EnvFilter::try_new(directives)?, self.console)?;
+        self.filter.reload(filter)?;
+
+        Ok(())
+    }
+}
+
+/// Targets of Tokio's own instrumentation, which only tokio-console consumes.
+fn is_runtime_target(target: &str) -> bool {
+    target.starts_with("tokio") || target.starts_with("runtime")
+}
+
+/// Let Tokio's instrumentation through `filter` if tokio-console is served.
+fn with_runtime_directives(filter: EnvFilter, console: bool) -> anyhow::Result<EnvFilter> {
+    Ok(if console {
+        filter
+            .add_directive("tokio=trace".parse()?)
+            .add_directive("runtime=trace".parse()?)
+    } else {
+        filter
+    })
+}
+
+impl Drop for Observability {
+    fn drop(&mut self) {
+        if self.otlp {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+async fn serve_metrics(addr: SocketAddr) -> anyhow::Result<()> {
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_| async {
+            let encoder = TextEncoder::new();
+            let mut buffer = Vec::new();
+            let response = match encoder.encode(&prometheus::gather(), &mut buffer) {
+                Ok(()) => Response::builder()
+                    .header(CONTENT_TYPE, encoder.format_type())
+                    .body(Body::from(buffer)),
+                Err(e) => Response::builder()
+                    .status(500)
+                    .body(Body::from(e.to_string())),
+            };
+            Ok::<_, Infallible>(response.unwrap())
+        }))
+    });
+
+    info!("Serving metrics on {}", addr);
+    Server::try_bind(&addr)?.serve(make_service).await?;
+
+    Ok(())
+}
+
+/// Install the global subscriber. `default_filter` applies unless `RUST_LOG` is set.
+///
+/// OTLP export and the metrics server are spawned on the current Tokio runtime, so it must be called from within one.
+pub fn init(
+    default_filter: &str,
+    ansi: bool,
+    opts: &ObservabilityOpts,
+) -> anyhow::Result<Observability> {
+    let env_filter = if std::env::var(EnvFilter::DEFAULT_ENV)
+        .unwrap_or_default()
+        .is_empty()
+    {
+        EnvFilter::new(default_filter)
+    } else {
+        EnvFilter::from_default_env()
+    };
+    let env_filter = with_runtime_directives(env_filter, opts.console_addr.is_some())?;
+    let (env_filter, filter) = reload::Layer::new(env_filter);
+
+    #[cfg(feature = "console")]
+    let console_layer = opts.console_addr.map(|addr| {
+        console_subscriber::ConsoleLayer::builder()
+            .server_addr(addr)
+            .spawn()
+    });
+    #[cfg(not(feature = "console"))]
+    let console_layer = match opts.console_addr {
+        Some(_) => anyhow::bail!("tokio-console needs a build with the `console` feature"),
+        None => None::<tracing_subscriber::layer::Identity>,
+    };
+
+    let otlp_layer = opts
+        .otlp_endpoint
+        .as_ref()
+        .map(|endpoint| {
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint.clone()),
+                )
+                .with_trace_config(opentelemetry::sdk::trace::config().with_resource(
+                    opentelemetry::sdk::Resource::new([opentelemetry::KeyValue::new(
+                        "service.name",
+                        env!("CARGO_PKG_NAME"),
+                    )]),
+                ))
+                .install_batch(opentelemetry::runtime::Tokio)
+                .context("failed to set up OTLP exporter")
+        })
+        .transpose()?
+        .map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+
+    let not_runtime = filter_fn(|metadata| !is_runtime_target(metadata.target()));
+    let json_layer = opts.log_json.then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_filter(not_runtime.clone())
+    });
+    let text_layer = (!opts.log_json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_target(false)
+            .with_ansi(ansi)
+            // Fields meant for the OpenTelemetry exporter, like `otel.name`, are left out.
+            .fmt_fields(debug_fn(|writer, field, value| match field.name() {
+                "message" => write!(writer, "{:?}", value),
+                name if name.starts_with("otel.") => Ok(()),
+                name => write!(writer, " {}={:?}", name, value),
+            }))
+            .with_filter(not_runtime)
+    });
+
+    tracing_subscriber::registry()
+        .with(env_filter)
+        .with(json_layer)
+        .with(text_layer)
+        .with(otlp_layer)
+        .with(opts.metrics_addr.map(|_| TaskMetricsLayer))
+        .with(console_layer)
+        .try_init()?;
+
+    if let Some(addr) = opts.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(addr).await {
+                error!("Metrics server failed: {:?}", e);
+            }
+        });
+    }
+
+    Ok(Observability {
+        filter,
+        otlp: opts.otlp_endpoint.is_some(),
+        console: opts.console_addr.is_some(),
+    })
+}
@@ -0,0 +1,192 @@
+use super::HeadSource;
+use crate::{
+    accessors::chain,
+    consensus,
+    crypto::keccak256,
+    kv::{mdbx::*, replica::ReadReplica},
+    models::*,
+    u256_to_h256,
+};
+use anyhow::format_err;
+use async_trait::async_trait;
+use jsonrpsee::{
+    core::{Error as RpcError, RpcResult},
+    proc_macros::rpc,
+    types::error::CallError,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::*;
+
+/// Most blocks covered by a `bor_getRootHash` checkpoint.
+const MAX_CHECKPOINT_LENGTH: u64 = 1 << 15;
+
+/// Bor extensions for validator queries. Proposer priorities are not tracked, so
+/// `bor_getCurrentProposer` is not served.
+#[rpc(server, namespace = "bor")]
+pub trait BorApi {
+    /// Producer that signed the block.
+    #[method(name = "getAuthor")]
+    async fn get_author(&self, block_number: BlockNumber) -> RpcResult<Option<Address>>;
+    #[method(name = "getSnapshot")]
+    async fn get_snapshot(&self, block_number: BlockNumber) -> RpcResult<Option<BorSnapshot>>;
+    /// Producers of the span of the block.
+    #[method(name = "getSigners")]
+    async fn get_signers(&self, block_number: BlockNumber) -> RpcResult<Option<Vec<Address>>>;
+    /// Producers of the span of the head block.
+    #[method(name = "getCurrentValidators")]
+    async fn get_current_validators(&self) -> RpcResult<Vec<BorValidator>>;
+    /// Merkle root of the blocks `start..=end` submitted in checkpoints to Ethereum.
+    #[method(name = "getRootHash")]
+    async fn get_root_hash(&self, start: u64, end: u64) -> RpcResult<String>;
+}
+
+#[derive(Debug, Serialize)]
+pub struct BorSnapshot {
+    pub number: U64,
+    pub hash: H256,
+    pub validators: Vec<BorValidator>,
+}
+
+pub struct BorApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    pub db: Arc<ReadReplica<E>>,
+    pub head: HeadSource,
+}
+
+impl<E> BorApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    fn read_snapshot<K: TransactionKind>(
+        &self,
+        tx: &MdbxTransaction<'_, K, E>,
+        block_number: BlockNumber,
+    ) -> anyhow::Result<Option<BorSnapshot>> {
+        if block_number > self.head.resolve(tx)? {
+            return Ok(None);
+        }
+
+        if let Some(block) = chain::block_id::resolve(tx, block_number)? {
+            if let Some(span) = chain::bor_span::read(tx, block.number)? {
+                return Ok(Some(BorSnapshot {
+                    number: block.number.0.into(),
+                    hash: block.hash,
+                    validators: span.selected_producers,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl<E> BorApiServer for BorApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    #[instrument(name = "bor_getAuthor", skip(self))]
+    async fn get_author(&self, block_number: BlockNumber) -> RpcResult<Option<Address>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        if block_number <= self.head.resolve(&tx)? {
+            if let Some(block) = chain::block_id::resolve(&tx, block_number)? {
+                if let Some(header) = chain::header::read(&tx, block.key())? {
+                    return Ok(Some(consensus::bor_signer(&header)?));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    #[instrument(name = "bor_getSnapshot", skip(self))]
+    async fn get_snapshot(&self, block_number: BlockNumber) -> RpcResult<Option<BorSnapshot>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        Ok(self.read_snapshot(&tx, block_number)?)
+    }
+
+    #[instrument(name = "bor_getSigners", skip(self))]
+    async fn get_signers(&self, block_number: BlockNumber) -> RpcResult<Option<Vec<Address>>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        Ok(self.read_snapshot(&tx, block_number)?.map(|snapshot| {
+            snapshot
+                .validators
+                .into_iter()
+                .map(|validator| validator.address)
+                .collect()
+        }))
+    }
+
+    #[instrument(name = "bor_getCurrentValidators", skip(self))]
+    async fn get_current_validators(&self) -> RpcResult<Vec<BorValidator>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        let head = self.head.resolve(&tx)?;
+        Ok(self
+            .read_snapshot(&tx, head)?
+            .ok_or_else(|| format_err!("No span found for head block {}", head))?
+            .validators)
+    }
+
+    #[instrument(name = "bor_getRootHash", skip(self))]
+    async fn get_root_hash(&self, start: u64, end: u64) -> RpcResult<String> {
+        if start > end || end - start >= MAX_CHECKPOINT_LENGTH {
+            return Err(RpcError::Call(CallError::InvalidParams(format_err!(
+                "invalid checkpoint range {}..={}, at most {} blocks",
+                start,
+                end,
+                MAX_CHECKPOINT_LENGTH
+            ))));
+        }
+
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        if BlockNumber(end) > self.head.resolve(&tx)? {
+            return Err(format_err!("Block {} not available yet", end).into());
+        }
+
+        let width = (end - start + 1).next_power_of_two() as usize;
+        let mut leaves = Vec::with_capacity(width);
+        for block_number in start..=end {
+            let block_number = BlockNumber(block_number);
+            let header = chain::header::read(
+                &tx,
+                BlockKey::new(
+                    block_number,
+                    chain::canonical_hash::read(&tx, block_number)?.ok_or_else(|| {
+                        format_err!("No canonical hash for block {}", block_number)
+                    })?,
+                ),
+            )?
+            .ok_or_else(|| format_err!("Header for block {} not found", block_number))?;
+
+            let mut leaf = Vec::with_capacity(128);
+            leaf.extend_from_slice(u256_to_h256(block_number.0.into()).as_bytes());
+            leaf.extend_from_slice(u256_to_h256(header.timestamp.into()).as_bytes());
+            leaf.extend_from_slice(header.transactions_root.as_bytes());
+            leaf.extend_from_slice(header.receipts_root.as_bytes());
+            leaves.push(keccak256(leaf));
+        }
+        leaves.resize(width, H256::zero());
+
+        while leaves.len() > 1 {
+            leaves = leaves
+                .chunks(2)
+                .map(|pair| keccak256([pair[0].as_bytes(), pair[1].as_bytes()].concat()))
+                .collect();
+        }
+
+        Ok(hex::encode(leaves[0]))
+    }
+}
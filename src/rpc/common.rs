@@ -0,0 +1,680 @@
+use crate::{
+    accessors::{chain, chain::last_forkchoice},
+    consensus,
+    execution::{
+        address::create_address,
+        analysis_cache::AnalysisCache,
+        optimism::L1Fee,
+        processor::ExecutionProcessor,
+        tracer::{CreationTracer, Tracer},
+    },
+    hexbytes,
+    kv::mdbx::*,
+    models::*,
+    Buffer,
+};
+use anyhow::format_err;
+use bytes::Bytes;
+use ethnum::U256;
+use jsonrpsee::{
+    core::{Error as RpcError, RpcResult},
+    types::error::CallError,
+};
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlockHeader {
+    pub hash: H256,
+    pub parent_hash: H256,
+    #[serde(rename = "sha3Uncles")]
+    pub ommers_hash: H256,
+    #[serde(rename = "miner")]
+    pub beneficiary: Address,
+    pub state_root: H256,
+    pub transactions_root: H256,
+    pub receipts_root: H256,
+    pub logs_bloom: Bloom,
+    pub difficulty: U256,
+    pub number: U64,
+    pub gas_limit: U64,
+    pub gas_used: U64,
+    pub timestamp: U64,
+    #[serde(with = "hexbytes")]
+    pub extra_data: Bytes,
+    pub mix_hash: H256,
+    pub nonce: H64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_fee_per_gas: Option<U256>,
+    /// Aura seal, in place of the mix hash and nonce.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<U64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<H520>,
+}
+
+impl RpcBlockHeader {
+    pub fn new(hash: H256, header: BlockHeader) -> Self {
+        Self {
+            hash,
+            parent_hash: header.parent_hash,
+            ommers_hash: header.ommers_hash,
+            beneficiary: header.beneficiary,
+            state_root: header.state_root,
+            transactions_root: header.transactions_root,
+            receipts_root: header.receipts_root,
+            logs_bloom: header.logs_bloom,
+            difficulty: header.difficulty,
+            number: header.number.0.into(),
+            gas_limit: header.gas_limit.into(),
+            gas_used: header.gas_used.into(),
+            timestamp: header.timestamp.into(),
+            extra_data: header.extra_data,
+            mix_hash: header.mix_hash,
+            nonce: header.nonce,
+            base_fee_per_gas: header.base_fee_per_gas,
+            step: header.aura_seal.map(|seal| seal.step.into()),
+            signature: header.aura_seal.map(|seal| seal.signature),
+        }
+    }
+}
+
+/// Signed transaction in its EIP-2718 envelope encoding, as it was broadcast.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RawTransaction(#[serde(with = "hexbytes")] pub Bytes);
+
+/// Transaction at `index` in block `block_hash`/`block_number`, canonical or not.
+pub(crate) fn read_block_transaction<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    block_hash: H256,
+    block_number: BlockNumber,
+    index: u64,
+) -> anyhow::Result<Option<MessageWithSignature>> {
+    if let Some(body) = chain::storage_body::read(tx, BlockKey::new(block_number, block_hash))? {
+        if index < body.tx_amount {
+            return Ok(chain::tx::read(tx, body.base_tx_id + index, 1)?.pop());
+        }
+    }
+
+    Ok(None)
+}
+
+pub(crate) fn read_transaction_by_hash<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    hash: H256,
+) -> anyhow::Result<Option<MessageWithSignature>> {
+    if let Some(block_number) = chain::tl::read(tx, hash)? {
+        if let Some(block) = chain::block_id::resolve(tx, block_number)? {
+            if let Some(body) = chain::storage_body::read(tx, block.key())? {
+                return Ok(
+                    chain::tx::read(tx, body.base_tx_id, body.tx_amount.try_into()?)?
+                        .into_iter()
+                        .find(|msg| msg.hash() == hash),
+                );
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcReceipt {
+    pub transaction_hash: H256,
+    pub transaction_index: U64,
+    pub block_hash: H256,
+    pub block_number: U64,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub cumulative_gas_used: U64,
+    pub gas_used: U64,
+    pub effective_gas_price: U256,
+    pub contract_address: Option<Address>,
+    /// Every contract the transaction deployed, including through CREATE and CREATE2 of other
+    /// contracts, in the order their creation completed.
+    #[serde(default)]
+    pub created_contracts: Vec<Address>,
+    pub logs: Vec<RpcLog>,
+    pub logs_bloom: Bloom,
+    pub status: U64,
+    #[serde(rename = "type")]
+    pub transaction_type: U64,
+    /// L1 data fee of OP-stack chains.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l1_gas_used: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l1_gas_price: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l1_fee: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l1_fee_scalar: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deposit_nonce: Option<U64>,
+}
+
+/// Block as stored, with the receipts of re-executing it on top of its parent state, up to the
+/// transaction replayed last.
+pub(crate) struct ReplayedBlock {
+    pub(crate) hash: H256,
+    pub(crate) header: BlockHeader,
+    pub(crate) transactions: Vec<MessageWithSignature>,
+    pub(crate) body: BlockBodyWithSenders,
+    pub(crate) receipts: Vec<Receipt>,
+    /// By transaction, on OP-stack chains.
+    pub(crate) l1_fees: Vec<Option<L1Fee>>,
+}
+
+/// Re-execute block `block_hash`/`block_number`, `None` if it is not known locally. With
+/// `last_transaction`, execution stops after that transaction, skipping the end of block
+/// changes, and `None` is returned if the block does not include it.
+pub(crate) fn replay_block<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    block_hash: H256,
+    block_number: BlockNumber,
+    last_transaction: Option<H256>,
+    tracer: &mut dyn Tracer,
+) -> anyhow::Result<Option<ReplayedBlock>> {
+    let (header, storage_body, body) = match (
+        chain::header::read(tx, BlockKey::new(block_number, block_hash))?,
+        chain::storage_body::read(tx, BlockKey::new(block_number, block_hash))?,
+        chain::block_body::read_with_senders(tx, BlockKey::new(block_number, block_hash))?,
+    ) {
+        (Some(header), Some(storage_body), Some(body)) => (header, storage_body, body),
+        _ => return Ok(None),
+    };
+    let transactions = chain::tx::read(
+        tx,
+        storage_body.base_tx_id,
+        storage_body.tx_amount.try_into()?,
+    )?;
+    let until = match last_transaction {
+        Some(hash) => match transactions.iter().position(|msg| msg.hash() == hash) {
+            Some(index) => Some(index + 1),
+            None => return Ok(None),
+        },
+        None => None,
+    };
+
+    let chain_spec =
+        chain::chain_config::read(tx)?.ok_or_else(|| format_err!("No chain config"))?;
+
+    let partial_header = PartialHeader::from(header.clone());
+    let mut buffer = Buffer::new(
+        tx,
+        BlockNumber(0),
+        Some(BlockNumber(block_number.0.saturating_sub(1))),
+    );
+    let mut analysis_cache = AnalysisCache::default();
+    let mut engine = consensus::engine_factory(chain_spec.clone())?;
+    let block_spec = chain_spec.collect_block_spec(block_number);
+
+    let mut processor = ExecutionProcessor::new(
+        &mut buffer,
+        Some(tracer),
+        &mut analysis_cache,
+        &mut *engine,
+        &partial_header,
+        &body,
+        &block_spec,
+    );
+    let receipts = match until {
+        Some(until) => processor.execute_block_up_to(until)?,
+        None => processor.execute_block_no_post_validation()?,
+    };
+    let l1_fees = processor.l1_fees().to_vec();
+
+    Ok(Some(ReplayedBlock {
+        hash: block_hash,
+        header,
+        transactions,
+        body,
+        receipts,
+        l1_fees,
+    }))
+}
+
+/// Receipts of every transaction of a replayed block, with the contracts each deployed.
+pub(crate) fn rpc_receipts(
+    block: &ReplayedBlock,
+    creations: impl Iterator<Item = (TxIndex, Vec<ContractCreation>)>,
+) -> Vec<RpcReceipt> {
+    let mut created_contracts = creations
+        .map(|(index, creations)| {
+            (
+                index.0 as usize,
+                creations
+                    .into_iter()
+                    .map(|creation| creation.address)
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut first_log_index = 0;
+    let mut prev_cumulative_gas_used = 0;
+    let mut out = Vec::with_capacity(block.receipts.len());
+    for (index, ((txn, msg), receipt)) in block
+        .body
+        .transactions
+        .iter()
+        .zip(&block.transactions)
+        .zip(&block.receipts)
+        .enumerate()
+    {
+        let l1_fee = block.l1_fees.get(index).copied().flatten();
+        out.push(RpcReceipt {
+            transaction_hash: msg.hash(),
+            transaction_index: (index as u64).into(),
+            block_hash: block.hash,
+            block_number: block.header.number.0.into(),
+            from: txn.sender,
+            to: match txn.action() {
+                TransactionAction::Call(to) => Some(to),
+                TransactionAction::Create => None,
+            },
+            cumulative_gas_used: receipt.cumulative_gas_used.into(),
+            gas_used: (receipt.cumulative_gas_used - prev_cumulative_gas_used).into(),
+            effective_gas_price: txn
+                .effective_gas_price(block.header.base_fee_per_gas.unwrap_or(U256::ZERO)),
+            contract_address: match txn.action() {
+                TransactionAction::Call(_) => None,
+                TransactionAction::Create => Some(create_address(
+                    txn.sender,
+                    receipt.deposit_nonce.unwrap_or_else(|| txn.nonce()),
+                )),
+            },
+            created_contracts: created_contracts.remove(&index).unwrap_or_default(),
+            logs: receipt
+                .logs
+                .iter()
+                .enumerate()
+                .map(|(i, log)| RpcLog {
+                    address: log.address,
+                    topics: log.topics.clone(),
+                    data: log.data.clone(),
+                    block_number: block.header.number.0.into(),
+                    block_hash: block.hash,
+                    transaction_index: (index as u64).into(),
+                    log_index: ((first_log_index + i) as u64).into(),
+                    removed: false,
+                })
+                .collect(),
+            logs_bloom: receipt.bloom,
+            status: u64::from(receipt.success).into(),
+            transaction_type: (receipt.tx_type as u64).into(),
+            l1_gas_used: l1_fee.map(|l1_fee| l1_fee.gas_used),
+            l1_gas_price: l1_fee.map(|l1_fee| l1_fee.gas_price),
+            l1_fee: l1_fee.map(|l1_fee| l1_fee.fee),
+            l1_fee_scalar: l1_fee.map(|l1_fee| l1_fee.scalar_decimal()),
+            deposit_nonce: receipt.deposit_nonce.map(From::from),
+        });
+
+        first_log_index += receipt.logs.len();
+        prev_cumulative_gas_used = receipt.cumulative_gas_used;
+    }
+
+    out
+}
+
+/// Receipts of the blocks replayed most recently, by block hash.
+pub struct ReceiptCache(Option<Mutex<LruCache<H256, Arc<Vec<RpcReceipt>>>>>);
+
+impl ReceiptCache {
+    /// Cache of the receipts of up to `blocks` blocks, none if 0.
+    pub fn new(blocks: usize) -> Self {
+        Self((blocks > 0).then(|| Mutex::new(LruCache::new(blocks))))
+    }
+}
+
+/// Receipt of the transaction with `hash`, obtained by re-executing its block on top of the
+/// parent state. `None` if the transaction is not known locally.
+///
+/// The receipts of the whole block are kept in `cache`. Without a cache, the block is only
+/// executed up to the transaction.
+pub(crate) fn read_transaction_receipt<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    hash: H256,
+    cache: &ReceiptCache,
+) -> anyhow::Result<Option<RpcReceipt>> {
+    let block = match chain::tl::read(tx, hash)? {
+        Some(block_number) => chain::block_id::resolve(tx, block_number)?,
+        None => None,
+    };
+    let block = match block {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+
+    if let Some(cache) = &cache.0 {
+        let cached = cache.lock().get(&block.hash).cloned();
+        let receipts = match cached {
+            Some(receipts) => receipts,
+            None => {
+                let mut creation_tracer = CreationTracer::default();
+                let replayed =
+                    match replay_block(tx, block.hash, block.number, None, &mut creation_tracer)? {
+                        Some(replayed) => replayed,
+                        None => return Ok(None),
+                    };
+                let receipts = Arc::new(rpc_receipts(&replayed, creation_tracer.into_creations()));
+                cache.lock().put(block.hash, receipts.clone());
+                receipts
+            }
+        };
+
+        return Ok(receipts
+            .iter()
+            .find(|receipt| receipt.transaction_hash == hash)
+            .cloned());
+    }
+
+    let mut creation_tracer = CreationTracer::default();
+    let replayed = match replay_block(
+        tx,
+        block.hash,
+        block.number,
+        Some(hash),
+        &mut creation_tracer,
+    )? {
+        Some(replayed) => replayed,
+        None => return Ok(None),
+    };
+
+    Ok(rpc_receipts(&replayed, creation_tracer.into_creations()).pop())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTransaction {
+    pub hash: H256,
+    /// Block and index, `None` while pending.
+    pub block_hash: Option<H256>,
+    pub block_number: Option<U64>,
+    pub transaction_index: Option<U64>,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub nonce: U64,
+    pub value: U256,
+    pub gas: U64,
+    /// Upstreams leave them out of legacy transactions.
+    #[serde(default)]
+    pub max_fee_per_gas: U256,
+    #[serde(default)]
+    pub max_priority_fee_per_gas: U256,
+    #[serde(with = "hexbytes")]
+    pub input: Bytes,
+    #[serde(rename = "type")]
+    pub transaction_type: U64,
+    /// Fields of OP-stack deposits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<H256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mint: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_system_tx: Option<bool>,
+}
+
+/// Transaction `hash` at `location`, the hash and number of its block and its index there,
+/// `None` while pending. `deposit_nonce` is the nonce of a deposit, recorded in its receipt.
+pub(crate) fn rpc_transaction(
+    txn: &MessageWithSender,
+    hash: H256,
+    location: Option<(H256, BlockNumber, usize)>,
+    deposit_nonce: Option<u64>,
+) -> RpcTransaction {
+    RpcTransaction {
+        hash,
+        block_hash: location.map(|(block_hash, _, _)| block_hash),
+        block_number: location.map(|(_, block_number, _)| block_number.0.into()),
+        transaction_index: location.map(|(_, _, index)| (index as u64).into()),
+        from: txn.sender,
+        to: match txn.action() {
+            TransactionAction::Call(to) => Some(to),
+            TransactionAction::Create => None,
+        },
+        nonce: deposit_nonce.unwrap_or_else(|| txn.nonce()).into(),
+        value: txn.value(),
+        gas: txn.gas_limit().into(),
+        max_fee_per_gas: txn.max_fee_per_gas(),
+        max_priority_fee_per_gas: txn.max_priority_fee_per_gas(),
+        input: txn.input().clone(),
+        transaction_type: (txn.tx_type() as u64).into(),
+        source_hash: match txn.message {
+            Message::Deposit { source_hash, .. } => Some(source_hash),
+            _ => None,
+        },
+        mint: match txn.message {
+            Message::Deposit { mint, .. } => Some(mint),
+            _ => None,
+        },
+        is_system_tx: match txn.message {
+            Message::Deposit { is_system_tx, .. } => Some(is_system_tx),
+            _ => None,
+        },
+    }
+}
+
+/// Transaction `hash` with its block, `None` if it is not known locally.
+pub(crate) fn read_rpc_transaction<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    hash: H256,
+    cache: &ReceiptCache,
+) -> anyhow::Result<Option<RpcTransaction>> {
+    let block = match chain::tl::read(tx, hash)? {
+        Some(block_number) => chain::block_id::resolve(tx, block_number)?,
+        None => None,
+    };
+    let block = match block {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+    let body = match chain::storage_body::read(tx, block.key())? {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+
+    let transactions = chain::tx::read(tx, body.base_tx_id, body.tx_amount.try_into()?)?;
+    let (index, msg) = match transactions
+        .into_iter()
+        .enumerate()
+        .find(|(_, msg)| msg.hash() == hash)
+    {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+
+    // Senders are only stored once the senders stage has run.
+    let sender = match chain::tx_sender::read(tx, block.key())?.get(index) {
+        Some(&sender) => sender,
+        None => msg.recover_sender()?,
+    };
+    // The nonce of a deposit is only recorded in its receipt.
+    let deposit_nonce = match msg.message {
+        Message::Deposit { .. } => read_transaction_receipt(tx, hash, cache)?
+            .and_then(|receipt| receipt.deposit_nonce)
+            .map(|nonce| nonce.as_u64()),
+        _ => None,
+    };
+
+    Ok(Some(rpc_transaction(
+        &MessageWithSender {
+            message: msg.message,
+            sender,
+        },
+        hash,
+        Some((block.hash, block.number, index)),
+        deposit_nonce,
+    )))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    #[serde(with = "hexbytes")]
+    pub data: Bytes,
+    pub block_number: U64,
+    pub block_hash: H256,
+    pub transaction_index: U64,
+    pub log_index: U64,
+    /// Set in `logs` subscriptions when the block left the canonical chain.
+    #[serde(default)]
+    pub removed: bool,
+}
+
+/// Single value or list of alternatives, as in the address and topics of log filters.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(v) => vec![v],
+            OneOrMany::Many(v) => v,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_block: Option<BlockParameter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_block: Option<BlockParameter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<H256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<OneOrMany<Address>>,
+    /// Alternatives for each topic position, `null` or empty for any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub topics: Vec<Option<OneOrMany<H256>>>,
+}
+
+/// Limits on the work of one `eth_getLogs` request.
+#[derive(Clone, Copy, Debug)]
+pub struct LogLimits {
+    pub max_blocks: Option<u64>,
+    pub max_results: Option<usize>,
+    pub max_topics: usize,
+}
+
+impl LogLimits {
+    pub fn new(max_blocks: u64, max_results: usize, max_topics: usize) -> Self {
+        Self {
+            max_blocks: (max_blocks > 0).then(|| max_blocks),
+            max_results: (max_results > 0).then(|| max_results),
+            max_topics,
+        }
+    }
+}
+
+/// Matcher of logs compiled from a [`LogFilter`], empty alternatives matching anything.
+pub(crate) struct LogMatcher {
+    addresses: Vec<Address>,
+    topics: Vec<Vec<H256>>,
+}
+
+impl LogMatcher {
+    pub(crate) fn new(filter: &LogFilter, limits: &LogLimits) -> RpcResult<Self> {
+        let addresses = filter
+            .address
+            .clone()
+            .map(OneOrMany::into_vec)
+            .unwrap_or_default();
+        let topics = filter
+            .topics
+            .iter()
+            .map(|topic| topic.clone().map(OneOrMany::into_vec).unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        if topics.len() > 4 {
+            return Err(RpcError::Call(CallError::InvalidParams(format_err!(
+                "{} topic positions, logs have at most 4",
+                topics.len()
+            ))));
+        }
+        let count = addresses.len() + topics.iter().map(Vec::len).sum::<usize>();
+        if count > limits.max_topics {
+            return Err(RpcError::Call(CallError::InvalidParams(format_err!(
+                "filter has {} addresses and topics, more than the limit of {}",
+                count,
+                limits.max_topics
+            ))));
+        }
+
+        Ok(Self { addresses, topics })
+    }
+
+    pub(crate) fn matches(&self, address: Address, topics: &[H256]) -> bool {
+        (self.addresses.is_empty() || self.addresses.contains(&address))
+            && self.topics.iter().enumerate().all(|(i, alternatives)| {
+                alternatives.is_empty()
+                    || topics
+                        .get(i)
+                        .map(|topic| alternatives.contains(topic))
+                        .unwrap_or(false)
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockTag {
+    Earliest,
+    Latest,
+    Pending,
+    Safe,
+    Finalized,
+}
+
+/// Number of the block the consensus client last marked safe or finalized.
+pub(crate) fn forkchoice_block<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    tag: BlockTag,
+) -> anyhow::Result<BlockNumber> {
+    let (key, name) = match tag {
+        BlockTag::Safe => (last_forkchoice::SAFE_BLOCK_HASH, "safe"),
+        BlockTag::Finalized => (last_forkchoice::FINALIZED_BLOCK_HASH, "finalized"),
+        _ => return Err(format_err!("{:?} is not a forkchoice block tag", tag)),
+    };
+
+    last_forkchoice::read_canonical_number(tx, key)?
+        .ok_or_else(|| format_err!("{} block not found", name))
+}
+
+/// Block number or tag, as accepted by the standard `eth_` methods.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BlockParameter {
+    Tag(BlockTag),
+    Number(U64),
+}
+
+/// Number of the block `block` refers to, `head` if none is given.
+pub(crate) fn resolve_block_parameter<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    head: BlockNumber,
+    block: Option<BlockParameter>,
+) -> anyhow::Result<BlockNumber> {
+    Ok(match block {
+        None
+        | Some(BlockParameter::Tag(BlockTag::Latest))
+        | Some(BlockParameter::Tag(BlockTag::Pending)) => head,
+        Some(BlockParameter::Tag(BlockTag::Earliest)) => BlockNumber(0),
+        Some(BlockParameter::Tag(tag @ (BlockTag::Safe | BlockTag::Finalized))) => {
+            forkchoice_block(tx, tag)?
+        }
+        Some(BlockParameter::Number(n)) => BlockNumber(n.as_u64()),
+    })
+}
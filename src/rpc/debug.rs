@@ -0,0 +1,396 @@
+use super::{trace::ensure_traceable, HeadSource};
+use crate::{
+    accessors::chain,
+    consensus,
+    execution::{
+        analysis_cache::AnalysisCache,
+        evm::StatusCode,
+        outcome::ExecutionOutcome,
+        processor::ExecutionProcessor,
+        tracer::{
+            AccountPrestate, CallKind, CallTree, CallTreeTracer, MessageKind, NoopTracer,
+            PrestateTracer, StructLog, StructLogger, StructLoggerConfig, Tracer,
+        },
+    },
+    hexbytes,
+    kv::{mdbx::*, replica::ReadReplica},
+    models::*,
+    u256_to_h256, Buffer,
+};
+use anyhow::format_err;
+use async_trait::async_trait;
+use bytes::Bytes;
+use ethnum::U256;
+use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::*;
+
+/// Transaction replayed on top of the state left by the ones before it in its block.
+struct ReplayedTransaction {
+    txn: MessageWithSender,
+    gas_used: u64,
+    outcome: ExecutionOutcome,
+    /// State it started from, of the accounts accessed when traced by the [`PrestateTracer`].
+    prestate: BTreeMap<Address, AccountPrestate>,
+}
+
+/// Replay transaction `hash` with `tracer`, `None` if it is not known locally. With
+/// `prestate`, the accounts it accessed are read before the transaction is executed.
+fn replay_transaction<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    hash: H256,
+    tracer: &mut dyn Tracer,
+    prestate: Option<&PrestateTracer>,
+) -> anyhow::Result<Option<ReplayedTransaction>> {
+    let block = match chain::tl::read(tx, hash)? {
+        Some(block_number) => chain::block_id::resolve(tx, block_number)?,
+        None => None,
+    };
+    let block = match block {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+    let (header, storage_body, body) = match (
+        chain::header::read(tx, block.key())?,
+        chain::storage_body::read(tx, block.key())?,
+        chain::block_body::read_with_senders(tx, block.key())?,
+    ) {
+        (Some(header), Some(storage_body), Some(body)) => (header, storage_body, body),
+        _ => return Ok(None),
+    };
+    let index = match chain::tx::read(
+        tx,
+        storage_body.base_tx_id,
+        storage_body.tx_amount.try_into()?,
+    )?
+    .iter()
+    .position(|msg| msg.hash() == hash)
+    {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+
+    let chain_spec =
+        chain::chain_config::read(tx)?.ok_or_else(|| format_err!("No chain config"))?;
+
+    let partial_header = PartialHeader::from(header);
+    let mut buffer = Buffer::new(
+        tx,
+        BlockNumber(0),
+        Some(BlockNumber(block.number.0.saturating_sub(1))),
+    );
+    let mut analysis_cache = AnalysisCache::default();
+    let mut engine = consensus::engine_factory(chain_spec.clone())?;
+    let block_spec = chain_spec.collect_block_spec(block.number);
+
+    let mut processor = ExecutionProcessor::new(
+        &mut buffer,
+        None,
+        &mut analysis_cache,
+        &mut *engine,
+        &partial_header,
+        &body,
+        &block_spec,
+    );
+    let receipts = processor.execute_block_up_to(index)?;
+    let prestate = match prestate {
+        Some(prestate) => prestate.read_prestate(processor.state())?,
+        None => BTreeMap::new(),
+    };
+
+    // Every transaction pays the beneficiary.
+    tracer.capture_account_read(partial_header.beneficiary);
+    processor.set_tracer(Some(tracer));
+    let (receipt, outcome) = processor.execute_block_transaction(index)?;
+
+    Ok(Some(ReplayedTransaction {
+        txn: body.transactions[index].clone(),
+        gas_used: receipt.cumulative_gas_used
+            - receipts
+                .last()
+                .map(|receipt| receipt.cumulative_gas_used)
+                .unwrap_or(0),
+        outcome,
+        prestate,
+    }))
+}
+
+/// Tracer to run `debug_traceTransaction` with, instead of logging every instruction.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TracerKind {
+    /// Messages sent by the transaction, nested by caller.
+    CallTracer,
+    /// Accounts accessed by the transaction, as they were before it.
+    PrestateTracer,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceOptions {
+    pub tracer: Option<TracerKind>,
+    #[serde(default)]
+    pub disable_stack: bool,
+    #[serde(default)]
+    pub disable_storage: bool,
+    #[serde(default)]
+    pub enable_memory: bool,
+}
+
+/// Instruction of a traced transaction, as logged by geth.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcStructLog {
+    pub pc: usize,
+    pub op: &'static str,
+    pub gas: u64,
+    pub gas_cost: u64,
+    /// Starting from 1 for the transaction itself.
+    pub depth: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack: Option<Vec<U256>>,
+    /// In 32-byte words.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<BTreeMap<H256, H256>>,
+}
+
+impl From<StructLog> for RpcStructLog {
+    fn from(log: StructLog) -> Self {
+        Self {
+            pc: log.pc,
+            op: log.op.name(),
+            gas: log.gas,
+            gas_cost: log.gas_cost,
+            depth: log.depth + 1,
+            stack: log.stack,
+            memory: log
+                .memory
+                .map(|memory| memory.chunks(32).map(hex::encode).collect()),
+            storage: log.storage.map(|storage| {
+                storage
+                    .into_iter()
+                    .map(|(key, value)| (u256_to_h256(key), u256_to_h256(value)))
+                    .collect()
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcExecutionResult {
+    pub gas: u64,
+    pub failed: bool,
+    /// Hex without prefix.
+    pub return_value: String,
+    pub struct_logs: Vec<RpcStructLog>,
+}
+
+/// Message of a traced transaction with its result, as traced by geth's `callTracer`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcCallTree {
+    #[serde(rename = "type")]
+    pub call_type: &'static str,
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub gas: U64,
+    pub gas_used: U64,
+    #[serde(with = "hexbytes")]
+    pub input: Bytes,
+    #[serde(with = "hexbytes")]
+    pub output: Bytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<RpcCallTree>,
+}
+
+impl From<CallTree> for RpcCallTree {
+    fn from(call: CallTree) -> Self {
+        Self {
+            call_type: match call.frame.kind {
+                MessageKind::Create => "CREATE",
+                MessageKind::Call { call_kind, .. } => match call_kind {
+                    CallKind::Call => "CALL",
+                    CallKind::CallCode => "CALLCODE",
+                    CallKind::DelegateCall => "DELEGATECALL",
+                    CallKind::StaticCall => "STATICCALL",
+                },
+            },
+            from: call.frame.from,
+            to: call.frame.to,
+            value: call.frame.value,
+            gas: call.frame.gas.into(),
+            gas_used: call.gas_used.into(),
+            input: call.frame.input,
+            error: (call.status_code != StatusCode::Success).then(|| {
+                ExecutionOutcome::new(call.status_code, Bytes::new(), call.gas_used).to_string()
+            }),
+            output: call.output,
+            calls: call
+                .calls
+                .into_iter()
+                .map(RpcCallTree::from)
+                .chain(call.self_destruct.map(|self_destruct| RpcCallTree {
+                    call_type: "SELFDESTRUCT",
+                    from: call.frame.to,
+                    to: self_destruct.beneficiary,
+                    value: self_destruct.balance,
+                    gas: U64::zero(),
+                    gas_used: U64::zero(),
+                    input: Bytes::new(),
+                    output: Bytes::new(),
+                    error: None,
+                    calls: vec![],
+                }))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcAccountPrestate {
+    pub balance: U256,
+    pub nonce: u64,
+    #[serde(with = "hexbytes", skip_serializing_if = "Bytes::is_empty")]
+    pub code: Bytes,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, H256>,
+}
+
+impl From<AccountPrestate> for RpcAccountPrestate {
+    fn from(account: AccountPrestate) -> Self {
+        Self {
+            balance: account.balance,
+            nonce: account.nonce,
+            code: account.code,
+            storage: account
+                .storage
+                .into_iter()
+                .map(|(key, value)| (u256_to_h256(key), u256_to_h256(value)))
+                .collect(),
+        }
+    }
+}
+
+/// Trace of transaction `hash` in the format of geth's `debug_traceTransaction`, `None` if it
+/// is not known locally.
+fn trace_transaction<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    hash: H256,
+    options: TraceOptions,
+) -> anyhow::Result<Option<serde_json::Value>> {
+    Ok(Some(match options.tracer {
+        None => {
+            let mut tracer = StructLogger::new(StructLoggerConfig {
+                disable_stack: options.disable_stack,
+                disable_storage: options.disable_storage,
+                enable_memory: options.enable_memory,
+            });
+            let replayed = match replay_transaction(tx, hash, &mut tracer, None)? {
+                Some(replayed) => replayed,
+                None => return Ok(None),
+            };
+
+            serde_json::to_value(RpcExecutionResult {
+                gas: replayed.gas_used,
+                failed: !replayed.outcome.is_success(),
+                return_value: hex::encode(replayed.outcome.output().cloned().unwrap_or_default()),
+                struct_logs: tracer.into_logs().into_iter().map(From::from).collect(),
+            })?
+        }
+        Some(TracerKind::CallTracer) => {
+            let mut tracer = CallTreeTracer::default();
+            let replayed = match replay_transaction(tx, hash, &mut tracer, None)? {
+                Some(replayed) => replayed,
+                None => return Ok(None),
+            };
+            let mut call = RpcCallTree::from(
+                tracer
+                    .into_trees()
+                    .pop()
+                    .flatten()
+                    .ok_or_else(|| format_err!("Transaction {:?} was not executed", hash))?,
+            );
+            // The transaction itself is charged intrinsic gas and refunded.
+            call.gas = replayed.txn.gas_limit().into();
+            call.gas_used = replayed.gas_used.into();
+
+            serde_json::to_value(call)?
+        }
+        Some(TracerKind::PrestateTracer) => {
+            // The accessed accounts are known after the transaction, their state is read
+            // before it on a second replay.
+            let mut tracer = PrestateTracer::default();
+            if replay_transaction(tx, hash, &mut tracer, None)?.is_none() {
+                return Ok(None);
+            }
+            let replayed = match replay_transaction(tx, hash, &mut NoopTracer, Some(&tracer))? {
+                Some(replayed) => replayed,
+                None => return Ok(None),
+            };
+
+            serde_json::to_value(
+                replayed
+                    .prestate
+                    .into_iter()
+                    .map(|(address, account)| (address, RpcAccountPrestate::from(account)))
+                    .collect::<BTreeMap<_, _>>(),
+            )?
+        }
+    }))
+}
+
+#[rpc(server, namespace = "debug")]
+pub trait DebugApi {
+    /// Replays transaction `hash` on top of the state before it, logging every instruction, or
+    /// with `callTracer` or `prestateTracer` as `tracer` in `options`.
+    #[method(name = "traceTransaction")]
+    async fn trace_transaction(
+        &self,
+        hash: H256,
+        options: Option<TraceOptions>,
+    ) -> RpcResult<serde_json::Value>;
+}
+
+pub struct DebugApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    pub db: Arc<ReadReplica<E>>,
+    pub head: HeadSource,
+}
+
+#[async_trait]
+impl<E> DebugApiServer for DebugApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    #[instrument(name = "debug_traceTransaction", skip(self))]
+    async fn trace_transaction(
+        &self,
+        hash: H256,
+        options: Option<TraceOptions>,
+    ) -> RpcResult<serde_json::Value> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        if let Some(block_number) = chain::tl::read(&tx, hash)? {
+            if block_number <= self.head.resolve(&tx)? {
+                ensure_traceable(&tx, block_number)?;
+
+                if let Some(trace) = trace_transaction(&tx, hash, options.unwrap_or_default())? {
+                    return Ok(trace);
+                }
+            }
+        }
+
+        Err(format_err!("Transaction {:?} not found", hash).into())
+    }
+}
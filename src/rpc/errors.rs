@@ -0,0 +1,118 @@
+use crate::{
+    accessors::{
+        prune,
+        prune::{DataPruned, PruneTarget},
+    },
+    execution::outcome::{error_code, ExecutionOutcome},
+    kv::mdbx::*,
+    models::*,
+};
+use jsonrpsee::{
+    core::{Error as RpcError, RpcResult},
+    types::error::{CallError, ErrorObject},
+};
+use serde::Serialize;
+
+/// Failed execution as a JSON-RPC error, carrying the revert data if there is any.
+pub(crate) fn execution_error(outcome: ExecutionOutcome) -> RpcError {
+    RpcError::Call(CallError::Custom(ErrorObject::owned(
+        outcome.error_code().unwrap_or(error_code::EXECUTION_FAILED),
+        outcome.to_string(),
+        outcome
+            .output()
+            .filter(|data| !data.is_empty())
+            .map(|data| format!("0x{}", hex::encode(data))),
+    )))
+}
+
+/// Error code of EIP-1474 for methods of the standard API that this node does not implement.
+pub const METHOD_NOT_SUPPORTED: i32 = -32004;
+
+/// `method` as known but unavailable, rather than the "method not found" of a typo.
+pub(crate) fn not_supported(method: &str) -> RpcError {
+    RpcError::Call(CallError::Custom(ErrorObject::owned(
+        METHOD_NOT_SUPPORTED,
+        format!(
+            "{} is not supported: Martinez does not produce blocks",
+            method
+        ),
+        None::<()>,
+    )))
+}
+
+/// Error code used by other clients for requests over the limits of the server.
+pub const LIMIT_EXCEEDED: i32 = -32005;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcRetryRange {
+    pub from_block: U64,
+    pub to_block: U64,
+}
+
+/// Request over a server limit, with the block range to retry with if there is one that fits.
+pub(crate) fn limit_exceeded(
+    message: String,
+    retry: Option<(BlockNumber, BlockNumber)>,
+) -> RpcError {
+    let message = match retry {
+        Some((from, to)) => format!("{}, retry with the range [{}, {}]", message, from, to),
+        None => message,
+    };
+    RpcError::Call(CallError::Custom(ErrorObject::owned(
+        LIMIT_EXCEEDED,
+        message,
+        retry.map(|(from, to)| RpcRetryRange {
+            from_block: from.0.into(),
+            to_block: to.0.into(),
+        }),
+    )))
+}
+
+/// Error code used by sequencers for conditional transactions whose preconditions are not met.
+pub const CONDITIONS_NOT_MET: i32 = -32003;
+
+pub(crate) fn conditions_not_met(reason: String) -> RpcError {
+    RpcError::Call(CallError::Custom(ErrorObject::owned(
+        CONDITIONS_NOT_MET,
+        format!("transaction conditions not met: {}", reason),
+        None::<()>,
+    )))
+}
+
+/// Error code proposed by EIP-4444 for history that is no longer served.
+pub const HISTORY_PRUNED: i32 = 4444;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcDataPruned {
+    pub target: String,
+    pub horizon: U64,
+    pub block_number: U64,
+    pub archive: Option<String>,
+}
+
+/// Pruned data as a JSON-RPC error, so that clients can tell it from data that never existed.
+pub(crate) fn pruned_error(e: DataPruned) -> RpcError {
+    RpcError::Call(CallError::Custom(ErrorObject::owned(
+        HISTORY_PRUNED,
+        e.to_string(),
+        Some(RpcDataPruned {
+            target: e.target.to_string(),
+            horizon: e.horizon.0.into(),
+            block_number: e.block_number.0.into(),
+            archive: e.archive,
+        }),
+    )))
+}
+
+pub(crate) fn ensure_available<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    target: PruneTarget,
+    block_number: BlockNumber,
+) -> RpcResult<()> {
+    match prune::pruned(tx, target, block_number)? {
+        Some(e) => Err(pruned_error(e)),
+        None => Ok(()),
+    }
+}
@@ -1,975 +1,1577 @@
+use super::{
+    common::{
+        forkchoice_block, read_block_transaction, read_rpc_transaction, read_transaction_by_hash,
+        read_transaction_receipt, resolve_block_parameter, rpc_transaction, BlockParameter,
+        BlockTag, LogFilter, LogLimits, LogMatcher, RawTransaction, ReceiptCache, RpcBlockHeader,
+        RpcLog, RpcReceipt, RpcTransaction,
+    },
+    errors::{
+        conditions_not_met, ensure_available, execution_error, limit_exceeded, not_supported,
+        pruned_error,
+    },
+    martinez::{sync_heads, SyncProgress, SyncStatus},
+    pubsub::{ChainNotification, ChainNotifications},
+    txpool::{
+        broadcast_transactions, latest_nonce, LocalTransactions, NonceStatus, SubmissionLimits,
+    },
+    HeadSource,
+};
 use crate::{
-    consensus::engine_factory,
-    execution::{analysis_cache::AnalysisCache, evm, processor::ExecutionProcessor},
-    kv::{
-        tables,
-        traits::{Transaction, KV},
+    accessors::{
+        chain, prune,
+        prune::{DataPruned, PruneTarget},
     },
+    consensus,
+    crypto::TrieEncode,
+    downloader::top_block_estimate::PublishedTopBlockEstimate,
+    execution::{
+        analysis_cache::AnalysisCache,
+        outcome::{error_code, ExecutionOutcome},
+        processor::ExecutionProcessor,
+        simulation,
+        simulation::{AccountOverride, BlockOverrides, BlockStateCalls, SimulatedCall},
+        tracer::Tracer,
+    },
+    h256_to_u256, hexbytes,
+    kv::{mdbx::*, replica::ReadReplica, tables},
     models::*,
-    res::chainspec::MAINNET,
-    stagedsync::stages::FINISH,
-    Buffer, InMemoryState, IntraBlockState,
+    stagedsync::stages::*,
+    trie, u256_to_h256, Buffer,
 };
+use anyhow::format_err;
 use async_trait::async_trait;
-use ethereum_jsonrpc::{
-    common::{Balance, CallData, ReturnData},
-    EthApiServer,
+use bytes::Bytes;
+use ethereum_interfaces::sentry::sentry_client::SentryClient;
+use ethnum::U256;
+use jsonrpsee::{
+    core::{client::ClientT, Error as RpcError, RpcResult},
+    http_client::HttpClient,
+    proc_macros::rpc,
+    types::{error::CallError, ParamsSer},
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use jsonrpsee::core::RpcResult;
-use std::sync::Arc;
+use tonic::transport::Channel;
+use tracing::*;
 
-pub struct EthApiServerImpl<DB>
-where
-    DB: KV,
-{
-    pub db: Arc<DB>,
+/// How long a filter is kept without being polled, as in other clients.
+const FILTER_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// What a filter collected since it was last polled.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum FilterChanges {
+    Hashes(Vec<H256>),
+    Transactions(Vec<RpcTransaction>),
 }
 
-#[async_trait]
-impl<DB> EthApiServer for EthApiServerImpl<DB>
-where
-    DB: KV,
-{
-    async fn block_number(&self) -> RpcResult<U64> {
-        Ok(U64::from(
-            self.db
-                .begin()
-                .await?
-                .get(tables::SyncStage, FINISH)
-                .await?
-                .unwrap_or(BlockNumber(0))
-                .0,
-        ))
-    }
+struct PendingTransactionFilter {
+    changes: FilterChanges,
+    last_poll: Instant,
+}
 
-    async fn call(
-        &self,
-        call_data: CallData,
-        block_number: U64,
-    ) -> RpcResult<ethereum_jsonrpc::common::ReturnData> {
-        let tx = &self.db.begin().await?;
-
-        let block_number = BlockNumber(block_number.as_u64());
-        let block_hash = crate::accessors::chain::canonical_hash::read(tx, block_number)
-            .await?
-            .unwrap();
-
-        let header = crate::accessors::chain::header::read(tx, block_hash, block_number)
-            .await?
-            .unwrap();
-
-        let mut state = Buffer::new(tx, BlockNumber(0), Some(block_number));
-        let mut analysis_cache = AnalysisCache::default();
-        let block_spec = MAINNET.collect_block_spec(block_number);
-
-        let value = call_data.value.unwrap_or(U256::ZERO);
-
-        let input = call_data.data.unwrap_or_default();
-
-        let sender = call_data.from.unwrap_or_else(Address::zero);
-
-        let msg_with_sender = MessageWithSender {
-            message: Message::Legacy {
-                chain_id: Some(ChainId(1)),
-                nonce: 0,
-                gas_price: Default::default(),
-                gas_limit: 0,
-                action: TransactionAction::Call(call_data.to),
-                value,
-                input,
-            },
-            sender,
-        };
+/// Filters of transactions admitted to the pool, polled through `eth_getFilterChanges`. Those
+/// not polled for [`FILTER_TIMEOUT`] are dropped.
+#[derive(Default)]
+pub struct PendingTransactionFilters(Mutex<(u64, HashMap<U64, PendingTransactionFilter>)>);
 
-        let gas = call_data.gas.map(|v| v.as_u64()).unwrap_or(100_000_000);
-
-        Ok(ReturnData(
-            evm::execute(
-                &mut IntraBlockState::new(&mut state),
-                None,
-                &mut analysis_cache,
-                &PartialHeader::from(header.clone()),
-                &block_spec,
-                &msg_with_sender,
-                gas,
-            )
-            .await?
-            .output_data,
-        ))
-    }
-
-    async fn estimate_gas(&self, call_data: CallData, block_number: U64) -> RpcResult<U64> {
-        let txn = self.db.begin().await?;
-        let block_number = BlockNumber(block_number.as_u64());
-        let hash = txn
-            .get(tables::CanonicalHeader, block_number)
-            .await?
-            .unwrap();
-        let header = txn
-            .get(tables::Header, (block_number, hash))
-            .await?
-            .unwrap();
-        let tx = MessageWithSender {
-            message: Message::Legacy {
-                chain_id: None,
-                nonce: 0,
-                gas_price: call_data
-                    .gas_price
-                    .map(|v| v.as_u64().as_u256())
-                    .unwrap_or(U256::ZERO),
-                gas_limit: call_data
-                    .gas
-                    .map(|gas| gas.as_u64())
-                    .unwrap_or_else(|| header.gas_limit),
-                action: TransactionAction::Call(call_data.to),
-                value: call_data.value.unwrap_or(U256::ZERO),
-                input: call_data.data.unwrap_or_default(),
+impl PendingTransactionFilters {
+    /// New filter, of full transactions if `full`, or else of their hashes.
+    pub fn install(&self, full: bool) -> U64 {
+        let (last_id, filters) = &mut *self.0.lock();
+        filters.retain(|_, filter| filter.last_poll.elapsed() < FILTER_TIMEOUT);
+
+        *last_id += 1;
+        let id = U64::from(*last_id);
+        filters.insert(
+            id,
+            PendingTransactionFilter {
+                changes: if full {
+                    FilterChanges::Transactions(vec![])
+                } else {
+                    FilterChanges::Hashes(vec![])
+                },
+                last_poll: Instant::now(),
             },
-            sender: call_data.from.unwrap_or_else(Address::zero),
+        );
+        id
+    }
+
+    pub fn uninstall(&self, id: U64) -> bool {
+        self.0.lock().1.remove(&id).is_some()
+    }
+
+    /// Transactions admitted since the last poll of filter `id`, `None` if there is no such
+    /// filter.
+    pub fn changes(&self, id: U64) -> Option<FilterChanges> {
+        let mut filters = self.0.lock();
+        let filter = filters.1.get_mut(&id)?;
+        filter.last_poll = Instant::now();
+        Some(match &mut filter.changes {
+            FilterChanges::Hashes(hashes) => FilterChanges::Hashes(std::mem::take(hashes)),
+            FilterChanges::Transactions(txs) => FilterChanges::Transactions(std::mem::take(txs)),
+        })
+    }
+
+    /// Record `txn` in every filter.
+    pub fn admit(&self, txn: &RpcTransaction) {
+        let (_, filters) = &mut *self.0.lock();
+        filters.retain(|_, filter| filter.last_poll.elapsed() < FILTER_TIMEOUT);
+        for filter in filters.values_mut() {
+            match &mut filter.changes {
+                FilterChanges::Hashes(hashes) => hashes.push(txn.hash),
+                FilterChanges::Transactions(txs) => txs.push(txn.clone()),
+            }
+        }
+    }
+}
+
+/// Blocks `eth_gasPrice` samples, back from the head.
+const GAS_PRICE_BLOCKS: u64 = 20;
+
+/// Cheapest transactions of each block sampled by `eth_gasPrice`.
+const GAS_PRICE_SAMPLES_PER_BLOCK: usize = 3;
+
+/// Percentile of the sampled tips that `eth_gasPrice` suggests.
+const GAS_PRICE_PERCENTILE: usize = 60;
+
+/// Most blocks one `eth_feeHistory` request may cover, more are cut off at the oldest end.
+const FEE_HISTORY_MAX_BLOCKS: u64 = 1024;
+
+/// Tip to pay to be included soon: a percentile of the tips of the cheapest transactions in the
+/// blocks up to `head`, leaving out those of the block producers themselves. `None` if these
+/// blocks have no transactions.
+fn suggest_tip<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    head: BlockNumber,
+) -> anyhow::Result<Option<U256>> {
+    let mut tips = Vec::new();
+    for number in head.0.saturating_sub(GAS_PRICE_BLOCKS - 1)..=head.0 {
+        let key = match chain::canonical_hash::read(tx, number)? {
+            Some(hash) => BlockKey::new(number, hash),
+            None => continue,
         };
-        let mut db = InMemoryState::default();
-        let mut state = IntraBlockState::new(&mut db);
-        let mut cache = AnalysisCache::default();
-        let spec = txn
-            .get(
-                tables::Config,
-                txn.get(tables::CanonicalHeader, BlockNumber(0))
-                    .await?
-                    .unwrap(),
-            )
-            .await?
-            .unwrap()
-            .collect_block_spec(block_number);
-        Ok(U64::from(
-            50_000_000
-                - evm::execute(
-                    &mut state,
-                    None,
-                    &mut cache,
-                    &PartialHeader::from(header),
-                    &spec,
-                    &tx,
-                    50_000_000,
-                )
-                .await?
-                .gas_left,
-        ))
+        let (header, body) = match (
+            chain::header::read(tx, key)?,
+            chain::block_body::read_without_senders(tx, key)?,
+        ) {
+            (Some(header), Some(body)) => (header, body),
+            _ => continue,
+        };
+        // Senders are only stored once the senders stage has run.
+        let senders = chain::tx_sender::read(tx, key)?;
+
+        let base_fee = header.base_fee_per_gas.unwrap_or(U256::ZERO);
+        let mut block_tips = body
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|&(index, msg)| {
+                !msg.is_deposit() && senders.get(index) != Some(&header.beneficiary)
+            })
+            .map(|(_, msg)| msg.effective_gas_price(base_fee) - base_fee)
+            .collect::<Vec<_>>();
+        block_tips.sort_unstable();
+        block_tips.truncate(GAS_PRICE_SAMPLES_PER_BLOCK);
+        tips.extend(block_tips);
     }
 
-    async fn get_balance(&self, address: Address, block_number: U64) -> RpcResult<Balance> {
-        Ok(crate::accessors::state::account::read(
-            &self.db.begin().await?,
-            address,
-            Some(BlockNumber(block_number.as_u64())),
-        )
-        .await?
-        .map(|acc| acc.balance)
-        .unwrap_or(U256::ZERO))
+    if tips.is_empty() {
+        return Ok(None);
     }
+    tips.sort_unstable();
+    Ok(Some(tips[(tips.len() - 1) * GAS_PRICE_PERCENTILE / 100]))
+}
 
-    async fn get_block_by_hash(
-        &self,
-        hash: H256,
-        full_tx_obj: bool,
-    ) -> RpcResult<ethereum_jsonrpc::common::Block> {
-        let txn = self.db.begin().await?;
-        let block_number = crate::accessors::chain::header_number::read(&txn, hash)
-            .await?
-            .unwrap();
-        let header = crate::accessors::chain::header::read(&txn, hash, block_number)
-            .await?
-            .unwrap();
-        let body =
-            crate::accessors::chain::block_body::read_without_senders(&txn, hash, block_number)
-                .await?
-                .unwrap();
-        let transactions: Vec<ethereum_jsonrpc::common::Transaction> = match full_tx_obj {
-            true => {
-                let senders =
-                    crate::accessors::chain::tx_sender::read(&txn, hash, block_number).await?;
-                body.transactions
-                    .into_iter()
-                    .zip(senders)
-                    .enumerate()
-                    .map(|(index, (tx, sender))| {
-                        ethereum_jsonrpc::common::Transaction::Full(Box::new(
-                            ethereum_jsonrpc::common::Tx {
-                                block_number: Some(U64::from(block_number.0)),
-                                block_hash: Some(hash),
-                                from: sender,
-                                gas: U64::from(tx.message.gas_limit()),
-                                gas_price: match tx.message {
-                                    Message::Legacy { gas_price, .. } => gas_price,
-                                    Message::EIP2930 { gas_price, .. } => gas_price,
-                                    Message::EIP1559 {
-                                        max_fee_per_gas, ..
-                                    } => max_fee_per_gas,
-                                },
-                                hash: tx.message.hash(),
-                                input: tx.message.input().clone(),
-                                nonce: U64::from(tx.message.nonce()),
-                                to: match tx.message.action() {
-                                    TransactionAction::Call(to) => Some(to),
-                                    TransactionAction::Create => None,
-                                },
-                                transaction_index: Some(U64::from(index as u64)),
-                                value: tx.message.value(),
-                                v: U64::from(tx.v()),
-                                r: tx.r(),
-                                s: tx.s(),
-                            },
-                        ))
-                    })
-                    .collect()
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeHistory {
+    pub oldest_block: U64,
+    /// Of every block, then of the block after the newest.
+    pub base_fee_per_gas: Vec<U256>,
+    pub gas_used_ratio: Vec<f64>,
+    /// Tips of every block at the requested percentiles of its gas.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reward: Option<Vec<Vec<U256>>>,
+}
+
+/// Tips at `percentiles` of the gas used by a block, from its transactions' tips and gas used.
+/// Each is the tip of the transaction that brings the gas used by the transactions tipping less
+/// up to the percentile.
+fn reward_percentiles(mut tips: Vec<(U256, u64)>, gas_used: u64, percentiles: &[f64]) -> Vec<U256> {
+    if tips.is_empty() {
+        return vec![U256::ZERO; percentiles.len()];
+    }
+    tips.sort_unstable_by_key(|&(tip, _)| tip);
+
+    let mut index = 0;
+    let mut sum = tips[0].1;
+    percentiles
+        .iter()
+        .map(|percentile| {
+            let threshold = (gas_used as f64 * percentile / 100.0) as u64;
+            while sum < threshold && index + 1 < tips.len() {
+                index += 1;
+                sum += tips[index].1;
             }
-            false => body
+            tips[index].0
+        })
+        .collect()
+}
+
+/// Fees of the `block_count` canonical blocks from `oldest`, with the tips paid at `percentiles`
+/// of their gas if given, for which the blocks are replayed.
+fn read_fee_history<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    oldest: BlockNumber,
+    block_count: u64,
+    percentiles: Option<&[f64]>,
+) -> anyhow::Result<FeeHistory> {
+    let chain_spec =
+        chain::chain_config::read(tx)?.ok_or_else(|| format_err!("No chain config"))?;
+    let mut replayer = percentiles
+        .map(|_| chain::receipts::Replayer::new(tx))
+        .transpose()?;
+
+    let mut history = FeeHistory {
+        oldest_block: oldest.0.into(),
+        base_fee_per_gas: Vec::with_capacity(block_count as usize + 1),
+        gas_used_ratio: Vec::with_capacity(block_count as usize),
+        reward: percentiles.map(|_| Vec::with_capacity(block_count as usize)),
+    };
+    for number in oldest.0..oldest.0 + block_count {
+        let hash = chain::canonical_hash::read(tx, number)?
+            .ok_or_else(|| format_err!("no canonical hash for block {}", number))?;
+        let key = BlockKey::new(number, hash);
+        let header = chain::header::read(tx, key)?
+            .ok_or_else(|| format_err!("no header for block {}", key))?;
+
+        let base_fee = header.base_fee_per_gas.unwrap_or(U256::ZERO);
+        history.base_fee_per_gas.push(base_fee);
+        history.gas_used_ratio.push(if header.gas_limit > 0 {
+            header.gas_used as f64 / header.gas_limit as f64
+        } else {
+            0.0
+        });
+
+        if let (Some(percentiles), Some(replayer), Some(reward)) =
+            (percentiles, &mut replayer, &mut history.reward)
+        {
+            let body = chain::block_body::read_without_senders(tx, key)?
+                .ok_or_else(|| format_err!("no body for block {}", key))?;
+            let receipts = replayer
+                .read(tx, key)?
+                .ok_or_else(|| format_err!("block {} not executed", key))?;
+
+            let mut cumulative_gas_used = 0;
+            let tips = body
                 .transactions
-                .into_iter()
-                .map(|tx| ethereum_jsonrpc::common::Transaction::Partial(tx.message.hash()))
-                .collect(),
-        };
+                .iter()
+                .zip(&receipts)
+                .map(|(msg, receipt)| {
+                    let gas_used = receipt.cumulative_gas_used - cumulative_gas_used;
+                    cumulative_gas_used = receipt.cumulative_gas_used;
+                    (
+                        msg.effective_gas_price(base_fee).saturating_sub(base_fee),
+                        gas_used,
+                    )
+                })
+                .collect();
+            reward.push(reward_percentiles(tips, header.gas_used, percentiles));
+        }
+
+        if number + 1 == oldest.0 + block_count {
+            history.base_fee_per_gas.push(
+                consensus::expected_base_fee_per_gas(
+                    chain_spec.consensus.eip1559_block,
+                    BlockNumber(number + 1),
+                    &header,
+                )
+                .unwrap_or(U256::ZERO),
+            );
+        }
+    }
+
+    Ok(history)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcAccount {
+    pub balance: U256,
+    pub nonce: U64,
+    pub code_hash: H256,
+    pub storage_root: H256,
+}
+
+/// Account at `block_number`, `None` if its storage root cannot be computed locally.
+///
+/// Storage roots are reverted from the hashed state, so blocks past it are not served.
+fn read_rpc_account<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    address: Address,
+    block_number: BlockNumber,
+) -> anyhow::Result<Option<RpcAccount>> {
+    match HASH_STATE.get_progress(tx)? {
+        Some(hashed_until) if block_number <= hashed_until => {}
+        _ => return Ok(None),
+    }
+
+    let account = crate::accessors::state::account::read(tx, address, Some(block_number))?
+        .unwrap_or_default();
+
+    Ok(Some(RpcAccount {
+        balance: account.balance,
+        nonce: account.nonce.into(),
+        code_hash: account.code_hash,
+        storage_root: trie::storage_root(tx, address, Some(block_number))?,
+    }))
+}
+
+/// Most storage slots the preconditions of a single conditional transaction may list.
+const MAX_KNOWN_ACCOUNT_SLOTS: usize = 1000;
+
+/// Expected state of an account: either its whole storage root or just some of its slots.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KnownAccount {
+    StorageRoot(H256),
+    Slots(BTreeMap<H256, H256>),
+}
 
-        let td = crate::accessors::chain::td::read(&txn, hash, block_number)
-            .await?
-            .unwrap();
-
-        Ok(ethereum_jsonrpc::common::Block {
-            number: Some(U64::from(block_number.0)),
-            hash: Some(hash),
-            parent_hash: header.parent_hash,
-            nonce: Some(header.nonce),
-            sha3_uncles: header.ommers_hash,
-            logs_bloom: Some(header.logs_bloom),
-            transactions_root: header.transactions_root,
-            state_root: header.state_root,
-            receipts_root: header.receipts_root,
-            miner: header.beneficiary,
-            difficulty: header.difficulty,
-            total_difficulty: td,
-            extra_data: header.extra_data,
-            size: U64::zero(),
-            gas_limit: U64::from(header.gas_limit),
-            gas_used: U64::from(header.gas_used),
-            timestamp: U64::from(header.timestamp),
-            transactions,
-            uncles: body.ommers.into_iter().map(|uncle| uncle.hash()).collect(),
+/// Preconditions of `eth_sendRawTransactionConditional`, all of which must hold at the head.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionConditions {
+    #[serde(default)]
+    pub known_accounts: BTreeMap<Address, KnownAccount>,
+    pub block_number_min: Option<U64>,
+    pub block_number_max: Option<U64>,
+    pub timestamp_min: Option<U64>,
+    pub timestamp_max: Option<U64>,
+}
+
+/// Check `conditions` against the state after `head`, the reason if any of them is not met.
+fn check_conditions<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    conditions: &TransactionConditions,
+    head: BlockNumber,
+) -> anyhow::Result<Option<String>> {
+    let slots = conditions
+        .known_accounts
+        .values()
+        .map(|known| match known {
+            KnownAccount::StorageRoot(_) => 1,
+            KnownAccount::Slots(slots) => slots.len(),
         })
+        .sum::<usize>();
+    if slots > MAX_KNOWN_ACCOUNT_SLOTS {
+        return Ok(Some(format!(
+            "too many known account slots: {} > {}",
+            slots, MAX_KNOWN_ACCOUNT_SLOTS
+        )));
     }
-    async fn get_block_by_number(
-        &self,
-        block_number: U64,
-        full_tx_obj: bool,
-    ) -> RpcResult<ethereum_jsonrpc::common::Block> {
-        let txn = self.db.begin().await?;
-        let block_number = BlockNumber(block_number.as_u64());
-        let hash = crate::accessors::chain::canonical_hash::read(&txn, block_number)
-            .await?
-            .unwrap();
-        let header = crate::accessors::chain::header::read(&txn, hash, block_number)
-            .await?
-            .unwrap();
-        let body =
-            crate::accessors::chain::block_body::read_without_senders(&txn, hash, block_number)
-                .await?
-                .unwrap();
-        let transactions: Vec<ethereum_jsonrpc::common::Transaction> = match full_tx_obj {
-            true => {
-                let senders =
-                    crate::accessors::chain::tx_sender::read(&txn, hash, block_number).await?;
-                body.transactions
-                    .into_iter()
-                    .zip(senders)
-                    .enumerate()
-                    .map(|(index, (tx, sender))| {
-                        ethereum_jsonrpc::common::Transaction::Full(Box::new(
-                            ethereum_jsonrpc::common::Tx {
-                                block_number: Some(U64::from(block_number.0)),
-                                block_hash: Some(hash),
-                                from: sender,
-                                gas: U64::from(tx.message.gas_limit()),
-                                gas_price: match tx.message {
-                                    Message::Legacy { gas_price, .. } => gas_price,
-                                    Message::EIP2930 { gas_price, .. } => gas_price,
-                                    Message::EIP1559 {
-                                        max_fee_per_gas, ..
-                                    } => max_fee_per_gas,
-                                },
-                                hash: tx.message.hash(),
-                                input: tx.message.input().clone(),
-                                nonce: U64::from(tx.message.nonce()),
-                                to: match tx.message.action() {
-                                    TransactionAction::Call(to) => Some(to),
-                                    TransactionAction::Create => None,
-                                },
-                                transaction_index: Some(U64::from(index as u64)),
-                                value: tx.message.value(),
-                                v: U64::from(tx.v()),
-                                r: tx.r(),
-                                s: tx.s(),
-                            },
+
+    let number = U64::from(head.0);
+    if let Some(min) = conditions.block_number_min {
+        if number < min {
+            return Ok(Some(format!(
+                "block number {} below minimum {}",
+                number, min
+            )));
+        }
+    }
+    if let Some(max) = conditions.block_number_max {
+        if number > max {
+            return Ok(Some(format!(
+                "block number {} above maximum {}",
+                number, max
+            )));
+        }
+    }
+
+    if conditions.timestamp_min.is_some() || conditions.timestamp_max.is_some() {
+        let header = chain::block_id::resolve(tx, head)?
+            .map(|block| chain::header::read(tx, block.key()))
+            .transpose()?
+            .flatten()
+            .ok_or_else(|| format_err!("Header of head block {} not found", head))?;
+        let timestamp = U64::from(header.timestamp);
+        if let Some(min) = conditions.timestamp_min {
+            if timestamp < min {
+                return Ok(Some(format!(
+                    "timestamp {} below minimum {}",
+                    timestamp, min
+                )));
+            }
+        }
+        if let Some(max) = conditions.timestamp_max {
+            if timestamp > max {
+                return Ok(Some(format!(
+                    "timestamp {} above maximum {}",
+                    timestamp, max
+                )));
+            }
+        }
+    }
+
+    for (&address, known) in &conditions.known_accounts {
+        match known {
+            KnownAccount::StorageRoot(expected) => {
+                match HASH_STATE.get_progress(tx)? {
+                    Some(hashed_until) if head <= hashed_until => {}
+                    _ => {
+                        return Err(format_err!(
+                            "Storage roots are not available at block {}",
+                            head
                         ))
-                    })
-                    .collect()
+                    }
+                }
+
+                let root = trie::storage_root(tx, address, Some(head))?;
+                if root != *expected {
+                    return Ok(Some(format!(
+                        "storage root of {:?} is {:?}, expected {:?}",
+                        address, root, expected
+                    )));
+                }
             }
-            false => body
-                .transactions
+            KnownAccount::Slots(slots) => {
+                for (&location, &expected) in slots {
+                    let value = crate::accessors::state::storage::read(
+                        tx,
+                        address,
+                        h256_to_u256(location),
+                        Some(head),
+                    )?;
+                    if u256_to_h256(value) != expected {
+                        return Ok(Some(format!(
+                            "storage slot {:?} of {:?} is {:?}, expected {:?}",
+                            location,
+                            address,
+                            u256_to_h256(value),
+                            expected
+                        )));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRequest {
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub gas: Option<U64>,
+    pub gas_price: Option<U256>,
+    pub value: Option<U256>,
+    #[serde(default, with = "hexbytes")]
+    pub data: Bytes,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CallOutput(#[serde(with = "hexbytes")] pub Bytes);
+
+/// Execute `call` on top of the state after `block_number`, `None` if the block is not known locally.
+pub(crate) fn call_at_block<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    call: CallRequest,
+    block_number: BlockNumber,
+    tracer: Option<&mut dyn Tracer>,
+) -> anyhow::Result<Option<ExecutionOutcome>> {
+    let header = if let Some(block) = chain::block_id::resolve(tx, block_number)? {
+        chain::header::read(tx, block.key())?
+    } else {
+        None
+    };
+    let header = match header {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    let chain_spec =
+        chain::chain_config::read(tx)?.ok_or_else(|| format_err!("No chain config"))?;
+
+    let sender = call.from.unwrap_or_else(Address::zero);
+    let nonce = crate::accessors::state::account::read(tx, sender, Some(block_number))?
+        .map(|acc| acc.nonce)
+        .unwrap_or(0);
+    let txn = MessageWithSender {
+        message: Message::Legacy {
+            chain_id: None,
+            nonce,
+            gas_price: call.gas_price.unwrap_or(U256::ZERO),
+            gas_limit: call.gas.map(|gas| gas.as_u64()).unwrap_or(header.gas_limit),
+            action: call
+                .to
+                .map(TransactionAction::Call)
+                .unwrap_or(TransactionAction::Create),
+            value: call.value.unwrap_or(U256::ZERO),
+            input: call.data,
+        },
+        sender,
+    };
+
+    let mut header = PartialHeader::from(header);
+    // Same as geth: calls without gas price are not subject to the base fee.
+    if call.gas_price.is_none() {
+        header.base_fee_per_gas = None;
+    }
+
+    let mut buffer = Buffer::new(tx, BlockNumber(0), Some(block_number));
+    let mut analysis_cache = AnalysisCache::default();
+    let mut engine = consensus::engine_factory(chain_spec.clone())?;
+    let block_spec = chain_spec.collect_block_spec(block_number);
+    let block = BlockBodyWithSenders::default();
+
+    // A processor of its own, dropped with the changes of the call.
+    Ok(Some(
+        ExecutionProcessor::new(
+            &mut buffer,
+            tracer,
+            &mut analysis_cache,
+            &mut *engine,
+            &header,
+            &block,
+            &block_spec,
+        )
+        .call(&txn)?,
+    ))
+}
+
+/// Most blocks a single `eth_simulateV1` request may simulate, same as geth.
+const MAX_SIMULATED_BLOCKS: usize = 256;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct HexData(#[serde(with = "hexbytes")] pub Bytes);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcAccountOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<U64>,
+    pub code: Option<HexData>,
+    pub state: Option<HashMap<H256, H256>>,
+    pub state_diff: Option<HashMap<H256, H256>>,
+}
+
+impl From<RpcAccountOverride> for AccountOverride {
+    fn from(account: RpcAccountOverride) -> Self {
+        let slots = |slots: HashMap<H256, H256>| {
+            slots
                 .into_iter()
-                .map(|tx| ethereum_jsonrpc::common::Transaction::Partial(tx.message.hash()))
-                .collect(),
+                .map(|(location, value)| (h256_to_u256(location), h256_to_u256(value)))
+                .collect()
         };
 
-        let td = crate::accessors::chain::td::read(&txn, hash, block_number)
-            .await?
-            .unwrap();
-
-        Ok(ethereum_jsonrpc::common::Block {
-            number: Some(U64::from(block_number.0)),
-            hash: Some(hash),
-            parent_hash: header.parent_hash,
-            nonce: Some(header.nonce),
-            sha3_uncles: header.ommers_hash,
-            logs_bloom: Some(header.logs_bloom),
-            transactions_root: header.transactions_root,
-            state_root: header.state_root,
-            receipts_root: header.receipts_root,
-            miner: header.beneficiary,
-            difficulty: header.difficulty,
-            total_difficulty: td,
-            extra_data: header.extra_data,
-            size: U64::zero(),
-            gas_limit: U64::from(header.gas_limit),
-            gas_used: U64::from(header.gas_used),
-            timestamp: U64::from(header.timestamp),
-            transactions,
-            uncles: body.ommers.into_iter().map(|uncle| uncle.hash()).collect(),
-        })
+        Self {
+            balance: account.balance,
+            nonce: account.nonce.map(|nonce| nonce.as_u64()),
+            code: account.code.map(|code| code.0),
+            state: account.state.map(slots),
+            state_diff: account.state_diff.map(slots).unwrap_or_default(),
+        }
     }
+}
 
-    async fn get_block_tx_count_by_hash(&self, hash: H256) -> RpcResult<U64> {
-        let txn = self.db.begin().await?;
-        Ok(U64::from(
-            crate::accessors::chain::block_body::read_without_senders(
-                &txn,
-                hash,
-                crate::accessors::chain::header_number::read(&txn, hash)
-                    .await?
-                    .unwrap(),
-            )
-            .await?
-            .unwrap()
-            .transactions
-            .len(),
-        ))
-    }
-
-    async fn get_block_tx_count_by_number(&self, block_number: U64) -> RpcResult<U64> {
-        let txn = self.db.begin().await?;
-        Ok(U64::from(
-            crate::accessors::chain::block_body::read_without_senders(
-                &txn,
-                crate::accessors::chain::canonical_hash::read(
-                    &txn,
-                    BlockNumber(block_number.as_u64()),
-                )
-                .await?
-                .unwrap(),
-                BlockNumber(block_number.as_u64()),
-            )
-            .await?
-            .unwrap()
-            .transactions
-            .len(),
-        ))
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlockOverrides {
+    pub number: Option<U64>,
+    pub time: Option<U64>,
+    pub gas_limit: Option<U64>,
+    pub fee_recipient: Option<Address>,
+    pub prev_randao: Option<H256>,
+    pub base_fee_per_gas: Option<U256>,
+}
+
+impl From<RpcBlockOverrides> for BlockOverrides {
+    fn from(overrides: RpcBlockOverrides) -> Self {
+        Self {
+            number: overrides.number.map(|number| BlockNumber(number.as_u64())),
+            timestamp: overrides.time.map(|time| time.as_u64()),
+            gas_limit: overrides.gas_limit.map(|gas_limit| gas_limit.as_u64()),
+            beneficiary: overrides.fee_recipient,
+            mix_hash: overrides.prev_randao,
+            base_fee_per_gas: overrides.base_fee_per_gas,
+        }
     }
+}
 
-    async fn get_code(
-        &self,
-        address: Address,
-        block_number: U64,
-    ) -> RpcResult<ethereum_jsonrpc::common::Code> {
-        let txn = self.db.begin().await?;
-        let account = crate::accessors::state::account::read(
-            &txn,
-            address,
-            Some(BlockNumber(block_number.as_u64())),
-        )
-        .await?
-        .unwrap();
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcAccessListItem {
+    pub address: Address,
+    pub storage_keys: Vec<H256>,
+}
 
-        Ok(ethereum_jsonrpc::common::Code(
-            txn.get(tables::Code, account.code_hash).await?.unwrap(),
-        ))
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSimulatedCall {
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub gas: Option<U64>,
+    pub gas_price: Option<U256>,
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    pub value: Option<U256>,
+    pub nonce: Option<U64>,
+    #[serde(default, alias = "input", with = "hexbytes")]
+    pub data: Bytes,
+    #[serde(default)]
+    pub access_list: Vec<RpcAccessListItem>,
+}
+
+impl From<RpcSimulatedCall> for SimulatedCall {
+    fn from(call: RpcSimulatedCall) -> Self {
+        Self {
+            sender: call.from.unwrap_or_else(Address::zero),
+            nonce: call.nonce.map(|nonce| nonce.as_u64()),
+            gas_limit: call.gas.map(|gas| gas.as_u64()),
+            gas_price: call.gas_price,
+            max_fee_per_gas: call.max_fee_per_gas,
+            max_priority_fee_per_gas: call.max_priority_fee_per_gas,
+            action: call.to,
+            value: call.value.unwrap_or(U256::ZERO),
+            input: call.data,
+            access_list: call
+                .access_list
+                .into_iter()
+                .map(|item| AccessListItem {
+                    address: item.address,
+                    slots: item.storage_keys,
+                })
+                .collect(),
+        }
     }
+}
 
-    async fn get_storage_at(
-        &self,
-        address: Address,
-        key: U256,
-        block_number: U64,
-    ) -> RpcResult<ethereum_jsonrpc::common::StorageData> {
-        Ok(crate::accessors::state::storage::read(
-            &self.db.begin().await?,
-            address,
-            key,
-            Some(BlockNumber(block_number.as_u64())),
-        )
-        .await?)
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlockStateCalls {
+    #[serde(default)]
+    pub block_overrides: RpcBlockOverrides,
+    #[serde(default)]
+    pub state_overrides: HashMap<Address, RpcAccountOverride>,
+    #[serde(default)]
+    pub calls: Vec<RpcSimulatedCall>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatePayload {
+    pub block_state_calls: Vec<RpcBlockStateCalls>,
+    /// Check calls like transactions of a real block: nonces, balances and fees.
+    #[serde(default)]
+    pub validation: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcCallError {
+    pub code: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSimulatedCallResult {
+    #[serde(with = "hexbytes")]
+    pub return_data: Bytes,
+    pub logs: Vec<RpcLog>,
+    pub gas_used: U64,
+    pub status: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcCallError>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSimulatedBlock {
+    #[serde(flatten)]
+    pub header: RpcBlockHeader,
+    pub calls: Vec<RpcSimulatedCallResult>,
+}
+
+impl From<simulation::SimulatedBlock> for RpcSimulatedBlock {
+    fn from(block: simulation::SimulatedBlock) -> Self {
+        let block_number = block.header.number;
+        let mut log_index = 0_u64;
+        let calls = block
+            .calls
+            .into_iter()
+            .enumerate()
+            .map(|(transaction_index, call)| {
+                let logs = call
+                    .logs
+                    .into_iter()
+                    .map(|log| {
+                        log_index += 1;
+                        RpcLog {
+                            address: log.address,
+                            topics: log.topics,
+                            data: log.data,
+                            block_number: block_number.0.into(),
+                            block_hash: block.hash,
+                            transaction_index: (transaction_index as u64).into(),
+                            log_index: (log_index - 1).into(),
+                            removed: false,
+                        }
+                    })
+                    .collect();
+
+                RpcSimulatedCallResult {
+                    return_data: call.outcome.output().cloned().unwrap_or_default(),
+                    logs,
+                    gas_used: call.outcome.gas_used().unwrap_or(0).into(),
+                    status: u64::from(call.outcome.is_success()).into(),
+                    error: (!call.outcome.is_success()).then(|| RpcCallError {
+                        code: call
+                            .outcome
+                            .error_code()
+                            .unwrap_or(error_code::EXECUTION_FAILED),
+                        message: call.outcome.to_string(),
+                        data: call
+                            .outcome
+                            .output()
+                            .filter(|data| !data.is_empty())
+                            .map(|data| format!("0x{}", hex::encode(data))),
+                    }),
+                }
+            })
+            .collect();
+
+        Self {
+            header: RpcBlockHeader::new(block.hash, block.header),
+            calls,
+        }
     }
+}
+
+/// Simulate `payload` on top of `block_number`, `None` if the block is not known locally.
+fn simulate_at_block<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    payload: SimulatePayload,
+    block_number: BlockNumber,
+) -> anyhow::Result<Option<Vec<RpcSimulatedBlock>>> {
+    let parent = match chain::block_id::resolve(tx, block_number)? {
+        Some(block) => chain::header::read(tx, block.key())?,
+        None => None,
+    };
+    let parent = match parent {
+        Some(parent) => parent,
+        None => return Ok(None),
+    };
+
+    let chain_spec =
+        chain::chain_config::read(tx)?.ok_or_else(|| format_err!("No chain config"))?;
+
+    let blocks = payload
+        .block_state_calls
+        .into_iter()
+        .map(|block| BlockStateCalls {
+            block_overrides: block.block_overrides.into(),
+            state_overrides: block
+                .state_overrides
+                .into_iter()
+                .map(|(address, account)| (address, account.into()))
+                .collect(),
+            calls: block.calls.into_iter().map(From::from).collect(),
+        })
+        .collect();
 
-    async fn get_tx_by_block_hash_and_index(
+    // Never flushed: simulated changes only live as long as the request.
+    let mut buffer = Buffer::new(tx, BlockNumber(0), Some(block_number));
+
+    Ok(Some(
+        simulation::simulate(&mut buffer, &chain_spec, parent, blocks, payload.validation)?
+            .into_iter()
+            .map(From::from)
+            .collect(),
+    ))
+}
+
+#[rpc(server, namespace = "eth")]
+pub trait EthApi {
+    #[method(name = "blockNumber")]
+    async fn block_number(&self) -> RpcResult<BlockNumber>;
+    #[method(name = "syncing")]
+    async fn syncing(&self) -> RpcResult<SyncStatus>;
+    /// Gas price to pay to be included soon, from the tips of recent transactions and the base
+    /// fee of the head, at least the lowest tip accepted by this server.
+    #[method(name = "gasPrice")]
+    async fn gas_price(&self) -> RpcResult<U256>;
+    /// Base fees and gas used ratios of up to 1024 blocks up to `newest_block`, with the tips
+    /// paid at `reward_percentiles` of their gas if given.
+    #[method(name = "feeHistory")]
+    async fn fee_history(
+        &self,
+        block_count: U64,
+        newest_block: BlockParameter,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> RpcResult<FeeHistory>;
+    #[method(name = "getBalance")]
+    async fn get_balance(&self, address: Address, block_number: BlockNumber) -> RpcResult<U256>;
+    #[method(name = "getHeaderByNumber")]
+    async fn get_header_by_number(
+        &self,
+        block_number: BlockNumber,
+    ) -> RpcResult<Option<RpcBlockHeader>>;
+    #[method(name = "getHeaderByHash")]
+    async fn get_header_by_hash(&self, block_hash: H256) -> RpcResult<Option<RpcBlockHeader>>;
+    #[method(name = "getRawTransactionByHash")]
+    async fn get_raw_transaction_by_hash(&self, hash: H256) -> RpcResult<Option<RawTransaction>>;
+    #[method(name = "getRawTransactionByBlockHashAndIndex")]
+    async fn get_raw_transaction_by_block_hash_and_index(
         &self,
         block_hash: H256,
         index: U64,
-    ) -> RpcResult<ethereum_jsonrpc::common::Tx> {
-        let txn = self.db.begin().await?;
-        let block_number = crate::accessors::chain::header_number::read(&txn, block_hash)
-            .await?
-            .unwrap();
-        Ok(crate::accessors::chain::block_body::read_without_senders(
-            &txn,
-            block_hash,
-            block_number,
-        )
-        .await?
-        .unwrap()
-        .transactions
-        .into_iter()
-        .nth(index.as_usize())
-        .map(|tx| ethereum_jsonrpc::common::Tx {
-            block_hash: Some(block_hash),
-            block_number: Some(U64::from(block_number.0)),
-            from: Address::zero(),
-            gas: U64::from(tx.message.gas_limit()),
-            gas_price: match tx.message {
-                Message::Legacy { gas_price, .. } => gas_price,
-                Message::EIP2930 { gas_price, .. } => gas_price,
-                Message::EIP1559 {
-                    max_fee_per_gas, ..
-                } => max_fee_per_gas,
-            },
-            hash: tx.message.hash(),
-            input: tx.message.input().clone(),
-            nonce: U64::from(tx.message.nonce()),
-            to: match tx.message.action() {
-                TransactionAction::Call(to) => Some(to),
-                _ => None,
-            },
-            transaction_index: Some(index),
-            value: tx.message.value(),
-            v: U64::from(tx.v()),
-            r: tx.r(),
-            s: tx.s(),
-        })
-        .unwrap())
+    ) -> RpcResult<Option<RawTransaction>>;
+    #[method(name = "getTransactionByHash")]
+    async fn get_transaction_by_hash(&self, hash: H256) -> RpcResult<Option<RpcTransaction>>;
+    #[method(name = "getTransactionReceipt")]
+    async fn get_transaction_receipt(&self, hash: H256) -> RpcResult<Option<RpcReceipt>>;
+    #[method(name = "call")]
+    async fn call(&self, call: CallRequest, block_number: BlockNumber) -> RpcResult<CallOutput>;
+    #[method(name = "getAccount")]
+    async fn get_account(
+        &self,
+        address: Address,
+        block_number: BlockNumber,
+    ) -> RpcResult<Option<RpcAccount>>;
+    #[method(name = "sendRawTransaction")]
+    async fn send_raw_transaction(&self, tx: RawTransaction) -> RpcResult<H256>;
+    /// Like `eth_sendRawTransaction`, but only if `conditions` hold at the head.
+    #[method(name = "sendRawTransactionConditional")]
+    async fn send_raw_transaction_conditional(
+        &self,
+        tx: RawTransaction,
+        conditions: TransactionConditions,
+    ) -> RpcResult<H256>;
+    #[method(name = "getTransactionCount")]
+    async fn get_transaction_count(
+        &self,
+        address: Address,
+        block: BlockParameter,
+    ) -> RpcResult<U64>;
+    #[method(name = "simulateV1")]
+    async fn simulate_v1(
+        &self,
+        payload: SimulatePayload,
+        block: Option<BlockParameter>,
+    ) -> RpcResult<Vec<RpcSimulatedBlock>>;
+    /// Always fails with [`METHOD_NOT_SUPPORTED`](super::errors::METHOD_NOT_SUPPORTED): blocks are only imported, never produced, so
+    /// there is no work package for external miners.
+    #[method(name = "getWork")]
+    async fn get_work(&self) -> RpcResult<Vec<H256>>;
+    /// Always fails with [`METHOD_NOT_SUPPORTED`](super::errors::METHOD_NOT_SUPPORTED), see `eth_getWork`.
+    #[method(name = "submitWork")]
+    async fn submit_work(&self, nonce: H64, pow_hash: H256, mix_digest: H256) -> RpcResult<bool>;
+    /// Configured fee recipient, fails if none has been set.
+    #[method(name = "coinbase")]
+    async fn coinbase(&self) -> RpcResult<Address>;
+    /// Logs matching `filter`, failing with [`LIMIT_EXCEEDED`](super::errors::LIMIT_EXCEEDED) and a block range to retry with
+    /// if the request scans too many blocks or matches too many logs.
+    #[method(name = "getLogs")]
+    async fn get_logs(&self, filter: LogFilter) -> RpcResult<Vec<RpcLog>>;
+    /// Filter of the transactions submitted through this server from now on, reported as full
+    /// transactions if `full`, or else as hashes.
+    #[method(name = "newPendingTransactionFilter")]
+    async fn new_pending_transaction_filter(&self, full: Option<bool>) -> RpcResult<U64>;
+    #[method(name = "getFilterChanges")]
+    async fn get_filter_changes(&self, id: U64) -> RpcResult<FilterChanges>;
+    #[method(name = "uninstallFilter")]
+    async fn uninstall_filter(&self, id: U64) -> RpcResult<bool>;
+}
+
+pub struct EthApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    pub db: Arc<ReadReplica<E>>,
+    pub head: HeadSource,
+    pub upstream: Option<Arc<HttpClient>>,
+    pub sentry: Option<SentryClient<Channel>>,
+    pub local_transactions: Arc<LocalTransactions>,
+    pub etherbase: Arc<Mutex<Option<Address>>>,
+    pub limits: SubmissionLimits,
+    pub log_limits: LogLimits,
+    pub receipt_cache: ReceiptCache,
+    pub pending_filters: PendingTransactionFilters,
+    pub notifications: ChainNotifications,
+    pub top_block_estimate: PublishedTopBlockEstimate,
+}
+
+impl<E> EthApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    async fn fallback<T>(
+        &self,
+        method: &str,
+        params: Vec<serde_json::Value>,
+    ) -> RpcResult<Option<T>>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        if let Some(upstream) = &self.upstream {
+            return upstream
+                .request(method, Some(ParamsSer::Array(params)))
+                .await;
+        }
+
+        Ok(None)
     }
 
-    async fn get_tx_by_block_number_and_index(
+    /// Like [`Self::fallback`], but fails with `pruned` if the upstream does not have the data
+    /// either, instead of reporting it as nonexistent.
+    async fn fallback_or_pruned<T>(
         &self,
-        block_number: U64,
-        index: U64,
-    ) -> RpcResult<ethereum_jsonrpc::common::Tx> {
-        let txn = self.db.begin().await?;
-        let block_hash =
-            crate::accessors::chain::canonical_hash::read(&txn, BlockNumber(block_number.as_u64()))
-                .await?
-                .unwrap();
-        Ok(crate::accessors::chain::block_body::read_without_senders(
-            &txn,
-            block_hash,
-            BlockNumber(block_number.as_u64()),
-        )
-        .await?
-        .unwrap()
-        .transactions
-        .into_iter()
-        .nth(index.as_usize())
-        .map(|tx| ethereum_jsonrpc::common::Tx {
-            block_hash: Some(block_hash),
-            block_number: Some(block_number),
-            from: Address::zero(),
-            gas: U64::from(tx.message.gas_limit()),
-            gas_price: match tx.message {
-                Message::Legacy { gas_price, .. } => gas_price,
-                Message::EIP2930 { gas_price, .. } => gas_price,
-                Message::EIP1559 {
-                    max_fee_per_gas, ..
-                } => max_fee_per_gas,
-            },
-            hash: tx.message.hash(),
-            input: tx.message.input().clone(),
-            nonce: U64::from(tx.message.nonce()),
-            to: match tx.message.action() {
-                TransactionAction::Call(to) => Some(to),
-                _ => None,
+        method: &str,
+        params: Vec<serde_json::Value>,
+        pruned: Option<DataPruned>,
+    ) -> RpcResult<Option<T>>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        match (self.fallback(method, params).await?, pruned) {
+            (None, Some(e)) => Err(pruned_error(e)),
+            (res, _) => Ok(res),
+        }
+    }
+
+    /// Pool `tx` and pass it on to the upstream and the sentry's peers, rebroadcasting it until
+    /// it is mined.
+    async fn submit_transaction(&self, tx: RawTransaction) -> RpcResult<H256> {
+        let msg = MessageWithSignature::trie_decode(&tx.0)
+            .map_err(|e| format_err!("Invalid transaction: {:?}", e))?;
+        self.limits.check(&msg.message)?;
+        let sender = msg.recover_sender()?;
+        if self.upstream.is_none() && self.sentry.is_none() {
+            return Err(format_err!("No upstream or sentry to submit transactions to").into());
+        }
+
+        let hash = msg.hash();
+        let replaced = {
+            let db = self.db.get().await?;
+            let db_tx = db.begin()?;
+            let head = self.head.resolve(&db_tx)?;
+            self.local_transactions
+                .add(&db_tx, head, msg.clone(), sender)?
+        };
+        if let Some(replaced) = replaced {
+            debug!("Local transaction {:?} replaced by {:?}", replaced, hash);
+        }
+
+        if self.upstream.is_some() {
+            if let Err(e) = self
+                .fallback::<H256>("eth_sendRawTransaction", vec![serde_json::to_value(&tx)?])
+                .await
+            {
+                self.local_transactions.remove(hash);
+                return Err(e);
+            }
+        }
+        if let Some(sentry) = &self.sentry {
+            match broadcast_transactions(sentry, vec![msg.clone()]).await {
+                Ok(peers) => debug!("Sent local transaction {:?} to {} peers", hash, peers),
+                Err(e) => debug!("Broadcast of local transaction {:?} failed: {}", hash, e),
+            }
+        }
+
+        let txn = rpc_transaction(
+            &MessageWithSender {
+                message: msg.message,
+                sender,
             },
-            transaction_index: Some(index),
-            value: tx.message.value(),
-            v: U64::from(tx.v()),
-            r: tx.r(),
-            s: tx.s(),
+            hash,
+            None,
+            None,
+        );
+        self.pending_filters.admit(&txn);
+        self.notifications
+            .send(ChainNotification::PendingTransaction(txn));
+
+        Ok(hash)
+    }
+}
+
+#[async_trait]
+impl<E> EthApiServer for EthApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    #[instrument(name = "eth_blockNumber", skip(self))]
+    async fn block_number(&self) -> RpcResult<BlockNumber> {
+        Ok(self.head.resolve(&self.db.get().await?.begin()?)?)
+    }
+
+    #[instrument(name = "eth_syncing", skip(self))]
+    async fn syncing(&self) -> RpcResult<SyncStatus> {
+        let (current_block, _, highest_block) =
+            sync_heads(&*self.db.get().await?, self.head, &self.top_block_estimate)?;
+
+        Ok(if current_block < highest_block {
+            SyncStatus::Syncing(SyncProgress {
+                starting_block: U64::zero(),
+                current_block: current_block.0.into(),
+                highest_block: highest_block.0.into(),
+            })
+        } else {
+            SyncStatus::NotSyncing(false)
         })
-        .unwrap())
     }
 
-    async fn get_transaction_count(&self, address: Address, block_number: U64) -> RpcResult<U64> {
-        Ok(U64::from(
-            crate::accessors::state::account::read(
-                &self.db.begin().await?,
-                address,
-                Some(BlockNumber(block_number.as_u64())),
-            )
-            .await?
-            .unwrap()
-            .nonce,
-        ))
+    #[instrument(name = "eth_gasPrice", skip(self))]
+    async fn gas_price(&self) -> RpcResult<U256> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+        let head = self.head.resolve(&tx)?;
+
+        let tip = suggest_tip(&tx, head)?.unwrap_or(U256::ZERO);
+        let base_fee = match chain::canonical_hash::read(&tx, head)? {
+            Some(hash) => chain::header::read(&tx, BlockKey::new(head, hash))?
+                .and_then(|header| header.base_fee_per_gas),
+            None => None,
+        };
+
+        Ok(std::cmp::max(tip, self.limits.min_gas_price) + base_fee.unwrap_or(U256::ZERO))
     }
 
-    async fn get_transaction_receipt(
+    #[instrument(name = "eth_feeHistory", skip(self))]
+    async fn fee_history(
         &self,
-        hash: H256,
-    ) -> RpcResult<ethereum_jsonrpc::common::TxReceipt> {
-        let txn = self.db.begin().await?;
-        let block_number = crate::accessors::chain::tl::read(&txn, hash)
-            .await?
-            .unwrap();
-        let block_hash = crate::accessors::chain::canonical_hash::read(&txn, block_number)
-            .await?
-            .unwrap();
-        let header = PartialHeader::from(
-            crate::accessors::chain::header::read(&txn, block_hash, block_number)
-                .await?
-                .unwrap(),
-        );
-        let block_body =
-            crate::accessors::chain::block_body::read_with_senders(&txn, block_hash, block_number)
-                .await?
-                .unwrap();
-        let chain_config = txn
-            .get(
-                tables::Config,
-                txn.get(tables::CanonicalHeader, BlockNumber(0))
-                    .await?
-                    .unwrap(),
-            )
-            .await?
-            .unwrap();
-        let block_spec = chain_config.collect_block_spec(block_number);
-
-        // Prepare the execution context.
-        let mut buffer = Buffer::new(&txn, BlockNumber(0), Some(BlockNumber(block_number.0 - 1)));
-        let mut engine = engine_factory(chain_config.clone())?;
-        let mut analysis_cache = AnalysisCache::default();
-        let mut processor = ExecutionProcessor::new(
-            &mut buffer,
-            None,
-            &mut analysis_cache,
-            &mut *engine,
-            &header,
-            &block_body,
-            &block_spec,
+        block_count: U64,
+        newest_block: BlockParameter,
+        reward_percentiles: Option<Vec<f64>>,
+    ) -> RpcResult<FeeHistory> {
+        if let Some(percentiles) = &reward_percentiles {
+            if percentiles.iter().any(|p| !(0.0..=100.0).contains(p))
+                || percentiles.windows(2).any(|w| w[0] > w[1])
+            {
+                return Err(RpcError::Call(CallError::InvalidParams(format_err!(
+                    "reward percentiles must be ascending and between 0 and 100"
+                ))));
+            }
+        }
+
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+        let head = self.head.resolve(&tx)?;
+        let newest = resolve_block_parameter(&tx, head, Some(newest_block))?;
+        if newest > head {
+            return Err(RpcError::Call(CallError::InvalidParams(format_err!(
+                "block {} is beyond the head {}",
+                newest,
+                head
+            ))));
+        }
+
+        let block_count = std::cmp::min(
+            std::cmp::min(block_count.as_u64(), FEE_HISTORY_MAX_BLOCKS),
+            newest.0 + 1,
         );
+        let oldest = BlockNumber(newest.0 + 1 - block_count);
+        if reward_percentiles.is_some() && block_count > 0 {
+            // Tips are weighed by gas used, found by replaying the blocks on top of their parents.
+            ensure_available(
+                &tx,
+                PruneTarget::History,
+                BlockNumber(oldest.0.saturating_sub(1)),
+            )?;
+        }
 
-        let receipts = processor.execute_block_no_post_validation().await?;
-        let transaction_index = block_body
-            .transactions
-            .iter()
-            .position(|tx| tx.message.hash() == hash)
-            .unwrap();
-        let transaction = block_body.transactions.get(transaction_index).unwrap();
-        let receipt = receipts.get(transaction_index).unwrap();
-        let gas_used = match transaction_index {
-            0 => U64::from(receipt.cumulative_gas_used),
-            _ => U64::from(
-                receipt.cumulative_gas_used
-                    - receipts
-                        .get(transaction_index - 1)
-                        .unwrap()
-                        .cumulative_gas_used,
-            ),
-        };
-        let logs = receipt
-            .logs
-            .iter()
-            .enumerate()
-            .map(|(i, log)| ethereum_jsonrpc::common::TxLog {
-                log_index: Some(U64::from(i)),
-                transaction_index: Some(U64::from(transaction_index)),
-                transaction_hash: Some(transaction.message.hash()),
-                block_hash: Some(block_hash),
-                block_number: Some(U64::from(block_number.0)),
-                address: log.clone().address,
-                data: log.clone().data,
-                topics: log.clone().topics,
-            })
-            .collect::<Vec<_>>();
+        Ok(read_fee_history(
+            &tx,
+            oldest,
+            block_count,
+            reward_percentiles.as_deref(),
+        )?)
+    }
 
-        Ok(ethereum_jsonrpc::common::TxReceipt {
-            transaction_hash: hash,
-            transaction_index: U64::from(transaction_index),
-            block_hash,
-            block_number: U64::from(block_number.0),
-            from: transaction.sender,
-            to: match transaction.message.action() {
-                TransactionAction::Call(to) => Some(to),
-                _ => None,
-            },
-            cumulative_gas_used: U64::from(receipt.cumulative_gas_used),
-            gas_used,
-            contract_address: match transaction.message.action() {
-                TransactionAction::Create => Some(crate::execution::address::create_address(
-                    transaction.sender,
-                    transaction.message.nonce(),
-                )),
-                _ => None,
-            },
-            logs,
-            logs_bloom: receipt.bloom,
-            status: if receipt.success {
-                U64::from(1)
-            } else {
-                U64::zero()
-            },
-        })
+    #[instrument(name = "eth_getBalance", skip(self))]
+    async fn get_balance(&self, address: Address, block_number: BlockNumber) -> RpcResult<U256> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        ensure_available(&tx, PruneTarget::History, block_number)?;
+
+        Ok(
+            crate::accessors::state::account::read(&tx, address, Some(block_number))?
+                .map(|acc| acc.balance)
+                .unwrap_or(U256::ZERO),
+        )
     }
 
-    async fn get_uncle_by_block_hash_and_index(
+    #[instrument(name = "eth_getHeaderByNumber", skip(self))]
+    async fn get_header_by_number(
         &self,
-        block_hash: H256,
-        index: U64,
-    ) -> RpcResult<ethereum_jsonrpc::common::Block> {
-        let txn = self.db.begin().await?;
-        let block_number = crate::accessors::chain::header_number::read(&txn, block_hash)
-            .await?
-            .unwrap();
-        let uncle_header = crate::accessors::chain::storage_body::read(
-            &txn,
-            crate::accessors::chain::canonical_hash::read(&txn, BlockNumber(block_number.0))
-                .await?
-                .unwrap(),
-            block_number,
+        block_number: BlockNumber,
+    ) -> RpcResult<Option<RpcBlockHeader>> {
+        {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+
+            if let Some(block) = chain::block_id::resolve(&tx, block_number)? {
+                if let Some(header) = chain::header::read(&tx, block.key())? {
+                    return Ok(Some(RpcBlockHeader::new(block.hash, header)));
+                }
+            }
+        }
+
+        self.fallback(
+            "eth_getHeaderByNumber",
+            vec![serde_json::to_value(U64::from(block_number.0))?],
         )
-        .await?
-        .unwrap()
-        .uncles
-        .into_iter()
-        .nth(index.as_usize())
-        .unwrap();
+        .await
+    }
+
+    #[instrument(name = "eth_getHeaderByHash", skip(self))]
+    async fn get_header_by_hash(&self, block_hash: H256) -> RpcResult<Option<RpcBlockHeader>> {
+        {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+
+            if let Some(block) = chain::block_id::resolve(&tx, block_hash)? {
+                if let Some(header) = chain::header::read(&tx, block.key())? {
+                    return Ok(Some(RpcBlockHeader::new(block.hash, header)));
+                }
+            }
+        }
 
-        let body = crate::accessors::chain::block_body::read_without_senders(
-            &txn,
-            uncle_header.hash(),
-            block_number,
+        self.fallback(
+            "eth_getHeaderByHash",
+            vec![serde_json::to_value(block_hash)?],
         )
-        .await?
-        .unwrap();
-        let senders =
-            crate::accessors::chain::tx_sender::read(&txn, uncle_header.hash(), block_number)
-                .await?;
+        .await
+    }
 
-        let transactions = body
-            .transactions
-            .into_iter()
-            .zip(senders)
-            .enumerate()
-            .map(|(index, (tx, sender))| {
-                ethereum_jsonrpc::common::Transaction::Full(Box::new(
-                    ethereum_jsonrpc::common::Tx {
-                        block_number: Some(U64::from(block_number.0)),
-                        block_hash: Some(uncle_header.hash()),
-                        from: sender,
-                        gas: U64::from(tx.message.gas_limit()),
-                        gas_price: match tx.message {
-                            Message::Legacy { gas_price, .. } => gas_price,
-                            Message::EIP2930 { gas_price, .. } => gas_price,
-                            Message::EIP1559 {
-                                max_fee_per_gas, ..
-                            } => max_fee_per_gas,
-                        },
-                        hash: tx.message.hash(),
-                        input: tx.message.input().clone(),
-                        nonce: U64::from(tx.message.nonce()),
-                        to: match tx.message.action() {
-                            TransactionAction::Call(to) => Some(to),
-                            TransactionAction::Create => None,
-                        },
-                        transaction_index: Some(U64::from(index as u64)),
-                        value: tx.message.value(),
-                        v: U64::from(tx.v()),
-                        r: tx.r(),
-                        s: tx.s(),
-                    },
-                ))
-            })
-            .collect();
+    #[instrument(name = "eth_getRawTransactionByHash", skip(self))]
+    async fn get_raw_transaction_by_hash(&self, hash: H256) -> RpcResult<Option<RawTransaction>> {
+        let pruned = {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
 
-        let td = crate::accessors::chain::td::read(&txn, uncle_header.hash(), block_number)
-            .await?
-            .unwrap();
-
-        Ok(ethereum_jsonrpc::common::Block {
-            number: Some(U64::from(block_number.0)),
-            hash: Some(uncle_header.hash()),
-            parent_hash: uncle_header.parent_hash,
-            nonce: Some(uncle_header.nonce),
-            sha3_uncles: uncle_header.ommers_hash,
-            logs_bloom: Some(uncle_header.logs_bloom),
-            transactions_root: uncle_header.transactions_root,
-            state_root: uncle_header.state_root,
-            receipts_root: uncle_header.receipts_root,
-            miner: uncle_header.beneficiary,
-            difficulty: uncle_header.difficulty,
-            total_difficulty: td,
-            extra_data: uncle_header.extra_data,
-            size: U64::zero(),
-            gas_limit: U64::from(uncle_header.gas_limit),
-            gas_used: U64::from(uncle_header.gas_used),
-            timestamp: U64::from(uncle_header.timestamp),
-            transactions,
-            uncles: body.ommers.into_iter().map(|uncle| uncle.hash()).collect(),
-        })
+            if let Some(msg) = read_transaction_by_hash(&tx, hash)? {
+                return Ok(Some(RawTransaction(msg.trie_encode())));
+            }
+
+            match chain::tl::read(&tx, hash)? {
+                Some(block_number) => prune::pruned(&tx, PruneTarget::Blocks, block_number)?,
+                None => None,
+            }
+        };
+
+        self.fallback_or_pruned(
+            "eth_getRawTransactionByHash",
+            vec![serde_json::to_value(hash)?],
+            pruned,
+        )
+        .await
     }
 
-    async fn get_uncle_by_block_number_and_index(
+    #[instrument(name = "eth_getRawTransactionByBlockHashAndIndex", skip(self))]
+    async fn get_raw_transaction_by_block_hash_and_index(
         &self,
-        block_number: U64,
+        block_hash: H256,
         index: U64,
-    ) -> RpcResult<ethereum_jsonrpc::common::Block> {
-        let txn = self.db.begin().await?;
-        let uncle_header = crate::accessors::chain::storage_body::read(
-            &txn,
-            crate::accessors::chain::canonical_hash::read(&txn, BlockNumber(block_number.as_u64()))
-                .await?
-                .unwrap(),
-            BlockNumber(block_number.as_u64()),
+    ) -> RpcResult<Option<RawTransaction>> {
+        let pruned = {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+
+            match chain::block_id::resolve(&tx, block_hash)? {
+                Some(block) => {
+                    if let Some(msg) =
+                        read_block_transaction(&tx, block.hash, block.number, index.as_u64())?
+                    {
+                        return Ok(Some(RawTransaction(msg.trie_encode())));
+                    }
+
+                    prune::pruned(&tx, PruneTarget::Blocks, block.number)?
+                }
+                None => None,
+            }
+        };
+
+        self.fallback_or_pruned(
+            "eth_getRawTransactionByBlockHashAndIndex",
+            vec![
+                serde_json::to_value(block_hash)?,
+                serde_json::to_value(index)?,
+            ],
+            pruned,
         )
-        .await?
-        .unwrap()
-        .uncles
-        .into_iter()
-        .nth(index.as_usize())
-        .unwrap();
+        .await
+    }
+
+    #[instrument(name = "eth_getTransactionByHash", skip(self))]
+    async fn get_transaction_by_hash(&self, hash: H256) -> RpcResult<Option<RpcTransaction>> {
+        let pruned = {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+
+            if let Some(transaction) = read_rpc_transaction(&tx, hash, &self.receipt_cache)? {
+                return Ok(Some(transaction));
+            }
+
+            match chain::tl::read(&tx, hash)? {
+                Some(block_number) => prune::pruned(&tx, PruneTarget::Blocks, block_number)?,
+                None => None,
+            }
+        };
 
-        let body = crate::accessors::chain::block_body::read_without_senders(
-            &txn,
-            uncle_header.hash(),
-            BlockNumber(block_number.as_u64()),
+        self.fallback_or_pruned(
+            "eth_getTransactionByHash",
+            vec![serde_json::to_value(hash)?],
+            pruned,
         )
-        .await?
-        .unwrap();
-        let senders = crate::accessors::chain::tx_sender::read(
-            &txn,
-            uncle_header.hash(),
-            BlockNumber(block_number.as_u64()),
+        .await
+    }
+
+    #[instrument(name = "eth_getTransactionReceipt", skip(self))]
+    async fn get_transaction_receipt(&self, hash: H256) -> RpcResult<Option<RpcReceipt>> {
+        let pruned = {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+            let head = self.head.resolve(&tx)?;
+
+            match chain::tl::read(&tx, hash)? {
+                Some(block_number) if block_number <= head => {
+                    // Receipts are not stored, the block is replayed on top of its parent.
+                    let parent = BlockNumber(block_number.0.saturating_sub(1));
+                    match prune::pruned(&tx, PruneTarget::History, parent)? {
+                        Some(e) => Some(e),
+                        None => {
+                            if let Some(receipt) =
+                                read_transaction_receipt(&tx, hash, &self.receipt_cache)?
+                            {
+                                return Ok(Some(receipt));
+                            }
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            }
+        };
+
+        self.fallback_or_pruned(
+            "eth_getTransactionReceipt",
+            vec![serde_json::to_value(hash)?],
+            pruned,
         )
-        .await?;
+        .await
+    }
 
-        let transactions = body
-            .transactions
-            .into_iter()
-            .zip(senders)
-            .enumerate()
-            .map(|(index, (tx, sender))| {
-                ethereum_jsonrpc::common::Transaction::Full(Box::new(
-                    ethereum_jsonrpc::common::Tx {
-                        block_number: Some(block_number),
-                        block_hash: Some(uncle_header.hash()),
-                        from: sender,
-                        gas: U64::from(tx.message.gas_limit()),
-                        gas_price: match tx.message {
-                            Message::Legacy { gas_price, .. } => gas_price,
-                            Message::EIP2930 { gas_price, .. } => gas_price,
-                            Message::EIP1559 {
-                                max_fee_per_gas, ..
-                            } => max_fee_per_gas,
-                        },
-                        hash: tx.message.hash(),
-                        input: tx.message.input().clone(),
-                        nonce: U64::from(tx.message.nonce()),
-                        to: match tx.message.action() {
-                            TransactionAction::Call(to) => Some(to),
-                            TransactionAction::Create => None,
-                        },
-                        transaction_index: Some(U64::from(index as u64)),
-                        value: tx.message.value(),
-                        v: U64::from(tx.v()),
-                        r: tx.r(),
-                        s: tx.s(),
-                    },
-                ))
-            })
-            .collect();
-        let td = crate::accessors::chain::td::read(
-            &txn,
-            uncle_header.hash(),
-            BlockNumber(block_number.as_u64()),
+    #[instrument(name = "eth_call", skip(self))]
+    async fn call(&self, call: CallRequest, block_number: BlockNumber) -> RpcResult<CallOutput> {
+        let params = vec![
+            serde_json::to_value(&call)?,
+            serde_json::to_value(block_number)?,
+        ];
+
+        let outcome = {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+
+            ensure_available(&tx, PruneTarget::History, block_number)?;
+
+            call_at_block(&tx, call, block_number, None)?
+        };
+
+        match outcome {
+            Some(ExecutionOutcome::Success { output, .. }) => Ok(CallOutput(output)),
+            Some(outcome) => Err(execution_error(outcome)),
+            None => self
+                .fallback("eth_call", params)
+                .await?
+                .ok_or_else(|| format_err!("Block {} not found", block_number).into()),
+        }
+    }
+
+    #[instrument(name = "eth_getAccount", skip(self))]
+    async fn get_account(
+        &self,
+        address: Address,
+        block_number: BlockNumber,
+    ) -> RpcResult<Option<RpcAccount>> {
+        {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+
+            ensure_available(&tx, PruneTarget::History, block_number)?;
+
+            if let Some(account) = read_rpc_account(&tx, address, block_number)? {
+                return Ok(Some(account));
+            }
+        }
+
+        self.fallback(
+            "eth_getAccount",
+            vec![
+                serde_json::to_value(address)?,
+                serde_json::to_value(block_number)?,
+            ],
+        )
+        .await
+    }
+
+    #[instrument(name = "eth_sendRawTransaction", skip(self, tx))]
+    async fn send_raw_transaction(&self, tx: RawTransaction) -> RpcResult<H256> {
+        self.submit_transaction(tx).await
+    }
+
+    #[instrument(name = "eth_sendRawTransactionConditional", skip(self, tx))]
+    async fn send_raw_transaction_conditional(
+        &self,
+        tx: RawTransaction,
+        conditions: TransactionConditions,
+    ) -> RpcResult<H256> {
+        {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+            let head = self.head.resolve(&tx)?;
+
+            if let Some(reason) = check_conditions(&tx, &conditions, head)? {
+                return Err(conditions_not_met(reason));
+            }
+        }
+
+        self.submit_transaction(tx).await
+    }
+
+    #[instrument(name = "eth_getTransactionCount", skip(self))]
+    async fn get_transaction_count(
+        &self,
+        address: Address,
+        block: BlockParameter,
+    ) -> RpcResult<U64> {
+        {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+            let head = self.head.resolve(&tx)?;
+
+            let block_number = match block {
+                BlockParameter::Tag(BlockTag::Earliest) => BlockNumber(0),
+                BlockParameter::Tag(BlockTag::Latest) => head,
+                BlockParameter::Tag(BlockTag::Pending) => {
+                    let latest = latest_nonce(&tx, address, head)?;
+                    return Ok(
+                        NonceStatus::new(latest, &self.local_transactions.nonces(address)).pending,
+                    );
+                }
+                BlockParameter::Tag(tag @ (BlockTag::Safe | BlockTag::Finalized)) => {
+                    forkchoice_block(&tx, tag)?
+                }
+                BlockParameter::Number(n) => BlockNumber(n.as_u64()),
+            };
+
+            if block_number <= head {
+                ensure_available(&tx, PruneTarget::History, block_number)?;
+
+                return Ok(latest_nonce(&tx, address, block_number)?.into());
+            }
+        }
+
+        self.fallback(
+            "eth_getTransactionCount",
+            vec![serde_json::to_value(address)?, serde_json::to_value(block)?],
         )
         .await?
-        .unwrap();
-
-        Ok(ethereum_jsonrpc::common::Block {
-            number: Some(block_number),
-            hash: Some(uncle_header.hash()),
-            parent_hash: uncle_header.parent_hash,
-            nonce: Some(uncle_header.nonce),
-            sha3_uncles: uncle_header.ommers_hash,
-            logs_bloom: Some(uncle_header.logs_bloom),
-            transactions_root: uncle_header.transactions_root,
-            state_root: uncle_header.state_root,
-            receipts_root: uncle_header.receipts_root,
-            miner: uncle_header.beneficiary,
-            difficulty: uncle_header.difficulty,
-            total_difficulty: td,
-            extra_data: uncle_header.extra_data,
-            size: U64::zero(),
-            gas_limit: U64::from(uncle_header.gas_limit),
-            gas_used: U64::from(uncle_header.gas_used),
-            timestamp: U64::from(uncle_header.timestamp),
-            transactions,
-            uncles: body.ommers.into_iter().map(|uncle| uncle.hash()).collect(),
-        })
+        .ok_or_else(|| format_err!("Block {:?} not found", block).into())
     }
 
-    async fn get_uncle_count_by_block_hash(&self, block_hash: H256) -> RpcResult<U64> {
-        let txn = self.db.begin().await?;
-        Ok(U64::from(
-            crate::accessors::chain::storage_body::read(
-                &txn,
-                block_hash,
-                crate::accessors::chain::header_number::read(&txn, block_hash)
-                    .await?
-                    .unwrap(),
+    #[instrument(name = "eth_simulateV1", skip(self, payload))]
+    async fn simulate_v1(
+        &self,
+        payload: SimulatePayload,
+        block: Option<BlockParameter>,
+    ) -> RpcResult<Vec<RpcSimulatedBlock>> {
+        if payload.block_state_calls.len() > MAX_SIMULATED_BLOCKS {
+            return Err(format_err!(
+                "Too many blocks to simulate: {}, at most {}",
+                payload.block_state_calls.len(),
+                MAX_SIMULATED_BLOCKS
             )
-            .await?
-            .unwrap()
-            .uncles
-            .len(),
-        ))
-    }
-
-    async fn get_uncle_count_by_block_number(&self, block_number: U64) -> RpcResult<U64> {
-        let txn = self.db.begin().await?;
-        Ok(U64::from(
-            crate::accessors::chain::storage_body::read(
-                &txn,
-                crate::accessors::chain::canonical_hash::read(
-                    &txn,
-                    BlockNumber(block_number.as_u64()),
-                )
+            .into());
+        }
+
+        let block = block.unwrap_or(BlockParameter::Tag(BlockTag::Latest));
+        let params = vec![
+            serde_json::to_value(&payload)?,
+            serde_json::to_value(block)?,
+        ];
+
+        let simulated = {
+            let db = self.db.get().await?;
+            let tx = db.begin()?;
+            let head = self.head.resolve(&tx)?;
+
+            let block_number = match block {
+                BlockParameter::Tag(BlockTag::Earliest) => BlockNumber(0),
+                BlockParameter::Tag(BlockTag::Latest | BlockTag::Pending) => head,
+                BlockParameter::Tag(tag @ (BlockTag::Safe | BlockTag::Finalized)) => {
+                    forkchoice_block(&tx, tag)?
+                }
+                BlockParameter::Number(n) => BlockNumber(n.as_u64()),
+            };
+
+            if block_number <= head {
+                ensure_available(&tx, PruneTarget::History, block_number)?;
+
+                simulate_at_block(&tx, payload, block_number)?
+            } else {
+                None
+            }
+        };
+
+        match simulated {
+            Some(simulated) => Ok(simulated),
+            None => self
+                .fallback("eth_simulateV1", params)
                 .await?
-                .unwrap(),
-                BlockNumber(block_number.as_u64()),
-            )
-            .await?
-            .unwrap()
-            .uncles
-            .len(),
-        ))
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        genesis::initialize_genesis,
-        kv::{new_mem_database, tables, traits::*},
-        models::{Account, BlockNumber, ETHER},
-        stagedsync::stages::FINISH,
-    };
-    use ethereum_jsonrpc::EthApiClient;
-    use ethereum_types::{H160, U64};
-    use hex_literal::hex;
-    use jsonrpsee::{
-        http_client::HttpClientBuilder,
-        http_server::{HttpServerBuilder, HttpServerHandle},
-    };
-    use std::sync::Arc;
-    use tempfile::TempDir;
-
-    // We'll need it later in our tests.
-    const DEADBEEF: Address = H160(hex!("00000000000000000000000000000000deadbeef"));
-
-    async fn init_mock_chain() -> anyhow::Result<Arc<impl MutableKV>> {
-        use super::*;
-        let mock_db = Arc::new(new_mem_database().unwrap());
-        let txn = mock_db.begin_mutable().await.unwrap();
-        assert!(initialize_genesis(
-            &txn,
-            &TempDir::new().unwrap(),
-            crate::res::chainspec::MAINNET.clone()
-        )
-        .await
-        .unwrap());
-        assert!(txn
-            .set(
-                tables::Account,
-                DEADBEEF,
-                Account {
-                    balance: ETHER.as_u256(),
-                    ..Default::default()
-                },
+                .ok_or_else(|| format_err!("Block {:?} not found", block).into()),
+        }
+    }
+
+    #[instrument(name = "eth_getWork", skip(self))]
+    async fn get_work(&self) -> RpcResult<Vec<H256>> {
+        Err(not_supported("eth_getWork"))
+    }
+
+    #[instrument(name = "eth_submitWork", skip(self))]
+    async fn submit_work(
+        &self,
+        _nonce: H64,
+        _pow_hash: H256,
+        _mix_digest: H256,
+    ) -> RpcResult<bool> {
+        Err(not_supported("eth_submitWork"))
+    }
+
+    #[instrument(name = "eth_coinbase", skip(self))]
+    async fn coinbase(&self) -> RpcResult<Address> {
+        Ok((*self.etherbase.lock())
+            .ok_or_else(|| format_err!("etherbase must be explicitly specified"))?)
+    }
+
+    #[instrument(name = "eth_getLogs", skip(self))]
+    async fn get_logs(&self, filter: LogFilter) -> RpcResult<Vec<RpcLog>> {
+        let matcher = LogMatcher::new(&filter, &self.log_limits)?;
+
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+        let head = self.head.resolve(&tx)?;
+
+        let (from, to) = if let Some(block_hash) = filter.block_hash {
+            if filter.from_block.is_some() || filter.to_block.is_some() {
+                return Err(RpcError::Call(CallError::InvalidParams(format_err!(
+                    "blockHash excludes fromBlock and toBlock"
+                ))));
+            }
+            let block_number = chain::header_number::read(&tx, block_hash)?
+                .ok_or_else(|| format_err!("Block {:?} not found", block_hash))?;
+            (block_number, block_number)
+        } else {
+            (
+                resolve_block_parameter(&tx, head, filter.from_block)?,
+                resolve_block_parameter(&tx, head, filter.to_block)?.min(head),
             )
-            .await
-            .is_ok());
-        assert!(txn.commit().await.is_ok());
-
-        Ok(mock_db)
-    }
-
-    async fn start_server(db: Arc<impl MutableKV>) -> anyhow::Result<(u16, HttpServerHandle)> {
-        let server = HttpServerBuilder::default().build("localhost:0")?;
-        let port = server.local_addr()?.port();
-        let _server_handle = server.start(EthApiServerImpl { db }.into_rpc())?;
-        Ok((port, _server_handle))
-    }
-
-    #[tokio::test]
-    async fn test_block_number() {
-        let db = init_mock_chain().await.unwrap();
-        let (port, _handle) = start_server(db.clone()).await.unwrap();
-
-        let client = HttpClientBuilder::default()
-            .build(format!("http://localhost:{}", port))
-            .unwrap();
-        let block_number = client.block_number().await.unwrap();
-        assert_eq!(block_number, U64::from(0));
-
-        let txn = db.begin_mutable().await.unwrap();
-        txn.set(tables::SyncStage, FINISH, BlockNumber(0xff))
-            .await
-            .unwrap();
-        txn.commit().await.unwrap();
-
-        let block_number = client.block_number().await.unwrap();
-        assert_eq!(block_number, U64::from(0xff));
-    }
-
-    #[tokio::test]
-    async fn test_get_balance() {
-        let db = init_mock_chain().await.unwrap();
-        let (port, _handle) = start_server(db.clone()).await.unwrap();
-
-        let client = HttpClientBuilder::default()
-            .build(format!("http://localhost:{}", port))
-            .unwrap();
-        let block_number = client.block_number().await.unwrap();
-        let balance = client.get_balance(DEADBEEF, block_number).await.unwrap();
-        assert_eq!(balance, ETHER);
-
-        let txn = db.begin_mutable().await.unwrap();
-        txn.set(
-            tables::Account,
-            DEADBEEF,
-            Account {
-                balance: ETHER.as_u256() * 100,
-                ..Default::default()
-            },
-        )
-        .await
-        .unwrap();
-        txn.commit().await.unwrap();
+        };
+        if from > to {
+            return Ok(vec![]);
+        }
+
+        if let Some(max_blocks) = self.log_limits.max_blocks {
+            if to.0 - from.0 >= max_blocks {
+                return Err(limit_exceeded(
+                    format!("query spans more than {} blocks", max_blocks),
+                    Some((from, from + (max_blocks - 1))),
+                ));
+            }
+        }
+
+        let mut out = vec![];
+        let mut block = None;
+        let mut log_index = 0_u64;
+        for res in tx.cursor(tables::Log)?.walk(Some((from, TxIndex(0)))) {
+            let ((block_number, transaction_index), logs) = res?;
+            if block_number > to {
+                break;
+            }
+            if block.map(|(number, _)| number) != Some(block_number) {
+                block = Some((block_number, None));
+                log_index = 0;
+            }
+
+            for log in logs {
+                if matcher.matches(log.address, &log.topics) {
+                    if let Some(max_results) = self.log_limits.max_results {
+                        if out.len() == max_results {
+                            return Err(limit_exceeded(
+                                format!("query returned more than {} results", max_results),
+                                (block_number > from)
+                                    .then(|| (from, BlockNumber(block_number.0 - 1))),
+                            ));
+                        }
+                    }
+
+                    let block_hash = match block {
+                        Some((_, Some(hash))) => hash,
+                        _ => {
+                            let hash = chain::canonical_hash::read(&tx, block_number)?.ok_or_else(
+                                || format_err!("no canonical hash for block {}", block_number),
+                            )?;
+                            block = Some((block_number, Some(hash)));
+                            hash
+                        }
+                    };
+                    out.push(RpcLog {
+                        address: log.address,
+                        topics: log.topics,
+                        data: log.data,
+                        block_number: block_number.0.into(),
+                        block_hash,
+                        transaction_index: transaction_index.0.into(),
+                        log_index: log_index.into(),
+                        removed: false,
+                    });
+                }
+                log_index += 1;
+            }
+        }
+
+        Ok(out)
+    }
+
+    #[instrument(name = "eth_newPendingTransactionFilter", skip(self))]
+    async fn new_pending_transaction_filter(&self, full: Option<bool>) -> RpcResult<U64> {
+        Ok(self.pending_filters.install(full.unwrap_or(false)))
+    }
+
+    #[instrument(name = "eth_getFilterChanges", skip(self))]
+    async fn get_filter_changes(&self, id: U64) -> RpcResult<FilterChanges> {
+        Ok(self
+            .pending_filters
+            .changes(id)
+            .ok_or_else(|| format_err!("filter not found"))?)
+    }
 
-        let balance = client.get_balance(DEADBEEF, block_number).await.unwrap();
-        assert_eq!(balance, ETHER.as_u256() * 100);
+    #[instrument(name = "eth_uninstallFilter", skip(self))]
+    async fn uninstall_filter(&self, id: U64) -> RpcResult<bool> {
+        Ok(self.pending_filters.uninstall(id))
     }
 }
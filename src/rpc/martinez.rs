@@ -0,0 +1,380 @@
+use super::{
+    common::{
+        replay_block, rpc_receipts, rpc_transaction, RpcBlockHeader, RpcReceipt, RpcTransaction,
+    },
+    errors::ensure_available,
+    HeadSource,
+};
+use crate::{
+    accessors::{chain, prune::PruneTarget},
+    downloader::top_block_estimate::PublishedTopBlockEstimate,
+    execution::tracer::{CallFrame, CallFrameTracer, CallKind, CreationTracer, MessageKind},
+    hexbytes,
+    kv::{mdbx::*, replica::ReadReplica, tables},
+    models::*,
+    observability::Observability,
+    stagedsync::{
+        freeze::{DbFreeze, FreezeStatus},
+        stages::*,
+    },
+    stages::read_address_appearances,
+};
+use anyhow::format_err;
+use async_trait::async_trait;
+use bytes::Bytes;
+use ethnum::U256;
+use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::*;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcCallType {
+    Call,
+    CallCode,
+    DelegateCall,
+    StaticCall,
+    Create,
+}
+
+/// Basic call trace entry. There is no result: the EVM does not report where calls end.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcCallFrame {
+    pub depth: U64,
+    #[serde(rename = "type")]
+    pub call_type: RpcCallType,
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub gas: U64,
+    #[serde(with = "hexbytes")]
+    pub input: Bytes,
+}
+
+impl From<CallFrame> for RpcCallFrame {
+    fn from(frame: CallFrame) -> Self {
+        Self {
+            depth: u64::from(frame.depth).into(),
+            call_type: match frame.kind {
+                MessageKind::Create => RpcCallType::Create,
+                MessageKind::Call { call_kind, .. } => match call_kind {
+                    CallKind::Call => RpcCallType::Call,
+                    CallKind::CallCode => RpcCallType::CallCode,
+                    CallKind::DelegateCall => RpcCallType::DelegateCall,
+                    CallKind::StaticCall => RpcCallType::StaticCall,
+                },
+            },
+            from: frame.from,
+            to: frame.to,
+            value: frame.value,
+            gas: frame.gas.into(),
+            input: frame.input,
+        }
+    }
+}
+
+/// Everything a block explorer shows on a block page.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlockBundle {
+    pub header: RpcBlockHeader,
+    pub transactions: Vec<RpcTransaction>,
+    pub receipts: Vec<RpcReceipt>,
+    /// Messages sent by each transaction, by transaction index.
+    pub calls: Vec<Vec<RpcCallFrame>>,
+}
+
+/// Header, transactions, receipts and call frames of block `block_hash`/`block_number`,
+/// `None` if it is not known locally.
+fn read_block_bundle<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    block_hash: H256,
+    block_number: BlockNumber,
+) -> anyhow::Result<Option<RpcBlockBundle>> {
+    let mut tracer = (CreationTracer::default(), CallFrameTracer::default());
+    let replayed = match replay_block(tx, block_hash, block_number, None, &mut tracer)? {
+        Some(replayed) => replayed,
+        None => return Ok(None),
+    };
+    let (creation_tracer, frame_tracer) = tracer;
+
+    let receipts = rpc_receipts(&replayed, creation_tracer.into_creations());
+    let transactions = replayed
+        .body
+        .transactions
+        .iter()
+        .zip(&replayed.transactions)
+        .zip(&replayed.receipts)
+        .enumerate()
+        .map(|(index, ((txn, msg), receipt))| {
+            rpc_transaction(
+                txn,
+                msg.hash(),
+                Some((replayed.hash, block_number, index)),
+                receipt.deposit_nonce,
+            )
+        })
+        .collect();
+    let calls = frame_tracer
+        .into_frames()
+        .into_iter()
+        .map(|frames| frames.into_iter().map(RpcCallFrame::from).collect())
+        .collect();
+
+    Ok(Some(RpcBlockBundle {
+        header: RpcBlockHeader::new(replayed.hash, replayed.header),
+        transactions,
+        receipts,
+        calls,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncProgress {
+    pub starting_block: U64,
+    pub current_block: U64,
+    pub highest_block: U64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum SyncStatus {
+    NotSyncing(bool),
+    Syncing(SyncProgress),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeStatus {
+    pub headers_block: U64,
+    pub current_block: U64,
+    pub highest_block: U64,
+    pub syncing: bool,
+    pub database: FreezeStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcInternalTransfers {
+    pub transaction_index: U64,
+    pub transfers: Vec<InternalTransfer>,
+}
+
+/// Current and highest known block: synced head, and the best of downloaded headers
+/// and the head estimated from peer announcements, as published by the node or last committed.
+pub(crate) fn sync_heads<E: EnvironmentKind>(
+    db: &MdbxEnvironment<E>,
+    head: HeadSource,
+    top_block_estimate: &PublishedTopBlockEstimate,
+) -> anyhow::Result<(BlockNumber, BlockNumber, BlockNumber)> {
+    let tx = db.begin()?;
+
+    let current_block = head.resolve(&tx)?;
+    let headers_block = tx
+        .get(tables::SyncStage, HEADERS)?
+        .unwrap_or(BlockNumber(0));
+    let highest_block = [
+        top_block_estimate.read()?,
+        chain::top_block_estimate::read(&tx)?,
+        Some(headers_block),
+    ]
+    .into_iter()
+    .flatten()
+    .max()
+    .unwrap_or(BlockNumber(0));
+
+    Ok((current_block, headers_block, highest_block))
+}
+
+#[rpc(server, namespace = "martinez")]
+pub trait MartinezApi {
+    #[method(name = "status")]
+    async fn status(&self) -> RpcResult<NodeStatus>;
+    /// Replace the log filter of this process, e.g. `martinez=info,martinez_rpc=debug`.
+    #[method(name = "setLogFilter")]
+    async fn set_log_filter(&self, directives: String) -> RpcResult<bool>;
+    /// Ask the node to stop writing to the database, e.g. to snapshot its files, while this
+    /// process keeps serving. The node stops at its next commit, when the status turns `frozen`.
+    #[method(name = "freezeDatabase")]
+    async fn freeze_database(&self) -> RpcResult<FreezeStatus>;
+    /// Let the node write to the database again.
+    #[method(name = "unfreezeDatabase")]
+    async fn unfreeze_database(&self) -> RpcResult<FreezeStatus>;
+    /// Chain spec the database was initialized with: chain id, genesis hash, consensus engine
+    /// and fork blocks.
+    #[method(name = "chainConfig")]
+    async fn chain_config(&self) -> RpcResult<ChainSpecSummary>;
+    /// Value transfers made inside contract execution of a block, for transactions that made
+    /// any. Empty unless the node indexes them with `--index-internal-transfers`,
+    /// `null` if the block is past the head.
+    #[method(name = "getInternalTransfers")]
+    async fn get_internal_transfers(
+        &self,
+        block_number: BlockNumber,
+    ) -> RpcResult<Option<Vec<RpcInternalTransfers>>>;
+    /// Header, transactions, receipts and call frames of a block in one response, replacing
+    /// the dozens of calls a block explorer would otherwise make per block. `null` if the block
+    /// is past the head.
+    #[method(name = "getBlockBundle")]
+    async fn get_block_bundle(
+        &self,
+        block_number: BlockNumber,
+    ) -> RpcResult<Option<RpcBlockBundle>>;
+    /// Blocks between `from_block` and `to_block`, up to the head, in which `address` sent or
+    /// received a call, emitted or was named in a log, or created or was created as a contract.
+    #[method(name = "getAddressAppearances")]
+    async fn get_address_appearances(
+        &self,
+        address: Address,
+        from_block: BlockNumber,
+        to_block: BlockNumber,
+    ) -> RpcResult<Vec<U64>>;
+}
+
+pub struct MartinezApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    pub db: Arc<ReadReplica<E>>,
+    pub head: HeadSource,
+    pub observability: Arc<Observability>,
+    pub freeze: DbFreeze,
+    pub top_block_estimate: PublishedTopBlockEstimate,
+}
+
+#[async_trait]
+impl<E> MartinezApiServer for MartinezApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    #[instrument(name = "martinez_status", skip(self))]
+    async fn status(&self) -> RpcResult<NodeStatus> {
+        let (current_block, headers_block, highest_block) =
+            sync_heads(&*self.db.get().await?, self.head, &self.top_block_estimate)?;
+
+        Ok(NodeStatus {
+            headers_block: headers_block.0.into(),
+            current_block: current_block.0.into(),
+            highest_block: highest_block.0.into(),
+            syncing: current_block < highest_block,
+            database: self.freeze.status(),
+        })
+    }
+
+    #[instrument(name = "martinez_setLogFilter", skip(self))]
+    async fn set_log_filter(&self, directives: String) -> RpcResult<bool> {
+        self.observability.set_filter(&directives)?;
+
+        Ok(true)
+    }
+
+    #[instrument(name = "martinez_freezeDatabase", skip(self))]
+    async fn freeze_database(&self) -> RpcResult<FreezeStatus> {
+        self.freeze.request()?;
+
+        Ok(self.freeze.status())
+    }
+
+    #[instrument(name = "martinez_unfreezeDatabase", skip(self))]
+    async fn unfreeze_database(&self) -> RpcResult<FreezeStatus> {
+        self.freeze.release()?;
+
+        Ok(self.freeze.status())
+    }
+
+    #[instrument(name = "martinez_chainConfig", skip(self))]
+    async fn chain_config(&self) -> RpcResult<ChainSpecSummary> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        let genesis_hash = chain::canonical_hash::read(&tx, BlockNumber(0))?
+            .ok_or_else(|| format_err!("Genesis block absent"))?;
+        let chain_spec =
+            chain::chain_config::read(&tx)?.ok_or_else(|| format_err!("No chain config"))?;
+
+        Ok(chain_spec.summary(genesis_hash))
+    }
+
+    #[instrument(name = "martinez_getInternalTransfers", skip(self))]
+    async fn get_internal_transfers(
+        &self,
+        block_number: BlockNumber,
+    ) -> RpcResult<Option<Vec<RpcInternalTransfers>>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        if block_number > self.head.resolve(&tx)? {
+            return Ok(None);
+        }
+
+        let mut out = vec![];
+        for res in tx
+            .cursor(tables::InternalTransfer)?
+            .walk(Some((block_number, TxIndex(0))))
+        {
+            let ((number, index), transfers) = res?;
+            if number != block_number {
+                break;
+            }
+
+            out.push(RpcInternalTransfers {
+                transaction_index: index.0.into(),
+                transfers,
+            });
+        }
+
+        Ok(Some(out))
+    }
+
+    #[instrument(name = "martinez_getBlockBundle", skip(self))]
+    async fn get_block_bundle(
+        &self,
+        block_number: BlockNumber,
+    ) -> RpcResult<Option<RpcBlockBundle>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        if block_number > self.head.resolve(&tx)? {
+            return Ok(None);
+        }
+
+        // The block is replayed on top of its parent.
+        ensure_available(&tx, PruneTarget::Blocks, block_number)?;
+        ensure_available(
+            &tx,
+            PruneTarget::History,
+            BlockNumber(block_number.0.saturating_sub(1)),
+        )?;
+
+        match chain::block_id::resolve(&tx, block_number)? {
+            Some(block) => Ok(read_block_bundle(&tx, block.hash, block.number)?),
+            None => Ok(None),
+        }
+    }
+
+    #[instrument(name = "martinez_getAddressAppearances", skip(self))]
+    async fn get_address_appearances(
+        &self,
+        address: Address,
+        from_block: BlockNumber,
+        to_block: BlockNumber,
+    ) -> RpcResult<Vec<U64>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        let to_block = std::cmp::min(to_block, self.head.resolve(&tx)?);
+        if from_block > to_block {
+            return Ok(vec![]);
+        }
+
+        Ok(
+            read_address_appearances(&tx, address, from_block..=to_block)?
+                .into_iter()
+                .map(|block_number| block_number.0.into())
+                .collect(),
+        )
+    }
+}
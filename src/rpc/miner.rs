@@ -0,0 +1,27 @@
+use crate::models::*;
+use async_trait::async_trait;
+use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::*;
+
+/// Settings for blocks built by this node.
+#[rpc(server, namespace = "miner")]
+pub trait MinerApi {
+    /// Change the fee recipient reported by `eth_coinbase`.
+    #[method(name = "setEtherbase")]
+    async fn set_etherbase(&self, etherbase: Address) -> RpcResult<bool>;
+}
+
+pub struct MinerApiServerImpl {
+    pub etherbase: Arc<Mutex<Option<Address>>>,
+}
+
+#[async_trait]
+impl MinerApiServer for MinerApiServerImpl {
+    #[instrument(name = "miner_setEtherbase", skip(self))]
+    async fn set_etherbase(&self, etherbase: Address) -> RpcResult<bool> {
+        *self.etherbase.lock() = Some(etherbase);
+        Ok(true)
+    }
+}
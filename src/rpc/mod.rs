@@ -1 +1,214 @@
+//! JSON-RPC API served by `martinez-rpc` over a database written by a running node.
+//!
+//! Each module holds one namespace, its server implementation and the types it returns.
+//! [`common`] and [`errors`] are shared by several of them.
+pub mod bor;
+pub mod common;
+pub mod debug;
+pub mod errors;
 pub mod eth;
+pub mod martinez;
+pub mod miner;
+pub mod net;
+pub mod ots;
+pub mod proxy;
+pub mod pubsub;
+pub mod trace;
+pub mod txpool;
+
+use crate::{
+    kv::{
+        object::{DynTransaction, DynTransactionExt},
+        tables,
+    },
+    models::*,
+    stagedsync::stages::*,
+};
+use anyhow::format_err;
+use jsonrpsee::{core::server::rpc_module::Methods, RpcModule};
+use std::str::FromStr;
+
+/// Where the chain head comes from.
+///
+/// A datadir written by another process may have no Finish progress at all, or update it
+/// long after the stages it is served from, so read-only deployments can follow canonical
+/// headers instead.
+#[derive(Clone, Copy, Debug)]
+pub enum HeadSource {
+    /// Finish stage progress, or the highest canonical header while there is none.
+    Finish,
+    /// Highest canonical header.
+    Canonical,
+    /// Highest canonical header, but no further than the progress of the stage.
+    Stage(StageId),
+}
+
+/// Stages the head can be capped at.
+const HEAD_STAGES: &[StageId] = &[
+    HEADERS,
+    BLOCK_HASHES,
+    BODIES,
+    SENDERS,
+    EXECUTION,
+    HASH_STATE,
+    INTERMEDIATE_HASHES,
+    ACCOUNT_HISTORY_INDEX,
+    STORAGE_HISTORY_INDEX,
+    LOG_INDEX,
+    CALL_TRACES,
+    CONTRACT_CREATORS,
+    ADDRESS_APPEARANCES,
+    TX_LOOKUP,
+];
+
+impl FromStr for HeadSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "finish" => Ok(Self::Finish),
+            "canonical" => Ok(Self::Canonical),
+            _ => HEAD_STAGES
+                .iter()
+                .find(|stage| stage.0.eq_ignore_ascii_case(s))
+                .map(|&stage| Self::Stage(stage))
+                .ok_or_else(|| {
+                    format_err!("expected finish, canonical or a stage name, got {}", s)
+                }),
+        }
+    }
+}
+
+impl HeadSource {
+    pub fn resolve(self, tx: &dyn DynTransaction) -> anyhow::Result<BlockNumber> {
+        let canonical = || -> anyhow::Result<BlockNumber> {
+            Ok(tx
+                .last(tables::CanonicalHeader)?
+                .map(|(number, _)| number)
+                .unwrap_or(BlockNumber(0)))
+        };
+
+        Ok(match self {
+            Self::Finish => match tx.get(tables::SyncStage, FINISH)? {
+                Some(progress) => progress,
+                None => canonical()?,
+            },
+            Self::Canonical => canonical()?,
+            Self::Stage(stage) => std::cmp::min(
+                canonical()?,
+                tx.get(tables::SyncStage, stage)?.unwrap_or(BlockNumber(0)),
+            ),
+        })
+    }
+}
+
+/// Methods grouped by the prefix of their name, enabled separately on each transport.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Namespace {
+    Eth,
+    Net,
+    Admin,
+    Martinez,
+    Ots,
+    Debug,
+    Trace,
+    Bor,
+    TxPool,
+    Miner,
+}
+
+const NAMESPACES: &[Namespace] = &[
+    Namespace::Eth,
+    Namespace::Net,
+    Namespace::Admin,
+    Namespace::Martinez,
+    Namespace::Ots,
+    Namespace::Debug,
+    Namespace::Trace,
+    Namespace::Bor,
+    Namespace::TxPool,
+    Namespace::Miner,
+];
+
+impl Namespace {
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Eth => "eth",
+            Self::Net => "net",
+            Self::Admin => "admin",
+            Self::Martinez => "martinez",
+            Self::Ots => "ots",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+            Self::Bor => "bor",
+            Self::TxPool => "txpool",
+            Self::Miner => "miner",
+        }
+    }
+
+    /// Namespace of a method, by its name.
+    pub(crate) fn of(method: &str) -> Option<Self> {
+        let (prefix, _) = method.split_once('_')?;
+        prefix.parse().ok()
+    }
+}
+
+impl FromStr for Namespace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NAMESPACES
+            .iter()
+            .find(|namespace| namespace.prefix() == s)
+            .copied()
+            .ok_or_else(|| {
+                format_err!(
+                    "expected one of {}, got {}",
+                    NAMESPACES
+                        .iter()
+                        .map(|namespace| namespace.prefix())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    s
+                )
+            })
+    }
+}
+
+/// Methods served for a database, built once and mounted on every transport with the namespaces
+/// enabled on it.
+#[derive(Default)]
+pub struct RpcRegistry {
+    /// With whether they are subscriptions, which need a connection to push notifications over.
+    methods: Vec<(Namespace, bool, Methods)>,
+}
+
+impl RpcRegistry {
+    pub fn register(&mut self, namespace: Namespace, methods: impl Into<Methods>) {
+        self.methods.push((namespace, false, methods.into()));
+    }
+
+    pub fn register_subscriptions(&mut self, namespace: Namespace, methods: impl Into<Methods>) {
+        self.methods.push((namespace, true, methods.into()));
+    }
+
+    pub(crate) fn contains(&self, method: &str) -> bool {
+        self.methods
+            .iter()
+            .any(|(_, _, methods)| methods.method(method).is_some())
+    }
+
+    /// Methods of the `enabled` namespaces, all of them if empty, with their subscriptions if
+    /// `connection` is set.
+    pub fn module(&self, enabled: &[Namespace], connection: bool) -> anyhow::Result<RpcModule<()>> {
+        let mut module = RpcModule::new(());
+        for (namespace, subscriptions, methods) in &self.methods {
+            if (enabled.is_empty() || enabled.contains(namespace)) && (connection || !subscriptions)
+            {
+                module.merge(methods.clone())?;
+            }
+        }
+
+        Ok(module)
+    }
+}
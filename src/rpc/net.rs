@@ -0,0 +1,245 @@
+use crate::{
+    models::*,
+    sentry::{
+        peer_tracker::PeerTracker,
+        sentry_client::{PeerEvent, PeerId},
+        server::SentryProtocols,
+    },
+};
+use anyhow::format_err;
+use async_trait::async_trait;
+use ethereum_interfaces::{
+    sentry::{sentry_client::SentryClient, PeerByIdRequest, PeerCountRequest},
+    types::{NodeInfoReply, PeerInfo},
+};
+use jsonrpsee::{core::RpcResult, proc_macros::rpc, SubscriptionSink};
+use serde::{Deserialize, Serialize};
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+use tracing::*;
+
+#[derive(Debug, Serialize)]
+pub struct NodePorts {
+    pub discovery: u32,
+    pub listener: u32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminNodeInfo {
+    pub name: String,
+    pub enode: String,
+    pub ports: NodePorts,
+    pub listen_addr: String,
+    pub protocols: SentryProtocols,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerNetwork {
+    pub local_address: String,
+    pub remote_address: String,
+    pub inbound: bool,
+    pub trusted: bool,
+    #[serde(rename = "static")]
+    pub is_static: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminPeerInfo {
+    pub id: String,
+    pub name: String,
+    pub enode: String,
+    pub caps: Vec<String>,
+    pub network: PeerNetwork,
+}
+
+impl From<PeerInfo> for AdminPeerInfo {
+    fn from(peer: PeerInfo) -> Self {
+        Self {
+            id: peer.id,
+            name: peer.name,
+            enode: peer.enode,
+            caps: peer.caps,
+            network: PeerNetwork {
+                local_address: peer.conn_local_addr,
+                remote_address: peer.conn_remote_addr,
+                inbound: peer.conn_is_inbound,
+                trusted: peer.conn_is_trusted,
+                is_static: peer.conn_is_static,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AdminSubscriptionKind {
+    PeerEvents,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminPeerEvent {
+    /// `add`, `drop` or `penalize` for a peer, `sentryDisconnect` when all of them are gone.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<PeerId>,
+}
+
+impl From<PeerEvent> for AdminPeerEvent {
+    fn from(event: PeerEvent) -> Self {
+        let (kind, peer) = match event {
+            PeerEvent::Connected(peer_id) => ("add", Some(peer_id)),
+            PeerEvent::Disconnected(peer_id) => ("drop", Some(peer_id)),
+            PeerEvent::Penalized(peer_id) => ("penalize", Some(peer_id)),
+            PeerEvent::SentryDisconnected => ("sentryDisconnect", None),
+        };
+        Self { kind, peer }
+    }
+}
+
+/// Peers and protocols as the node's sentry sees them, in the namespace of each method.
+#[rpc(server, namespace = "net")]
+pub trait SentryNetApi {
+    #[method(name = "peerCount")]
+    async fn peer_count(&self) -> RpcResult<U64>;
+}
+
+#[rpc(server, namespace = "eth")]
+pub trait SentryEthApi {
+    /// Highest eth protocol version the sentry runs.
+    #[method(name = "protocolVersion")]
+    async fn protocol_version(&self) -> RpcResult<U64>;
+}
+
+#[rpc(server, namespace = "admin")]
+pub trait SentryAdminApi {
+    /// Identity of the node, with its peers broken down by negotiated protocol.
+    #[method(name = "nodeInfo")]
+    async fn node_info(&self) -> RpcResult<AdminNodeInfo>;
+    /// Peers connected to the sentry.
+    #[method(name = "peers")]
+    async fn peers(&self) -> RpcResult<Vec<AdminPeerInfo>>;
+    /// Pushes peers connecting to, disconnecting from and penalized by the sentry to the
+    /// subscriber.
+    #[subscription(
+        name = "subscribe" => "subscription",
+        unsubscribe = "unsubscribe",
+        item = AdminPeerEvent
+    )]
+    fn subscribe(&self, kind: AdminSubscriptionKind) -> RpcResult<()>;
+}
+
+#[derive(Clone)]
+pub struct SentryApiServerImpl {
+    pub client: SentryClient<Channel>,
+    /// Following the peer events of the sentry.
+    pub peers: PeerTracker,
+}
+
+impl SentryApiServerImpl {
+    async fn sentry_node_info(&self) -> anyhow::Result<(NodeInfoReply, SentryProtocols)> {
+        let reply = self.client.clone().node_info(()).await?.into_inner();
+        let protocols = serde_json::from_slice(&reply.protocols)
+            .map_err(|e| format_err!("sentry does not report its protocols: {}", e))?;
+
+        Ok((reply, protocols))
+    }
+}
+
+#[async_trait]
+impl SentryNetApiServer for SentryApiServerImpl {
+    #[instrument(name = "net_peerCount", skip(self))]
+    async fn peer_count(&self) -> RpcResult<U64> {
+        let reply = self
+            .client
+            .clone()
+            .peer_count(PeerCountRequest {})
+            .await
+            .map_err(anyhow::Error::from)?
+            .into_inner();
+
+        Ok(reply.count.into())
+    }
+}
+
+#[async_trait]
+impl SentryEthApiServer for SentryApiServerImpl {
+    #[instrument(name = "eth_protocolVersion", skip(self))]
+    async fn protocol_version(&self) -> RpcResult<U64> {
+        let (_, protocols) = self.sentry_node_info().await?;
+
+        let version = protocols
+            .version("eth")
+            .ok_or_else(|| format_err!("sentry does not run the eth protocol"))?;
+
+        Ok(U64::from(version as u64))
+    }
+}
+
+#[async_trait]
+impl SentryAdminApiServer for SentryApiServerImpl {
+    #[instrument(name = "admin_nodeInfo", skip(self))]
+    async fn node_info(&self) -> RpcResult<AdminNodeInfo> {
+        let (reply, protocols) = self.sentry_node_info().await?;
+        let ports = reply.ports.unwrap_or_default();
+
+        Ok(AdminNodeInfo {
+            name: reply.name,
+            enode: reply.enode,
+            ports: NodePorts {
+                discovery: ports.discovery,
+                listener: ports.listener,
+            },
+            listen_addr: reply.listener_addr,
+            protocols,
+        })
+    }
+
+    #[instrument(name = "admin_peers", skip(self))]
+    async fn peers(&self) -> RpcResult<Vec<AdminPeerInfo>> {
+        let mut peer_ids = self
+            .peers
+            .connected_peers()
+            .read()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        peer_ids.sort();
+
+        let mut peers = Vec::with_capacity(peer_ids.len());
+        for peer_id in peer_ids {
+            let reply = self
+                .client
+                .clone()
+                .peer_by_id(PeerByIdRequest {
+                    peer_id: Some(peer_id.into()),
+                })
+                .await
+                .map_err(anyhow::Error::from)?
+                .into_inner();
+            // Gone since.
+            if let Some(peer) = reply.peer {
+                peers.push(peer.into());
+            }
+        }
+
+        Ok(peers)
+    }
+
+    fn subscribe(&self, mut sink: SubscriptionSink, kind: AdminSubscriptionKind) -> RpcResult<()> {
+        let AdminSubscriptionKind::PeerEvents = kind;
+        let mut events = self.peers.peer_events();
+        tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                if let Err(e) = sink.send(&AdminPeerEvent::from(event)) {
+                    debug!("Subscription closed: {}", e);
+                    return;
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use super::{common::read_block_transaction, HeadSource};
+use crate::{
+    accessors::chain,
+    kv::{mdbx::*, replica::ReadReplica},
+    models::*,
+    stages::read_contract_creator,
+};
+use async_trait::async_trait;
+use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::*;
+
+#[derive(Debug, Serialize)]
+pub struct ContractCreator {
+    pub hash: H256,
+    pub creator: Address,
+}
+
+/// Subset of the Otterscan API.
+#[rpc(server, namespace = "ots")]
+pub trait OtsApi {
+    /// Transaction that deployed the contract at `address` and the account that executed the
+    /// creation, which is a factory contract for CREATE/CREATE2 made by other contracts.
+    #[method(name = "getContractCreator")]
+    async fn get_contract_creator(&self, address: Address) -> RpcResult<Option<ContractCreator>>;
+}
+
+pub struct OtsApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    pub db: Arc<ReadReplica<E>>,
+    pub head: HeadSource,
+}
+
+#[async_trait]
+impl<E> OtsApiServer for OtsApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    #[instrument(name = "ots_getContractCreator", skip(self))]
+    async fn get_contract_creator(&self, address: Address) -> RpcResult<Option<ContractCreator>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        if let Some((block_number, index, creator)) = read_contract_creator(&tx, address)? {
+            if block_number <= self.head.resolve(&tx)? {
+                if let Some(block) = chain::block_id::resolve(&tx, block_number)? {
+                    if let Some(msg) =
+                        read_block_transaction(&tx, block.hash, block.number, index.0)?
+                    {
+                        return Ok(Some(ContractCreator {
+                            hash: msg.hash(),
+                            creator,
+                        }));
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
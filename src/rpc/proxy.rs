@@ -0,0 +1,195 @@
+use super::{
+    common::{BlockParameter, BlockTag},
+    HeadSource, Namespace, RpcRegistry,
+};
+use crate::{
+    accessors::chain::last_forkchoice,
+    kv::{mdbx::*, replica::ReadReplica},
+    models::*,
+};
+use anyhow::format_err;
+use jsonrpsee::{
+    core::{client::ClientT, Error as RpcError},
+    http_client::HttpClient,
+    types::{error::CallError, ParamsSer},
+    RpcModule,
+};
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::*;
+
+/// Methods forwarded to the upstream as-is unless they are served locally.
+const PROXIED_METHODS: &[&str] = &[
+    "eth_chainId",
+    "eth_getBlockByNumber",
+    "eth_getBlockByHash",
+    "eth_getCode",
+    "eth_getStorageAt",
+    "eth_estimateGas",
+    "net_version",
+];
+
+/// Position of the block parameter of a proxied method, `None` for those that take none.
+fn block_parameter_index(method: &str) -> Option<usize> {
+    match method {
+        "eth_getBlockByNumber" => Some(0),
+        "eth_getCode" | "eth_estimateGas" => Some(1),
+        "eth_getStorageAt" => Some(2),
+        _ => None,
+    }
+}
+
+/// How long a response of the upstream can be served again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Validity {
+    /// Not at all: the block is named by a tag, which the upstream resolves as it sees fit.
+    Never,
+    /// While the served head stays at this block, as a reorg may replace the block asked for.
+    Head(BlockNumber),
+    /// For good: the block is named by hash, or is finalized.
+    Forever,
+}
+
+/// Responses of the upstream to proxied requests, by method and params, kept for those asked
+/// again while they cannot have changed.
+pub struct ProxyCache<E>
+where
+    E: EnvironmentKind,
+{
+    db: Arc<ReadReplica<E>>,
+    head: HeadSource,
+    entries: Option<Mutex<LruCache<(&'static str, String), (Validity, serde_json::Value)>>>,
+}
+
+impl<E> ProxyCache<E>
+where
+    E: EnvironmentKind,
+{
+    /// Keeping the latest `entries` responses, none if 0.
+    pub fn new(db: Arc<ReadReplica<E>>, head: HeadSource, entries: usize) -> Self {
+        Self {
+            db,
+            head,
+            entries: (entries > 0).then(|| Mutex::new(LruCache::new(entries))),
+        }
+    }
+
+    /// How long the response to `method` with `params` stays valid.
+    async fn validity(&self, method: &str, params: &serde_json::Value) -> anyhow::Result<Validity> {
+        let block = match (block_parameter_index(method), params) {
+            // The chain id, the network id and blocks by hash do not change.
+            (None, _) => return Ok(Validity::Forever),
+            (Some(index), serde_json::Value::Array(params)) => params.get(index),
+            _ => return Ok(Validity::Never),
+        };
+        let block = match block {
+            Some(serde_json::Value::Object(block)) => {
+                if block.contains_key("blockHash") {
+                    return Ok(Validity::Forever);
+                }
+                block.get("blockNumber")
+            }
+            block => block,
+        };
+        let number = match block.cloned().map(serde_json::from_value) {
+            Some(Ok(BlockParameter::Number(number))) => BlockNumber(number.as_u64()),
+            Some(Ok(BlockParameter::Tag(BlockTag::Earliest))) => return Ok(Validity::Forever),
+            // Missing, `latest` by default, or another tag.
+            _ => return Ok(Validity::Never),
+        };
+
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+        if let Some(finalized) =
+            last_forkchoice::read_canonical_number(&tx, last_forkchoice::FINALIZED_BLOCK_HASH)?
+        {
+            if number <= finalized {
+                return Ok(Validity::Forever);
+            }
+        }
+
+        Ok(Validity::Head(self.head.resolve(&tx)?))
+    }
+
+    fn get(&self, key: &(&'static str, String), validity: Validity) -> Option<serde_json::Value> {
+        if validity == Validity::Never {
+            return None;
+        }
+
+        match self.entries.as_ref()?.lock().get(key) {
+            Some((cached, res)) if *cached == validity => Some(res.clone()),
+            _ => None,
+        }
+    }
+
+    fn put(&self, key: (&'static str, String), validity: Validity, res: &serde_json::Value) {
+        // Nothing found may be found later, once the upstream has it.
+        if validity == Validity::Never || res.is_null() {
+            return;
+        }
+
+        if let Some(entries) = &self.entries {
+            entries.lock().put(key, (validity, res.clone()));
+        }
+    }
+}
+
+/// Register upstream forwarders for every proxied method the module does not serve itself,
+/// answering repeated requests from `cache`.
+pub fn proxy_to_upstream<E: EnvironmentKind>(
+    registry: &mut RpcRegistry,
+    upstream: Arc<HttpClient>,
+    cache: Arc<ProxyCache<E>>,
+) -> anyhow::Result<()> {
+    for &method in PROXIED_METHODS {
+        if registry.contains(method) {
+            continue;
+        }
+
+        let namespace = Namespace::of(method)
+            .ok_or_else(|| format_err!("no namespace for proxied method {}", method))?;
+        let upstream = upstream.clone();
+        let cache = cache.clone();
+        let mut module = RpcModule::new(());
+        module.register_async_method(method, move |params, _| {
+            let upstream = upstream.clone();
+            let cache = cache.clone();
+            async move {
+                // Forwarded as given, positional or named.
+                let params = params.parse::<serde_json::Value>()?;
+                let validity = cache.validity(method, &params).await?;
+                let key = (method, params.to_string());
+                if let Some(res) = cache.get(&key, validity) {
+                    return Ok(res);
+                }
+
+                let params = match &params {
+                    serde_json::Value::Null => None,
+                    serde_json::Value::Array(params) => Some(ParamsSer::Array(params.clone())),
+                    serde_json::Value::Object(params) => Some(ParamsSer::Map(
+                        params
+                            .iter()
+                            .map(|(name, value)| (name.as_str(), value.clone()))
+                            .collect(),
+                    )),
+                    _ => {
+                        return Err(RpcError::Call(CallError::InvalidParams(format_err!(
+                            "params must be an array or an object"
+                        ))))
+                    }
+                };
+                let res = upstream
+                    .request::<serde_json::Value>(method, params)
+                    .await?;
+                cache.put(key, validity, &res);
+
+                Ok(res)
+            }
+        })?;
+        registry.register(namespace, module);
+        debug!("Proxying {} to upstream", method);
+    }
+
+    Ok(())
+}
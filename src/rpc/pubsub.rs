@@ -0,0 +1,282 @@
+use super::{
+    common::{LogLimits, LogMatcher, RpcBlockHeader, RpcLog, RpcTransaction},
+    HeadSource,
+};
+use crate::{
+    accessors::chain,
+    kv::{mdbx::*, replica::ReadReplica, tables},
+    models::*,
+};
+use anyhow::format_err;
+use jsonrpsee::{
+    core::{Error as RpcError, RpcResult},
+    proc_macros::rpc,
+    types::error::CallError,
+    SubscriptionSink,
+};
+use serde::Deserialize;
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, broadcast::error::RecvError};
+use tracing::*;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SubscriptionKind {
+    NewHeads,
+    Logs,
+    NewPendingTransactions,
+}
+
+#[rpc(server, namespace = "eth")]
+pub trait EthPubSubApi {
+    /// Pushes headers of new canonical blocks, logs matching a [`LogFilter`](super::common::LogFilter), or transactions
+    /// submitted through this server, as hashes unless `true` follows, to the subscriber.
+    #[subscription(
+        name = "subscribe" => "subscription",
+        unsubscribe = "unsubscribe",
+        item = serde_json::Value
+    )]
+    fn subscribe(&self, kind: SubscriptionKind, params: Option<serde_json::Value>)
+        -> RpcResult<()>;
+}
+
+pub struct EthPubSubApiServerImpl {
+    pub notifications: ChainNotifications,
+    pub log_limits: LogLimits,
+}
+
+/// What a subscription is sent.
+enum SubscriptionFilter {
+    NewHeads,
+    Logs(LogMatcher),
+    PendingTransactions { full: bool },
+}
+
+impl EthPubSubApiServer for EthPubSubApiServerImpl {
+    fn subscribe(
+        &self,
+        mut sink: SubscriptionSink,
+        kind: SubscriptionKind,
+        params: Option<serde_json::Value>,
+    ) -> RpcResult<()> {
+        let invalid_params =
+            |e: serde_json::Error| RpcError::Call(CallError::InvalidParams(e.into()));
+        let filter = match (kind, params) {
+            (SubscriptionKind::NewHeads, _) => SubscriptionFilter::NewHeads,
+            (SubscriptionKind::Logs, params) => SubscriptionFilter::Logs(LogMatcher::new(
+                &params
+                    .map(serde_json::from_value)
+                    .transpose()
+                    .map_err(invalid_params)?
+                    .unwrap_or_default(),
+                &self.log_limits,
+            )?),
+            (SubscriptionKind::NewPendingTransactions, params) => {
+                SubscriptionFilter::PendingTransactions {
+                    full: params
+                        .map(serde_json::from_value)
+                        .transpose()
+                        .map_err(invalid_params)?
+                        .unwrap_or(false),
+                }
+            }
+        };
+
+        let mut receiver = self.notifications.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let notification = match receiver.recv().await {
+                    Ok(notification) => notification,
+                    Err(RecvError::Lagged(missed)) => {
+                        debug!("Subscriber fell behind, {} notifications dropped", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                let sent = match (&filter, &*notification) {
+                    (SubscriptionFilter::NewHeads, ChainNotification::NewHead(header)) => {
+                        sink.send(header)
+                    }
+                    (SubscriptionFilter::Logs(matcher), ChainNotification::Log(log))
+                        if matcher.matches(log.address, &log.topics) =>
+                    {
+                        sink.send(log)
+                    }
+                    (
+                        SubscriptionFilter::PendingTransactions { full },
+                        ChainNotification::PendingTransaction(txn),
+                    ) => {
+                        if *full {
+                            sink.send(txn)
+                        } else {
+                            sink.send(&txn.hash)
+                        }
+                    }
+                    _ => continue,
+                };
+                if let Err(e) = sent {
+                    debug!("Subscription closed: {}", e);
+                    return;
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Notifications buffered for each subscription before it misses some.
+const NOTIFICATION_CAPACITY: usize = 4096;
+
+/// Canonical blocks kept announced, so that subscribers are told of their logs being removed if
+/// a reorg takes them out. The head moving further at once skips the blocks in between.
+const ANNOUNCED_BLOCKS: usize = 128;
+
+/// How often the head is checked for blocks to announce.
+const HEAD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Push notification of `eth_subscribe`.
+#[derive(Debug)]
+pub enum ChainNotification {
+    NewHead(RpcBlockHeader),
+    Log(RpcLog),
+    PendingTransaction(RpcTransaction),
+}
+
+/// Fans out notifications to the `eth_subscribe` subscriptions of a chain.
+#[derive(Clone, Debug)]
+pub struct ChainNotifications(broadcast::Sender<Arc<ChainNotification>>);
+
+impl Default for ChainNotifications {
+    fn default() -> Self {
+        Self(broadcast::channel(NOTIFICATION_CAPACITY).0)
+    }
+}
+
+impl ChainNotifications {
+    fn subscribe(&self) -> broadcast::Receiver<Arc<ChainNotification>> {
+        self.0.subscribe()
+    }
+
+    pub(crate) fn send(&self, notification: ChainNotification) {
+        // No subscribers is not an error.
+        let _ = self.0.send(Arc::new(notification));
+    }
+}
+
+/// Block announced to subscribers, with the logs to announce as removed if it leaves the
+/// canonical chain.
+struct AnnouncedBlock {
+    key: BlockKey,
+    logs: Vec<RpcLog>,
+}
+
+/// Logs of canonical block `key`.
+fn read_block_logs<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    key: BlockKey,
+) -> anyhow::Result<Vec<RpcLog>> {
+    let mut out = vec![];
+    for res in tx.cursor(tables::Log)?.walk(Some((key.number, TxIndex(0)))) {
+        let ((block_number, transaction_index), logs) = res?;
+        if block_number != key.number {
+            break;
+        }
+
+        for log in logs {
+            out.push(RpcLog {
+                address: log.address,
+                topics: log.topics,
+                data: log.data,
+                block_number: key.number.0.into(),
+                block_hash: key.hash,
+                transaction_index: transaction_index.0.into(),
+                log_index: (out.len() as u64).into(),
+                removed: false,
+            });
+        }
+    }
+
+    Ok(out)
+}
+
+/// Announce the logs of blocks that left the canonical chain, newest first, then the headers
+/// and logs of blocks that joined it up to `head`.
+fn announce_head<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    head: HeadSource,
+    announced: &mut VecDeque<AnnouncedBlock>,
+    notifications: &ChainNotifications,
+) -> anyhow::Result<()> {
+    let head = head.resolve(tx)?;
+
+    while let Some(block) = announced.pop_back() {
+        if block.key.number <= head
+            && chain::canonical_hash::read(tx, block.key.number)? == Some(block.key.hash)
+        {
+            announced.push_back(block);
+            break;
+        }
+
+        for log in block.logs.into_iter().rev() {
+            notifications.send(ChainNotification::Log(RpcLog {
+                removed: true,
+                ..log
+            }));
+        }
+    }
+
+    let from = announced
+        .back()
+        .map(|block| block.key.number + 1)
+        .unwrap_or(head)
+        .max(BlockNumber(
+            (head.0 + 1).saturating_sub(ANNOUNCED_BLOCKS as u64),
+        ));
+    for number in from..=head {
+        let hash = chain::canonical_hash::read(tx, number)?
+            .ok_or_else(|| format_err!("no canonical hash for block {}", number))?;
+        let key = BlockKey::new(number, hash);
+        let header = chain::header::read(tx, key)?
+            .ok_or_else(|| format_err!("no header for block {}", key))?;
+        let logs = read_block_logs(tx, key)?;
+
+        notifications.send(ChainNotification::NewHead(RpcBlockHeader::new(
+            hash, header,
+        )));
+        for log in &logs {
+            notifications.send(ChainNotification::Log(log.clone()));
+        }
+
+        announced.push_back(AnnouncedBlock { key, logs });
+        if announced.len() > ANNOUNCED_BLOCKS {
+            announced.pop_front();
+        }
+    }
+
+    Ok(())
+}
+
+/// Follow the served head, by default the Finish stage progress of the writing node, and notify
+/// subscribers of the blocks that join or leave the canonical chain.
+pub async fn announce_heads<E: EnvironmentKind>(
+    db: Arc<ReadReplica<E>>,
+    head: HeadSource,
+    notifications: ChainNotifications,
+) {
+    let mut announced = VecDeque::new();
+    loop {
+        let res = async {
+            let env = db.get().await?;
+            let tx = env.begin()?;
+            announce_head(&tx, head, &mut announced, &notifications)
+        }
+        .await;
+        if let Err(e) = res {
+            warn!("Failed to announce new blocks: {}", e);
+        }
+
+        tokio::time::sleep(HEAD_POLL_INTERVAL).await;
+    }
+}
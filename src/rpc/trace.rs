@@ -0,0 +1,585 @@
+use super::{
+    common::{replay_block, resolve_block_parameter, BlockParameter, LogLimits},
+    errors::{ensure_available, execution_error, limit_exceeded},
+    eth::{call_at_block, CallRequest},
+    HeadSource,
+};
+use crate::{
+    accessors::{chain, prune::PruneTarget},
+    bitmapdb,
+    consensus::RewardKind,
+    execution::{
+        evm::StatusCode,
+        outcome::ExecutionOutcome,
+        tracer::{CallKind, CallTree, CallTreeTracer, MessageKind, RewardTracer},
+    },
+    hexbytes,
+    kv::{
+        mdbx::*,
+        replica::ReadReplica,
+        tables::{self, BitmapKey},
+        traits::Table,
+    },
+    models::*,
+    stagedsync::stages::CALL_TRACES,
+};
+use anyhow::format_err;
+use async_trait::async_trait;
+use bytes::Bytes;
+use croaring::Treemap as RoaringTreemap;
+use ethnum::U256;
+use jsonrpsee::{
+    core::{Error as RpcError, RpcResult},
+    proc_macros::rpc,
+    types::error::CallError,
+};
+use serde::{Deserialize, Serialize};
+use std::{ops::RangeInclusive, sync::Arc};
+use tracing::*;
+
+/// Block and transaction of a Parity-style trace, none for simulated calls.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTraceLocation {
+    pub block_hash: Option<H256>,
+    pub block_number: Option<u64>,
+    pub transaction_hash: Option<H256>,
+    pub transaction_position: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RpcTraceAction {
+    #[serde(rename_all = "camelCase")]
+    Call {
+        call_type: &'static str,
+        from: Address,
+        to: Address,
+        value: U256,
+        gas: U64,
+        #[serde(with = "hexbytes")]
+        input: Bytes,
+    },
+    Create {
+        from: Address,
+        value: U256,
+        gas: U64,
+        #[serde(with = "hexbytes")]
+        init: Bytes,
+    },
+    #[serde(rename_all = "camelCase")]
+    Suicide {
+        address: Address,
+        refund_address: Address,
+        balance: U256,
+    },
+    #[serde(rename_all = "camelCase")]
+    Reward {
+        author: Address,
+        reward_type: &'static str,
+        value: U256,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RpcTraceResult {
+    #[serde(rename_all = "camelCase")]
+    Call {
+        gas_used: U64,
+        #[serde(with = "hexbytes")]
+        output: Bytes,
+    },
+    #[serde(rename_all = "camelCase")]
+    Create {
+        gas_used: U64,
+        #[serde(with = "hexbytes")]
+        code: Bytes,
+        address: Address,
+    },
+}
+
+/// Call, creation, self-destruct or reward, as traced by OpenEthereum's `trace_` methods.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTrace {
+    pub action: RpcTraceAction,
+    #[serde(flatten)]
+    pub location: RpcTraceLocation,
+    /// `None` for failed messages, self-destructs and rewards.
+    pub result: Option<RpcTraceResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub subtraces: usize,
+    /// Index of the message among the ones sent by its caller, for every caller above it.
+    pub trace_address: Vec<usize>,
+    #[serde(rename = "type")]
+    pub trace_type: &'static str,
+}
+
+impl RpcTrace {
+    /// Sender and recipient the trace is filtered by.
+    pub(crate) fn addresses(&self) -> (Option<Address>, Option<Address>) {
+        match (&self.action, &self.result) {
+            (RpcTraceAction::Call { from, to, .. }, _) => (Some(*from), Some(*to)),
+            (RpcTraceAction::Create { from, .. }, Some(RpcTraceResult::Create { address, .. })) => {
+                (Some(*from), Some(*address))
+            }
+            (RpcTraceAction::Create { from, .. }, _) => (Some(*from), None),
+            (
+                RpcTraceAction::Suicide {
+                    address,
+                    refund_address,
+                    ..
+                },
+                _,
+            ) => (Some(*address), Some(*refund_address)),
+            (RpcTraceAction::Reward { author, .. }, _) => (None, Some(*author)),
+        }
+    }
+}
+
+/// Error of a failed message as reported by OpenEthereum.
+fn trace_error(status_code: StatusCode) -> String {
+    match status_code {
+        StatusCode::Revert => "Reverted".to_string(),
+        StatusCode::OutOfGas => "Out of gas".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Traces of `call` and the messages under it, depth first, at `trace_address`.
+fn flatten_call_tree(
+    call: CallTree,
+    trace_address: Vec<usize>,
+    location: RpcTraceLocation,
+    out: &mut Vec<RpcTrace>,
+) {
+    let success = call.status_code == StatusCode::Success;
+    let gas = call.frame.gas.into();
+    let gas_used = call.gas_used.into();
+    let (trace_type, action, result) = match call.frame.kind {
+        MessageKind::Create => (
+            "create",
+            RpcTraceAction::Create {
+                from: call.frame.from,
+                value: call.frame.value,
+                gas,
+                init: call.frame.input,
+            },
+            success.then(|| RpcTraceResult::Create {
+                gas_used,
+                code: call.output,
+                address: call.frame.to,
+            }),
+        ),
+        MessageKind::Call { call_kind, .. } => (
+            "call",
+            RpcTraceAction::Call {
+                call_type: match call_kind {
+                    CallKind::Call => "call",
+                    CallKind::CallCode => "callcode",
+                    CallKind::DelegateCall => "delegatecall",
+                    CallKind::StaticCall => "staticcall",
+                },
+                from: call.frame.from,
+                to: call.frame.to,
+                value: call.frame.value,
+                gas,
+                input: call.frame.input,
+            },
+            success.then(|| RpcTraceResult::Call {
+                gas_used,
+                output: call.output,
+            }),
+        ),
+    };
+    out.push(RpcTrace {
+        action,
+        location,
+        result,
+        error: (!success).then(|| trace_error(call.status_code)),
+        subtraces: call.calls.len() + usize::from(call.self_destruct.is_some()),
+        trace_address: trace_address.clone(),
+        trace_type,
+    });
+
+    let subtrace_address = |index| {
+        let mut address = trace_address.clone();
+        address.push(index);
+        address
+    };
+    let calls = call.calls.len();
+    for (index, subcall) in call.calls.into_iter().enumerate() {
+        flatten_call_tree(subcall, subtrace_address(index), location, out);
+    }
+    if let Some(self_destruct) = call.self_destruct {
+        out.push(RpcTrace {
+            action: RpcTraceAction::Suicide {
+                address: call.frame.to,
+                refund_address: self_destruct.beneficiary,
+                balance: self_destruct.balance,
+            },
+            location,
+            result: None,
+            error: None,
+            subtraces: 0,
+            trace_address: subtrace_address(calls),
+            trace_type: "suicide",
+        });
+    }
+}
+
+/// Traces of block `block_hash`/`block_number`, up to transaction `last_transaction` if given
+/// or else with the rewards. `None` if the block is not known locally.
+fn read_block_traces<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    block_hash: H256,
+    block_number: BlockNumber,
+    last_transaction: Option<H256>,
+) -> anyhow::Result<Option<Vec<RpcTrace>>> {
+    let mut tracer = (CallTreeTracer::default(), RewardTracer::default());
+    let replayed = match replay_block(tx, block_hash, block_number, last_transaction, &mut tracer)?
+    {
+        Some(replayed) => replayed,
+        None => return Ok(None),
+    };
+    let (call_tracer, reward_tracer) = tracer;
+
+    let mut out = vec![];
+    for (index, (tree, msg)) in call_tracer
+        .into_trees()
+        .into_iter()
+        .zip(&replayed.transactions)
+        .enumerate()
+    {
+        if let Some(tree) = tree {
+            let location = RpcTraceLocation {
+                block_hash: Some(block_hash),
+                block_number: Some(block_number.0),
+                transaction_hash: Some(msg.hash()),
+                transaction_position: Some(index as u64),
+            };
+            flatten_call_tree(tree, vec![], location, &mut out);
+        }
+    }
+    for (author, kind, value) in reward_tracer.into_rewards() {
+        out.push(RpcTrace {
+            action: RpcTraceAction::Reward {
+                author,
+                reward_type: match kind {
+                    RewardKind::Block => "block",
+                    RewardKind::Uncle => "uncle",
+                },
+                value,
+            },
+            location: RpcTraceLocation {
+                block_hash: Some(block_hash),
+                block_number: Some(block_number.0),
+                ..Default::default()
+            },
+            result: None,
+            error: None,
+            subtraces: 0,
+            trace_address: vec![],
+            trace_type: "reward",
+        });
+    }
+
+    Ok(Some(out))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceFilter {
+    pub from_block: Option<BlockParameter>,
+    pub to_block: Option<BlockParameter>,
+    /// Senders to match, any if empty.
+    #[serde(default)]
+    pub from_address: Vec<Address>,
+    /// Recipients, created contracts and reward authors to match, any if empty.
+    #[serde(default)]
+    pub to_address: Vec<Address>,
+    /// Matching traces to skip.
+    pub after: Option<usize>,
+    /// Most matching traces to return.
+    pub count: Option<usize>,
+}
+
+impl TraceFilter {
+    pub(crate) fn matches(&self, trace: &RpcTrace) -> bool {
+        let (from, to) = trace.addresses();
+        let matches = |addresses: &[Address], address: Option<Address>| {
+            addresses.is_empty() || matches!(address, Some(address) if addresses.contains(&address))
+        };
+
+        matches(&self.from_address, from) && matches(&self.to_address, to)
+    }
+}
+
+/// Output of a call simulated with `trace_call`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTraceResults {
+    #[serde(with = "hexbytes")]
+    pub output: Bytes,
+    /// Not supported, always `None`.
+    pub state_diff: Option<()>,
+    pub trace: Vec<RpcTrace>,
+    /// Not supported, always `None`.
+    pub vm_trace: Option<()>,
+}
+
+/// Refuse to trace `block_number` if the state it executes on, or its call traces, were pruned.
+pub(crate) fn ensure_traceable<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RO, E>,
+    block_number: BlockNumber,
+) -> RpcResult<()> {
+    ensure_available(
+        tx,
+        PruneTarget::History,
+        BlockNumber(block_number.0.saturating_sub(1)),
+    )?;
+    ensure_available(tx, PruneTarget::CallTraces, block_number)
+}
+
+fn read_indexed_calls<T, E>(
+    tx: &MdbxTransaction<'_, RO, E>,
+    table: T,
+    addresses: &[Address],
+    range: RangeInclusive<BlockNumber>,
+) -> anyhow::Result<Option<RoaringTreemap>>
+where
+    T: Table<Key = BitmapKey<Address>, Value = RoaringTreemap, SeekKey = BitmapKey<Address>> + Copy,
+    E: EnvironmentKind,
+{
+    if addresses.is_empty() {
+        return Ok(None);
+    }
+
+    let mut blocks = RoaringTreemap::create();
+    for &address in addresses {
+        blocks = blocks | bitmapdb::get(tx, table, address, range.clone())?;
+    }
+
+    Ok(Some(blocks))
+}
+
+/// Blocks within `range` with calls from and to the addresses of `filter`, from the call trace
+/// index. `None` if the filter matches any address, or the index does not cover `range` yet.
+fn read_filtered_blocks<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RO, E>,
+    filter: &TraceFilter,
+    range: RangeInclusive<BlockNumber>,
+) -> anyhow::Result<Option<Vec<BlockNumber>>> {
+    if CALL_TRACES.get_progress(tx)?.unwrap_or_default() < *range.end() {
+        return Ok(None);
+    }
+
+    let froms = read_indexed_calls(
+        tx,
+        tables::CallFromIndex,
+        &filter.from_address,
+        range.clone(),
+    )?;
+    let tos = read_indexed_calls(tx, tables::CallToIndex, &filter.to_address, range.clone())?;
+    let blocks = match (froms, tos) {
+        (Some(froms), Some(tos)) => froms.iter().filter(|&b| tos.contains(b)).collect(),
+        (Some(blocks), None) | (None, Some(blocks)) => blocks.iter().collect::<Vec<_>>(),
+        (None, None) => return Ok(None),
+    };
+
+    Ok(Some(
+        blocks
+            .into_iter()
+            .map(BlockNumber)
+            .filter(|block_number| range.contains(block_number))
+            .collect(),
+    ))
+}
+
+/// OpenEthereum's flat call traces.
+#[rpc(server, namespace = "trace")]
+pub trait TraceApi {
+    #[method(name = "transaction")]
+    async fn transaction(&self, hash: H256) -> RpcResult<Option<Vec<RpcTrace>>>;
+    /// Traces of every transaction of the block, then its rewards.
+    #[method(name = "block")]
+    async fn block(&self, block: BlockParameter) -> RpcResult<Option<Vec<RpcTrace>>>;
+    /// Traces of a block range, within the limits of `eth_getLogs`.
+    #[method(name = "filter")]
+    async fn filter(&self, filter: TraceFilter) -> RpcResult<Vec<RpcTrace>>;
+    /// Only `trace` is supported in `trace_types`.
+    #[method(name = "call")]
+    async fn call(
+        &self,
+        call: CallRequest,
+        trace_types: Vec<String>,
+        block: Option<BlockParameter>,
+    ) -> RpcResult<RpcTraceResults>;
+}
+
+pub struct TraceApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    pub db: Arc<ReadReplica<E>>,
+    pub head: HeadSource,
+    pub log_limits: LogLimits,
+}
+
+#[async_trait]
+impl<E> TraceApiServer for TraceApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    #[instrument(name = "trace_transaction", skip(self))]
+    async fn transaction(&self, hash: H256) -> RpcResult<Option<Vec<RpcTrace>>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+
+        let block = match chain::tl::read(&tx, hash)? {
+            Some(block_number) if block_number <= self.head.resolve(&tx)? => {
+                chain::block_id::resolve(&tx, block_number)?
+            }
+            _ => None,
+        };
+        let block = match block {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        ensure_traceable(&tx, block.number)?;
+
+        Ok(
+            read_block_traces(&tx, block.hash, block.number, Some(hash))?.map(|traces| {
+                traces
+                    .into_iter()
+                    .filter(|trace| trace.location.transaction_hash == Some(hash))
+                    .collect()
+            }),
+        )
+    }
+
+    #[instrument(name = "trace_block", skip(self))]
+    async fn block(&self, block: BlockParameter) -> RpcResult<Option<Vec<RpcTrace>>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+        let head = self.head.resolve(&tx)?;
+
+        let block_number = resolve_block_parameter(&tx, head, Some(block))?;
+        if block_number > head {
+            return Ok(None);
+        }
+        ensure_traceable(&tx, block_number)?;
+
+        Ok(match chain::canonical_hash::read(&tx, block_number)? {
+            Some(block_hash) => read_block_traces(&tx, block_hash, block_number, None)?,
+            None => None,
+        })
+    }
+
+    #[instrument(name = "trace_filter", skip(self))]
+    async fn filter(&self, filter: TraceFilter) -> RpcResult<Vec<RpcTrace>> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+        let head = self.head.resolve(&tx)?;
+
+        let from = resolve_block_parameter(&tx, head, filter.from_block)?;
+        let to = resolve_block_parameter(&tx, head, filter.to_block)?.min(head);
+        if from > to {
+            return Ok(vec![]);
+        }
+
+        if let Some(max_blocks) = self.log_limits.max_blocks {
+            if to.0 - from.0 >= max_blocks {
+                return Err(limit_exceeded(
+                    format!("query spans more than {} blocks", max_blocks),
+                    Some((from, from + (max_blocks - 1))),
+                ));
+            }
+        }
+        ensure_traceable(&tx, from)?;
+
+        // Blocks without calls to match are not replayed, if the index tells which those are.
+        let blocks = match read_filtered_blocks(&tx, &filter, from..=to)? {
+            Some(blocks) => blocks,
+            None => (from..=to).collect(),
+        };
+
+        let mut skip = filter.after.unwrap_or(0);
+        let mut out = vec![];
+        for block_number in blocks {
+            let block_hash = chain::canonical_hash::read(&tx, block_number)?
+                .ok_or_else(|| format_err!("no canonical hash for block {}", block_number))?;
+            let traces = read_block_traces(&tx, block_hash, block_number, None)?
+                .ok_or_else(|| format_err!("Block {} not found", block_number))?;
+
+            for trace in traces {
+                if !filter.matches(&trace) {
+                    continue;
+                }
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                if filter.count == Some(out.len()) {
+                    return Ok(out);
+                }
+                if let Some(max_results) = self.log_limits.max_results {
+                    if out.len() == max_results {
+                        return Err(limit_exceeded(
+                            format!("query returned more than {} results", max_results),
+                            (block_number > from).then(|| (from, BlockNumber(block_number.0 - 1))),
+                        ));
+                    }
+                }
+
+                out.push(trace);
+            }
+        }
+
+        Ok(out)
+    }
+
+    #[instrument(name = "trace_call", skip(self))]
+    async fn call(
+        &self,
+        call: CallRequest,
+        trace_types: Vec<String>,
+        block: Option<BlockParameter>,
+    ) -> RpcResult<RpcTraceResults> {
+        if let Some(trace_type) = trace_types.iter().find(|trace_type| *trace_type != "trace") {
+            return Err(RpcError::Call(CallError::InvalidParams(format_err!(
+                "trace type {} is not supported",
+                trace_type
+            ))));
+        }
+
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+        let head = self.head.resolve(&tx)?;
+        let block_number = resolve_block_parameter(&tx, head, block)?;
+        ensure_available(&tx, PruneTarget::History, block_number)?;
+
+        let mut tracer = CallTreeTracer::default();
+        let outcome = call_at_block(&tx, call, block_number, Some(&mut tracer))?
+            .ok_or_else(|| format_err!("Block {} not found", block_number))?;
+        if matches!(outcome, ExecutionOutcome::InvalidTransaction(_)) {
+            return Err(execution_error(outcome));
+        }
+
+        let mut trace = vec![];
+        if !trace_types.is_empty() {
+            if let Some(tree) = tracer.into_trees().pop().flatten() {
+                flatten_call_tree(tree, vec![], RpcTraceLocation::default(), &mut trace);
+            }
+        }
+
+        Ok(RpcTraceResults {
+            output: outcome.output().cloned().unwrap_or_default(),
+            state_diff: None,
+            trace,
+            vm_trace: None,
+        })
+    }
+}
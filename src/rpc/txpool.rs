@@ -0,0 +1,414 @@
+use super::{
+    common::{rpc_transaction, RawTransaction, RpcTransaction},
+    HeadSource,
+};
+use crate::{
+    accessors::chain,
+    consensus,
+    crypto::TrieEncode,
+    kv::{mdbx::*, replica::ReadReplica},
+    models::*,
+    sentry::messages::TransactionsMessage,
+    txpool::{PoolContext, PoolLimits, PooledTransaction, TxPool},
+};
+use anyhow::format_err;
+use async_trait::async_trait;
+use ethereum_interfaces::sentry::{sentry_client::SentryClient, MessageId, OutboundMessageData};
+use ethnum::U256;
+use jsonrpsee::{
+    core::{client::ClientT, RpcResult},
+    http_client::HttpClient,
+    proc_macros::rpc,
+    types::ParamsSer,
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
+use tonic::transport::Channel;
+use tracing::*;
+
+/// Limits on transactions submitted through this server, checked before they are pooled.
+#[derive(Clone, Copy, Debug)]
+pub struct SubmissionLimits {
+    /// Highest fee in wei, `None` for no cap.
+    pub tx_fee_cap: Option<U256>,
+    /// Lowest priority fee per gas in wei.
+    pub min_gas_price: U256,
+    /// Of the pool they wait in until mined.
+    pub pool: PoolLimits,
+}
+
+impl SubmissionLimits {
+    pub fn new(tx_fee_cap: f64, min_gas_price: u64, pool: PoolLimits) -> anyhow::Result<Self> {
+        if !(tx_fee_cap >= 0.0 && tx_fee_cap.is_finite()) {
+            return Err(format_err!("invalid tx fee cap {}", tx_fee_cap));
+        }
+
+        Ok(Self {
+            tx_fee_cap: (tx_fee_cap > 0.0).then(|| U256::from((tx_fee_cap * 1e18) as u128)),
+            min_gas_price: min_gas_price.as_u256(),
+            pool,
+        })
+    }
+
+    pub(crate) fn check(&self, message: &Message) -> anyhow::Result<()> {
+        if let Some(cap) = self.tx_fee_cap {
+            let fee = message
+                .max_fee_per_gas()
+                .checked_mul(message.gas_limit().as_u256());
+            if fee.map(|fee| fee > cap).unwrap_or(true) {
+                return Err(format_err!(
+                    "tx fee ({} wei) exceeds the configured cap ({} wei)",
+                    fee.map(|fee| fee.to_string())
+                        .unwrap_or_else(|| "overflow".to_string()),
+                    cap
+                ));
+            }
+        }
+
+        let tip = message.max_priority_fee_per_gas();
+        if tip < self.min_gas_price {
+            return Err(format_err!(
+                "transaction underpriced: priority fee {} wei below minimum {} wei",
+                tip,
+                self.min_gas_price
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Transactions submitted through this server, pooled until mined or replaced and rebroadcast in
+/// the meantime.
+#[derive(Debug)]
+pub struct LocalTransactions(Mutex<TxPool>);
+
+impl LocalTransactions {
+    pub fn new(limits: PoolLimits) -> Self {
+        Self(Mutex::new(TxPool::new(limits)))
+    }
+
+    /// Validate `msg` from `sender` for the block after `head` and pool it. Returns the hash of
+    /// the transaction it replaced, if any.
+    pub(crate) fn add<E: EnvironmentKind>(
+        &self,
+        tx: &MdbxTransaction<'_, mdbx::RO, E>,
+        head: BlockNumber,
+        msg: MessageWithSignature,
+        sender: Address,
+    ) -> anyhow::Result<Option<H256>> {
+        let chain_spec =
+            chain::chain_config::read(tx)?.ok_or_else(|| format_err!("No chain config"))?;
+        let hash = chain::canonical_hash::read(tx, head)?
+            .ok_or_else(|| format_err!("no canonical header for block #{}", head))?;
+        let header = chain::header::read(tx, BlockKey::new(head, hash))?
+            .ok_or_else(|| format_err!("no header for block #{}", head))?;
+        let number = BlockNumber(head.0 + 1);
+        let block_spec = chain_spec.collect_block_spec(number);
+        let context = PoolContext {
+            chain_id: block_spec.params.chain_id,
+            revision: block_spec.revision,
+            base_fee_per_gas: consensus::expected_base_fee_per_gas(
+                chain_spec.consensus.eip1559_block,
+                number,
+                &header,
+            ),
+            gas_limit: header.gas_limit,
+        };
+        let (nonce, balance) = crate::accessors::state::account::read(tx, sender, None)?
+            .map(|acc| (acc.nonce, acc.balance))
+            .unwrap_or((0, U256::ZERO));
+
+        Ok(self.0.lock().add(msg, sender, nonce, balance, &context)?)
+    }
+
+    pub(crate) fn remove(&self, hash: H256) {
+        self.0.lock().remove(hash);
+    }
+
+    pub fn addresses(&self) -> BTreeSet<Address> {
+        self.0.lock().senders()
+    }
+
+    pub fn nonces(&self, sender: Address) -> BTreeSet<u64> {
+        self.0.lock().nonces(sender)
+    }
+
+    /// Drop transactions whose nonce was used on chain, by them or by others.
+    fn prune<E: EnvironmentKind>(
+        &self,
+        tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    ) -> anyhow::Result<()> {
+        for hash in self.0.lock().prune(|sender| next_nonce(tx, sender))? {
+            debug!(
+                "Local transaction {:?} is mined, no longer tracking it",
+                hash
+            );
+        }
+
+        Ok(())
+    }
+
+    fn pending(&self) -> Vec<MessageWithSignature> {
+        self.0.lock().iter().map(|tx| tx.message.clone()).collect()
+    }
+
+    /// Pooled transactions by sender and nonce, `pending` and `queued` for `txpool_content`.
+    fn content<E: EnvironmentKind>(
+        &self,
+        tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    ) -> anyhow::Result<TxPoolContent> {
+        let pool = self.0.lock();
+        let content = pool.content(|sender| next_nonce(tx, sender))?;
+        let by_nonce = |txs: BTreeMap<Address, Vec<&PooledTransaction>>| {
+            txs.into_iter()
+                .map(|(sender, txs)| {
+                    let txs = txs
+                        .into_iter()
+                        .map(|tx| {
+                            (
+                                tx.message.nonce().to_string(),
+                                rpc_transaction(
+                                    &MessageWithSender {
+                                        message: tx.message.message.clone(),
+                                        sender,
+                                    },
+                                    tx.hash,
+                                    None,
+                                    None,
+                                ),
+                            )
+                        })
+                        .collect();
+                    (sender, txs)
+                })
+                .collect()
+        };
+
+        Ok(TxPoolContent {
+            pending: by_nonce(content.pending),
+            queued: by_nonce(content.queued),
+        })
+    }
+
+    pub(crate) fn status<E: EnvironmentKind>(
+        &self,
+        tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    ) -> anyhow::Result<TxPoolStatus> {
+        let pool = self.0.lock();
+        let content = pool.content(|sender| next_nonce(tx, sender))?;
+        let count = |txs: BTreeMap<Address, Vec<&PooledTransaction>>| {
+            U64::from(txs.values().map(Vec::len).sum::<usize>())
+        };
+
+        Ok(TxPoolStatus {
+            pending: count(content.pending),
+            queued: count(content.queued),
+        })
+    }
+}
+
+/// Next nonce of `address` at the latest committed state.
+fn next_nonce<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    address: Address,
+) -> anyhow::Result<u64> {
+    Ok(crate::accessors::state::account::read(tx, address, None)?
+        .map(|acc| acc.nonce)
+        .unwrap_or(0))
+}
+
+/// Most transactions sent to peers in one `Transactions` message.
+const BROADCAST_BATCH: usize = 128;
+
+/// Send `transactions` to every peer of the sentry. Returns the number of peers reached.
+pub(crate) async fn broadcast_transactions(
+    sentry: &SentryClient<Channel>,
+    transactions: Vec<MessageWithSignature>,
+) -> anyhow::Result<usize> {
+    let mut peers = 0;
+    for batch in transactions.chunks(BROADCAST_BATCH) {
+        let data = OutboundMessageData {
+            id: MessageId::Transactions66 as i32,
+            data: rlp::encode(&TransactionsMessage {
+                transactions: batch.to_vec(),
+            })
+            .into(),
+        };
+        let sent = sentry.clone().send_message_to_all(data).await?.into_inner();
+        peers = peers.max(sent.peers.len());
+    }
+
+    Ok(peers)
+}
+
+/// Nonces of a sender: the next one by the chain, the next one after its consecutive
+/// local transactions, and the nonces missing between those and its later local transactions.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NonceStatus {
+    pub latest: U64,
+    pub pending: U64,
+    pub gaps: Vec<U64>,
+}
+
+impl NonceStatus {
+    pub fn new(latest: u64, local_nonces: &BTreeSet<u64>) -> Self {
+        let mut pending = latest;
+        while local_nonces.contains(&pending) {
+            pending += 1;
+        }
+
+        let highest = local_nonces.iter().next_back().copied().unwrap_or(0);
+        let gaps = (pending..highest)
+            .filter(|nonce| !local_nonces.contains(nonce))
+            .map(U64::from)
+            .collect();
+
+        Self {
+            latest: latest.into(),
+            pending: pending.into(),
+            gaps,
+        }
+    }
+}
+
+pub(crate) fn latest_nonce<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, mdbx::RO, E>,
+    address: Address,
+    block_number: BlockNumber,
+) -> anyhow::Result<u64> {
+    Ok(
+        crate::accessors::state::account::read(tx, address, Some(block_number))?
+            .map(|acc| acc.nonce)
+            .unwrap_or(0),
+    )
+}
+
+#[rpc(server, namespace = "txpool")]
+pub trait TxPoolApi {
+    /// Senders of the transactions submitted through this server that are not mined yet.
+    #[method(name = "localAddresses")]
+    async fn local_addresses(&self) -> RpcResult<BTreeSet<Address>>;
+    /// Nonce status of `address`, `pending` being the next nonce to use.
+    #[method(name = "nonce")]
+    async fn nonce(&self, address: Address) -> RpcResult<NonceStatus>;
+    /// Pooled transactions by sender and nonce: `pending` if they follow on from the sender's
+    /// nonce at the head without a gap, or else `queued`.
+    #[method(name = "content")]
+    async fn content(&self) -> RpcResult<TxPoolContent>;
+    /// Numbers of pending and queued transactions in the pool.
+    #[method(name = "status")]
+    async fn status(&self) -> RpcResult<TxPoolStatus>;
+}
+
+#[derive(Debug, Serialize)]
+pub struct TxPoolContent {
+    pub pending: BTreeMap<Address, BTreeMap<String, RpcTransaction>>,
+    pub queued: BTreeMap<Address, BTreeMap<String, RpcTransaction>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TxPoolStatus {
+    pub pending: U64,
+    pub queued: U64,
+}
+
+pub struct TxPoolApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    pub db: Arc<ReadReplica<E>>,
+    pub head: HeadSource,
+    pub local_transactions: Arc<LocalTransactions>,
+}
+
+#[async_trait]
+impl<E> TxPoolApiServer for TxPoolApiServerImpl<E>
+where
+    E: EnvironmentKind,
+{
+    #[instrument(name = "txpool_localAddresses", skip(self))]
+    async fn local_addresses(&self) -> RpcResult<BTreeSet<Address>> {
+        Ok(self.local_transactions.addresses())
+    }
+
+    #[instrument(name = "txpool_nonce", skip(self))]
+    async fn nonce(&self, address: Address) -> RpcResult<NonceStatus> {
+        let db = self.db.get().await?;
+        let tx = db.begin()?;
+        let head = self.head.resolve(&tx)?;
+
+        Ok(NonceStatus::new(
+            latest_nonce(&tx, address, head)?,
+            &self.local_transactions.nonces(address),
+        ))
+    }
+
+    #[instrument(name = "txpool_content", skip(self))]
+    async fn content(&self) -> RpcResult<TxPoolContent> {
+        Ok(self
+            .local_transactions
+            .content(&self.db.get().await?.begin()?)?)
+    }
+
+    #[instrument(name = "txpool_status", skip(self))]
+    async fn status(&self) -> RpcResult<TxPoolStatus> {
+        Ok(self
+            .local_transactions
+            .status(&self.db.get().await?.begin()?)?)
+    }
+}
+
+/// Resubmit local transactions to the upstream and the sentry's peers every minute until they
+/// are mined.
+pub async fn rebroadcast_local_transactions<E: EnvironmentKind>(
+    db: Arc<ReadReplica<E>>,
+    upstream: Option<Arc<HttpClient>>,
+    sentry: Option<SentryClient<Channel>>,
+    local_transactions: Arc<LocalTransactions>,
+) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+
+        let res = async { local_transactions.prune(&db.get().await?.begin()?) }.await;
+        if let Err(e) = res {
+            warn!("Failed to check local transactions: {}", e);
+            continue;
+        }
+
+        let pending = local_transactions.pending();
+        if pending.is_empty() {
+            continue;
+        }
+
+        if let Some(upstream) = &upstream {
+            for msg in &pending {
+                let hash = msg.hash();
+                let params = match serde_json::to_value(RawTransaction(msg.trie_encode())) {
+                    Ok(raw) => vec![raw],
+                    Err(e) => {
+                        warn!("Failed to encode local transaction {:?}: {}", hash, e);
+                        continue;
+                    }
+                };
+                if let Err(e) = upstream
+                    .request::<H256>("eth_sendRawTransaction", Some(ParamsSer::Array(params)))
+                    .await
+                {
+                    debug!("Rebroadcast of local transaction {:?} failed: {}", hash, e);
+                }
+            }
+        }
+        if let Some(sentry) = &sentry {
+            if let Err(e) = broadcast_transactions(sentry, pending).await {
+                debug!("Rebroadcast of local transactions to peers failed: {}", e);
+            }
+        }
+    }
+}
@@ -2,7 +2,8 @@ use crate::{
     genesis::GenesisState,
     models::{ChainSpec, NetworkId, *},
 };
-use std::collections::HashMap;
+use anyhow::Context;
+use std::{collections::HashMap, path::Path};
 
 pub struct ChainsConfig(HashMap<String, ChainConfig>);
 
@@ -24,6 +25,23 @@ impl ChainConfig {
         }
     }
 
+    /// Load a chain spec file: a geth genesis if it has a `.json` extension, RON otherwise.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read chain spec {}", path.display()))?;
+        let chain_spec = if path.extension().map_or(false, |ext| ext == "json") {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            serde_json::from_str::<GethGenesis>(&contents)?.into_chain_spec(name)?
+        } else {
+            ron::from_str(&contents)?
+        };
+
+        Ok(Self::new(chain_spec))
+    }
+
     pub fn network_id(&self) -> NetworkId {
         self.chain_spec.params.network_id
     }
@@ -40,6 +58,10 @@ impl ChainConfig {
         self.genesis_block_hash
     }
 
+    pub fn summary(&self) -> ChainSpecSummary {
+        self.chain_spec.summary(self.genesis_block_hash)
+    }
+
     pub fn fork_block_numbers(&self) -> Vec<BlockNumber> {
         self.chain_spec.gather_forks().iter().cloned().collect()
     }
@@ -0,0 +1,95 @@
+use super::{messages::EthMessageId, sentry_client::PeerId};
+use ethereum_types::H256;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+
+static DUPLICATES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sentry_duplicate_messages_total",
+        "Data dropped for having been received from the same peer before, by message type",
+        &["message"]
+    )
+    .unwrap()
+});
+
+/// Remembers hashes of data recently received from each peer, so that data a peer sends again
+/// is dropped instead of being processed twice.
+///
+/// Both the peers and the hashes of each peer are bounded, least recently used first out.
+pub struct PeerDedupCache {
+    message: EthMessageId,
+    hashes_per_peer: usize,
+    peers: Mutex<LruCache<PeerId, LruCache<H256, ()>>>,
+}
+
+impl PeerDedupCache {
+    pub fn new(message: EthMessageId, max_peers: usize, hashes_per_peer: usize) -> Self {
+        Self {
+            message,
+            hashes_per_peer,
+            peers: Mutex::new(LruCache::new(max_peers)),
+        }
+    }
+
+    /// Whether `peer_id` already sent `hash`, without recording it.
+    pub fn contains(&self, peer_id: PeerId, hash: H256) -> bool {
+        let seen = self
+            .peers
+            .lock()
+            .get(&peer_id)
+            .map(|hashes| hashes.contains(&hash))
+            .unwrap_or(false);
+        if seen {
+            DUPLICATES
+                .with_label_values(&[&format!("{:?}", self.message)])
+                .inc();
+        }
+        seen
+    }
+
+    /// Records `hash` as received from `peer_id`, returning `false` if it was already.
+    pub fn insert(&self, peer_id: PeerId, hash: H256) -> bool {
+        let mut peers = self.peers.lock();
+        if peers.get(&peer_id).is_none() {
+            peers.put(peer_id, LruCache::new(self.hashes_per_peer));
+        }
+        let is_new = peers.get_mut(&peer_id).unwrap().put(hash, ()).is_none();
+        if !is_new {
+            DUPLICATES
+                .with_label_values(&[&format!("{:?}", self.message)])
+                .inc();
+        }
+        is_new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedup_per_peer() {
+        let cache = PeerDedupCache::new(EthMessageId::NewBlockHashes, 2, 2);
+        let peer = |n| PeerId::repeat_byte(n);
+        let hash = |n| H256::repeat_byte(n);
+
+        assert!(cache.insert(peer(1), hash(1)));
+        assert!(!cache.insert(peer(1), hash(1)));
+        assert!(cache.contains(peer(1), hash(1)));
+        // Other peers are tracked separately.
+        assert!(!cache.contains(peer(2), hash(1)));
+        assert!(cache.insert(peer(2), hash(1)));
+
+        // Oldest hashes of a peer are forgotten first.
+        assert!(cache.insert(peer(1), hash(2)));
+        assert!(cache.insert(peer(1), hash(3)));
+        assert!(!cache.contains(peer(1), hash(1)));
+        assert!(cache.contains(peer(1), hash(3)));
+
+        // So are the least recently active peers.
+        assert!(cache.insert(peer(3), hash(1)));
+        assert!(!cache.contains(peer(2), hash(1)));
+    }
+}
@@ -212,6 +212,7 @@ mod tests {
                         mix_hash: H256(hex!("0000000000000000000000000000000000000000000000000000000000000000")),
                         nonce: H64(hex!("0000000000000000")),
                         base_fee_per_gas: None,
+                        aura_seal: None,
                     }
                 ]
             })
@@ -294,7 +295,8 @@ mod tests {
                         extra_data: vec![0x77, 0x88].into(),
                         mix_hash: H256(hex!("0000000000000000000000000000000000000000000000000000000000000000")),
                         nonce: H64(hex!("0000000000000000")),
-                        base_fee_per_gas: None
+                        base_fee_per_gas: None,
+                        aura_seal: None
                     }]
                 }]
             })
@@ -384,6 +386,7 @@ mod tests {
                             cumulative_gas_used: 1,
                             bloom: Bloom(hex!("00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000")),
                             tx_type: crate::models::TxType::Legacy,
+                            deposit_nonce: None,
                         }],
                     }
                 }]
@@ -0,0 +1,156 @@
+use super::{
+    messages::EthMessageId,
+    peer_tracker::ConnectedPeers,
+    sentry_client::{PeerEvent, PeerId},
+};
+use futures_core::Stream;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+use tokio_stream::StreamExt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Inbound => "inbound",
+            Self::Outbound => "outbound",
+        }
+    }
+}
+
+static MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sentry_messages_total",
+        "eth messages exchanged with peers, by direction and message type",
+        &["direction", "message"]
+    )
+    .unwrap()
+});
+
+static MESSAGE_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sentry_message_bytes_total",
+        "RLP bytes of eth messages exchanged with peers, by direction and message type",
+        &["direction", "message"]
+    )
+    .unwrap()
+});
+
+static PEER_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sentry_peer_bytes_total",
+        "RLP bytes of eth messages exchanged with each connected peer",
+        &["direction", "peer"]
+    )
+    .unwrap()
+});
+
+static PEERS: Lazy<IntGauge> =
+    Lazy::new(|| register_int_gauge!("sentry_peers", "Peers connected to the sentry").unwrap());
+
+static PEER_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sentry_peer_events_total",
+        "Peers connected, disconnected and penalized, and sentry connections lost",
+        &["event"]
+    )
+    .unwrap()
+});
+
+/// Label of `peer_id` in per-peer series.
+pub fn peer_label(peer_id: PeerId) -> String {
+    format!("{:x}", peer_id)
+}
+
+/// Counts a message sent or received through the sentry.
+///
+/// `peer_id` is `None` for outbound messages the sentry picks the peers for.
+pub fn record_message(
+    direction: Direction,
+    id: EthMessageId,
+    size: usize,
+    peer_id: Option<PeerId>,
+) {
+    let message = format!("{:?}", id);
+    let labels = [direction.as_str(), message.as_str()];
+    MESSAGES.with_label_values(&labels).inc();
+    MESSAGE_BYTES.with_label_values(&labels).inc_by(size as u64);
+
+    if let Some(peer_id) = peer_id {
+        PEER_BYTES
+            .with_label_values(&[direction.as_str(), &peer_label(peer_id)])
+            .inc_by(size as u64);
+    }
+}
+
+/// Drops the per-peer series of a peer that is gone.
+pub fn remove_peer(peer_id: PeerId) {
+    let peer = peer_label(peer_id);
+    for direction in [Direction::Inbound, Direction::Outbound] {
+        let _ = PEER_BYTES.remove_label_values(&[direction.as_str(), &peer]);
+    }
+}
+
+/// Drops all per-peer series, e.g. when the sentry connection is lost.
+pub fn remove_all_peers() {
+    PEER_BYTES.reset();
+}
+
+/// Counts `events` and keeps the peer gauge at the size of `connected_peers`, until the events
+/// end.
+pub async fn record_peer_events(
+    mut events: impl Stream<Item = PeerEvent> + Unpin,
+    connected_peers: ConnectedPeers,
+) {
+    PEERS.set(connected_peers.read().len() as i64);
+    while let Some(event) = events.next().await {
+        let event = match event {
+            PeerEvent::Connected(_) => "connected",
+            PeerEvent::Disconnected(_) => "disconnected",
+            PeerEvent::Penalized(_) => "penalized",
+            PeerEvent::SentryDisconnected => "sentry_disconnected",
+        };
+        PEER_EVENTS.with_label_values(&[event]).inc();
+        PEERS.set(connected_peers.read().len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_series_are_removed() {
+        let peer_id = PeerId::repeat_byte(0xab);
+        record_message(
+            Direction::Inbound,
+            EthMessageId::BlockHeaders,
+            100,
+            Some(peer_id),
+        );
+        record_message(
+            Direction::Inbound,
+            EthMessageId::BlockHeaders,
+            50,
+            Some(peer_id),
+        );
+        record_message(Direction::Outbound, EthMessageId::GetBlockHeaders, 10, None);
+
+        let peer = peer_label(peer_id);
+        assert_eq!(PEER_BYTES.with_label_values(&["inbound", &peer]).get(), 150);
+        assert!(
+            MESSAGES
+                .with_label_values(&["inbound", "BlockHeaders"])
+                .get()
+                >= 2
+        );
+
+        remove_peer(peer_id);
+        assert_eq!(PEER_BYTES.with_label_values(&["inbound", &peer]).get(), 0);
+    }
+}
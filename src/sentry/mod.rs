@@ -1,10 +1,17 @@
 pub mod block_id;
 pub mod chain_config;
+pub mod dedup;
 mod message_decoder;
 pub mod messages;
+pub mod metrics;
+pub mod peer_tracker;
 pub mod sentry_address;
 pub mod sentry_client;
 pub mod sentry_client_connector;
 pub mod sentry_client_impl;
 pub mod sentry_client_mock;
+pub mod sentry_client_recorder;
 pub mod sentry_client_reactor;
+pub mod server;
+pub mod snap;
+pub mod snap_server;
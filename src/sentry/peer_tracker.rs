@@ -0,0 +1,150 @@
+use super::{
+    metrics,
+    sentry_address::SentryAddress,
+    sentry_client::{PeerEvent, PeerEventStream, PeerId, SentryClient},
+    sentry_client_impl::SentryClientImpl,
+};
+use futures_core::Stream;
+use futures_util::TryStreamExt;
+use parking_lot::RwLock;
+use std::{collections::HashSet, pin::Pin, sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    StreamExt,
+};
+use tracing::*;
+
+pub type ConnectedPeers = Arc<RwLock<HashSet<PeerId>>>;
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Peers connected to a sentry, kept up to date from its peer events and penalties, which are
+/// passed on to subscribers.
+#[derive(Clone, Debug)]
+pub struct PeerTracker {
+    connected_peers: ConnectedPeers,
+    peer_events_sender: broadcast::Sender<PeerEvent>,
+}
+
+impl PeerTracker {
+    pub fn new() -> Self {
+        Self {
+            connected_peers: ConnectedPeers::default(),
+            peer_events_sender: broadcast::channel(1024).0,
+        }
+    }
+
+    pub fn connected_peers(&self) -> ConnectedPeers {
+        Arc::clone(&self.connected_peers)
+    }
+
+    pub fn peer_events(&self) -> Pin<Box<dyn Stream<Item = PeerEvent> + Send>> {
+        let stream = BroadcastStream::new(self.peer_events_sender.subscribe())
+            .map_err(|error| match error {
+                BroadcastStreamRecvError::Lagged(skipped_count) => {
+                    warn!(
+                        "PeerTracker peer events receiver lagged too far behind, skipping {} events",
+                        skipped_count
+                    );
+                }
+            })
+            // ignore errors (logged above)
+            .filter_map(|result| result.ok());
+
+        Box::pin(stream)
+    }
+
+    pub fn on_event(&self, event: PeerEvent) {
+        let changed = match event {
+            PeerEvent::Connected(peer_id) => self.connected_peers.write().insert(peer_id),
+            PeerEvent::Disconnected(peer_id) | PeerEvent::Penalized(peer_id) => {
+                let removed = self.connected_peers.write().remove(&peer_id);
+                metrics::remove_peer(peer_id);
+                // A penalty is worth reporting even for a peer that is already gone.
+                removed || matches!(event, PeerEvent::Penalized(_))
+            }
+            PeerEvent::SentryDisconnected => {
+                self.connected_peers.write().clear();
+                metrics::remove_all_peers();
+                true
+            }
+        };
+
+        if changed {
+            debug!("PeerTracker: {:?}", event);
+            let _ = self.peer_events_sender.send(event);
+        }
+    }
+
+    /// Follow the peer events of the sentry at `addr`, subscribing again whenever the
+    /// connection drops.
+    pub async fn follow(self, addr: SentryAddress) {
+        loop {
+            match subscribe(addr.clone()).await {
+                Ok(mut events) => {
+                    while let Some(event) = events.next().await {
+                        match event {
+                            Ok(event) => self.on_event(event),
+                            Err(e) => {
+                                warn!("PeerTracker: sentry peer events failed: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    self.on_event(PeerEvent::SentryDisconnected);
+                }
+                Err(e) => debug!(
+                    "PeerTracker: failed to subscribe to sentry peer events: {}",
+                    e
+                ),
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+}
+
+impl Default for PeerTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn subscribe(addr: SentryAddress) -> anyhow::Result<PeerEventStream> {
+    SentryClientImpl::new(addr).await?.peer_events().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tracks_peers() {
+        let tracker = PeerTracker::new();
+        let events = tracker.peer_events();
+        let (a, b) = (PeerId::repeat_byte(0xa), PeerId::repeat_byte(0xb));
+
+        tracker.on_event(PeerEvent::Connected(a));
+        tracker.on_event(PeerEvent::Connected(b));
+        // Seen again, e.g. by a message after the handshake.
+        tracker.on_event(PeerEvent::Connected(a));
+        tracker.on_event(PeerEvent::Disconnected(a));
+        // Already gone.
+        tracker.on_event(PeerEvent::Disconnected(a));
+        assert_eq!(*tracker.connected_peers().read(), HashSet::from([b]));
+
+        tracker.on_event(PeerEvent::SentryDisconnected);
+        assert!(tracker.connected_peers().read().is_empty());
+
+        drop(tracker);
+        assert_eq!(
+            events.collect::<Vec<_>>().await,
+            vec![
+                PeerEvent::Connected(a),
+                PeerEvent::Connected(b),
+                PeerEvent::Disconnected(a),
+                PeerEvent::SentryDisconnected,
+            ]
+        );
+    }
+}
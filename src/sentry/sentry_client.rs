@@ -17,6 +17,18 @@ pub struct Status {
 
 pub type PeerId = H256;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// The sentry finished the handshake with a peer, or a peer sent the first message since
+    /// the sentry connection was (re)established.
+    Connected(PeerId),
+    /// The sentry lost a peer.
+    Disconnected(PeerId),
+    Penalized(PeerId),
+    /// The sentry connection dropped, so all of its peers are gone.
+    SentryDisconnected,
+}
+
 #[derive(Clone, Debug)]
 pub enum PeerFilter {
     MinBlock(u64),
@@ -29,11 +41,15 @@ pub enum PeerFilter {
 pub struct MessageFromPeer {
     pub message: Message,
     pub from_peer_id: Option<PeerId>,
+    /// Size of the message as received, in RLP bytes.
+    pub encoded_size: usize,
 }
 
 pub type MessageFromPeerStream =
     Pin<Box<dyn Stream<Item = anyhow::Result<MessageFromPeer>> + Send>>;
 
+pub type PeerEventStream = Pin<Box<dyn Stream<Item = anyhow::Result<PeerEvent>> + Send>>;
+
 #[async_trait]
 pub trait SentryClient: Send + Debug {
     async fn set_status(&mut self, status: Status) -> anyhow::Result<()>;
@@ -50,4 +66,7 @@ pub trait SentryClient: Send + Debug {
         &mut self,
         filter_ids: &[EthMessageId],
     ) -> anyhow::Result<MessageFromPeerStream>;
+
+    /// Peers connecting to and disconnecting from the sentry.
+    async fn peer_events(&mut self) -> anyhow::Result<PeerEventStream>;
 }
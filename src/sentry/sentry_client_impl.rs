@@ -148,6 +148,7 @@ impl SentryClient for SentryClientImpl {
                     let message_from_peer = MessageFromPeer {
                         message,
                         from_peer_id: peer_id,
+                        encoded_size: message_bytes.len(),
                     };
                     debug!("SentryClient receive_messages received a message {:?} from {:?}",
                         message_from_peer.message.eth_id(),
@@ -165,6 +166,37 @@ impl SentryClient for SentryClientImpl {
         });
         Ok(Box::pin(stream))
     }
+
+    async fn peer_events(&mut self) -> anyhow::Result<PeerEventStream> {
+        let request = tonic::Request::new(grpc_sentry::PeerEventsRequest {});
+        let response = self.client.peer_events(request).await?;
+        let tonic_stream = tonic_stream_fuse_on_error(response.into_inner());
+        debug!("SentryClient peer_events subscribed to peer events");
+
+        let stream = tonic_stream.map(
+            |result: Result<grpc_sentry::PeerEvent, tonic::Status>| -> anyhow::Result<PeerEvent> {
+                let event = result?;
+                let peer_id = PeerId::from(event.peer_id.ok_or_else(|| {
+                    anyhow::format_err!(
+                        "SentryClient peer_events stream got an event without a peer"
+                    )
+                })?);
+                match grpc_sentry::peer_event::PeerEventId::from_i32(event.event_id) {
+                    Some(grpc_sentry::peer_event::PeerEventId::Connect) => {
+                        Ok(PeerEvent::Connected(peer_id))
+                    }
+                    Some(grpc_sentry::peer_event::PeerEventId::Disconnect) => {
+                        Ok(PeerEvent::Disconnected(peer_id))
+                    }
+                    None => Err(anyhow::format_err!(
+                        "SentryClient peer_events stream got an invalid PeerEventId {}",
+                        event.event_id
+                    )),
+                }
+            },
+        );
+        Ok(Box::pin(stream))
+    }
 }
 
 fn tonic_stream_fuse_on_error<T: 'static + Send>(
@@ -1,6 +1,8 @@
 use super::{
     messages::{EthMessageId, Message},
-    sentry_client::{MessageFromPeer, MessageFromPeerStream, PeerFilter, SentryClient, Status},
+    sentry_client::{
+        MessageFromPeer, MessageFromPeerStream, PeerEventStream, PeerFilter, SentryClient, Status,
+    },
 };
 use crate::{
     models::{BlockHeader, BlockNumber},
@@ -83,8 +85,10 @@ impl SentryClient for SentryClientMock {
                     headers,
                 };
 
+                let message = Message::BlockHeaders(response);
                 let response_message = MessageFromPeer {
-                    message: Message::BlockHeaders(response),
+                    encoded_size: rlp::encode(&message).len(),
+                    message,
                     from_peer_id: None,
                 };
 
@@ -134,6 +138,10 @@ impl SentryClient for SentryClientMock {
             anyhow::bail!("SentryClientMock::receive_messages supports only one receiver")
         }
     }
+
+    async fn peer_events(&mut self) -> anyhow::Result<PeerEventStream> {
+        Ok(Box::pin(tokio_stream::empty()))
+    }
 }
 
 impl Default for SentryClientMock {
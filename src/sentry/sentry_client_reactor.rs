@@ -1,5 +1,7 @@
 use super::{
     messages::{EthMessageId, Message},
+    metrics::{self, Direction},
+    peer_tracker::{ConnectedPeers, PeerTracker},
     sentry_client::*,
     sentry_client_connector,
 };
@@ -32,6 +34,7 @@ type ReceiveMessagesSenders =
 pub struct SentryClientReactor {
     send_message_sender: mpsc::Sender<SentryCommand>,
     receive_messages_senders: ReceiveMessagesSenders,
+    peer_tracker: PeerTracker,
     event_loop: Mutex<Option<SentryClientReactorEventLoop>>,
     event_loop_handle: Option<JoinHandle<()>>,
     stop_signal_sender: mpsc::Sender<()>,
@@ -41,6 +44,7 @@ struct SentryClientReactorEventLoop {
     sentry_connector: sentry_client_connector::SentryClientConnectorStream,
     send_message_receiver: mpsc::Receiver<SentryCommand>,
     receive_messages_senders: ReceiveMessagesSenders,
+    peer_tracker: PeerTracker,
     stop_signal_receiver: mpsc::Receiver<()>,
 }
 
@@ -88,18 +92,22 @@ impl SentryClientReactor {
         }
         let receive_messages_senders = Arc::new(RwLock::new(receive_messages_senders));
 
+        let peer_tracker = PeerTracker::new();
+
         let (stop_signal_sender, stop_signal_receiver) = mpsc::channel::<()>(1);
 
         let event_loop = SentryClientReactorEventLoop {
             sentry_connector: sentry_connector_stream,
             send_message_receiver,
             receive_messages_senders: Arc::clone(&receive_messages_senders),
+            peer_tracker: peer_tracker.clone(),
             stop_signal_receiver,
         };
 
         Self {
             send_message_sender,
             receive_messages_senders: Arc::clone(&receive_messages_senders),
+            peer_tracker,
             event_loop: Mutex::new(Some(event_loop)),
             event_loop_handle: None,
             stop_signal_sender,
@@ -150,7 +158,19 @@ impl SentryClientReactor {
     pub async fn penalize_peer(&self, peer_id: PeerId) -> anyhow::Result<()> {
         let command = SentryCommand::PenalizePeer(peer_id);
         let result = self.send_message_sender.send(command).await;
-        result.map_err(|_| anyhow::Error::new(SendMessageError::ReactorStopped))
+        result.map_err(|_| anyhow::Error::new(SendMessageError::ReactorStopped))?;
+
+        self.peer_tracker.on_event(PeerEvent::Penalized(peer_id));
+        Ok(())
+    }
+
+    /// Peers connected to the sentry, as it reported them or they sent us something.
+    pub fn connected_peers(&self) -> ConnectedPeers {
+        self.peer_tracker.connected_peers()
+    }
+
+    pub fn peer_events(&self) -> Pin<Box<dyn Stream<Item = PeerEvent> + Send>> {
+        self.peer_tracker.peer_events()
     }
 
     pub async fn send_message(
@@ -261,6 +281,7 @@ enum EventLoopStreamId {
     Sentry,
     Send,
     Receive,
+    PeerEvents,
     Stop,
 }
 
@@ -269,6 +290,7 @@ enum EventLoopStreamResult {
     Sentry(Box<dyn SentryClient>),
     Send(u32),
     Receive(MessageFromPeer),
+    PeerEvent(PeerEvent),
     Stop(()),
 }
 
@@ -330,9 +352,19 @@ mod stream_factory {
     ) -> anyhow::Result<u32> {
         match command {
             SentryCommand::SendMessage(params) => {
-                sentry
+                let id = params.message.eth_id();
+                let size = rlp::encode(&params.message).len();
+                let peer_id = match params.peer_filter {
+                    PeerFilter::PeerId(peer_id) => Some(peer_id),
+                    _ => None,
+                };
+                let sent_peers_count = sentry
                     .send_message(params.message, params.peer_filter)
-                    .await
+                    .await?;
+                if sent_peers_count > 0 {
+                    metrics::record_message(Direction::Outbound, id, size, peer_id);
+                }
+                Ok(sent_peers_count)
             }
             SentryCommand::PenalizePeer(peer_id) => {
                 // this is sent to a single peer (1)
@@ -366,13 +398,25 @@ mod stream_factory {
         mut sentry: Box<dyn SentryClient>,
         send_message_receiver: Arc<Mutex<mpsc::Receiver<SentryCommand>>>,
         receive_messages_senders_dropper: EventLoopReceiveMessagesSendersDropper,
-    ) -> anyhow::Result<(EventLoopStream, EventLoopStream)> {
+    ) -> anyhow::Result<(EventLoopStream, EventLoopStream, EventLoopStream)> {
         // subscribe to incoming messages
         let stream = sentry.receive_messages(&[]).await?;
         let receive_stream = make_receive_stream(stream, receive_messages_senders_dropper);
 
+        // peers are still tracked by the messages they send if the sentry has no peer events
+        let peer_events_stream: EventLoopStream = match sentry.peer_events().await {
+            Ok(stream) => Box::pin(stream.map_ok(EventLoopStreamResult::PeerEvent)),
+            Err(error) => {
+                warn!(
+                    "SentryClientReactor.EventLoop failed to subscribe to peer events: {}",
+                    error
+                );
+                Box::pin(tokio_stream::empty())
+            }
+        };
+
         let send_stream = make_send_stream(send_message_receiver, sentry);
-        Ok((send_stream, receive_stream))
+        Ok((send_stream, receive_stream, peer_events_stream))
     }
 }
 
@@ -407,15 +451,17 @@ impl SentryClientReactorEventLoop {
                         sentry_stream_holder =
                             Some(stream.remove(&EventLoopStreamId::Sentry).unwrap());
 
-                        let (send_stream, receive_stream) = stream_factory::make_sentry_streams(
-                            sentry,
-                            send_message_receiver.clone(),
-                            receive_messages_senders_dropper.clone(),
-                        )
-                        .await?;
+                        let (send_stream, receive_stream, peer_events_stream) =
+                            stream_factory::make_sentry_streams(
+                                sentry,
+                                send_message_receiver.clone(),
+                                receive_messages_senders_dropper.clone(),
+                            )
+                            .await?;
 
                         stream.insert(EventLoopStreamId::Send, send_stream);
                         stream.insert(EventLoopStreamId::Receive, receive_stream);
+                        stream.insert(EventLoopStreamId::PeerEvents, peer_events_stream);
                     }
                     Ok(_) => panic!("unexpected result {:?}", result),
                     Err(error) => {
@@ -442,8 +488,10 @@ impl SentryClientReactorEventLoop {
                             );
                             if sentry_client_connector::is_disconnect_error(&error) {
                                 info!("SentryClientReactor.EventLoop reconnecting sentry streams");
+                                self.peer_tracker.on_event(PeerEvent::SentryDisconnected);
                                 stream.remove(&EventLoopStreamId::Send);
                                 stream.remove(&EventLoopStreamId::Receive);
+                                stream.remove(&EventLoopStreamId::PeerEvents);
                                 match sentry_stream_holder.take() {
                                     Some(sentry_stream) => {
                                         stream.insert(EventLoopStreamId::Sentry, sentry_stream);
@@ -463,6 +511,16 @@ impl SentryClientReactorEventLoop {
                             let id = message_from_peer.message.eth_id();
                             debug!("SentryClientReactor.EventLoop incoming message: {:?}", id);
 
+                            if let Some(peer_id) = message_from_peer.from_peer_id {
+                                self.peer_tracker.on_event(PeerEvent::Connected(peer_id));
+                            }
+                            metrics::record_message(
+                                Direction::Inbound,
+                                id,
+                                message_from_peer.encoded_size,
+                                message_from_peer.from_peer_id,
+                            );
+
                             let receive_messages_senders = self.receive_messages_senders.read();
                             let sender_opt = receive_messages_senders.get(&id);
                             let sender = sender_opt.ok_or_else(|| {
@@ -485,8 +543,10 @@ impl SentryClientReactorEventLoop {
                             );
                             if sentry_client_connector::is_disconnect_error(&error) {
                                 info!("SentryClientReactor.EventLoop reconnecting sentry streams");
+                                self.peer_tracker.on_event(PeerEvent::SentryDisconnected);
                                 stream.remove(&EventLoopStreamId::Send);
                                 stream.remove(&EventLoopStreamId::Receive);
+                                stream.remove(&EventLoopStreamId::PeerEvents);
                                 match sentry_stream_holder.take() {
                                     Some(sentry_stream) => {
                                         stream.insert(EventLoopStreamId::Sentry, sentry_stream);
@@ -499,6 +559,16 @@ impl SentryClientReactorEventLoop {
                         }
                     }
                 }
+                EventLoopStreamId::PeerEvents => match result {
+                    Ok(EventLoopStreamResult::PeerEvent(event)) => {
+                        self.peer_tracker.on_event(event);
+                    }
+                    Ok(_) => panic!("unexpected result {:?}", result),
+                    Err(error) => {
+                        // a lost sentry connection is handled by the receive stream
+                        warn!("SentryClientReactor.EventLoop peer events error: {}", error);
+                    }
+                },
                 EventLoopStreamId::Stop => {
                     break;
                 }
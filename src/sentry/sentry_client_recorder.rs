@@ -0,0 +1,384 @@
+//! Recording of a sentry session, to replay it offline and reproduce what a downloader did with
+//! the messages it got.
+//!
+//! A recording starts with the name of the chain and holds, in order, every message received
+//! from the sentry, and a marker for every message sent to it. On replay, a received message is
+//! held back until as many messages were sent as before it was recorded, so that responses do
+//! not arrive ahead of the requests that asked for them.
+use super::{
+    message_decoder,
+    messages::{EthMessageId, Message},
+    sentry_client::*,
+    sentry_client_connector::SentryClientConnector,
+};
+use crate::models::H256;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::{
+    fs::File,
+    io::{self, BufWriter, ErrorKind, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use strum::IntoEnumIterator;
+use tokio::{
+    io::{AsyncReadExt, BufReader},
+    sync::watch,
+};
+use tokio_stream::StreamExt;
+use tracing::*;
+
+const MAGIC: &[u8; 4] = b"MZSR";
+const VERSION: u8 = 1;
+
+const RECEIVED: u8 = 0;
+const SENT: u8 = 1;
+
+/// How long a replayed message waits for the messages sent before it. Past that, the replayed
+/// session is taken to send less than the recorded one, and the following messages wait for
+/// that many fewer.
+const REPLAY_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn message_id(id: u8) -> io::Result<EthMessageId> {
+    EthMessageId::iter()
+        .find(|message_id| *message_id as u8 == id)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("unknown message id {}", id)))
+}
+
+/// File a session is recorded to, shared by the clients of every sentry connection.
+#[derive(Clone, Debug)]
+pub struct Recording(Arc<Mutex<BufWriter<File>>>);
+
+impl Recording {
+    pub fn create(path: &Path, chain_name: &str) -> anyhow::Result<Self> {
+        let chain_name = chain_name.as_bytes();
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION, u8::try_from(chain_name.len())?])?;
+        file.write_all(chain_name)?;
+        file.flush()?;
+
+        Ok(Self(Arc::new(Mutex::new(file))))
+    }
+
+    // Flushed at once, so that a session cut short is still recorded up to there.
+    fn write(&self, event: &[&[u8]]) -> io::Result<()> {
+        let mut file = self.0.lock();
+        for part in event {
+            file.write_all(part)?;
+        }
+        file.flush()
+    }
+
+    fn record_received(&self, message: &MessageFromPeer) -> io::Result<()> {
+        let data = rlp::encode(&message.message);
+        let peer = match &message.from_peer_id {
+            Some(peer_id) => [&[1][..], peer_id.as_bytes()].concat(),
+            None => vec![0],
+        };
+        self.write(&[
+            &[RECEIVED, message.message.eth_id() as u8],
+            &peer,
+            &u32::try_from(data.len())
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "message too large"))?
+                .to_be_bytes(),
+            &data,
+        ])
+    }
+
+    fn record_sent(&self, id: EthMessageId) -> io::Result<()> {
+        self.write(&[&[SENT, id as u8]])
+    }
+}
+
+/// Passes everything through to `inner`, recording the messages received and sent.
+#[derive(Debug)]
+pub struct SentryClientRecorder {
+    inner: Box<dyn SentryClient>,
+    recording: Recording,
+}
+
+#[async_trait]
+impl SentryClient for SentryClientRecorder {
+    async fn set_status(&mut self, status: Status) -> anyhow::Result<()> {
+        self.inner.set_status(status).await
+    }
+
+    async fn penalize_peer(&mut self, peer_id: PeerId) -> anyhow::Result<()> {
+        self.inner.penalize_peer(peer_id).await
+    }
+
+    async fn send_message(
+        &mut self,
+        message: Message,
+        peer_filter: PeerFilter,
+    ) -> anyhow::Result<u32> {
+        if let Err(e) = self.recording.record_sent(message.eth_id()) {
+            warn!("Failed to record a sent message: {}", e);
+        }
+        self.inner.send_message(message, peer_filter).await
+    }
+
+    async fn receive_messages(
+        &mut self,
+        filter_ids: &[EthMessageId],
+    ) -> anyhow::Result<MessageFromPeerStream> {
+        let recording = self.recording.clone();
+        let stream = self.inner.receive_messages(filter_ids).await?;
+
+        Ok(Box::pin(stream.map(move |result| {
+            if let Ok(message) = &result {
+                if let Err(e) = recording.record_received(message) {
+                    warn!("Failed to record a received message: {}", e);
+                }
+            }
+            result
+        })))
+    }
+
+    async fn peer_events(&mut self) -> anyhow::Result<PeerEventStream> {
+        self.inner.peer_events().await
+    }
+}
+
+/// Connects through `inner`, recording the sessions of all its connections in one recording.
+pub struct SentryClientConnectorRecorder {
+    inner: Box<dyn SentryClientConnector>,
+    recording: Recording,
+}
+
+impl SentryClientConnectorRecorder {
+    pub fn new(inner: Box<dyn SentryClientConnector>, recording: Recording) -> Self {
+        Self { inner, recording }
+    }
+}
+
+#[async_trait]
+impl SentryClientConnector for SentryClientConnectorRecorder {
+    async fn connect(&mut self, status: Status) -> anyhow::Result<Box<dyn SentryClient>> {
+        Ok(Box::new(SentryClientRecorder {
+            inner: self.inner.connect(status).await?,
+            recording: self.recording.clone(),
+        }))
+    }
+}
+
+enum Event {
+    Received(MessageFromPeer),
+    Sent,
+}
+
+async fn read_event(reader: &mut BufReader<tokio::fs::File>) -> anyhow::Result<Option<Event>> {
+    let kind = match reader.read_u8().await {
+        Ok(kind) => kind,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let id = message_id(reader.read_u8().await?)?;
+
+    match kind {
+        RECEIVED => {
+            let from_peer_id = match reader.read_u8().await? {
+                0 => None,
+                _ => {
+                    let mut peer_id = H256::zero();
+                    reader.read_exact(peer_id.as_bytes_mut()).await?;
+                    Some(peer_id)
+                }
+            };
+            let mut data = vec![0; reader.read_u32().await? as usize];
+            reader.read_exact(&mut data).await?;
+
+            Ok(Some(Event::Received(MessageFromPeer {
+                message: message_decoder::decode_rlp_message(id, &data)?,
+                from_peer_id,
+                encoded_size: data.len(),
+            })))
+        }
+        SENT => Ok(Some(Event::Sent)),
+        other => anyhow::bail!("unknown recorded event {}", other),
+    }
+}
+
+/// Plays a recording back in place of a sentry. Only one connection can be made to it, as the
+/// recording is played out once.
+#[derive(Debug)]
+pub struct SentryClientReplay {
+    chain_name: String,
+    reader: Option<BufReader<tokio::fs::File>>,
+    sent_count: usize,
+    sent: watch::Sender<usize>,
+    played_out: Arc<AtomicBool>,
+}
+
+impl SentryClientReplay {
+    pub async fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(tokio::fs::File::open(path).await?);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).await?;
+        if &magic != MAGIC {
+            anyhow::bail!("{} is not a sentry session recording", path.display());
+        }
+        let version = reader.read_u8().await?;
+        if version != VERSION {
+            anyhow::bail!("unsupported recording version {}", version);
+        }
+        let mut chain_name = vec![0; reader.read_u8().await? as usize];
+        reader.read_exact(&mut chain_name).await?;
+
+        Ok(Self {
+            chain_name: String::from_utf8(chain_name)?,
+            reader: Some(reader),
+            sent_count: 0,
+            sent: watch::channel(0).0,
+            played_out: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Chain the session was recorded on.
+    pub fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    /// Set once every recorded message was received, or the recording turned out to be broken.
+    pub fn played_out(&self) -> Arc<AtomicBool> {
+        self.played_out.clone()
+    }
+}
+
+#[async_trait]
+impl SentryClient for SentryClientReplay {
+    async fn set_status(&mut self, _status: Status) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn penalize_peer(&mut self, _peer_id: PeerId) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn send_message(
+        &mut self,
+        _message: Message,
+        _peer_filter: PeerFilter,
+    ) -> anyhow::Result<u32> {
+        self.sent_count += 1;
+        let _ = self.sent.send(self.sent_count);
+        Ok(1)
+    }
+
+    async fn receive_messages(
+        &mut self,
+        filter_ids: &[EthMessageId],
+    ) -> anyhow::Result<MessageFromPeerStream> {
+        let mut reader = self.reader.take().ok_or_else(|| {
+            anyhow::format_err!("SentryClientReplay::receive_messages supports only one receiver")
+        })?;
+        let filter_ids = filter_ids.to_vec();
+        let mut sent = self.sent.subscribe();
+        let played_out = self.played_out.clone();
+
+        let stream = async_stream::stream! {
+            // Messages sent before the next one received, less those the replayed session
+            // turned out not to send.
+            let mut sent_before = 0_usize;
+            let mut missing = 0_usize;
+            loop {
+                match read_event(&mut reader).await {
+                    Ok(Some(Event::Sent)) => sent_before += 1,
+                    Ok(Some(Event::Received(message))) => {
+                        let expected = sent_before - missing;
+                        let wait = async {
+                            while *sent.borrow() < expected {
+                                if sent.changed().await.is_err() {
+                                    break;
+                                }
+                            }
+                        };
+                        if tokio::time::timeout(REPLAY_SEND_TIMEOUT, wait).await.is_err() {
+                            let sent = *sent.borrow();
+                            debug!(
+                                "SentryClientReplay: {} messages sent before {:?}, {} recorded",
+                                sent,
+                                message.message.eth_id(),
+                                expected
+                            );
+                            missing += expected.saturating_sub(sent);
+                        }
+
+                        if filter_ids.is_empty() || filter_ids.contains(&message.message.eth_id()) {
+                            yield Ok(message);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        yield Err(e);
+                        break;
+                    }
+                }
+            }
+
+            debug!("SentryClientReplay: recording played out");
+            played_out.store(true, Ordering::SeqCst);
+        };
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn peer_events(&mut self) -> anyhow::Result<PeerEventStream> {
+        // Peers are not recorded, only what they sent.
+        Ok(Box::pin(tokio_stream::empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::messages::BlockHeadersMessage, *};
+
+    #[tokio::test]
+    async fn replay_holds_responses_until_requested() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session");
+
+        let headers = Message::BlockHeaders(BlockHeadersMessage {
+            request_id: 1,
+            headers: vec![],
+        });
+        let peer_id = H256::repeat_byte(1);
+        let recording = Recording::create(&path, "mainnet").unwrap();
+        recording
+            .record_sent(EthMessageId::GetBlockHeaders)
+            .unwrap();
+        recording
+            .record_received(&MessageFromPeer {
+                message: headers.clone(),
+                from_peer_id: Some(peer_id),
+                encoded_size: 0,
+            })
+            .unwrap();
+        drop(recording);
+
+        let mut replay = SentryClientReplay::open(&path).await.unwrap();
+        assert_eq!(replay.chain_name(), "mainnet");
+        let played_out = replay.played_out();
+        let mut messages = replay.receive_messages(&[]).await.unwrap();
+
+        let held = tokio::time::timeout(Duration::from_millis(100), messages.next()).await;
+        assert!(held.is_err());
+
+        replay
+            .send_message(headers.clone(), PeerFilter::All)
+            .await
+            .unwrap();
+        let message = messages.next().await.unwrap().unwrap();
+        assert_eq!(message.message, headers);
+        assert_eq!(message.from_peer_id, Some(peer_id));
+
+        assert!(messages.next().await.is_none());
+        assert!(played_out.load(Ordering::SeqCst));
+    }
+}
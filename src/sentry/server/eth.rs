@@ -0,0 +1,375 @@
+use super::opts::NodeId;
+use crate::sentry::messages::{EthMessageId, StatusMessage};
+use arrayvec::ArrayString;
+use async_trait::async_trait;
+use bytes::Bytes;
+use devp2p::{
+    CapabilityName, CapabilityServer, CapabilityVersion, DisconnectReason, InboundEvent, Message,
+    OutboundEvent,
+};
+use ethereum_forkid::ForkFilter;
+use ethereum_interfaces::{sentry as grpc_sentry, types as grpc_types};
+use ethereum_types::{H256, U256};
+use parking_lot::RwLock;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use strum::IntoEnumIterator;
+use tokio::sync::{broadcast, mpsc, Mutex as AsyncMutex};
+use tracing::*;
+
+pub const PROTOCOL_VERSION: usize = 66;
+/// Number of message ids reserved by eth/66.
+pub const MESSAGE_COUNT: usize = 17;
+
+const OUTBOUND_BUFFER: usize = 64;
+
+pub fn capability_name() -> CapabilityName {
+    CapabilityName(ArrayString::from("eth").unwrap())
+}
+
+/// Id of the peer as seen by sentry clients: the keccak hash of its public key.
+pub fn peer_id(node_id: NodeId) -> H256 {
+    crate::crypto::keccak256(node_id)
+}
+
+pub fn message_id(id: usize) -> Option<EthMessageId> {
+    EthMessageId::iter().find(|message_id| *message_id as usize == id)
+}
+
+/// Our own status as last set by the downloader.
+#[derive(Clone, Debug)]
+pub struct FullStatus {
+    pub status: StatusMessage,
+    pub fork_filter: ForkFilter,
+}
+
+impl FullStatus {
+    pub fn new(status: grpc_sentry::StatusData) -> anyhow::Result<Self> {
+        let fork_data = status
+            .fork_data
+            .ok_or_else(|| anyhow::format_err!("no fork data"))?;
+        let genesis_hash = H256::from(
+            fork_data
+                .genesis
+                .ok_or_else(|| anyhow::format_err!("no genesis hash"))?,
+        );
+        let fork_filter = ForkFilter::new(status.max_block, genesis_hash, fork_data.forks);
+
+        Ok(Self {
+            status: StatusMessage {
+                protocol_version: PROTOCOL_VERSION,
+                network_id: status.network_id,
+                total_difficulty: U256::from_big_endian(
+                    H256::from(status.total_difficulty.unwrap_or_default()).as_bytes(),
+                ),
+                best_hash: H256::from(status.best_hash.unwrap_or_default()),
+                genesis_hash,
+                fork_id: fork_filter.current(),
+            },
+            fork_filter,
+        })
+    }
+
+    fn validate(&self, peer_status: &StatusMessage) -> anyhow::Result<()> {
+        if peer_status.network_id != self.status.network_id {
+            anyhow::bail!("network id mismatch: {}", peer_status.network_id);
+        }
+        if peer_status.genesis_hash != self.status.genesis_hash {
+            anyhow::bail!("genesis mismatch: {:?}", peer_status.genesis_hash);
+        }
+        self.fork_filter
+            .validate(peer_status.fork_id)
+            .map_err(|e| anyhow::format_err!("fork id rejected: {:?}", e))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Pipes {
+    sender: mpsc::Sender<OutboundEvent>,
+    receiver: Arc<AsyncMutex<mpsc::Receiver<OutboundEvent>>>,
+}
+
+#[derive(Debug)]
+pub struct Peer {
+    pub node_id: NodeId,
+    /// Status the peer sent us, `None` until the handshake completes.
+    pub status: Option<StatusMessage>,
+    /// Highest block the downloader has seen the peer announce.
+    pub min_block: u64,
+    pub trusted: bool,
+    /// Capabilities negotiated with the peer, as `name/version`.
+    pub protocols: Vec<String>,
+    pipes: Pipes,
+}
+
+/// Runs the eth subprotocol for every connected peer and fans inbound messages out to the gRPC clients.
+#[derive(Debug)]
+pub struct CapabilityServerImpl {
+    peers: RwLock<HashMap<H256, Peer>>,
+    status: RwLock<Option<FullStatus>>,
+    trusted_peers: HashSet<NodeId>,
+    messages: broadcast::Sender<grpc_sentry::InboundMessage>,
+    peer_events: broadcast::Sender<grpc_sentry::PeerEvent>,
+    /// Set until the downloader gives us a status to handshake with.
+    no_new_peers: Arc<AtomicBool>,
+}
+
+impl CapabilityServerImpl {
+    pub fn new(trusted_peers: HashSet<NodeId>, no_new_peers: Arc<AtomicBool>) -> Self {
+        no_new_peers.store(true, Ordering::SeqCst);
+        Self {
+            peers: Default::default(),
+            status: Default::default(),
+            trusted_peers,
+            messages: broadcast::channel(1024).0,
+            peer_events: broadcast::channel(64).0,
+            no_new_peers,
+        }
+    }
+
+    pub fn set_status(&self, status: FullStatus) {
+        *self.status.write() = Some(status);
+        self.no_new_peers.store(false, Ordering::SeqCst);
+    }
+
+    pub fn subscribe_messages(&self) -> broadcast::Receiver<grpc_sentry::InboundMessage> {
+        self.messages.subscribe()
+    }
+
+    pub fn subscribe_peer_events(&self) -> broadcast::Receiver<grpc_sentry::PeerEvent> {
+        self.peer_events.subscribe()
+    }
+
+    /// Peers that completed the handshake.
+    pub fn ready_peers(&self) -> Vec<H256> {
+        self.peers
+            .read()
+            .iter()
+            .filter(|(_, peer)| peer.status.is_some())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Peers that completed the handshake, by negotiated capability.
+    pub fn protocol_peer_counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for peer in self.peers.read().values() {
+            if peer.status.is_some() {
+                for protocol in &peer.protocols {
+                    *counts.entry(protocol.clone()).or_default() += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    pub fn peers_with_min_block(&self, min_block: u64) -> Vec<H256> {
+        self.peers
+            .read()
+            .iter()
+            .filter(|(_, peer)| peer.status.is_some() && peer.min_block >= min_block)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    pub fn with_peer<T>(&self, peer_id: H256, f: impl FnOnce(&Peer) -> T) -> Option<T> {
+        self.peers.read().get(&peer_id).map(f)
+    }
+
+    pub fn set_peer_min_block(&self, peer_id: H256, min_block: u64) {
+        if let Some(peer) = self.peers.write().get_mut(&peer_id) {
+            peer.min_block = peer.min_block.max(min_block);
+        }
+    }
+
+    /// Queues a message for the peer. Returns `false` if the peer is gone or not keeping up.
+    pub fn send_message(&self, peer_id: H256, id: EthMessageId, data: Bytes) -> bool {
+        self.send_event(
+            peer_id,
+            OutboundEvent::Message {
+                capability_name: capability_name(),
+                message: Message {
+                    id: id as usize,
+                    data,
+                },
+            },
+        )
+    }
+
+    /// Disconnects the peer unless it is trusted. Returns `false` if nothing was done.
+    pub fn penalize(&self, peer_id: H256) -> bool {
+        if self.with_peer(peer_id, |peer| peer.trusted).unwrap_or(true) {
+            return false;
+        }
+
+        self.send_event(
+            peer_id,
+            OutboundEvent::Disconnect {
+                reason: DisconnectReason::DisconnectRequested,
+            },
+        )
+    }
+
+    fn send_event(&self, peer_id: H256, event: OutboundEvent) -> bool {
+        self.peers
+            .read()
+            .get(&peer_id)
+            .map(|peer| peer.pipes.sender.try_send(event).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn disconnect(&self, peer_id: H256, reason: DisconnectReason) {
+        self.send_event(peer_id, OutboundEvent::Disconnect { reason });
+    }
+
+    fn announce(&self, peer_id: H256, event_id: grpc_sentry::peer_event::PeerEventId) {
+        let _ = self.peer_events.send(grpc_sentry::PeerEvent {
+            peer_id: Some(grpc_types::H256::from(peer_id)),
+            event_id: event_id as i32,
+        });
+    }
+
+    fn handle_status(&self, peer_id: H256, data: &[u8]) -> anyhow::Result<()> {
+        let peer_status = rlp::decode::<StatusMessage>(data)?;
+        self.status
+            .read()
+            .as_ref()
+            .ok_or_else(|| anyhow::format_err!("no status to check against"))?
+            .validate(&peer_status)?;
+
+        let mut peers = self.peers.write();
+        let peer = peers
+            .get_mut(&peer_id)
+            .ok_or_else(|| anyhow::format_err!("peer is gone"))?;
+        if peer.status.is_some() {
+            anyhow::bail!("repeated status");
+        }
+        peer.status = Some(peer_status);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CapabilityServer for CapabilityServerImpl {
+    #[instrument(skip(self, caps), fields(peer = &*peer.to_string()))]
+    fn on_peer_connect(&self, peer: NodeId, caps: HashMap<CapabilityName, CapabilityVersion>) {
+        let id = peer_id(peer);
+        let (sender, receiver) = mpsc::channel(OUTBOUND_BUFFER);
+
+        let status = self.status.read().as_ref().map(|s| s.status.clone());
+        match status {
+            Some(status) => {
+                let _ = sender.try_send(OutboundEvent::Message {
+                    capability_name: capability_name(),
+                    message: Message {
+                        id: EthMessageId::Status as usize,
+                        data: rlp::encode(&status).freeze(),
+                    },
+                });
+            }
+            None => {
+                let _ = sender.try_send(OutboundEvent::Disconnect {
+                    reason: DisconnectReason::DisconnectRequested,
+                });
+            }
+        }
+
+        self.peers.write().insert(
+            id,
+            Peer {
+                node_id: peer,
+                status: None,
+                min_block: 0,
+                trusted: self.trusted_peers.contains(&peer),
+                protocols: caps
+                    .iter()
+                    .map(|(name, version)| format!("{}/{}", name.0, version))
+                    .collect(),
+                pipes: Pipes {
+                    sender,
+                    receiver: Arc::new(AsyncMutex::new(receiver)),
+                },
+            },
+        );
+    }
+
+    #[instrument(skip(self, event), fields(peer = &*peer.to_string()))]
+    async fn on_peer_event(&self, peer: NodeId, event: InboundEvent) {
+        let id = peer_id(peer);
+        match event {
+            InboundEvent::Disconnect { reason } => {
+                debug!("Peer disconnected: {:?}", reason);
+                if let Some(peer) = self.peers.write().remove(&id) {
+                    if peer.status.is_some() {
+                        self.announce(id, grpc_sentry::peer_event::PeerEventId::Disconnect);
+                    }
+                }
+            }
+            InboundEvent::Message { message, .. } => {
+                let ready = self
+                    .with_peer(id, |peer| peer.status.is_some())
+                    .unwrap_or(false);
+
+                match message_id(message.id) {
+                    Some(EthMessageId::Status) => {
+                        if let Err(e) = self.handle_status(id, &message.data) {
+                            debug!("Handshake failed: {}", e);
+                            self.disconnect(id, DisconnectReason::UselessPeer);
+                        } else {
+                            self.announce(id, grpc_sentry::peer_event::PeerEventId::Connect);
+                        }
+                    }
+                    Some(message_id) if ready => {
+                        let _ = self.messages.send(grpc_sentry::InboundMessage {
+                            id: grpc_sentry::MessageId::from(message_id) as i32,
+                            data: message.data,
+                            peer_id: Some(grpc_types::H256::from(id)),
+                        });
+                    }
+                    Some(_) => {
+                        debug!("Unexpected message {} before handshake", message.id);
+                        self.disconnect(id, DisconnectReason::ProtocolBreach);
+                    }
+                    None => {
+                        debug!("Unknown message id {}", message.id);
+                        self.disconnect(id, DisconnectReason::ProtocolBreach);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn next(&self, peer: NodeId) -> OutboundEvent {
+        let receiver = self.with_peer(peer_id(peer), |peer| peer.pipes.receiver.clone());
+
+        let event = match receiver {
+            Some(receiver) => receiver.lock().await.recv().await,
+            None => None,
+        };
+
+        event.unwrap_or(OutboundEvent::Disconnect {
+            reason: DisconnectReason::DisconnectRequested,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_ids() {
+        assert_eq!(message_id(0), Some(EthMessageId::Status));
+        assert_eq!(message_id(16), Some(EthMessageId::Receipts));
+        assert_eq!(message_id(11), None);
+        assert_eq!(message_id(MESSAGE_COUNT), None);
+    }
+}
@@ -0,0 +1,311 @@
+use super::eth::{capability_name, CapabilityServerImpl, FullStatus, PROTOCOL_VERSION};
+use crate::sentry::messages::EthMessageId;
+use ethereum_interfaces::{
+    sentry::{self as grpc_sentry, sentry_server::Sentry},
+    types as grpc_types,
+};
+use ethereum_types::H256;
+use futures_core::Stream;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    pin::Pin,
+    sync::Arc,
+};
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tonic::{Request, Response, Status};
+use tracing::*;
+
+/// Address and identity of this node, reported through `NodeInfo`.
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+    pub enode: String,
+    pub listen_port: u16,
+    pub discovery_port: u16,
+}
+
+/// Protocols of the sentry, sent as JSON in the `protocols` field of `NodeInfo`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SentryProtocols {
+    /// Protocols the sentry runs, as `name/version`.
+    pub running: Vec<String>,
+    /// Peers that completed the handshake, by negotiated protocol.
+    pub peers: BTreeMap<String, usize>,
+}
+
+impl SentryProtocols {
+    /// Highest version of protocol `name` the sentry runs.
+    pub fn version(&self, name: &str) -> Option<usize> {
+        self.running
+            .iter()
+            .filter_map(|protocol| {
+                let (n, version) = protocol.split_once('/')?;
+                if n == name {
+                    version.parse().ok()
+                } else {
+                    None
+                }
+            })
+            .max()
+    }
+}
+
+/// The gRPC surface consumed by [`SentryClientImpl`](crate::sentry::sentry_client_impl::SentryClientImpl).
+#[derive(Debug)]
+pub struct SentryService {
+    capability_server: Arc<CapabilityServerImpl>,
+    node_info: NodeInfo,
+}
+
+impl SentryService {
+    pub fn new(capability_server: Arc<CapabilityServerImpl>, node_info: NodeInfo) -> Self {
+        Self {
+            capability_server,
+            node_info,
+        }
+    }
+
+    fn send_to_peers(
+        &self,
+        data: Option<grpc_sentry::OutboundMessageData>,
+        peers: impl IntoIterator<Item = H256>,
+    ) -> Result<Response<grpc_sentry::SentPeers>, Status> {
+        let data = data.ok_or_else(|| Status::invalid_argument("no message data"))?;
+        let id = grpc_sentry::MessageId::from_i32(data.id)
+            .and_then(|id| EthMessageId::try_from(id).ok())
+            .ok_or_else(|| {
+                Status::invalid_argument(format!("unsupported message id {}", data.id))
+            })?;
+        if id == EthMessageId::Status {
+            return Err(Status::invalid_argument(
+                "status is sent by the sentry itself",
+            ));
+        }
+
+        let peers = peers
+            .into_iter()
+            .filter(|peer_id| {
+                self.capability_server
+                    .send_message(*peer_id, id, data.data.clone())
+            })
+            .map(grpc_types::H256::from)
+            .collect();
+
+        Ok(Response::new(grpc_sentry::SentPeers { peers }))
+    }
+}
+
+fn peer_id(peer_id: Option<grpc_types::H256>) -> Result<H256, Status> {
+    peer_id
+        .map(H256::from)
+        .ok_or_else(|| Status::invalid_argument("no peer id"))
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
+
+#[tonic::async_trait]
+impl Sentry for SentryService {
+    async fn penalize_peer(
+        &self,
+        request: Request<grpc_sentry::PenalizePeerRequest>,
+    ) -> Result<Response<()>, Status> {
+        let peer_id = peer_id(request.into_inner().peer_id)?;
+        if self.capability_server.penalize(peer_id) {
+            debug!("Penalized peer {:?}", peer_id);
+        }
+
+        Ok(Response::new(()))
+    }
+
+    async fn peer_min_block(
+        &self,
+        request: Request<grpc_sentry::PeerMinBlockRequest>,
+    ) -> Result<Response<()>, Status> {
+        let request = request.into_inner();
+        self.capability_server
+            .set_peer_min_block(peer_id(request.peer_id)?, request.min_block);
+
+        Ok(Response::new(()))
+    }
+
+    async fn hand_shake(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<grpc_sentry::HandShakeReply>, Status> {
+        Ok(Response::new(grpc_sentry::HandShakeReply {
+            protocol: grpc_sentry::Protocol::Eth66 as i32,
+        }))
+    }
+
+    async fn set_status(
+        &self,
+        request: Request<grpc_sentry::StatusData>,
+    ) -> Result<Response<grpc_sentry::SetStatusReply>, Status> {
+        let status = FullStatus::new(request.into_inner())
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        debug!(
+            "Status set: best hash {:?}, fork id {:?}",
+            status.status.best_hash, status.status.fork_id
+        );
+        self.capability_server.set_status(status);
+
+        Ok(Response::new(Default::default()))
+    }
+
+    async fn send_message_by_min_block(
+        &self,
+        request: Request<grpc_sentry::SendMessageByMinBlockRequest>,
+    ) -> Result<Response<grpc_sentry::SentPeers>, Status> {
+        let request = request.into_inner();
+        let mut peers = self
+            .capability_server
+            .peers_with_min_block(request.min_block);
+        // A single peer is enough for the requests the downloader sends this way.
+        peers.shuffle(&mut rand::thread_rng());
+        peers.truncate(1);
+
+        self.send_to_peers(request.data, peers)
+    }
+
+    async fn send_message_by_id(
+        &self,
+        request: Request<grpc_sentry::SendMessageByIdRequest>,
+    ) -> Result<Response<grpc_sentry::SentPeers>, Status> {
+        let request = request.into_inner();
+        let peer_id = peer_id(request.peer_id)?;
+
+        self.send_to_peers(request.data, [peer_id])
+    }
+
+    async fn send_message_to_random_peers(
+        &self,
+        request: Request<grpc_sentry::SendMessageToRandomPeersRequest>,
+    ) -> Result<Response<grpc_sentry::SentPeers>, Status> {
+        let request = request.into_inner();
+        let mut peers = self.capability_server.ready_peers();
+        peers.shuffle(&mut rand::thread_rng());
+        peers.truncate(request.max_peers as usize);
+
+        self.send_to_peers(request.data, peers)
+    }
+
+    async fn send_message_to_all(
+        &self,
+        request: Request<grpc_sentry::OutboundMessageData>,
+    ) -> Result<Response<grpc_sentry::SentPeers>, Status> {
+        self.send_to_peers(
+            Some(request.into_inner()),
+            self.capability_server.ready_peers(),
+        )
+    }
+
+    type MessagesStream = ResponseStream<grpc_sentry::InboundMessage>;
+
+    async fn messages(
+        &self,
+        request: Request<grpc_sentry::MessagesRequest>,
+    ) -> Result<Response<Self::MessagesStream>, Status> {
+        let ids = request.into_inner().ids.into_iter().collect::<HashSet<_>>();
+
+        let stream = BroadcastStream::new(self.capability_server.subscribe_messages()).filter_map(
+            move |message| match message {
+                Ok(message) if ids.contains(&message.id) => Some(Ok(message)),
+                Ok(_) => None,
+                Err(e) => {
+                    // The subscriber fell behind, the requests it missed will time out.
+                    warn!("Inbound message stream: {}", e);
+                    None
+                }
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn peer_count(
+        &self,
+        _: Request<grpc_sentry::PeerCountRequest>,
+    ) -> Result<Response<grpc_sentry::PeerCountReply>, Status> {
+        Ok(Response::new(grpc_sentry::PeerCountReply {
+            count: self.capability_server.ready_peers().len() as u64,
+        }))
+    }
+
+    async fn peer_by_id(
+        &self,
+        request: Request<grpc_sentry::PeerByIdRequest>,
+    ) -> Result<Response<grpc_sentry::PeerByIdReply>, Status> {
+        let peer_id = peer_id(request.into_inner().peer_id)?;
+        let peer = self
+            .capability_server
+            .with_peer(peer_id, |peer| grpc_types::PeerInfo {
+                id: hex::encode(peer.node_id),
+                conn_is_trusted: peer.trusted,
+                ..Default::default()
+            });
+
+        Ok(Response::new(grpc_sentry::PeerByIdReply { peer }))
+    }
+
+    type PeerEventsStream = ResponseStream<grpc_sentry::PeerEvent>;
+
+    async fn peer_events(
+        &self,
+        _: Request<grpc_sentry::PeerEventsRequest>,
+    ) -> Result<Response<Self::PeerEventsStream>, Status> {
+        // Subscribed before listing the peers, so that none connecting meanwhile is missed.
+        let events = BroadcastStream::new(self.capability_server.subscribe_peer_events())
+            .filter_map(|event| event.ok().map(Ok));
+        let connected = self
+            .capability_server
+            .ready_peers()
+            .into_iter()
+            .map(|peer_id| {
+                Ok(grpc_sentry::PeerEvent {
+                    peer_id: Some(peer_id.into()),
+                    event_id: grpc_sentry::peer_event::PeerEventId::Connect as i32,
+                })
+            });
+        let stream = tokio_stream::iter(connected).chain(events);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn node_info(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<grpc_types::NodeInfoReply>, Status> {
+        Ok(Response::new(grpc_types::NodeInfoReply {
+            name: crate::version_string(),
+            enode: self.node_info.enode.clone(),
+            ports: Some(grpc_types::NodeInfoPorts {
+                discovery: self.node_info.discovery_port.into(),
+                listener: self.node_info.listen_port.into(),
+            }),
+            listener_addr: format!("0.0.0.0:{}", self.node_info.listen_port),
+            protocols: serde_json::to_vec(&SentryProtocols {
+                running: vec![format!("{}/{}", capability_name().0, PROTOCOL_VERSION)],
+                peers: self.capability_server.protocol_peer_counts(),
+            })
+            .map_err(|e| Status::internal(e.to_string()))?,
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocol_version() {
+        let protocols = SentryProtocols {
+            running: vec!["eth/66".into(), "snap/1".into(), "eth/67".into()],
+            ..Default::default()
+        };
+        assert_eq!(protocols.version("eth"), Some(67));
+        assert_eq!(protocols.version("snap"), Some(1));
+        assert_eq!(protocols.version("les"), None);
+    }
+}
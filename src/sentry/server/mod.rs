@@ -0,0 +1,287 @@
+//! Sentry server: keeps the devp2p peer set and serves it over gRPC to the downloader.
+mod eth;
+mod grpc;
+pub mod nat;
+pub mod opts;
+
+pub use self::{
+    grpc::SentryProtocols,
+    opts::{EnrTreeUrl, NodeId, NodeRecord, SentryOpts},
+};
+
+use self::{
+    eth::{capability_name, CapabilityServerImpl, MESSAGE_COUNT, PROTOCOL_VERSION},
+    grpc::{NodeInfo, SentryService},
+    nat::{NatMethod, PortMapping, Protocol},
+};
+use crate::models::P2PParams;
+use anyhow::Context;
+use devp2p::{
+    disc::{Discv4Builder, Discv5, DnsDiscovery, StaticNodes},
+    CapabilityId, Discovery, ListenOptions, Swarm,
+};
+use ethereum_interfaces::sentry::sentry_server::SentryServer;
+use maplit::btreemap;
+use secp256k1::{PublicKey, SecretKey, SECP256K1};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use task_group::TaskGroup;
+use tokio_stream::StreamMap;
+use tracing::*;
+use trust_dns_resolver::TokioAsyncResolver;
+
+pub fn node_id(secret_key: &SecretKey) -> NodeId {
+    NodeId::from_slice(
+        &PublicKey::from_secret_key(SECP256K1, secret_key).serialize_uncompressed()[1..],
+    )
+}
+
+async fn discovery(
+    opts: &SentryOpts,
+    secret_key: SecretKey,
+    bootnodes: Vec<NodeRecord>,
+    dns_networks: Vec<EnrTreeUrl>,
+    external_ip: Option<IpAddr>,
+) -> anyhow::Result<StreamMap<String, Discovery>> {
+    let mut tasks = StreamMap::<String, Discovery>::new();
+
+    if !opts.no_dnsdisc && !dns_networks.is_empty() {
+        let resolver = Arc::new(dnsdisc::Resolver::new(Arc::new(
+            TokioAsyncResolver::tokio_from_system_conf().context("failed to start DNS resolver")?,
+        )));
+        for network in dns_networks {
+            info!("Starting DNS discovery from {}", network.domain);
+            tasks.insert(
+                format!("dnsdisc {}", network.domain),
+                Box::pin(DnsDiscovery::new(
+                    resolver.clone(),
+                    network.domain,
+                    Some(network.public_key),
+                )),
+            );
+        }
+    }
+
+    if !opts.no_discv4 {
+        let bootnodes = bootnodes
+            .into_iter()
+            .map(|record| discv4::NodeRecord {
+                address: record.addr.ip(),
+                tcp_port: record.addr.port(),
+                udp_port: record.discovery_port,
+                id: record.id,
+            })
+            .collect::<Vec<_>>();
+        info!("Starting discv4 with {} bootnodes", bootnodes.len());
+
+        let node = discv4::Node::new(
+            SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, opts.discv4_port).into(),
+            secret_key,
+            bootnodes,
+            external_ip,
+            false,
+            opts.listen_port,
+        )
+        .await
+        .context("failed to start discv4")?;
+
+        tasks.insert(
+            "discv4".to_string(),
+            Box::pin(
+                Discv4Builder::default()
+                    .with_cache(opts.discv4_cache)
+                    .build(node),
+            ),
+        );
+    }
+
+    if opts.discv5 {
+        let enr_key =
+            discv5::enr::CombinedKey::secp256k1_from_bytes(&mut secret_key.as_ref().to_vec())
+                .context("invalid node key")?;
+        let mut enr = discv5::enr::EnrBuilder::new("v4");
+        enr.tcp(opts.listen_port).udp(opts.discv5_port);
+        if let Some(ip) = external_ip {
+            enr.ip(ip);
+        }
+        let enr = enr.build(&enr_key)?;
+
+        let mut service =
+            discv5::Discv5::new(enr, enr_key, discv5::Discv5ConfigBuilder::new().build())
+                .map_err(|e| anyhow::format_err!("failed to create discv5 service: {}", e))?;
+        service
+            .start(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, opts.discv5_port).into())
+            .await
+            .map_err(|e| anyhow::format_err!("failed to start discv5: {}", e))?;
+        for bootnode in &opts.discv5_bootnodes {
+            let enr = bootnode
+                .parse()
+                .map_err(|e| anyhow::format_err!("invalid ENR {}: {}", bootnode, e))?;
+            service
+                .add_enr(enr)
+                .map_err(|e| anyhow::format_err!("failed to add ENR {}: {}", bootnode, e))?;
+        }
+        info!(
+            "Starting discv5 with {} bootnodes",
+            opts.discv5_bootnodes.len()
+        );
+
+        tasks.insert("discv5".to_string(), Box::pin(Discv5::new(service, 20)));
+    }
+
+    let static_peers = opts
+        .static_peers
+        .iter()
+        .chain(&opts.trusted_peers)
+        .map(|record| (record.addr, record.id))
+        .collect::<std::collections::HashMap<_, _>>();
+    if !static_peers.is_empty() {
+        info!("Dialing {} static peers", static_peers.len());
+        tasks.insert(
+            "static peers".to_string(),
+            Box::pin(StaticNodes::new(static_peers, opts.static_peers_interval())),
+        );
+    }
+
+    Ok(tasks)
+}
+
+/// Runs the sentry until the gRPC server stops.
+///
+/// Bootnodes and DNS node lists of the chain are used unless overridden on the command line.
+pub async fn run(opts: SentryOpts, grpc_addr: SocketAddr, p2p: &P2PParams) -> anyhow::Result<()> {
+    let secret_key = match opts.node_key {
+        Some(key) => key,
+        None => {
+            info!("No node key provided, generating a new one");
+            SecretKey::from_slice(&rand::random::<[u8; 32]>())?
+        }
+    };
+    let node_id = node_id(&secret_key);
+
+    let bootnodes = if opts.bootnodes.is_empty() {
+        p2p.bootnodes
+            .iter()
+            .map(|url| url.parse())
+            .collect::<anyhow::Result<Vec<NodeRecord>>>()
+            .context("invalid chain bootnode")?
+    } else {
+        opts.bootnodes.clone()
+    };
+    let dns_networks = if opts.dns_networks.is_empty() {
+        p2p.dns_networks
+            .iter()
+            .map(|url| url.parse())
+            .collect::<anyhow::Result<Vec<EnrTreeUrl>>>()
+            .context("invalid chain DNS network")?
+    } else {
+        opts.dns_networks.clone()
+    };
+
+    let tasks = Arc::new(TaskGroup::new());
+    let no_new_peers = Arc::new(AtomicBool::new(true));
+    let capability_server = Arc::new(CapabilityServerImpl::new(
+        opts.trusted_peers.iter().map(|record| record.id).collect(),
+        no_new_peers.clone(),
+    ));
+
+    let mut mappings = vec![PortMapping {
+        protocol: Protocol::Tcp,
+        port: opts.listen_port,
+    }];
+    if !opts.no_discv4 {
+        mappings.push(PortMapping {
+            protocol: Protocol::Udp,
+            port: opts.discv4_port,
+        });
+    }
+    if opts.discv5 {
+        mappings.push(PortMapping {
+            protocol: Protocol::Udp,
+            port: opts.discv5_port,
+        });
+    }
+    let detected_ip = match nat::map_ports(opts.nat, &mappings).await {
+        Ok(ip) => ip.map(IpAddr::V4),
+        Err(e) => {
+            warn!(
+                "Port mapping failed, peers may not be able to connect to us: {:?}",
+                e
+            );
+            None
+        }
+    };
+    if detected_ip.is_some() {
+        tasks.spawn(nat::renew_port_mappings(opts.nat, mappings));
+    }
+    let external_ip = opts.external_ip.or(detected_ip);
+    match external_ip {
+        Some(ip) => info!("External address: {}", ip),
+        None if opts.nat != NatMethod::None => warn!("Could not determine external address"),
+        None => {}
+    }
+
+    let discovery_tasks =
+        discovery(&opts, secret_key, bootnodes, dns_networks, external_ip).await?;
+    let listen_addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, opts.listen_port).into();
+    let _swarm = Swarm::builder()
+        .with_task_group(tasks.clone())
+        .with_listen_options(ListenOptions::new(
+            discovery_tasks,
+            opts.max_peers,
+            listen_addr,
+            None,
+            no_new_peers,
+        ))
+        .with_client_version(crate::version_string())
+        .build(
+            btreemap! {
+                CapabilityId {
+                    name: capability_name(),
+                    version: PROTOCOL_VERSION as _,
+                } => MESSAGE_COUNT,
+            },
+            capability_server.clone(),
+            secret_key,
+        )
+        .await
+        .context("failed to start devp2p swarm")?;
+
+    let enode = format!(
+        "enode://{}@{}:{}",
+        hex::encode(node_id),
+        external_ip.unwrap_or(Ipv4Addr::UNSPECIFIED.into()),
+        opts.listen_port
+    );
+    info!("Node {} listening on {}", enode, listen_addr);
+
+    tasks.spawn({
+        let capability_server = capability_server.clone();
+        async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                info!("Peers: {}", capability_server.ready_peers().len());
+            }
+        }
+    });
+
+    let service = SentryService::new(
+        capability_server,
+        NodeInfo {
+            enode,
+            listen_port: opts.listen_port,
+            discovery_port: opts.discv4_port,
+        },
+    );
+
+    info!("Sentry gRPC API listening on {}", grpc_addr);
+    tonic::transport::Server::builder()
+        .add_service(SentryServer::new(service))
+        .serve(grpc_addr)
+        .await?;
+
+    Ok(())
+}
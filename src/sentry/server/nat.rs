@@ -0,0 +1,166 @@
+use anyhow::{bail, format_err, Context};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddrV4, UdpSocket},
+    str::FromStr,
+    time::Duration,
+};
+use tracing::*;
+
+/// How long port mappings are requested for. They are renewed at half this interval.
+const MAPPING_LIFETIME: Duration = Duration::from_secs(20 * 60);
+
+/// Way to make the node reachable from behind a router.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NatMethod {
+    None,
+    /// UPnP, falling back to NAT-PMP.
+    Any,
+    Upnp,
+    NatPmp,
+}
+
+impl FromStr for NatMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "none" => Self::None,
+            "any" => Self::Any,
+            "upnp" => Self::Upnp,
+            "natpmp" | "pmp" => Self::NatPmp,
+            other => bail!(
+                "unknown NAT method {}, expected none, any, upnp or natpmp",
+                other
+            ),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortMapping {
+    pub protocol: Protocol,
+    pub port: u16,
+}
+
+async fn upnp(mappings: &[PortMapping]) -> anyhow::Result<Ipv4Addr> {
+    let gateway = igd::aio::search_gateway(Default::default())
+        .await
+        .context("no UPnP gateway found")?;
+
+    // Address of the interface facing the gateway, to point the mappings at.
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(gateway.addr)?;
+    let local_ip = match socket.local_addr()?.ip() {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(ip) => bail!("gateway reached over IPv6 from {}", ip),
+    };
+
+    for mapping in mappings {
+        gateway
+            .add_port(
+                match mapping.protocol {
+                    Protocol::Tcp => igd::PortMappingProtocol::TCP,
+                    Protocol::Udp => igd::PortMappingProtocol::UDP,
+                },
+                mapping.port,
+                SocketAddrV4::new(local_ip, mapping.port),
+                MAPPING_LIFETIME.as_secs() as u32,
+                "martinez",
+            )
+            .await
+            .with_context(|| format!("failed to map port {:?}", mapping))?;
+    }
+
+    Ok(gateway.get_external_ip().await?)
+}
+
+async fn natpmp(mappings: &[PortMapping]) -> anyhow::Result<Ipv4Addr> {
+    let client = natpmp::new_tokio_natpmp()
+        .await
+        .map_err(|e| format_err!("no NAT-PMP gateway found: {:?}", e))?;
+
+    client
+        .send_public_address_request()
+        .await
+        .map_err(|e| format_err!("NAT-PMP request failed: {:?}", e))?;
+    let external_ip = match client
+        .read_response_or_retry()
+        .await
+        .map_err(|e| format_err!("NAT-PMP request failed: {:?}", e))?
+    {
+        natpmp::Response::Gateway(response) => *response.public_address(),
+        other => bail!("unexpected NAT-PMP response {:?}", other),
+    };
+
+    for mapping in mappings {
+        client
+            .send_port_mapping_request(
+                match mapping.protocol {
+                    Protocol::Tcp => natpmp::Protocol::TCP,
+                    Protocol::Udp => natpmp::Protocol::UDP,
+                },
+                mapping.port,
+                mapping.port,
+                MAPPING_LIFETIME.as_secs() as u32,
+            )
+            .await
+            .map_err(|e| format_err!("failed to map port {:?}: {:?}", mapping, e))?;
+        client
+            .read_response_or_retry()
+            .await
+            .map_err(|e| format_err!("failed to map port {:?}: {:?}", mapping, e))?;
+    }
+
+    Ok(external_ip)
+}
+
+/// Sets up the port mappings once. Returns the external address the router reports.
+pub async fn map_ports(
+    method: NatMethod,
+    mappings: &[PortMapping],
+) -> anyhow::Result<Option<Ipv4Addr>> {
+    Ok(match method {
+        NatMethod::None => None,
+        NatMethod::Upnp => Some(upnp(mappings).await?),
+        NatMethod::NatPmp => Some(natpmp(mappings).await?),
+        NatMethod::Any => match upnp(mappings).await {
+            Ok(ip) => Some(ip),
+            Err(e) => {
+                debug!("UPnP failed, trying NAT-PMP: {:?}", e);
+                Some(natpmp(mappings).await?)
+            }
+        },
+    })
+}
+
+/// Keeps the mappings alive for as long as the node runs.
+pub async fn renew_port_mappings(method: NatMethod, mappings: Vec<PortMapping>) {
+    loop {
+        tokio::time::sleep(MAPPING_LIFETIME / 2).await;
+        match map_ports(method, &mappings).await {
+            Ok(external_ip) => {
+                debug!("Renewed port mappings, external address {:?}", external_ip)
+            }
+            Err(e) => warn!("Failed to renew port mappings: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_nat_method() {
+        assert_eq!("none".parse::<NatMethod>().unwrap(), NatMethod::None);
+        assert_eq!("UPnP".parse::<NatMethod>().unwrap(), NatMethod::Upnp);
+        assert_eq!("pmp".parse::<NatMethod>().unwrap(), NatMethod::NatPmp);
+        "extip".parse::<NatMethod>().unwrap_err();
+    }
+}
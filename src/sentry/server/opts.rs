@@ -0,0 +1,220 @@
+use super::nat::NatMethod;
+use anyhow::{bail, format_err, Context};
+use clap::Parser;
+use ethereum_types::H512;
+use std::{
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Public key of a devp2p node.
+pub type NodeId = H512;
+
+/// Address and identity of a node as given by an `enode://<id>@<host>:<port>` URL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeRecord {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+    /// UDP port for discovery, if it differs from the TCP port.
+    pub discovery_port: u16,
+}
+
+impl FromStr for NodeRecord {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s
+            .strip_prefix("enode://")
+            .ok_or_else(|| format_err!("not an enode URL: {}", s))?;
+        let (id, addr) = s
+            .split_once('@')
+            .ok_or_else(|| format_err!("enode URL without address: {}", s))?;
+        let (addr, query) = match addr.split_once('?') {
+            Some((addr, query)) => (addr, Some(query)),
+            None => (addr, None),
+        };
+
+        let id = hex::decode(id).context("invalid node id")?;
+        if id.len() != NodeId::len_bytes() {
+            bail!("node id must be {} bytes long", NodeId::len_bytes());
+        }
+        let id = NodeId::from_slice(&id);
+
+        let addr = addr
+            .to_socket_addrs()
+            .with_context(|| format!("invalid node address {}", addr))?
+            .next()
+            .ok_or_else(|| format_err!("node address {} does not resolve", addr))?;
+
+        let mut discovery_port = addr.port();
+        if let Some(query) = query {
+            for param in query.split('&') {
+                if let Some(port) = param.strip_prefix("discport=") {
+                    discovery_port = port.parse().context("invalid discovery port")?;
+                }
+            }
+        }
+
+        Ok(Self {
+            id,
+            addr,
+            discovery_port,
+        })
+    }
+}
+
+/// Root of an EIP-1459 node list: `enrtree://<base32 public key>@<domain>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnrTreeUrl {
+    /// Key the tree root is signed with.
+    pub public_key: secp256k1::PublicKey,
+    pub domain: String,
+}
+
+impl FromStr for EnrTreeUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (public_key, domain) = s
+            .strip_prefix("enrtree://")
+            .and_then(|s| s.split_once('@'))
+            .ok_or_else(|| format_err!("not an enrtree URL: {}", s))?;
+        if domain.is_empty() {
+            bail!("enrtree URL without domain: {}", s);
+        }
+
+        let public_key = data_encoding::BASE32_NOPAD
+            .decode(public_key.as_bytes())
+            .context("invalid enrtree public key encoding")?;
+        let public_key =
+            secp256k1::PublicKey::from_slice(&public_key).context("invalid enrtree public key")?;
+
+        Ok(Self {
+            public_key,
+            domain: domain.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct SentryOpts {
+    /// Port to accept devp2p connections on.
+    #[clap(long = "p2p.listen-port", default_value = "30303")]
+    pub listen_port: u16,
+
+    /// Maximum number of connected peers.
+    #[clap(long = "p2p.max-peers", default_value = "50")]
+    pub max_peers: usize,
+
+    /// Port mapping on the router: none, any, upnp or natpmp.
+    #[clap(long = "p2p.nat", default_value = "any")]
+    pub nat: NatMethod,
+
+    /// Address peers can reach us at, instead of the one reported by the router.
+    #[clap(long = "p2p.external-ip")]
+    pub external_ip: Option<IpAddr>,
+
+    /// Hex-encoded secp256k1 node key. A new one is generated on every start if not set.
+    #[clap(long = "p2p.node-key")]
+    pub node_key: Option<secp256k1::SecretKey>,
+
+    /// Disable discv4 discovery.
+    #[clap(long = "p2p.no-discv4")]
+    pub no_discv4: bool,
+
+    /// UDP port for discv4 discovery.
+    #[clap(long = "p2p.discv4-port", default_value = "30303")]
+    pub discv4_port: u16,
+
+    /// Number of discovered nodes to keep for dialing.
+    #[clap(long = "p2p.discv4-cache", default_value = "1000")]
+    pub discv4_cache: usize,
+
+    /// Enable discv5 discovery.
+    #[clap(long = "p2p.discv5")]
+    pub discv5: bool,
+
+    /// UDP port for discv5 discovery.
+    #[clap(long = "p2p.discv5-port", default_value = "30304")]
+    pub discv5_port: u16,
+
+    /// Base64 ENRs to bootstrap discv5 from.
+    #[clap(long = "p2p.discv5-bootnodes", multiple_values = true)]
+    pub discv5_bootnodes: Vec<String>,
+
+    /// Discv4 bootnodes as enode URLs. Defaults to the bootnodes of the chain.
+    #[clap(long = "p2p.bootnodes", multiple_values = true)]
+    pub bootnodes: Vec<NodeRecord>,
+
+    /// Disable DNS discovery.
+    #[clap(long = "p2p.no-dnsdisc")]
+    pub no_dnsdisc: bool,
+
+    /// EIP-1459 node lists to discover peers from, as enrtree URLs. Defaults to the lists of the chain.
+    #[clap(long = "p2p.dns-networks", multiple_values = true)]
+    pub dns_networks: Vec<EnrTreeUrl>,
+
+    /// Peers to always keep dialing, as enode URLs.
+    #[clap(long = "p2p.static-peers", multiple_values = true)]
+    pub static_peers: Vec<NodeRecord>,
+
+    /// Like static peers, but never disconnected on penalty.
+    #[clap(long = "p2p.trusted-peers", multiple_values = true)]
+    pub trusted_peers: Vec<NodeRecord>,
+
+    /// How often to redial static and trusted peers, in seconds.
+    #[clap(long = "p2p.static-peers-interval", default_value = "30")]
+    pub static_peers_interval_secs: u64,
+}
+
+impl SentryOpts {
+    pub fn static_peers_interval(&self) -> Duration {
+        Duration::from_secs(self.static_peers_interval_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn parse_enode() {
+        let record = "enode://a24ac7c5484ef4ed0c5eb2d36620ba4e4aa13b8c84684e1b4aab0cebea2ae45cb4d375b77eab56516d34bfbd3c1a833fc51296ff084b770b94fb9028c4d25ccf@52.169.42.101:30303?discport=30301"
+            .parse::<NodeRecord>()
+            .unwrap();
+        assert_eq!(
+            record,
+            NodeRecord {
+                id: hex!("a24ac7c5484ef4ed0c5eb2d36620ba4e4aa13b8c84684e1b4aab0cebea2ae45cb4d375b77eab56516d34bfbd3c1a833fc51296ff084b770b94fb9028c4d25ccf").into(),
+                addr: "52.169.42.101:30303".parse().unwrap(),
+                discovery_port: 30301,
+            }
+        );
+
+        "enode://a24ac7@52.169.42.101:30303"
+            .parse::<NodeRecord>()
+            .unwrap_err();
+        "52.169.42.101:30303".parse::<NodeRecord>().unwrap_err();
+    }
+
+    #[test]
+    fn parse_enrtree() {
+        let url = "enrtree://AKA3AM6LPBYEUDMVNU3BSVQJ5AD45Y7YPOHJLEF6W26QOE4VTUDPE@all.mainnet.ethdisco.net"
+            .parse::<EnrTreeUrl>()
+            .unwrap();
+        assert_eq!(url.domain, "all.mainnet.ethdisco.net");
+        assert_eq!(
+            url.public_key.serialize(),
+            hex!("0281b033cb78704a0d956d36195609e807cee3f87b8e9590beb6bd0713959d06f2")
+        );
+
+        "enrtree://AKA3AM6LPBYEUDMVNU3BSVQJ5AD45Y7YPOHJLEF6W26QOE4VTUDPE@"
+            .parse::<EnrTreeUrl>()
+            .unwrap_err();
+        "enrtree://AKA3AM6L@all.mainnet.ethdisco.net"
+            .parse::<EnrTreeUrl>()
+            .unwrap_err();
+    }
+}
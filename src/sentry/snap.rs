@@ -0,0 +1,347 @@
+//! Messages of the [snap/1 protocol](https://github.com/ethereum/devp2p/blob/master/caps/snap.md),
+//! used to download the state at a recent block as ranges of accounts, storage slots and
+//! contract code instead of executing every block since genesis.
+use crate::{
+    crypto::keccak,
+    models::{RlpAccount, EMPTY_HASH, EMPTY_ROOT},
+};
+use anyhow::{bail, ensure};
+use bytes::Bytes;
+use ethereum_types::H256;
+use rlp_derive::*;
+
+pub const PROTOCOL_VERSION: usize = 1;
+/// Number of message ids reserved by snap/1.
+pub const MESSAGE_COUNT: usize = 8;
+
+/// Response size that requests ask for unless configured otherwise.
+pub const SOFT_RESPONSE_LIMIT: u64 = 2 * 1024 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, strum::EnumIter)]
+pub enum SnapMessageId {
+    GetAccountRange = 0,
+    AccountRange = 1,
+    GetStorageRanges = 2,
+    StorageRanges = 3,
+    GetByteCodes = 4,
+    ByteCodes = 5,
+    GetTrieNodes = 6,
+    TrieNodes = 7,
+}
+
+/// Account of a range response, in the "slim" encoding of snap: the storage root and code hash
+/// of accounts without storage or code are encoded as empty strings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AccountData {
+    pub hash: H256,
+    pub account: RlpAccount,
+}
+
+impl rlp::Encodable for AccountData {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        let slim_hash = |h: H256, empty: H256| -> Vec<u8> {
+            if h == empty {
+                vec![]
+            } else {
+                h.as_bytes().to_vec()
+            }
+        };
+
+        let mut body = rlp::RlpStream::new_list(4);
+        body.append(&self.account.nonce);
+        body.append(&self.account.balance);
+        body.append(&slim_hash(self.account.storage_root, EMPTY_ROOT));
+        body.append(&slim_hash(self.account.code_hash, EMPTY_HASH));
+
+        s.begin_list(2);
+        s.append(&self.hash);
+        s.append_raw(&body.out(), 1);
+    }
+}
+
+impl rlp::Decodable for AccountData {
+    fn decode(rlp: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        if rlp.item_count()? != 2 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+        let body = rlp.at(1)?;
+        if body.item_count()? != 4 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+        let full_hash = |item: rlp::Rlp, empty: H256| -> Result<H256, rlp::DecoderError> {
+            if item.is_empty() {
+                Ok(empty)
+            } else {
+                item.as_val()
+            }
+        };
+
+        Ok(Self {
+            hash: rlp.val_at(0)?,
+            account: RlpAccount {
+                nonce: body.val_at(0)?,
+                balance: body.val_at(1)?,
+                storage_root: full_hash(body.at(2)?, EMPTY_ROOT)?,
+                code_hash: full_hash(body.at(3)?, EMPTY_HASH)?,
+            },
+        })
+    }
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct GetAccountRangeMessage {
+    pub request_id: u64,
+    pub root_hash: H256,
+    pub starting_hash: H256,
+    pub limit_hash: H256,
+    pub response_bytes: u64,
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct AccountRangeMessage {
+    pub request_id: u64,
+    pub accounts: Vec<AccountData>,
+    pub proof: Vec<Bytes>,
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct StorageData {
+    pub hash: H256,
+    /// RLP of the slot value.
+    pub data: Bytes,
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct GetStorageRangesMessage {
+    pub request_id: u64,
+    pub root_hash: H256,
+    pub account_hashes: Vec<H256>,
+    /// Empty to start from the first slot.
+    pub starting_hash: Bytes,
+    /// Empty to continue to the last slot.
+    pub limit_hash: Bytes,
+    pub response_bytes: u64,
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct StorageRangesMessage {
+    pub request_id: u64,
+    /// Slots of each requested account, in request order.
+    pub slots: Vec<Vec<StorageData>>,
+    pub proof: Vec<Bytes>,
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct GetByteCodesMessage {
+    pub request_id: u64,
+    pub hashes: Vec<H256>,
+    pub response_bytes: u64,
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct ByteCodesMessage {
+    pub request_id: u64,
+    pub codes: Vec<Bytes>,
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct GetTrieNodesMessage {
+    pub request_id: u64,
+    pub root_hash: H256,
+    /// Paths of the nodes: an account trie path optionally followed by storage trie paths.
+    pub paths: Vec<Vec<Bytes>>,
+    pub response_bytes: u64,
+}
+
+#[derive(RlpEncodable, RlpDecodable, Clone, PartialEq, Debug)]
+pub struct TrieNodesMessage {
+    pub request_id: u64,
+    pub nodes: Vec<Bytes>,
+}
+
+/// Fails unless `hashes` are strictly increasing and none is below `starting_hash`.
+fn check_ordered<'a>(
+    mut hashes: impl Iterator<Item = &'a H256>,
+    starting_hash: H256,
+) -> anyhow::Result<()> {
+    let mut previous = match hashes.next() {
+        Some(first) if *first < starting_hash => {
+            bail!(
+                "{:?} is before the requested start {:?}",
+                first,
+                starting_hash
+            )
+        }
+        Some(first) => *first,
+        None => return Ok(()),
+    };
+    for hash in hashes {
+        ensure!(*hash > previous, "{:?} is out of order", hash);
+        previous = *hash;
+    }
+    Ok(())
+}
+
+impl AccountRangeMessage {
+    /// Checks that the accounts answer `request`. Proofs are left to the caller.
+    pub fn check(&self, request: &GetAccountRangeMessage) -> anyhow::Result<()> {
+        ensure!(self.request_id == request.request_id, "request id mismatch");
+        check_ordered(
+            self.accounts.iter().map(|account| &account.hash),
+            request.starting_hash,
+        )
+    }
+}
+
+impl StorageRangesMessage {
+    /// Checks that the slots answer `request`. Proofs are left to the caller.
+    pub fn check(&self, request: &GetStorageRangesMessage) -> anyhow::Result<()> {
+        ensure!(self.request_id == request.request_id, "request id mismatch");
+        ensure!(
+            self.slots.len() <= request.account_hashes.len(),
+            "{} storage ranges for {} accounts",
+            self.slots.len(),
+            request.account_hashes.len()
+        );
+        for (i, slots) in self.slots.iter().enumerate() {
+            // Only the first account is continued from the requested start.
+            let starting_hash = if i == 0 && !request.starting_hash.is_empty() {
+                ensure!(request.starting_hash.len() == 32, "invalid starting hash");
+                H256::from_slice(&request.starting_hash)
+            } else {
+                H256::zero()
+            };
+            check_ordered(slots.iter().map(|slot| &slot.hash), starting_hash)?;
+        }
+        Ok(())
+    }
+}
+
+impl ByteCodesMessage {
+    /// Pairs the codes with the requested hashes they match.
+    ///
+    /// Peers may leave out codes they do not have, but must keep the request order.
+    pub fn match_request(
+        &self,
+        request: &GetByteCodesMessage,
+    ) -> anyhow::Result<Vec<(H256, Bytes)>> {
+        ensure!(self.request_id == request.request_id, "request id mismatch");
+
+        let code_hashes = keccak::hash_batch(&self.codes);
+        let mut requested = request.hashes.iter();
+        let mut out = Vec::with_capacity(self.codes.len());
+        for (code, code_hash) in self.codes.iter().zip(code_hashes) {
+            if !requested.any(|hash| *hash == code_hash) {
+                bail!("unrequested or reordered code {:?}", code_hash);
+            }
+            out.push((code_hash, code.clone()));
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::keccak256, models::U256};
+    use hex_literal::hex;
+
+    fn account_data(hash: u8, storage_root: H256, code_hash: H256) -> AccountData {
+        AccountData {
+            hash: H256::repeat_byte(hash),
+            account: RlpAccount {
+                nonce: 1,
+                balance: U256::from(2_u64),
+                storage_root,
+                code_hash,
+            },
+        }
+    }
+
+    #[test]
+    fn slim_account_encoding() {
+        let empty = account_data(1, EMPTY_ROOT, EMPTY_HASH);
+        let encoded = rlp::encode(&empty);
+        assert_eq!(
+            &encoded[..],
+            hex!("e6a00101010101010101010101010101010101010101010101010101010101010101c401028080")
+        );
+        assert_eq!(rlp::decode::<AccountData>(&encoded).unwrap(), empty);
+
+        let contract = account_data(1, H256::repeat_byte(2), H256::repeat_byte(3));
+        assert_eq!(
+            rlp::decode::<AccountData>(&rlp::encode(&contract)).unwrap(),
+            contract
+        );
+
+        let message = AccountRangeMessage {
+            request_id: 7,
+            accounts: vec![empty, contract],
+            proof: vec![Bytes::from_static(b"node")],
+        };
+        assert_eq!(
+            rlp::decode::<AccountRangeMessage>(&rlp::encode(&message)).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn account_range_order() {
+        let request = GetAccountRangeMessage {
+            request_id: 1,
+            root_hash: H256::zero(),
+            starting_hash: H256::repeat_byte(2),
+            limit_hash: H256::repeat_byte(0xff),
+            response_bytes: SOFT_RESPONSE_LIMIT,
+        };
+        let response = |hashes: &[u8]| AccountRangeMessage {
+            request_id: 1,
+            accounts: hashes
+                .iter()
+                .map(|&hash| account_data(hash, EMPTY_ROOT, EMPTY_HASH))
+                .collect(),
+            proof: vec![],
+        };
+
+        assert!(response(&[]).check(&request).is_ok());
+        assert!(response(&[2, 3, 5]).check(&request).is_ok());
+        assert!(response(&[1, 3]).check(&request).is_err());
+        assert!(response(&[3, 3]).check(&request).is_err());
+        assert!(response(&[4, 3]).check(&request).is_err());
+    }
+
+    #[test]
+    fn byte_codes_match() {
+        let codes = [
+            Bytes::from_static(b"a"),
+            Bytes::from_static(b"b"),
+            Bytes::from_static(b"c"),
+        ];
+        let request = GetByteCodesMessage {
+            request_id: 1,
+            hashes: codes.iter().map(keccak256).collect(),
+            response_bytes: SOFT_RESPONSE_LIMIT,
+        };
+        let response = |codes: Vec<Bytes>| ByteCodesMessage {
+            request_id: 1,
+            codes,
+        };
+
+        // missing codes are skipped
+        assert_eq!(
+            response(vec![codes[0].clone(), codes[2].clone()])
+                .match_request(&request)
+                .unwrap(),
+            vec![
+                (keccak256(&codes[0]), codes[0].clone()),
+                (keccak256(&codes[2]), codes[2].clone())
+            ]
+        );
+        assert!(response(vec![codes[2].clone(), codes[0].clone()])
+            .match_request(&request)
+            .is_err());
+        assert!(response(vec![Bytes::from_static(b"d")])
+            .match_request(&request)
+            .is_err());
+    }
+}
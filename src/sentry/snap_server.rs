@@ -0,0 +1,519 @@
+//! Answers snap/1 requests of peers from the hashed state and code tables.
+//!
+//! Only the state at the block the intermediate hashes were last verified at is served, and
+//! only while the hashed state has not moved past it. Range proofs and trie nodes are not served:
+//! the intermediate hashes tables keep branch node hashes only, not the nodes themselves.
+use super::{sentry_client::PeerId, snap::*};
+use crate::{
+    accessors::chain,
+    kv::{mdbx::*, tables},
+    models::*,
+    stagedsync::stages::{HASH_STATE, INTERMEDIATE_HASHES},
+    trie::hashed_storage_root,
+};
+use bytes::Bytes;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use std::time::Instant;
+
+static SERVED_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "snap_served_bytes_total",
+        "Bytes of snap responses served to peers, by message type",
+        &["message"]
+    )
+    .unwrap()
+});
+
+static THROTTLED_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "snap_throttled_requests_total",
+        "Snap requests dropped for exceeding the response budget of the peer, by message type",
+        &["message"]
+    )
+    .unwrap()
+});
+
+/// Maximum number of codes in a response.
+const MAX_CODES: usize = 1024;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum SnapRequest {
+    GetAccountRange(GetAccountRangeMessage),
+    GetStorageRanges(GetStorageRangesMessage),
+    GetByteCodes(GetByteCodesMessage),
+    GetTrieNodes(GetTrieNodesMessage),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum SnapResponse {
+    AccountRange(AccountRangeMessage),
+    StorageRanges(StorageRangesMessage),
+    ByteCodes(ByteCodesMessage),
+    TrieNodes(TrieNodesMessage),
+}
+
+impl SnapRequest {
+    pub fn snap_id(&self) -> SnapMessageId {
+        match self {
+            SnapRequest::GetAccountRange(_) => SnapMessageId::GetAccountRange,
+            SnapRequest::GetStorageRanges(_) => SnapMessageId::GetStorageRanges,
+            SnapRequest::GetByteCodes(_) => SnapMessageId::GetByteCodes,
+            SnapRequest::GetTrieNodes(_) => SnapMessageId::GetTrieNodes,
+        }
+    }
+}
+
+impl SnapResponse {
+    pub fn snap_id(&self) -> SnapMessageId {
+        match self {
+            SnapResponse::AccountRange(_) => SnapMessageId::AccountRange,
+            SnapResponse::StorageRanges(_) => SnapMessageId::StorageRanges,
+            SnapResponse::ByteCodes(_) => SnapMessageId::ByteCodes,
+            SnapResponse::TrieNodes(_) => SnapMessageId::TrieNodes,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        match self {
+            SnapResponse::AccountRange(m) => rlp::encode(m),
+            SnapResponse::StorageRanges(m) => rlp::encode(m),
+            SnapResponse::ByteCodes(m) => rlp::encode(m),
+            SnapResponse::TrieNodes(m) => rlp::encode(m),
+        }
+        .freeze()
+    }
+}
+
+struct PeerBudget {
+    time: Instant,
+    bytes: f64,
+}
+
+/// Budget of response bytes of each peer, refilled at a constant rate up to a burst size.
+///
+/// A request is served as long as the budget of the peer is not exhausted,
+/// and its response is then charged to the budget, possibly taking it below zero.
+pub struct SnapRateLimiter {
+    bytes_per_sec: u64,
+    burst_bytes: u64,
+    peers: Mutex<LruCache<PeerId, PeerBudget>>,
+}
+
+impl SnapRateLimiter {
+    pub fn new(bytes_per_sec: u64, burst_bytes: u64, max_peers: usize) -> Self {
+        Self {
+            bytes_per_sec,
+            burst_bytes,
+            peers: Mutex::new(LruCache::new(max_peers)),
+        }
+    }
+
+    fn refill(&self, budget: &mut PeerBudget, now: Instant) {
+        let elapsed = now.saturating_duration_since(budget.time).as_secs_f64();
+        budget.bytes =
+            (budget.bytes + elapsed * self.bytes_per_sec as f64).min(self.burst_bytes as f64);
+        budget.time = now;
+    }
+
+    /// Whether `peer_id` has budget left at `now`.
+    pub fn allow(&self, peer_id: PeerId, now: Instant) -> bool {
+        let mut peers = self.peers.lock();
+        match peers.get_mut(&peer_id) {
+            Some(budget) => {
+                self.refill(budget, now);
+                budget.bytes > 0.0
+            }
+            None => true,
+        }
+    }
+
+    /// Takes `bytes` sent to `peer_id` at `now` from its budget.
+    pub fn charge(&self, peer_id: PeerId, bytes: usize, now: Instant) {
+        let mut peers = self.peers.lock();
+        if peers.get(&peer_id).is_none() {
+            peers.put(
+                peer_id,
+                PeerBudget {
+                    time: now,
+                    bytes: self.burst_bytes as f64,
+                },
+            );
+        }
+        let budget = peers.get_mut(&peer_id).unwrap();
+        self.refill(budget, now);
+        budget.bytes -= bytes as f64;
+    }
+}
+
+pub struct SnapServer {
+    limiter: SnapRateLimiter,
+}
+
+impl SnapServer {
+    pub fn new(limiter: SnapRateLimiter) -> Self {
+        Self { limiter }
+    }
+
+    /// Response to `request` of `peer_id`, or `None` if the peer is over its budget.
+    pub fn serve<K, E>(
+        &self,
+        txn: &MdbxTransaction<'_, K, E>,
+        peer_id: PeerId,
+        request: &SnapRequest,
+    ) -> anyhow::Result<Option<SnapResponse>>
+    where
+        K: TransactionKind,
+        E: EnvironmentKind,
+    {
+        let message_label = format!("{:?}", request.snap_id());
+        if !self.limiter.allow(peer_id, Instant::now()) {
+            THROTTLED_REQUESTS
+                .with_label_values(&[&message_label])
+                .inc();
+            return Ok(None);
+        }
+
+        let response = match request {
+            SnapRequest::GetAccountRange(request) => {
+                SnapResponse::AccountRange(account_range(txn, request)?)
+            }
+            SnapRequest::GetStorageRanges(request) => {
+                SnapResponse::StorageRanges(storage_ranges(txn, request)?)
+            }
+            SnapRequest::GetByteCodes(request) => {
+                SnapResponse::ByteCodes(byte_codes(txn, request)?)
+            }
+            SnapRequest::GetTrieNodes(request) => SnapResponse::TrieNodes(TrieNodesMessage {
+                request_id: request.request_id,
+                nodes: vec![],
+            }),
+        };
+
+        let len = response.encode().len();
+        self.limiter.charge(peer_id, len, Instant::now());
+        SERVED_BYTES
+            .with_label_values(&[&message_label])
+            .inc_by(len as u64);
+
+        Ok(Some(response))
+    }
+}
+
+fn response_limit(response_bytes: u64) -> usize {
+    response_bytes.min(SOFT_RESPONSE_LIMIT) as usize
+}
+
+/// State root that can be served: that of the block the intermediate hashes were last verified
+/// at, unless the hashed state has already moved past it.
+pub fn served_state_root<K, E>(txn: &MdbxTransaction<'_, K, E>) -> anyhow::Result<Option<H256>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let Some(block_number) = INTERMEDIATE_HASHES.get_progress(txn)? else {
+        return Ok(None);
+    };
+    if HASH_STATE.get_progress(txn)? != Some(block_number) {
+        return Ok(None);
+    }
+    let Some(hash) = chain::canonical_hash::read(txn, block_number)? else {
+        return Ok(None);
+    };
+    Ok(
+        chain::header::read(txn, BlockKey::new(block_number, hash))?
+            .map(|header| header.state_root),
+    )
+}
+
+pub fn account_range<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    request: &GetAccountRangeMessage,
+) -> anyhow::Result<AccountRangeMessage>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut response = AccountRangeMessage {
+        request_id: request.request_id,
+        accounts: vec![],
+        proof: vec![],
+    };
+    if served_state_root(txn)? != Some(request.root_hash) {
+        return Ok(response);
+    }
+
+    let limit = response_limit(request.response_bytes);
+    let mut size = 0;
+    for res in txn
+        .cursor(tables::HashedAccount)?
+        .walk(Some(request.starting_hash))
+    {
+        let (hash, account) = res?;
+        let account = AccountData {
+            hash,
+            account: account.to_rlp(hashed_storage_root(txn, hash)?),
+        };
+        size += rlp::encode(&account).len();
+        response.accounts.push(account);
+
+        // The first account past the limit proves there are no more in the range.
+        if size >= limit || hash >= request.limit_hash {
+            break;
+        }
+    }
+
+    Ok(response)
+}
+
+pub fn storage_ranges<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    request: &GetStorageRangesMessage,
+) -> anyhow::Result<StorageRangesMessage>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut response = StorageRangesMessage {
+        request_id: request.request_id,
+        slots: vec![],
+        proof: vec![],
+    };
+    if served_state_root(txn)? != Some(request.root_hash) {
+        return Ok(response);
+    }
+
+    let bound = |bytes: &Bytes, default| {
+        if bytes.len() == 32 {
+            H256::from_slice(bytes)
+        } else {
+            default
+        }
+    };
+    let starting_hash = bound(&request.starting_hash, H256::zero());
+    let limit_hash = bound(&request.limit_hash, H256::repeat_byte(0xff));
+
+    let limit = response_limit(request.response_bytes);
+    let mut size = 0;
+    let mut cursor = txn.cursor(tables::HashedStorage)?;
+    for (i, hashed_address) in request.account_hashes.iter().enumerate() {
+        // Only the first account is continued from the requested start.
+        let (start, end) = if i == 0 {
+            (starting_hash, limit_hash)
+        } else {
+            (H256::zero(), H256::repeat_byte(0xff))
+        };
+
+        let mut slots = vec![];
+        let mut entry = cursor.seek_both_range(*hashed_address, start)?;
+        while let Some((location, value)) = entry {
+            let slot = StorageData {
+                hash: location,
+                data: rlp::encode(&value).freeze(),
+            };
+            size += rlp::encode(&slot).len();
+            slots.push(slot);
+
+            if size >= limit || location >= end {
+                break;
+            }
+            entry = cursor.next_dup()?.map(|(_, v)| v);
+        }
+        response.slots.push(slots);
+
+        if size >= limit {
+            break;
+        }
+    }
+
+    Ok(response)
+}
+
+pub fn byte_codes<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    request: &GetByteCodesMessage,
+) -> anyhow::Result<ByteCodesMessage>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let limit = response_limit(request.response_bytes);
+    let mut size = 0;
+    let mut codes = vec![];
+    for hash in request.hashes.iter().take(MAX_CODES) {
+        if let Some(code) = txn.get(tables::Code, *hash)? {
+            size += code.len();
+            codes.push(code);
+        }
+        if size >= limit {
+            break;
+        }
+    }
+
+    Ok(ByteCodesMessage {
+        request_id: request.request_id,
+        codes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::{keccak256, trie_root},
+        kv::new_mem_database,
+        trie::regenerate_intermediate_hashes,
+        u256_to_h256, upsert_hashed_storage_value, zeroless_view,
+    };
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn rate_limit() {
+        let limiter = SnapRateLimiter::new(100, 1000, 2);
+        let peer = PeerId::repeat_byte(1);
+        let other = PeerId::repeat_byte(2);
+        let now = Instant::now();
+
+        assert!(limiter.allow(peer, now));
+        limiter.charge(peer, 1500, now);
+        assert!(!limiter.allow(peer, now));
+        assert!(limiter.allow(other, now));
+        assert!(!limiter.allow(peer, now + Duration::from_secs(5)));
+        assert!(limiter.allow(peer, now + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn serve_state() {
+        let db = new_mem_database().unwrap();
+        let txn = db.begin_mutable().unwrap();
+
+        let accounts = (1..=3_u64)
+            .map(|n| {
+                let address = Address::from_low_u64_be(n);
+                let account = Account {
+                    nonce: n,
+                    code_hash: keccak256([n as u8]),
+                    ..Account::default()
+                };
+                txn.set(tables::HashedAccount, keccak256(address), account)
+                    .unwrap();
+                txn.set(
+                    tables::Code,
+                    account.code_hash,
+                    Bytes::copy_from_slice(&[n as u8]),
+                )
+                .unwrap();
+                (keccak256(address), account)
+            })
+            .collect::<std::collections::BTreeMap<_, _>>();
+        let (&contract, _) = accounts.iter().next().unwrap();
+        let mut storage = txn.cursor(tables::HashedStorage).unwrap();
+        for n in 1..=3_u64 {
+            upsert_hashed_storage_value(
+                &mut storage,
+                contract,
+                H256::from_low_u64_be(n),
+                n.as_u256(),
+            )
+            .unwrap();
+        }
+
+        let state_root =
+            regenerate_intermediate_hashes(&txn, &TempDir::new().unwrap(), None).unwrap();
+        let block = BlockNumber(5);
+        let header = BlockHeader {
+            number: block,
+            state_root,
+            ..BlockHeader::empty()
+        };
+        txn.set(tables::CanonicalHeader, block, header.hash())
+            .unwrap();
+        txn.set(tables::Header, (block, header.hash()), header)
+            .unwrap();
+        HASH_STATE.save_progress(&txn, block).unwrap();
+        INTERMEDIATE_HASHES.save_progress(&txn, block).unwrap();
+
+        let server = SnapServer::new(SnapRateLimiter::new(1 << 20, 1 << 20, 16));
+        let peer = PeerId::repeat_byte(1);
+        let serve = |request| server.serve(&txn, peer, &request).unwrap().unwrap();
+
+        let SnapResponse::AccountRange(range) =
+            serve(SnapRequest::GetAccountRange(GetAccountRangeMessage {
+                request_id: 1,
+                root_hash: state_root,
+                starting_hash: H256::zero(),
+                limit_hash: H256::repeat_byte(0xff),
+                response_bytes: SOFT_RESPONSE_LIMIT,
+            }))
+        else {
+            panic!("unexpected response")
+        };
+        assert_eq!(range.accounts.len(), 3);
+        assert_eq!(
+            range
+                .accounts
+                .iter()
+                .map(|account| account.hash)
+                .collect::<Vec<_>>(),
+            accounts.keys().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            range.accounts[0].account.storage_root,
+            trie_root((1..=3_u64).map(|n| (
+                H256::from_low_u64_be(n),
+                rlp::encode(&zeroless_view(&u256_to_h256(n.as_u256())))
+            )))
+        );
+        assert_eq!(range.accounts[1].account.storage_root, EMPTY_ROOT);
+
+        // Other roots are not served.
+        let SnapResponse::AccountRange(range) =
+            serve(SnapRequest::GetAccountRange(GetAccountRangeMessage {
+                request_id: 2,
+                root_hash: H256::repeat_byte(1),
+                starting_hash: H256::zero(),
+                limit_hash: H256::repeat_byte(0xff),
+                response_bytes: SOFT_RESPONSE_LIMIT,
+            }))
+        else {
+            panic!("unexpected response")
+        };
+        assert!(range.accounts.is_empty());
+
+        let SnapResponse::StorageRanges(ranges) =
+            serve(SnapRequest::GetStorageRanges(GetStorageRangesMessage {
+                request_id: 3,
+                root_hash: state_root,
+                account_hashes: vec![contract],
+                starting_hash: Bytes::copy_from_slice(H256::from_low_u64_be(2).as_bytes()),
+                limit_hash: Bytes::new(),
+                response_bytes: SOFT_RESPONSE_LIMIT,
+            }))
+        else {
+            panic!("unexpected response")
+        };
+        assert_eq!(
+            ranges.slots,
+            vec![[2_u64, 3]
+                .map(|n| StorageData {
+                    hash: H256::from_low_u64_be(n),
+                    data: rlp::encode(&n.as_u256()).freeze(),
+                })
+                .to_vec()]
+        );
+
+        let request = GetByteCodesMessage {
+            request_id: 4,
+            hashes: vec![keccak256([3_u8]), H256::repeat_byte(1), keccak256([1_u8])],
+            response_bytes: SOFT_RESPONSE_LIMIT,
+        };
+        let SnapResponse::ByteCodes(codes) = serve(SnapRequest::GetByteCodes(request.clone()))
+        else {
+            panic!("unexpected response")
+        };
+        assert_eq!(codes.match_request(&request).unwrap().len(), 2);
+    }
+}
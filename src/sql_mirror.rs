@@ -0,0 +1,376 @@
+//! Mirror of executed canonical blocks into an SQL database, SQLite or Postgres, for ad-hoc
+//! querying: the rows of [`export`](crate::export) for the selected tables and columns, kept up
+//! to date from the [`BlockStream`](crate::stagedsync::block_stream::BlockStream).
+//!
+//! The mirror never holds the sync back. It only sees blocks executed while it runs, and skips
+//! blocks when it falls behind the stream. Every mirrored table keeps its block number column,
+//! by which the rows of orphaned blocks are deleted.
+use crate::{
+    export::{block_rows, text_field, BlockRows, Column, ColumnType, ExportSchema, Table, Value},
+    models::*,
+    stagedsync::block_stream::proto,
+};
+use anyhow::{ensure, Context};
+use sqlx::{
+    any::{AnyArguments, AnyPoolOptions},
+    query::Query,
+    Any, AnyPool,
+};
+use std::sync::Arc;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::*;
+
+/// Column of `table` holding the block number.
+fn block_number_column(table: Table) -> &'static str {
+    match table {
+        Table::Blocks => "number",
+        _ => "block_number",
+    }
+}
+
+fn sql_type(column: &Column) -> &'static str {
+    match column.ty {
+        ColumnType::U64 => "BIGINT",
+        ColumnType::Bool => "BOOLEAN",
+        // Hex and decimal text, as in CSV exports.
+        ColumnType::Bytes | ColumnType::U256 => "TEXT",
+    }
+}
+
+fn create_table_sql(table: Table, columns: &[Column]) -> String {
+    format!(
+        r#"CREATE TABLE IF NOT EXISTS "{}" ({})"#,
+        table.name(),
+        columns
+            .iter()
+            .map(|column| format!(
+                r#""{}" {}{}"#,
+                column.name,
+                sql_type(column),
+                if column.nullable { "" } else { " NOT NULL" }
+            ))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+fn create_index_sql(table: Table) -> String {
+    format!(
+        r#"CREATE INDEX IF NOT EXISTS "{table}_{column}" ON "{table}" ("{column}")"#,
+        table = table.name(),
+        column = block_number_column(table)
+    )
+}
+
+fn insert_sql(table: Table, columns: &[Column]) -> String {
+    format!(
+        r#"INSERT INTO "{}" ({}) VALUES ({})"#,
+        table.name(),
+        columns
+            .iter()
+            .map(|column| format!(r#""{}""#, column.name))
+            .collect::<Vec<_>>()
+            .join(", "),
+        (1..=columns.len())
+            .map(|i| format!("${}", i))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+fn delete_sql(table: Table) -> String {
+    format!(
+        r#"DELETE FROM "{}" WHERE "{}" = $1"#,
+        table.name(),
+        block_number_column(table)
+    )
+}
+
+fn bind<'q>(
+    query: Query<'q, Any, AnyArguments<'q>>,
+    column: &Column,
+    value: Value,
+) -> Query<'q, Any, AnyArguments<'q>> {
+    match value {
+        Value::U64(v) => query.bind(v as i64),
+        Value::Bool(v) => query.bind(v),
+        Value::Null => match column.ty {
+            ColumnType::U64 => query.bind(None::<i64>),
+            ColumnType::Bool => query.bind(None::<bool>),
+            ColumnType::Bytes | ColumnType::U256 => query.bind(None::<String>),
+        },
+        value => query.bind(text_field(&value)),
+    }
+}
+
+/// Rows of a block sent to the stream, in [`Table::columns`] order.
+fn streamed_block_rows(block: &proto::Block) -> anyhow::Result<BlockRows> {
+    ensure!(block.hash.len() == 32, "malformed block hash");
+    let hash = H256::from_slice(&block.hash);
+    let header = rlp::decode::<BlockHeader>(&block.header)?;
+    let transactions = block
+        .transactions
+        .iter()
+        .map(|transaction| rlp::decode::<MessageWithSignature>(transaction))
+        .collect::<Result<Vec<_>, _>>()?;
+    let body = BlockBodyWithSenders {
+        transactions: transactions
+            .iter()
+            .map(|transaction| {
+                Ok(MessageWithSender {
+                    message: transaction.message.clone(),
+                    sender: transaction.recover_sender()?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?,
+        ommers: vec![],
+        rollup_cost_data: vec![],
+    };
+    let receipts = block
+        .receipts
+        .iter()
+        .map(|receipt| {
+            Ok(Receipt {
+                tx_type: TxType::try_from(receipt.tx_type as u8)?,
+                success: receipt.success,
+                cumulative_gas_used: receipt.cumulative_gas_used,
+                bloom: Bloom::zero(),
+                logs: receipt
+                    .logs
+                    .iter()
+                    .map(|log| Log {
+                        address: Address::from_slice(&log.address),
+                        topics: log
+                            .topics
+                            .iter()
+                            .map(|topic| H256::from_slice(topic))
+                            .collect(),
+                        data: log.data.clone().into(),
+                    })
+                    .collect(),
+                deposit_nonce: None,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(block_rows(
+        &header,
+        hash,
+        &transactions,
+        &body,
+        Some(&receipts),
+    ))
+}
+
+#[derive(Debug)]
+struct MirroredTable {
+    table: Table,
+    columns: Vec<Column>,
+    insert: String,
+    delete: String,
+}
+
+#[derive(Debug)]
+pub struct SqlMirror {
+    pool: AnyPool,
+    schema: ExportSchema,
+    tables: Vec<MirroredTable>,
+}
+
+impl SqlMirror {
+    /// Connects to the database at `url`, e.g. `sqlite://mirror.db?mode=rwc` or
+    /// `postgres://user@localhost/chain`, and creates the tables of `schema` that do not exist
+    /// yet. Existing tables must have the selected columns.
+    pub async fn connect(url: &str, schema: ExportSchema) -> anyhow::Result<Self> {
+        let mut tables = Vec::new();
+        for table in schema.tables() {
+            let columns = schema.columns(table);
+            let number_column = block_number_column(table);
+            ensure!(
+                columns.iter().any(|column| column.name == number_column),
+                "mirrored table {} must keep column {}",
+                table.name(),
+                number_column
+            );
+            tables.push(MirroredTable {
+                table,
+                insert: insert_sql(table, &columns),
+                delete: delete_sql(table),
+                columns,
+            });
+        }
+
+        // Blocks are written one at a time.
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect(url)
+            .await
+            .with_context(|| format!("failed to connect to SQL mirror at {}", url))?;
+        for table in &tables {
+            sqlx::query(&create_table_sql(table.table, &table.columns))
+                .execute(&pool)
+                .await?;
+            sqlx::query(&create_index_sql(table.table))
+                .execute(&pool)
+                .await?;
+        }
+
+        Ok(Self {
+            pool,
+            schema,
+            tables,
+        })
+    }
+
+    /// Replaces the rows of the block, or deletes them if it was removed from the canonical
+    /// chain, in one database transaction.
+    async fn apply(&self, block: &proto::Block) -> anyhow::Result<()> {
+        let mut rows = if block.removed {
+            None
+        } else {
+            Some(streamed_block_rows(block)?)
+        };
+
+        let mut tx = self.pool.begin().await?;
+        for table in &self.tables {
+            // Rows of the block may be there from before a restart.
+            sqlx::query(&table.delete)
+                .bind(block.number as i64)
+                .execute(&mut tx)
+                .await?;
+
+            if let Some(rows) = &mut rows {
+                for row in rows.take(table.table) {
+                    let row = self.schema.project(table.table, &row);
+                    let mut query = sqlx::query(&table.insert);
+                    for (column, value) in table.columns.iter().zip(row) {
+                        query = bind(query, column, value);
+                    }
+                    query.execute(&mut tx).await?;
+                }
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Mirrors the blocks received from the block stream until it closes.
+    pub async fn run(self, mut receiver: Receiver<Arc<proto::Block>>) -> anyhow::Result<()> {
+        loop {
+            match receiver.recv().await {
+                Ok(block) => {
+                    self.apply(&block)
+                        .await
+                        .with_context(|| format!("failed to mirror block {}", block.number))?;
+                    debug!(
+                        "Mirrored block {}{}",
+                        block.number,
+                        if block.removed { " removal" } else { "" }
+                    );
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("SQL mirror fell behind, {} blocks not mirrored", missed);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements() {
+        let schema = ExportSchema::new(
+            &[Table::Transactions],
+            &[
+                "transactions.block_number".into(),
+                "transactions.to".into(),
+                "transactions.value".into(),
+            ],
+        )
+        .unwrap();
+        let columns = schema.columns(Table::Transactions);
+
+        assert_eq!(
+            create_table_sql(Table::Transactions, &columns),
+            r#"CREATE TABLE IF NOT EXISTS "transactions" ("block_number" BIGINT NOT NULL, "to" TEXT, "value" TEXT NOT NULL)"#
+        );
+        assert_eq!(
+            create_index_sql(Table::Transactions),
+            r#"CREATE INDEX IF NOT EXISTS "transactions_block_number" ON "transactions" ("block_number")"#
+        );
+        assert_eq!(
+            insert_sql(Table::Transactions, &columns),
+            r#"INSERT INTO "transactions" ("block_number", "to", "value") VALUES ($1, $2, $3)"#
+        );
+        assert_eq!(
+            delete_sql(Table::Blocks),
+            r#"DELETE FROM "blocks" WHERE "number" = $1"#
+        );
+    }
+
+    #[tokio::test]
+    async fn mirror_and_remove() {
+        let schema = ExportSchema::new(&[Table::Blocks], &["blocks.hash".into()]).unwrap();
+        SqlMirror::connect("sqlite::memory:", schema)
+            .await
+            .unwrap_err();
+
+        let schema = ExportSchema::new(
+            &[Table::Blocks, Table::Logs],
+            &["blocks.number".into(), "blocks.hash".into()],
+        )
+        .unwrap();
+        let mirror = SqlMirror::connect("sqlite::memory:", schema).await.unwrap();
+
+        let block = |number, removed| {
+            let header = BlockHeader {
+                number: BlockNumber(number),
+                ..BlockHeader::empty()
+            };
+            proto::Block {
+                number,
+                hash: header.hash().as_bytes().to_vec(),
+                removed,
+                header: rlp::encode(&header).to_vec(),
+                ..Default::default()
+            }
+        };
+        let pool = &mirror.pool;
+        let blocks = || async move {
+            sqlx::query_as::<_, (i64, String)>(
+                r#"SELECT "number", "hash" FROM "blocks" ORDER BY "number""#,
+            )
+            .fetch_all(pool)
+            .await
+            .unwrap()
+        };
+
+        for number in [1, 2, 2] {
+            mirror.apply(&block(number, false)).await.unwrap();
+        }
+        let mirrored = blocks().await;
+        assert_eq!(mirrored.len(), 2);
+        assert_eq!(
+            mirrored[1],
+            (
+                2,
+                text_field(&Value::from(H256::from_slice(&block(2, false).hash)))
+            )
+        );
+
+        mirror.apply(&block(2, true)).await.unwrap();
+        assert_eq!(
+            blocks()
+                .await
+                .into_iter()
+                .map(|(number, _)| number)
+                .collect::<Vec<_>>(),
+            [1]
+        );
+    }
+}
@@ -0,0 +1,478 @@
+//! Stream of executed canonical blocks for external indexers: header, transactions, receipts and
+//! state changes of every block, as length-delimited protobuf messages over HTTP. The schema is
+//! [`SCHEMA`], also served at `/schema`.
+//!
+//! Receipts are not stored, so blocks are replayed for them, and only while someone listens.
+use super::stages::EXECUTION;
+use crate::{
+    accessors::{chain, state},
+    h256_to_u256,
+    kv::{mdbx::*, tables},
+    models::*,
+};
+use anyhow::format_err;
+use bytes::Bytes;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use prost::Message;
+use std::{collections::BTreeMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::*;
+
+pub const SCHEMA: &str = include_str!("block_stream.proto");
+
+/// Messages of [`SCHEMA`].
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Block {
+        #[prost(uint64, tag = "1")]
+        pub number: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub hash: Vec<u8>,
+        #[prost(bool, tag = "3")]
+        pub removed: bool,
+        #[prost(bytes = "vec", tag = "4")]
+        pub header: Vec<u8>,
+        #[prost(bytes = "vec", repeated, tag = "5")]
+        pub transactions: Vec<Vec<u8>>,
+        #[prost(message, repeated, tag = "6")]
+        pub receipts: Vec<Receipt>,
+        #[prost(message, repeated, tag = "7")]
+        pub accounts: Vec<AccountDiff>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Receipt {
+        #[prost(uint32, tag = "1")]
+        pub tx_type: u32,
+        #[prost(bool, tag = "2")]
+        pub success: bool,
+        #[prost(uint64, tag = "3")]
+        pub cumulative_gas_used: u64,
+        #[prost(message, repeated, tag = "4")]
+        pub logs: Vec<Log>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Log {
+        #[prost(bytes = "vec", tag = "1")]
+        pub address: Vec<u8>,
+        #[prost(bytes = "vec", repeated, tag = "2")]
+        pub topics: Vec<Vec<u8>>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub data: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AccountDiff {
+        #[prost(bytes = "vec", tag = "1")]
+        pub address: Vec<u8>,
+        #[prost(message, optional, tag = "2")]
+        pub before: Option<Account>,
+        #[prost(message, optional, tag = "3")]
+        pub after: Option<Account>,
+        #[prost(message, repeated, tag = "4")]
+        pub storage: Vec<StorageDiff>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Account {
+        #[prost(uint64, tag = "1")]
+        pub nonce: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub balance: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub code_hash: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StorageDiff {
+        #[prost(bytes = "vec", tag = "1")]
+        pub location: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub before: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub after: Vec<u8>,
+    }
+}
+
+impl From<Account> for proto::Account {
+    fn from(account: Account) -> Self {
+        Self {
+            nonce: account.nonce,
+            balance: account.balance.to_be_bytes().to_vec(),
+            code_hash: account.code_hash.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl From<Log> for proto::Log {
+    fn from(log: Log) -> Self {
+        Self {
+            address: log.address.as_bytes().to_vec(),
+            topics: log
+                .topics
+                .into_iter()
+                .map(|topic| topic.as_bytes().to_vec())
+                .collect(),
+            data: log.data.to_vec(),
+        }
+    }
+}
+
+impl From<Receipt> for proto::Receipt {
+    fn from(receipt: Receipt) -> Self {
+        Self {
+            tx_type: receipt.tx_type as u32,
+            success: receipt.success,
+            cumulative_gas_used: receipt.cumulative_gas_used,
+            logs: receipt.logs.into_iter().map(From::from).collect(),
+        }
+    }
+}
+
+/// Fans out executed blocks to stream consumers.
+///
+/// When the chain is unwound, consumers receive the orphaned blocks, marked removed, before any
+/// block of the new canonical chain.
+#[derive(Clone, Debug)]
+pub struct BlockStream {
+    sender: broadcast::Sender<Arc<proto::Block>>,
+}
+
+impl BlockStream {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<proto::Block>> {
+        self.sender.subscribe()
+    }
+
+    /// Blocks are only read while someone listens.
+    pub(crate) fn has_consumers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub(crate) fn send(&self, blocks: impl IntoIterator<Item = proto::Block>) {
+        for block in blocks {
+            // No consumers is not an error.
+            let _ = self.sender.send(Arc::new(block));
+        }
+    }
+}
+
+/// Blocks `from..=to` about to leave the canonical chain, newest first.
+pub(crate) fn read_removed_blocks<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> anyhow::Result<Vec<proto::Block>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut blocks = Vec::new();
+    for number in (from.0..=to.0).rev() {
+        let hash = chain::canonical_hash::read(tx, number)?
+            .ok_or_else(|| format_err!("no canonical hash for block {}", number))?;
+        blocks.push(proto::Block {
+            number,
+            hash: hash.as_bytes().to_vec(),
+            removed: true,
+            ..Default::default()
+        });
+    }
+
+    Ok(blocks)
+}
+
+/// Sends executed canonical blocks `from..=to` to `stream`, in chain order.
+pub(crate) fn send_blocks<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    from: BlockNumber,
+    to: BlockNumber,
+    stream: &BlockStream,
+) -> anyhow::Result<()>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    if from > to {
+        return Ok(());
+    }
+
+    let mut replayer = chain::receipts::Replayer::new(tx)?;
+
+    for number in from.0..=to.0 {
+        let number = BlockNumber(number);
+        let hash = chain::canonical_hash::read(tx, number)?
+            .ok_or_else(|| format_err!("no canonical hash for block {}", number))?;
+        let key = BlockKey::new(number, hash);
+        let header = chain::header::read(tx, key)?
+            .ok_or_else(|| format_err!("no header for block {}", key))?;
+        let transactions = chain::block_body::read_without_senders(tx, key)?
+            .ok_or_else(|| format_err!("no body for block {}", key))?
+            .transactions;
+        let receipts = replayer
+            .read(tx, key)?
+            .ok_or_else(|| format_err!("no receipts for block {}", key))?;
+
+        stream.send(Some(proto::Block {
+            number: number.0,
+            hash: hash.as_bytes().to_vec(),
+            removed: false,
+            header: rlp::encode(&header).to_vec(),
+            transactions: transactions
+                .iter()
+                .map(|transaction| rlp::encode(transaction).to_vec())
+                .collect(),
+            receipts: receipts.into_iter().map(From::from).collect(),
+            accounts: read_state_diff(tx, number)?,
+        }));
+    }
+
+    Ok(())
+}
+
+/// Accounts and storage changed by executed block `number`, in address order.
+fn read_state_diff<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    number: BlockNumber,
+) -> anyhow::Result<Vec<proto::AccountDiff>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut accounts = BTreeMap::new();
+
+    for entry in tx.cursor(tables::AccountChangeSet)?.walk_dup(number) {
+        let tables::AccountChange { address, account } = entry?;
+        accounts.insert(
+            address,
+            proto::AccountDiff {
+                address: address.as_bytes().to_vec(),
+                before: account.map(From::from),
+                after: state::account::read(tx, address, Some(number))?.map(From::from),
+                storage: vec![],
+            },
+        );
+    }
+
+    for entry in tx.cursor(tables::StorageChangeSet)?.walk(Some(number)) {
+        let (
+            tables::StorageChangeKey {
+                block_number,
+                address,
+            },
+            tables::StorageChange { location, value },
+        ) = entry?;
+        if block_number != number {
+            break;
+        }
+
+        let after = state::storage::read(tx, address, h256_to_u256(location), Some(number))?;
+        accounts
+            .entry(address)
+            .or_insert_with(|| {
+                // Storage changes without an account change, e.g. under EIP-2200 gas refunds.
+                let account = state::account::read(tx, address, Some(number))
+                    .ok()
+                    .flatten()
+                    .map(proto::Account::from);
+                proto::AccountDiff {
+                    address: address.as_bytes().to_vec(),
+                    before: account.clone(),
+                    after: account,
+                    storage: vec![],
+                }
+            })
+            .storage
+            .push(proto::StorageDiff {
+                location: location.as_bytes().to_vec(),
+                before: value.to_be_bytes().to_vec(),
+                after: after.to_be_bytes().to_vec(),
+            });
+    }
+
+    Ok(accounts.into_values().collect())
+}
+
+/// Height up to which blocks have been streamed, i.e. the execution progress.
+pub(crate) fn streamed_head<K, E>(tx: &MdbxTransaction<'_, K, E>) -> anyhow::Result<BlockNumber>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    Ok(EXECUTION.get_progress(tx)?.unwrap_or(BlockNumber(0)))
+}
+
+async fn handle(request: Request<Body>, stream: BlockStream) -> Response<Body> {
+    match request.uri().path() {
+        "/blocks" => {
+            let mut receiver = stream.subscribe();
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(block) => {
+                            let data = Bytes::from(block.encode_length_delimited_to_vec());
+                            if sender.send_data(data).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(RecvError::Lagged(missed)) => {
+                            // Cut the response rather than silently skipping blocks.
+                            warn!(
+                                "Block stream consumer missed {} blocks, disconnecting",
+                                missed
+                            );
+                            sender.abort();
+                            break;
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+
+            Response::builder()
+                .header(CONTENT_TYPE, "application/x-protobuf")
+                .body(body)
+                .unwrap()
+        }
+        "/schema" => Response::new(Body::from(SCHEMA)),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+/// Serves `stream` on `listen_address`.
+pub async fn serve(listen_address: SocketAddr, stream: BlockStream) -> anyhow::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let stream = stream.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let stream = stream.clone();
+                async move { Ok::<_, Infallible>(handle(request, stream).await) }
+            }))
+        }
+    });
+
+    info!("Streaming blocks on http://{}/blocks", listen_address);
+    Server::try_bind(&listen_address)?
+        .serve(make_service)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::new_mem_database;
+
+    #[test]
+    fn state_diff() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let sender = Address::repeat_byte(0xaa);
+        let contract = Address::repeat_byte(0xbb);
+        let account = |nonce| Account {
+            nonce,
+            balance: U256::from(100_u64 - nonce),
+            code_hash: EMPTY_HASH,
+        };
+
+        // Block 1 creates the sender, block 2 bumps its nonce and writes a slot of the
+        // contract, which block 3 clears again.
+        tx.set(
+            tables::AccountChangeSet,
+            BlockNumber(1),
+            tables::AccountChange {
+                address: sender,
+                account: None,
+            },
+        )
+        .unwrap();
+        tx.set(
+            tables::AccountChangeSet,
+            BlockNumber(2),
+            tables::AccountChange {
+                address: sender,
+                account: Some(account(0)),
+            },
+        )
+        .unwrap();
+        for (block_number, value) in [(2, 0_u64), (3, 7)] {
+            tx.set(
+                tables::StorageChangeSet,
+                tables::StorageChangeKey {
+                    block_number: BlockNumber(block_number),
+                    address: contract,
+                },
+                tables::StorageChange {
+                    location: H256::from_low_u64_be(1),
+                    value: value.into(),
+                },
+            )
+            .unwrap();
+        }
+        tx.set(tables::Account, sender, account(1)).unwrap();
+        // No history index, changesets are probed.
+        EXECUTION.save_progress(&tx, BlockNumber(3)).unwrap();
+
+        let diff = read_state_diff(&tx, BlockNumber(2)).unwrap();
+        assert_eq!(diff.len(), 2);
+        assert_eq!(diff[0].address, sender.as_bytes());
+        assert_eq!(diff[0].before, Some(account(0).into()));
+        assert_eq!(diff[0].after, Some(account(1).into()));
+        assert_eq!(diff[1].address, contract.as_bytes());
+        assert_eq!(diff[1].before, None);
+        assert_eq!(
+            diff[1].storage,
+            vec![proto::StorageDiff {
+                location: H256::from_low_u64_be(1).as_bytes().to_vec(),
+                before: U256::ZERO.to_be_bytes().to_vec(),
+                after: U256::from(7_u64).to_be_bytes().to_vec(),
+            }]
+        );
+
+        let diff = read_state_diff(&tx, BlockNumber(1)).unwrap();
+        assert_eq!(diff[0].before, None);
+        assert_eq!(diff[0].after, Some(account(0).into()));
+
+        let removed = read_removed_blocks(&tx, BlockNumber(1), BlockNumber(0));
+        assert!(removed.unwrap().is_empty());
+    }
+
+    #[test]
+    fn length_delimited() {
+        let block = proto::Block {
+            number: 5,
+            hash: vec![0x55; 32],
+            removed: true,
+            ..Default::default()
+        };
+        let mut data = block.encode_length_delimited_to_vec();
+        data.extend_from_slice(&block.encode_length_delimited_to_vec());
+
+        let mut data = &data[..];
+        assert_eq!(
+            proto::Block::decode_length_delimited(&mut data).unwrap(),
+            block
+        );
+        assert_eq!(
+            proto::Block::decode_length_delimited(&mut data).unwrap(),
+            block
+        );
+        assert!(data.is_empty());
+    }
+}
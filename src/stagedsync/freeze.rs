@@ -0,0 +1,143 @@
+//! Maintenance freeze of the database: the node commits what it has and stops writing, while
+//! readers such as the RPC daemon keep serving, so that operators can snapshot the files or check
+//! them.
+//!
+//! Requests and acknowledgements are files in the data directory, so that any process with
+//! access to it can freeze the node.
+use anyhow::Context;
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::*;
+
+const REQUEST_FILE: &str = "FREEZE";
+const ACK_FILE: &str = "FROZEN";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FreezeStatus {
+    Running,
+    /// Requested, but the node has not finished its write transaction yet.
+    Freezing,
+    /// The node is not writing to the database.
+    Frozen,
+}
+
+#[derive(Clone, Debug)]
+pub struct DbFreeze {
+    dir: PathBuf,
+}
+
+impl DbFreeze {
+    /// Freeze of the database in data directory `dir`.
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn request_path(&self) -> PathBuf {
+        self.dir.join(REQUEST_FILE)
+    }
+
+    fn ack_path(&self) -> PathBuf {
+        self.dir.join(ACK_FILE)
+    }
+
+    /// Asks the node to stop writing. It does at its next commit, see [`Self::status`].
+    pub fn request(&self) -> anyhow::Result<()> {
+        std::fs::write(self.request_path(), b"")
+            .with_context(|| format!("failed to request freeze in {}", self.dir.display()))
+    }
+
+    /// Lets the node write again.
+    pub fn release(&self) -> anyhow::Result<()> {
+        match std::fs::remove_file(self.request_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)
+                .with_context(|| format!("failed to release freeze in {}", self.dir.display())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.request_path().exists()
+    }
+
+    pub fn status(&self) -> FreezeStatus {
+        match (self.is_requested(), self.ack_path().exists()) {
+            (false, _) => FreezeStatus::Running,
+            (true, false) => FreezeStatus::Freezing,
+            (true, true) => FreezeStatus::Frozen,
+        }
+    }
+
+    /// Called by the writer between transactions: returns at once unless a freeze is requested,
+    /// in which case it acknowledges it and waits for the release.
+    pub async fn wait_while_frozen(&self) -> anyhow::Result<()> {
+        if !self.is_requested() {
+            return Ok(());
+        }
+
+        std::fs::write(self.ack_path(), std::process::id().to_string())
+            .with_context(|| format!("failed to acknowledge freeze in {}", self.dir.display()))?;
+        info!("Database frozen, waiting for release");
+
+        while self.is_requested() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+
+        std::fs::remove_file(self.ack_path())?;
+        info!("Database released");
+
+        Ok(())
+    }
+
+    /// Drops the acknowledgement of a writer that died while frozen.
+    pub fn clear_stale_ack(&self) -> anyhow::Result<()> {
+        if self.ack_path().exists() {
+            std::fs::remove_file(self.ack_path())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn freeze() {
+        let dir = tempfile::tempdir().unwrap();
+        let freeze = DbFreeze::new(dir.path());
+
+        assert_eq!(freeze.status(), FreezeStatus::Running);
+        freeze.wait_while_frozen().await.unwrap();
+
+        freeze.request().unwrap();
+        assert_eq!(freeze.status(), FreezeStatus::Freezing);
+
+        let mut writer = tokio::spawn({
+            let freeze = freeze.clone();
+            async move { freeze.wait_while_frozen().await }
+        });
+        while freeze.status() != FreezeStatus::Frozen {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut writer)
+            .await
+            .is_err());
+
+        freeze.release().unwrap();
+        writer.await.unwrap().unwrap();
+        assert_eq!(freeze.status(), FreezeStatus::Running);
+        assert!(!dir.path().join(ACK_FILE).exists());
+
+        // Releasing twice is fine.
+        freeze.release().unwrap();
+    }
+}
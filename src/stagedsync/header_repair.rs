@@ -0,0 +1,287 @@
+//! Repair of canonical blocks missing their header or total difficulty, as left by interrupted
+//! header saves, before later stages fail on them.
+//!
+//! The canonical chain is checked a batch of blocks per staged sync cycle, from genesis up to the
+//! head and then as the chain grows. Missing total difficulties are recomputed from the parent's.
+//! Missing headers cannot be rebuilt locally, so the sync is unwound to below them for the header
+//! stage to download them again.
+use crate::{
+    accessors::chain,
+    kv::{mdbx::*, tables},
+    models::*,
+};
+use anyhow::format_err;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, IntCounter};
+use tracing::*;
+
+static REPAIRED_TOTAL_DIFFICULTIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "stagedsync_repaired_total_difficulties_total",
+        "Total difficulties of canonical blocks recomputed for being missing"
+    )
+    .unwrap()
+});
+
+static MISSING_HEADERS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "stagedsync_missing_headers_total",
+        "Canonical blocks found without a header and unwound to be downloaded again"
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Missing {
+    Header,
+    TotalDifficulty,
+}
+
+/// Canonical blocks `from..=to`, all missing the same data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gap {
+    pub missing: Missing,
+    pub from: BlockNumber,
+    pub to: BlockNumber,
+}
+
+/// Gaps in the headers and total difficulties of canonical blocks `from..=to`, in chain order.
+/// A block without a header is reported as such, whether it has a total difficulty or not.
+pub fn find_gaps<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> anyhow::Result<Vec<Gap>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut gaps = Vec::<Gap>::new();
+    for entry in tx.cursor(tables::CanonicalHeader)?.walk(Some(from)) {
+        let (number, hash) = entry?;
+        if number > to {
+            break;
+        }
+
+        let key = BlockKey::new(number, hash);
+        let missing = if chain::header::read(tx, key)?.is_none() {
+            Missing::Header
+        } else if chain::td::read(tx, key)?.is_none() {
+            Missing::TotalDifficulty
+        } else {
+            continue;
+        };
+
+        match gaps.last_mut() {
+            Some(gap) if gap.missing == missing && gap.to + 1 == number => gap.to = number,
+            _ => gaps.push(Gap {
+                missing,
+                from: number,
+                to: number,
+            }),
+        }
+    }
+
+    Ok(gaps)
+}
+
+/// Write the total difficulties of canonical blocks `from..=to`, which must have headers, on top
+/// of the parent's.
+pub fn recompute_total_difficulty<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RW, E>,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> anyhow::Result<()> {
+    let mut total_difficulty = match from.0.checked_sub(1) {
+        Some(parent) => {
+            let parent = BlockNumber(parent);
+            let hash = chain::canonical_hash::read(tx, parent)?
+                .ok_or_else(|| format_err!("no canonical hash for block {}", parent))?;
+            chain::td::read(tx, BlockKey::new(parent, hash))?
+                .ok_or_else(|| format_err!("no total difficulty for block {}", parent))?
+        }
+        None => U256::ZERO,
+    };
+
+    for number in from..=to {
+        let hash = chain::canonical_hash::read(tx, number)?
+            .ok_or_else(|| format_err!("no canonical hash for block {}", number))?;
+        let key = BlockKey::new(number, hash);
+        let header = chain::header::read(tx, key)?
+            .ok_or_else(|| format_err!("no header for block {}", key))?;
+
+        total_difficulty += header.difficulty;
+        chain::td::write(tx, key, total_difficulty)?;
+    }
+
+    Ok(())
+}
+
+/// Progress of the repair through the canonical chain.
+#[derive(Debug)]
+pub struct HeaderRepair {
+    next: BlockNumber,
+    batch: u64,
+}
+
+impl HeaderRepair {
+    /// Repair checking `batch` blocks per call to [`Self::run`].
+    pub fn new(batch: u64) -> Self {
+        Self {
+            next: BlockNumber(0),
+            batch: batch.max(1),
+        }
+    }
+
+    /// Check the next batch of canonical blocks and recompute their missing total difficulties.
+    /// Returns the block to unwind to if some have no header.
+    pub fn run<E: EnvironmentKind>(
+        &mut self,
+        tx: &MdbxTransaction<'_, RW, E>,
+    ) -> anyhow::Result<Option<BlockNumber>> {
+        let head = match chain::canonical_hash::last(tx)? {
+            Some((head, _)) => head,
+            None => return Ok(None),
+        };
+
+        // Blocks above the head were unwound, and are checked again once saved.
+        self.next = std::cmp::min(self.next, head + 1);
+        let from = self.next;
+        if from > head {
+            return Ok(None);
+        }
+        let to = std::cmp::min(head, from + (self.batch - 1));
+        self.next = to + 1;
+
+        for gap in find_gaps(tx, from, to)? {
+            match gap.missing {
+                Missing::TotalDifficulty => {
+                    recompute_total_difficulty(tx, gap.from, gap.to)?;
+                    REPAIRED_TOTAL_DIFFICULTIES.inc_by(gap.to.0 - gap.from.0 + 1);
+                    info!(
+                        "Recomputed missing total difficulty of blocks {} to {}",
+                        gap.from, gap.to
+                    );
+                }
+                Missing::Header => {
+                    let unwind_to = gap.from.0.checked_sub(1).ok_or_else(|| {
+                        format_err!("genesis header missing, the database must be recreated")
+                    })?;
+                    MISSING_HEADERS.inc_by(gap.to.0 - gap.from.0 + 1);
+                    warn!(
+                        "Blocks {} to {} have no header, unwinding to download them again",
+                        gap.from, gap.to
+                    );
+
+                    // Unwinding lowers the head, so that the gap is checked again once downloaded.
+                    self.next = gap.to + 1;
+                    return Ok(Some(BlockNumber(unwind_to)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::new_mem_database;
+
+    #[test]
+    fn repair_gaps() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let mut total_difficulty = U256::ZERO;
+        for number in 0..6_u64 {
+            let header = BlockHeader {
+                number: BlockNumber(number),
+                difficulty: U256::from(number + 1),
+                ..BlockHeader::empty()
+            };
+            let key = BlockKey::new(number, header.hash());
+            total_difficulty += header.difficulty;
+            tx.set(tables::CanonicalHeader, key.number, key.hash)
+                .unwrap();
+            tx.set(tables::Header, (key.number, key.hash), header)
+                .unwrap();
+            chain::td::write(&tx, key, total_difficulty).unwrap();
+        }
+        let key = |number: u64| {
+            BlockKey::new(
+                number,
+                chain::canonical_hash::read(&tx, number).unwrap().unwrap(),
+            )
+        };
+        for number in [1, 2] {
+            tx.del(
+                tables::HeadersTotalDifficulty,
+                (BlockNumber(number), key(number).hash),
+                None,
+            )
+            .unwrap();
+        }
+        tx.del(tables::Header, (BlockNumber(4), key(4).hash), None)
+            .unwrap();
+
+        assert_eq!(
+            find_gaps(&tx, BlockNumber(0), BlockNumber(5)).unwrap(),
+            [
+                Gap {
+                    missing: Missing::TotalDifficulty,
+                    from: BlockNumber(1),
+                    to: BlockNumber(2)
+                },
+                Gap {
+                    missing: Missing::Header,
+                    from: BlockNumber(4),
+                    to: BlockNumber(4)
+                }
+            ]
+        );
+
+        let mut repair = HeaderRepair::new(3);
+        assert_eq!(repair.run(&tx).unwrap(), None);
+        assert_eq!(
+            chain::td::read(&tx, key(2)).unwrap(),
+            Some(U256::from(1 + 2 + 3_u64))
+        );
+        assert_eq!(repair.run(&tx).unwrap(), Some(BlockNumber(3)));
+
+        // Unwound, then saved again by the header stage, with block 5 missing its total
+        // difficulty this time.
+        let (key4, key5) = (key(4), key(5));
+        for number in [4, 5] {
+            tx.del(tables::CanonicalHeader, BlockNumber(number), None)
+                .unwrap();
+        }
+        assert_eq!(repair.run(&tx).unwrap(), None);
+        let header = BlockHeader {
+            number: BlockNumber(4),
+            difficulty: U256::from(5_u64),
+            ..BlockHeader::empty()
+        };
+        tx.set(tables::Header, (key4.number, key4.hash), header)
+            .unwrap();
+        tx.del(
+            tables::HeadersTotalDifficulty,
+            (key5.number, key5.hash),
+            None,
+        )
+        .unwrap();
+        for key in [key4, key5] {
+            tx.set(tables::CanonicalHeader, key.number, key.hash)
+                .unwrap();
+        }
+        assert_eq!(repair.run(&tx).unwrap(), None);
+        assert_eq!(
+            chain::td::read(&tx, key5).unwrap(),
+            Some(U256::from(21_u64))
+        );
+        assert!(find_gaps(&tx, BlockNumber(0), BlockNumber(5))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use super::stages::EXECUTION;
+use crate::{
+    accessors::chain,
+    kv::{mdbx::*, tables},
+    models::*,
+};
+use anyhow::format_err;
+use tokio::sync::broadcast;
+
+/// Log of a canonical block, or of a block that left the canonical chain if `removed` is set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEvent {
+    pub block_number: BlockNumber,
+    pub block_hash: H256,
+    pub transaction_index: u64,
+    /// Position of the log within the block.
+    pub log_index: u64,
+    pub log: Log,
+    pub removed: bool,
+}
+
+/// Fans out logs of executed blocks to subscribers.
+///
+/// When the chain is unwound, subscribers receive the logs of every orphaned block with `removed`
+/// set before any log of the new canonical chain.
+#[derive(Clone, Debug)]
+pub struct LogSubscriptions {
+    sender: broadcast::Sender<LogEvent>,
+}
+
+impl LogSubscriptions {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogEvent> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn send(&self, events: Vec<LogEvent>) {
+        for event in events {
+            // No subscribers is not an error.
+            let _ = self.sender.send(event);
+        }
+    }
+}
+
+/// Height up to which logs have been announced, i.e. the execution progress.
+pub(crate) fn announced_head<K, E>(tx: &MdbxTransaction<'_, K, E>) -> anyhow::Result<BlockNumber>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    Ok(EXECUTION.get_progress(tx)?.unwrap_or(BlockNumber(0)))
+}
+
+/// Logs of canonical blocks `from..=to`, in chain order.
+pub(crate) fn read_log_events<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    from: BlockNumber,
+    to: BlockNumber,
+    removed: bool,
+) -> anyhow::Result<Vec<LogEvent>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut events = Vec::new();
+    if from > to {
+        return Ok(events);
+    }
+
+    let mut cursor = tx.cursor(tables::Log)?;
+    let mut block_hash = None;
+    let mut log_index = 0;
+    let mut entry = cursor.seek((from, TxIndex(0)))?;
+    while let Some(((block_number, transaction_index), logs)) = entry {
+        if block_number > to {
+            break;
+        }
+
+        let hash = match block_hash {
+            Some((number, hash)) if number == block_number => hash,
+            _ => {
+                let hash = chain::block_id::resolve(tx, block_number)?
+                    .ok_or_else(|| format_err!("no canonical hash for block {}", block_number))?
+                    .hash;
+                block_hash = Some((block_number, hash));
+                log_index = 0;
+                hash
+            }
+        };
+
+        for log in logs {
+            events.push(LogEvent {
+                block_number,
+                block_hash: hash,
+                transaction_index: transaction_index.0,
+                log_index,
+                log,
+                removed,
+            });
+            log_index += 1;
+        }
+
+        entry = cursor.next()?;
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::new_mem_database;
+
+    #[tokio::test]
+    async fn removed_logs_are_announced_first() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let log = |address: u64| Log {
+            address: Address::from_low_u64_be(address),
+            topics: vec![],
+            data: Default::default(),
+        };
+        for (block_number, hash) in [(1, 0x01), (2, 0x02)] {
+            tx.set(
+                tables::CanonicalHeader,
+                BlockNumber(block_number),
+                H256::from_low_u64_be(hash),
+            )
+            .unwrap();
+        }
+        tx.set(
+            tables::Log,
+            (BlockNumber(1), TxIndex(0)),
+            vec![log(1), log(2)],
+        )
+        .unwrap();
+        tx.set(tables::Log, (BlockNumber(2), TxIndex(1)), vec![log(3)])
+            .unwrap();
+
+        let subscriptions = LogSubscriptions::new(16);
+        let mut receiver = subscriptions.subscribe();
+
+        let removed = read_log_events(&tx, BlockNumber(2), BlockNumber(2), true).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].block_hash, H256::from_low_u64_be(0x02));
+        assert_eq!(removed[0].transaction_index, 1);
+        subscriptions.send(removed);
+
+        tx.set(
+            tables::CanonicalHeader,
+            BlockNumber(2),
+            H256::from_low_u64_be(0x12),
+        )
+        .unwrap();
+        subscriptions.send(read_log_events(&tx, BlockNumber(2), BlockNumber(2), false).unwrap());
+
+        let first = receiver.recv().await.unwrap();
+        assert!(first.removed);
+        assert_eq!(first.block_hash, H256::from_low_u64_be(0x02));
+        let second = receiver.recv().await.unwrap();
+        assert!(!second.removed);
+        assert_eq!(second.block_hash, H256::from_low_u64_be(0x12));
+
+        let all = read_log_events(&tx, BlockNumber(1), BlockNumber(2), false).unwrap();
+        assert_eq!(
+            all.iter().map(|e| e.log_index).collect::<Vec<_>>(),
+            vec![0, 1, 0]
+        );
+    }
+}
@@ -1,7 +1,20 @@
+pub mod block_stream;
+pub mod freeze;
+pub mod header_repair;
+pub mod log_subscriptions;
+pub mod reorg;
+pub mod single_stage;
 pub mod stage;
 pub mod stages;
 
-use self::stage::{Stage, StageInput, UnwindInput};
+use self::{
+    block_stream::{read_removed_blocks, send_blocks, streamed_head, BlockStream},
+    freeze::DbFreeze,
+    header_repair::HeaderRepair,
+    log_subscriptions::{announced_head, read_log_events, LogSubscriptions},
+    reorg::check_unwind,
+    stage::{Stage, StageInput, UnwindInput},
+};
 use crate::{kv::mdbx::MdbxEnvironment, models::BlockNumber, stagedsync::stage::*};
 use mdbx::EnvironmentKind;
 use std::time::{Duration, Instant};
@@ -26,8 +39,13 @@ where
     stages: Vec<Box<dyn Stage<'db, E>>>,
     min_progress_to_commit_after_stage: u64,
     max_block: Option<BlockNumber>,
+    max_unwind_depth: Option<u64>,
     exit_after_sync: bool,
     delay_after_sync: Option<Duration>,
+    log_subscriptions: Option<LogSubscriptions>,
+    block_stream: Option<BlockStream>,
+    freeze: Option<DbFreeze>,
+    header_repair: Option<HeaderRepair>,
 }
 
 impl<'db, E> Default for StagedSync<'db, E>
@@ -48,8 +66,13 @@ where
             stages: Vec::new(),
             min_progress_to_commit_after_stage: 0,
             max_block: None,
+            max_unwind_depth: None,
             exit_after_sync: false,
             delay_after_sync: None,
+            log_subscriptions: None,
+            block_stream: None,
+            freeze: None,
+            header_repair: None,
         }
     }
 
@@ -70,6 +93,12 @@ where
         self
     }
 
+    /// Refuse unwinds of more than `v` blocks, see [`reorg::check_unwind`].
+    pub fn set_max_unwind_depth(&mut self, v: Option<u64>) -> &mut Self {
+        self.max_unwind_depth = v;
+        self
+    }
+
     pub fn set_exit_after_sync(&mut self, v: bool) -> &mut Self {
         self.exit_after_sync = v;
         self
@@ -80,19 +109,95 @@ where
         self
     }
 
+    pub fn set_log_subscriptions(&mut self, v: LogSubscriptions) -> &mut Self {
+        self.log_subscriptions = Some(v);
+        self
+    }
+
+    pub fn set_block_stream(&mut self, v: BlockStream) -> &mut Self {
+        self.block_stream = Some(v);
+        self
+    }
+
+    /// Stop writing between transactions while `v` is requested.
+    pub fn set_freeze(&mut self, v: DbFreeze) -> &mut Self {
+        self.freeze = Some(v);
+        self
+    }
+
+    /// Check the canonical chain for missing headers and total difficulties before each cycle,
+    /// see [`header_repair`].
+    pub fn set_header_repair(&mut self, v: HeaderRepair) -> &mut Self {
+        self.header_repair = Some(v);
+        self
+    }
+
     /// Run staged sync loop.
     /// Invokes each loaded stage, and does unwinds if necessary.
     ///
     /// NOTE: it should never return, except if the loop or any stage fails with error.
     pub async fn run(&mut self, db: &'db MdbxEnvironment<E>) -> anyhow::Result<()> {
         let num_stages = self.stages.len();
+        let stage_ids = self
+            .stages
+            .iter()
+            .map(|stage| stage.id())
+            .collect::<Vec<_>>();
 
         let mut unwind_to = None;
+        // Logs up to this block have been sent to subscribers.
+        let mut announced = None;
+        // Blocks up to this one have been sent to the block stream.
+        let mut streamed = None;
         'run_loop: loop {
+            if let Some(freeze) = &self.freeze {
+                freeze.wait_while_frozen().await?;
+            }
             let mut tx = db.begin_mutable()?;
 
+            if self.log_subscriptions.is_some() && announced.is_none() {
+                announced = Some(announced_head(&tx)?);
+            }
+            if self.block_stream.is_some() && streamed.is_none() {
+                streamed = Some(streamed_head(&tx)?);
+            }
+
+            if let (Some(header_repair), None) = (&mut self.header_repair, unwind_to) {
+                if let Some(to) = header_repair.run(&tx)? {
+                    let mut head = BlockNumber(0);
+                    for stage_id in &stage_ids {
+                        head = std::cmp::max(head, stage_id.get_progress(&tx)?.unwrap_or_default());
+                    }
+                    // The node got this far without the headers, so keep going if refused.
+                    match check_unwind(&tx, head, to, self.max_unwind_depth) {
+                        Ok(()) => unwind_to = Some(to),
+                        Err(e) => error!("Cannot download missing headers again: {}", e),
+                    }
+                }
+            }
+
             // Start with unwinding if it's been requested.
             if let Some(to) = unwind_to.take() {
+                // Orphaned logs have to be read before the stages delete them.
+                let removed_logs = match announced {
+                    Some(head) if head > to => {
+                        announced = Some(to);
+                        read_log_events(&tx, to + 1, head, true)?
+                    }
+                    _ => vec![],
+                };
+                let removed_blocks = match (&self.block_stream, streamed) {
+                    (Some(block_stream), Some(head)) if head > to => {
+                        streamed = Some(to);
+                        if block_stream.has_consumers() {
+                            read_removed_blocks(&tx, to + 1, head)?
+                        } else {
+                            vec![]
+                        }
+                    }
+                    _ => vec![],
+                };
+
                 // Unwind stages in reverse order.
                 for (stage_index, stage) in self.stages.iter_mut().enumerate().rev() {
                     let stage_id = stage.id();
@@ -135,6 +240,7 @@ where
                     .instrument(span!(
                         Level::INFO,
                         "",
+                        otel.name = %format!("unwind {}", stage_id),
                         " Unwinding {}/{} {} ",
                         stage_index + 1,
                         num_stages,
@@ -146,6 +252,13 @@ where
                 }
 
                 tx.commit()?;
+
+                if let Some(log_subscriptions) = &self.log_subscriptions {
+                    log_subscriptions.send(removed_logs);
+                }
+                if let Some(block_stream) = &self.block_stream {
+                    block_stream.send(removed_blocks);
+                }
             } else {
                 // Now that we're done with unwind, let's roll.
 
@@ -241,6 +354,7 @@ where
                         .instrument(span!(
                             Level::INFO,
                             "",
+                            otel.name = %format!("stage {}", stage_id),
                             " {}/{} {} ",
                             stage_index + 1,
                             num_stages,
@@ -271,6 +385,9 @@ where
                                     debug!("Commit requested");
                                     tx.commit()?;
                                     debug!("Commit complete");
+                                    if let Some(freeze) = &self.freeze {
+                                        freeze.wait_while_frozen().await?;
+                                    }
                                     tx = db.begin_mutable()?;
                                 }
 
@@ -284,6 +401,19 @@ where
                             }
                             stage::ExecOutput::Unwind { unwind_to: to } => {
                                 // Stage has asked us to unwind.
+                                // Refuse if it is too deep, rather than unwinding for days.
+                                let mut head = BlockNumber(0);
+                                for stage_id in &stage_ids {
+                                    head = std::cmp::max(
+                                        head,
+                                        stage_id.get_progress(&tx)?.unwrap_or_default(),
+                                    );
+                                }
+                                if let Err(e) = check_unwind(&tx, head, to, self.max_unwind_depth) {
+                                    error!("{}", e);
+                                    return Err(e);
+                                }
+
                                 // Set unwind point and restart the whole staged sync loop.
                                 // Current DB transaction will be aborted.
                                 unwind_to = Some(to);
@@ -297,6 +427,26 @@ where
                 }
                 tx.commit()?;
 
+                if let (Some(log_subscriptions), Some(from)) = (&self.log_subscriptions, announced)
+                {
+                    let tx = db.begin()?;
+                    let head = announced_head(&tx)?;
+                    log_subscriptions.send(read_log_events(&tx, from + 1, head, false)?);
+                    announced = Some(head);
+                }
+
+                if let (Some(block_stream), Some(from)) = (&self.block_stream, streamed) {
+                    let tx = db.begin()?;
+                    let head = streamed_head(&tx)?;
+                    if block_stream.has_consumers() {
+                        // Consumers must not stop the sync.
+                        if let Err(e) = send_blocks(&tx, from + 1, head, block_stream) {
+                            warn!("Failed to stream blocks {} to {}: {}", from + 1, head, e);
+                        }
+                    }
+                    streamed = Some(head);
+                }
+
                 let t = timings
                     .into_iter()
                     .fold(String::new(), |acc, (stage_id, time)| {
@@ -0,0 +1,161 @@
+//! Safety checks on unwinds requested by stages, so that a bogus or hostile fork makes the node
+//! stop with an error instead of unwinding days of blocks or data that is gone.
+use crate::{
+    accessors::{
+        chain::{header_number, last_forkchoice},
+        prune::{self, DataPruned, PruneTarget},
+    },
+    kv::mdbx::MdbxTransaction,
+    models::BlockNumber,
+};
+use mdbx::{EnvironmentKind, TransactionKind};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, IntCounter};
+use std::fmt::Display;
+
+static REFUSED_UNWINDS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "stagedsync_refused_unwinds_total",
+        "Unwinds refused for being too deep or below the pruned or finalized horizon"
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnwindRefusal {
+    /// Deeper than the configured maximum reorg depth.
+    TooDeep { max_depth: u64 },
+    /// Data needed to unwind has been pruned.
+    Pruned(DataPruned),
+    /// The unwind would revert a finalized block.
+    Finalized { finalized: BlockNumber },
+}
+
+/// Unwind that was not performed, see [`check_unwind`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnwindRefused {
+    pub head: BlockNumber,
+    pub unwind_to: BlockNumber,
+    pub reason: UnwindRefusal,
+}
+
+impl Display for UnwindRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "refusing to unwind from {} to {} ({} blocks): ",
+            self.head,
+            self.unwind_to,
+            self.head.saturating_sub(*self.unwind_to)
+        )?;
+        match &self.reason {
+            UnwindRefusal::TooDeep { max_depth } => {
+                write!(f, "deeper than the maximum reorg depth of {}", max_depth)
+            }
+            UnwindRefusal::Pruned(e) => write!(f, "{}", e),
+            UnwindRefusal::Finalized { finalized } => {
+                write!(f, "block {} is finalized", finalized)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnwindRefused {}
+
+/// Fails with [`UnwindRefused`] if unwinding from `head` to `unwind_to` is deeper than
+/// `max_depth`, needs pruned data or reverts the finalized block.
+pub fn check_unwind<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    head: BlockNumber,
+    unwind_to: BlockNumber,
+    max_depth: Option<u64>,
+) -> anyhow::Result<()> {
+    let refuse = |reason| -> anyhow::Result<()> {
+        REFUSED_UNWINDS.inc();
+        Err(UnwindRefused {
+            head,
+            unwind_to,
+            reason,
+        }
+        .into())
+    };
+
+    if let Some(max_depth) = max_depth {
+        if head.saturating_sub(*unwind_to) > max_depth {
+            return refuse(UnwindRefusal::TooDeep { max_depth });
+        }
+    }
+
+    // Unwinding reads changesets, call traces and bodies of the unwound blocks.
+    for target in [
+        PruneTarget::History,
+        PruneTarget::CallTraces,
+        PruneTarget::Blocks,
+    ] {
+        if let Some(e) = prune::pruned(tx, target, unwind_to + 1)? {
+            return refuse(UnwindRefusal::Pruned(e));
+        }
+    }
+
+    if let Some(hash) = last_forkchoice::read(tx, last_forkchoice::FINALIZED_BLOCK_HASH)? {
+        if let Some(finalized) = header_number::read(tx, hash)? {
+            if unwind_to < finalized {
+                return refuse(UnwindRefusal::Finalized { finalized });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::{new_mem_database, tables};
+    use ethereum_types::H256;
+
+    #[test]
+    fn unwind_limits() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let refusal = |head, unwind_to, max_depth| {
+            check_unwind(&tx, BlockNumber(head), BlockNumber(unwind_to), max_depth)
+                .err()
+                .map(|e| e.downcast::<UnwindRefused>().unwrap().reason)
+        };
+
+        assert_eq!(refusal(1000, 0, None), None);
+        assert_eq!(refusal(1000, 900, Some(100)), None);
+        assert_eq!(
+            refusal(1000, 899, Some(100)),
+            Some(UnwindRefusal::TooDeep { max_depth: 100 })
+        );
+
+        prune::write(&tx, PruneTarget::History, BlockNumber(500)).unwrap();
+        assert_eq!(refusal(1000, 499, None), None);
+        assert_eq!(
+            refusal(1000, 498, None),
+            Some(UnwindRefusal::Pruned(DataPruned {
+                target: PruneTarget::History,
+                horizon: BlockNumber(500),
+                block_number: BlockNumber(499),
+                archive: None,
+            }))
+        );
+
+        let finalized = H256::repeat_byte(0xf);
+        last_forkchoice::write(&tx, last_forkchoice::FINALIZED_BLOCK_HASH, finalized).unwrap();
+        // Not known locally yet.
+        assert_eq!(refusal(1000, 600, None), None);
+        tx.set(tables::HeaderNumber, finalized, BlockNumber(700))
+            .unwrap();
+        assert_eq!(refusal(1000, 700, None), None);
+        assert_eq!(
+            refusal(1000, 699, None),
+            Some(UnwindRefusal::Finalized {
+                finalized: BlockNumber(700)
+            })
+        );
+    }
+}
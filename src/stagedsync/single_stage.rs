@@ -0,0 +1,318 @@
+//! Running or unwinding one stage on its own, outside of the sync loop, e.g. to benchmark or
+//! validate a change to the stage against a real database, or to recover from a bad stage.
+use super::{format_duration, stage::*};
+use crate::{kv::mdbx::MdbxEnvironment, models::*};
+use anyhow::bail;
+use mdbx::EnvironmentKind;
+use std::time::Instant;
+use tracing::*;
+
+/// Runs `stage` until it reaches `to`, starting from block `from` or else after its recorded
+/// progress, and returns the progress reached.
+///
+/// A dry run writes into a single transaction that is aborted at the end, so that the database
+/// is left untouched, including the recorded progress of the stage.
+pub async fn run_stage<'db, E>(
+    db: &'db MdbxEnvironment<E>,
+    stage: &mut dyn Stage<'db, E>,
+    from: Option<BlockNumber>,
+    to: BlockNumber,
+    dry_run: bool,
+) -> anyhow::Result<BlockNumber>
+where
+    E: EnvironmentKind,
+{
+    let stage_id = stage.id();
+
+    let mut tx = db.begin_mutable()?;
+    let start_progress = match from {
+        Some(from) => from.0.checked_sub(1).map(BlockNumber),
+        None => stage_id.get_progress(&tx)?,
+    };
+    let start_time = Instant::now();
+
+    info!(
+        "Running {} from {} to {}{}",
+        stage_id,
+        start_progress.map(|v| v.0 + 1).unwrap_or(0),
+        to,
+        if dry_run { " (dry run)" } else { "" }
+    );
+
+    let mut progress = start_progress;
+    let mut restarted = false;
+    let reached = loop {
+        match stage
+            .execute(
+                &mut tx,
+                StageInput {
+                    restarted,
+                    first_started_at: (start_time, start_progress),
+                    // Stands in for the stages before this one, which only bound the run.
+                    previous_stage: Some((stage_id, to)),
+                    stage_progress: progress,
+                },
+            )
+            .await?
+        {
+            ExecOutput::Progress {
+                stage_progress,
+                done,
+            } => {
+                if !dry_run {
+                    stage_id.save_progress(&tx, stage_progress)?;
+                    tx.commit()?;
+                    tx = db.begin_mutable()?;
+                }
+
+                if done || stage_progress >= to {
+                    break stage_progress;
+                }
+
+                progress = Some(stage_progress);
+                restarted = true;
+            }
+            ExecOutput::Unwind { unwind_to } => {
+                bail!("{} requested an unwind to {}", stage_id, unwind_to)
+            }
+        }
+    };
+
+    if dry_run {
+        // Dropping the transaction discards everything the stage wrote.
+        drop(tx);
+        info!(
+            "Dry run of {} reached {} in {}, changes discarded",
+            stage_id,
+            reached,
+            format_duration(Instant::now() - start_time, true)
+        );
+    } else {
+        info!(
+            "{} reached {} in {}",
+            stage_id,
+            reached,
+            format_duration(Instant::now() - start_time, true)
+        );
+    }
+
+    Ok(reached)
+}
+
+/// Unwinds `stage` alone down to `to` and returns the progress it reached. Stages after it are
+/// left as they are, to be unwound or rerun on their own.
+///
+/// As with [`run_stage`], a dry run discards all writes.
+pub async fn unwind_stage<'db, E>(
+    db: &'db MdbxEnvironment<E>,
+    stage: &mut dyn Stage<'db, E>,
+    to: BlockNumber,
+    dry_run: bool,
+) -> anyhow::Result<BlockNumber>
+where
+    E: EnvironmentKind,
+{
+    let stage_id = stage.id();
+
+    let mut tx = db.begin_mutable()?;
+    let mut stage_progress = stage_id.get_progress(&tx)?.unwrap_or_default();
+    if stage_progress <= to {
+        info!("{} is at {}, nothing to unwind", stage_id, stage_progress);
+        return Ok(stage_progress);
+    }
+
+    let start_time = Instant::now();
+    info!(
+        "Unwinding {} from {} to {}{}",
+        stage_id,
+        stage_progress,
+        to,
+        if dry_run { " (dry run)" } else { "" }
+    );
+
+    while stage_progress > to {
+        stage_progress = stage
+            .unwind(
+                &mut tx,
+                UnwindInput {
+                    stage_progress,
+                    unwind_to: to,
+                },
+            )
+            .await?
+            .stage_progress;
+    }
+
+    if dry_run {
+        drop(tx);
+        info!(
+            "Dry run unwind of {} reached {} in {}, changes discarded",
+            stage_id,
+            stage_progress,
+            format_duration(Instant::now() - start_time, true)
+        );
+    } else {
+        stage_id.save_progress(&tx, stage_progress)?;
+        tx.commit()?;
+        info!(
+            "{} unwound to {} in {}",
+            stage_id,
+            stage_progress,
+            format_duration(Instant::now() - start_time, true)
+        );
+    }
+
+    Ok(stage_progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kv::{mdbx::MdbxTransaction, new_mem_database, tables},
+        StageId,
+    };
+    use async_trait::async_trait;
+    use mdbx::RW;
+
+    /// Records the gas of each block as its number, a few blocks per invocation.
+    #[derive(Debug)]
+    struct CountingStage;
+
+    const COUNTING: StageId = StageId("Counting");
+
+    #[async_trait]
+    impl<'db, E> Stage<'db, E> for CountingStage
+    where
+        E: EnvironmentKind,
+    {
+        fn id(&self) -> StageId {
+            COUNTING
+        }
+
+        async fn execute<'tx>(
+            &mut self,
+            tx: &'tx mut MdbxTransaction<'db, RW, E>,
+            input: StageInput,
+        ) -> anyhow::Result<ExecOutput>
+        where
+            'db: 'tx,
+        {
+            let max_block = input.previous_stage.unwrap().1;
+            let from = input
+                .stage_progress
+                .map(|v| v + 1)
+                .unwrap_or(BlockNumber(0));
+            let to = std::cmp::min(from + 2, max_block);
+            for block_number in from.0..=to.0 {
+                tx.set(tables::TotalGas, BlockNumber(block_number), block_number)?;
+            }
+
+            Ok(ExecOutput::Progress {
+                stage_progress: to,
+                done: to == max_block,
+            })
+        }
+
+        async fn unwind<'tx>(
+            &mut self,
+            tx: &'tx mut MdbxTransaction<'db, RW, E>,
+            input: UnwindInput,
+        ) -> anyhow::Result<UnwindOutput>
+        where
+            'db: 'tx,
+        {
+            for block_number in input.unwind_to.0 + 1..=input.stage_progress.0 {
+                tx.del(tables::TotalGas, BlockNumber(block_number), None)?;
+            }
+
+            Ok(UnwindOutput {
+                stage_progress: input.unwind_to,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        let db = new_mem_database().unwrap();
+
+        assert_eq!(
+            run_stage(&db, &mut CountingStage, None, BlockNumber(10), true)
+                .await
+                .unwrap(),
+            BlockNumber(10)
+        );
+        let tx = db.begin().unwrap();
+        assert_eq!(COUNTING.get_progress(&tx).unwrap(), None);
+        assert_eq!(tx.get(tables::TotalGas, BlockNumber(5)).unwrap(), None);
+        drop(tx);
+
+        assert_eq!(
+            run_stage(&db, &mut CountingStage, None, BlockNumber(10), false)
+                .await
+                .unwrap(),
+            BlockNumber(10)
+        );
+        let tx = db.begin().unwrap();
+        assert_eq!(COUNTING.get_progress(&tx).unwrap(), Some(BlockNumber(10)));
+        assert_eq!(tx.get(tables::TotalGas, BlockNumber(5)).unwrap(), Some(5));
+        drop(tx);
+
+        // Re-running an earlier range leaves the database as it is.
+        assert_eq!(
+            run_stage(
+                &db,
+                &mut CountingStage,
+                Some(BlockNumber(3)),
+                BlockNumber(4),
+                true
+            )
+            .await
+            .unwrap(),
+            BlockNumber(4)
+        );
+        assert_eq!(
+            COUNTING.get_progress(&db.begin().unwrap()).unwrap(),
+            Some(BlockNumber(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn unwind() {
+        let db = new_mem_database().unwrap();
+        run_stage(&db, &mut CountingStage, None, BlockNumber(10), false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            unwind_stage(&db, &mut CountingStage, BlockNumber(4), true)
+                .await
+                .unwrap(),
+            BlockNumber(4)
+        );
+        let tx = db.begin().unwrap();
+        assert_eq!(COUNTING.get_progress(&tx).unwrap(), Some(BlockNumber(10)));
+        assert_eq!(tx.get(tables::TotalGas, BlockNumber(5)).unwrap(), Some(5));
+        drop(tx);
+
+        assert_eq!(
+            unwind_stage(&db, &mut CountingStage, BlockNumber(4), false)
+                .await
+                .unwrap(),
+            BlockNumber(4)
+        );
+        let tx = db.begin().unwrap();
+        assert_eq!(COUNTING.get_progress(&tx).unwrap(), Some(BlockNumber(4)));
+        assert_eq!(tx.get(tables::TotalGas, BlockNumber(4)).unwrap(), Some(4));
+        assert_eq!(tx.get(tables::TotalGas, BlockNumber(5)).unwrap(), None);
+        drop(tx);
+
+        // Already below the unwind point.
+        assert_eq!(
+            unwind_stage(&db, &mut CountingStage, BlockNumber(7), false)
+                .await
+                .unwrap(),
+            BlockNumber(4)
+        );
+    }
+}
@@ -15,6 +15,7 @@ pub const BODIES: StageId = StageId("Bodies");
 pub const SENDERS: StageId = StageId("SenderRecovery");
 pub const TOTAL_GAS_INDEX: StageId = StageId("TotalGasIndex");
 pub const TOTAL_TX_INDEX: StageId = StageId("TotalTxIndex");
+pub const BOR_HEIMDALL: StageId = StageId("BorHeimdall");
 pub const EXECUTION: StageId = StageId("Execution");
 pub const INTERMEDIATE_HASHES: StageId = StageId("IntermediateHashes");
 pub const HASH_STATE: StageId = StageId("HashState");
@@ -22,6 +23,8 @@ pub const ACCOUNT_HISTORY_INDEX: StageId = StageId("AccountHistoryIndex");
 pub const STORAGE_HISTORY_INDEX: StageId = StageId("StorageHistoryIndex");
 pub const LOG_INDEX: StageId = StageId("LogIndex");
 pub const CALL_TRACES: StageId = StageId("CallTraces");
+pub const CONTRACT_CREATORS: StageId = StageId("ContractCreators");
+pub const ADDRESS_APPEARANCES: StageId = StageId("AddressAppearances");
 pub const TX_LOOKUP: StageId = StageId("TxLookup");
 pub const TX_POOL: StageId = StageId("TxPool");
 pub const FINISH: StageId = StageId("Finish");
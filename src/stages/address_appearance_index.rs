@@ -0,0 +1,319 @@
+use super::call_trace_index::{load_address_bitmaps, unwind_address_bitmaps};
+use crate::{
+    bitmapdb,
+    etl::collector::*,
+    kv::{mdbx::*, tables},
+    models::*,
+    stagedsync::{stage::*, stages::*},
+    StageId,
+};
+use anyhow::format_err;
+use async_trait::async_trait;
+use mdbx::{EnvironmentKind, RW};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::RangeInclusive,
+    sync::Arc,
+};
+use tempfile::TempDir;
+use tokio::pin;
+
+/// Generation of the address appearance index: blocks in which an address was the sender or
+/// recipient of a call, emitted a log or was named in one of its topics, or created or was
+/// created as a contract.
+#[derive(Debug)]
+pub struct AddressAppearanceIndex {
+    pub temp_dir: Arc<TempDir>,
+    pub flush_interval: u64,
+}
+
+#[async_trait]
+impl<'db, E> Stage<'db, E> for AddressAppearanceIndex
+where
+    E: EnvironmentKind,
+{
+    fn id(&self) -> StageId {
+        ADDRESS_APPEARANCES
+    }
+
+    async fn execute<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: StageInput,
+    ) -> anyhow::Result<ExecOutput>
+    where
+        'db: 'tx,
+    {
+        let starting_block = input.stage_progress.unwrap_or(BlockNumber(0));
+        let max_block = input
+            .previous_stage
+            .ok_or_else(|| {
+                format_err!("Address appearance index generation cannot be the first stage")
+            })?
+            .1;
+
+        let mut collector =
+            Collector::<Address, croaring::Treemap>::new(&*self.temp_dir, OPTIMAL_BUFFER_CAPACITY);
+
+        let mut from = starting_block + 1;
+        while from <= max_block {
+            let to = std::cmp::min(BlockNumber(from.0 + self.flush_interval), max_block);
+
+            for (address, appearances) in collect_appearances(tx, from..=to)? {
+                collector.push(address, appearances);
+            }
+
+            from = to + 1;
+        }
+
+        load_address_bitmaps(&mut tx.cursor(tables::AddressAppearanceIndex)?, collector)?;
+
+        Ok(ExecOutput::Progress {
+            stage_progress: max_block,
+            done: true,
+        })
+    }
+
+    async fn unwind<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: UnwindInput,
+    ) -> anyhow::Result<UnwindOutput>
+    where
+        'db: 'tx,
+    {
+        let addresses = collect_appearances(tx, input.unwind_to + 1..=BlockNumber(u64::MAX))?
+            .into_keys()
+            .collect::<BTreeSet<_>>();
+
+        unwind_address_bitmaps(
+            &mut tx.cursor(tables::AddressAppearanceIndex)?,
+            addresses,
+            input.unwind_to,
+        )?;
+
+        Ok(UnwindOutput {
+            stage_progress: input.unwind_to,
+        })
+    }
+}
+
+/// Topics holding an ABI-encoded address, such as indexed `from` and `to` of token transfers.
+fn topic_address(topic: H256) -> Option<Address> {
+    let (padding, address) = topic.as_bytes().split_at(12);
+    if padding.iter().all(|&b| b == 0) {
+        let address = Address::from_slice(address);
+        if !address.is_zero() {
+            return Some(address);
+        }
+    }
+
+    None
+}
+
+/// Appearances of every address in blocks `range`, from call traces, logs and contract
+/// creations recorded by execution.
+fn collect_appearances<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    range: RangeInclusive<BlockNumber>,
+) -> anyhow::Result<HashMap<Address, croaring::Treemap>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut appearances = HashMap::<Address, croaring::Treemap>::new();
+    let mut add = |address: Address, block_number: BlockNumber| {
+        appearances.entry(address).or_default().add(block_number.0);
+    };
+
+    let walker = tx.cursor(tables::CallTraceSet)?.walk(Some(*range.start()));
+    pin!(walker);
+    while let Some((block_number, entry)) = walker.next().transpose()? {
+        if block_number > *range.end() {
+            break;
+        }
+
+        add(entry.address, block_number);
+    }
+
+    let walker = tx
+        .cursor(tables::Log)?
+        .walk(Some((*range.start(), TxIndex(0))));
+    pin!(walker);
+    while let Some(((block_number, _), logs)) = walker.next().transpose()? {
+        if block_number > *range.end() {
+            break;
+        }
+
+        for log in logs {
+            add(log.address, block_number);
+            for topic in log.topics {
+                if let Some(address) = topic_address(topic) {
+                    add(address, block_number);
+                }
+            }
+        }
+    }
+
+    let walker = tx
+        .cursor(tables::ContractCreation)?
+        .walk(Some((*range.start(), TxIndex(0))));
+    pin!(walker);
+    while let Some(((block_number, _), creations)) = walker.next().transpose()? {
+        if block_number > *range.end() {
+            break;
+        }
+
+        for ContractCreation { creator, address } in creations {
+            add(creator, block_number);
+            add(address, block_number);
+        }
+    }
+
+    Ok(appearances)
+}
+
+/// Blocks within `range` in which `address` appears, in ascending order.
+pub fn read_address_appearances<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    address: Address,
+    range: RangeInclusive<BlockNumber>,
+) -> anyhow::Result<Vec<BlockNumber>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    Ok(
+        bitmapdb::get(tx, tables::AddressAppearanceIndex, address, range.clone())?
+            .iter()
+            .map(BlockNumber)
+            .filter(|block_number| range.contains(block_number))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::tables::CallTraceSetEntry;
+    use bytes::Bytes;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn address_appearances() {
+        let db = crate::kv::new_mem_database().unwrap();
+        let mut tx = db.begin_mutable().unwrap();
+
+        let sender = Address::repeat_byte(0xaa);
+        let token = Address::repeat_byte(0xbb);
+        let recipient = Address::repeat_byte(0xcc);
+        let factory = Address::repeat_byte(0xdd);
+        let child = Address::repeat_byte(0xee);
+
+        // Block 1: sender calls the token, which logs a transfer to the recipient.
+        for (address, from, to) in [(sender, true, false), (token, false, true)] {
+            tx.set(
+                tables::CallTraceSet,
+                BlockNumber(1),
+                CallTraceSetEntry { address, from, to },
+            )
+            .unwrap();
+        }
+        tx.set(
+            tables::Log,
+            (BlockNumber(1), TxIndex(0)),
+            vec![Log {
+                address: token,
+                topics: vec![
+                    H256::repeat_byte(0x01),
+                    H256::from(sender),
+                    H256::from(recipient),
+                    H256::zero(),
+                ],
+                data: Bytes::new(),
+            }],
+        )
+        .unwrap();
+        // Block 3: the factory creates a child contract.
+        tx.set(
+            tables::ContractCreation,
+            (BlockNumber(3), TxIndex(0)),
+            vec![ContractCreation {
+                creator: factory,
+                address: child,
+            }],
+        )
+        .unwrap();
+        // Block 5: the recipient calls the child.
+        for (address, from, to) in [(recipient, true, false), (child, false, true)] {
+            tx.set(
+                tables::CallTraceSet,
+                BlockNumber(5),
+                CallTraceSetEntry { address, from, to },
+            )
+            .unwrap();
+        }
+
+        let stage = || AddressAppearanceIndex {
+            temp_dir: Arc::new(TempDir::new().unwrap()),
+            flush_interval: 0,
+        };
+
+        fn appearances<K: TransactionKind, E: EnvironmentKind>(
+            tx: &MdbxTransaction<'_, K, E>,
+            address: Address,
+        ) -> Vec<u64> {
+            read_address_appearances(tx, address, BlockNumber(0)..=BlockNumber(10))
+                .unwrap()
+                .into_iter()
+                .map(|block_number| block_number.0)
+                .collect()
+        }
+
+        assert_eq!(
+            stage()
+                .execute(
+                    &mut tx,
+                    StageInput {
+                        restarted: false,
+                        first_started_at: (Instant::now(), Some(BlockNumber(0))),
+                        previous_stage: Some((EXECUTION, BlockNumber(5))),
+                        stage_progress: None,
+                    },
+                )
+                .await
+                .unwrap(),
+            ExecOutput::Progress {
+                stage_progress: BlockNumber(5),
+                done: true,
+            }
+        );
+
+        assert_eq!(appearances(&tx, sender), vec![1]);
+        assert_eq!(appearances(&tx, token), vec![1]);
+        assert_eq!(appearances(&tx, recipient), vec![1, 5]);
+        assert_eq!(appearances(&tx, factory), vec![3]);
+        assert_eq!(appearances(&tx, child), vec![3, 5]);
+        assert_eq!(appearances(&tx, Address::zero()), vec![]);
+        assert_eq!(
+            read_address_appearances(&tx, recipient, BlockNumber(2)..=BlockNumber(10)).unwrap(),
+            vec![BlockNumber(5)]
+        );
+
+        stage()
+            .unwind(
+                &mut tx,
+                UnwindInput {
+                    stage_progress: BlockNumber(5),
+                    unwind_to: BlockNumber(2),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(appearances(&tx, recipient), vec![1]);
+        assert_eq!(appearances(&tx, factory), vec![]);
+        assert_eq!(appearances(&tx, child), vec![]);
+        assert_eq!(appearances(&tx, sender), vec![1]);
+    }
+}
@@ -0,0 +1,502 @@
+//! Ingestion of blocks pushed by a trusted feed, such as an L2 sequencer or a relay, instead of
+//! downloading them from peers.
+//!
+//! The feed submits every block with its signature of the block hash over a local WebSocket
+//! JSON-RPC endpoint. [`FeedHeaders`] and [`FeedBodies`] take the place of the header and body
+//! stages, so that fed blocks go through the rest of the pipeline like downloaded ones.
+use crate::{
+    accessors::chain,
+    consensus::pre_validate_body,
+    crypto::pubkey_to_address,
+    hexbytes,
+    kv::{mdbx::*, tables},
+    models::*,
+    stagedsync::{stage::*, stages::*},
+    StageId,
+};
+use anyhow::{ensure, format_err};
+use async_trait::async_trait;
+use bytes::Bytes;
+use jsonrpsee::{
+    core::{Error as RpcError, RpcResult},
+    proc_macros::rpc,
+    types::error::CallError,
+    ws_server::{WsServerBuilder, WsServerHandle},
+};
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    Message, SECP256K1,
+};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tracing::*;
+
+/// Blocks accepted from the feed and not imported yet. Submissions wait while it is full.
+const QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct SignedBlock {
+    /// RLP of the block.
+    #[serde(with = "hexbytes")]
+    pub block: Bytes,
+    /// Signature of the block hash by the feed signer, as `r || s || v`.
+    #[serde(with = "hexbytes")]
+    pub signature: Bytes,
+}
+
+#[rpc(server, namespace = "feed")]
+pub trait FeedApi {
+    /// Queues a block for import and returns its hash.
+    #[method(name = "submitBlock")]
+    async fn submit_block(&self, block: SignedBlock) -> RpcResult<H256>;
+}
+
+struct FeedApiServerImpl {
+    signer: Address,
+    sender: mpsc::Sender<Block>,
+}
+
+fn recover_signer(hash: H256, signature: &[u8]) -> anyhow::Result<Address> {
+    ensure!(
+        signature.len() == 65,
+        "signature is {} bytes long, not 65",
+        signature.len()
+    );
+
+    let v = signature[64];
+    let recovery_id = RecoveryId::from_i32(i32::from(if v >= 27 { v - 27 } else { v }))?;
+    let public = SECP256K1.recover_ecdsa(
+        &Message::from_slice(hash.as_bytes())?,
+        &RecoverableSignature::from_compact(&signature[..64], recovery_id)?,
+    )?;
+
+    Ok(pubkey_to_address(&public))
+}
+
+impl FeedApiServerImpl {
+    /// Decodes the block and checks that it is signed by the feed and that its body matches its
+    /// header. Linking it to the chain is left to [`FeedHeaders`].
+    fn check(&self, signed: &SignedBlock) -> anyhow::Result<Block> {
+        let block = rlp::decode::<Block>(&signed.block)?;
+        let hash = block.header.hash();
+
+        let signer = recover_signer(hash, &signed.signature)?;
+        ensure!(
+            signer == self.signer,
+            "block {:?} is signed by {:?}, not by the feed signer",
+            hash,
+            signer
+        );
+
+        pre_validate_body(&block.header, &block.transactions, &block.ommers)?;
+
+        Ok(block)
+    }
+}
+
+#[async_trait]
+impl FeedApiServer for FeedApiServerImpl {
+    #[instrument(name = "feed_submitBlock", skip(self, block))]
+    async fn submit_block(&self, block: SignedBlock) -> RpcResult<H256> {
+        let block = self
+            .check(&block)
+            .map_err(|e| RpcError::Call(CallError::InvalidParams(e)))?;
+        let hash = block.header.hash();
+
+        debug!("Queueing fed block {}/{:?}", block.header.number, hash);
+        self.sender
+            .send(block)
+            .await
+            .map_err(|_| format_err!("block import has stopped"))?;
+
+        Ok(hash)
+    }
+}
+
+/// Starts accepting blocks signed by `signer` on `listen_address`, and returns the server with
+/// the header stage importing them.
+pub async fn start_block_feed(
+    listen_address: SocketAddr,
+    signer: Address,
+) -> anyhow::Result<(WsServerHandle, FeedHeaders)> {
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+
+    let server = WsServerBuilder::default().build(listen_address).await?;
+    let handle = server.start(FeedApiServerImpl { signer, sender }.into_rpc())?;
+    info!(
+        "Accepting blocks signed by {:?} on ws://{}",
+        signer, listen_address
+    );
+
+    Ok((
+        handle,
+        FeedHeaders {
+            receiver,
+            pending: None,
+        },
+    ))
+}
+
+/// Writes fed blocks on top of the canonical chain: headers, with their bodies so that they
+/// cannot get separated by a restart.
+#[derive(Debug)]
+pub struct FeedHeaders {
+    receiver: mpsc::Receiver<Block>,
+    /// Block on a fork, waiting for the unwind to its parent.
+    pending: Option<Block>,
+}
+
+#[async_trait]
+impl<'db, E> Stage<'db, E> for FeedHeaders
+where
+    E: EnvironmentKind,
+{
+    fn id(&self) -> StageId {
+        HEADERS
+    }
+
+    async fn execute<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: StageInput,
+    ) -> anyhow::Result<ExecOutput>
+    where
+        'db: 'tx,
+    {
+        let original_head = input.stage_progress.unwrap_or(BlockNumber(0));
+        let mut head = original_head;
+        let mut head_hash = tx
+            .get(tables::CanonicalHeader, head)?
+            .ok_or_else(|| format_err!("No canonical hash for block {}", head))?;
+
+        loop {
+            let block = match self.pending.take() {
+                Some(block) => block,
+                None => match self.receiver.try_recv() {
+                    Ok(block) => block,
+                    Err(_) => break,
+                },
+            };
+            let number = block.header.number;
+            let hash = block.header.hash();
+
+            if number != head + 1 || block.header.parent_hash != head_hash {
+                if number <= head && tx.get(tables::CanonicalHeader, number)? == Some(hash) {
+                    debug!("Fed block {}/{:?} is already canonical", number, hash);
+                    continue;
+                }
+
+                // A fork of our chain: unwind to its parent, then import it.
+                if number.0 > 0
+                    && number <= head
+                    && tx.get(tables::CanonicalHeader, BlockNumber(number.0 - 1))?
+                        == Some(block.header.parent_hash)
+                {
+                    self.pending = Some(block);
+                    if head > original_head {
+                        break;
+                    }
+
+                    return Ok(ExecOutput::Unwind {
+                        unwind_to: BlockNumber(number.0 - 1),
+                    });
+                }
+
+                warn!(
+                    "Dropping fed block {}/{:?}: it does not extend the chain at {}/{:?}",
+                    number, hash, head, head_hash
+                );
+                continue;
+            }
+
+            let total_difficulty = tx
+                .get(tables::HeadersTotalDifficulty, (head, head_hash))?
+                .ok_or_else(|| format_err!("No total difficulty for block {}", head))?
+                + block.header.difficulty;
+
+            tx.set(tables::CanonicalHeader, number, hash)?;
+            tx.set(
+                tables::HeadersTotalDifficulty,
+                (number, hash),
+                total_difficulty,
+            )?;
+            chain::block_body::write(
+                tx,
+                BlockKey::new(number, hash),
+                &BlockBody {
+                    transactions: block.transactions,
+                    ommers: block.ommers,
+                },
+            )?;
+            tx.set(tables::Header, (number, hash), block.header)?;
+
+            head = number;
+            head_hash = hash;
+        }
+
+        if head > original_head {
+            info!("Imported fed blocks {} to {}", original_head + 1, head);
+        }
+
+        Ok(ExecOutput::Progress {
+            stage_progress: head,
+            done: true,
+        })
+    }
+
+    async fn unwind<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: UnwindInput,
+    ) -> anyhow::Result<UnwindOutput>
+    where
+        'db: 'tx,
+    {
+        let mut canonical_cur = tx.cursor(tables::CanonicalHeader)?;
+        while let Some((block_num, _)) = canonical_cur.last()? {
+            if block_num <= input.unwind_to {
+                break;
+            }
+
+            canonical_cur.delete_current()?;
+        }
+
+        let mut header_cur = tx.cursor(tables::Header)?;
+        while let Some(((block_num, _), _)) = header_cur.last()? {
+            if block_num <= input.unwind_to {
+                break;
+            }
+
+            header_cur.delete_current()?;
+        }
+
+        let mut td_cur = tx.cursor(tables::HeadersTotalDifficulty)?;
+        while let Some(((block_num, _), _)) = td_cur.last()? {
+            if block_num <= input.unwind_to {
+                break;
+            }
+
+            td_cur.delete_current()?;
+        }
+
+        Ok(UnwindOutput {
+            stage_progress: input.unwind_to,
+        })
+    }
+}
+
+/// Catches up with [`FeedHeaders`], which already wrote the bodies.
+#[derive(Debug)]
+pub struct FeedBodies;
+
+#[async_trait]
+impl<'db, E> Stage<'db, E> for FeedBodies
+where
+    E: EnvironmentKind,
+{
+    fn id(&self) -> StageId {
+        BODIES
+    }
+
+    async fn execute<'tx>(
+        &mut self,
+        _: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: StageInput,
+    ) -> anyhow::Result<ExecOutput>
+    where
+        'db: 'tx,
+    {
+        let max_block = input
+            .previous_stage
+            .map(|(_, v)| v)
+            .ok_or_else(|| format_err!("Cannot be the first stage"))?;
+
+        Ok(ExecOutput::Progress {
+            stage_progress: max_block,
+            done: true,
+        })
+    }
+
+    async fn unwind<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: UnwindInput,
+    ) -> anyhow::Result<UnwindOutput>
+    where
+        'db: 'tx,
+    {
+        let mut block_body_cur = tx.cursor(tables::BlockBody)?;
+        let mut block_tx_cur = tx.cursor(tables::BlockTransaction)?;
+        while let Some(((block_num, _), body)) = block_body_cur.last()? {
+            if block_num <= input.unwind_to {
+                break;
+            }
+
+            block_body_cur.delete_current()?;
+
+            let mut deleted = 0;
+            while deleted < body.tx_amount {
+                let to_delete = body.base_tx_id + deleted;
+                // Siblings may share transactions, the range can be gone already.
+                if block_tx_cur.seek_exact(to_delete)?.is_some() {
+                    block_tx_cur.delete_current()?;
+                }
+
+                deleted += 1;
+            }
+        }
+
+        Ok(UnwindOutput {
+            stage_progress: input.unwind_to,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::to_pubkey, kv::new_mem_database};
+    use secp256k1::SecretKey;
+    use std::time::Instant;
+
+    fn block(parent: &BlockHeader, extra_data: &'static [u8]) -> Block {
+        Block::new(
+            PartialHeader {
+                parent_hash: parent.hash(),
+                number: parent.number + 1,
+                difficulty: U256::ONE,
+                extra_data: Bytes::from_static(extra_data),
+                ..PartialHeader::empty()
+            },
+            vec![],
+            vec![],
+        )
+    }
+
+    fn sign(key: &SecretKey, block: &Block) -> SignedBlock {
+        let (recovery_id, signature) = SECP256K1
+            .sign_ecdsa_recoverable(
+                &Message::from_slice(block.header.hash().as_bytes()).unwrap(),
+                key,
+            )
+            .serialize_compact();
+        let mut signature = signature.to_vec();
+        signature.push(recovery_id.to_i32() as u8 + 27);
+
+        SignedBlock {
+            block: rlp::encode(block).freeze(),
+            signature: signature.into(),
+        }
+    }
+
+    fn input(stage_progress: BlockNumber) -> StageInput {
+        StageInput {
+            restarted: false,
+            first_started_at: (Instant::now(), None),
+            previous_stage: None,
+            stage_progress: Some(stage_progress),
+        }
+    }
+
+    #[tokio::test]
+    async fn feed() {
+        let key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let api = FeedApiServerImpl {
+            signer: pubkey_to_address(&to_pubkey(&key)),
+            sender,
+        };
+        let mut stage = FeedHeaders {
+            receiver,
+            pending: None,
+        };
+
+        let db = new_mem_database().unwrap();
+        let mut tx = db.begin_mutable().unwrap();
+        let genesis = BlockHeader::new(PartialHeader::empty(), H256::zero(), H256::zero());
+        let genesis_hash = genesis.hash();
+        tx.set(tables::CanonicalHeader, BlockNumber(0), genesis_hash)
+            .unwrap();
+        tx.set(
+            tables::Header,
+            (BlockNumber(0), genesis_hash),
+            genesis.clone(),
+        )
+        .unwrap();
+        tx.set(
+            tables::HeadersTotalDifficulty,
+            (BlockNumber(0), genesis_hash),
+            U256::ZERO,
+        )
+        .unwrap();
+
+        let block1 = block(&genesis, b"1");
+        let block2 = block(&block1.header, b"2");
+        let fork2 = block(&block1.header, b"fork");
+
+        // Only the feed signer is accepted.
+        let other = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        assert!(api.check(&sign(&other, &block1)).is_err());
+
+        for block in [&block1, &block2, &block1] {
+            api.submit_block(sign(&key, block)).await.unwrap();
+        }
+        assert_eq!(
+            stage.execute(&mut tx, input(BlockNumber(0))).await.unwrap(),
+            ExecOutput::Progress {
+                stage_progress: BlockNumber(2),
+                done: true
+            }
+        );
+        assert_eq!(
+            tx.get(
+                tables::HeadersTotalDifficulty,
+                (BlockNumber(2), block2.header.hash())
+            )
+            .unwrap(),
+            Some(U256::from(2_u8))
+        );
+        assert_eq!(
+            chain::block_body::read_without_senders(
+                &tx,
+                BlockKey::new(BlockNumber(2), block2.header.hash())
+            )
+            .unwrap(),
+            Some(BlockBody {
+                transactions: vec![],
+                ommers: vec![],
+            })
+        );
+
+        // A fork replaces the head after an unwind.
+        api.submit_block(sign(&key, &fork2)).await.unwrap();
+        assert_eq!(
+            stage.execute(&mut tx, input(BlockNumber(2))).await.unwrap(),
+            ExecOutput::Unwind {
+                unwind_to: BlockNumber(1)
+            }
+        );
+        stage
+            .unwind(
+                &mut tx,
+                UnwindInput {
+                    stage_progress: BlockNumber(2),
+                    unwind_to: BlockNumber(1),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            stage.execute(&mut tx, input(BlockNumber(1))).await.unwrap(),
+            ExecOutput::Progress {
+                stage_progress: BlockNumber(2),
+                done: true
+            }
+        );
+        assert_eq!(
+            tx.get(tables::CanonicalHeader, BlockNumber(2)).unwrap(),
+            Some(fork2.header.hash())
+        );
+    }
+}
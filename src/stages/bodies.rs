@@ -0,0 +1,852 @@
+//! Download of block bodies from peers, for the canonical headers written by the header stage.
+//!
+//! Bodies are asked for by hash in batches, several batches at a time, and written in block
+//! order. Every body is checked against its header with [`pre_validate_body`] before it is
+//! kept: a peer that sends a body that does not belong to its header is penalized and the
+//! batch is asked for again.
+//!
+//! Peers that answer a batch with no bodies, or not at all, are tracked by a
+//! [`BodyWithholdingDetector`], so that the batch goes to another peer next. Once enough peers
+//! withheld a batch it is read from an era1 archive if one is configured, and the peers that
+//! withheld it are penalized otherwise, so that the sentry replaces them.
+use crate::{
+    accessors::chain,
+    consensus::pre_validate_body,
+    downloader::body_withholding::{BodyWithholdingDetector, RangeStatus},
+    era1::{Era1Reader, MAX_ERA1_SIZE},
+    kv::{mdbx::*, tables},
+    models::*,
+    sentry::{
+        messages::{BlockBodiesMessage, EthMessageId, GetBlockBodiesMessage, Message},
+        sentry_client::{MessageFromPeer, PeerEvent, PeerFilter, PeerId},
+        sentry_client_reactor::{SendMessageError, SentryClientReactorShared},
+    },
+    stagedsync::{stage::*, stages::BODIES},
+    StageId,
+};
+use anyhow::{bail, ensure, format_err};
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio_stream::StreamExt;
+use tracing::*;
+
+/// Batches asked for at once.
+const MAX_IN_FLIGHT: usize = 16;
+/// How far past the next block to write bodies are asked for, in batches.
+const DOWNLOAD_WINDOW: u64 = 4 * MAX_IN_FLIGHT as u64;
+/// How long a batch waits for its response before it is asked for again.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often requests are checked for timing out while no response comes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a stage run downloads before it lets the bodies be committed.
+const COMMIT_AFTER: Duration = Duration::from_secs(60);
+/// Distinct peers that may withhold a batch before it is retrieved some other way.
+const MAX_WITHHOLDING_PEERS: usize = 3;
+
+/// Blocks asked for in one request, contiguous from `start`.
+#[derive(Debug)]
+struct Request {
+    start: BlockNumber,
+    hashes: Vec<H256>,
+    /// Peer asked, if a particular one was.
+    peer: Option<PeerId>,
+    sent_at: Instant,
+}
+
+/// What became of the bodies of a batch.
+#[derive(Debug, PartialEq)]
+enum Response {
+    /// Bodies of the first `delivered` blocks of the batch at `start`, the rest to be asked
+    /// for again.
+    Accepted {
+        start: BlockNumber,
+        delivered: usize,
+    },
+    /// A body did not match its header, or more were sent than asked for. The whole batch is
+    /// asked for again, and the sender should be penalized.
+    Invalid,
+}
+
+/// Batches asked for and bodies received ahead of the next block to write.
+#[derive(Debug)]
+struct BodyRequests {
+    batch_size: usize,
+    /// Next block not asked for yet.
+    next: BlockNumber,
+    /// Last block to download.
+    target: BlockNumber,
+    /// Batches to ask for again, by their first block, with their length.
+    retry: BTreeMap<BlockNumber, usize>,
+    in_flight: HashMap<u64, Request>,
+    downloaded: BTreeMap<BlockNumber, (H256, BlockBody)>,
+    last_request_id: u64,
+}
+
+impl BodyRequests {
+    fn new(
+        from: BlockNumber,
+        target: BlockNumber,
+        batch_size: usize,
+        last_request_id: u64,
+    ) -> Self {
+        Self {
+            batch_size,
+            next: from,
+            target,
+            retry: BTreeMap::new(),
+            in_flight: HashMap::new(),
+            downloaded: BTreeMap::new(),
+            last_request_id,
+        }
+    }
+
+    /// Next batch to ask for: one to ask for again first, or the one after the last asked for,
+    /// unless it is too far ahead of `written`, the last block written.
+    fn next_batch(&mut self, written: BlockNumber) -> Option<(BlockNumber, usize)> {
+        if let Some(first) = self.retry.keys().next().copied() {
+            return self.retry.remove_entry(&first);
+        }
+
+        let window_end = written.0 + DOWNLOAD_WINDOW * self.batch_size as u64;
+        if self.next > self.target || self.next.0 > window_end {
+            return None;
+        }
+
+        let start = self.next;
+        let len = std::cmp::min(self.batch_size as u64, self.target.0 - start.0 + 1);
+        self.next = start + len;
+
+        Some((start, len as usize))
+    }
+
+    /// Canonical hashes of the `len` blocks from `start`.
+    fn hashes<K, E>(
+        tx: &MdbxTransaction<'_, K, E>,
+        start: BlockNumber,
+        len: usize,
+    ) -> anyhow::Result<Vec<H256>>
+    where
+        K: TransactionKind,
+        E: EnvironmentKind,
+    {
+        (start.0..start.0 + len as u64)
+            .map(|number| {
+                chain::canonical_hash::read(tx, BlockNumber(number))?
+                    .ok_or_else(|| format_err!("No canonical hash for block {}", number))
+            })
+            .collect()
+    }
+
+    /// Records a request for the batch of `len` blocks from `start`, sent to `peer` if given.
+    fn request<K, E>(
+        &mut self,
+        tx: &MdbxTransaction<'_, K, E>,
+        start: BlockNumber,
+        len: usize,
+        peer: Option<PeerId>,
+    ) -> anyhow::Result<GetBlockBodiesMessage>
+    where
+        K: TransactionKind,
+        E: EnvironmentKind,
+    {
+        let hashes = Self::hashes(tx, start, len)?;
+
+        self.last_request_id += 1;
+        let request_id = self.last_request_id;
+        self.in_flight.insert(
+            request_id,
+            Request {
+                start,
+                hashes: hashes.clone(),
+                peer,
+                sent_at: Instant::now(),
+            },
+        );
+
+        Ok(GetBlockBodiesMessage {
+            request_id,
+            block_hashes: hashes,
+        })
+    }
+
+    fn in_flight_count(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Gives up on a request, to ask for its batch again.
+    fn cancel(&mut self, request_id: u64) {
+        if let Some(request) = self.in_flight.remove(&request_id) {
+            self.retry.insert(request.start, request.hashes.len());
+        }
+    }
+
+    /// Gives up on the requests sent longer than `timeout` ago, and returns the first block and
+    /// the peer of each.
+    fn expire(&mut self, timeout: Duration) -> Vec<(BlockNumber, Option<PeerId>)> {
+        let expired = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| request.sent_at.elapsed() > timeout)
+            .map(|(&request_id, request)| (request_id, request.start, request.peer))
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .map(|(request_id, start, peer)| {
+                self.cancel(request_id);
+                (start, peer)
+            })
+            .collect()
+    }
+
+    /// Takes in the bodies of `response`, checked against their headers. `None` if it does not
+    /// answer one of our requests, e.g. because it came after the request timed out.
+    fn on_response<K, E>(
+        &mut self,
+        tx: &MdbxTransaction<'_, K, E>,
+        response: BlockBodiesMessage,
+    ) -> anyhow::Result<Option<Response>>
+    where
+        K: TransactionKind,
+        E: EnvironmentKind,
+    {
+        let request = match self.in_flight.remove(&response.request_id) {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+
+        let bodies = response
+            .block_bodies
+            .into_iter()
+            .map(|body| BlockBody {
+                transactions: body.transactions,
+                ommers: body.ommers,
+            })
+            .collect();
+
+        self.accept(tx, request.start, request.hashes, bodies)
+            .map(Some)
+    }
+
+    /// Length of the batch at `start` waiting to be asked for again.
+    fn retry_len(&self, start: BlockNumber) -> Option<usize> {
+        self.retry.get(&start).copied()
+    }
+
+    /// Takes in the bodies of the batch at `start` waiting to be asked for again, retrieved some
+    /// other way than from a peer.
+    fn retrieved<K, E>(
+        &mut self,
+        tx: &MdbxTransaction<'_, K, E>,
+        start: BlockNumber,
+        bodies: Vec<BlockBody>,
+    ) -> anyhow::Result<Response>
+    where
+        K: TransactionKind,
+        E: EnvironmentKind,
+    {
+        let len = self
+            .retry
+            .remove(&start)
+            .ok_or_else(|| format_err!("No batch from block {} to retrieve", start))?;
+        let hashes = Self::hashes(tx, start, len)?;
+
+        self.accept(tx, start, hashes, bodies)
+    }
+
+    fn accept<K, E>(
+        &mut self,
+        tx: &MdbxTransaction<'_, K, E>,
+        start: BlockNumber,
+        hashes: Vec<H256>,
+        bodies: Vec<BlockBody>,
+    ) -> anyhow::Result<Response>
+    where
+        K: TransactionKind,
+        E: EnvironmentKind,
+    {
+        let requested = hashes.len();
+
+        if bodies.len() > requested {
+            debug!(
+                "Got {} bodies for a batch of {} from block {}",
+                bodies.len(),
+                requested,
+                start
+            );
+            self.retry.insert(start, requested);
+            return Ok(Response::Invalid);
+        }
+
+        let mut accepted = Vec::with_capacity(bodies.len());
+        for ((number, hash), body) in (start.0..).map(BlockNumber).zip(hashes).zip(bodies) {
+            let header = chain::header::read(tx, BlockKey::new(number, hash))?
+                .ok_or_else(|| format_err!("No header for block {}/{:?}", number, hash))?;
+            if let Err(e) = pre_validate_body(&header, &body.transactions, &body.ommers) {
+                debug!(
+                    "Body of block {}/{:?} does not match its header: {}",
+                    number, hash, e
+                );
+                self.retry.insert(start, requested);
+                return Ok(Response::Invalid);
+            }
+
+            accepted.push((number, hash, body));
+        }
+
+        let delivered = accepted.len();
+        for (number, hash, body) in accepted {
+            self.downloaded.insert(number, (hash, body));
+        }
+        if delivered < requested {
+            self.retry
+                .insert(start + delivered as u64, requested - delivered);
+        }
+
+        Ok(Response::Accepted { start, delivered })
+    }
+
+    /// Body of `block`, if it was received.
+    fn take(&mut self, block: BlockNumber) -> Option<(H256, BlockBody)> {
+        self.downloaded.remove(&block)
+    }
+}
+
+/// Bodies of the blocks with `hashes` from `start` on, as far as the era1 archive of their
+/// epoch in `dir` holds them. Empty if there is no such archive.
+fn read_era1_bodies(
+    dir: &Path,
+    start: BlockNumber,
+    hashes: &[H256],
+) -> anyhow::Result<Vec<BlockBody>> {
+    let epoch = format!("-{:05}-", start.0 / MAX_ERA1_SIZE as u64);
+    let path = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .find(|path| {
+            path.extension().map_or(false, |ext| ext == "era1")
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map_or(false, |name| name.contains(&epoch))
+        });
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(vec![]),
+    };
+
+    let mut reader = Era1Reader::new(BufReader::new(File::open(&path)?))?;
+    let mut bodies = Vec::with_capacity(hashes.len());
+    while bodies.len() < hashes.len() {
+        let block = match reader.next_block()? {
+            Some(block) => block,
+            None => break,
+        };
+        if block.header.number < start {
+            continue;
+        }
+
+        ensure!(
+            block.header.hash() == hashes[bodies.len()],
+            "{} does not hold canonical block {}",
+            path.display(),
+            block.header.number
+        );
+        bodies.push(block.body);
+    }
+
+    Ok(bodies)
+}
+
+/// Download of block bodies from peers.
+#[derive(Debug)]
+pub struct BodyDownload {
+    sentry: SentryClientReactorShared,
+    /// Bodies asked for in one request.
+    batch_size: usize,
+    /// Era1 archives to read the batches withheld by peers from.
+    era1_dir: Option<PathBuf>,
+    detector: BodyWithholdingDetector,
+    last_request_id: u64,
+}
+
+impl BodyDownload {
+    pub fn new(
+        sentry: SentryClientReactorShared,
+        batch_size: usize,
+        era1_dir: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            sentry,
+            batch_size,
+            era1_dir,
+            detector: BodyWithholdingDetector::new(MAX_WITHHOLDING_PEERS),
+            last_request_id: 0,
+        }
+    }
+
+    /// Retrieves the batch at `start` withheld by too many peers from the era1 archives, or
+    /// penalizes the peers that withheld it if they do not have it.
+    async fn retrieve_withheld<E>(
+        &mut self,
+        tx: &MdbxTransaction<'_, RW, E>,
+        requests: &mut BodyRequests,
+        start: BlockNumber,
+    ) -> anyhow::Result<()>
+    where
+        E: EnvironmentKind,
+    {
+        let withheld_by = self.detector.give_up(start);
+        let len = match requests.retry_len(start) {
+            Some(len) => len,
+            None => return Ok(()),
+        };
+
+        let mut retrieved = 0;
+        if let Some(dir) = &self.era1_dir {
+            let hashes = BodyRequests::hashes(tx, start, len)?;
+            match read_era1_bodies(dir, start, &hashes) {
+                Ok(bodies) if !bodies.is_empty() => match requests.retrieved(tx, start, bodies)? {
+                    Response::Accepted { delivered, .. } => retrieved = delivered,
+                    Response::Invalid => {
+                        warn!(
+                            "Era1 bodies from block {} do not match their headers",
+                            start
+                        )
+                    }
+                },
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "Failed to read bodies from block {} from era1: {}",
+                    start, e
+                ),
+            }
+        }
+
+        if retrieved > 0 {
+            info!(
+                "Read bodies {} to {} withheld by peers from era1",
+                start,
+                start + (retrieved as u64 - 1)
+            );
+        } else {
+            warn!(
+                "Bodies from block {} withheld by {} peers, penalizing them",
+                start,
+                withheld_by.len()
+            );
+            let sentry = self.sentry.read().await;
+            for peer_id in withheld_by {
+                sentry.penalize_peer(peer_id).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'db, E> Stage<'db, E> for BodyDownload
+where
+    E: EnvironmentKind,
+{
+    fn id(&self) -> StageId {
+        BODIES
+    }
+
+    async fn execute<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: StageInput,
+    ) -> anyhow::Result<ExecOutput>
+    where
+        'db: 'tx,
+    {
+        let original_progress = input.stage_progress.unwrap_or(BlockNumber(0));
+        let target = input
+            .previous_stage
+            .map(|(_, v)| v)
+            .ok_or_else(|| format_err!("Cannot be the first stage"))?;
+
+        if original_progress >= target {
+            return Ok(ExecOutput::Progress {
+                stage_progress: original_progress,
+                done: true,
+            });
+        }
+
+        let (mut messages, mut peer_events) = {
+            let sentry = self.sentry.read().await;
+            (
+                sentry.receive_messages(EthMessageId::BlockBodies)?,
+                sentry.peer_events(),
+            )
+        };
+        let mut requests = BodyRequests::new(
+            original_progress + 1,
+            target,
+            self.batch_size,
+            self.last_request_id,
+        );
+        let mut written = original_progress;
+        let started_at = Instant::now();
+
+        while written < target && started_at.elapsed() < COMMIT_AFTER {
+            {
+                let sentry = self.sentry.read().await;
+                let connected_peers = sentry
+                    .connected_peers()
+                    .read()
+                    .iter()
+                    .copied()
+                    .collect::<Vec<_>>();
+                while requests.in_flight_count() < MAX_IN_FLIGHT {
+                    let (start, len) = match requests.next_batch(written) {
+                        Some(batch) => batch,
+                        None => break,
+                    };
+                    let peer = self
+                        .detector
+                        .next_peer(start, connected_peers.iter().copied());
+                    let peer_filter = peer.map_or(PeerFilter::Random(1), PeerFilter::PeerId);
+                    let request = requests.request(tx, start, len, peer)?;
+                    let request_id = request.request_id;
+                    if let Err(e) =
+                        sentry.try_send_message(Message::GetBlockBodies(request), peer_filter)
+                    {
+                        requests.cancel(request_id);
+                        match e.downcast_ref::<SendMessageError>() {
+                            Some(SendMessageError::SendQueueFull) => break,
+                            _ => return Err(e),
+                        }
+                    }
+                }
+            }
+
+            let mut withheld = Vec::new();
+            tokio::select! {
+                message = messages.next() => match message {
+                    Some(MessageFromPeer {
+                        message: Message::BlockBodies(response),
+                        from_peer_id,
+                        ..
+                    }) => match requests.on_response(tx, response)? {
+                        Some(Response::Accepted { start, delivered }) => {
+                            if let Some(peer_id) = from_peer_id {
+                                if self.detector.record_response(start, peer_id, delivered)
+                                    == RangeStatus::NeedsAlternative
+                                {
+                                    withheld.push(start);
+                                }
+                            }
+                        }
+                        Some(Response::Invalid) => match from_peer_id {
+                            Some(peer_id) => {
+                                warn!("Penalizing peer {:?} for mismatched bodies", peer_id);
+                                self.sentry.read().await.penalize_peer(peer_id).await?;
+                            }
+                            None => warn!("Got mismatched bodies from an unknown peer"),
+                        },
+                        None => {}
+                    },
+                    Some(_) => {}
+                    None => bail!("Sentry stopped delivering block bodies"),
+                },
+                Some(event) = peer_events.next() => match event {
+                    PeerEvent::Disconnected(peer_id) | PeerEvent::Penalized(peer_id) => {
+                        self.detector.remove_peer(peer_id)
+                    }
+                    PeerEvent::SentryDisconnected => self.detector.reset_from(BlockNumber(0)),
+                    PeerEvent::Connected(_) => {}
+                },
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+
+            let expired = requests.expire(REQUEST_TIMEOUT);
+            if !expired.is_empty() {
+                debug!("{} body requests timed out", expired.len());
+            }
+            for (start, peer_id) in expired {
+                if let Some(peer_id) = peer_id {
+                    if self.detector.record_response(start, peer_id, 0)
+                        == RangeStatus::NeedsAlternative
+                    {
+                        withheld.push(start);
+                    }
+                }
+            }
+
+            for start in withheld {
+                self.retrieve_withheld(tx, &mut requests, start).await?;
+            }
+
+            while let Some((hash, body)) = requests.take(written + 1) {
+                chain::block_body::write(tx, BlockKey::new(written + 1, hash), &body)?;
+                written = written + 1;
+            }
+        }
+
+        self.last_request_id = requests.last_request_id;
+
+        if written > original_progress {
+            info!("Downloaded bodies {} to {}", original_progress + 1, written);
+        }
+
+        Ok(ExecOutput::Progress {
+            stage_progress: written,
+            done: written == target,
+        })
+    }
+
+    async fn unwind<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: UnwindInput,
+    ) -> anyhow::Result<UnwindOutput>
+    where
+        'db: 'tx,
+    {
+        self.detector.reset_from(input.unwind_to + 1);
+
+        let mut block_body_cur = tx.cursor(tables::BlockBody)?;
+        let mut block_tx_cur = tx.cursor(tables::BlockTransaction)?;
+        while let Some(((block_num, _), body)) = block_body_cur.last()? {
+            if block_num <= input.unwind_to {
+                break;
+            }
+
+            block_body_cur.delete_current()?;
+
+            let mut deleted = 0;
+            while deleted < body.tx_amount {
+                let to_delete = body.base_tx_id + deleted;
+                // Siblings may share transactions, the range can be gone already.
+                if block_tx_cur.seek_exact(to_delete)?.is_some() {
+                    block_tx_cur.delete_current()?;
+                }
+
+                deleted += 1;
+            }
+        }
+
+        Ok(UnwindOutput {
+            stage_progress: input.unwind_to,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        era1::{file_name, Era1Block, Era1Writer},
+        kv::new_mem_database,
+        sentry::messages::BlockBodyType,
+    };
+
+    /// Body told apart from the others by its ommer.
+    fn body(number: u64) -> BlockBodyType {
+        BlockBodyType {
+            transactions: vec![],
+            ommers: vec![BlockHeader::new(
+                PartialHeader {
+                    number: BlockNumber(number),
+                    ..PartialHeader::empty()
+                },
+                EMPTY_LIST_HASH,
+                EMPTY_ROOT,
+            )],
+        }
+    }
+
+    fn header(number: u64) -> BlockHeader {
+        BlockHeader::new(
+            PartialHeader {
+                number: BlockNumber(number),
+                ..PartialHeader::empty()
+            },
+            Block::ommers_hash(&body(number).ommers),
+            EMPTY_ROOT,
+        )
+    }
+
+    fn write_headers<E: EnvironmentKind>(tx: &MdbxTransaction<'_, RW, E>, numbers: &[u64]) {
+        for &number in numbers {
+            let header = header(number);
+            let hash = header.hash();
+            tx.set(tables::CanonicalHeader, BlockNumber(number), hash)
+                .unwrap();
+            tx.set(tables::Header, (BlockNumber(number), hash), header)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn bodies_are_checked_against_headers() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        write_headers(&tx, &[1, 2, 3]);
+
+        let mut requests = BodyRequests::new(BlockNumber(1), BlockNumber(3), 2, 0);
+        assert_eq!(
+            requests.next_batch(BlockNumber(0)),
+            Some((BlockNumber(1), 2))
+        );
+        let request = requests.request(&tx, BlockNumber(1), 2, None).unwrap();
+        assert_eq!(request.block_hashes.len(), 2);
+
+        // Swapped bodies do not belong to their headers.
+        assert_eq!(
+            requests
+                .on_response(
+                    &tx,
+                    BlockBodiesMessage {
+                        request_id: request.request_id,
+                        block_bodies: vec![body(2), body(1)],
+                    },
+                )
+                .unwrap(),
+            Some(Response::Invalid)
+        );
+        assert!(requests.take(BlockNumber(1)).is_none());
+        assert_eq!(
+            requests.next_batch(BlockNumber(0)),
+            Some((BlockNumber(1), 2))
+        );
+
+        // Only the first of the batch is sent, the other one is asked for again.
+        let request = requests.request(&tx, BlockNumber(1), 2, None).unwrap();
+        assert_eq!(
+            requests
+                .on_response(
+                    &tx,
+                    BlockBodiesMessage {
+                        request_id: request.request_id,
+                        block_bodies: vec![body(1)],
+                    },
+                )
+                .unwrap(),
+            Some(Response::Accepted {
+                start: BlockNumber(1),
+                delivered: 1
+            })
+        );
+        assert_eq!(
+            requests.take(BlockNumber(1)).unwrap().1.ommers,
+            body(1).ommers
+        );
+        assert_eq!(
+            requests.next_batch(BlockNumber(1)),
+            Some((BlockNumber(2), 1))
+        );
+
+        // Answers to requests no longer waited for are ignored.
+        let peer = PeerId::repeat_byte(1);
+        let request = requests
+            .request(&tx, BlockNumber(2), 1, Some(peer))
+            .unwrap();
+        assert_eq!(
+            requests.expire(Duration::ZERO),
+            vec![(BlockNumber(2), Some(peer))]
+        );
+        assert_eq!(
+            requests
+                .on_response(
+                    &tx,
+                    BlockBodiesMessage {
+                        request_id: request.request_id,
+                        block_bodies: vec![body(2)],
+                    },
+                )
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            requests.next_batch(BlockNumber(1)),
+            Some((BlockNumber(2), 1))
+        );
+        assert_eq!(
+            requests.next_batch(BlockNumber(1)),
+            Some((BlockNumber(3), 1))
+        );
+        assert_eq!(requests.next_batch(BlockNumber(1)), None);
+    }
+
+    #[test]
+    fn withheld_bodies_from_era1() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        write_headers(&tx, &[0, 1, 2, 3]);
+
+        let mut out = Vec::new();
+        let mut writer = Era1Writer::new(&mut out).unwrap();
+        for number in 0..=2 {
+            let body = body(number);
+            writer
+                .push(&Era1Block {
+                    header: header(number),
+                    body: BlockBody {
+                        transactions: body.transactions,
+                        ommers: body.ommers,
+                    },
+                    receipts: Default::default(),
+                    total_difficulty: U256::ZERO,
+                })
+                .unwrap();
+        }
+        let root = writer.finish().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(file_name("mainnet", 0, root)), out).unwrap();
+
+        let mut requests = BodyRequests::new(BlockNumber(1), BlockNumber(3), 3, 0);
+        let (start, len) = requests.next_batch(BlockNumber(0)).unwrap();
+        let request = requests.request(&tx, start, len, None).unwrap();
+        assert_eq!(
+            requests
+                .on_response(
+                    &tx,
+                    BlockBodiesMessage {
+                        request_id: request.request_id,
+                        block_bodies: vec![],
+                    },
+                )
+                .unwrap(),
+            Some(Response::Accepted {
+                start: BlockNumber(1),
+                delivered: 0
+            })
+        );
+        assert_eq!(requests.retry_len(BlockNumber(1)), Some(3));
+
+        // The archive ends before the last block of the batch.
+        let hashes = BodyRequests::hashes(&tx, BlockNumber(1), 3).unwrap();
+        let bodies = read_era1_bodies(dir.path(), BlockNumber(1), &hashes).unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(
+            requests.retrieved(&tx, BlockNumber(1), bodies).unwrap(),
+            Response::Accepted {
+                start: BlockNumber(1),
+                delivered: 2
+            }
+        );
+        assert_eq!(
+            requests.take(BlockNumber(2)).unwrap().1.ommers,
+            body(2).ommers
+        );
+        assert_eq!(requests.retry_len(BlockNumber(3)), Some(1));
+
+        // Archives of other epochs are not looked into.
+        assert!(
+            read_era1_bodies(dir.path(), BlockNumber(MAX_ERA1_SIZE as u64), &hashes)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
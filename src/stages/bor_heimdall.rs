@@ -0,0 +1,195 @@
+use crate::{
+    accessors::chain,
+    consensus::bor_sprint_length,
+    downloader::heimdall::HeimdallClient,
+    kv::{mdbx::*, tables},
+    models::*,
+    stagedsync::{stage::*, stages::*},
+    StageId,
+};
+use anyhow::{bail, format_err};
+use async_trait::async_trait;
+use mdbx::{EnvironmentKind, RW};
+use std::collections::VecDeque;
+use tokio::pin;
+use tracing::*;
+
+/// Fetching of Bor spans and state-sync events from Heimdall, for the Bor engine to commit on
+/// execution.
+///
+/// Spans are fetched up to the one after the span of the last block, as the last sprint of a
+/// span commits the next one. The events committed at the start of a sprint are the ones that
+/// follow the last committed event, up to the first one recorded too late: after the start of
+/// the previous sprint or, once a state-sync delay is set, more recently than the delay before
+/// the block.
+#[derive(Debug)]
+pub struct BorHeimdall {
+    pub client: HeimdallClient,
+}
+
+fn header_timestamp<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RW, E>,
+    block_number: BlockNumber,
+) -> anyhow::Result<u64> {
+    let hash = chain::canonical_hash::read(tx, block_number)?
+        .ok_or_else(|| format_err!("No canonical hash found for block {}", block_number))?;
+    Ok(chain::header::read(tx, BlockKey::new(block_number, hash))?
+        .ok_or_else(|| format_err!("Header not found: {}/{:?}", block_number, hash))?
+        .timestamp)
+}
+
+#[async_trait]
+impl<'db, E> Stage<'db, E> for BorHeimdall
+where
+    E: EnvironmentKind,
+{
+    fn id(&self) -> StageId {
+        BOR_HEIMDALL
+    }
+
+    async fn execute<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: StageInput,
+    ) -> anyhow::Result<ExecOutput>
+    where
+        'db: 'tx,
+    {
+        let starting_block = input
+            .stage_progress
+            .map(|v| v + 1)
+            .unwrap_or(BlockNumber(0));
+        let max_block = input
+            .previous_stage
+            .ok_or_else(|| format_err!("Bor Heimdall stage cannot be the first stage"))?
+            .1;
+
+        let chain_spec =
+            chain::chain_config::read(tx)?.ok_or_else(|| format_err!("No chain config"))?;
+        let (sprint, state_sync_delay) = match chain_spec.consensus.seal_verification {
+            SealVerificationParams::Bor {
+                sprint,
+                state_sync_delay,
+                ..
+            } => (sprint, state_sync_delay),
+            _ => bail!("Bor Heimdall stage run on a chain without Bor consensus"),
+        };
+        let chain_id = chain_spec.params.chain_id.0.to_string();
+
+        let mut last_span = chain::bor_span::last(tx)?;
+        while last_span
+            .as_ref()
+            .map(|span| span.start_block <= max_block)
+            .unwrap_or(true)
+        {
+            let id = last_span.as_ref().map(|span| span.id + 1).unwrap_or(0);
+            let span = match self.client.fetch_span(id).await {
+                Ok(span) => span,
+                // Heimdall may not have elected the producers of the next span yet.
+                Err(e)
+                    if last_span
+                        .as_ref()
+                        .map(|span| span.contains(max_block))
+                        .unwrap_or(false) =>
+                {
+                    debug!("Span {} not available yet: {}", id, e);
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            debug!(
+                "Fetched span {} of blocks {}..={}",
+                span.id, span.start_block, span.end_block
+            );
+            tx.set(tables::BorSpan, span.start_block, span.clone())?;
+            last_span = Some(span);
+        }
+
+        let last_event_id = {
+            let walker = tx.cursor(tables::BorStateSyncEvents)?.walk_back(None);
+            pin!(walker);
+
+            let mut last_event_id = 0;
+            while let Some((block_number, events)) = walker.next().transpose()? {
+                if block_number < starting_block {
+                    last_event_id = events.last().map(|event| event.id).unwrap_or_default();
+                    break;
+                }
+            }
+            last_event_id
+        };
+
+        let mut next_id = last_event_id + 1;
+        let mut fetched = VecDeque::<StateSyncEvent>::new();
+        let mut committed = 0;
+        for block_number in starting_block.0..=max_block.0 {
+            let block_number = BlockNumber(block_number);
+            let sprint_length = bor_sprint_length(&sprint, block_number)?;
+            if block_number.0 == 0 || block_number.0 % sprint_length != 0 {
+                continue;
+            }
+
+            let to_time = match state_sync_delay.range(..=block_number).next_back() {
+                Some((_, &delay)) => header_timestamp(tx, block_number)?.saturating_sub(delay),
+                None => header_timestamp(tx, BlockNumber(block_number.0 - sprint_length))?,
+            };
+
+            let mut events = Vec::new();
+            loop {
+                if fetched.is_empty() {
+                    fetched.extend(self.client.fetch_events(next_id, to_time).await?);
+                    if fetched.is_empty() {
+                        break;
+                    }
+                }
+
+                let event = fetched.front().unwrap();
+                if event.id < next_id {
+                    fetched.pop_front();
+                    continue;
+                }
+                if event.id != next_id || event.chain_id != chain_id || event.time >= to_time {
+                    break;
+                }
+
+                events.push(fetched.pop_front().unwrap());
+                next_id += 1;
+            }
+
+            if !events.is_empty() {
+                committed += events.len();
+                tx.set(tables::BorStateSyncEvents, block_number, events)?;
+            }
+        }
+
+        info!("Fetched {} state-sync events", committed);
+
+        Ok(ExecOutput::Progress {
+            stage_progress: max_block,
+            done: true,
+        })
+    }
+
+    /// Spans are kept, they do not depend on the blocks.
+    async fn unwind<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: UnwindInput,
+    ) -> anyhow::Result<UnwindOutput>
+    where
+        'db: 'tx,
+    {
+        let mut cursor = tx.cursor(tables::BorStateSyncEvents)?;
+        while let Some((block_number, _)) = cursor.last()? {
+            if block_number <= input.unwind_to {
+                break;
+            }
+
+            cursor.delete_current()?;
+        }
+
+        Ok(UnwindOutput {
+            stage_progress: input.unwind_to,
+        })
+    }
+}
@@ -104,8 +104,8 @@ where
         flush(&mut froms_collector, &mut froms);
         flush(&mut tos_collector, &mut tos);
 
-        load_call_traces(&mut tx.cursor(tables::CallFromIndex)?, froms_collector)?;
-        load_call_traces(&mut tx.cursor(tables::CallToIndex)?, tos_collector)?;
+        load_address_bitmaps(&mut tx.cursor(tables::CallFromIndex)?, froms_collector)?;
+        load_address_bitmaps(&mut tx.cursor(tables::CallToIndex)?, tos_collector)?;
 
         Ok(ExecOutput::Progress {
             stage_progress: max_block,
@@ -138,12 +138,12 @@ where
             }
         }
 
-        unwind_call_traces(
+        unwind_address_bitmaps(
             &mut tx.cursor(tables::CallFromIndex)?,
             from_addresses,
             input.unwind_to,
         )?;
-        unwind_call_traces(
+        unwind_address_bitmaps(
             &mut tx.cursor(tables::CallToIndex)?,
             to_addresses,
             input.unwind_to,
@@ -155,7 +155,7 @@ where
     }
 }
 
-fn load_call_traces<T>(
+pub(super) fn load_address_bitmaps<T>(
     cursor: &mut MdbxCursor<'_, RW, T>,
     mut collector: Collector<'_, Address, croaring::Treemap>,
 ) -> anyhow::Result<()>
@@ -213,7 +213,7 @@ where
     Ok(())
 }
 
-fn unwind_call_traces<T>(
+pub(super) fn unwind_address_bitmaps<T>(
     cursor: &mut MdbxCursor<'_, RW, T>,
     addresses: BTreeSet<Address>,
     unwind_to: BlockNumber,
@@ -0,0 +1,267 @@
+use crate::{
+    kv::{
+        mdbx::*,
+        tables,
+        write_buffer::{WriteBuffer, DEFAULT_WRITE_BUFFER_CAPACITY},
+    },
+    models::*,
+    stagedsync::{stage::*, stages::*},
+    StageId,
+};
+use anyhow::format_err;
+use async_trait::async_trait;
+use mdbx::{EnvironmentKind, RW};
+use tokio::pin;
+use tracing::*;
+
+/// Generation of contract address => creating transaction mapping, from contract creations
+/// recorded by execution.
+///
+/// A contract re-created at the same address after self-destructing maps to its latest creation,
+/// and loses its entry altogether if that creation is unwound.
+#[derive(Debug)]
+pub struct ContractCreatorIndex;
+
+#[async_trait]
+impl<'db, E> Stage<'db, E> for ContractCreatorIndex
+where
+    E: EnvironmentKind,
+{
+    fn id(&self) -> StageId {
+        CONTRACT_CREATORS
+    }
+
+    async fn execute<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: StageInput,
+    ) -> anyhow::Result<ExecOutput>
+    where
+        'db: 'tx,
+    {
+        let starting_block = input.stage_progress.unwrap_or(BlockNumber(0));
+        let max_block = input
+            .previous_stage
+            .ok_or_else(|| {
+                format_err!("Contract creator index generation cannot be the first stage")
+            })?
+            .1;
+
+        // Contracts are created all over the address space, and re-created at the same addresses
+        // by factories.
+        let mut creators = WriteBuffer::new(
+            tx.cursor(tables::ContractCreator.erased())?,
+            DEFAULT_WRITE_BUFFER_CAPACITY,
+        );
+
+        let walker = tx
+            .cursor(tables::ContractCreation)?
+            .walk(Some((starting_block + 1, TxIndex(0))));
+        pin!(walker);
+
+        let mut indexed = 0;
+        while let Some(((block_number, index), creations)) = walker.next().transpose()? {
+            if block_number > max_block {
+                break;
+            }
+
+            for ContractCreation { address, .. } in creations {
+                creators.upsert(address, (block_number, index))?;
+                indexed += 1;
+            }
+        }
+        creators.flush()?;
+
+        info!("Indexed {} contract creations", indexed);
+
+        Ok(ExecOutput::Progress {
+            stage_progress: max_block,
+            done: true,
+        })
+    }
+
+    async fn unwind<'tx>(
+        &mut self,
+        tx: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: UnwindInput,
+    ) -> anyhow::Result<UnwindOutput>
+    where
+        'db: 'tx,
+    {
+        let mut creator_cursor = tx.cursor(tables::ContractCreator)?;
+
+        let walker = tx
+            .cursor(tables::ContractCreation)?
+            .walk(Some((input.unwind_to + 1, TxIndex(0))));
+        pin!(walker);
+
+        while let Some((_, creations)) = walker.next().transpose()? {
+            for ContractCreation { address, .. } in creations {
+                if let Some((_, (block_number, _))) = creator_cursor.seek_exact(address)? {
+                    if block_number > input.unwind_to {
+                        creator_cursor.delete_current()?;
+                    }
+                }
+            }
+        }
+
+        Ok(UnwindOutput {
+            stage_progress: input.unwind_to,
+        })
+    }
+}
+
+/// Block and index within it of the transaction that created the contract at `address`, along
+/// with the account that executed the creation.
+pub fn read_contract_creator<K, E>(
+    tx: &MdbxTransaction<'_, K, E>,
+    address: Address,
+) -> anyhow::Result<Option<(BlockNumber, TxIndex, Address)>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    if let Some((block_number, index)) = tx.get(tables::ContractCreator, address)? {
+        if let Some(creations) = tx.get(tables::ContractCreation, (block_number, index))? {
+            if let Some(creation) = creations.into_iter().find(|c| c.address == address) {
+                return Ok(Some((block_number, index, creation.creator)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn contract_creators() {
+        let db = crate::kv::new_mem_database().unwrap();
+        let mut tx = db.begin_mutable().unwrap();
+
+        let sender = Address::repeat_byte(0xaa);
+        let factory = Address::repeat_byte(0xbb);
+        let contract = Address::repeat_byte(0x01);
+        let child = Address::repeat_byte(0x02);
+        let recreated = Address::repeat_byte(0x03);
+
+        for (block, index, creations) in [
+            (
+                1,
+                0,
+                vec![ContractCreation {
+                    creator: sender,
+                    address: factory,
+                }],
+            ),
+            (
+                2,
+                3,
+                vec![
+                    ContractCreation {
+                        creator: sender,
+                        address: contract,
+                    },
+                    ContractCreation {
+                        creator: contract,
+                        address: recreated,
+                    },
+                ],
+            ),
+            (
+                5,
+                1,
+                vec![ContractCreation {
+                    creator: factory,
+                    address: child,
+                }],
+            ),
+            (
+                6,
+                0,
+                vec![ContractCreation {
+                    creator: factory,
+                    address: recreated,
+                }],
+            ),
+        ] {
+            tx.set(
+                tables::ContractCreation,
+                (BlockNumber(block), TxIndex(index)),
+                creations,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            ContractCreatorIndex
+                .execute(
+                    &mut tx,
+                    StageInput {
+                        restarted: false,
+                        first_started_at: (Instant::now(), Some(BlockNumber(0))),
+                        previous_stage: Some((EXECUTION, BlockNumber(5))),
+                        stage_progress: None,
+                    },
+                )
+                .await
+                .unwrap(),
+            ExecOutput::Progress {
+                stage_progress: BlockNumber(5),
+                done: true,
+            }
+        );
+
+        assert_eq!(
+            read_contract_creator(&tx, child).unwrap(),
+            Some((BlockNumber(5), TxIndex(1), factory))
+        );
+        assert_eq!(
+            read_contract_creator(&tx, recreated).unwrap(),
+            Some((BlockNumber(2), TxIndex(3), contract))
+        );
+        assert_eq!(read_contract_creator(&tx, sender).unwrap(), None);
+
+        ContractCreatorIndex
+            .execute(
+                &mut tx,
+                StageInput {
+                    restarted: false,
+                    first_started_at: (Instant::now(), Some(BlockNumber(5))),
+                    previous_stage: Some((EXECUTION, BlockNumber(6))),
+                    stage_progress: Some(BlockNumber(5)),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            read_contract_creator(&tx, recreated).unwrap(),
+            Some((BlockNumber(6), TxIndex(0), factory))
+        );
+
+        ContractCreatorIndex
+            .unwind(
+                &mut tx,
+                UnwindInput {
+                    stage_progress: BlockNumber(6),
+                    unwind_to: BlockNumber(4),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(read_contract_creator(&tx, child).unwrap(), None);
+        assert_eq!(read_contract_creator(&tx, recreated).unwrap(), None);
+        assert_eq!(
+            read_contract_creator(&tx, contract).unwrap(),
+            Some((BlockNumber(2), TxIndex(3), sender))
+        );
+        assert_eq!(
+            read_contract_creator(&tx, factory).unwrap(),
+            Some((BlockNumber(1), TxIndex(0), sender))
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use crate::{
+    accessors,
     downloader::{
-        sentry_status_provider::SentryStatusProvider, ui::ui_system::UISystem, HeadersDownloader,
-        HeadersDownloaderRunState,
+        sentry_status_provider::SentryStatusProvider,
+        ui::ui_system::{UIMode, UISystem},
+        HeaderSlicesProgress, HeadersDownloader, HeadersDownloaderRunState,
     },
     kv::mdbx::*,
     models::BlockNumber,
@@ -11,7 +13,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use std::sync::Arc;
-use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::{watch, Mutex as AsyncMutex};
 
 /// Download of headers
 #[derive(Debug)]
@@ -19,6 +21,7 @@ pub struct HeaderDownload {
     downloader: HeadersDownloader,
     batch_size: usize,
     sentry_status_provider: SentryStatusProvider,
+    ui_mode: UIMode,
     previous_run_state: Arc<AsyncMutex<Option<HeadersDownloaderRunState>>>,
 }
 
@@ -29,6 +32,7 @@ impl HeaderDownload {
         batch_size: usize,
         sentry: SentryClientReactorShared,
         sentry_status_provider: SentryStatusProvider,
+        ui_mode: UIMode,
     ) -> anyhow::Result<Self> {
         let verifier = crate::downloader::header_slice_verifier::make_ethash_verifier();
 
@@ -38,11 +42,23 @@ impl HeaderDownload {
             downloader,
             batch_size,
             sentry_status_provider,
+            ui_mode,
             previous_run_state: Arc::new(AsyncMutex::new(None)),
         };
         Ok(instance)
     }
 
+    /// Header download progress, for reporting sync status outside of the stage.
+    pub fn watch_progress(&self) -> watch::Receiver<HeaderSlicesProgress> {
+        self.downloader.watch_progress()
+    }
+
+    /// Top block estimated from peer announcements, as soon as it changes rather than when the
+    /// stage commits.
+    pub fn watch_top_block_estimate(&self) -> watch::Receiver<Option<BlockNumber>> {
+        self.downloader.watch_top_block_estimate()
+    }
+
     async fn load_previous_run_state(&self) -> Option<HeadersDownloaderRunState> {
         self.previous_run_state.lock().await.clone()
     }
@@ -84,7 +100,7 @@ where
 
         let previous_run_state = self.load_previous_run_state().await;
 
-        let mut ui_system = UISystem::new();
+        let mut ui_system = UISystem::new(self.ui_mode);
         ui_system.start()?;
         let ui_system = Arc::new(AsyncMutex::new(ui_system));
 
@@ -107,6 +123,10 @@ where
             return Ok(ExecOutput::Unwind { unwind_to });
         }
 
+        if let Some(estimated_top_block_num) = report.run_state.estimated_top_block_num {
+            accessors::chain::top_block_estimate::write(tx, estimated_top_block_num)?;
+        }
+
         self.save_run_state(report.run_state).await;
 
         let final_block_num = report.final_block_num.0;
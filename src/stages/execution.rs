@@ -1,10 +1,11 @@
 use crate::{
-    accessors,
-    consensus::engine_factory,
+    accessors::{self, chain::last_forkchoice, prune::PruneTarget},
+    consensus::{engine_factory, ValidationError},
     execution::{
         analysis_cache::AnalysisCache,
+        gas_audit::audit_block_gas,
         processor::ExecutionProcessor,
-        tracer::{CallTracer, CallTracerFlags},
+        tracer::{CallTracer, CallTracerFlags, CreationTracer, TransferTracer},
     },
     h256_to_u256,
     kv::{
@@ -18,9 +19,81 @@ use crate::{
 use anyhow::{format_err, Context};
 use async_trait::async_trait;
 use mdbx::{EnvironmentKind, RW};
+use once_cell::sync::Lazy;
+use prometheus::{register_counter, register_int_counter, Counter, IntCounter};
 use std::time::{Duration, Instant};
 use tracing::*;
 
+static EXECUTED_GAS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("execution_gas_total", "Gas used by executed blocks").unwrap()
+});
+
+static THROTTLED_SECONDS: Lazy<Counter> = Lazy::new(|| {
+    register_counter!(
+        "execution_throttled_seconds_total",
+        "Time the execution stage spent sleeping to stay within its throttle"
+    )
+    .unwrap()
+});
+
+/// Pauses shorter than this are not worth a sleep.
+const MIN_THROTTLE_PAUSE: Duration = Duration::from_millis(10);
+
+/// Limit on how fast blocks are executed, to leave resources to other services on the machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExecutionThrottle {
+    /// Gas executed per second.
+    GasPerSecond(u64),
+    /// Share of wall time, in percent, spent executing blocks.
+    CpuPercent(u8),
+}
+
+/// Tracks the work done during a batch against an [`ExecutionThrottle`].
+#[derive(Debug)]
+struct Throttler {
+    throttle: ExecutionThrottle,
+    started_at: Instant,
+    gas: u64,
+    busy: Duration,
+}
+
+impl Throttler {
+    fn new(throttle: ExecutionThrottle) -> Self {
+        Self {
+            throttle,
+            started_at: Instant::now(),
+            gas: 0,
+            busy: Duration::ZERO,
+        }
+    }
+
+    /// Account for a block that used `gas` and took `busy` to execute, and return how long to
+    /// pause to get back within the throttle, `elapsed` after the batch started.
+    fn record(&mut self, gas: u64, busy: Duration, elapsed: Duration) -> Option<Duration> {
+        self.gas += gas;
+        self.busy += busy;
+
+        let due = match self.throttle {
+            ExecutionThrottle::GasPerSecond(rate) => {
+                Duration::from_secs_f64(self.gas as f64 / rate.max(1) as f64)
+            }
+            ExecutionThrottle::CpuPercent(percent) => {
+                self.busy * 100 / u32::from(percent.clamp(1, 100))
+            }
+        };
+
+        due.checked_sub(elapsed)
+            .filter(|&pause| pause >= MIN_THROTTLE_PAUSE)
+    }
+
+    fn block_done(&mut self, gas: u64, busy: Duration) {
+        if let Some(pause) = self.record(gas, busy, self.started_at.elapsed()) {
+            THROTTLED_SECONDS.inc_by(pause.as_secs_f64());
+            std::thread::sleep(pause);
+        }
+    }
+}
+
 /// Execution of blocks through EVM
 #[derive(Debug)]
 pub struct Execution {
@@ -30,6 +103,9 @@ pub struct Execution {
     pub batch_until: Option<BlockNumber>,
     pub commit_every: Option<Duration>,
     pub prune_from: BlockNumber,
+    /// Record value transfers made inside contract execution into [`tables::InternalTransfer`].
+    pub index_internal_transfers: bool,
+    pub throttle: Option<ExecutionThrottle>,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -44,10 +120,13 @@ fn execute_batch_of_blocks<E: EnvironmentKind>(
     starting_block: BlockNumber,
     first_started_at: (Instant, Option<BlockNumber>),
     prune_from: BlockNumber,
+    index_internal_transfers: bool,
+    throttle: Option<ExecutionThrottle>,
 ) -> anyhow::Result<BlockNumber> {
     let mut buffer = Buffer::new(tx, prune_from, None);
     let mut consensus_engine = engine_factory(chain_config.clone())?;
     let mut analysis_cache = AnalysisCache::default();
+    let mut block_specs = BlockSpecCache::new(chain_config);
 
     let mut block_number = starting_block;
     let mut gas_since_start = 0;
@@ -62,57 +141,121 @@ fn execute_batch_of_blocks<E: EnvironmentKind>(
         .unwrap();
     let mut last_message = Instant::now();
     let mut printed_at_least_once = false;
+    let mut throttler = throttle.map(Throttler::new);
     loop {
+        let block_started_at = Instant::now();
         let block_hash = tx
             .get(tables::CanonicalHeader, block_number)?
             .ok_or_else(|| format_err!("No canonical hash found for block {}", block_number))?;
-        let header = tx
+        let block_header = tx
             .get(tables::Header, (block_number, block_hash))?
-            .ok_or_else(|| format_err!("Header not found: {}/{:?}", block_number, block_hash))?
-            .into();
-        let block = accessors::chain::block_body::read_with_senders(tx, block_hash, block_number)?
-            .ok_or_else(|| {
-                format_err!("Block body not found: {}/{:?}", block_number, block_hash)
-            })?;
+            .ok_or_else(|| format_err!("Header not found: {}/{:?}", block_number, block_hash))?;
+        let block = accessors::chain::block_body::read_with_senders(
+            tx,
+            BlockKey::new(block_number, block_hash),
+        )?
+        .ok_or_else(|| format_err!("Block body not found: {}/{:?}", block_number, block_hash))?;
+
+        if !block.ommers.is_empty() {
+            consensus_engine
+                .validate_ommers(&block_header, &block.ommers, &mut buffer)
+                .with_context(|| {
+                    format!(
+                        "Invalid ommers in block #{} ({:?})",
+                        block_number, block_hash
+                    )
+                })?;
+        }
+        let header = block_header.into();
 
-        let block_spec = chain_config.collect_block_spec(block_number);
+        let block_spec = block_specs.get(block_number);
 
         let mut call_tracer = CallTracer::default();
+        let mut creation_tracer = CreationTracer::default();
+        let mut transfer_tracer = index_internal_transfers.then(TransferTracer::default);
+        let mut tracer = (
+            &mut call_tracer,
+            (&mut creation_tracer, transfer_tracer.as_mut()),
+        );
         let receipts = ExecutionProcessor::new(
             &mut buffer,
-            Some(&mut call_tracer),
+            Some(&mut tracer),
             &mut analysis_cache,
             &mut *consensus_engine,
             &header,
             &block,
             &block_spec,
         )
-        .execute_and_write_block()
-        .with_context(|| {
-            format!(
-                "Failed to execute block #{} ({:?})",
-                block_number, block_hash
-            )
-        })?;
+        .execute_and_write_block();
+
+        let receipts = match receipts {
+            Ok(receipts) => receipts,
+            Err(e) => {
+                let context = format!(
+                    "Failed to execute block #{} ({:?})",
+                    block_number, block_hash
+                );
+                // Nothing of the block reached the buffer, execute it again to see where its gas
+                // went.
+                if let Some(ValidationError::WrongBlockGas { .. }) = e.downcast_ref() {
+                    let report = audit_block_gas(
+                        &mut buffer,
+                        &mut analysis_cache,
+                        &mut *consensus_engine,
+                        &header,
+                        block_hash,
+                        &block,
+                        &block_spec,
+                    )
+                    .with_context(|| context.clone())?;
+                    error!("Gas used diverges from header: {}", report);
+                    return Err(anyhow::Error::new(report).context(context));
+                }
+                return Err(e.context(context));
+            }
+        };
 
         buffer.insert_receipts(block_number, receipts);
 
-        {
+        for (code_hash, analysis) in analysis_cache.take_unpersisted() {
+            tx.set(tables::CodeAnalysis, code_hash, analysis)?;
+        }
+
+        if block_number >= prune_from {
             let mut c = tx.cursor(tables::CallTraceSet)?;
             for (address, CallTracerFlags { from, to }) in call_tracer.into_sorted_iter() {
                 c.append_dup(header.number, CallTraceSetEntry { address, from, to })?;
             }
         }
 
+        {
+            let mut c = tx.cursor(tables::ContractCreation)?;
+            for (index, creations) in creation_tracer.into_creations() {
+                c.append((header.number, index), creations)?;
+            }
+        }
+
+        if let Some(transfer_tracer) = transfer_tracer {
+            let mut c = tx.cursor(tables::InternalTransfer)?;
+            for (index, transfers) in transfer_tracer.into_transfers() {
+                c.append((header.number, index), transfers)?;
+            }
+        }
+
         gas_since_start += header.gas_used;
         gas_since_last_message += header.gas_used;
         gas_since_history_commit += header.gas_used;
+        EXECUTED_GAS.inc_by(header.gas_used);
 
         if gas_since_history_commit >= history_batch_size {
             buffer.write_history()?;
             gas_since_history_commit = 0;
         }
 
+        if let Some(throttler) = &mut throttler {
+            throttler.block_done(header.gas_used, block_started_at.elapsed());
+        }
+
         let now = Instant::now();
 
         let stage_complete = block_number == max_block;
@@ -179,6 +322,154 @@ fn execute_batch_of_blocks<E: EnvironmentKind>(
     Ok(block_number)
 }
 
+/// Reverts state, logs, call trace sets, contract creations and internal transfers of blocks
+/// after `unwind_to`.
+fn unwind_state<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RW, E>,
+    unwind_to: BlockNumber,
+) -> anyhow::Result<()> {
+    info!("Unwinding accounts");
+    let mut account_cursor = tx.cursor(tables::Account)?;
+
+    let mut account_cs_cursor = tx.cursor(tables::AccountChangeSet)?;
+
+    while let Some((block_number, tables::AccountChange { address, account })) =
+        account_cs_cursor.last()?
+    {
+        if block_number <= unwind_to {
+            break;
+        }
+
+        if let Some(account) = account {
+            account_cursor.put(address, account)?;
+        } else if account_cursor.seek(address)?.is_some() {
+            account_cursor.delete_current()?;
+        }
+
+        account_cs_cursor.delete_current()?;
+    }
+
+    info!("Unwinding storage");
+    let mut storage_cursor = tx.cursor(tables::Storage)?;
+
+    let mut storage_cs_cursor = tx.cursor(tables::StorageChangeSet)?;
+
+    while let Some((
+        tables::StorageChangeKey {
+            block_number,
+            address,
+        },
+        tables::StorageChange { location, value },
+    )) = storage_cs_cursor.last()?
+    {
+        if block_number <= unwind_to {
+            break;
+        }
+
+        upsert_storage_value(&mut storage_cursor, address, h256_to_u256(location), value)?;
+
+        storage_cs_cursor.delete_current()?;
+    }
+
+    info!("Unwinding logs");
+    let mut log_cursor = tx.cursor(tables::Log)?;
+    while let Some(((block_number, _), _)) = log_cursor.last()? {
+        if block_number <= unwind_to {
+            break;
+        }
+
+        log_cursor.delete_current()?;
+    }
+
+    info!("Unwinding call trace sets");
+    let mut call_trace_set_cursor = tx.cursor(tables::CallTraceSet)?;
+    while let Some((block_number, _)) = call_trace_set_cursor.last()? {
+        if block_number <= unwind_to {
+            break;
+        }
+
+        call_trace_set_cursor.delete_current_duplicates()?;
+    }
+
+    info!("Unwinding contract creations");
+    let mut contract_creation_cursor = tx.cursor(tables::ContractCreation)?;
+    while let Some(((block_number, _), _)) = contract_creation_cursor.last()? {
+        if block_number <= unwind_to {
+            break;
+        }
+
+        contract_creation_cursor.delete_current()?;
+    }
+
+    info!("Unwinding internal transfers");
+    let mut internal_transfer_cursor = tx.cursor(tables::InternalTransfer)?;
+    while let Some(((block_number, _), _)) = internal_transfer_cursor.last()? {
+        if block_number <= unwind_to {
+            break;
+        }
+
+        internal_transfer_cursor.delete_current()?;
+    }
+
+    Ok(())
+}
+
+/// Highest block with state changes written by execution.
+fn last_executed_block<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RW, E>,
+) -> anyhow::Result<BlockNumber> {
+    Ok([
+        tx.cursor(tables::AccountChangeSet)?
+            .last()?
+            .map(|(block_number, _)| block_number),
+        tx.cursor(tables::StorageChangeSet)?
+            .last()?
+            .map(|(key, _)| key.block_number),
+        tx.cursor(tables::Log)?
+            .last()?
+            .map(|((block_number, _), _)| block_number),
+        tx.cursor(tables::CallTraceSet)?
+            .last()?
+            .map(|(block_number, _)| block_number),
+        tx.cursor(tables::ContractCreation)?
+            .last()?
+            .map(|((block_number, _), _)| block_number),
+        tx.cursor(tables::InternalTransfer)?
+            .last()?
+            .map(|((block_number, _), _)| block_number),
+    ]
+    .into_iter()
+    .flatten()
+    .max()
+    .unwrap_or_default())
+}
+
+/// Rolls state back to the last block the Execution stage recorded as done, if there are
+/// changes past it. Returns the block state was rolled back to.
+///
+/// Executed state and stage progress are committed together, but with the history tables kept
+/// in an environment of their own, their commit lands first: a crash before the main one leaves
+/// changesets of blocks the stage never recorded as done. Undoing them is safe whether or not the
+/// state they revert was committed, as they only restore the values of the blocks before.
+pub fn recover_interrupted_execution<E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, RW, E>,
+) -> anyhow::Result<Option<BlockNumber>> {
+    let progress = EXECUTION.get_progress(tx)?.unwrap_or_default();
+    let last_executed = last_executed_block(tx)?;
+
+    if last_executed <= progress {
+        return Ok(None);
+    }
+
+    warn!(
+        "Execution was interrupted (changes up to block {}), rolling state back to block {}",
+        last_executed, progress
+    );
+    unwind_state(tx, progress)?;
+
+    Ok(Some(progress))
+}
+
 #[async_trait]
 impl<'db, E> Stage<'db, E> for Execution
 where
@@ -198,19 +489,33 @@ where
     {
         let _ = tx;
 
-        let genesis_hash = tx
-            .get(tables::CanonicalHeader, BlockNumber(0))?
-            .ok_or_else(|| format_err!("Genesis block absent"))?;
-        let chain_config = tx
-            .get(tables::Config, genesis_hash)?
-            .ok_or_else(|| format_err!("No chain config for genesis block {:?}", genesis_hash))?;
+        let chain_config = accessors::chain::chain_config::read(tx)?
+            .ok_or_else(|| format_err!("No chain config"))?;
 
         let prev_progress = input.stage_progress.unwrap_or_default();
         let starting_block = prev_progress + 1;
         let max_block = input
             .previous_stage.ok_or_else(|| format_err!("Execution stage cannot be executed first, but no previous stage progress specified"))?.1;
 
+        // Changesets of blocks that are not finalized yet are kept, they may still be unwound.
+        let prune_from = match last_forkchoice::read_canonical_number(
+            tx,
+            last_forkchoice::FINALIZED_BLOCK_HASH,
+        )? {
+            Some(finalized) => std::cmp::min(self.prune_from, finalized + 1),
+            None => self.prune_from,
+        };
+
         Ok(if max_block >= starting_block {
+            if starting_block < prune_from {
+                // Call trace sets are left out along with changesets.
+                for target in [PruneTarget::History, PruneTarget::CallTraces] {
+                    if accessors::prune::read(tx, target)?.unwrap_or_default() < prune_from {
+                        accessors::prune::write(tx, target, prune_from)?;
+                    }
+                }
+            }
+
             let executed_to = execute_batch_of_blocks(
                 tx,
                 chain_config,
@@ -221,7 +526,9 @@ where
                 self.commit_every,
                 starting_block,
                 input.first_started_at,
-                self.prune_from,
+                prune_from,
+                self.index_internal_transfers,
+                self.throttle,
             )?;
 
             let done = executed_to == max_block || self.exit_after_batch;
@@ -246,71 +553,176 @@ where
     where
         'db: 'tx,
     {
-        info!("Unwinding accounts");
-        let mut account_cursor = tx.cursor(tables::Account)?;
-
-        let mut account_cs_cursor = tx.cursor(tables::AccountChangeSet)?;
-
-        while let Some((block_number, tables::AccountChange { address, account })) =
-            account_cs_cursor.last()?
-        {
-            if block_number <= input.unwind_to {
-                break;
-            }
-
-            if let Some(account) = account {
-                account_cursor.put(address, account)?;
-            } else if account_cursor.seek(address)?.is_some() {
-                account_cursor.delete_current()?;
-            }
-
-            account_cs_cursor.delete_current()?;
-        }
+        unwind_state(tx, input.unwind_to)?;
 
-        info!("Unwinding storage");
-        let mut storage_cursor = tx.cursor(tables::Storage)?;
+        Ok(UnwindOutput {
+            stage_progress: input.unwind_to,
+        })
+    }
+}
 
-        let mut storage_cs_cursor = tx.cursor(tables::StorageChangeSet)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::new_mem_database;
+
+    #[test]
+    fn throttle() {
+        let ms = Duration::from_millis;
+
+        let mut throttler = Throttler::new(ExecutionThrottle::GasPerSecond(10_000_000));
+        assert_eq!(throttler.record(5_000_000, ms(100), ms(100)), Some(ms(400)));
+        assert_eq!(throttler.record(5_000_000, ms(100), ms(600)), Some(ms(400)));
+        assert_eq!(throttler.record(1_000_000, ms(100), ms(1200)), None);
+
+        let mut throttler = Throttler::new(ExecutionThrottle::CpuPercent(25));
+        assert_eq!(throttler.record(0, ms(100), ms(100)), Some(ms(300)));
+        assert_eq!(throttler.record(0, ms(100), ms(500)), Some(ms(300)));
+        // Pauses too short to sleep for are caught up on later.
+        assert_eq!(throttler.record(0, ms(1), ms(803)), None);
+        assert_eq!(throttler.record(0, ms(10), ms(813)), Some(ms(31)));
+
+        let mut throttler = Throttler::new(ExecutionThrottle::CpuPercent(100));
+        assert_eq!(throttler.record(0, ms(100), ms(100)), None);
+    }
 
-        while let Some((
-            tables::StorageChangeKey {
-                block_number,
-                address,
+    #[test]
+    fn recover_rolls_back_changes_past_progress() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let existing = Address::repeat_byte(1);
+        let created = Address::repeat_byte(2);
+        let before = Account {
+            nonce: 1,
+            ..Default::default()
+        };
+        let after = Account {
+            nonce: 2,
+            ..Default::default()
+        };
+
+        EXECUTION.save_progress(&tx, BlockNumber(1)).unwrap();
+        assert_eq!(recover_interrupted_execution(&tx).unwrap(), None);
+
+        // Block 2 executed, but its progress was never recorded.
+        tx.set(tables::Account, existing, after).unwrap();
+        tx.set(tables::Account, created, after).unwrap();
+        tx.set(
+            tables::AccountChangeSet,
+            BlockNumber(2),
+            tables::AccountChange {
+                address: existing,
+                account: Some(before),
             },
-            tables::StorageChange { location, value },
-        )) = storage_cs_cursor.last()?
-        {
-            if block_number <= input.unwind_to {
-                break;
-            }
+        )
+        .unwrap();
+        tx.set(
+            tables::AccountChangeSet,
+            BlockNumber(2),
+            tables::AccountChange {
+                address: created,
+                account: None,
+            },
+        )
+        .unwrap();
 
-            upsert_storage_value(&mut storage_cursor, address, h256_to_u256(location), value)?;
+        assert_eq!(
+            recover_interrupted_execution(&tx).unwrap(),
+            Some(BlockNumber(1))
+        );
+        assert_eq!(tx.get(tables::Account, existing).unwrap(), Some(before));
+        assert_eq!(tx.get(tables::Account, created).unwrap(), None);
+        assert_eq!(recover_interrupted_execution(&tx).unwrap(), None);
+    }
 
-            storage_cs_cursor.delete_current()?;
-        }
+    #[tokio::test]
+    async fn recover_rolls_back_executed_block() {
+        let db = new_mem_database().unwrap();
+        let mut tx = db.begin_mutable().unwrap();
 
-        info!("Unwinding logs");
-        let mut log_cursor = tx.cursor(tables::Log)?;
-        while let Some(((block_number, _), _)) = log_cursor.last()? {
-            if block_number <= input.unwind_to {
-                break;
-            }
+        let chain_spec = crate::res::chainspec::MAINNET.clone();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        crate::state::genesis::initialize_genesis(&tx, &temp_dir, chain_spec).unwrap();
+        EXECUTION.save_progress(&tx, BlockNumber(0)).unwrap();
 
-            log_cursor.delete_current()?;
+        let genesis_hash = tx
+            .get(tables::CanonicalHeader, BlockNumber(0))
+            .unwrap()
+            .unwrap();
+        let genesis = tx
+            .get(tables::Header, (BlockNumber(0), genesis_hash))
+            .unwrap()
+            .unwrap();
+
+        // An empty block, which only pays its miner.
+        let miner = Address::repeat_byte(0xee);
+        let header = BlockHeader {
+            parent_hash: genesis_hash,
+            beneficiary: miner,
+            number: BlockNumber(1),
+            gas_limit: genesis.gas_limit,
+            timestamp: genesis.timestamp + 15,
+            ..genesis
+        };
+        let hash = header.hash();
+        tx.set(tables::Header, (BlockNumber(1), hash), header)
+            .unwrap();
+        tx.set(tables::CanonicalHeader, BlockNumber(1), hash)
+            .unwrap();
+        tx.set(tables::HeaderNumber, hash, BlockNumber(1)).unwrap();
+        tx.set(
+            tables::BlockBody,
+            (BlockNumber(1), hash),
+            BodyForStorage {
+                base_tx_id: 0.into(),
+                tx_amount: 0,
+                uncles: vec![],
+            },
+        )
+        .unwrap();
+        tx.set(tables::TotalGas, BlockNumber(1), 0).unwrap();
+
+        let output = Execution {
+            batch_size: u64::MAX,
+            history_batch_size: u64::MAX,
+            exit_after_batch: false,
+            batch_until: None,
+            commit_every: None,
+            prune_from: BlockNumber(0),
+            index_internal_transfers: false,
+            throttle: None,
         }
-
-        info!("Unwinding call trace sets");
-        let mut call_trace_set_cursor = tx.cursor(tables::CallTraceSet)?;
-        while let Some((block_number, _)) = call_trace_set_cursor.last()? {
-            if block_number <= input.unwind_to {
-                break;
+        .execute(
+            &mut tx,
+            StageInput {
+                restarted: false,
+                first_started_at: (Instant::now(), None),
+                previous_stage: Some((crate::stagedsync::stages::SENDERS, BlockNumber(1))),
+                stage_progress: Some(BlockNumber(0)),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            output,
+            ExecOutput::Progress {
+                stage_progress: BlockNumber(1),
+                done: true,
             }
-
-            call_trace_set_cursor.delete_current_duplicates()?;
-        }
-
-        Ok(UnwindOutput {
-            stage_progress: input.unwind_to,
-        })
+        );
+        assert!(tx.get(tables::Account, miner).unwrap().is_some());
+
+        // Crashed before the progress of the stage was saved.
+        assert_eq!(
+            recover_interrupted_execution(&tx).unwrap(),
+            Some(BlockNumber(0))
+        );
+        assert_eq!(tx.get(tables::Account, miner).unwrap(), None);
+        assert_eq!(
+            tx.cursor(tables::AccountChangeSet).unwrap().last().unwrap(),
+            None
+        );
+        assert_eq!(recover_interrupted_execution(&tx).unwrap(), None);
     }
 }
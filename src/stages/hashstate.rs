@@ -1,20 +1,24 @@
 use crate::{
-    crypto::keccak256,
+    crypto::{keccak, keccak256},
     etl::collector::*,
+    h256_to_u256,
     kv::{mdbx::*, tables},
     models::*,
     stagedsync::{stage::*, stages::*},
     stages::stage_util::should_do_clean_promotion,
-    upsert_hashed_storage_value,
+    u256_to_h256, upsert_hashed_storage_value,
 };
 use anyhow::format_err;
 use async_trait::async_trait;
-use mdbx::{EnvironmentKind, RW};
-use std::sync::Arc;
+use mdbx::{EnvironmentKind, TransactionKind, RW};
+use std::{cmp::Ordering, sync::Arc};
 use tempfile::TempDir;
 use tokio::pin;
 use tracing::*;
 
+/// Entries read from plain state before their keys are hashed as one batch.
+const HASH_BATCH_SIZE: usize = 65_536;
+
 pub fn promote_clean_accounts<'db, E>(
     txn: &MdbxTransaction<'db, RW, E>,
     temp_dir: &TempDir,
@@ -30,14 +34,28 @@ where
     let mut src = txn.cursor(tables::Account)?;
     src.first()?;
     let mut i = 0;
+    let mut batch = Vec::with_capacity(HASH_BATCH_SIZE);
     let walker = src.walk(None);
     pin!(walker);
-    while let Some((address, account)) = walker.next().transpose()? {
-        collector_account.push(keccak256(address), account);
+    loop {
+        let entry = walker.next().transpose()?;
+        let done = entry.is_none();
+        batch.extend(entry);
+
+        if batch.len() == HASH_BATCH_SIZE || done {
+            let hashes = keccak::hash_batch_by(&batch, |(address, _)| *address);
+            for (hashed_address, (_, account)) in hashes.into_iter().zip(batch.drain(..)) {
+                collector_account.push(hashed_address, account);
+
+                i += 1;
+                if i % 5_000_000 == 0 {
+                    debug!("Converted {} entries", i);
+                }
+            }
+        }
 
-        i += 1;
-        if i % 5_000_000 == 0 {
-            debug!("Converted {} entries", i);
+        if done {
+            break;
         }
     }
 
@@ -63,14 +81,39 @@ where
     let mut src = txn.cursor(tables::Storage)?;
     src.first()?;
     let mut i = 0;
+    let mut batch = Vec::with_capacity(HASH_BATCH_SIZE);
+    // Storage is sorted by address, so consecutive slots share the address hash.
+    let mut last_address_hash = None;
     let walker = src.walk(None);
     pin!(walker);
-    while let Some((address, (location, value))) = walker.next().transpose()? {
-        collector_storage.push(keccak256(address), (keccak256(location), value));
+    loop {
+        let entry = walker.next().transpose()?;
+        let done = entry.is_none();
+        batch.extend(entry);
+
+        if batch.len() == HASH_BATCH_SIZE || done {
+            let hashes = keccak::hash_batch_by(&batch, |(_, (location, _))| *location);
+            for (hashed_location, (address, (_, value))) in hashes.into_iter().zip(batch.drain(..))
+            {
+                let hashed_address = match last_address_hash {
+                    Some((last, hash)) if last == address => hash,
+                    _ => {
+                        let hash = keccak256(address);
+                        last_address_hash = Some((address, hash));
+                        hash
+                    }
+                };
+                collector_storage.push(hashed_address, (hashed_location, value));
+
+                i += 1;
+                if i % 5_000_000 == 0 {
+                    debug!("Converted {} entries", i);
+                }
+            }
+        }
 
-        i += 1;
-        if i % 5_000_000 == 0 {
-            debug!("Converted {} entries", i);
+        if done {
+            break;
         }
     }
 
@@ -143,6 +186,236 @@ where
     Ok(())
 }
 
+/// Entry on which the hashed state disagrees with the plain state.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HashedStateMismatch {
+    /// Plain account whose hashed copy is missing or differs.
+    Account {
+        address: Address,
+        plain: Account,
+        hashed: Option<Account>,
+    },
+    /// Hashed account that no plain account hashes to.
+    OrphanedHashedAccount {
+        hashed_address: H256,
+        account: Account,
+    },
+    /// Plain storage slot whose hashed copy is missing or differs.
+    Storage {
+        address: Address,
+        location: H256,
+        plain: U256,
+        hashed: Option<U256>,
+    },
+    /// Hashed storage slot that no plain storage slot hashes to.
+    OrphanedHashedStorage {
+        hashed_address: H256,
+        hashed_location: H256,
+        value: U256,
+    },
+}
+
+/// Walk two iterators sorted by key side by side, calling `f` once per key with the entries of both.
+fn merge_sorted<Key, A, B>(
+    mut a: impl Iterator<Item = anyhow::Result<(Key, A)>>,
+    mut b: impl Iterator<Item = anyhow::Result<(Key, B)>>,
+    mut f: impl FnMut(Key, Option<A>, Option<B>) -> anyhow::Result<()>,
+) -> anyhow::Result<()>
+where
+    Key: Ord,
+{
+    let mut next_a = a.next().transpose()?;
+    let mut next_b = b.next().transpose()?;
+    loop {
+        match (next_a.take(), next_b.take()) {
+            (Some((ka, va)), Some((kb, vb))) => match ka.cmp(&kb) {
+                Ordering::Less => {
+                    f(ka, Some(va), None)?;
+                    next_a = a.next().transpose()?;
+                    next_b = Some((kb, vb));
+                }
+                Ordering::Greater => {
+                    f(kb, None, Some(vb))?;
+                    next_a = Some((ka, va));
+                    next_b = b.next().transpose()?;
+                }
+                Ordering::Equal => {
+                    f(ka, Some(va), Some(vb))?;
+                    next_a = a.next().transpose()?;
+                    next_b = b.next().transpose()?;
+                }
+            },
+            (Some((ka, va)), None) => {
+                f(ka, Some(va), None)?;
+                next_a = a.next().transpose()?;
+            }
+            (None, Some((kb, vb))) => {
+                f(kb, None, Some(vb))?;
+                next_b = b.next().transpose()?;
+            }
+            (None, None) => return Ok(()),
+        }
+    }
+}
+
+/// Cross-check `HashedAccount` and `HashedStorage` against the plain state they are promoted from.
+///
+/// Plain keys are hashed and sorted through ETL, so both sides are walked once in hashed key order
+/// without holding either of them in memory.
+pub fn check_hashed_state<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    temp_dir: &TempDir,
+) -> anyhow::Result<Vec<HashedStateMismatch>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut out = vec![];
+
+    let mut plain_accounts = Collector::<H256, Address>::new(temp_dir, OPTIMAL_BUFFER_CAPACITY);
+    for res in txn.cursor(tables::Account)?.walk(None) {
+        let (address, _) = res?;
+        plain_accounts.push(keccak256(address), address);
+    }
+
+    debug!("Checking hashed accounts");
+    merge_sorted(
+        plain_accounts
+            .iter()
+            .map(|res| res.map(|(k, v)| (H256::from_slice(&k), Address::from_slice(&v)))),
+        txn.cursor(tables::HashedAccount)?.walk(None),
+        |hashed_address, address, hashed| {
+            match address {
+                Some(address) => {
+                    let plain = txn
+                        .get(tables::Account, address)?
+                        .ok_or_else(|| format_err!("plain account {:?} disappeared", address))?;
+                    if hashed != Some(plain) {
+                        out.push(HashedStateMismatch::Account {
+                            address,
+                            plain,
+                            hashed,
+                        });
+                    }
+                }
+                None => out.push(HashedStateMismatch::OrphanedHashedAccount {
+                    hashed_address,
+                    account: hashed.unwrap(),
+                }),
+            }
+            Ok(())
+        },
+    )?;
+
+    let mut plain_storage = Collector::<Vec<u8>, Vec<u8>>::new(temp_dir, OPTIMAL_BUFFER_CAPACITY);
+    for res in txn.cursor(tables::Storage)?.walk(None) {
+        let (address, (location, value)) = res?;
+        plain_storage.push(
+            [
+                keccak256(address).as_bytes(),
+                keccak256(location).as_bytes(),
+            ]
+            .concat(),
+            [
+                address.as_bytes(),
+                location.as_bytes(),
+                u256_to_h256(value).as_bytes(),
+            ]
+            .concat(),
+        );
+    }
+
+    debug!("Checking hashed storage");
+    merge_sorted(
+        plain_storage.iter().map(|res| {
+            res.map(|(k, v)| {
+                (
+                    (H256::from_slice(&k[..32]), H256::from_slice(&k[32..])),
+                    (
+                        Address::from_slice(&v[..20]),
+                        H256::from_slice(&v[20..52]),
+                        h256_to_u256(H256::from_slice(&v[52..])),
+                    ),
+                )
+            })
+        }),
+        txn.cursor(tables::HashedStorage)?
+            .walk(None)
+            .map(|res| res.map(|(address, (location, value))| ((address, location), value))),
+        |(hashed_address, hashed_location), plain, hashed| {
+            match plain {
+                Some((address, location, plain)) => {
+                    if hashed != Some(plain) {
+                        out.push(HashedStateMismatch::Storage {
+                            address,
+                            location,
+                            plain,
+                            hashed,
+                        });
+                    }
+                }
+                None => out.push(HashedStateMismatch::OrphanedHashedStorage {
+                    hashed_address,
+                    hashed_location,
+                    value: hashed.unwrap(),
+                }),
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(out)
+}
+
+/// Bring the hashed state back in line with the plain state, entry by entry, from the mismatches
+/// found by [`check_hashed_state`].
+pub fn heal_hashed_state<E>(
+    txn: &MdbxTransaction<'_, RW, E>,
+    mismatches: &[HashedStateMismatch],
+) -> anyhow::Result<()>
+where
+    E: EnvironmentKind,
+{
+    let mut storage = txn.cursor(tables::HashedStorage)?;
+    for mismatch in mismatches {
+        match mismatch {
+            HashedStateMismatch::Account { address, plain, .. } => {
+                txn.set(tables::HashedAccount, keccak256(address), *plain)?;
+            }
+            HashedStateMismatch::OrphanedHashedAccount { hashed_address, .. } => {
+                txn.del(tables::HashedAccount, *hashed_address, None)?;
+            }
+            HashedStateMismatch::Storage {
+                address,
+                location,
+                plain,
+                ..
+            } => {
+                upsert_hashed_storage_value(
+                    &mut storage,
+                    keccak256(address),
+                    keccak256(location),
+                    *plain,
+                )?;
+            }
+            HashedStateMismatch::OrphanedHashedStorage {
+                hashed_address,
+                hashed_location,
+                ..
+            } => {
+                upsert_hashed_storage_value(
+                    &mut storage,
+                    *hashed_address,
+                    *hashed_location,
+                    U256::ZERO,
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct HashState {
     temp_dir: Arc<TempDir>,
@@ -328,6 +601,7 @@ mod tests {
                 deployment_code.into_iter().chain(contract_code).collect(),
             )],
             ommers: vec![],
+            rollup_cost_data: vec![],
         };
 
         let mut buffer = Buffer::new(&tx, BlockNumber(0), None);
@@ -453,5 +727,97 @@ mod tests {
         }
 
         assert!(walker.next().transpose().unwrap().is_none());
+
+        assert_eq!(
+            check_hashed_state(&tx, &TempDir::new().unwrap()).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn hashed_state_mismatches() {
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let account = |nonce| Account {
+            nonce,
+            ..Account::default()
+        };
+
+        let in_sync = Address::from_low_u64_be(1);
+        let stale = Address::from_low_u64_be(2);
+        let unhashed = Address::from_low_u64_be(3);
+        let removed = Address::from_low_u64_be(4);
+
+        tx.set(tables::Account, in_sync, account(1)).unwrap();
+        tx.set(tables::HashedAccount, keccak256(in_sync), account(1))
+            .unwrap();
+        tx.set(tables::Account, stale, account(2)).unwrap();
+        tx.set(tables::HashedAccount, keccak256(stale), account(1))
+            .unwrap();
+        tx.set(tables::Account, unhashed, account(3)).unwrap();
+        tx.set(tables::HashedAccount, keccak256(removed), account(4))
+            .unwrap();
+
+        let location = |n: u64| u256_to_h256(n.as_u256());
+        tx.set(tables::Storage, in_sync, (location(1), 0x10.as_u256()))
+            .unwrap();
+        tx.set(
+            tables::HashedStorage,
+            keccak256(in_sync),
+            (keccak256(location(1)), 0x10.as_u256()),
+        )
+        .unwrap();
+        tx.set(tables::Storage, in_sync, (location(2), 0x20.as_u256()))
+            .unwrap();
+        tx.set(
+            tables::HashedStorage,
+            keccak256(removed),
+            (keccak256(location(3)), 0x30.as_u256()),
+        )
+        .unwrap();
+
+        let mismatches = check_hashed_state(&tx, &TempDir::new().unwrap()).unwrap();
+        let expected = [
+            HashedStateMismatch::Account {
+                address: stale,
+                plain: account(2),
+                hashed: Some(account(1)),
+            },
+            HashedStateMismatch::Account {
+                address: unhashed,
+                plain: account(3),
+                hashed: None,
+            },
+            HashedStateMismatch::OrphanedHashedAccount {
+                hashed_address: keccak256(removed),
+                account: account(4),
+            },
+            HashedStateMismatch::Storage {
+                address: in_sync,
+                location: location(2),
+                plain: 0x20.as_u256(),
+                hashed: None,
+            },
+            HashedStateMismatch::OrphanedHashedStorage {
+                hashed_address: keccak256(removed),
+                hashed_location: keccak256(location(3)),
+                value: 0x30.as_u256(),
+            },
+        ];
+        assert_eq!(mismatches.len(), expected.len());
+        for mismatch in expected {
+            assert!(mismatches.contains(&mismatch), "{:?}", mismatch);
+        }
+
+        heal_hashed_state(&tx, &mismatches).unwrap();
+        assert_eq!(
+            check_hashed_state(&tx, &TempDir::new().unwrap()).unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            tx.get(tables::HashedAccount, keccak256(removed)).unwrap(),
+            None
+        );
     }
 }
@@ -1,5 +1,10 @@
+mod address_appearance_index;
+mod block_feed;
 mod block_hashes;
+mod bodies;
+mod bor_heimdall;
 mod call_trace_index;
+mod contract_creator_index;
 mod downloader;
 mod execution;
 mod hashstate;
@@ -10,11 +15,19 @@ mod total_gas_index;
 mod total_tx_index;
 mod tx_lookup;
 
+pub use address_appearance_index::{read_address_appearances, AddressAppearanceIndex};
+pub use block_feed::{start_block_feed, FeedBodies, FeedHeaders, SignedBlock};
 pub use block_hashes::BlockHashes;
+pub use bodies::BodyDownload;
+pub use bor_heimdall::BorHeimdall;
 pub use call_trace_index::CallTraceIndex;
+pub use contract_creator_index::{read_contract_creator, ContractCreatorIndex};
 pub use downloader::HeaderDownload;
-pub use execution::Execution;
-pub use hashstate::{promote_clean_accounts, promote_clean_storage, HashState};
+pub use execution::{recover_interrupted_execution, Execution, ExecutionThrottle};
+pub use hashstate::{
+    check_hashed_state, heal_hashed_state, promote_clean_accounts, promote_clean_storage,
+    HashState, HashedStateMismatch,
+};
 pub use interhashes::Interhashes;
 pub use sender_recovery::SenderRecovery;
 pub use total_gas_index::TotalGasIndex;
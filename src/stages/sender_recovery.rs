@@ -339,9 +339,9 @@ mod tests {
         let hash2 = H256::random();
         let hash3 = H256::random();
 
-        chain::storage_body::write(&tx, hash1, 1, &block1).unwrap();
-        chain::storage_body::write(&tx, hash2, 2, &block2).unwrap();
-        chain::storage_body::write(&tx, hash3, 3, &block3).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(1, hash1), &block1).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(2, hash2), &block2).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(3, hash3), &block3).unwrap();
 
         tx.set(tables::CanonicalHeader, 1.into(), hash1).unwrap();
         tx.set(tables::CanonicalHeader, 2.into(), hash2).unwrap();
@@ -371,13 +371,13 @@ mod tests {
             }
         );
 
-        let senders1 = chain::tx_sender::read(&tx, hash1, 1);
+        let senders1 = chain::tx_sender::read(&tx, BlockKey::new(1, hash1));
         assert_eq!(senders1.unwrap(), [sender1, sender1]);
 
-        let senders2 = chain::tx_sender::read(&tx, hash2, 2);
+        let senders2 = chain::tx_sender::read(&tx, BlockKey::new(2, hash2));
         assert_eq!(senders2.unwrap(), [sender1, sender2, sender2]);
 
-        let senders3 = chain::tx_sender::read(&tx, hash3, 3);
+        let senders3 = chain::tx_sender::read(&tx, BlockKey::new(3, hash3));
         assert!(senders3.unwrap().is_empty());
     }
 }
@@ -289,9 +289,9 @@ mod tests {
         let hash2 = H256::random();
         let hash3 = H256::random();
 
-        chain::storage_body::write(&tx, hash1, 1, &block1).unwrap();
-        chain::storage_body::write(&tx, hash2, 2, &block2).unwrap();
-        chain::storage_body::write(&tx, hash3, 3, &block3).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(1, hash1), &block1).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(2, hash2), &block2).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(3, hash3), &block3).unwrap();
 
         chain::tx::write(&tx, block1.base_tx_id, &[tx1_1, tx1_2]).unwrap();
         chain::tx::write(&tx, block2.base_tx_id, &[tx2_1, tx2_2, tx2_3]).unwrap();
@@ -505,9 +505,9 @@ mod tests {
         let hash2 = H256::random();
         let hash3 = H256::random();
 
-        chain::storage_body::write(&tx, hash1, 1, &block1).unwrap();
-        chain::storage_body::write(&tx, hash2, 2, &block2).unwrap();
-        chain::storage_body::write(&tx, hash3, 3, &block3).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(1, hash1), &block1).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(2, hash2), &block2).unwrap();
+        chain::storage_body::write(&tx, BlockKey::new(3, hash3), &block3).unwrap();
 
         chain::tx::write(&tx, block1.base_tx_id, &[tx1_1, tx1_2]).unwrap();
         chain::tx::write(&tx, block2.base_tx_id, &[tx2_1, tx2_2, tx2_3]).unwrap();
@@ -113,6 +113,10 @@ where
         }
     }
 
+    fn read_code_analysis(&self, code_hash: H256) -> anyhow::Result<Option<Bytes>> {
+        self.txn.get(tables::CodeAnalysis, code_hash)
+    }
+
     fn read_storage(&self, address: Address, location: U256) -> anyhow::Result<U256> {
         if let Some(account_storage) = self.storage.get(&address) {
             if let Some(value) = account_storage.slots.get(&location) {
@@ -193,7 +197,10 @@ where
         block_number: BlockNumber,
         block_hash: H256,
     ) -> anyhow::Result<Option<BlockBody>> {
-        accessors::chain::block_body::read_without_senders(self.txn, block_hash, block_number)
+        accessors::chain::block_body::read_without_senders(
+            self.txn,
+            BlockKey::new(block_number, block_hash),
+        )
     }
 
     fn total_difficulty(
@@ -201,7 +208,18 @@ where
         block_number: BlockNumber,
         block_hash: H256,
     ) -> anyhow::Result<Option<U256>> {
-        accessors::chain::td::read(self.txn, block_hash, block_number)
+        accessors::chain::td::read(self.txn, BlockKey::new(block_number, block_hash))
+    }
+
+    fn read_bor_span(&self, block_number: BlockNumber) -> anyhow::Result<Option<BorSpan>> {
+        accessors::chain::bor_span::read(self.txn, block_number)
+    }
+
+    fn read_bor_events(&self, block_number: BlockNumber) -> anyhow::Result<Vec<StateSyncEvent>> {
+        Ok(self
+            .txn
+            .get(tables::BorStateSyncEvents, block_number)?
+            .unwrap_or_default())
     }
 
     /// State changes
@@ -1,11 +1,56 @@
 use crate::{
+    accessors::chain,
+    crypto::keccak256,
+    h256_to_u256,
     kv::{mdbx::MdbxTransaction, tables},
     models::*,
     state::*,
 };
 use mdbx::{EnvironmentKind, RW};
+use std::collections::BTreeSet;
 use tempfile::TempDir;
 
+/// Write the genesis allocations of `chain_spec` into `state`.
+fn allocate<S: State>(state: &mut S, chain_spec: &ChainSpec) -> anyhow::Result<()> {
+    let balances = chain_spec.balances.get(&chain_spec.genesis.number);
+    let accounts = &chain_spec.genesis.accounts;
+
+    for &address in balances
+        .into_iter()
+        .flat_map(|balances| balances.keys())
+        .chain(accounts.keys())
+        .collect::<BTreeSet<_>>()
+    {
+        let mut account = Account {
+            balance: balances
+                .and_then(|balances| balances.get(&address))
+                .copied()
+                .unwrap_or(U256::ZERO),
+            ..Default::default()
+        };
+
+        if let Some(GenesisAccount {
+            nonce,
+            code,
+            storage,
+        }) = accounts.get(&address)
+        {
+            account.nonce = *nonce;
+            if !code.is_empty() {
+                account.code_hash = keccak256(code);
+                state.update_code(account.code_hash, code.clone())?;
+            }
+            for (&location, &value) in storage {
+                state.update_storage(address, h256_to_u256(location), U256::ZERO, value)?;
+            }
+        }
+
+        state.update_account(address, None, Some(account));
+    }
+
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 pub struct GenesisState {
     chain_spec: ChainSpec,
@@ -20,16 +65,8 @@ impl GenesisState {
 impl GenesisState {
     pub fn initial_state(&self) -> InMemoryState {
         let mut state_buffer = InMemoryState::new();
-        // Allocate accounts
-        if let Some(balances) = self.chain_spec.balances.get(&BlockNumber(0)) {
-            for (&address, &balance) in balances {
-                let current_account = Account {
-                    balance,
-                    ..Default::default()
-                };
-                state_buffer.update_account(address, None, Some(current_account));
-            }
-        }
+        // In-memory state updates cannot fail.
+        allocate(&mut state_buffer, &self.chain_spec).unwrap();
         state_buffer
     }
 
@@ -51,7 +88,8 @@ impl GenesisState {
             extra_data: seal.extra_data(),
             mix_hash: seal.mix_hash(),
             nonce: seal.nonce(),
-            base_fee_per_gas: None,
+            base_fee_per_gas: genesis.base_fee_per_gas,
+            aura_seal: seal.aura_seal(),
 
             receipts_root: EMPTY_ROOT,
             ommers_hash: EMPTY_LIST_HASH,
@@ -75,19 +113,7 @@ where
 
     let mut state_buffer = Buffer::new(txn, genesis, None);
     state_buffer.begin_block(genesis);
-    // Allocate accounts
-    if let Some(balances) = chainspec.balances.get(&genesis) {
-        for (&address, &balance) in balances {
-            state_buffer.update_account(
-                address,
-                None,
-                Some(Account {
-                    balance,
-                    ..Default::default()
-                }),
-            );
-        }
-    }
+    allocate(&mut state_buffer, &chainspec)?;
 
     state_buffer.write_to_db()?;
 
@@ -108,7 +134,8 @@ where
         extra_data: chainspec.genesis.seal.extra_data(),
         mix_hash: chainspec.genesis.seal.mix_hash(),
         nonce: chainspec.genesis.seal.nonce(),
-        base_fee_per_gas: None,
+        base_fee_per_gas: chainspec.genesis.base_fee_per_gas,
+        aura_seal: chainspec.genesis.seal.aura_seal(),
 
         receipts_root: EMPTY_ROOT,
         ommers_hash: EMPTY_LIST_HASH,
@@ -138,7 +165,7 @@ where
     txn.set(tables::TotalGas, genesis, 0)?;
     txn.set(tables::TotalTx, genesis, 0)?;
 
-    txn.set(tables::LastHeader, Default::default(), block_hash)?;
+    chain::last_header::write(txn, block_hash)?;
 
     txn.set(tables::Config, block_hash, chainspec)?;
 
@@ -149,6 +176,7 @@ where
 mod tests {
     use super::*;
     use crate::kv::new_mem_database;
+    use bytes::Bytes;
     use hex_literal::hex;
 
     fn genesis_header_hash(chain_spec: &'static ChainSpec) -> H256 {
@@ -190,4 +218,50 @@ mod tests {
             hex!("d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3").into()
         );
     }
+
+    #[test]
+    fn genesis_with_code_and_storage() {
+        let contract = Address::from_low_u64_be(0x100);
+        let code = Bytes::from(hex!("6001600055").to_vec());
+
+        let mut chain_spec = crate::res::chainspec::RINKEBY.clone();
+        chain_spec.genesis.accounts.insert(
+            contract,
+            GenesisAccount {
+                nonce: 1,
+                code: code.clone(),
+                storage: [(H256::from_low_u64_be(1), 0x2a.as_u256())]
+                    .into_iter()
+                    .collect(),
+            },
+        );
+
+        let genesis = GenesisState::new(chain_spec.clone());
+        let initial_state = genesis.initial_state();
+        let header = genesis.header(&initial_state);
+        assert_ne!(
+            header.hash(),
+            hex!("6341fd3daf94b748c72ced5a5b26028f2474f5f00d824504e4fa37a75767e177").into()
+        );
+
+        let db = new_mem_database().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        assert!(initialize_genesis(&tx, &temp_dir, chain_spec).unwrap());
+
+        let genesis_hash = tx.get(tables::CanonicalHeader, 0.into()).unwrap().unwrap();
+        assert_eq!(genesis_hash, header.hash());
+
+        let account = tx.get(tables::Account, contract).unwrap().unwrap();
+        assert_eq!(account.nonce, 1);
+        assert_eq!(account.code_hash, keccak256(&code));
+        assert_eq!(
+            tx.get(tables::Code, account.code_hash).unwrap().unwrap(),
+            code
+        );
+        assert_eq!(
+            crate::accessors::state::storage::read(&tx, contract, 1.as_u256(), None).unwrap(),
+            0x2a.as_u256()
+        );
+    }
 }
@@ -165,6 +165,11 @@ impl InMemoryState {
                             })
                             .collect::<anyhow::Result<_>>()?,
                         ommers: body.ommers.clone(),
+                        rollup_cost_data: body
+                            .transactions
+                            .iter()
+                            .map(MessageWithSignature::rollup_cost_data)
+                            .collect(),
                     })
                 })
                 .transpose();
@@ -9,6 +9,12 @@ pub trait State: Debug + Send + Sync {
 
     fn read_code(&self, code_hash: H256) -> anyhow::Result<Bytes>;
 
+    /// Persisted jump destination analysis of the code, see
+    /// [`crate::execution::analysis_cache::decode_analysis`].
+    fn read_code_analysis(&self, _code_hash: H256) -> anyhow::Result<Option<Bytes>> {
+        Ok(None)
+    }
+
     fn read_storage(&self, address: Address, location: U256) -> anyhow::Result<U256>;
 
     fn erase_storage(&mut self, address: Address) -> anyhow::Result<()>;
@@ -31,6 +37,16 @@ pub trait State: Debug + Send + Sync {
         block_hash: H256,
     ) -> anyhow::Result<Option<U256>>;
 
+    /// Bor span containing the block, see [`crate::consensus::Bor`].
+    fn read_bor_span(&self, _block_number: BlockNumber) -> anyhow::Result<Option<BorSpan>> {
+        Ok(None)
+    }
+
+    /// State-sync events committed at the start of the Bor sprint at `block_number`.
+    fn read_bor_events(&self, _block_number: BlockNumber) -> anyhow::Result<Vec<StateSyncEvent>> {
+        Ok(vec![])
+    }
+
     /// State changes
     /// Change sets are backward changes of the state, i.e. account/storage values _at the beginning of a block_.
 
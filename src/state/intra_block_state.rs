@@ -8,6 +8,8 @@ use std::{collections::*, fmt::Debug};
 pub struct Snapshot {
     journal_size: usize,
     log_size: usize,
+    internal_transfer_size: usize,
+    created_contract_size: usize,
     refund: u64,
 }
 
@@ -31,6 +33,8 @@ where
     // substate
     pub(crate) self_destructs: HashSet<Address>,
     pub(crate) logs: Vec<Log>,
+    pub(crate) internal_transfers: Vec<InternalTransfer>,
+    pub(crate) created_contracts: Vec<ContractCreation>,
     pub(crate) touched: HashSet<Address>,
     pub(crate) refund: u64,
     // EIP-2929 substate
@@ -107,6 +111,8 @@ impl<'storage, 'r, S: State> IntraBlockState<'r, S> {
             journal: Default::default(),
             self_destructs: Default::default(),
             logs: Default::default(),
+            internal_transfers: Default::default(),
+            created_contracts: Default::default(),
             touched: Default::default(),
             refund: Default::default(),
             accessed_addresses: Default::default(),
@@ -483,6 +489,8 @@ impl<'storage, 'r, S: State> IntraBlockState<'r, S> {
         Snapshot {
             journal_size: self.journal.len(),
             log_size: self.logs.len(),
+            internal_transfer_size: self.internal_transfers.len(),
+            created_contract_size: self.created_contracts.len(),
             refund: self.refund,
         }
     }
@@ -491,6 +499,10 @@ impl<'storage, 'r, S: State> IntraBlockState<'r, S> {
             self.journal.pop().unwrap().revert(self);
         }
         self.logs.truncate(snapshot.log_size);
+        self.internal_transfers
+            .truncate(snapshot.internal_transfer_size);
+        self.created_contracts
+            .truncate(snapshot.created_contract_size);
         self.refund = snapshot.refund;
     }
 
@@ -510,6 +522,8 @@ impl<'storage, 'r, S: State> IntraBlockState<'r, S> {
         // and the substate
         self.self_destructs.clear();
         self.logs.clear();
+        self.internal_transfers.clear();
+        self.created_contracts.clear();
         self.touched.clear();
         self.refund = 0;
         // EIP-2929
@@ -525,6 +539,22 @@ impl<'storage, 'r, S: State> IntraBlockState<'r, S> {
         &self.logs
     }
 
+    pub fn add_internal_transfer(&mut self, transfer: InternalTransfer) {
+        self.internal_transfers.push(transfer);
+    }
+
+    pub fn internal_transfers(&self) -> &[InternalTransfer] {
+        &self.internal_transfers
+    }
+
+    pub fn add_created_contract(&mut self, creation: ContractCreation) {
+        self.created_contracts.push(creation);
+    }
+
+    pub fn created_contracts(&self) -> &[ContractCreation] {
+        &self.created_contracts
+    }
+
     pub fn add_refund(&mut self, addend: u64) {
         self.refund += addend;
     }
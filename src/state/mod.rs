@@ -6,7 +6,9 @@ mod in_memory_state;
 mod interface;
 mod intra_block_state;
 mod object;
+mod witness;
 
 pub use self::{
     buffer::*, database::*, in_memory_state::*, interface::*, intra_block_state::*, object::*,
+    witness::*,
 };
@@ -0,0 +1,425 @@
+use crate::{crypto::keccak256, models::*, trie::StateProof, State};
+use anyhow::{ensure, format_err};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+
+/// State read while executing a block, as it was before the block.
+/// Enough to execute the block again without the database.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Witness {
+    pub accounts: HashMap<Address, Option<Account>>,
+    // address -> location -> value
+    pub storage: HashMap<Address, HashMap<U256, U256>>,
+    pub code: HashMap<H256, Bytes>,
+    pub headers: HashMap<(BlockNumber, H256), Option<BlockHeader>>,
+    pub bodies: HashMap<(BlockNumber, H256), Option<BlockBody>>,
+    pub total_difficulties: HashMap<(BlockNumber, H256), Option<U256>>,
+    /// Trie nodes proving the accounts and storage against the state root before the block.
+    pub proof: StateProof,
+}
+
+impl Witness {
+    /// Checks the accounts, storage and code of the witness against its proof, and the proof
+    /// against `state_root`, the state root of the parent block.
+    pub fn verify(&self, state_root: H256) -> anyhow::Result<()> {
+        ensure!(
+            self.proof.state_root == state_root,
+            "Witness is proven against state root {:?}, not {:?}",
+            self.proof.state_root,
+            state_root
+        );
+
+        for (&address, account) in &self.accounts {
+            let proven = self.proof.account(address)?;
+            ensure!(
+                proven.map(|proven| (proven.nonce, proven.balance, proven.code_hash))
+                    == account.map(|account| (account.nonce, account.balance, account.code_hash)),
+                "Account {:?} does not match its proof",
+                address
+            );
+        }
+
+        for (&address, storage) in &self.storage {
+            for (&location, &value) in storage {
+                ensure!(
+                    self.proof.storage(address, location)? == value,
+                    "Storage {:?}/{} does not match its proof",
+                    address,
+                    location
+                );
+            }
+        }
+
+        for (&code_hash, code) in &self.code {
+            ensure!(
+                keccak256(code) == code_hash,
+                "Code {:?} does not match its hash",
+                code_hash
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Passes everything through to the inner state, recording the first value of every read into a [`Witness`].
+#[derive(Debug)]
+pub struct WitnessRecorder<S>
+where
+    S: State,
+{
+    inner: S,
+    witness: Mutex<Witness>,
+}
+
+impl<S> WitnessRecorder<S>
+where
+    S: State,
+{
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            witness: Default::default(),
+        }
+    }
+
+    pub fn into_witness(self) -> Witness {
+        self.witness.into_inner()
+    }
+}
+
+impl<S> State for WitnessRecorder<S>
+where
+    S: State,
+{
+    fn read_account(&self, address: Address) -> anyhow::Result<Option<Account>> {
+        let account = self.inner.read_account(address)?;
+        self.witness
+            .lock()
+            .accounts
+            .entry(address)
+            .or_insert(account);
+
+        Ok(account)
+    }
+
+    fn read_code(&self, code_hash: H256) -> anyhow::Result<Bytes> {
+        let code = self.inner.read_code(code_hash)?;
+        self.witness
+            .lock()
+            .code
+            .entry(code_hash)
+            .or_insert_with(|| code.clone());
+
+        Ok(code)
+    }
+
+    fn read_code_analysis(&self, code_hash: H256) -> anyhow::Result<Option<Bytes>> {
+        self.inner.read_code_analysis(code_hash)
+    }
+
+    fn read_storage(&self, address: Address, location: U256) -> anyhow::Result<U256> {
+        let value = self.inner.read_storage(address, location)?;
+        self.witness
+            .lock()
+            .storage
+            .entry(address)
+            .or_default()
+            .entry(location)
+            .or_insert(value);
+
+        Ok(value)
+    }
+
+    fn erase_storage(&mut self, address: Address) -> anyhow::Result<()> {
+        self.inner.erase_storage(address)
+    }
+
+    fn read_header(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<BlockHeader>> {
+        let header = self.inner.read_header(block_number, block_hash)?;
+        self.witness
+            .lock()
+            .headers
+            .entry((block_number, block_hash))
+            .or_insert_with(|| header.clone());
+
+        Ok(header)
+    }
+
+    fn read_body(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<BlockBody>> {
+        let body = self.inner.read_body(block_number, block_hash)?;
+        self.witness
+            .lock()
+            .bodies
+            .entry((block_number, block_hash))
+            .or_insert_with(|| body.clone());
+
+        Ok(body)
+    }
+
+    fn total_difficulty(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<U256>> {
+        let total_difficulty = self.inner.total_difficulty(block_number, block_hash)?;
+        self.witness
+            .lock()
+            .total_difficulties
+            .entry((block_number, block_hash))
+            .or_insert(total_difficulty);
+
+        Ok(total_difficulty)
+    }
+
+    fn read_bor_span(&self, block_number: BlockNumber) -> anyhow::Result<Option<BorSpan>> {
+        self.inner.read_bor_span(block_number)
+    }
+
+    fn read_bor_events(&self, block_number: BlockNumber) -> anyhow::Result<Vec<StateSyncEvent>> {
+        self.inner.read_bor_events(block_number)
+    }
+
+    fn begin_block(&mut self, block_number: BlockNumber) {
+        self.inner.begin_block(block_number)
+    }
+
+    fn update_account(
+        &mut self,
+        address: Address,
+        initial: Option<Account>,
+        current: Option<Account>,
+    ) {
+        self.inner.update_account(address, initial, current)
+    }
+
+    fn update_code(&mut self, code_hash: H256, code: Bytes) -> anyhow::Result<()> {
+        self.inner.update_code(code_hash, code)
+    }
+
+    fn update_storage(
+        &mut self,
+        address: Address,
+        location: U256,
+        initial: U256,
+        current: U256,
+    ) -> anyhow::Result<()> {
+        self.inner
+            .update_storage(address, location, initial, current)
+    }
+}
+
+/// State backed by a [`Witness`] alone. Reading anything the witness does not cover is an error.
+#[derive(Debug)]
+pub struct WitnessState {
+    witness: Witness,
+    // accounts whose storage not in the witness is known to be empty
+    erased: HashSet<Address>,
+}
+
+impl WitnessState {
+    pub fn new(witness: Witness) -> Self {
+        Self {
+            witness,
+            erased: Default::default(),
+        }
+    }
+}
+
+impl State for WitnessState {
+    fn read_account(&self, address: Address) -> anyhow::Result<Option<Account>> {
+        self.witness
+            .accounts
+            .get(&address)
+            .copied()
+            .ok_or_else(|| format_err!("Account {:?} is not in the witness", address))
+    }
+
+    fn read_code(&self, code_hash: H256) -> anyhow::Result<Bytes> {
+        self.witness
+            .code
+            .get(&code_hash)
+            .cloned()
+            .ok_or_else(|| format_err!("Code {:?} is not in the witness", code_hash))
+    }
+
+    fn read_storage(&self, address: Address, location: U256) -> anyhow::Result<U256> {
+        if let Some(value) = self
+            .witness
+            .storage
+            .get(&address)
+            .and_then(|storage| storage.get(&location))
+        {
+            return Ok(*value);
+        }
+
+        if self.erased.contains(&address) {
+            return Ok(U256::ZERO);
+        }
+
+        Err(format_err!(
+            "Storage {:?}/{} is not in the witness",
+            address,
+            location
+        ))
+    }
+
+    fn erase_storage(&mut self, address: Address) -> anyhow::Result<()> {
+        self.witness.storage.remove(&address);
+        self.erased.insert(address);
+
+        Ok(())
+    }
+
+    fn read_header(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<BlockHeader>> {
+        self.witness
+            .headers
+            .get(&(block_number, block_hash))
+            .cloned()
+            .ok_or_else(|| {
+                format_err!(
+                    "Header {}/{:?} is not in the witness",
+                    block_number,
+                    block_hash
+                )
+            })
+    }
+
+    fn read_body(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<BlockBody>> {
+        self.witness
+            .bodies
+            .get(&(block_number, block_hash))
+            .cloned()
+            .ok_or_else(|| {
+                format_err!(
+                    "Body {}/{:?} is not in the witness",
+                    block_number,
+                    block_hash
+                )
+            })
+    }
+
+    fn total_difficulty(
+        &self,
+        block_number: BlockNumber,
+        block_hash: H256,
+    ) -> anyhow::Result<Option<U256>> {
+        self.witness
+            .total_difficulties
+            .get(&(block_number, block_hash))
+            .copied()
+            .ok_or_else(|| {
+                format_err!(
+                    "Total difficulty {}/{:?} is not in the witness",
+                    block_number,
+                    block_hash
+                )
+            })
+    }
+
+    fn begin_block(&mut self, _: BlockNumber) {}
+
+    fn update_account(&mut self, address: Address, _: Option<Account>, current: Option<Account>) {
+        self.witness.accounts.insert(address, current);
+    }
+
+    fn update_code(&mut self, code_hash: H256, code: Bytes) -> anyhow::Result<()> {
+        self.witness.code.insert(code_hash, code);
+
+        Ok(())
+    }
+
+    fn update_storage(
+        &mut self,
+        address: Address,
+        location: U256,
+        _: U256,
+        current: U256,
+    ) -> anyhow::Result<()> {
+        self.witness
+            .storage
+            .entry(address)
+            .or_default()
+            .insert(location, current);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryState;
+    use hex_literal::hex;
+
+    #[test]
+    fn witness_holds_pre_state() {
+        let address = hex!("b685342b8c54347aad148e1f22eff3eb3eb29391").into();
+        let missing = hex!("5a0b54d5dc17e0aadc383d2db43b0a0d3e029c4c").into();
+        let account = Account {
+            nonce: 1,
+            ..Default::default()
+        };
+
+        let mut state = InMemoryState::default();
+        state.update_account(address, None, Some(account));
+        state
+            .update_storage(address, 1.as_u256(), U256::ZERO, 0x2a.as_u256())
+            .unwrap();
+
+        let mut recorder = WitnessRecorder::new(&mut state);
+        assert_eq!(recorder.read_account(address).unwrap(), Some(account));
+        assert_eq!(recorder.read_account(missing).unwrap(), None);
+        assert_eq!(
+            recorder.read_storage(address, 1.as_u256()).unwrap(),
+            0x2a.as_u256()
+        );
+
+        let updated = Account {
+            nonce: 2,
+            ..account
+        };
+        recorder.update_account(address, Some(account), Some(updated));
+        recorder
+            .update_storage(address, 1.as_u256(), 0x2a.as_u256(), 0x2b.as_u256())
+            .unwrap();
+        assert_eq!(recorder.read_account(address).unwrap(), Some(updated));
+
+        let witness = recorder.into_witness();
+        assert_eq!(witness.accounts[&address], Some(account));
+        assert_eq!(witness.storage[&address][&1.as_u256()], 0x2a.as_u256());
+
+        let mut state = WitnessState::new(witness);
+        assert_eq!(state.read_account(address).unwrap(), Some(account));
+        assert_eq!(state.read_account(missing).unwrap(), None);
+        state.read_storage(address, 2.as_u256()).unwrap_err();
+        state.read_code(EMPTY_HASH).unwrap_err();
+
+        state.erase_storage(address).unwrap();
+        assert_eq!(
+            state.read_storage(address, 1.as_u256()).unwrap(),
+            U256::ZERO
+        );
+        assert_eq!(
+            state.read_storage(address, 2.as_u256()).unwrap(),
+            U256::ZERO
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Prometheus metrics of the futures and streams the runtime polls: how many of each are alive,
+//! and how long every poll takes, which tells one that blocks its worker from one that waits.
+//!
+//! Futures and streams are measured either by wrapping them with [`TaskMetrics::instrument`], or
+//! through their tracing spans by [`TaskMetricsLayer`], as spans are entered on every poll of the
+//! future they instrument.
+use futures_core::Stream;
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_gauge_vec, Histogram, HistogramVec,
+    IntGauge, IntGaugeVec,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tracing::{span, Subscriber};
+use tracing_subscriber::{layer, registry::LookupSpan, Layer};
+
+static ALIVE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "task_alive",
+        "Measured futures and streams not dropped yet",
+        &["kind", "name"]
+    )
+    .unwrap()
+});
+
+static POLL_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "task_poll_seconds",
+        "Time spent in single polls of measured futures and streams",
+        &["kind", "name"],
+        // 10µs to 2.6s
+        exponential_buckets(1e-5, 4.0, 10).unwrap()
+    )
+    .unwrap()
+});
+
+/// Metrics of the futures or streams of one kind and name.
+#[derive(Clone, Debug)]
+pub struct TaskMetrics {
+    alive: IntGauge,
+    poll_seconds: Histogram,
+}
+
+impl TaskMetrics {
+    pub fn new(kind: &str, name: &str) -> Self {
+        Self {
+            alive: ALIVE.with_label_values(&[kind, name]),
+            poll_seconds: POLL_SECONDS.with_label_values(&[kind, name]),
+        }
+    }
+
+    /// Measure `inner`, a future or a stream, until it is dropped.
+    pub fn instrument<T>(&self, inner: T) -> Instrumented<T> {
+        self.alive.inc();
+        Instrumented {
+            inner: Box::pin(inner),
+            metrics: self.clone(),
+        }
+    }
+}
+
+/// Future or stream measured by [`TaskMetrics`].
+pub struct Instrumented<T> {
+    // Boxed so that the wrapper is `Unpin` whatever it wraps.
+    inner: Pin<Box<T>>,
+    metrics: TaskMetrics,
+}
+
+impl<T> Instrumented<T> {
+    fn timed<R>(&mut self, poll: impl FnOnce(Pin<&mut T>) -> R) -> R {
+        let started = Instant::now();
+        let res = poll(self.inner.as_mut());
+        self.metrics
+            .poll_seconds
+            .observe(started.elapsed().as_secs_f64());
+        res
+    }
+}
+
+impl<T: Future> Future for Instrumented<T> {
+    type Output = T::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().timed(|inner| inner.poll(cx))
+    }
+}
+
+impl<T: Stream> Stream for Instrumented<T> {
+    type Item = T::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().timed(|inner| inner.poll_next(cx))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> Drop for Instrumented<T> {
+    fn drop(&mut self) {
+        self.metrics.alive.dec();
+    }
+}
+
+/// Measures every span that the subscriber's filter lets through, by target and name, from the
+/// time it is entered until it is exited. Spans closed are no longer counted as alive.
+pub struct TaskMetricsLayer;
+
+struct SpanTiming {
+    metrics: TaskMetrics,
+    entered: Option<Instant>,
+}
+
+impl Drop for SpanTiming {
+    fn drop(&mut self) {
+        self.metrics.alive.dec();
+    }
+}
+
+impl<S> Layer<S> for TaskMetricsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: layer::Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let metadata = attrs.metadata();
+            let metrics = TaskMetrics::new(metadata.target(), metadata.name());
+            metrics.alive.inc();
+            span.extensions_mut().insert(SpanTiming {
+                metrics,
+                entered: None,
+            });
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                timing.entered = Some(Instant::now());
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                if let Some(entered) = timing.entered.take() {
+                    timing
+                        .metrics
+                        .poll_seconds
+                        .observe(entered.elapsed().as_secs_f64());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn instrumented_counts_polls_and_alive() {
+        let metrics = TaskMetrics::new("test", "instrumented_counts_polls_and_alive");
+
+        let mut stream = metrics.instrument(futures_util::stream::iter([1, 2]));
+        assert_eq!(metrics.alive.get(), 1);
+        assert_eq!(stream.next().await, Some(1));
+        assert_eq!(stream.next().await, Some(2));
+        assert_eq!(stream.next().await, None);
+        assert_eq!(metrics.poll_seconds.get_sample_count(), 3);
+
+        assert_eq!(metrics.instrument(async { 3 }).await, 3);
+        assert_eq!(metrics.poll_seconds.get_sample_count(), 4);
+
+        drop(stream);
+        assert_eq!(metrics.alive.get(), 0);
+    }
+}
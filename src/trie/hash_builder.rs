@@ -4,12 +4,13 @@ use crate::{
     models::{EMPTY_ROOT, KECCAK_LENGTH},
     trie::{
         node::Node,
-        util::{assert_subset, prefix_length},
+        util::{assert_subset, has_prefix, prefix_length},
     },
 };
+use bytes::Bytes;
 use ethereum_types::H256;
 use rlp::RlpStream;
-use std::{boxed::Box, cmp};
+use std::{boxed::Box, cmp, collections::BTreeMap};
 
 const RLP_EMPTY_STRING_CODE: u8 = 0x80;
 
@@ -53,6 +54,45 @@ fn node_ref(rlp: &[u8]) -> Vec<u8> {
 
 type NodeCollector<'nc> = Box<dyn FnMut(&[u8], &Node) + Send + Sync + 'nc>;
 
+/// Keeps the nodes on the paths to some keys, to prove them.
+pub(crate) struct ProofRetainer {
+    // unpacked key -> RLP of the nodes on its path, deepest first
+    proofs: BTreeMap<Vec<u8>, Vec<Bytes>>,
+}
+
+impl ProofRetainer {
+    pub(crate) fn new(keys: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Self {
+            proofs: keys.into_iter().map(|key| (key, vec![])).collect(),
+        }
+    }
+
+    fn retain(&mut self, path: &[u8], rlp: &[u8]) {
+        // Nodes shorter than a hash are embedded in their parent, and proven with it.
+        if !path.is_empty() && rlp.len() < KECCAK_LENGTH {
+            return;
+        }
+
+        for (key, nodes) in self.proofs.range_mut(path.to_vec()..) {
+            if !has_prefix(key, path) {
+                break;
+            }
+            nodes.push(Bytes::copy_from_slice(rlp));
+        }
+    }
+
+    /// Proof of every key, root first.
+    pub(crate) fn into_proofs(self) -> BTreeMap<Vec<u8>, Vec<Bytes>> {
+        self.proofs
+            .into_iter()
+            .map(|(key, mut nodes)| {
+                nodes.reverse();
+                (key, nodes)
+            })
+            .collect()
+    }
+}
+
 #[derive(Clone)]
 enum HashBuilderValue {
     Bytes(Vec<u8>),
@@ -61,6 +101,7 @@ enum HashBuilderValue {
 
 pub(crate) struct HashBuilder<'nc> {
     pub(crate) node_collector: Option<NodeCollector<'nc>>,
+    pub(crate) proof_retainer: Option<ProofRetainer>,
     key: Vec<u8>,
     value: HashBuilderValue,
     is_in_db_trie: bool,
@@ -75,6 +116,7 @@ impl<'nc> HashBuilder<'nc> {
     pub(crate) fn new() -> Self {
         Self {
             node_collector: None,
+            proof_retainer: None,
             key: vec![],
             value: HashBuilderValue::Bytes(vec![]),
             is_in_db_trie: false,
@@ -176,11 +218,9 @@ impl<'nc> HashBuilder<'nc> {
                 let value = self.value.clone();
                 match value {
                     HashBuilderValue::Bytes(ref leaf_value) => {
-                        let x = node_ref(
-                            self.leaf_node_rlp(short_node_key.as_slice(), leaf_value)
-                                .as_slice(),
-                        );
-                        self.stack.push(x);
+                        let leaf_rlp = self.leaf_node_rlp(short_node_key.as_slice(), leaf_value);
+                        self.retain_proof_node(&current[..len_from], &leaf_rlp);
+                        self.stack.push(node_ref(leaf_rlp.as_slice()));
                     }
                     HashBuilderValue::Hash(ref hash) => {
                         self.stack.push(wrap_hash(hash));
@@ -208,11 +248,10 @@ impl<'nc> HashBuilder<'nc> {
                 }
 
                 let stack_last = self.stack.pop().unwrap();
-                let new_stack_last = node_ref(
-                    self.extension_node_rlp(short_node_key.as_slice(), stack_last.as_slice())
-                        .as_slice(),
-                );
-                self.stack.push(new_stack_last);
+                let extension_rlp =
+                    self.extension_node_rlp(short_node_key.as_slice(), stack_last.as_slice());
+                self.retain_proof_node(&current[..len_from], &extension_rlp);
+                self.stack.push(node_ref(extension_rlp.as_slice()));
 
                 self.hash_masks.resize(len_from, 0u16);
                 self.tree_masks.resize(len_from, 0u16);
@@ -223,7 +262,8 @@ impl<'nc> HashBuilder<'nc> {
             }
 
             if !succeeding.is_empty() || preceding_exists {
-                let child_hashes = self.branch_ref(self.groups[len], self.hash_masks[len]);
+                let child_hashes =
+                    self.branch_ref(&current[..len], self.groups[len], self.hash_masks[len]);
 
                 let have_node_collector = self.node_collector.is_some();
                 if have_node_collector {
@@ -274,7 +314,13 @@ impl<'nc> HashBuilder<'nc> {
         }
     }
 
-    fn branch_ref(&mut self, state_mask: u16, hash_mask: u16) -> Vec<Vec<u8>> {
+    fn retain_proof_node(&mut self, path: &[u8], rlp: &[u8]) {
+        if let Some(proof_retainer) = &mut self.proof_retainer {
+            proof_retainer.retain(path, rlp);
+        }
+    }
+
+    fn branch_ref(&mut self, path: &[u8], state_mask: u16, hash_mask: u16) -> Vec<Vec<u8>> {
         assert_subset(hash_mask, state_mask);
         let mut child_hashes = Vec::<Vec<u8>>::with_capacity(hash_mask.count_ones() as usize);
         let first_child_idx = self.stack.len() - state_mask.count_ones() as usize;
@@ -295,6 +341,9 @@ impl<'nc> HashBuilder<'nc> {
         stream.append_empty_data();
 
         self.rlp_buffer = stream.out().to_vec();
+        if let Some(proof_retainer) = &mut self.proof_retainer {
+            proof_retainer.retain(path, &self.rlp_buffer);
+        }
         self.stack.truncate(first_child_idx);
         self.stack.push(node_ref(self.rlp_buffer.as_slice()));
 
@@ -86,12 +86,15 @@ fn increment_key(unpacked: &[u8]) -> Option<Vec<u8>> {
     None
 }
 
-struct Cursor<'cu, 'tx, 'ps, T>
+pub(crate) struct Cursor<'cu, 'tx, 'ps, K, T>
 where
+    K: TransactionKind,
     T: Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
     'tx: 'cu,
 {
-    cursor: Mutex<&'cu mut MdbxCursor<'tx, RW, T>>,
+    cursor: Mutex<&'cu mut MdbxCursor<'tx, K, T>>,
+    // what becomes of the nodes on the changed paths, which are about to be written anew
+    on_consumed: fn(&mut MdbxCursor<'tx, K, T>) -> Result<()>,
     changed: &'ps mut PrefixSet,
     prefix: Vec<u8>,
     stack: Vec<CursorSubNode>,
@@ -99,7 +102,7 @@ where
     _marker: PhantomData<&'tx T>,
 }
 
-impl<'cu, 'tx, 'ps, T> Cursor<'cu, 'tx, 'ps, T>
+impl<'cu, 'tx, 'ps, T> Cursor<'cu, 'tx, 'ps, RW, T>
 where
     T: Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
     'tx: 'cu,
@@ -108,9 +111,35 @@ where
         cursor: &'cu mut MdbxCursor<'tx, RW, T>,
         changed: &'ps mut PrefixSet,
         prefix: &[u8],
-    ) -> Result<Cursor<'cu, 'tx, 'ps, T>> {
+    ) -> Result<Self> {
+        Self::with_on_consumed(cursor, changed, prefix, |cursor| cursor.delete_current())
+    }
+}
+
+impl<'cu, 'tx, 'ps, K, T> Cursor<'cu, 'tx, 'ps, K, T>
+where
+    K: TransactionKind,
+    T: Table<Key = Vec<u8>, SeekKey = Vec<u8>, Value = Vec<u8>>,
+    'tx: 'cu,
+{
+    /// Walks the trie without touching it, e.g. to prove the changed keys.
+    pub(crate) fn read_only(
+        cursor: &'cu mut MdbxCursor<'tx, K, T>,
+        changed: &'ps mut PrefixSet,
+        prefix: &[u8],
+    ) -> Result<Self> {
+        Self::with_on_consumed(cursor, changed, prefix, |_| Ok(()))
+    }
+
+    fn with_on_consumed(
+        cursor: &'cu mut MdbxCursor<'tx, K, T>,
+        changed: &'ps mut PrefixSet,
+        prefix: &[u8],
+        on_consumed: fn(&mut MdbxCursor<'tx, K, T>) -> Result<()>,
+    ) -> Result<Self> {
         let mut new_cursor = Self {
             cursor: Mutex::new(cursor),
+            on_consumed,
             changed,
             prefix: prefix.to_vec(),
             stack: vec![],
@@ -121,7 +150,7 @@ where
         Ok(new_cursor)
     }
 
-    fn next(&mut self) -> Result<()> {
+    pub(crate) fn next(&mut self) -> Result<()> {
         if self.stack.is_empty() {
             return Ok(()); // end-of-tree
         }
@@ -140,7 +169,7 @@ where
         Ok(())
     }
 
-    fn key(&self) -> Option<Vec<u8>> {
+    pub(crate) fn key(&self) -> Option<Vec<u8>> {
         if self.stack.is_empty() {
             None
         } else {
@@ -148,25 +177,25 @@ where
         }
     }
 
-    fn hash(&self) -> Option<H256> {
+    pub(crate) fn hash(&self) -> Option<H256> {
         if self.stack.is_empty() {
             return None;
         }
         self.stack.last().unwrap().hash()
     }
 
-    fn children_are_in_trie(&self) -> bool {
+    pub(crate) fn children_are_in_trie(&self) -> bool {
         if self.stack.is_empty() {
             return false;
         }
         self.stack.last().unwrap().tree_flag()
     }
 
-    fn can_skip_state(&self) -> bool {
+    pub(crate) fn can_skip_state(&self) -> bool {
         self.can_skip_state
     }
 
-    fn first_uncovered_prefix(&self) -> Option<Vec<u8>> {
+    pub(crate) fn first_uncovered_prefix(&self) -> Option<Vec<u8>> {
         let mut k = self.key();
 
         if self.can_skip_state && k.is_some() {
@@ -225,7 +254,7 @@ where
         self.update_skip_state();
 
         if entry.is_some() && (!self.can_skip_state || nibble != -1) {
-            self.cursor.lock().delete_current()?;
+            (self.on_consumed)(&mut **self.cursor.lock())?;
         }
 
         Ok(())
@@ -283,7 +312,7 @@ where
         }
     }
 
-    fn changed_mut(&mut self) -> &mut PrefixSet {
+    pub(crate) fn changed_mut(&mut self) -> &mut PrefixSet {
         self.changed
     }
 }
@@ -2,6 +2,12 @@ mod hash_builder;
 mod intermediate_hashes;
 mod node;
 mod prefix_set;
+mod proof;
+mod storage_root;
 mod util;
+mod walk;
 
 pub use intermediate_hashes::{increment_intermediate_hashes, regenerate_intermediate_hashes};
+pub use proof::{prove, verify_proof, StateProof};
+pub use storage_root::{hashed_storage_root, storage_root};
+pub use walk::{account_trie_path, walk_account_trie, walk_storage_trie, TrieNode, TrieNodeChild};
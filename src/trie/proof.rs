@@ -0,0 +1,443 @@
+use crate::{
+    crypto::keccak256,
+    kv::{mdbx::*, tables},
+    models::*,
+    trie::{
+        hash_builder::{unpack_nibbles, HashBuilder, ProofRetainer},
+        intermediate_hashes::Cursor,
+        prefix_set::PrefixSet,
+    },
+    u256_to_h256,
+};
+use anyhow::{bail, ensure, format_err, Result};
+use bytes::Bytes;
+use rlp::Rlp;
+use std::collections::HashMap;
+
+/// Trie nodes proving accounts and storage slots against a state root.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateProof {
+    pub state_root: H256,
+    /// Account trie nodes on the path to each account, root first.
+    pub accounts: HashMap<Address, Vec<Bytes>>,
+    /// Storage trie nodes on the path to each slot, from the storage root of its account.
+    pub storage: HashMap<Address, HashMap<U256, Vec<Bytes>>>,
+}
+
+impl StateProof {
+    /// Account at `address` as proven, `None` if it is proven not to exist.
+    pub fn account(&self, address: Address) -> Result<Option<RlpAccount>> {
+        let proof = self
+            .accounts
+            .get(&address)
+            .ok_or_else(|| format_err!("Account {:?} is not proven", address))?;
+
+        Ok(verify_proof(self.state_root, keccak256(address), proof)?
+            .map(|rlp| rlp::decode(&rlp))
+            .transpose()?)
+    }
+
+    /// Storage value at `location` of the account at `address` as proven, zero if the slot is
+    /// proven empty.
+    pub fn storage(&self, address: Address, location: U256) -> Result<U256> {
+        let storage_root = match self.account(address)? {
+            Some(account) => account.storage_root,
+            None => return Ok(U256::ZERO),
+        };
+        let proof = self
+            .storage
+            .get(&address)
+            .and_then(|storage| storage.get(&location))
+            .ok_or_else(|| format_err!("Storage {:?}/{} is not proven", address, location))?;
+
+        Ok(
+            verify_proof(storage_root, keccak256(u256_to_h256(location)), proof)?
+                .map(|rlp| rlp::decode(&rlp))
+                .transpose()?
+                .unwrap_or(U256::ZERO),
+        )
+    }
+}
+
+/// Unpacks a hex-prefix encoded node path, telling whether it is the path of a leaf.
+fn decode_path(encoded: &[u8]) -> Result<(Vec<u8>, bool)> {
+    let (&first, rest) = encoded
+        .split_first()
+        .ok_or_else(|| format_err!("empty trie node path"))?;
+    let flags = first >> 4;
+    if flags > 3 {
+        bail!("invalid trie node path flags {}", flags);
+    }
+
+    let mut nibbles = if flags & 1 != 0 {
+        vec![first & 0x0f]
+    } else {
+        vec![]
+    };
+    nibbles.extend(unpack_nibbles(rest));
+
+    Ok((nibbles, flags & 2 != 0))
+}
+
+fn next_node<'p>(nodes: &mut impl Iterator<Item = &'p Bytes>, hash: H256) -> Result<Vec<u8>> {
+    let node = nodes
+        .next()
+        .ok_or_else(|| format_err!("proof is missing node {:?}", hash))?;
+    ensure!(
+        keccak256(node) == hash,
+        "proof node does not match hash {:?}",
+        hash
+    );
+    Ok(node.to_vec())
+}
+
+/// Value at hashed `key` of the trie with `root` as proven by `proof`, the nodes on the path to
+/// the key root first, or `None` if the proof shows the key is not in the trie.
+pub fn verify_proof(root: H256, key: H256, proof: &[Bytes]) -> Result<Option<Vec<u8>>> {
+    let key = unpack_nibbles(key.as_bytes());
+    let mut path = key.as_slice();
+    let mut nodes = proof.iter();
+
+    let value = if root == EMPTY_ROOT {
+        None
+    } else {
+        let mut node = next_node(&mut nodes, root)?;
+        loop {
+            let rlp = Rlp::new(&node);
+            let child = match rlp.item_count()? {
+                17 => {
+                    let (&nibble, rest) = path
+                        .split_first()
+                        .ok_or_else(|| format_err!("proof goes past the key"))?;
+                    path = rest;
+                    rlp.at(nibble as usize)?
+                }
+                2 => {
+                    let (node_path, is_leaf) = decode_path(rlp.at(0)?.data()?)?;
+                    if is_leaf {
+                        break (node_path == path)
+                            .then(|| rlp.at(1)?.data().map(<[u8]>::to_vec))
+                            .transpose()?;
+                    }
+                    if !path.starts_with(&node_path) {
+                        break None;
+                    }
+                    path = &path[node_path.len()..];
+                    rlp.at(1)?
+                }
+                _ => bail!("invalid trie node"),
+            };
+
+            let next = if child.is_empty() {
+                break None;
+            } else if child.is_list() {
+                // embedded in its parent
+                child.as_raw().to_vec()
+            } else {
+                let hash = child.data()?;
+                ensure!(hash.len() == KECCAK_LENGTH, "invalid trie node reference");
+                next_node(&mut nodes, H256::from_slice(hash))?
+            };
+            node = next;
+        }
+    };
+
+    ensure!(nodes.next().is_none(), "proof has nodes off the path");
+
+    Ok(value)
+}
+
+/// Walks the hashed state like the intermediate hashes loader, but read-only, keeping the nodes
+/// on the paths to the proven keys.
+struct ProofLoader<'db, 'tx, K, E>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    txn: &'tx MdbxTransaction<'db, K, E>,
+    // hashed address -> (address, unpacked hashed location -> location)
+    storage_keys: HashMap<H256, (Address, HashMap<Vec<u8>, U256>)>,
+    storage_proofs: HashMap<Address, HashMap<U256, Vec<Bytes>>>,
+}
+
+impl<'db, 'tx, K, E> ProofLoader<'db, 'tx, K, E>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    fn calculate_root(
+        &mut self,
+        changed: &mut PrefixSet,
+        account_keys: &HashMap<Vec<u8>, Address>,
+    ) -> Result<(H256, HashMap<Address, Vec<Bytes>>)> {
+        let txn = self.txn;
+        let mut state = txn.cursor(tables::HashedAccount)?;
+        let mut trie_db_cursor = txn.cursor(tables::TrieAccount)?;
+
+        let mut hb = HashBuilder::new();
+        hb.proof_retainer = Some(ProofRetainer::new(account_keys.keys().cloned()));
+
+        let mut trie = Cursor::read_only(&mut trie_db_cursor, changed, &[])?;
+        while trie.key().is_some() {
+            if trie.can_skip_state() {
+                assert!(trie.hash().is_some());
+                hb.add_branch_node(
+                    trie.key().unwrap(),
+                    trie.hash().as_ref().unwrap(),
+                    trie.children_are_in_trie(),
+                );
+            }
+
+            let uncovered = trie.first_uncovered_prefix();
+            if uncovered.is_none() {
+                break;
+            }
+
+            trie.next()?;
+
+            let mut seek_key = uncovered.unwrap().to_vec();
+            seek_key.resize(32, 0);
+
+            let mut acc = state.seek(H256::from_slice(seek_key.as_slice()))?;
+            while let Some((hashed_address, account)) = acc {
+                let unpacked_key = unpack_nibbles(hashed_address.as_bytes());
+                if trie.key().is_some() && trie.key().unwrap() < unpacked_key {
+                    break;
+                }
+
+                let storage_root =
+                    self.calculate_storage_root(hashed_address, trie.changed_mut())?;
+
+                hb.add_leaf(
+                    unpacked_key,
+                    rlp::encode(&account.to_rlp(storage_root)).as_ref(),
+                );
+
+                acc = state.next()?
+            }
+        }
+
+        let root = hb.root_hash();
+        let proofs = hb
+            .proof_retainer
+            .take()
+            .unwrap()
+            .into_proofs()
+            .into_iter()
+            .map(|(key, proof)| (account_keys[&key], proof))
+            .collect();
+
+        Ok((root, proofs))
+    }
+
+    fn calculate_storage_root(
+        &mut self,
+        hashed_address: H256,
+        changed: &mut PrefixSet,
+    ) -> Result<H256> {
+        let txn = self.txn;
+        let mut state = txn.cursor(tables::HashedStorage)?;
+        let mut trie_db_cursor = txn.cursor(tables::TrieStorage)?;
+
+        let proven = self.storage_keys.get(&hashed_address);
+
+        let mut hb = HashBuilder::new();
+        if let Some((_, locations)) = proven {
+            hb.proof_retainer = Some(ProofRetainer::new(locations.keys().cloned()));
+        }
+
+        let mut trie = Cursor::read_only(&mut trie_db_cursor, changed, hashed_address.as_bytes())?;
+        while trie.key().is_some() {
+            if trie.can_skip_state() {
+                assert!(trie.hash().is_some());
+                hb.add_branch_node(
+                    trie.key().unwrap(),
+                    trie.hash().as_ref().unwrap(),
+                    trie.children_are_in_trie(),
+                );
+            }
+
+            let uncovered = trie.first_uncovered_prefix();
+            if uncovered.is_none() {
+                break;
+            }
+
+            trie.next()?;
+
+            let mut seek_key = uncovered.unwrap().to_vec();
+            seek_key.resize(32, 0);
+
+            let mut storage =
+                state.seek_both_range(hashed_address, H256::from_slice(seek_key.as_slice()))?;
+            while let Some((storage_location, value)) = storage {
+                let unpacked_loc = unpack_nibbles(storage_location.as_bytes());
+                if trie.key().is_some() && trie.key().unwrap() < unpacked_loc {
+                    break;
+                }
+                hb.add_leaf(unpacked_loc, rlp::encode(&value).as_ref());
+                storage = state.next_dup()?.map(|(_, v)| v);
+            }
+        }
+
+        let root = hb.root_hash();
+        if let Some((address, locations)) = proven {
+            let proofs = hb
+                .proof_retainer
+                .take()
+                .unwrap()
+                .into_proofs()
+                .into_iter()
+                .map(|(key, proof)| (locations[&key], proof))
+                .collect();
+            self.storage_proofs.insert(*address, proofs);
+        }
+
+        Ok(root)
+    }
+}
+
+/// Proves `accounts`, and the `storage` slots given by address and location, against the state
+/// root of the hashed state. The intermediate hashes must be up to date with the hashed state:
+/// only the paths to the proven keys are hashed anew.
+pub fn prove<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    accounts: impl IntoIterator<Item = Address>,
+    storage: impl IntoIterator<Item = (Address, U256)>,
+) -> Result<StateProof>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut changed = PrefixSet::new();
+    // unpacked hashed address -> address
+    let mut account_keys = HashMap::new();
+    let mut storage_keys = HashMap::<_, (_, HashMap<_, _>)>::new();
+
+    for (address, location) in storage {
+        let hashed_address = keccak256(address);
+        let unpacked_location = unpack_nibbles(keccak256(u256_to_h256(location)).as_bytes());
+        changed.insert(&[hashed_address.as_bytes(), &unpacked_location].concat());
+        storage_keys
+            .entry(hashed_address)
+            .or_insert_with(|| (address, HashMap::new()))
+            .1
+            .insert(unpacked_location, location);
+        // Storage is proven from the storage root of the account.
+        account_keys.insert(unpack_nibbles(hashed_address.as_bytes()), address);
+    }
+    for address in accounts {
+        account_keys.insert(unpack_nibbles(keccak256(address).as_bytes()), address);
+    }
+    for unpacked_key in account_keys.keys() {
+        changed.insert(unpacked_key);
+    }
+
+    let mut loader = ProofLoader {
+        txn,
+        storage_keys,
+        storage_proofs: HashMap::new(),
+    };
+    let (state_root, accounts) = loader.calculate_root(&mut changed, &account_keys)?;
+
+    Ok(StateProof {
+        state_root,
+        accounts,
+        storage: loader.storage_proofs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kv::new_mem_database, trie::regenerate_intermediate_hashes};
+    use tempfile::TempDir;
+
+    #[test]
+    fn prove_accounts_and_storage() {
+        let db = new_mem_database().unwrap();
+        let txn = db.begin_mutable().unwrap();
+
+        let address = |i: u64| Address::from_low_u64_be(i);
+        let account = |i: u64| Account {
+            nonce: i,
+            balance: (i * 1000).as_u256(),
+            ..Default::default()
+        };
+        let with_storage = address(7);
+        let location = |i: u64| i.as_u256();
+
+        // Enough accounts and slots for the intermediate hashes to hold branch nodes.
+        let mut accounts = txn.cursor(tables::HashedAccount).unwrap();
+        for i in 1..=300 {
+            accounts.upsert(keccak256(address(i)), account(i)).unwrap();
+        }
+        let mut storage = txn.cursor(tables::HashedStorage).unwrap();
+        for i in 1..=300 {
+            storage
+                .upsert(
+                    keccak256(with_storage),
+                    (keccak256(u256_to_h256(location(i))), (i + 1).as_u256()),
+                )
+                .unwrap();
+        }
+
+        let temp_dir = TempDir::new().unwrap();
+        let state_root = regenerate_intermediate_hashes(&txn, &temp_dir, None).unwrap();
+
+        let missing = address(1000);
+        let proof = prove(
+            &txn,
+            [address(1), address(150), missing],
+            [
+                (with_storage, location(3)),
+                (with_storage, location(1000)),
+                (missing, location(3)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(proof.state_root, state_root);
+
+        for i in [1, 150] {
+            let proven = proof.account(address(i)).unwrap().unwrap();
+            assert_eq!(proven.nonce, i);
+            assert_eq!(proven.balance, (i * 1000).as_u256());
+            assert_eq!(proven.storage_root, EMPTY_ROOT);
+        }
+        assert_eq!(proof.account(missing).unwrap(), None);
+        assert_ne!(
+            proof.account(with_storage).unwrap().unwrap().storage_root,
+            EMPTY_ROOT
+        );
+        assert_eq!(
+            proof.storage(with_storage, location(3)).unwrap(),
+            4.as_u256()
+        );
+        assert_eq!(
+            proof.storage(with_storage, location(1000)).unwrap(),
+            U256::ZERO
+        );
+        assert_eq!(proof.storage(missing, location(3)).unwrap(), U256::ZERO);
+
+        // Not asked for.
+        proof.account(address(2)).unwrap_err();
+        proof.storage(with_storage, location(4)).unwrap_err();
+
+        let mut tampered = proof.clone();
+        let nodes = tampered.accounts.get_mut(&address(150)).unwrap();
+        let last = nodes.last_mut().unwrap();
+        let mut node = last.to_vec();
+        *node.last_mut().unwrap() ^= 1;
+        *last = node.into();
+        tampered.account(address(150)).unwrap_err();
+
+        let mut truncated = proof;
+        truncated
+            .storage
+            .get_mut(&with_storage)
+            .unwrap()
+            .get_mut(&location(3))
+            .unwrap()
+            .pop();
+        truncated.storage(with_storage, location(3)).unwrap_err();
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{
+    accessors::state::{history_index, storage},
+    crypto::keccak256,
+    kv::{
+        mdbx::*,
+        tables::{self, BitmapKey},
+    },
+    models::*,
+    stagedsync::stages::STORAGE_HISTORY_INDEX,
+    trie::hash_builder::{unpack_nibbles, HashBuilder},
+};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Storage root of `address`, either in the current hashed state or, if `block_number` is given,
+/// right after that block.
+///
+/// Built from the hashed storage alone, so it does not depend on the intermediate hashes being up to date.
+/// Historical roots revert the hashed storage with the storage changesets of the later blocks,
+/// found through the storage history index as far as it goes, so `block_number` must not be past
+/// the hashed state.
+pub fn storage_root<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    address: Address,
+    block_number: Option<BlockNumber>,
+) -> Result<H256>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let hashed_address = keccak256(address);
+
+    let mut storage = BTreeMap::new();
+    let mut cursor = txn.cursor(tables::HashedStorage)?;
+    let mut entry = cursor.seek_both_range(hashed_address, H256::zero())?;
+    while let Some((location, value)) = entry {
+        storage.insert(location, value);
+        entry = cursor.next_dup()?.map(|(_, v)| v);
+    }
+
+    if let Some(block_number) = block_number {
+        let mut changes = txn.cursor(tables::StorageChangeSet)?;
+        let last_change_block = changes.last()?.map(|(key, _)| key.block_number);
+
+        // Values the block left behind, from the earliest change after it, by plain location.
+        let mut reverted = BTreeMap::new();
+        let mut scan_from = block_number + 1;
+        if let Some(indexed_to) = STORAGE_HISTORY_INDEX.get_progress(txn)? {
+            if indexed_to > block_number {
+                for location in indexed_locations(txn, address)? {
+                    if let Some(value) = history_index::find_change(
+                        txn,
+                        STORAGE_HISTORY_INDEX,
+                        tables::StorageHistory,
+                        (address, location),
+                        block_number,
+                        // Changes past the index are scanned for below.
+                        None,
+                        |change_block| {
+                            storage::read_change(&mut changes, address, location, change_block)
+                        },
+                    )? {
+                        reverted.insert(location, value);
+                    }
+                }
+                scan_from = indexed_to + 1;
+            }
+        }
+
+        if let Some(last_change_block) = last_change_block {
+            for change_block in scan_from..=last_change_block {
+                let mut entry = changes
+                    .seek_exact(tables::StorageChangeKey {
+                        block_number: change_block,
+                        address,
+                    })?
+                    .map(|(_, v)| v);
+                while let Some(tables::StorageChange { location, value }) = entry {
+                    reverted.entry(location).or_insert(value);
+                    entry = changes.next_dup()?.map(|(_, v)| v);
+                }
+            }
+        }
+
+        for (location, value) in reverted {
+            let location = keccak256(location);
+            if value == 0 {
+                storage.remove(&location);
+            } else {
+                storage.insert(location, value);
+            }
+        }
+    }
+
+    let mut hb = HashBuilder::new();
+    for (location, value) in storage {
+        hb.add_leaf(unpack_nibbles(location.as_bytes()), &rlp::encode(&value));
+    }
+
+    Ok(hb.root_hash())
+}
+
+/// Locations of `address` with changes in the storage history index.
+fn indexed_locations<K, E>(txn: &MdbxTransaction<'_, K, E>, address: Address) -> Result<Vec<H256>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut locations = Vec::new();
+    let mut cursor = txn.cursor(tables::StorageHistory)?;
+    let mut entry = cursor.seek(BitmapKey {
+        inner: (address, H256::zero()),
+        block_number: BlockNumber(0),
+    })?;
+    while let Some((
+        BitmapKey {
+            inner: (chunk_address, location),
+            ..
+        },
+        _,
+    )) = entry
+    {
+        if chunk_address != address {
+            break;
+        }
+
+        // The chunks of a location are next to each other.
+        if locations.last() != Some(&location) {
+            locations.push(location);
+        }
+        entry = cursor.next()?;
+    }
+
+    Ok(locations)
+}
+
+/// Storage root of the account with `hashed_address` in the current hashed state.
+pub fn hashed_storage_root<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    hashed_address: H256,
+) -> Result<H256>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let mut hb = HashBuilder::new();
+    for res in txn.cursor(tables::HashedStorage)?.walk_dup(hashed_address) {
+        let (location, value) = res?;
+        hb.add_leaf(unpack_nibbles(location.as_bytes()), &rlp::encode(&value));
+    }
+    Ok(hb.root_hash())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::trie_root, kv::new_mem_database, u256_to_h256, upsert_hashed_storage_value,
+        zeroless_view,
+    };
+    use croaring::Treemap as RoaringTreemap;
+    use hex_literal::hex;
+
+    #[test]
+    fn storage_root_from_hashed_storage() {
+        let db = new_mem_database().unwrap();
+        let txn = db.begin_mutable().unwrap();
+
+        let address = hex!("b685342b8c54347aad148e1f22eff3eb3eb29391").into();
+        let other = hex!("5a0b54d5dc17e0aadc383d2db43b0a0d3e029c4c").into();
+
+        assert_eq!(storage_root(&txn, address, None).unwrap(), EMPTY_ROOT);
+
+        let storage = [(1_u64, 0x2a_u64), (2, 0x01c9), (0xdead, 7)]
+            .map(|(k, v)| (u256_to_h256(k.as_u256()), v.as_u256()));
+
+        let mut hashed_storage = txn.cursor(tables::HashedStorage).unwrap();
+        for (location, value) in storage {
+            upsert_hashed_storage_value(
+                &mut hashed_storage,
+                keccak256(address),
+                keccak256(location),
+                value,
+            )
+            .unwrap();
+        }
+        upsert_hashed_storage_value(
+            &mut hashed_storage,
+            keccak256(other),
+            keccak256(H256::zero()),
+            1.as_u256(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            storage_root(&txn, address, None).unwrap(),
+            expected_storage_root(&storage)
+        );
+        assert_eq!(
+            hashed_storage_root(&txn, keccak256(address)).unwrap(),
+            expected_storage_root(&storage)
+        );
+
+        // Block 10 set slot 1 and created slot 0xdead, block 11 changed slot 2 and rewrote slot 1.
+        let mut changes = txn.cursor(tables::StorageChangeSet).unwrap();
+        for (block_number, location, value) in [
+            (10, 1_u64, 0x29_u64),
+            (10, 0xdead, 0),
+            (11, 2, 0x01c8),
+            (11, 1, 0x2a),
+        ] {
+            changes
+                .upsert(
+                    tables::StorageChangeKey {
+                        block_number: BlockNumber(block_number),
+                        address,
+                    },
+                    tables::StorageChange {
+                        location: u256_to_h256(location.as_u256()),
+                        value: value.as_u256(),
+                    },
+                )
+                .unwrap();
+        }
+
+        let at =
+            |block_number| storage_root(&txn, address, Some(BlockNumber(block_number))).unwrap();
+        let check = || {
+            assert_eq!(at(11), expected_storage_root(&storage));
+            assert_eq!(
+                at(10),
+                expected_storage_root(&[storage[0], (storage[1].0, 0x01c8.as_u256()), storage[2]])
+            );
+            assert_eq!(
+                at(9),
+                expected_storage_root(&[
+                    (storage[0].0, 0x29.as_u256()),
+                    (storage[1].0, 0x01c8.as_u256())
+                ])
+            );
+        };
+        check();
+
+        // Changes of block 10 indexed, those of block 11 not yet, then both.
+        for (indexed_to, changes) in [
+            (10, &[(1_u64, &[10_u64][..]), (0xdead, &[10][..])][..]),
+            (
+                11,
+                &[(1, &[10, 11][..]), (2, &[11][..]), (0xdead, &[10][..])][..],
+            ),
+        ] {
+            for &(location, change_blocks) in changes {
+                let mut bitmap = RoaringTreemap::create();
+                for &block_number in change_blocks {
+                    bitmap.add(block_number);
+                }
+                txn.set(
+                    tables::StorageHistory,
+                    BitmapKey {
+                        inner: (address, u256_to_h256(location.as_u256())),
+                        block_number: BlockNumber(u64::MAX),
+                    },
+                    bitmap,
+                )
+                .unwrap();
+            }
+            STORAGE_HISTORY_INDEX
+                .save_progress(&txn, BlockNumber(indexed_to))
+                .unwrap();
+            assert_eq!(
+                indexed_locations(&txn, address).unwrap().len(),
+                changes.len()
+            );
+            check();
+        }
+    }
+
+    fn expected_storage_root(storage: &[(H256, U256)]) -> H256 {
+        trie_root(storage.iter().map(|(location, value)| {
+            (
+                keccak256(location),
+                rlp::encode(&zeroless_view(&u256_to_h256(*value))),
+            )
+        }))
+    }
+}
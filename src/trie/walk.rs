@@ -0,0 +1,255 @@
+use crate::{
+    crypto::keccak256,
+    kv::{mdbx::*, tables, traits::Table},
+    models::*,
+    trie::{
+        hash_builder::unpack_nibbles,
+        node::{unmarshal_node, Node},
+    },
+};
+use anyhow::{format_err, Result};
+
+/// Branch node of the account trie or of a storage trie, as kept in the intermediate hashes tables.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrieNode {
+    /// Path from the root of the trie to the node, one nibble per byte.
+    pub path: Vec<u8>,
+    /// Children that exist at all.
+    pub state_mask: u16,
+    /// Children that are branch nodes stored in the table themselves.
+    pub tree_mask: u16,
+    /// Children whose hash is cached in `hashes`.
+    pub hash_mask: u16,
+    /// Cached child hashes, in nibble order of `hash_mask`.
+    pub hashes: Vec<H256>,
+    /// Hash of the node itself, only kept for the root.
+    pub root_hash: Option<H256>,
+}
+
+/// Child of a [`TrieNode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrieNodeChild {
+    pub nibble: u8,
+    /// Whether the child is a branch node stored in the table.
+    pub in_tree: bool,
+    pub hash: Option<H256>,
+}
+
+impl TrieNode {
+    fn decode(path: Vec<u8>, value: &[u8]) -> Result<Self> {
+        let node = unmarshal_node(value)
+            .ok_or_else(|| format_err!("invalid trie node at path {}", hex::encode(&path)))?;
+        Ok(Self::from_node(path, &node))
+    }
+
+    fn from_node(path: Vec<u8>, node: &Node) -> Self {
+        Self {
+            path,
+            state_mask: node.state_mask(),
+            tree_mask: node.tree_mask(),
+            hash_mask: node.hash_mask(),
+            hashes: node.hashes(),
+            root_hash: node.root_hash(),
+        }
+    }
+
+    /// Existing children of the node, in nibble order.
+    pub fn children(&self) -> impl Iterator<Item = TrieNodeChild> + '_ {
+        let mut hashes = self.hashes.iter().copied();
+        (0..16_u8)
+            .filter(|&nibble| self.state_mask & (1 << nibble) != 0)
+            .map(move |nibble| TrieNodeChild {
+                nibble,
+                in_tree: self.tree_mask & (1 << nibble) != 0,
+                hash: if self.hash_mask & (1 << nibble) != 0 {
+                    hashes.next()
+                } else {
+                    None
+                },
+            })
+    }
+}
+
+/// Account trie nodes whose path starts with `prefix` (unpacked nibbles), in key order.
+pub fn walk_account_trie<'tx, K, E>(
+    txn: &'tx MdbxTransaction<'_, K, E>,
+    prefix: Vec<u8>,
+) -> Result<impl Iterator<Item = Result<TrieNode>> + 'tx>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    Ok(walk_table(txn.cursor(tables::TrieAccount)?, vec![], prefix))
+}
+
+/// Storage trie nodes of the account with `hashed_address` whose path starts with `prefix`
+/// (unpacked nibbles), in key order.
+pub fn walk_storage_trie<'tx, K, E>(
+    txn: &'tx MdbxTransaction<'_, K, E>,
+    hashed_address: H256,
+    prefix: Vec<u8>,
+) -> Result<impl Iterator<Item = Result<TrieNode>> + 'tx>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    Ok(walk_table(
+        txn.cursor(tables::TrieStorage)?,
+        hashed_address.as_bytes().to_vec(),
+        prefix,
+    ))
+}
+
+fn walk_table<'tx, K, T>(
+    cursor: MdbxCursor<'tx, K, T>,
+    key_prefix: Vec<u8>,
+    prefix: Vec<u8>,
+) -> impl Iterator<Item = Result<TrieNode>> + 'tx
+where
+    K: TransactionKind,
+    T: Table<Key = Vec<u8>, Value = Vec<u8>, SeekKey = Vec<u8>>,
+{
+    let strip = key_prefix.len();
+    let start = [key_prefix, prefix].concat();
+    cursor
+        .walk(Some(start.clone()))
+        .take_while(move |res| match res {
+            Ok((key, _)) => key.starts_with(&start),
+            Err(_) => true,
+        })
+        .map(move |res| {
+            let (key, value) = res?;
+            TrieNode::decode(key[strip..].to_vec(), &value)
+        })
+}
+
+/// Account trie nodes on the way from the root to the leaf of `address`, root first.
+pub fn account_trie_path<K, E>(
+    txn: &MdbxTransaction<'_, K, E>,
+    address: Address,
+) -> Result<Vec<TrieNode>>
+where
+    K: TransactionKind,
+    E: EnvironmentKind,
+{
+    let nibbles = unpack_nibbles(keccak256(address).as_bytes());
+
+    let mut out = vec![];
+    for len in 0..=nibbles.len() {
+        let path = nibbles[..len].to_vec();
+        match txn.get(tables::TrieAccount, path.clone())? {
+            Some(value) => out.push(TrieNode::decode(path, &value)?),
+            // An intermediate node may sit at any depth below an extension, so only stop
+            // once the previous node has no branch node child in our direction.
+            None => {
+                if let Some(last) = out.last() {
+                    let nibble = nibbles[last.path.len()];
+                    if last.tree_mask & (1 << nibble) == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kv::new_mem_database, trie::node::marshal_node};
+    use hex_literal::hex;
+
+    #[test]
+    fn walk_by_prefix() {
+        let db = new_mem_database().unwrap();
+        let txn = db.begin_mutable().unwrap();
+
+        let hash = H256::from(hex!(
+            "0384e6e2c2b33c4eb911a08a7ff57f83dc3eb86d8d0c92ec112f3b416d6685a9"
+        ));
+
+        for (key, node) in [
+            (vec![], Node::new(0b1010, 0b0010, 0, vec![], Some(hash))),
+            (
+                vec![0x1],
+                Node::new(0b1011, 0b1001, 0b0010, vec![hash], None),
+            ),
+            (vec![0x1, 0x0, 0xB], Node::new(0b1010, 0, 0, vec![], None)),
+            (vec![0x1, 0x3], Node::new(0b1110, 0, 0, vec![], None)),
+            (vec![0x3], Node::new(0b0110, 0, 0, vec![], None)),
+        ] {
+            txn.set(tables::TrieAccount, key, marshal_node(&node))
+                .unwrap();
+        }
+        let hashed_address = H256::repeat_byte(0xAA);
+        txn.set(
+            tables::TrieStorage,
+            [hashed_address.as_bytes(), &[0x5]].concat(),
+            marshal_node(&Node::new(0b11, 0, 0, vec![], None)),
+        )
+        .unwrap();
+
+        let paths = |prefix: Vec<u8>| {
+            walk_account_trie(&txn, prefix)
+                .unwrap()
+                .map(|node| node.unwrap().path)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(vec![]),
+            vec![
+                vec![],
+                vec![0x1],
+                vec![0x1, 0x0, 0xB],
+                vec![0x1, 0x3],
+                vec![0x3]
+            ]
+        );
+        assert_eq!(
+            paths(vec![0x1]),
+            vec![vec![0x1], vec![0x1, 0x0, 0xB], vec![0x1, 0x3]]
+        );
+        assert_eq!(paths(vec![0x1, 0x0]), vec![vec![0x1, 0x0, 0xB]]);
+        assert!(paths(vec![0x2]).is_empty());
+
+        let node = walk_account_trie(&txn, vec![0x1])
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            node.children().collect::<Vec<_>>(),
+            vec![
+                TrieNodeChild {
+                    nibble: 0x0,
+                    in_tree: true,
+                    hash: None
+                },
+                TrieNodeChild {
+                    nibble: 0x1,
+                    in_tree: false,
+                    hash: Some(hash)
+                },
+                TrieNodeChild {
+                    nibble: 0x3,
+                    in_tree: true,
+                    hash: None
+                },
+            ]
+        );
+
+        let storage = walk_storage_trie(&txn, hashed_address, vec![])
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage[0].path, vec![0x5]);
+        assert_eq!(storage[0].state_mask, 0b11);
+        assert!(walk_storage_trie(&txn, H256::repeat_byte(0xAB), vec![])
+            .unwrap()
+            .next()
+            .is_none());
+    }
+}
@@ -0,0 +1,396 @@
+//! Pool of transactions submitted to this node and waiting to be mined.
+//!
+//! Transactions are validated against the head when added and kept by sender in nonce order. A
+//! transaction only replaces the one with the same sender and nonce if it raises both of its fee
+//! caps by [`PoolLimits::price_bump`] percent. Each sender, and the pool as a whole, hold a
+//! limited number of transactions.
+use crate::{
+    chain::intrinsic_gas::intrinsic_gas,
+    consensus::{pre_validate_transaction, ValidationError},
+    models::*,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct PoolLimits {
+    /// Most transactions of one sender.
+    pub max_per_account: usize,
+    /// Most transactions in the pool.
+    pub max_total: usize,
+    /// Percentage by which a replacement must raise both fee caps of the transaction it replaces.
+    pub price_bump: u64,
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        Self {
+            max_per_account: 16,
+            max_total: 4096,
+            price_bump: 10,
+        }
+    }
+}
+
+/// Block after the head, which transactions are validated for.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolContext {
+    pub chain_id: ChainId,
+    pub revision: Revision,
+    pub base_fee_per_gas: Option<U256>,
+    pub gas_limit: u64,
+}
+
+#[derive(Debug)]
+pub enum PoolError {
+    AlreadyKnown,
+    /// Deposits are derived from L1, not submitted.
+    Deposit,
+    Invalid(ValidationError),
+    NonceTooLow {
+        next: u64,
+        got: u64,
+    },
+    IntrinsicGas,
+    GasLimitExceeded,
+    InsufficientFunds,
+    ReplacementUnderpriced,
+    AccountLimit,
+    PoolFull,
+}
+
+impl Display for PoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlreadyKnown => write!(f, "already known"),
+            Self::Deposit => write!(f, "deposit transactions cannot be submitted"),
+            Self::Invalid(e) => write!(f, "invalid transaction: {}", e),
+            Self::NonceTooLow { next, got } => {
+                write!(f, "nonce too low: next nonce {}, tx nonce {}", next, got)
+            }
+            Self::IntrinsicGas => write!(f, "intrinsic gas too low"),
+            Self::GasLimitExceeded => write!(f, "exceeds block gas limit"),
+            Self::InsufficientFunds => write!(f, "insufficient funds for gas * price + value"),
+            Self::ReplacementUnderpriced => write!(f, "replacement transaction underpriced"),
+            Self::AccountLimit => write!(f, "account has too many pooled transactions"),
+            Self::PoolFull => write!(f, "txpool is full"),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PooledTransaction {
+    pub hash: H256,
+    pub sender: Address,
+    pub message: MessageWithSignature,
+}
+
+/// Pooled transactions of every sender, split at the first nonce gap.
+#[derive(Debug, Default)]
+pub struct PoolContent<'a> {
+    /// Transactions with consecutive nonces from the next nonce of their sender.
+    pub pending: BTreeMap<Address, Vec<&'a PooledTransaction>>,
+    /// Transactions after a nonce gap, which cannot be mined until it is filled.
+    pub queued: BTreeMap<Address, Vec<&'a PooledTransaction>>,
+}
+
+#[derive(Debug, Default)]
+pub struct TxPool {
+    limits: PoolLimits,
+    by_sender: HashMap<Address, BTreeMap<u64, PooledTransaction>>,
+    by_hash: HashMap<H256, (Address, u64)>,
+}
+
+impl TxPool {
+    pub fn new(limits: PoolLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_hash.is_empty()
+    }
+
+    pub fn get(&self, hash: H256) -> Option<&PooledTransaction> {
+        let (sender, nonce) = self.by_hash.get(&hash)?;
+        self.by_sender.get(sender)?.get(nonce)
+    }
+
+    pub fn senders(&self) -> BTreeSet<Address> {
+        self.by_sender.keys().copied().collect()
+    }
+
+    pub fn nonces(&self, sender: Address) -> BTreeSet<u64> {
+        self.by_sender
+            .get(&sender)
+            .map(|txs| txs.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PooledTransaction> {
+        self.by_sender.values().flat_map(|txs| txs.values())
+    }
+
+    /// Add `message` from `sender`, whose account has `nonce` and `balance` at the head. Returns
+    /// the hash of the transaction it replaced, if any.
+    pub fn add(
+        &mut self,
+        message: MessageWithSignature,
+        sender: Address,
+        nonce: u64,
+        balance: U256,
+        context: &PoolContext,
+    ) -> Result<Option<H256>, PoolError> {
+        let hash = message.hash();
+        if self.by_hash.contains_key(&hash) {
+            return Err(PoolError::AlreadyKnown);
+        }
+        if message.is_deposit() {
+            return Err(PoolError::Deposit);
+        }
+
+        pre_validate_transaction(&message, context.chain_id, context.base_fee_per_gas)
+            .map_err(PoolError::Invalid)?;
+        if message.nonce() < nonce {
+            return Err(PoolError::NonceTooLow {
+                next: nonce,
+                got: message.nonce(),
+            });
+        }
+        let g0 = intrinsic_gas(
+            &message,
+            context.revision >= Revision::Homestead,
+            context.revision >= Revision::Istanbul,
+        );
+        if u128::from(message.gas_limit()) < g0 {
+            return Err(PoolError::IntrinsicGas);
+        }
+        if message.gas_limit() > context.gas_limit {
+            return Err(PoolError::GasLimitExceeded);
+        }
+        let cost = message
+            .max_fee_per_gas()
+            .checked_mul(message.gas_limit().as_u256())
+            .and_then(|fee| fee.checked_add(message.value()));
+        if cost.map(|cost| cost > balance).unwrap_or(true) {
+            return Err(PoolError::InsufficientFunds);
+        }
+
+        let txs = self.by_sender.get(&sender);
+        let replaced = txs.and_then(|txs| txs.get(&message.nonce()));
+        match replaced {
+            Some(replaced) => {
+                let bumped = |fee: U256| fee + fee * self.limits.price_bump.as_u256() / 100;
+                if message.max_fee_per_gas() < bumped(replaced.message.max_fee_per_gas())
+                    || message.max_priority_fee_per_gas()
+                        < bumped(replaced.message.max_priority_fee_per_gas())
+                {
+                    return Err(PoolError::ReplacementUnderpriced);
+                }
+            }
+            None => {
+                if txs.map(|txs| txs.len()).unwrap_or(0) >= self.limits.max_per_account {
+                    return Err(PoolError::AccountLimit);
+                }
+                if self.len() >= self.limits.max_total {
+                    return Err(PoolError::PoolFull);
+                }
+            }
+        }
+
+        let nonce = message.nonce();
+        self.by_hash.insert(hash, (sender, nonce));
+        let replaced = self.by_sender.entry(sender).or_default().insert(
+            nonce,
+            PooledTransaction {
+                hash,
+                sender,
+                message,
+            },
+        );
+
+        Ok(replaced.map(|replaced| {
+            self.by_hash.remove(&replaced.hash);
+            replaced.hash
+        }))
+    }
+
+    pub fn remove(&mut self, hash: H256) -> Option<PooledTransaction> {
+        let (sender, nonce) = self.by_hash.remove(&hash)?;
+        let txs = self.by_sender.get_mut(&sender)?;
+        let removed = txs.remove(&nonce);
+        if txs.is_empty() {
+            self.by_sender.remove(&sender);
+        }
+        removed
+    }
+
+    /// Drop the transactions whose nonce was used on chain, `next_nonce` giving the next nonce of
+    /// a sender at the head. Returns the hashes of the dropped transactions.
+    pub fn prune(
+        &mut self,
+        mut next_nonce: impl FnMut(Address) -> anyhow::Result<u64>,
+    ) -> anyhow::Result<Vec<H256>> {
+        let mut done = Vec::new();
+        for (&sender, txs) in &self.by_sender {
+            let next_nonce = next_nonce(sender)?;
+            done.extend(txs.range(..next_nonce).map(|(_, tx)| tx.hash));
+        }
+        for hash in &done {
+            self.remove(*hash);
+        }
+
+        Ok(done)
+    }
+
+    /// Pooled transactions, `next_nonce` giving the next nonce of a sender at the head.
+    pub fn content(
+        &self,
+        mut next_nonce: impl FnMut(Address) -> anyhow::Result<u64>,
+    ) -> anyhow::Result<PoolContent<'_>> {
+        let mut content = PoolContent::default();
+        for (&sender, txs) in &self.by_sender {
+            let mut expected = next_nonce(sender)?;
+            for (&nonce, tx) in txs.range(expected..) {
+                if nonce == expected {
+                    expected += 1;
+                    content.pending.entry(sender).or_default().push(tx);
+                } else {
+                    content.queued.entry(sender).or_default().push(tx);
+                }
+            }
+        }
+
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use hex_literal::hex;
+
+    const CHAIN_ID: ChainId = ChainId(1);
+
+    fn context() -> PoolContext {
+        PoolContext {
+            chain_id: CHAIN_ID,
+            revision: Revision::London,
+            base_fee_per_gas: Some(7_u64.as_u256()),
+            gas_limit: 30_000_000,
+        }
+    }
+
+    fn tx(nonce: u64, max_fee_per_gas: u64, max_priority_fee_per_gas: u64) -> MessageWithSignature {
+        MessageWithSignature {
+            message: Message::EIP1559 {
+                chain_id: CHAIN_ID,
+                nonce,
+                max_priority_fee_per_gas: max_priority_fee_per_gas.as_u256(),
+                max_fee_per_gas: max_fee_per_gas.as_u256(),
+                gas_limit: 21_000,
+                action: TransactionAction::Call(Address::repeat_byte(0xaa)),
+                value: U256::ZERO,
+                input: Bytes::new(),
+                access_list: vec![],
+            },
+            signature: MessageSignature::new(
+                false,
+                H256::from(hex!(
+                    "11d244ae19e3bb96d1bb864aa761d48e957984a154329f0de757cd105f9c7ac4"
+                )),
+                H256::from(hex!(
+                    "0e3828d13eed24036941eb5f7fd65de57aad1184342f2244130d2941554342ba"
+                )),
+            )
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn validation_replacement_and_limits() {
+        let sender = Address::repeat_byte(1);
+        let balance = 2_100_000_u64.as_u256();
+        let mut pool = TxPool::new(PoolLimits {
+            max_per_account: 3,
+            max_total: 4,
+            price_bump: 10,
+        });
+        let add =
+            |pool: &mut TxPool, sender, message| pool.add(message, sender, 5, balance, &context());
+
+        assert!(matches!(
+            add(&mut pool, sender, tx(4, 10, 1)),
+            Err(PoolError::NonceTooLow { next: 5, got: 4 })
+        ));
+        assert!(matches!(
+            add(&mut pool, sender, tx(5, 6, 1)),
+            Err(PoolError::Invalid(ValidationError::MaxFeeLessThanBase))
+        ));
+        assert!(matches!(
+            add(&mut pool, sender, tx(5, 101, 1)),
+            Err(PoolError::InsufficientFunds)
+        ));
+
+        let first = tx(5, 10, 1);
+        assert_eq!(add(&mut pool, sender, first.clone()).unwrap(), None);
+        assert!(matches!(
+            add(&mut pool, sender, first.clone()),
+            Err(PoolError::AlreadyKnown)
+        ));
+        assert!(matches!(
+            add(&mut pool, sender, tx(5, 10, 2)),
+            Err(PoolError::ReplacementUnderpriced)
+        ));
+        let replacement = tx(5, 11, 2);
+        assert_eq!(
+            add(&mut pool, sender, replacement.clone()).unwrap(),
+            Some(first.hash())
+        );
+        assert_eq!(pool.len(), 1);
+        assert!(pool.get(first.hash()).is_none());
+
+        add(&mut pool, sender, tx(6, 10, 1)).unwrap();
+        add(&mut pool, sender, tx(8, 10, 1)).unwrap();
+        assert!(matches!(
+            add(&mut pool, sender, tx(9, 10, 1)),
+            Err(PoolError::AccountLimit)
+        ));
+        add(&mut pool, Address::repeat_byte(2), tx(5, 10, 1)).unwrap();
+        assert!(matches!(
+            add(&mut pool, Address::repeat_byte(3), tx(5, 10, 1)),
+            Err(PoolError::PoolFull)
+        ));
+
+        let content = pool.content(|_| Ok(5)).unwrap();
+        assert_eq!(
+            content.pending[&sender]
+                .iter()
+                .map(|tx| tx.message.nonce())
+                .collect::<Vec<_>>(),
+            [5, 6]
+        );
+        assert_eq!(content.queued[&sender][0].message.nonce(), 8);
+        assert_eq!(content.pending[&Address::repeat_byte(2)].len(), 1);
+
+        assert_eq!(
+            pool.prune(|address| Ok(if address == sender { 7 } else { 5 }))
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(pool.nonces(sender), BTreeSet::from([8]));
+        assert!(pool.get(replacement.hash()).is_none());
+    }
+}